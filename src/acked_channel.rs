@@ -0,0 +1,458 @@
+//! An unreliable message channel which reports which of its packets were received.
+//!
+//! Every packet sent by an `AckedChannel` carries a sequence number, along with the sequence
+//! number of the latest packet received from the remote and a bitfield of which of the 32 packets
+//! before that were also received.  Since acknowledgements ride along on every packet in both
+//! directions, each packet is acknowledged many times over and losing a few of them costs nothing.
+//!
+//! The channel itself never resends anything.  Instead, `AckedChannel::next_event` reports each
+//! sent packet, and the range of messages it contained, as either acknowledged or presumed lost,
+//! so that the application can implement its own reliability on top.  This is how Quake style
+//! protocols synchronize state: rather than resending lost messages, which are stale by the time
+//! they would arrive, the sender tracks what the remote is known to have received and sends the
+//! latest state, or a delta against the last acknowledged state.
+
+use std::{collections::VecDeque, convert::TryInto, mem, ops::Range, pin::Pin, time::Duration};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{future::poll_fn, Sink, SinkExt, Stream, StreamExt};
+use thiserror::Error;
+
+use crate::{
+    bandwidth_limiter::BandwidthLimiter,
+    packet::{Packet, PacketPool, MAX_PACKET_LEN},
+    runtime::Timer,
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+/// The length of the sequence and acknowledgement header at the start of every packet.
+pub const HEADER_LEN: usize = 8;
+
+/// The maximum possible message length of an `AckedChannel` message for the largest possible
+/// packet, based on the `MAX_PACKET_LEN`.
+pub const MAX_MESSAGE_LEN: u16 = MAX_PACKET_LEN - HEADER_LEN as u16 - 2;
+
+/// The maximum number of sent packets tracked while waiting for an acknowledgement.  Sending more
+/// reports the oldest as lost.
+pub const MAX_IN_FLIGHT: usize = 256;
+
+// The number of packets before the latest received packet acknowledged by the ack bits.
+const ACK_BITS: u16 = 32;
+
+// Events not yet taken by `AckedChannel::next_event` are dropped past this many.
+const MAX_EVENTS: usize = 1024;
+
+/// Identifies a message sent on an `AckedChannel`, message ids count up from zero in the order
+/// messages are sent.
+pub type MessageId = u64;
+
+#[derive(Debug, Error)]
+pub enum SendError {
+    /// Fatal error due to channel disconnection.
+    #[error("outgoing packet stream has been disconnected")]
+    Disconnected,
+    /// Non-fatal error, message is unsent.
+    #[error("sent message is larger than the maximum packet size")]
+    TooBig,
+}
+
+#[derive(Debug, Error)]
+pub enum RecvError {
+    /// Fatal error due to channel disconnection.
+    #[error("incoming packet stream has been disconnected")]
+    Disconnected,
+    /// Non-fatal error, the remainder of the incoming packet is dropped.
+    #[error("incoming packet has bad message format")]
+    BadFormat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The target outgoing bandwidth, in bytes / sec.
+    pub bandwidth: u32,
+    /// The maximum amount of bandwidth credit that can accumulate.  This is the maximum bytes that
+    /// will be sent in a single burst.
+    pub burst_bandwidth: u32,
+    /// Sent packets which have not been acknowledged after this long are presumed lost.
+    ///
+    /// Packets are also presumed lost as soon as the remote acknowledges a packet sent more than
+    /// 32 packets after them, so this only matters when the remote stops sending.
+    pub loss_timeout: Duration,
+}
+
+/// The fate of a packet sent by an `AckedChannel`, returned by `AckedChannel::next_event`.
+///
+/// Only packets containing messages are reported, packets sent by `AckedChannel::flush` only to
+/// carry acknowledgements are not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AckEvent {
+    /// The remote received the packet with the given sequence number, containing the given range
+    /// of messages.
+    Acked {
+        packet: u16,
+        messages: Range<MessageId>,
+    },
+    /// The packet is presumed lost.  It may still have been received, but that will never be
+    /// reported.
+    Lost {
+        packet: u16,
+        messages: Range<MessageId>,
+    },
+}
+
+/// Turns a stream of unreliable, unordered packets into a stream of unreliable, unordered messages,
+/// and reports which sent messages were received.
+///
+/// Messages are coalesced into packets exactly like with `UnreliableChannel`, after the
+/// acknowledgement header.  Duplicated packets are dropped, but packets may still arrive out of
+/// order.
+///
+/// Acknowledgements are only sent with outgoing packets, so both sides must `flush` regularly,
+/// usually once per tick, for packets to be acknowledged.  A flush with no messages sends a packet
+/// only if there are new acknowledgements to send.  Received packets, and so acknowledgements,
+/// are only processed while receiving.
+pub struct AckedChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    packet_pool: P,
+    bandwidth_limiter: BandwidthLimiter<R>,
+    loss_timeout: Duration,
+    incoming_packets: I,
+    outgoing_packets: O,
+    // The packet being coalesced, which starts with room for the header, and the id of its first
+    // message.
+    out_packet: P::Packet,
+    out_first_message: MessageId,
+    next_message: MessageId,
+    next_seq: u16,
+    in_flight: VecDeque<InFlight<R::Instant>>,
+    events: VecDeque<AckEvent>,
+    // The latest sequence number received from the remote and which of the packets before it were
+    // received, and whether any of this has changed since the last packet was sent.
+    remote_seq: u16,
+    remote_bits: u32,
+    received_any: bool,
+    acks_pending: bool,
+    // The packet currently being read, the position of the next message in it, and the time it
+    // was received.
+    in_packet: Option<(P::Packet, usize, R::Instant)>,
+}
+
+impl<R, P, I, O> AckedChannel<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(runtime: R, packet_pool: P, settings: Settings, incoming: I, outgoing: O) -> Self {
+        let out_packet = new_packet(&packet_pool);
+        AckedChannel {
+            packet_pool,
+            bandwidth_limiter: BandwidthLimiter::new(
+                runtime,
+                settings.bandwidth,
+                settings.burst_bandwidth,
+            ),
+            loss_timeout: settings.loss_timeout,
+            incoming_packets: incoming,
+            outgoing_packets: outgoing,
+            out_packet,
+            out_first_message: 0,
+            next_message: 0,
+            next_seq: 0,
+            in_flight: VecDeque::new(),
+            events: VecDeque::new(),
+            remote_seq: u16::MAX,
+            remote_bits: 0,
+            received_any: false,
+            acks_pending: false,
+            in_packet: None,
+        }
+    }
+
+    /// The number of sent packets which have been neither acknowledged nor presumed lost.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Take the next acknowledged or lost packet event, if any.
+    ///
+    /// Events are generated as packets are received, and as packets pass the loss timeout.  At most
+    /// 1024 events are buffered, older events are dropped if they are not taken.
+    pub fn next_event(&mut self) -> Option<AckEvent> {
+        let now = self.bandwidth_limiter.timer().now();
+        while let Some(sent) = self.in_flight.front() {
+            let elapsed = self
+                .bandwidth_limiter
+                .timer()
+                .duration_between(sent.sent, now);
+            if elapsed < self.loss_timeout {
+                break;
+            }
+            let sent = self.in_flight.pop_front().unwrap();
+            push_event(&mut self.events, sent.lost());
+        }
+        self.events.pop_front()
+    }
+
+    /// Write the given message to the channel, returning its id.
+    ///
+    /// Messages are coalesced into larger packets before being sent, so in order to guarantee that
+    /// the message is actually sent, you must call `flush`.  The maximum message length is
+    /// `HEADER_LEN + 2` less than the size of the packets returned by the pool.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send(&mut self, msg: &[u8]) -> Result<MessageId, SendError> {
+        let msg_len: u16 = msg.len().try_into().map_err(|_| SendError::TooBig)?;
+
+        // The capacity may have shrunk below the length with a lower `DatagramLimit`.
+        let available = self
+            .out_packet
+            .capacity()
+            .saturating_sub(self.out_packet.len());
+        if available < msg_len as usize + 2 {
+            self.flush().await?;
+            let max_len = self.out_packet.capacity().checked_sub(HEADER_LEN + 2);
+            if max_len.is_none_or(|max_len| max_len < msg_len as usize) {
+                debug_event!(len = msg_len, "acked message too big");
+                return Err(SendError::TooBig);
+            }
+        }
+
+        let mut len = [0; 2];
+        LittleEndian::write_u16(&mut len, msg_len);
+        self.out_packet.extend(&len);
+        self.out_packet.extend(msg);
+
+        let id = self.next_message;
+        self.next_message += 1;
+        Ok(id)
+    }
+
+    /// Finish sending any unsent coalesced packets, or send a packet with no messages if there
+    /// are acknowledgements which have not been sent.
+    ///
+    /// This *must* be called to guarantee that any sent messages are actually sent to the outgoing
+    /// packet stream.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        let has_messages = self.next_message != self.out_first_message;
+        if has_messages || self.acks_pending {
+            self.bandwidth_limiter.update_available();
+            self.bandwidth_limiter.delay_until_available().await;
+
+            poll_fn(|cx| Pin::new(&mut self.outgoing_packets).poll_ready(cx))
+                .await
+                .map_err(|_| SendError::Disconnected)?;
+
+            let seq = self.next_seq;
+            self.next_seq = self.next_seq.wrapping_add(1);
+            LittleEndian::write_u16(&mut self.out_packet[0..2], seq);
+            LittleEndian::write_u16(&mut self.out_packet[2..4], self.remote_seq);
+            LittleEndian::write_u32(&mut self.out_packet[4..8], self.remote_bits);
+            self.acks_pending = false;
+
+            if has_messages {
+                self.in_flight.push_back(InFlight {
+                    seq,
+                    messages: self.out_first_message..self.next_message,
+                    sent: self.bandwidth_limiter.timer().now(),
+                });
+                self.out_first_message = self.next_message;
+                if self.in_flight.len() > MAX_IN_FLIGHT {
+                    let sent = self.in_flight.pop_front().unwrap();
+                    push_event(&mut self.events, sent.lost());
+                }
+            }
+
+            let out_packet = mem::replace(&mut self.out_packet, new_packet(&self.packet_pool));
+            self.bandwidth_limiter.take_bytes(out_packet.len() as u32);
+            trace_event!(seq, len = out_packet.len(), "sending acked packet");
+            Pin::new(&mut self.outgoing_packets)
+                .start_send(out_packet)
+                .map_err(|_| SendError::Disconnected)?;
+        }
+
+        // Always flush the outgoing sink, even with no new packet, in case a previous flush was
+        // canceled after the packet was handed to the sink.
+        self.outgoing_packets
+            .flush()
+            .await
+            .map_err(|_| SendError::Disconnected)?;
+
+        Ok(())
+    }
+
+    /// Receive a message.
+    ///
+    /// Receiving processes the acknowledgements in each incoming packet, which generates events for
+    /// `AckedChannel::next_event`.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv(&mut self) -> Result<&[u8], RecvError> {
+        Ok(self.recv_timed().await?.0)
+    }
+
+    /// Like `AckedChannel::recv`, but also returns the time at which the packet containing the
+    /// message was received, like `UnreliableChannel::recv_timed`.
+    pub async fn recv_timed(&mut self) -> Result<(&[u8], R::Instant), RecvError> {
+        loop {
+            if let Some((packet, in_pos, _)) = &self.in_packet {
+                if *in_pos < packet.len() {
+                    break;
+                }
+            }
+            self.in_packet = None;
+
+            let packet = self
+                .incoming_packets
+                .next()
+                .await
+                .ok_or(RecvError::Disconnected)?;
+            let received = self.bandwidth_limiter.timer().now();
+            if packet.len() < HEADER_LEN {
+                debug_event!(len = packet.len(), "dropping malformed acked packet");
+                return Err(RecvError::BadFormat);
+            }
+
+            let seq = LittleEndian::read_u16(&packet[0..2]);
+            let ack = LittleEndian::read_u16(&packet[2..4]);
+            let ack_bits = LittleEndian::read_u32(&packet[4..8]);
+            self.recv_acks(ack, ack_bits);
+            if self.recv_seq(seq) {
+                self.in_packet = Some((packet, HEADER_LEN, received));
+            } else {
+                debug_event!(seq, "dropping duplicate acked packet");
+            }
+        }
+        let (packet, in_pos, received) = self.in_packet.as_mut().unwrap();
+
+        if *in_pos + 2 > packet.len() {
+            debug_event!(
+                dropped = packet.len() - *in_pos,
+                "dropping malformed acked packet"
+            );
+            *in_pos = packet.len();
+            return Err(RecvError::BadFormat);
+        }
+        let length = LittleEndian::read_u16(&packet[*in_pos..*in_pos + 2]) as usize;
+        *in_pos += 2;
+
+        if *in_pos + length > packet.len() {
+            debug_event!(
+                dropped = packet.len() - *in_pos,
+                "dropping malformed acked packet"
+            );
+            *in_pos = packet.len();
+            return Err(RecvError::BadFormat);
+        }
+
+        let msg = &packet[*in_pos..*in_pos + length];
+        *in_pos += length;
+
+        Ok((msg, *received))
+    }
+
+    // Record a received sequence number to be acknowledged, returning false if it is a duplicate.
+    fn recv_seq(&mut self, seq: u16) -> bool {
+        if !self.received_any {
+            self.received_any = true;
+            self.remote_seq = seq;
+            self.remote_bits = 0;
+            self.acks_pending = true;
+            return true;
+        }
+
+        let diff = seq.wrapping_sub(self.remote_seq) as i16;
+        if diff > 0 {
+            let diff = diff as u32;
+            self.remote_bits = self.remote_bits.checked_shl(diff).unwrap_or(0);
+            if diff <= ACK_BITS as u32 {
+                self.remote_bits |= 1 << (diff - 1);
+            }
+            self.remote_seq = seq;
+        } else if diff == 0 {
+            return false;
+        } else {
+            // Packets too old to be acknowledged are still delivered, they are only unordered.
+            let diff = -(diff as i32) as u32;
+            if diff <= ACK_BITS as u32 {
+                let bit = 1 << (diff - 1);
+                if self.remote_bits & bit != 0 {
+                    return false;
+                }
+                self.remote_bits |= bit;
+            }
+        }
+        self.acks_pending = true;
+        true
+    }
+
+    // Generate events for the packets acknowledged by a received header, and for the packets which
+    // can no longer be.
+    fn recv_acks(&mut self, ack: u16, ack_bits: u32) {
+        let events = &mut self.events;
+        self.in_flight.retain(|sent| {
+            let diff = ack.wrapping_sub(sent.seq);
+            let acked = diff == 0 || (diff <= ACK_BITS && ack_bits & (1 << (diff - 1)) != 0);
+            if acked {
+                push_event(events, sent.acked());
+                false
+            } else if diff > ACK_BITS && diff < 0x8000 {
+                // The remote has received a packet too far after this one for it to ever be
+                // acknowledged.
+                push_event(events, sent.lost());
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+struct InFlight<I> {
+    seq: u16,
+    messages: Range<MessageId>,
+    sent: I,
+}
+
+impl<I> InFlight<I> {
+    fn acked(&self) -> AckEvent {
+        AckEvent::Acked {
+            packet: self.seq,
+            messages: self.messages.clone(),
+        }
+    }
+
+    fn lost(&self) -> AckEvent {
+        AckEvent::Lost {
+            packet: self.seq,
+            messages: self.messages.clone(),
+        }
+    }
+}
+
+fn push_event(events: &mut VecDeque<AckEvent>, event: AckEvent) {
+    if events.len() >= MAX_EVENTS {
+        debug_event!("dropping acked channel event, events are not being taken");
+        events.pop_front();
+    }
+    events.push_back(event);
+}
+
+fn new_packet<P: PacketPool>(pool: &P) -> P::Packet {
+    let mut packet = pool.acquire();
+    packet.resize(HEADER_LEN, 0);
+    packet
+}
@@ -0,0 +1,315 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{ready, Sink, Stream};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketMetadata, PacketPool},
+    replay_window::ReplayWindow,
+};
+
+/// The size of the sequence number header at the start of every authenticated packet.
+pub const HEADER_LEN: usize = 8;
+/// The size of the truncated HMAC-SHA256 tag appended to every authenticated packet.
+pub const TAG_LEN: usize = 16;
+/// The total number of bytes authentication adds to every packet.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+
+/// Pre-shared secrets for each direction of a connection.
+///
+/// The `send` secret of one side must be the `recv` secret of the other.  The two secrets must be
+/// different, since both directions number their packets from 0, and a packet reflected back to
+/// its sender would otherwise verify as one sent by the remote.
+#[derive(Clone)]
+pub struct Secrets {
+    pub send: Vec<u8>,
+    pub recv: Vec<u8>,
+}
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secrets { .. }")
+    }
+}
+
+/// A wrapper over a `Packet` that reserves space for the sequence number header and the
+/// authentication tag.
+#[derive(Debug)]
+pub struct AuthenticatedPacket<P>(P);
+
+impl<P> Packet for AuthenticatedPacket<P>
+where
+    P: Packet,
+{
+    fn capacity(&self) -> usize {
+        self.0.capacity().saturating_sub(OVERHEAD)
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.0.resize(len + HEADER_LEN, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
+    }
+}
+
+impl<P> Deref for AuthenticatedPacket<P>
+where
+    P: Packet,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[HEADER_LEN..]
+    }
+}
+
+impl<P> DerefMut for AuthenticatedPacket<P>
+where
+    P: Packet,
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[HEADER_LEN..]
+    }
+}
+
+/// A packet pool which produces `AuthenticatedPacket`s with room for the authentication tag.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedPacketPool<P>(P);
+
+impl<P> AuthenticatedPacketPool<P> {
+    pub fn new(packet_pool: P) -> Self {
+        AuthenticatedPacketPool(packet_pool)
+    }
+}
+
+impl<P> PacketPool for AuthenticatedPacketPool<P>
+where
+    P: PacketPool,
+{
+    type Packet = AuthenticatedPacket<P::Packet>;
+
+    fn acquire(&self) -> AuthenticatedPacket<P::Packet> {
+        let mut packet = self.0.acquire();
+        packet.resize(HEADER_LEN, 0);
+        AuthenticatedPacket(packet)
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
+}
+
+impl<P> From<P> for AuthenticatedPacketPool<P> {
+    fn from(pool: P) -> AuthenticatedPacketPool<P> {
+        AuthenticatedPacketPool(pool)
+    }
+}
+
+/// Error returned when verifying a packet, verification errors are not fatal.
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("packet is too short to be authenticated")]
+    TooShort,
+    #[error("packet failed authentication")]
+    Unauthenticated,
+    #[error("packet has already been received")]
+    Replayed,
+}
+
+/// Appends a sequence number and a truncated HMAC-SHA256 tag keyed by a pre-shared secret to
+/// outgoing packets.
+///
+/// This provides authenticity but not confidentiality, packet contents are still sent in the
+/// clear.  It is cheaper than the `encryption` module, for games which only need to prevent
+/// tampering and spoofing.
+pub struct Signer {
+    mac: Hmac<Sha256>,
+    sequence: u64,
+}
+
+impl Signer {
+    /// The pre-shared secret may be of any length, but should be at least 32 random bytes.
+    pub fn new(secret: &[u8]) -> Signer {
+        Signer {
+            mac: new_mac(secret),
+            sequence: 0,
+        }
+    }
+
+    /// The sequence number that will be used for the next packet.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Append the authentication tag to a packet, returning the underlying packet ready to be
+    /// sent.
+    pub fn sign<P: Packet>(&mut self, packet: AuthenticatedPacket<P>) -> P {
+        let mut packet = packet.0;
+        LittleEndian::write_u64(&mut packet[0..HEADER_LEN], self.sequence);
+        self.sequence = self
+            .sequence
+            .checked_add(1)
+            .expect("sequence numbers exhausted");
+
+        let mut mac = self.mac.clone();
+        mac.update(&packet);
+        let tag = mac.finalize().into_bytes();
+        packet.extend(&tag[..TAG_LEN]);
+        packet
+    }
+}
+
+/// Verifies incoming packets signed by a `Signer` with the same pre-shared secret.
+///
+/// The secret must only be used to sign packets in the direction being verified, see `Secrets`.
+///
+/// Packets which have already been received are rejected with `VerifyError::Replayed`, so that
+/// recorded packets cannot be re-injected, see `ReplayWindow`.
+pub struct Verifier {
+    mac: Hmac<Sha256>,
+    replay_window: ReplayWindow,
+}
+
+impl Verifier {
+    pub fn new(secret: &[u8]) -> Verifier {
+        Verifier {
+            mac: new_mac(secret),
+            replay_window: ReplayWindow::new(),
+        }
+    }
+
+    /// Verify and remove the authentication tag of a packet, returning the packet and its
+    /// sequence number.
+    pub fn verify<P: Packet>(
+        &mut self,
+        mut packet: P,
+    ) -> Result<(u64, AuthenticatedPacket<P>), VerifyError> {
+        if packet.len() < OVERHEAD {
+            return Err(VerifyError::TooShort);
+        }
+        let sequence = LittleEndian::read_u64(&packet[0..HEADER_LEN]);
+        if !self.replay_window.accepts(sequence) {
+            return Err(VerifyError::Replayed);
+        }
+
+        let tag_start = packet.len() - TAG_LEN;
+        let mut mac = self.mac.clone();
+        mac.update(&packet[..tag_start]);
+        mac.verify_truncated_left(&packet[tag_start..])
+            .map_err(|_| VerifyError::Unauthenticated)?;
+        packet.resize(tag_start, 0);
+
+        self.replay_window.insert(sequence);
+        Ok((sequence, AuthenticatedPacket(packet)))
+    }
+}
+
+fn new_mac(secret: &[u8]) -> Hmac<Sha256> {
+    Hmac::new_from_slice(secret).expect("HMAC accepts keys of any length")
+}
+
+/// Wrap a raw packet stream and sink so that every packet is authenticated with pre-shared
+/// secrets, outgoing packets are signed with `secrets.send` and incoming packets verified with
+/// `secrets.recv`.
+///
+/// The returned stream and sink carry `AuthenticatedPacket`s, and are meant to be given to a
+/// `PacketMultiplexer` (or channel) using an `AuthenticatedPacketPool`.  Incoming packets are
+/// verified before they reach the multiplexer, and packets that fail verification or are replayed
+/// are dropped.
+pub fn authenticated<I, O>(
+    secrets: &Secrets,
+    incoming: I,
+    outgoing: O,
+) -> (VerifyStream<I>, SignSink<O>) {
+    (
+        VerifyStream::new(Verifier::new(&secrets.recv), incoming),
+        SignSink::new(Signer::new(&secrets.send), outgoing),
+    )
+}
+
+/// A `Stream` of verified packets, created by `authenticated`.
+pub struct VerifyStream<I> {
+    verifier: Verifier,
+    incoming: I,
+}
+
+impl<I> VerifyStream<I> {
+    pub fn new(verifier: Verifier, incoming: I) -> Self {
+        VerifyStream { verifier, incoming }
+    }
+}
+
+impl<I, P> Stream for VerifyStream<I>
+where
+    I: Stream<Item = P> + Unpin,
+    P: Packet,
+{
+    type Item = AuthenticatedPacket<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.incoming).poll_next(cx)) {
+                Some(packet) => {
+                    if let Ok((_, packet)) = self.verifier.verify(packet) {
+                        return Poll::Ready(Some(packet));
+                    }
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// A `Sink` which signs packets, created by `authenticated`.
+pub struct SignSink<O> {
+    signer: Signer,
+    outgoing: O,
+}
+
+impl<O> SignSink<O> {
+    pub fn new(signer: Signer, outgoing: O) -> Self {
+        SignSink { signer, outgoing }
+    }
+}
+
+impl<O, P> Sink<AuthenticatedPacket<P>> for SignSink<O>
+where
+    O: Sink<P> + Unpin,
+    P: Packet,
+{
+    type Error = O::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(
+        mut self: Pin<&mut Self>,
+        packet: AuthenticatedPacket<P>,
+    ) -> Result<(), Self::Error> {
+        let packet = self.signer.sign(packet);
+        Pin::new(&mut self.outgoing).start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Estimates the available throughput of a connection passively, from samples of the rate at
+/// which data is delivered to the other side.
+///
+/// Every `ReliableChannel` maintains one of these internally, sampling the delivery rate each time
+/// new data is acknowledged, and a `BandwidthEstimate` handle to it is available from
+/// `ReliableChannel::bandwidth_estimate`.  An estimator can also be fed with any other source of
+/// delivery rates, such as rates periodically reported by the receiver over an unreliable channel.
+///
+/// The estimate is the maximum delivery rate sampled within a recent window of time, since
+/// samples are easily lowered by the sender not having enough data to send, but are rarely
+/// raised above the real available throughput.  This means that the estimate is only ever as high
+/// as the highest rate data has recently been sent at, so it measures the throughput the
+/// connection has recently proven able to carry rather than its full capacity.
+#[derive(Debug)]
+pub struct BandwidthEstimator {
+    window: Duration,
+    samples: VecDeque<(Duration, f64)>,
+    estimate: BandwidthEstimate,
+}
+
+impl BandwidthEstimator {
+    /// Create an estimator which remembers samples for the given window of time.
+    pub fn new(window: Duration) -> Self {
+        BandwidthEstimator {
+            window,
+            samples: VecDeque::new(),
+            estimate: BandwidthEstimate(Arc::new(AtomicU64::new(NO_ESTIMATE))),
+        }
+    }
+
+    /// Add a sample of `bytes` delivered over the given `interval`, ending at the given `time`.
+    ///
+    /// Times may be measured from any starting point, but must not decrease.  Samples with a zero
+    /// interval are ignored.
+    pub fn add_sample(&mut self, time: Duration, bytes: u64, interval: Duration) {
+        if interval == Duration::ZERO {
+            return;
+        }
+        let rate = bytes as f64 / interval.as_secs_f64();
+
+        while let Some(&(sample_time, _)) = self.samples.front() {
+            if time.saturating_sub(sample_time) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        // Samples that are lower than a newer sample can never be the maximum again.
+        while let Some(&(_, sample_rate)) = self.samples.back() {
+            if sample_rate > rate {
+                break;
+            }
+            self.samples.pop_back();
+        }
+        self.samples.push_back((time, rate));
+
+        self.estimate
+            .set(self.samples.front().map(|&(_, rate)| rate));
+    }
+
+    /// The current estimate in bytes per second, or `None` if there are no samples yet.
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        self.estimate.bytes_per_sec()
+    }
+
+    /// A handle to read the estimate, which is updated as samples are added.
+    pub fn estimate(&self) -> BandwidthEstimate {
+        self.estimate.clone()
+    }
+}
+
+const NO_ESTIMATE: u64 = u64::MAX;
+
+/// A handle to the current estimate of a `BandwidthEstimator`.
+#[derive(Debug, Clone)]
+pub struct BandwidthEstimate(Arc<AtomicU64>);
+
+impl BandwidthEstimate {
+    /// The estimated available throughput in bytes per second, or `None` if nothing has been
+    /// sampled yet.
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        match self.0.load(Ordering::Relaxed) {
+            NO_ESTIMATE => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+
+    fn set(&self, estimate: Option<f64>) {
+        self.0.store(
+            estimate.map_or(NO_ESTIMATE, f64::to_bits),
+            Ordering::Relaxed,
+        );
+    }
+}
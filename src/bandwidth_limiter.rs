@@ -1,8 +1,8 @@
-use std::time::Duration;
+use core::time::Duration;
 
-use crate::Runtime;
+use crate::runtime::Timer;
 
-pub struct BandwidthLimiter<R: Runtime> {
+pub struct BandwidthLimiter<R: Timer> {
     runtime: R,
     bandwidth: u32,
     burst_bandwidth: u32,
@@ -10,7 +10,7 @@ pub struct BandwidthLimiter<R: Runtime> {
     last_calculation: R::Instant,
 }
 
-impl<R: Runtime> BandwidthLimiter<R> {
+impl<R: Timer> BandwidthLimiter<R> {
     /// The `burst_bandwidth` is the maximum amount of bandwidth credit that can accumulate.
     pub fn new(runtime: R, bandwidth: u32, burst_bandwidth: u32) -> BandwidthLimiter<R> {
         let last_calculation = runtime.now();
@@ -23,14 +23,38 @@ impl<R: Runtime> BandwidthLimiter<R> {
         }
     }
 
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn bandwidth(&self) -> u32 {
+        self.bandwidth
+    }
+
+    /// Change the rate at which bandwidth credit accumulates, credit accumulated so far at the old
+    /// rate is kept.
+    pub fn set_bandwidth(&mut self, bandwidth: u32) {
+        self.update_available();
+        self.bandwidth = bandwidth;
+    }
+
+    pub fn timer(&self) -> &R {
+        &self.runtime
+    }
+
     /// Delay until a time where there will be bandwidth available.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
     pub async fn delay_until_available(&self) {
+        if let Some(delay) = self.delay() {
+            self.runtime.sleep(delay).await;
+        }
+    }
+
+    /// How long until there will be bandwidth available, if there is none available now.
+    pub fn delay(&self) -> Option<Duration> {
         if self.bytes_available < 0. {
-            self.runtime
-                .sleep(Duration::from_secs_f64(
-                    (-self.bytes_available) / self.bandwidth as f64,
-                ))
-                .await;
+            Some(Duration::from_secs_f64(
+                (-self.bytes_available) / self.bandwidth as f64,
+            ))
+        } else {
+            None
         }
     }
 
@@ -55,6 +79,13 @@ impl<R: Runtime> BandwidthLimiter<R> {
         self.bytes_available >= 0.
     }
 
+    /// Returns true if at least the given amount of bytes is available, for limiters which must
+    /// not go into debt.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub fn has_bytes(&self, bytes: u32) -> bool {
+        self.bytes_available >= bytes as f64
+    }
+
     /// Record that bytes were sent, possibly going into bandwidth debt.
     pub fn take_bytes(&mut self, bytes: u32) {
         self.bytes_available -= bytes as f64
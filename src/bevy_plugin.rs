@@ -0,0 +1,250 @@
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use bevy_app::{App, Plugin, PostUpdate, PreUpdate};
+use bevy_ecs::{
+    event::{Event, EventWriter},
+    system::{ResMut, Resource},
+};
+use futures::{task::noop_waker_ref, Sink, SinkExt, Stream};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    message_channels::MessageChannels,
+    packet::Packet,
+    packet_multiplexer::{
+        IncomingMultiplexedPackets, OutgoingMultiplexedPackets, PacketMultiplexer,
+    },
+};
+
+/// Identifies a connection added to `Connections`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+/// Sent when a connection is added to or removed from `Connections`.
+///
+/// A connection is removed automatically, and `Disconnected` sent, when its transport stream
+/// ends, its transport sink errors, or its `MessageChannels` becomes disconnected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Event)]
+pub enum ConnectionEvent {
+    Connected(ConnectionId),
+    Disconnected(ConnectionId),
+}
+
+type BoxIncoming<P> = Box<dyn Stream<Item = P> + Send + Unpin>;
+type BoxOutgoing<P> = Box<dyn Sink<P, Error = ()> + Send + Unpin>;
+
+struct Connection<P> {
+    channels: MessageChannels,
+    // Neither packets nor transports need be `Sync`, but resources must be.
+    pump: Mutex<Pump<P>>,
+}
+
+struct Pump<P> {
+    mux_incoming: IncomingMultiplexedPackets<P>,
+    mux_outgoing: OutgoingMultiplexedPackets<P>,
+    incoming: BoxIncoming<P>,
+    outgoing: BoxOutgoing<P>,
+}
+
+/// A resource holding the `MessageChannels` for every connection.
+///
+/// Connections are added along with the packet stream and sink of their transport (for example a
+/// `UdpPeer`), and packets are moved between the transport and the multiplexer by the
+/// `TurbulencePlugin` systems every frame.  The channel tasks themselves still run on whichever
+/// `Runtime` the `MessageChannels` were built with.
+#[derive(Resource)]
+pub struct Connections<P> {
+    next_id: u64,
+    connections: FxHashMap<ConnectionId, Connection<P>>,
+    events: Vec<ConnectionEvent>,
+}
+
+impl<P> Default for Connections<P> {
+    fn default() -> Self {
+        Connections {
+            next_id: 0,
+            connections: FxHashMap::default(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl<P> Connections<P>
+where
+    P: Packet + Unpin + Send + 'static,
+{
+    /// Add a connection whose `MessageChannels` were built with the given multiplexer.
+    ///
+    /// Sends `ConnectionEvent::Connected` on the next frame.
+    pub fn add<I, O>(
+        &mut self,
+        multiplexer: PacketMultiplexer<P>,
+        channels: MessageChannels,
+        incoming: I,
+        outgoing: O,
+    ) -> ConnectionId
+    where
+        I: Stream<Item = P> + Send + Unpin + 'static,
+        O: Sink<P> + Send + Unpin + 'static,
+    {
+        let id = ConnectionId(self.next_id);
+        self.next_id += 1;
+        let (mux_incoming, mux_outgoing) = multiplexer.start();
+        self.connections.insert(
+            id,
+            Connection {
+                channels,
+                pump: Mutex::new(Pump {
+                    mux_incoming,
+                    mux_outgoing,
+                    incoming: Box::new(incoming),
+                    outgoing: Box::new(outgoing.sink_map_err(|_| ())),
+                }),
+            },
+        );
+        self.events.push(ConnectionEvent::Connected(id));
+        id
+    }
+
+    /// Remove a connection, returning its `MessageChannels`.
+    ///
+    /// Sends `ConnectionEvent::Disconnected` on the next frame.
+    pub fn remove(&mut self, id: ConnectionId) -> Option<MessageChannels> {
+        let connection = self.connections.remove(&id)?;
+        self.events.push(ConnectionEvent::Disconnected(id));
+        Some(connection.channels)
+    }
+
+    pub fn contains(&self, id: ConnectionId) -> bool {
+        self.connections.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = ConnectionId> + '_ {
+        self.connections.keys().copied()
+    }
+
+    pub fn get_mut(&mut self, id: ConnectionId) -> Option<&mut MessageChannels> {
+        self.connections.get_mut(&id).map(|c| &mut c.channels)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ConnectionId, &mut MessageChannels)> {
+        self.connections
+            .iter_mut()
+            .map(|(&id, c)| (id, &mut c.channels))
+    }
+
+    /// Move every packet that is ready between the transports and the multiplexers without
+    /// blocking, and remove any connections that have become disconnected.
+    ///
+    /// This is called every frame by the `TurbulencePlugin` systems, and only needs to be called
+    /// manually when not using the plugin.
+    pub fn poll(&mut self) {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut disconnected = Vec::new();
+        for (&id, connection) in &mut self.connections {
+            if !connection.poll(&mut cx) {
+                disconnected.push(id);
+            }
+        }
+        for id in disconnected {
+            self.remove(id);
+        }
+    }
+}
+
+impl<P> Connection<P>
+where
+    P: Packet + Unpin,
+{
+    // Returns false if the connection has become disconnected.
+    fn poll(&mut self, cx: &mut Context) -> bool {
+        let Pump {
+            mux_incoming,
+            mux_outgoing,
+            incoming,
+            outgoing,
+        } = self.pump.get_mut().unwrap();
+
+        loop {
+            match Pin::new(&mut *incoming).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    // Packets for unknown or full channels are dropped, the same as every other
+                    // transport adapter.
+                    let _ = mux_incoming.try_send(packet);
+                }
+                Poll::Ready(None) => return false,
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            match Pin::new(&mut *outgoing).poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(())) => return false,
+                Poll::Pending => break,
+            }
+            match Pin::new(&mut *mux_outgoing).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    if Pin::new(&mut *outgoing).start_send(packet).is_err() {
+                        return false;
+                    }
+                }
+                Poll::Ready(None) => return false,
+                Poll::Pending => break,
+            }
+        }
+        if let Poll::Ready(Err(())) = Pin::new(&mut *outgoing).poll_flush(cx) {
+            return false;
+        }
+
+        self.channels.is_connected()
+    }
+}
+
+/// A Bevy plugin which inserts a `Connections<P>` resource, drives every connection's packets at
+/// the start and end of each frame, and sends `ConnectionEvent`s.
+///
+/// Add it with `app.add_plugins(TurbulencePlugin::<P>::default())`, where `P` is the packet type
+/// of the transport, then add connections with `Connections::add`.
+pub struct TurbulencePlugin<P>(PhantomData<fn() -> P>);
+
+impl<P> Default for TurbulencePlugin<P> {
+    fn default() -> Self {
+        TurbulencePlugin(PhantomData)
+    }
+}
+
+impl<P> Plugin for TurbulencePlugin<P>
+where
+    P: Packet + Unpin + Send + 'static,
+{
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Connections<P>>()
+            .add_event::<ConnectionEvent>()
+            .add_systems(PreUpdate, poll_connections::<P>)
+            .add_systems(PostUpdate, poll_connections::<P>);
+    }
+}
+
+fn poll_connections<P>(
+    mut connections: ResMut<Connections<P>>,
+    mut events: EventWriter<ConnectionEvent>,
+) where
+    P: Packet + Unpin + Send + 'static,
+{
+    connections.poll();
+    events.send_batch(connections.events.drain(..));
+}
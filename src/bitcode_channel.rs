@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    serde_channel::{
+        self, Format, ReliableSerdeChannel, ReliableSerdeTypedChannel, UnreliableSerdeChannel,
+        UnreliableSerdeTypedChannel,
+    },
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+#[derive(Debug, Error)]
+pub enum BitcodeError {
+    #[error("bitcode error: {0}")]
+    Bitcode(#[from] bitcode::Error),
+    /// The serialized message does not fit in the buffer.
+    #[error("bitcode message too large for buffer")]
+    TooLarge,
+}
+
+/// The bitcode data format.
+///
+/// Bitcode packs values at the bit level rather than padding them to byte boundaries, so messages
+/// with many booleans or small enums are much smaller than with bincode.  The format is not
+/// self-describing and is not stable across major versions of bitcode, so both peers must use the
+/// same version.
+///
+/// Bitcode always serializes into its own buffer first, so sending allocates.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Bitcode;
+
+impl Format for Bitcode {
+    type Error = BitcodeError;
+
+    fn serialize<T: Serialize + ?Sized>(buffer: &mut [u8], msg: &T) -> Result<usize, BitcodeError> {
+        let encoded = bitcode::serialize(msg)?;
+        let buffer = buffer
+            .get_mut(0..encoded.len())
+            .ok_or(BitcodeError::TooLarge)?;
+        buffer.copy_from_slice(&encoded);
+        Ok(encoded.len())
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(buffer: &'a [u8]) -> Result<T, BitcodeError> {
+        Ok(bitcode::deserialize(buffer)?)
+    }
+}
+
+pub type SendError = serde_channel::SendError<BitcodeError>;
+pub type RecvError = serde_channel::RecvError<BitcodeError>;
+pub type Error = serde_channel::Error<BitcodeError>;
+
+/// An `UnreliableChannel` which sends messages bit-packed with bitcode.
+pub type UnreliableBitcodeChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeChannel<Bitcode, R, P, I, O>;
+
+pub type UnreliableBitcodeTypedChannel<
+    T,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeTypedChannel<T, Bitcode, R, P, I, O>;
+
+/// A `ReliableChannel` which sends messages bit-packed with bitcode, each prefixed with its length.
+pub type ReliableBitcodeChannel = ReliableSerdeChannel<Bitcode>;
+
+pub type ReliableBitcodeTypedChannel<T> = ReliableSerdeTypedChannel<T, Bitcode>;
@@ -0,0 +1,354 @@
+use byteorder::{ByteOrder, LittleEndian};
+use thiserror::Error;
+
+use crate::reliable_channel::{self, ReliableChannel};
+
+const OFFER: u8 = 0;
+const RESUME: u8 = 1;
+const CHUNK: u8 = 2;
+const COMPLETE: u8 = 3;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Fatal internal channel error.
+    #[error("reliable channel error: {0}")]
+    ReliableChannelError(#[from] reliable_channel::Error),
+    /// Fatal, the other side sent a message which is not valid at this point of a transfer.
+    #[error("remote endpoint has violated the blob transfer protocol")]
+    ProtocolError,
+    /// Non-fatal, the offered blob is larger than `Settings::max_blob_len` and has been rejected.
+    #[error("offered blob exceeds the configured max blob length")]
+    BlobTooLarge,
+    /// Non-fatal, the received blob does not match the hash it was offered with.  The partial blob
+    /// is reset and the sender is told of the failure, so the transfer may be retried from the
+    /// start.
+    #[error("received blob failed its integrity check")]
+    HashMismatch,
+    /// Non-fatal, the receiver rejected the blob, either because it was too large or because it
+    /// failed its integrity check.
+    #[error("blob was rejected by the receiver")]
+    Rejected,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The maximum number of bytes of blob data sent in a single message.
+    pub chunk_len: u16,
+    /// The largest blob that will be accepted by `BlobChannel::recv`.
+    pub max_blob_len: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            chunk_len: 1024,
+            max_blob_len: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash used to check the integrity of transferred blobs.
+///
+/// This detects corruption and mismatched blobs, it is not a cryptographic hash.
+pub fn blob_hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// The receiving side's state of a single blob transfer, which survives the connection it was
+/// received on.
+///
+/// If a transfer is interrupted, passing the same `PartialBlob` to `BlobChannel::recv` on a new
+/// connection resumes the transfer of the same blob from the last offset received.
+#[derive(Debug, Default, Clone)]
+pub struct PartialBlob {
+    offer: Option<Offer>,
+    data: Vec<u8>,
+}
+
+impl PartialBlob {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The id of the blob being received, if any.
+    pub fn id(&self) -> Option<u64> {
+        self.offer.as_ref().map(|offer| offer.id)
+    }
+
+    /// The total length of the blob being received, if any.
+    pub fn total_len(&self) -> Option<u64> {
+        self.offer.as_ref().map(|offer| offer.len)
+    }
+
+    /// The number of bytes received so far, the offset a resumed transfer will start from.
+    pub fn received(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn reset(&mut self) {
+        self.offer = None;
+        self.data.clear();
+    }
+}
+
+/// A fully received and verified blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub id: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Offer {
+    id: u64,
+    len: u64,
+    hash: u64,
+}
+
+enum Message {
+    Offer(Offer),
+    Resume { id: u64, offset: u64 },
+    // The chunk data itself is in the read buffer.
+    Chunk { offset: u64 },
+    Complete { id: u64, accepted: bool },
+}
+
+/// Wraps a `ReliableChannel` to transfer large byte blobs, such as assets or replays, in chunks.
+///
+/// The sender first offers the blob's id, length and hash, and the receiver answers with the
+/// offset to start from, which is non-zero when it already holds part of the same blob from an
+/// interrupted transfer.  The rest of the blob is then sent in chunks, and once the receiver has
+/// verified the hash of the whole blob it tells the sender whether the transfer succeeded.
+///
+/// Both sides must agree on `Settings::chunk_len`.  A channel may be used to send and receive
+/// blobs in either direction, but only one transfer may be in progress at a time.
+pub struct BlobChannel {
+    channel: ReliableChannel,
+    settings: Settings,
+    write_buffer: Vec<u8>,
+    read_buffer: Vec<u8>,
+}
+
+impl BlobChannel {
+    pub fn new(channel: ReliableChannel, settings: Settings) -> Self {
+        BlobChannel {
+            channel,
+            settings,
+            write_buffer: Vec::new(),
+            read_buffer: Vec::new(),
+        }
+    }
+
+    /// Send the given blob, resuming from wherever the receiver has already received up to.
+    ///
+    /// `progress` is called with the number of bytes sent so far and the total length of the blob,
+    /// first with the offset the transfer is resumed from, and then after every chunk.  Returns
+    /// once the receiver has received and verified the entire blob.
+    ///
+    /// This method is not cancel safe, the channel must not be used for another transfer after
+    /// canceling it.
+    pub async fn send(
+        &mut self,
+        id: u64,
+        data: &[u8],
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<(), Error> {
+        let len = data.len() as u64;
+        self.write_buffer.clear();
+        self.write_buffer.push(OFFER);
+        write_u64(&mut self.write_buffer, id);
+        write_u64(&mut self.write_buffer, len);
+        write_u64(&mut self.write_buffer, blob_hash(data));
+        self.write_message().await?;
+        self.channel.flush().await?;
+
+        let mut offset = match self.recv_message().await? {
+            Message::Resume {
+                id: resume_id,
+                offset,
+            } if resume_id == id && offset <= len => offset,
+            Message::Complete {
+                id: complete_id,
+                accepted: false,
+            } if complete_id == id => return Err(Error::Rejected),
+            _ => return Err(Error::ProtocolError),
+        };
+        progress(offset, len);
+
+        while offset < len {
+            let end = (offset + self.settings.chunk_len as u64).min(len);
+            self.write_buffer.clear();
+            self.write_buffer.push(CHUNK);
+            write_u64(&mut self.write_buffer, offset);
+            write_u16(&mut self.write_buffer, (end - offset) as u16);
+            self.write_buffer
+                .extend_from_slice(&data[offset as usize..end as usize]);
+            self.write_message().await?;
+            offset = end;
+            progress(offset, len);
+        }
+        self.channel.flush().await?;
+
+        match self.recv_message().await? {
+            Message::Complete {
+                id: complete_id,
+                accepted,
+            } if complete_id == id => {
+                if accepted {
+                    Ok(())
+                } else {
+                    Err(Error::Rejected)
+                }
+            }
+            _ => Err(Error::ProtocolError),
+        }
+    }
+
+    /// Receive the next blob offered by the other side.
+    ///
+    /// If the offered blob is the same blob that `partial` holds the beginning of, the transfer is
+    /// resumed from the end of the received data, otherwise `partial` is reset first.  `progress`
+    /// is called with the number of bytes received so far and the total length of the blob, first
+    /// with the resumed offset and then after every chunk.
+    ///
+    /// On success, `partial` is reset and the whole blob is returned.  Canceling this method, or
+    /// any fatal error, leaves everything received so far in `partial` to be resumed on a later
+    /// connection, but just like `send` the channel itself must not be used again.
+    pub async fn recv(
+        &mut self,
+        partial: &mut PartialBlob,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<Blob, Error> {
+        let offer = match self.recv_message().await? {
+            Message::Offer(offer) => offer,
+            _ => return Err(Error::ProtocolError),
+        };
+
+        if offer.len > self.settings.max_blob_len {
+            self.complete(offer.id, false).await?;
+            return Err(Error::BlobTooLarge);
+        }
+        if partial.offer != Some(offer) {
+            partial.reset();
+            partial.offer = Some(offer);
+        }
+
+        self.write_buffer.clear();
+        self.write_buffer.push(RESUME);
+        write_u64(&mut self.write_buffer, offer.id);
+        write_u64(&mut self.write_buffer, partial.received());
+        self.write_message().await?;
+        self.channel.flush().await?;
+        progress(partial.received(), offer.len);
+
+        while partial.received() < offer.len {
+            match self.recv_message().await? {
+                Message::Chunk { offset }
+                    if offset == partial.received()
+                        && offset + self.read_buffer.len() as u64 <= offer.len =>
+                {
+                    partial.data.extend_from_slice(&self.read_buffer);
+                }
+                _ => return Err(Error::ProtocolError),
+            }
+            progress(partial.received(), offer.len);
+        }
+
+        if blob_hash(&partial.data) != offer.hash {
+            partial.reset();
+            self.complete(offer.id, false).await?;
+            return Err(Error::HashMismatch);
+        }
+
+        let data = std::mem::take(&mut partial.data);
+        partial.reset();
+        self.complete(offer.id, true).await?;
+        Ok(Blob { id: offer.id, data })
+    }
+
+    async fn complete(&mut self, id: u64, accepted: bool) -> Result<(), Error> {
+        self.write_buffer.clear();
+        self.write_buffer.push(COMPLETE);
+        write_u64(&mut self.write_buffer, id);
+        self.write_buffer.push(accepted as u8);
+        self.write_message().await?;
+        self.channel.flush().await?;
+        Ok(())
+    }
+
+    async fn write_message(&mut self) -> Result<(), Error> {
+        let mut pos = 0;
+        while pos < self.write_buffer.len() {
+            pos += self.channel.write(&self.write_buffer[pos..]).await?;
+        }
+        Ok(())
+    }
+
+    async fn recv_message(&mut self) -> Result<Message, Error> {
+        self.read_exact(1).await?;
+        let message = match self.read_buffer[0] {
+            OFFER => {
+                self.read_exact(24).await?;
+                Message::Offer(Offer {
+                    id: LittleEndian::read_u64(&self.read_buffer[0..8]),
+                    len: LittleEndian::read_u64(&self.read_buffer[8..16]),
+                    hash: LittleEndian::read_u64(&self.read_buffer[16..24]),
+                })
+            }
+            RESUME => {
+                self.read_exact(16).await?;
+                Message::Resume {
+                    id: LittleEndian::read_u64(&self.read_buffer[0..8]),
+                    offset: LittleEndian::read_u64(&self.read_buffer[8..16]),
+                }
+            }
+            CHUNK => {
+                self.read_exact(10).await?;
+                let offset = LittleEndian::read_u64(&self.read_buffer[0..8]);
+                let len = LittleEndian::read_u16(&self.read_buffer[8..10]);
+                if len > self.settings.chunk_len {
+                    return Err(Error::ProtocolError);
+                }
+                self.read_exact(len as usize).await?;
+                Message::Chunk { offset }
+            }
+            COMPLETE => {
+                self.read_exact(9).await?;
+                Message::Complete {
+                    id: LittleEndian::read_u64(&self.read_buffer[0..8]),
+                    accepted: self.read_buffer[8] != 0,
+                }
+            }
+            _ => return Err(Error::ProtocolError),
+        };
+        Ok(message)
+    }
+
+    // Replaces the contents of the read buffer with exactly `len` bytes read from the channel.
+    async fn read_exact(&mut self, len: usize) -> Result<(), Error> {
+        self.read_buffer.resize(len, 0);
+        let mut pos = 0;
+        while pos < len {
+            pos += self.channel.read(&mut self.read_buffer[pos..]).await?;
+        }
+        Ok(())
+    }
+}
+
+fn write_u16(buffer: &mut Vec<u8>, val: u16) {
+    let mut bytes = [0; 2];
+    LittleEndian::write_u16(&mut bytes, val);
+    buffer.extend_from_slice(&bytes);
+}
+
+fn write_u64(buffer: &mut Vec<u8>, val: u64) {
+    let mut bytes = [0; 8];
+    LittleEndian::write_u64(&mut bytes, val);
+    buffer.extend_from_slice(&bytes);
+}
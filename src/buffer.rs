@@ -1,4 +1,8 @@
-use std::ops::{Deref, DerefMut};
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+use alloc::{boxed::Box, vec};
 
 pub use crate::packet::{Packet, PacketPool};
 
@@ -7,6 +11,12 @@ pub trait BufferPool {
     type Buffer: Deref<Target = [u8]> + DerefMut;
 
     fn acquire(&self) -> Self::Buffer;
+
+    /// See `PacketPool::prewarm`.
+    fn prewarm(&self, _count: usize) {}
+
+    /// See `PacketPool::shrink_to`.
+    fn shrink_to(&self, _count: usize) {}
 }
 
 /// Turns a `BufferPool` implementation into something that implements `PacketPool`.
@@ -28,6 +38,14 @@ impl<B: BufferPool> PacketPool for BufferPacketPool<B> {
             len: 0,
         }
     }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
 }
 
 #[derive(Debug)]
@@ -72,3 +90,220 @@ where
         &mut self.buffer[0..self.len]
     }
 }
+
+/// A heap allocated buffer positioned so that the byte at a chosen offset has a chosen alignment.
+///
+/// Packet wrappers such as `MuxPacket` place their headers in front of the payload, so to align
+/// the payload itself the offset should be the combined length of those headers, for example
+/// `packet_multiplexer::HEADER_LEN`.  The payload can then be used directly by zero-copy
+/// deserializers such as `rkyv`, which require their input to be aligned.
+#[derive(Debug)]
+pub struct AlignedBuffer {
+    storage: Box<[u8]>,
+    start: usize,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    /// Allocate a zeroed buffer of `len` bytes, where the byte at `offset` is aligned to `align`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two.
+    pub fn new(len: usize, align: usize, offset: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        let storage = vec![0; len + align - 1].into_boxed_slice();
+        let addr = storage.as_ptr() as usize + offset;
+        let start = addr.next_multiple_of(align) - addr;
+        AlignedBuffer {
+            storage,
+            start,
+            len,
+        }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.storage[self.start..self.start + self.len]
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.storage[self.start..self.start + self.len]
+    }
+}
+
+/// A `BufferPool` which allocates a new `AlignedBuffer` for every packet.
+#[derive(Debug, Copy, Clone)]
+pub struct AlignedBufferPool {
+    buffer_len: usize,
+    align: usize,
+    offset: usize,
+}
+
+impl AlignedBufferPool {
+    /// Create a pool of buffers of `buffer_len` bytes, whose start is aligned to `align`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two.
+    pub fn new(buffer_len: usize, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        AlignedBufferPool {
+            buffer_len,
+            align,
+            offset: 0,
+        }
+    }
+
+    /// Align the byte at `offset` into each buffer instead of its start.
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buffer_len
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl BufferPool for AlignedBufferPool {
+    type Buffer = AlignedBuffer;
+
+    fn acquire(&self) -> AlignedBuffer {
+        AlignedBuffer::new(self.buffer_len, self.align, self.offset)
+    }
+}
+
+/// A `BufferPool` of heap allocated buffers of a fixed length, which are returned to the pool when
+/// dropped and reused by later acquires.
+///
+/// The pool keeps every returned buffer, so after a burst of traffic it holds as many free buffers
+/// as were in use at the peak.  Use `prewarm` and `shrink_to` to allocate buffers ahead of time and
+/// to release them again, for example at the start and end of a match.
+///
+/// Buffers are `AlignedBuffer`s, with an alignment of 1 unless set with `with_alignment`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct RecyclingBufferPool {
+    buffers: AlignedBufferPool,
+    free: Arc<Mutex<Vec<AlignedBuffer>>>,
+}
+
+#[cfg(feature = "std")]
+impl RecyclingBufferPool {
+    pub fn new(buffer_len: usize) -> Self {
+        RecyclingBufferPool {
+            buffers: AlignedBufferPool::new(buffer_len, 1),
+            free: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Align the byte at `offset` into each buffer to `align`, see `AlignedBuffer`.  Any free
+    /// buffers are released.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two.
+    pub fn with_alignment(self, align: usize, offset: usize) -> Self {
+        RecyclingBufferPool {
+            buffers: AlignedBufferPool::new(self.buffers.buffer_len, align).with_offset(offset),
+            free: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buffers.buffer_len
+    }
+
+    pub fn align(&self) -> usize {
+        self.buffers.align
+    }
+
+    pub fn offset(&self) -> usize {
+        self.buffers.offset
+    }
+
+    /// The number of buffers currently held for reuse.
+    pub fn free(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+}
+
+#[cfg(feature = "std")]
+impl BufferPool for RecyclingBufferPool {
+    type Buffer = PooledBuffer;
+
+    fn acquire(&self) -> PooledBuffer {
+        let buffer = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| self.buffers.acquire());
+        PooledBuffer {
+            buffer: Some(buffer),
+            free: Arc::clone(&self.free),
+        }
+    }
+
+    fn prewarm(&self, count: usize) {
+        let mut free = self.free.lock().unwrap();
+        let missing = count.saturating_sub(free.len());
+        free.reserve(missing);
+        while free.len() < count {
+            free.push(self.buffers.acquire());
+        }
+    }
+
+    fn shrink_to(&self, count: usize) {
+        let mut free = self.free.lock().unwrap();
+        free.truncate(count);
+        free.shrink_to_fit();
+    }
+}
+
+/// A buffer acquired from a `RecyclingBufferPool`, which returns itself to the pool when dropped.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buffer: Option<AlignedBuffer>,
+    free: Arc<Mutex<Vec<AlignedBuffer>>>,
+}
+
+#[cfg(feature = "std")]
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+#[cfg(feature = "std")]
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buffer.as_mut().unwrap()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let (Some(buffer), Ok(mut free)) = (self.buffer.take(), self.free.lock()) {
+            free.push(buffer);
+        }
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    io::{self, Read, Write},
+    net::SocketAddrV4,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+use futures::{ready, Sink, SinkExt, Stream};
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketPool},
+    runtime::Timer,
+};
+
+const MAGIC: &[u8; 6] = b"TRBCAP";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+/// A single captured packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// The time the packet was captured, relative to the start of the capture.
+    pub time: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("capture IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("capture has bad format")]
+    BadFormat,
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("error reading capture: {0}")]
+    Capture(#[from] CaptureError),
+    #[error("captured packet is larger than the packet capacity")]
+    TooLarge,
+    #[error("replay packet sink has been disconnected")]
+    Disconnected,
+}
+
+/// Writes captured packets to a compact log.
+///
+/// The log starts with a short header, followed by one record per packet: the time since the
+/// previous record in microseconds as a LEB128 varint, a direction byte, the packet length as a
+/// LEB128 varint, and the packet contents.
+pub struct Recorder<W> {
+    writer: W,
+    last_time: Duration,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Recorder {
+            writer,
+            last_time: Duration::ZERO,
+        })
+    }
+
+    /// Record a packet.  Times before the time of the previous record are recorded as the time
+    /// of the previous record.
+    pub fn record(&mut self, time: Duration, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let time = time.max(self.last_time);
+        let mut header = [0; 21];
+        let mut len = write_varint(&mut header, (time - self.last_time).as_micros() as u64);
+        header[len] = match direction {
+            Direction::Incoming => 0,
+            Direction::Outgoing => 1,
+        };
+        len += 1;
+        len += write_varint(&mut header[len..], data.len() as u64);
+        self.writer.write_all(&header[..len])?;
+        self.writer.write_all(data)?;
+        self.last_time = time;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the records of a log written by a `Recorder`.
+pub struct CaptureReader<R> {
+    reader: R,
+    time: Duration,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Create a reader, checking the log header.
+    pub fn new(mut reader: R) -> Result<Self, CaptureError> {
+        let mut header = [0; 7];
+        reader.read_exact(&mut header).map_err(eof_is_bad_format)?;
+        if &header[0..6] != MAGIC || header[6] != VERSION {
+            return Err(CaptureError::BadFormat);
+        }
+        Ok(CaptureReader {
+            reader,
+            time: Duration::ZERO,
+        })
+    }
+
+    /// Read the next record, returns `Ok(None)` at the end of the log.
+    pub fn read_record(&mut self) -> Result<Option<Record>, CaptureError> {
+        let mut first = [0];
+        if self.reader.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+        let delta = self.read_varint(first[0])?;
+
+        let mut direction = [0];
+        self.reader
+            .read_exact(&mut direction)
+            .map_err(eof_is_bad_format)?;
+        let direction = match direction[0] {
+            0 => Direction::Incoming,
+            1 => Direction::Outgoing,
+            _ => return Err(CaptureError::BadFormat),
+        };
+
+        let mut first = [0];
+        self.reader
+            .read_exact(&mut first)
+            .map_err(eof_is_bad_format)?;
+        let len = self.read_varint(first[0])?;
+        // Packets are never larger than `MAX_PACKET_LEN`, so anything much larger is corrupt.
+        if len > u16::MAX as u64 {
+            return Err(CaptureError::BadFormat);
+        }
+        let mut data = vec![0; len as usize];
+        self.reader
+            .read_exact(&mut data)
+            .map_err(eof_is_bad_format)?;
+
+        self.time += Duration::from_micros(delta);
+        Ok(Some(Record {
+            time: self.time,
+            direction,
+            data,
+        }))
+    }
+
+    fn read_varint(&mut self, first: u8) -> Result<u64, CaptureError> {
+        let mut val = (first & 0x7f) as u64;
+        let mut byte = first;
+        let mut shift = 7;
+        while byte & 0x80 != 0 {
+            if shift >= 64 {
+                return Err(CaptureError::BadFormat);
+            }
+            let mut next = [0];
+            self.reader
+                .read_exact(&mut next)
+                .map_err(eof_is_bad_format)?;
+            byte = next[0];
+            val |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+        }
+        Ok(val)
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<Record, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// The link layer of the packets in a pcapng file written by `PcapngWriter`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PcapngLink {
+    /// Packets are written as they were captured, with the `LINKTYPE_USER0` link type.  Wireshark
+    /// needs a dissector registered for `DLT_USER0` to decode them.
+    User,
+    /// Packets are wrapped in synthesized IPv4 and UDP headers between the given addresses, so
+    /// that Wireshark decodes them as UDP and a dissector can be registered for the port.  Incoming
+    /// packets are sent from `remote` to `local`, outgoing packets from `local` to `remote`.
+    Udp {
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+    },
+}
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_EPB_FLAGS: u16 = 2;
+const LINKTYPE_USER0: u16 = 147;
+const LINKTYPE_IPV4: u16 = 228;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+
+/// Writes captured packets as a pcapng file, so that captures can be inspected with Wireshark.
+///
+/// The file has a single interface with microsecond timestamps, and each packet is an enhanced
+/// packet block whose flags record its direction.  Records are timestamped relative to the given
+/// start time.
+pub struct PcapngWriter<W> {
+    writer: W,
+    link: PcapngLink,
+    start: Duration,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Create a writer, writing the section header and interface description.  Record times are
+    /// added to `start`, usually the wall clock time the capture was started.
+    pub fn new(mut writer: W, link: PcapngLink, start: SystemTime) -> io::Result<Self> {
+        let start = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        writer.write_u32::<LittleEndian>(PCAPNG_SECTION_HEADER)?;
+        writer.write_u32::<LittleEndian>(28)?;
+        writer.write_u32::<LittleEndian>(PCAPNG_BYTE_ORDER_MAGIC)?;
+        writer.write_u16::<LittleEndian>(1)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        // The section length is unknown.
+        writer.write_i64::<LittleEndian>(-1)?;
+        writer.write_u32::<LittleEndian>(28)?;
+
+        writer.write_u32::<LittleEndian>(PCAPNG_INTERFACE_DESCRIPTION)?;
+        writer.write_u32::<LittleEndian>(20)?;
+        writer.write_u16::<LittleEndian>(match link {
+            PcapngLink::User => LINKTYPE_USER0,
+            PcapngLink::Udp { .. } => LINKTYPE_IPV4,
+        })?;
+        writer.write_u16::<LittleEndian>(0)?;
+        // A snap length of zero means packets are never truncated.
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(20)?;
+
+        Ok(PcapngWriter {
+            writer,
+            link,
+            start,
+        })
+    }
+
+    /// Write a captured packet.  Returns an `InvalidInput` error if the packet is too large to fit
+    /// in a UDP datagram with `PcapngLink::Udp`.
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let mut headers = [0; IPV4_HEADER_LEN + UDP_HEADER_LEN];
+        let headers: &[u8] = match self.link {
+            PcapngLink::User => &[],
+            PcapngLink::Udp { local, remote } => {
+                let (src, dst) = match record.direction {
+                    Direction::Incoming => (remote, local),
+                    Direction::Outgoing => (local, remote),
+                };
+                if record.data.len() > u16::MAX as usize - headers.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "captured packet too large for a UDP datagram",
+                    ));
+                }
+                write_udp_headers(&mut headers, src, dst, record.data.len());
+                &headers
+            }
+        };
+
+        let len = headers.len() + record.data.len();
+        let padding = (4 - len % 4) % 4;
+        // The block header, packet fields, data, flags option, end of options and block trailer.
+        let block_len = 28 + len + padding + 8 + 4 + 4;
+        let time = (self.start + record.time).as_micros() as u64;
+
+        self.writer
+            .write_u32::<LittleEndian>(PCAPNG_ENHANCED_PACKET)?;
+        self.writer.write_u32::<LittleEndian>(block_len as u32)?;
+        self.writer.write_u32::<LittleEndian>(0)?;
+        self.writer.write_u32::<LittleEndian>((time >> 32) as u32)?;
+        self.writer.write_u32::<LittleEndian>(time as u32)?;
+        self.writer.write_u32::<LittleEndian>(len as u32)?;
+        self.writer.write_u32::<LittleEndian>(len as u32)?;
+        self.writer.write_all(headers)?;
+        self.writer.write_all(&record.data)?;
+        self.writer.write_all(&[0; 3][..padding])?;
+
+        self.writer.write_u16::<LittleEndian>(PCAPNG_EPB_FLAGS)?;
+        self.writer.write_u16::<LittleEndian>(4)?;
+        self.writer
+            .write_u32::<LittleEndian>(match record.direction {
+                Direction::Incoming => 1,
+                Direction::Outgoing => 2,
+            })?;
+        self.writer.write_u32::<LittleEndian>(0)?;
+        self.writer.write_u32::<LittleEndian>(block_len as u32)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Convert a log written by a `Recorder` to pcapng, writing every record to the given writer and
+/// returning the underlying writer once flushed.
+pub fn export_pcapng<R, W>(
+    records: CaptureReader<R>,
+    mut writer: PcapngWriter<W>,
+) -> Result<W, CaptureError>
+where
+    R: Read,
+    W: Write,
+{
+    for record in records {
+        writer.write_record(&record?)?;
+    }
+    writer.flush()?;
+    Ok(writer.into_inner())
+}
+
+/// Taps packet streams and sinks, recording every packet that passes through them to a
+/// `Recorder`, timestamped with the time since the `PacketCapture` was created.
+///
+/// Tap the raw incoming packet stream and outgoing packet sink of a connection, between the
+/// transport and the multiplexer.  Capturing never interrupts the connection: if writing a record
+/// fails, capturing stops and the error is available from `PacketCapture::take_error`.
+pub struct PacketCapture<T: Timer, W> {
+    timer: T,
+    start: T::Instant,
+    state: Arc<Mutex<CaptureState<W>>>,
+}
+
+struct CaptureState<W> {
+    recorder: Option<Recorder<W>>,
+    error: Option<io::Error>,
+}
+
+impl<T: Timer, W> Clone for PacketCapture<T, W> {
+    fn clone(&self) -> Self {
+        PacketCapture {
+            timer: self.timer.clone(),
+            start: self.start,
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<T: Timer, W: Write> PacketCapture<T, W> {
+    pub fn new(timer: T, recorder: Recorder<W>) -> Self {
+        PacketCapture {
+            start: timer.now(),
+            timer,
+            state: Arc::new(Mutex::new(CaptureState {
+                recorder: Some(recorder),
+                error: None,
+            })),
+        }
+    }
+
+    /// Record every packet received from the given stream as incoming.
+    pub fn tap_incoming<I>(&self, incoming: I) -> TapStream<T, W, I> {
+        TapStream {
+            capture: self.clone(),
+            incoming,
+        }
+    }
+
+    /// Record every packet sent to the given sink as outgoing.
+    pub fn tap_outgoing<O>(&self, outgoing: O) -> TapSink<T, W, O> {
+        TapSink {
+            capture: self.clone(),
+            outgoing,
+        }
+    }
+
+    /// Returns the error that stopped capturing, if any.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.state.lock().unwrap().error.take()
+    }
+
+    /// Stop capturing, flushing and returning the recorder.  Returns `None` if it has already
+    /// been stopped, or if capturing stopped due to an error.
+    pub fn stop(&self) -> Option<io::Result<Recorder<W>>> {
+        let mut recorder = self.state.lock().unwrap().recorder.take()?;
+        Some(recorder.flush().map(|_| recorder))
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) {
+        let time = self.timer.elapsed(self.start);
+        let mut state = self.state.lock().unwrap();
+        if let Some(recorder) = &mut state.recorder {
+            if let Err(err) = recorder.record(time, direction, data) {
+                state.recorder = None;
+                state.error = Some(err);
+            }
+        }
+    }
+}
+
+/// A `Stream` which records packets, created by `PacketCapture::tap_incoming`.
+pub struct TapStream<T: Timer, W, I> {
+    capture: PacketCapture<T, W>,
+    incoming: I,
+}
+
+impl<T, W, I, P> Stream for TapStream<T, W, I>
+where
+    T: Timer,
+    W: Write,
+    I: Stream<Item = P> + Unpin,
+    P: Packet,
+{
+    type Item = P;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<P>> {
+        let packet = ready!(Pin::new(&mut self.incoming).poll_next(cx));
+        if let Some(packet) = &packet {
+            self.capture.record(Direction::Incoming, packet);
+        }
+        Poll::Ready(packet)
+    }
+}
+
+/// A `Sink` which records packets, created by `PacketCapture::tap_outgoing`.
+pub struct TapSink<T: Timer, W, O> {
+    capture: PacketCapture<T, W>,
+    outgoing: O,
+}
+
+impl<T, W, O, P> Sink<P> for TapSink<T, W, O>
+where
+    T: Timer,
+    W: Write,
+    O: Sink<P> + Unpin,
+    P: Packet,
+{
+    type Error = O::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: P) -> Result<(), Self::Error> {
+        self.capture.record(Direction::Outgoing, &packet);
+        Pin::new(&mut self.outgoing).start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
+
+/// Replay the incoming packets of a capture into a sink, such as the `IncomingMultiplexedPackets`
+/// of a started multiplexer, with the same relative timing as they were captured.
+///
+/// Outgoing records are skipped, the other side of a replayed connection is not running.
+pub async fn replay<T, P, R, S>(
+    timer: T,
+    pool: P,
+    records: CaptureReader<R>,
+    incoming: &mut S,
+) -> Result<(), ReplayError>
+where
+    T: Timer,
+    P: PacketPool,
+    R: Read,
+    S: Sink<P::Packet> + Unpin,
+{
+    let start = timer.now();
+    for record in records {
+        let record = record?;
+        if record.direction != Direction::Incoming {
+            continue;
+        }
+
+        let elapsed = timer.elapsed(start);
+        if record.time > elapsed {
+            timer.sleep(record.time - elapsed).await;
+        }
+
+        let mut packet = pool.acquire();
+        if packet.capacity() < record.data.len() {
+            return Err(ReplayError::TooLarge);
+        }
+        packet.extend(&record.data);
+        incoming
+            .send(packet)
+            .await
+            .map_err(|_| ReplayError::Disconnected)?;
+    }
+    Ok(())
+}
+
+fn write_varint(buf: &mut [u8], mut val: u64) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            buf[len] = byte;
+            return len + 1;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+fn write_udp_headers(buf: &mut [u8], src: SocketAddrV4, dst: SocketAddrV4, len: usize) {
+    let (ip, udp) = buf.split_at_mut(IPV4_HEADER_LEN);
+
+    // Version 4 with a five word header, and the don't fragment flag set.
+    ip[0] = 0x45;
+    BigEndian::write_u16(
+        &mut ip[2..4],
+        (IPV4_HEADER_LEN + UDP_HEADER_LEN + len) as u16,
+    );
+    ip[6] = 0x40;
+    ip[8] = 64;
+    ip[9] = 17;
+    ip[12..16].copy_from_slice(&src.ip().octets());
+    ip[16..20].copy_from_slice(&dst.ip().octets());
+    let mut sum = ip
+        .chunks(2)
+        .map(|word| BigEndian::read_u16(word) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    BigEndian::write_u16(&mut ip[10..12], !(sum as u16));
+
+    // A UDP checksum of zero means no checksum was computed.
+    BigEndian::write_u16(&mut udp[0..2], src.port());
+    BigEndian::write_u16(&mut udp[2..4], dst.port());
+    BigEndian::write_u16(&mut udp[4..6], (UDP_HEADER_LEN + len) as u16);
+}
+
+fn eof_is_bad_format(err: io::Error) -> CaptureError {
+    if err.kind() == io::ErrorKind::UnexpectedEof {
+        CaptureError::BadFormat
+    } else {
+        CaptureError::Io(err)
+    }
+}
@@ -0,0 +1,78 @@
+use minicbor_serde::{
+    error::{DecodeError, EncodeError},
+    Deserializer, Serializer,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    serde_channel::{
+        self, Format, ReliableSerdeChannel, ReliableSerdeTypedChannel, UnreliableSerdeChannel,
+        UnreliableSerdeTypedChannel,
+    },
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+#[derive(Debug, Error)]
+pub enum CborError {
+    #[error("CBOR serialization error: {0}")]
+    Encode(#[from] EncodeError<minicbor::encode::write::EndOfSlice>),
+    #[error("CBOR deserialization error: {0}")]
+    Decode(#[from] DecodeError),
+    /// The message was followed by more data.
+    #[error("trailing bytes after CBOR message")]
+    TrailingBytes,
+}
+
+/// The CBOR data format (RFC 8949).
+///
+/// CBOR is self-describing and standardized, which makes it a good fit for messages that are
+/// archived or read by peers written in other languages.  Every message is a single CBOR data
+/// item, and structs are serialized as maps keyed by field name.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Cbor;
+
+impl Format for Cbor {
+    type Error = CborError;
+
+    fn serialize<T: Serialize + ?Sized>(buffer: &mut [u8], msg: &T) -> Result<usize, CborError> {
+        let mut serializer = Serializer::new(minicbor::encode::write::Cursor::new(buffer));
+        msg.serialize(&mut serializer)?;
+        Ok(serializer.into_encoder().into_writer().position())
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(buffer: &'a [u8]) -> Result<T, CborError> {
+        let mut deserializer = Deserializer::new(buffer);
+        let msg = T::deserialize(&mut deserializer)?;
+        if deserializer.decoder().position() != buffer.len() {
+            return Err(CborError::TrailingBytes);
+        }
+        Ok(msg)
+    }
+}
+
+pub type SendError = serde_channel::SendError<CborError>;
+pub type RecvError = serde_channel::RecvError<CborError>;
+pub type Error = serde_channel::Error<CborError>;
+
+/// An `UnreliableChannel` which sends messages serialized as CBOR.
+pub type UnreliableCborChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeChannel<Cbor, R, P, I, O>;
+
+pub type UnreliableCborTypedChannel<
+    T,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeTypedChannel<T, Cbor, R, P, I, O>;
+
+/// A `ReliableChannel` which sends messages serialized as CBOR, each prefixed with its length.
+pub type ReliableCborChannel = ReliableSerdeChannel<Cbor>;
+
+pub type ReliableCborTypedChannel<T> = ReliableSerdeTypedChannel<T, Cbor>;
@@ -6,33 +6,62 @@ use crate::{
     },
     reliable_bincode_channel::{ReliableBincodeChannel, ReliableTypedChannel},
     reliable_channel::{self, ReliableChannel},
-    runtime::Runtime,
+    runtime::{LocalRuntime, Runtime, Timer},
+    scratch::ScratchArena,
+    tick_budget::TickBudget,
     unreliable_bincode_channel::{UnreliableBincodeChannel, UnreliableTypedChannel},
-    unreliable_channel::{self, UnreliableChannel},
+    unreliable_channel::{self, OverflowPolicy, ShedPolicy, UnreliableChannel},
+    work_budget::WorkBudget,
 };
 
 /// Helper that allows for easily opening different channel types on a `PacketMultiplexer`.
 ///
 /// Contains a `MuxPacketPool` and a `Runtime` implemenentation that is used for each created
 /// channel.
+///
+/// Every created channel's packets follow the `DatagramLimit` of the multiplexer it is opened on,
+/// so the channels size their packets to any maximum datagram size the transport sets.
 pub struct ChannelBuilder<R, P> {
     pub runtime: R,
     pub pool: MuxPacketPool<P>,
+    /// The shed policy given to each created unreliable channel.
+    pub shed_policy: ShedPolicy,
+    /// The overflow policy given to each created unreliable channel.
+    pub overflow_policy: OverflowPolicy,
+    /// The work budget given to each created reliable channel.
+    pub work_budget: Option<WorkBudget>,
+    /// The initial buffer length given to each created bincode or typed channel, which grows on
+    /// demand up to the maximum message length.  If `None`, buffers start at the maximum message
+    /// length.
+    pub initial_buffer_len: Option<u16>,
+    /// The scratch arena each created bincode, typed or compressed channel borrows its buffers
+    /// from, if any.
+    pub scratch_arena: Option<ScratchArena>,
+    /// The tick budget given to each created unreliable or reliable channel.
+    pub tick_budget: Option<TickBudget>,
 }
 
-impl<R, P> ChannelBuilder<R, P>
-where
-    R: Runtime + 'static,
-    P: PacketPool + Clone + Send + 'static,
-    P::Packet: Unpin + Send,
-{
+impl<R, P> ChannelBuilder<R, P> {
     pub fn new(runtime: R, pool: P) -> Self {
         ChannelBuilder {
             runtime,
             pool: MuxPacketPool::new(pool),
+            shed_policy: ShedPolicy::Queue,
+            overflow_policy: OverflowPolicy::Wait,
+            work_budget: None,
+            initial_buffer_len: None,
+            scratch_arena: None,
+            tick_budget: None,
         }
     }
+}
 
+impl<R, P> ChannelBuilder<R, P>
+where
+    R: Timer,
+    P: PacketPool + Clone,
+    P::Packet: Unpin,
+{
     pub fn open_unreliable_channel(
         &mut self,
         multiplexer: &mut PacketMultiplexer<P::Packet>,
@@ -41,16 +70,19 @@ where
         settings: unreliable_channel::Settings,
     ) -> Result<(UnreliableChannel<R, MuxPacketPool<P>>, ChannelStatistics), DuplicateChannel> {
         let (sender, receiver, statistics) = multiplexer.open_channel(channel, buffer_size)?;
-        Ok((
-            UnreliableChannel::new(
-                self.runtime.clone(),
-                self.pool.clone(),
-                settings,
-                receiver,
-                sender,
-            ),
-            statistics,
-        ))
+        let mut channel = UnreliableChannel::new(
+            self.runtime.clone(),
+            self.pool
+                .clone()
+                .with_datagram_limit(multiplexer.datagram_limit()),
+            settings,
+            receiver,
+            sender,
+        );
+        channel.set_shed_policy(self.shed_policy);
+        channel.set_overflow_policy(self.overflow_policy);
+        channel.set_tick_budget(self.tick_budget);
+        Ok((channel, statistics))
     }
 
     pub fn open_unreliable_bincode_channel(
@@ -69,12 +101,16 @@ where
     > {
         let (channel, statistics) =
             self.open_unreliable_channel(multiplexer, channel, buffer_size, settings)?;
-        Ok((
-            UnreliableBincodeChannel::new(channel, max_message_len),
-            statistics,
-        ))
+        let mut channel = UnreliableBincodeChannel::with_initial_buffer_len(
+            channel,
+            max_message_len,
+            self.initial_buffer_len.unwrap_or(max_message_len),
+        );
+        channel.set_scratch_arena(self.scratch_arena.clone());
+        Ok((channel, statistics))
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn open_unreliable_typed_channel<M>(
         &mut self,
         multiplexer: &mut PacketMultiplexer<P::Packet>,
@@ -98,7 +134,48 @@ where
         )?;
         Ok((UnreliableTypedChannel::new(channel), statistics))
     }
+}
+
+impl<R, P> ChannelBuilder<R, P>
+where
+    R: LocalRuntime + 'static,
+    P: PacketPool + Clone + 'static,
+    P::Packet: Unpin,
+{
+    /// Like `ChannelBuilder::open_reliable_channel`, but constructs the channel with
+    /// `ReliableChannel::new_local`, so the runtime and packet pool do not need to be `Send`.
+    ///
+    /// The returned channel can be wrapped in any of the reliable bincode or compressed channel
+    /// types directly.
+    pub fn open_reliable_channel_local(
+        &mut self,
+        multiplexer: &mut PacketMultiplexer<P::Packet>,
+        channel: PacketChannel,
+        buffer_size: usize,
+        settings: reliable_channel::Settings,
+    ) -> Result<(ReliableChannel, ChannelStatistics), DuplicateChannel> {
+        let (sender, receiver, statistics) = multiplexer.open_channel(channel, buffer_size)?;
+        let channel = ReliableChannel::new_local(
+            self.runtime.clone(),
+            self.pool
+                .clone()
+                .with_datagram_limit(multiplexer.datagram_limit()),
+            settings,
+            receiver,
+            sender,
+        );
+        channel.set_work_budget(self.work_budget);
+        channel.set_tick_budget(self.tick_budget);
+        Ok((channel, statistics))
+    }
+}
 
+impl<R, P> ChannelBuilder<R, P>
+where
+    R: Runtime + 'static,
+    P: PacketPool + Clone + Send + 'static,
+    P::Packet: Unpin + Send,
+{
     pub fn open_reliable_channel(
         &mut self,
         multiplexer: &mut PacketMultiplexer<P::Packet>,
@@ -107,16 +184,18 @@ where
         settings: reliable_channel::Settings,
     ) -> Result<(ReliableChannel, ChannelStatistics), DuplicateChannel> {
         let (sender, receiver, statistics) = multiplexer.open_channel(channel, buffer_size)?;
-        Ok((
-            ReliableChannel::new(
-                self.runtime.clone(),
-                self.pool.clone(),
-                settings,
-                receiver,
-                sender,
-            ),
-            statistics,
-        ))
+        let channel = ReliableChannel::new(
+            self.runtime.clone(),
+            self.pool
+                .clone()
+                .with_datagram_limit(multiplexer.datagram_limit()),
+            settings,
+            receiver,
+            sender,
+        );
+        channel.set_work_budget(self.work_budget);
+        channel.set_tick_budget(self.tick_budget);
+        Ok((channel, statistics))
     }
 
     pub fn open_reliable_bincode_channel(
@@ -129,10 +208,13 @@ where
     ) -> Result<(ReliableBincodeChannel, ChannelStatistics), DuplicateChannel> {
         let (channel, statistics) =
             self.open_reliable_channel(multiplexer, channel, buffer_size, settings)?;
-        Ok((
-            ReliableBincodeChannel::new(channel, max_message_len),
-            statistics,
-        ))
+        let mut channel = ReliableBincodeChannel::with_initial_buffer_len(
+            channel,
+            max_message_len,
+            self.initial_buffer_len.unwrap_or(max_message_len),
+        );
+        channel.set_scratch_arena(self.scratch_arena.clone());
+        Ok((channel, statistics))
     }
 
     pub fn open_reliable_typed_channel<M>(
@@ -163,10 +245,9 @@ where
     ) -> Result<(CompressedBincodeChannel, ChannelStatistics), DuplicateChannel> {
         let (channel, statistics) =
             self.open_reliable_channel(multiplexer, channel, buffer_size, settings)?;
-        Ok((
-            CompressedBincodeChannel::new(channel, max_chunk_len),
-            statistics,
-        ))
+        let mut channel = CompressedBincodeChannel::new(channel, max_chunk_len);
+        channel.set_scratch_arena(self.scratch_arena.clone());
+        Ok((channel, statistics))
     }
 
     pub fn open_compressed_typed_channel<M>(
@@ -0,0 +1,313 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{
+    future::{self, Either},
+    pin_mut, Sink, Stream, StreamExt,
+};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// How often to send a timestamp probe to the other side.
+    pub probe_interval: Duration,
+    /// The number of recent probe results used for the estimate.  More samples smooth out jitter
+    /// and measure drift more accurately, but adapt more slowly to changes.
+    pub samples: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            probe_interval: Duration::from_secs(1),
+            samples: 16,
+        }
+    }
+}
+
+/// Error returned by `ClockSync::run`, all errors are fatal.
+#[derive(Debug, Error)]
+pub enum ClockSyncError {
+    #[error("unreliable channel send error: {0}")]
+    SendError(#[from] unreliable_channel::SendError),
+    #[error("unreliable channel receive error: {0}")]
+    RecvError(#[from] unreliable_channel::RecvError),
+}
+
+const PROBE: u8 = 0;
+const REPLY: u8 = 1;
+const PROBE_LEN: usize = 9;
+const REPLY_LEN: usize = 25;
+
+/// Estimates the offset and drift of a remote clock by periodically exchanging timestamp probes,
+/// in the same way as NTP.
+///
+/// Each side of a connection has its own timeline, the time elapsed since its `ClockSync` was
+/// created.  Both sides run a `ClockSync` over an unreliable channel dedicated to it, each
+/// answering the other's probes, and each can then convert its own time into the other side's
+/// timeline with `RemoteClock::remote_time`.  Usually the server's timeline is used as the shared
+/// timeline for game events.
+///
+/// The offset is taken from the probe with the lowest round trip time, since it is the least
+/// affected by queuing delays, and the drift is fitted over all recent probes.  The estimate
+/// assumes that the network delay is the same in both directions.
+pub struct ClockSync<T: Timer> {
+    clock: RemoteClock<T>,
+    settings: Settings,
+}
+
+impl<T: Timer> ClockSync<T> {
+    pub fn new(timer: T, settings: Settings) -> Self {
+        ClockSync {
+            clock: RemoteClock {
+                start: timer.now(),
+                timer,
+                estimator: Arc::new(Mutex::new(Estimator {
+                    samples: VecDeque::new(),
+                    max_samples: settings.samples.max(1),
+                })),
+            },
+            settings,
+        }
+    }
+
+    /// A handle to read the current estimate, which is updated while `run` is running.
+    pub fn remote_clock(&self) -> RemoteClock<T> {
+        self.clock.clone()
+    }
+
+    /// Send probes and answer the other side's probes over the given channel, until the channel
+    /// is disconnected.
+    pub async fn run<R, P, I, O>(
+        self,
+        mut channel: UnreliableChannel<R, P, I, O>,
+    ) -> Result<(), ClockSyncError>
+    where
+        R: Timer,
+        P: PacketPool,
+        I: Stream<Item = P::Packet> + Unpin,
+        O: Sink<P::Packet> + Unpin,
+    {
+        let clock = self.clock;
+        let mut interval = clock.timer.interval(self.settings.probe_interval);
+
+        let mut probe = [0; PROBE_LEN];
+        probe[0] = PROBE;
+        write_time(&mut probe[1..9], clock.local_time());
+        channel.send(&probe).await?;
+        channel.flush().await?;
+
+        loop {
+            let mut msg = [0; REPLY_LEN];
+            let len = {
+                let recv = channel.recv();
+                pin_mut!(recv);
+                match future::select(recv, interval.next()).await {
+                    Either::Left((received, _)) => {
+                        let received = received?;
+                        let len = received.len().min(REPLY_LEN);
+                        msg[..len].copy_from_slice(&received[..len]);
+                        Some(received.len())
+                    }
+                    Either::Right(_) => None,
+                }
+            };
+
+            match len {
+                None => {
+                    write_time(&mut probe[1..9], clock.local_time());
+                    channel.send(&probe).await?;
+                }
+                Some(PROBE_LEN) if msg[0] == PROBE => {
+                    let received_at = clock.local_time();
+                    let mut reply = [0; REPLY_LEN];
+                    reply[0] = REPLY;
+                    reply[1..9].copy_from_slice(&msg[1..9]);
+                    write_time(&mut reply[9..17], received_at);
+                    write_time(&mut reply[17..25], clock.local_time());
+                    channel.send(&reply).await?;
+                }
+                Some(REPLY_LEN) if msg[0] == REPLY => {
+                    let received_at = clock.local_time();
+                    let sent = read_time(&msg[1..9]);
+                    let remote_received = read_time(&msg[9..17]);
+                    let remote_sent = read_time(&msg[17..25]);
+                    if sent <= received_at && remote_received <= remote_sent {
+                        clock.estimator.lock().unwrap().add(
+                            sent,
+                            remote_received,
+                            remote_sent,
+                            received_at,
+                        );
+                    }
+                    continue;
+                }
+                // Unknown messages are ignored.
+                Some(_) => continue,
+            }
+            channel.flush().await?;
+        }
+    }
+}
+
+/// A handle to the estimated remote clock of a `ClockSync`.
+pub struct RemoteClock<T: Timer> {
+    timer: T,
+    start: T::Instant,
+    estimator: Arc<Mutex<Estimator>>,
+}
+
+impl<T: Timer> Clone for RemoteClock<T> {
+    fn clone(&self) -> Self {
+        RemoteClock {
+            timer: self.timer.clone(),
+            start: self.start,
+            estimator: Arc::clone(&self.estimator),
+        }
+    }
+}
+
+impl<T: Timer> RemoteClock<T> {
+    /// The time on the local timeline, the time since the `ClockSync` was created.
+    pub fn local_time(&self) -> Duration {
+        self.timer.elapsed(self.start)
+    }
+
+    /// The current time on the remote timeline, if any probe has completed yet.
+    pub fn remote_time(&self) -> Option<Duration> {
+        self.to_remote(self.local_time())
+    }
+
+    /// Convert a time on the local timeline to the remote timeline, if any probe has completed
+    /// yet.  Times which would be before the start of the remote timeline are clamped to zero.
+    pub fn to_remote(&self, local_time: Duration) -> Option<Duration> {
+        let offset = self
+            .estimator
+            .lock()
+            .unwrap()
+            .offset_at(local_time.as_secs_f64())?;
+        Some(Duration::from_secs_f64(
+            (local_time.as_secs_f64() + offset).max(0.0),
+        ))
+    }
+
+    /// Convert a time on the remote timeline to the local timeline, if any probe has completed yet.
+    /// Times which would be before the start of the local timeline are clamped to zero.
+    pub fn to_local(&self, remote_time: Duration) -> Option<Duration> {
+        let estimator = self.estimator.lock().unwrap();
+        // The offset is a function of local time, so estimate the local time with the current
+        // offset first.
+        let remote = remote_time.as_secs_f64();
+        let approx = remote - estimator.offset_at(self.local_time().as_secs_f64())?;
+        let offset = estimator.offset_at(approx)?;
+        Some(Duration::from_secs_f64((remote - offset).max(0.0)))
+    }
+
+    /// The current estimated offset of the remote clock from the local clock in seconds, the
+    /// remote time minus the local time.
+    pub fn offset(&self) -> Option<f64> {
+        self.estimator
+            .lock()
+            .unwrap()
+            .offset_at(self.local_time().as_secs_f64())
+    }
+
+    /// The estimated rate at which the offset changes, in seconds per second.  A positive drift
+    /// means that the remote clock runs faster than the local clock.
+    ///
+    /// Returns `None` until at least two probes have completed.
+    pub fn drift(&self) -> Option<f64> {
+        self.estimator.lock().unwrap().drift()
+    }
+
+    /// The lowest round trip time of the recent probes.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.estimator
+            .lock()
+            .unwrap()
+            .best()
+            .map(|s| Duration::from_secs_f64(s.rtt))
+    }
+}
+
+struct Sample {
+    // The local time the reply was received, in seconds.
+    local: f64,
+    offset: f64,
+    rtt: f64,
+}
+
+struct Estimator {
+    samples: VecDeque<Sample>,
+    max_samples: usize,
+}
+
+impl Estimator {
+    fn add(
+        &mut self,
+        sent: Duration,
+        remote_received: Duration,
+        remote_sent: Duration,
+        received: Duration,
+    ) {
+        let [t0, t1, t2, t3] =
+            [sent, remote_received, remote_sent, received].map(|t| t.as_secs_f64());
+        if self.samples.len() == self.max_samples {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            local: t3,
+            offset: ((t1 - t0) + (t2 - t3)) / 2.0,
+            rtt: ((t3 - t0) - (t2 - t1)).max(0.0),
+        });
+    }
+
+    fn best(&self) -> Option<&Sample> {
+        self.samples
+            .iter()
+            .min_by(|a, b| a.rtt.partial_cmp(&b.rtt).unwrap())
+    }
+
+    fn offset_at(&self, local: f64) -> Option<f64> {
+        let best = self.best()?;
+        Some(best.offset + self.drift().unwrap_or(0.0) * (local - best.local))
+    }
+
+    // The least squares slope of offset over local time.
+    fn drift(&self) -> Option<f64> {
+        let n = self.samples.len() as f64;
+        if n < 2.0 {
+            return None;
+        }
+        let mean_x = self.samples.iter().map(|s| s.local).sum::<f64>() / n;
+        let mean_y = self.samples.iter().map(|s| s.offset).sum::<f64>() / n;
+        let (mut cov, mut var) = (0.0, 0.0);
+        for s in &self.samples {
+            cov += (s.local - mean_x) * (s.offset - mean_y);
+            var += (s.local - mean_x) * (s.local - mean_x);
+        }
+        if var == 0.0 {
+            return None;
+        }
+        Some(cov / var)
+    }
+}
+
+fn write_time(buf: &mut [u8], time: Duration) {
+    LittleEndian::write_u64(buf, time.as_micros() as u64);
+}
+
+fn read_time(buf: &[u8]) -> Duration {
+    Duration::from_micros(LittleEndian::read_u64(buf))
+}
@@ -1,4 +1,4 @@
-use std::{convert::TryInto, marker::PhantomData, u16};
+use std::{convert::TryInto, marker::PhantomData};
 
 use bincode::Options as _;
 use byteorder::{ByteOrder, LittleEndian};
@@ -6,7 +6,11 @@ use serde::{de::DeserializeOwned, Serialize};
 use snap::raw::{decompress_len, max_compress_len, Decoder as SnapDecoder, Encoder as SnapEncoder};
 use thiserror::Error;
 
-use crate::reliable_channel::{self, ReliableChannel};
+use crate::{
+    envelope::Envelope,
+    reliable_channel::{self, ReliableChannel},
+    scratch::ScratchArena,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -46,6 +50,9 @@ pub struct CompressedBincodeChannel {
     write_buffer: Vec<u8>,
     write_pos: usize,
 
+    // The header of the chunk being read, the chunk itself is read into `read_buffer`.  Positions
+    // are within the header followed by the chunk.
+    read_header: [u8; 3],
     read_buffer: Vec<u8>,
     read_pos: usize,
 
@@ -54,6 +61,8 @@ pub struct CompressedBincodeChannel {
 
     encoder: SnapEncoder,
     decoder: SnapDecoder,
+
+    arena: Option<ScratchArena>,
 }
 
 impl CompressedBincodeChannel {
@@ -68,15 +77,31 @@ impl CompressedBincodeChannel {
             send_chunk: Vec::new(),
             write_buffer: Vec::new(),
             write_pos: 0,
+            read_header: [0; 3],
             read_buffer: Vec::new(),
             read_pos: 0,
             recv_chunk: Vec::new(),
             recv_pos: 0,
             encoder: SnapEncoder::new(),
             decoder: SnapDecoder::new(),
+            arena: None,
         }
     }
 
+    /// Borrow the buffers for the messages being sent and received, and for compressing and
+    /// decompressing them, from the given arena, or own them again if `None`, see
+    /// `ScratchArena`.
+    ///
+    /// Buffers are returned to the arena once they are empty, so a channel only holds a buffer
+    /// while it has unsent or unreceived messages.
+    pub fn set_scratch_arena(&mut self, arena: Option<ScratchArena>) {
+        self.arena = arena;
+    }
+
+    pub fn scratch_arena(&self) -> Option<&ScratchArena> {
+        self.arena.as_ref()
+    }
+
     /// Send the given message.
     ///
     /// This method is cancel safe, it will never partially send a message, though canceling it may
@@ -89,7 +114,12 @@ impl CompressedBincodeChannel {
             self.write_send_chunk().await?;
         }
 
-        bincode_config.serialize_into(&mut self.send_chunk, msg)?;
+        ScratchArena::acquire(&self.arena, &mut self.send_chunk);
+        let res = bincode_config.serialize_into(&mut self.send_chunk, msg);
+        if self.send_chunk.is_empty() {
+            ScratchArena::release(&self.arena, &mut self.send_chunk);
+        }
+        res?;
 
         Ok(())
     }
@@ -119,35 +149,37 @@ impl CompressedBincodeChannel {
                 self.recv_pos = self.recv_chunk.len() - reader.len();
                 return Ok(msg);
             }
+            ScratchArena::release(&self.arena, &mut self.recv_chunk);
 
-            if self.read_pos < 3 {
-                self.read_buffer.resize(3, 0);
-                self.finish_read().await?;
-            }
+            self.finish_read_header().await?;
 
-            let compressed = self.read_buffer[0] != 0;
-            let chunk_len = LittleEndian::read_u16(&self.read_buffer[1..3]);
+            let compressed = self.read_header[0] != 0;
+            let chunk_len = LittleEndian::read_u16(&self.read_header[1..3]);
             if chunk_len > self.max_chunk_len {
                 return Err(Error::ChunkTooLarge);
             }
-            self.read_buffer.resize(chunk_len as usize + 3, 0);
-            self.finish_read().await?;
+            ScratchArena::acquire(&self.arena, &mut self.read_buffer);
+            self.read_buffer.resize(chunk_len as usize, 0);
+            self.finish_read_chunk().await?;
 
+            ScratchArena::acquire(&self.arena, &mut self.recv_chunk);
             if compressed {
-                let decompressed_len = decompress_len(&self.read_buffer[3..])?;
+                let decompressed_len = decompress_len(&self.read_buffer)?;
                 if decompressed_len > self.max_chunk_len as usize {
                     return Err(Error::ChunkTooLarge);
                 }
                 self.recv_chunk.resize(decompressed_len, 0);
                 self.decoder
-                    .decompress(&self.read_buffer[3..], &mut self.recv_chunk)?;
+                    .decompress(&self.read_buffer, &mut self.recv_chunk)?;
             } else {
                 self.recv_chunk.resize(chunk_len as usize, 0);
-                self.recv_chunk.copy_from_slice(&self.read_buffer[3..]);
+                self.recv_chunk.copy_from_slice(&self.read_buffer);
             }
 
             self.recv_pos = 0;
             self.read_pos = 0;
+            self.read_buffer.clear();
+            ScratchArena::release(&self.arena, &mut self.read_buffer);
         }
     }
 
@@ -156,6 +188,7 @@ impl CompressedBincodeChannel {
             self.finish_write().await?;
 
             self.write_pos = 0;
+            ScratchArena::acquire(&self.arena, &mut self.write_buffer);
             self.write_buffer
                 .resize(max_compress_len(self.send_chunk.len()) + 3, 0);
             let compressed_len = self
@@ -182,6 +215,7 @@ impl CompressedBincodeChannel {
             }
 
             self.send_chunk.clear();
+            ScratchArena::release(&self.arena, &mut self.send_chunk);
         }
 
         Ok(())
@@ -195,14 +229,26 @@ impl CompressedBincodeChannel {
                 .await?;
             self.write_pos += len;
         }
+        ScratchArena::release(&self.arena, &mut self.write_buffer);
+        Ok(())
+    }
+
+    async fn finish_read_header(&mut self) -> Result<(), Error> {
+        while self.read_pos < 3 {
+            let len = self
+                .channel
+                .read(&mut self.read_header[self.read_pos..])
+                .await?;
+            self.read_pos += len;
+        }
         Ok(())
     }
 
-    async fn finish_read(&mut self) -> Result<(), Error> {
-        while self.read_pos < self.read_buffer.len() {
+    async fn finish_read_chunk(&mut self) -> Result<(), Error> {
+        while self.read_pos < 3 + self.read_buffer.len() {
             let len = self
                 .channel
-                .read(&mut self.read_buffer[self.read_pos..])
+                .read(&mut self.read_buffer[self.read_pos - 3..])
                 .await?;
             self.read_pos += len;
         }
@@ -214,9 +260,23 @@ impl CompressedBincodeChannel {
     }
 }
 
+impl Drop for CompressedBincodeChannel {
+    fn drop(&mut self) {
+        for buffer in [
+            &mut self.send_chunk,
+            &mut self.write_buffer,
+            &mut self.read_buffer,
+            &mut self.recv_chunk,
+        ] {
+            ScratchArena::release(&self.arena, buffer);
+        }
+    }
+}
+
 /// Wrapper over an `CompressedBincodeChannel` that only allows a single message type.
 pub struct CompressedTypedChannel<T> {
     channel: CompressedBincodeChannel,
+    envelope: Option<Envelope<T>>,
     _phantom: PhantomData<T>,
 }
 
@@ -224,6 +284,21 @@ impl<T> CompressedTypedChannel<T> {
     pub fn new(channel: CompressedBincodeChannel) -> Self {
         CompressedTypedChannel {
             channel,
+            envelope: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Create a typed channel which sends and receives messages in the given versioned
+    /// `Envelope`.
+    ///
+    /// Since every message is length prefixed inside of the envelope, a message which fails to
+    /// decode does not desynchronize the stream, so unlike without an envelope a `BincodeError`
+    /// from decoding the message inside of the envelope is non-fatal.
+    pub fn with_envelope(channel: CompressedBincodeChannel, envelope: Envelope<T>) -> Self {
+        CompressedTypedChannel {
+            channel,
+            envelope: Some(envelope),
             _phantom: PhantomData,
         }
     }
@@ -235,12 +310,21 @@ impl<T> CompressedTypedChannel<T> {
 
 impl<T: Serialize> CompressedTypedChannel<T> {
     pub async fn send(&mut self, msg: &T) -> Result<(), Error> {
-        self.channel.send(msg).await
+        match &self.envelope {
+            Some(envelope) => self.channel.send(&envelope.encode(msg)?).await,
+            None => self.channel.send(msg).await,
+        }
     }
 }
 
 impl<T: DeserializeOwned> CompressedTypedChannel<T> {
     pub async fn recv(&mut self) -> Result<T, Error> {
-        self.channel.recv().await
+        match &self.envelope {
+            Some(envelope) => {
+                let (version, payload): (u16, Vec<u8>) = self.channel.recv().await?;
+                Ok(envelope.decode(version, &payload, None)?)
+            }
+            None => self.channel.recv().await,
+        }
     }
 }
@@ -0,0 +1,289 @@
+//! Golden byte-level test vectors for the `turbulence` wire format.
+//!
+//! Every vector here is the exact bytes this version of `turbulence` produces and accepts, for the
+//! current `WIRE_VERSION`.  The crate's own tests check the implementation against these vectors,
+//! so a change to any of them is a wire format change and comes with a `WIRE_VERSION` bump.
+//! Implementations in other languages, or wrappers which rewrite packets, can check themselves
+//! against the same vectors.
+//!
+//! All multi-byte integers on the wire are little endian.
+
+use crate::packet_multiplexer::PacketChannel;
+
+/// A multiplexed packet, which is the packet payload prefixed with its channel.
+#[derive(Debug, Copy, Clone)]
+pub struct MuxVector {
+    pub name: &'static str,
+    pub channel: PacketChannel,
+    pub payload: &'static [u8],
+    pub packet: &'static [u8],
+}
+
+pub const MUX: &[MuxVector] = &[
+    MuxVector {
+        name: "channel header",
+        channel: 3,
+        payload: b"abc",
+        packet: b"\x03abc",
+    },
+    MuxVector {
+        name: "empty payload",
+        channel: 200,
+        payload: b"",
+        packet: b"\xc8",
+    },
+];
+
+/// A multiplexed data packet and a reliable acknowledgement on channel 1, bundled into a single
+/// packet by `piggyback::piggyback_acks`.
+///
+/// Bundles are the bundle channel followed by an entry for each packet: its channel, the length of
+/// its payload as a u16, then the payload.
+#[derive(Debug, Copy, Clone)]
+pub struct BundleVector {
+    pub name: &'static str,
+    pub bundle_channel: PacketChannel,
+    pub data: &'static [u8],
+    pub ack: &'static [u8],
+    pub bundle: &'static [u8],
+}
+
+pub const BUNDLE: &[BundleVector] = &[BundleVector {
+    name: "data with a piggybacked ack",
+    bundle_channel: 254,
+    data: b"\x03abc",
+    ack: b"\x01\xfb\xff\x00\x00\x00\x00\x00\x10\x00\x00",
+    bundle: b"\xfe\x03\x03\x00abc\x01\x0a\x00\xfb\xff\x00\x00\x00\x00\x00\x10\x00\x00",
+}];
+
+/// Messages coalesced into a single `UnreliableChannel` packet.
+///
+/// Each message is a u16 length followed by the message bytes.  The packet does not include the
+/// multiplexer channel header.
+#[derive(Debug, Copy, Clone)]
+pub struct UnreliableVector {
+    pub name: &'static str,
+    pub messages: &'static [&'static [u8]],
+    pub packet: &'static [u8],
+}
+
+pub const UNRELIABLE: &[UnreliableVector] = &[
+    UnreliableVector {
+        name: "single message",
+        messages: &[b"hello"],
+        packet: b"\x05\x00hello",
+    },
+    UnreliableVector {
+        name: "coalesced messages",
+        messages: &[b"ab", b"", b"c"],
+        packet: b"\x02\x00ab\x00\x00\x01\x00c",
+    },
+];
+
+/// Messages sent in a single `AckedChannel` packet, after receiving the given remote packets.
+///
+/// Packets are the u16 sequence number of the packet, the u16 sequence number of the latest packet
+/// received from the remote, and a u32 whose bit `n` is set if the packet `n + 1` before the latest
+/// was also received, followed by messages framed like `UnreliableChannel` messages.  Until a packet
+/// is received the latest received sequence number is `u16::MAX`.  The packet does not include the
+/// multiplexer channel header.
+///
+/// The vectors are consecutive packets sent by a single channel, which receives message-less
+/// remote packets with the `received` sequence numbers before sending each.
+#[derive(Debug, Copy, Clone)]
+pub struct AckedVector {
+    pub name: &'static str,
+    pub received: &'static [u16],
+    pub messages: &'static [&'static [u8]],
+    pub packet: &'static [u8],
+}
+
+pub const ACKED: &[AckedVector] = &[
+    AckedVector {
+        name: "first packet",
+        received: &[],
+        messages: &[b"hi"],
+        packet: b"\x00\x00\xff\xff\x00\x00\x00\x00\x02\x00hi",
+    },
+    AckedVector {
+        name: "acknowledging received packets",
+        received: &[0, 2],
+        messages: &[b"a", b"b"],
+        packet: b"\x01\x00\x02\x00\x02\x00\x00\x00\x01\x00a\x01\x00b",
+    },
+];
+
+/// Messages sent in a single `TimestampChannel` packet, each after the sender's timeline has reached
+/// the given time in milliseconds.
+///
+/// Packets are the time of the first message as a u32 of milliseconds, followed by each message as
+/// its length as a u16, the milliseconds since the first message as a u16, then the message.  The
+/// packet does not include the multiplexer channel header.
+#[derive(Debug, Copy, Clone)]
+pub struct TimestampVector {
+    pub name: &'static str,
+    pub messages: &'static [(u32, &'static [u8])],
+    pub packet: &'static [u8],
+}
+
+pub const TIMESTAMP: &[TimestampVector] = &[
+    TimestampVector {
+        name: "single message",
+        messages: &[(5, b"hi")],
+        packet: b"\x05\x00\x00\x00\x02\x00\x00\x00hi",
+    },
+    TimestampVector {
+        name: "later messages",
+        messages: &[(300, b"a"), (301, b""), (600, b"b")],
+        packet: b"\x2c\x01\x00\x00\x01\x00\x00\x00a\x00\x00\x01\x00\x01\x00\x2c\x01b",
+    },
+];
+
+/// A `ReliableChannel` data packet, and the acknowledgement the receiving side responds with.
+///
+/// Data packets are the data length as an i16, the stream position of the start of the data as a
+/// u32, then the data.  Acknowledgements are the negated length of the acknowledged data as an i16,
+/// the stream position of the start of the acknowledged data as a u32, then the end of the
+/// receiver's receive window as a u32.  Neither includes the multiplexer channel header.
+///
+/// The vectors are consecutive packets of a single stream, sent to a receiver with the given
+/// `recv_window_size` which has not read any data yet.
+#[derive(Debug, Copy, Clone)]
+pub struct ReliableVector {
+    pub name: &'static str,
+    pub recv_window_size: u32,
+    pub start: u32,
+    pub data: &'static [u8],
+    pub packet: &'static [u8],
+    pub ack: &'static [u8],
+}
+
+pub const RELIABLE: &[ReliableVector] = &[
+    ReliableVector {
+        name: "first data",
+        recv_window_size: 4096,
+        start: 0,
+        data: b"hello",
+        packet: b"\x05\x00\x00\x00\x00\x00hello",
+        ack: b"\xfb\xff\x00\x00\x00\x00\x00\x10\x00\x00",
+    },
+    ReliableVector {
+        name: "following data",
+        recv_window_size: 4096,
+        start: 5,
+        data: b"world",
+        packet: b"\x05\x00\x05\x00\x00\x00world",
+        ack: b"\xfb\xff\x05\x00\x00\x00\x00\x10\x00\x00",
+    },
+];
+
+/// A `ReliableChannel` urgent message packet, and the urgent acknowledgement the receiving side
+/// responds with.
+///
+/// Urgent packets are the length marker `i16::MIN`, a kind byte which is 0 for messages and 1 for
+/// acknowledgements, and the u32 sequence number of the urgent message, followed by the message for
+/// message packets.  Neither includes the multiplexer channel header.
+///
+/// The vectors are consecutive urgent messages sent on a single channel.
+#[derive(Debug, Copy, Clone)]
+pub struct UrgentVector {
+    pub name: &'static str,
+    pub seq: u32,
+    pub message: &'static [u8],
+    pub packet: &'static [u8],
+    pub ack: &'static [u8],
+}
+
+pub const URGENT: &[UrgentVector] = &[
+    UrgentVector {
+        name: "first urgent message",
+        seq: 0,
+        message: b"kick",
+        packet: b"\x00\x80\x00\x00\x00\x00\x00kick",
+        ack: b"\x00\x80\x01\x00\x00\x00\x00",
+    },
+    UrgentVector {
+        name: "following urgent message",
+        seq: 1,
+        message: b"",
+        packet: b"\x00\x80\x00\x01\x00\x00\x00",
+        ack: b"\x00\x80\x01\x01\x00\x00\x00",
+    },
+];
+
+/// A `ReliableChannel` congestion feedback packet, without the multiplexer channel header.
+///
+/// Feedback packets are the feedback marker (`i16::MIN + 1`), the delivery rate in bytes / sec as
+/// a u32, then the fraction of the receive window buffered but unread, scaled to a u16.
+#[derive(Debug, Copy, Clone)]
+pub struct FeedbackVector {
+    pub name: &'static str,
+    pub delivery_rate: u32,
+    pub occupancy: u16,
+    pub packet: &'static [u8],
+}
+
+pub const FEEDBACK: &[FeedbackVector] = &[FeedbackVector {
+    name: "half full receive window",
+    delivery_rate: 16384,
+    occupancy: 0x8000,
+    packet: b"\x01\x80\x00\x40\x00\x00\x00\x80",
+}];
+
+/// The stream bytes a `ReliableBincodeChannel` writes for a `&str` message.
+///
+/// Each message is prefixed with its length as a u16, and serialized with bincode using variable
+/// length integer encoding.
+#[derive(Debug, Copy, Clone)]
+pub struct BincodeVector {
+    pub name: &'static str,
+    pub message: &'static str,
+    pub stream: &'static [u8],
+}
+
+pub const RELIABLE_BINCODE: &[BincodeVector] = &[
+    BincodeVector {
+        name: "string message",
+        message: "hi",
+        stream: b"\x03\x00\x02hi",
+    },
+    BincodeVector {
+        name: "empty string message",
+        message: "",
+        stream: b"\x01\x00\x00",
+    },
+];
+
+/// A handshake hello packet, including the handshake channel header.
+///
+/// Hellos are the handshake channel, the magic bytes `TRBH`, the wire version as a u16, a byte
+/// which is 1 once the remote hello has been seen, the application protocol version as a u32, then
+/// the number of channels as a u8 followed by the sorted channel table.
+#[derive(Debug, Copy, Clone)]
+pub struct HelloVector {
+    pub name: &'static str,
+    pub handshake_channel: PacketChannel,
+    pub seen: bool,
+    pub protocol_version: u32,
+    pub channels: &'static [PacketChannel],
+    pub packet: &'static [u8],
+}
+
+pub const HELLO: &[HelloVector] = &[
+    HelloVector {
+        name: "initial hello",
+        handshake_channel: 255,
+        seen: false,
+        protocol_version: 7,
+        channels: &[0, 1, 4],
+        packet: b"\xffTRBH\x03\x00\x00\x07\x00\x00\x00\x03\x00\x01\x04",
+    },
+    HelloVector {
+        name: "hello after seeing the remote hello",
+        handshake_channel: 255,
+        seen: true,
+        protocol_version: 7,
+        channels: &[0, 1, 4],
+        packet: b"\xffTRBH\x03\x00\x01\x07\x00\x00\x00\x03\x00\x01\x04",
+    },
+];
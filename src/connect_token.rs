@@ -0,0 +1,154 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::packet_multiplexer::PacketChannel;
+
+/// The size of an encoded connect token.
+pub const TOKEN_LEN: usize = 4 + 8 + 8 + TAG_LEN;
+
+const MAGIC: &[u8; 4] = b"TRBT";
+const TAG_LEN: usize = 16;
+
+/// The contents of a validated connect token.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectToken {
+    /// The client identifier chosen by the backend that issued the token.
+    pub client_id: u64,
+    /// The token is rejected after this time.
+    pub expires_at: SystemTime,
+}
+
+/// Error returned when validating a connect token, validation errors are not fatal.
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("connect token is malformed")]
+    Malformed,
+    #[error("connect token failed authentication")]
+    Unauthenticated,
+    #[error("connect token has expired")]
+    Expired,
+}
+
+/// Issues short-lived connect tokens, signed with a secret shared between a matchmaking backend
+/// and the game servers.
+///
+/// A client is given a token by the backend after it has logged in, and sends it to the game
+/// server as the very first packet of a connection, see `handshake::Settings::connect_token`.  The
+/// server checks it with a `TokenValidator` before allocating any state for the client.
+///
+/// Tokens are not bound to an address and are sent in the clear, so anyone who observes a token
+/// can use it until it expires.  Keep token lifetimes short, on the order of seconds.
+#[derive(Clone)]
+pub struct TokenIssuer {
+    mac: Hmac<Sha256>,
+}
+
+impl fmt::Debug for TokenIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TokenIssuer { .. }")
+    }
+}
+
+impl TokenIssuer {
+    /// The shared secret may be of any length, but should be at least 32 random bytes.
+    pub fn new(secret: &[u8]) -> TokenIssuer {
+        TokenIssuer {
+            mac: new_mac(secret),
+        }
+    }
+
+    /// Issue a token for the given client which is valid for the given duration from now.
+    pub fn issue(&self, client_id: u64, valid_for: Duration) -> [u8; TOKEN_LEN] {
+        self.issue_token(&ConnectToken {
+            client_id,
+            expires_at: SystemTime::now() + valid_for,
+        })
+    }
+
+    /// Issue a token with the given contents.  The expiry time is stored with a resolution of one
+    /// second, rounded down.
+    pub fn issue_token(&self, token: &ConnectToken) -> [u8; TOKEN_LEN] {
+        let expires_at = token
+            .expires_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let mut encoded = [0; TOKEN_LEN];
+        encoded[0..4].copy_from_slice(MAGIC);
+        LittleEndian::write_u64(&mut encoded[4..12], token.client_id);
+        LittleEndian::write_u64(&mut encoded[12..20], expires_at);
+
+        let mut mac = self.mac.clone();
+        mac.update(&encoded[..TOKEN_LEN - TAG_LEN]);
+        encoded[TOKEN_LEN - TAG_LEN..].copy_from_slice(&mac.finalize().into_bytes()[..TAG_LEN]);
+        encoded
+    }
+}
+
+/// Validates connect tokens issued by a `TokenIssuer` with the same shared secret.
+#[derive(Clone)]
+pub struct TokenValidator {
+    mac: Hmac<Sha256>,
+}
+
+impl fmt::Debug for TokenValidator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TokenValidator { .. }")
+    }
+}
+
+impl TokenValidator {
+    pub fn new(secret: &[u8]) -> TokenValidator {
+        TokenValidator {
+            mac: new_mac(secret),
+        }
+    }
+
+    /// Validate an encoded token, which must be authentic and not yet expired.
+    pub fn validate(&self, token: &[u8]) -> Result<ConnectToken, TokenError> {
+        if token.len() != TOKEN_LEN || &token[0..4] != MAGIC {
+            return Err(TokenError::Malformed);
+        }
+
+        let mut mac = self.mac.clone();
+        mac.update(&token[..TOKEN_LEN - TAG_LEN]);
+        mac.verify_truncated_left(&token[TOKEN_LEN - TAG_LEN..])
+            .map_err(|_| TokenError::Unauthenticated)?;
+
+        let token = ConnectToken {
+            client_id: LittleEndian::read_u64(&token[4..12]),
+            expires_at: UNIX_EPOCH + Duration::from_secs(LittleEndian::read_u64(&token[12..20])),
+        };
+        if token.expires_at <= SystemTime::now() {
+            return Err(TokenError::Expired);
+        }
+        Ok(token)
+    }
+
+    /// Validate a connect request packet sent by `handshake` on the given handshake channel.
+    ///
+    /// This is meant to be used as the accept filter of a server transport (see
+    /// `UdpTransport::bind_with_filter`), so that packet floods from clients without a valid token
+    /// do not create any connection state.
+    pub fn validate_request(
+        &self,
+        channel: PacketChannel,
+        packet: &[u8],
+    ) -> Result<ConnectToken, TokenError> {
+        match packet.split_first() {
+            Some((&c, token)) if c == channel => self.validate(token),
+            _ => Err(TokenError::Malformed),
+        }
+    }
+}
+
+fn new_mac(secret: &[u8]) -> Hmac<Sha256> {
+    Hmac::new_from_slice(secret).expect("HMAC accepts keys of any length")
+}
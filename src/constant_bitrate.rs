@@ -0,0 +1,276 @@
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{ready, Stream};
+
+use crate::{
+    packet::{Packet, PacketMetadata, PacketPool},
+    runtime::{Interval, Timer},
+};
+
+/// The size of the payload length header at the start of every padded packet.
+pub const HEADER_LEN: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The time between sent packets.  Exactly one packet is sent every interval, so the bitrate
+    /// is the capacity of the packets over the interval.
+    pub interval: Duration,
+    /// The maximum number of packets waiting for their turn to be sent.  Once this many packets
+    /// are queued, no more are taken from the inner stream until some are sent.
+    pub max_queue: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: Duration::from_micros(16_667),
+            max_queue: 64,
+        }
+    }
+}
+
+/// A wrapper over a `Packet` that reserves space for the payload length header.
+#[derive(Debug)]
+pub struct PaddedPacket<P>(P);
+
+impl<P> Packet for PaddedPacket<P>
+where
+    P: Packet,
+{
+    fn capacity(&self) -> usize {
+        self.0.capacity().saturating_sub(HEADER_LEN)
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.0.resize(len + HEADER_LEN, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
+    }
+}
+
+impl<P> Deref for PaddedPacket<P>
+where
+    P: Packet,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[HEADER_LEN..]
+    }
+}
+
+impl<P> DerefMut for PaddedPacket<P>
+where
+    P: Packet,
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[HEADER_LEN..]
+    }
+}
+
+/// A packet pool for unpadded packets, which produces `PaddedPacket`s with room for the length
+/// header.
+#[derive(Debug, Clone)]
+pub struct PaddedPacketPool<P>(P);
+
+impl<P> PaddedPacketPool<P> {
+    pub fn new(packet_pool: P) -> Self {
+        PaddedPacketPool(packet_pool)
+    }
+}
+
+impl<P> PacketPool for PaddedPacketPool<P>
+where
+    P: PacketPool,
+{
+    type Packet = PaddedPacket<P::Packet>;
+
+    fn acquire(&self) -> PaddedPacket<P::Packet> {
+        let mut packet = self.0.acquire();
+        packet.resize(HEADER_LEN, 0);
+        PaddedPacket(packet)
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
+}
+
+impl<P> From<P> for PaddedPacketPool<P> {
+    fn from(pool: P) -> PaddedPacketPool<P> {
+        PaddedPacketPool(pool)
+    }
+}
+
+/// Wrap a stream of outgoing padded packets, usually the `OutgoingMultiplexedPackets` of a
+/// multiplexer using a `PaddedPacketPool`, so that the connection sends at a constant bitrate.
+///
+/// The remote must strip the padding again by wrapping its incoming packets with `unpadded`.
+pub fn constant_bitrate<T, P, S>(
+    timer: T,
+    settings: Settings,
+    packet_pool: P,
+    outgoing: S,
+) -> ConstantBitrate<T, P, S>
+where
+    T: Timer,
+    P: PacketPool,
+    S: Stream<Item = PaddedPacket<P::Packet>> + Unpin,
+{
+    ConstantBitrate::new(timer, settings, packet_pool, outgoing)
+}
+
+/// A `Stream` which sends the packets of an inner stream at a constant bitrate, created by
+/// `constant_bitrate`.
+///
+/// The size and timing of packets can reveal what a game is doing even through encryption, for
+/// example a burst of larger packets when an opponent comes into view.  Instead, one packet is
+/// sent every `Settings::interval`, always padded to the full capacity of the packet pool.  If no
+/// packet is waiting its turn, a packet of pure padding is sent in its place.
+///
+/// Padding only hides anything if it is applied before encryption, so that the length header and
+/// padding are encrypted along with the packet.  Packets are delayed by up to an interval waiting
+/// for their turn, and by more if they arrive faster than one per interval, so the interval should
+/// keep up with the most packets the connection ever needs to send.
+pub struct ConstantBitrate<T: Timer, P: PacketPool, S> {
+    packet_pool: P,
+    max_queue: usize,
+    inner: S,
+    inner_done: bool,
+    queue: VecDeque<PaddedPacket<P::Packet>>,
+    interval: Interval<T>,
+    padding_sent: u64,
+}
+
+// No field is ever pinned, queued packets and the inner stream are only accessed by `&mut`.
+impl<T: Timer, P: PacketPool, S> Unpin for ConstantBitrate<T, P, S> {}
+
+impl<T, P, S> ConstantBitrate<T, P, S>
+where
+    T: Timer,
+    P: PacketPool,
+    S: Stream<Item = PaddedPacket<P::Packet>> + Unpin,
+{
+    pub fn new(timer: T, settings: Settings, packet_pool: P, outgoing: S) -> Self {
+        ConstantBitrate {
+            packet_pool,
+            max_queue: settings.max_queue,
+            inner: outgoing,
+            inner_done: false,
+            queue: VecDeque::new(),
+            interval: timer.interval(settings.interval),
+            padding_sent: 0,
+        }
+    }
+
+    /// The number of packets currently waiting for their turn to be sent.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The total number of packets of pure padding sent.
+    pub fn padding_sent(&self) -> u64 {
+        self.padding_sent
+    }
+}
+
+impl<T, P, S> Stream for ConstantBitrate<T, P, S>
+where
+    T: Timer,
+    P: PacketPool,
+    S: Stream<Item = PaddedPacket<P::Packet>> + Unpin,
+{
+    type Item = P::Packet;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<P::Packet>> {
+        let this = &mut *self;
+
+        while !this.inner_done && this.queue.len() < this.max_queue {
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(packet)) => this.queue.push_back(packet),
+                Poll::Ready(None) => this.inner_done = true,
+                Poll::Pending => break,
+            }
+        }
+        if this.inner_done && this.queue.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        ready!(Pin::new(&mut this.interval).poll_next(cx));
+
+        let mut packet = match this.queue.pop_front() {
+            Some(PaddedPacket(mut packet)) => {
+                let len = packet.len() - HEADER_LEN;
+                LittleEndian::write_u16(&mut packet[0..HEADER_LEN], len as u16);
+                packet
+            }
+            None => {
+                // A payload length of zero marks a packet of pure padding, multiplexed packets are
+                // never empty.
+                this.padding_sent += 1;
+                let mut packet = this.packet_pool.acquire();
+                packet.resize(HEADER_LEN, 0);
+                packet
+            }
+        };
+        let capacity = packet.capacity();
+        packet.resize(capacity, 0);
+        Poll::Ready(Some(packet))
+    }
+}
+
+/// Wrap a stream of incoming packets sent by a `ConstantBitrate`, so that the padding is removed,
+/// and packets of pure padding are dropped.
+pub fn unpadded<I>(incoming: I) -> Unpadded<I> {
+    Unpadded { incoming }
+}
+
+/// A `Stream` of packets with their padding removed, created by `unpadded`.
+pub struct Unpadded<I> {
+    incoming: I,
+}
+
+impl<I, P> Stream for Unpadded<I>
+where
+    I: Stream<Item = P> + Unpin,
+    P: Packet,
+{
+    type Item = PaddedPacket<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            let mut packet = match ready!(Pin::new(&mut self.incoming).poll_next(cx)) {
+                Some(packet) => packet,
+                None => return Poll::Ready(None),
+            };
+            if packet.len() < HEADER_LEN {
+                debug_event!("dropping padded packet without a length header");
+                continue;
+            }
+            let len = LittleEndian::read_u16(&packet[0..HEADER_LEN]) as usize;
+            if len == 0 {
+                continue;
+            }
+            if HEADER_LEN + len > packet.len() {
+                debug_event!(len, "dropping padded packet with a bad length");
+                continue;
+            }
+            packet.resize(HEADER_LEN + len, 0);
+            return Poll::Ready(Some(PaddedPacket(packet)));
+        }
+    }
+}
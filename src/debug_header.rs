@@ -0,0 +1,206 @@
+use std::{
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use byteorder::{BigEndian, ByteOrder};
+use futures::{ready, Stream};
+
+use crate::packet::{Packet, PacketMetadata, PacketPool};
+
+/// The magic bytes at the start of every packet with a debug header.
+pub const MAGIC: [u8; 4] = *b"TRBD";
+/// The version written to debug headers, packets with any other version are dropped.
+pub const VERSION: u8 = 1;
+/// The size of the debug header, including the multiplexer channel header in its last byte.
+pub const HEADER_LEN: usize = 13;
+
+/// The offset of the version byte in the debug header.
+pub const VERSION_OFFSET: usize = 4;
+/// The offset of the flags byte in the debug header.  No flags are defined yet, they are always
+/// written as zero and ignored when received.
+pub const FLAGS_OFFSET: usize = 5;
+/// The offset of the big endian `u16` payload length, which does not include the header.
+pub const LENGTH_OFFSET: usize = 6;
+/// The offset of the big endian `u32` sequence number, which counts the packets sent since the
+/// stream was created, so that gaps in a capture show lost packets.
+pub const SEQUENCE_OFFSET: usize = 8;
+/// The offset of the multiplexer channel.
+pub const CHANNEL_OFFSET: usize = 12;
+
+/// A wrapper over a `Packet` that reserves space for the debug header in front of the multiplexer
+/// channel header.
+#[derive(Debug)]
+pub struct DebugPacket<P>(P);
+
+impl<P> Packet for DebugPacket<P>
+where
+    P: Packet,
+{
+    fn capacity(&self) -> usize {
+        self.0.capacity().saturating_sub(CHANNEL_OFFSET)
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.0.resize(len + CHANNEL_OFFSET, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
+    }
+}
+
+impl<P> Deref for DebugPacket<P>
+where
+    P: Packet,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[CHANNEL_OFFSET..]
+    }
+}
+
+impl<P> DerefMut for DebugPacket<P>
+where
+    P: Packet,
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[CHANNEL_OFFSET..]
+    }
+}
+
+/// A packet pool for packets with debug headers, which produces `DebugPacket`s with room for the
+/// header.
+#[derive(Debug, Clone)]
+pub struct DebugPacketPool<P>(P);
+
+impl<P> DebugPacketPool<P> {
+    pub fn new(packet_pool: P) -> Self {
+        DebugPacketPool(packet_pool)
+    }
+}
+
+impl<P> PacketPool for DebugPacketPool<P>
+where
+    P: PacketPool,
+{
+    type Packet = DebugPacket<P::Packet>;
+
+    fn acquire(&self) -> DebugPacket<P::Packet> {
+        let mut packet = self.0.acquire();
+        packet.resize(CHANNEL_OFFSET, 0);
+        DebugPacket(packet)
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
+}
+
+impl<P> From<P> for DebugPacketPool<P> {
+    fn from(pool: P) -> DebugPacketPool<P> {
+        DebugPacketPool(pool)
+    }
+}
+
+/// Wrap a stream of outgoing packets, usually the `OutgoingMultiplexedPackets` of a multiplexer
+/// using a `DebugPacketPool`, so that every packet starts with a debug header.
+///
+/// The remote must remove the headers again by wrapping its incoming packets with
+/// `strip_debug_headers`.
+pub fn debug_headers<S>(outgoing: S) -> DebugHeaders<S> {
+    DebugHeaders {
+        outgoing,
+        sequence: 0,
+    }
+}
+
+/// A `Stream` of packets with self-describing debug headers, created by `debug_headers`.
+///
+/// The compact wire format is hard to read in a capture, a packet is a single channel byte
+/// followed by whatever the channel sends.  The debug header puts the magic bytes `MAGIC`, a
+/// version, flags, the payload length, a sequence number and the channel at fixed offsets, all
+/// big endian, so packets can be picked out by eye in a hex dump or decoded by a trivial
+/// dissector.  This costs `HEADER_LEN - 1` bytes of every packet, so it is meant for protocol
+/// development rather than production.
+pub struct DebugHeaders<S> {
+    outgoing: S,
+    sequence: u32,
+}
+
+impl<S> DebugHeaders<S> {
+    /// The number of packets sent so far, which is also the sequence number of the next packet.
+    pub fn sent(&self) -> u32 {
+        self.sequence
+    }
+}
+
+impl<S, P> Stream for DebugHeaders<S>
+where
+    S: Stream<Item = DebugPacket<P>> + Unpin,
+    P: Packet,
+{
+    type Item = P;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<P>> {
+        let DebugPacket(mut packet) = match ready!(Pin::new(&mut self.outgoing).poll_next(cx)) {
+            Some(packet) => packet,
+            None => return Poll::Ready(None),
+        };
+        let len = packet.len() - HEADER_LEN;
+        packet[0..VERSION_OFFSET].copy_from_slice(&MAGIC);
+        packet[VERSION_OFFSET] = VERSION;
+        packet[FLAGS_OFFSET] = 0;
+        BigEndian::write_u16(&mut packet[LENGTH_OFFSET..SEQUENCE_OFFSET], len as u16);
+        BigEndian::write_u32(&mut packet[SEQUENCE_OFFSET..CHANNEL_OFFSET], self.sequence);
+        self.sequence = self.sequence.wrapping_add(1);
+        Poll::Ready(Some(packet))
+    }
+}
+
+/// Wrap a stream of incoming packets sent by a `DebugHeaders`, so that the debug headers are
+/// removed, and packets without a valid header are dropped.
+pub fn strip_debug_headers<I>(incoming: I) -> StripDebugHeaders<I> {
+    StripDebugHeaders { incoming }
+}
+
+/// A `Stream` of packets with their debug headers removed, created by `strip_debug_headers`.
+pub struct StripDebugHeaders<I> {
+    incoming: I,
+}
+
+impl<I, P> Stream for StripDebugHeaders<I>
+where
+    I: Stream<Item = P> + Unpin,
+    P: Packet,
+{
+    type Item = DebugPacket<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            let packet = match ready!(Pin::new(&mut self.incoming).poll_next(cx)) {
+                Some(packet) => packet,
+                None => return Poll::Ready(None),
+            };
+            if packet.len() < HEADER_LEN
+                || packet[0..VERSION_OFFSET] != MAGIC
+                || packet[VERSION_OFFSET] != VERSION
+            {
+                debug_event!("dropping packet without a debug header");
+                continue;
+            }
+            let len = BigEndian::read_u16(&packet[LENGTH_OFFSET..SEQUENCE_OFFSET]) as usize;
+            if HEADER_LEN + len != packet.len() {
+                debug_event!(len, "dropping packet with a bad debug header length");
+                continue;
+            }
+            return Poll::Ready(Some(DebugPacket(packet)));
+        }
+    }
+}
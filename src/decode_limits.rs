@@ -0,0 +1,416 @@
+//! Limits on the size and nesting depth of decoded messages.
+//!
+//! The byte limit of a channel bounds how much data a peer can send, but not how much memory the
+//! data decodes into, nor how deeply it nests.  A tiny message can claim a sequence of billions of
+//! zero sized elements, or nest a recursive type deeply enough to overflow the stack.  Wrapping
+//! deserialization with `DecodeLimits` rejects such messages as they are decoded, before they are
+//! fully allocated.
+//!
+//! The decoded size is estimated from what the deserializer visits: every primitive counts its
+//! in-memory size, every string or byte buffer its length, and every sequence, map, option or enum
+//! the size of a `Vec`.  This is not the exact size of the decoded value, but it grows with it.
+
+use std::{cell::Cell, fmt, marker::PhantomData, mem};
+
+use serde::{
+    de::{self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess},
+    Deserialize,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodeLimits {
+    /// The largest estimated decoded size of a message, in bytes.
+    pub max_size: usize,
+    /// The deepest nesting of values within a message, where a primitive at the top level has a
+    /// depth of 1.
+    pub max_depth: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_size: 1 << 20,
+            max_depth: 64,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Deserialize a `T` from the given deserializer, failing with a custom error of the
+    /// deserializer if the message exceeds these limits.
+    pub fn deserialize<'de, T, D>(&self, deserializer: D) -> Result<T, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        self.seed::<T>().deserialize(deserializer)
+    }
+
+    /// A `DeserializeSeed` for a `T` within these limits, for deserializers which take a seed,
+    /// such as `bincode::Options::deserialize_seed`.
+    pub fn seed<T>(&self) -> LimitedSeed<T> {
+        LimitedSeed {
+            limits: *self,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// A `DeserializeSeed` which deserializes a `T` within `DecodeLimits`, created by
+/// `DecodeLimits::seed`.
+pub struct LimitedSeed<T> {
+    limits: DecodeLimits,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<'de, T: Deserialize<'de>> DeserializeSeed<'de> for LimitedSeed<T> {
+    type Value = T;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<T, D::Error> {
+        let budget = Budget {
+            limits: self.limits,
+            size: Cell::new(0),
+            depth: Cell::new(0),
+        };
+        T::deserialize(Limited {
+            inner: deserializer,
+            budget: &budget,
+        })
+    }
+}
+
+struct Budget {
+    limits: DecodeLimits,
+    size: Cell<usize>,
+    depth: Cell<usize>,
+}
+
+impl Budget {
+    fn charge<E: de::Error>(&self, size: usize) -> Result<(), E> {
+        let size = self.size.get().saturating_add(size);
+        if size > self.limits.max_size {
+            return Err(E::custom("message exceeds the decoded size limit"));
+        }
+        self.size.set(size);
+        Ok(())
+    }
+
+    fn enter<E: de::Error>(&self) -> Result<(), E> {
+        let depth = self.depth.get() + 1;
+        if depth > self.limits.max_depth {
+            return Err(E::custom("message exceeds the decoded depth limit"));
+        }
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    fn exit(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+}
+
+// The size charged for every sequence, map, option or enum.
+const CONTAINER_SIZE: usize = mem::size_of::<Vec<u8>>();
+
+// Wraps a deserializer, counting the depth of every value it deserializes.
+struct Limited<'a, D> {
+    inner: D,
+    budget: &'a Budget,
+}
+
+// Wraps a visitor, a seed or an access, charging the budget for what is visited.
+struct Wrap<'a, T> {
+    inner: T,
+    budget: &'a Budget,
+}
+
+impl<'a, T> Wrap<'a, T> {
+    fn new(inner: T, budget: &'a Budget) -> Self {
+        Wrap { inner, budget }
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method<V: de::Visitor<'de>>(
+                self,
+                $($arg: $ty,)*
+                visitor: V,
+            ) -> Result<V::Value, D::Error> {
+                self.budget.enter()?;
+                let value = self
+                    .inner
+                    .$method($($arg,)* Wrap::new(visitor, self.budget));
+                self.budget.exit();
+                value
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Limited<'_, D> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty);)*) => {
+        $(
+            fn $method<E: de::Error>(self, v: $ty) -> Result<V::Value, E> {
+                self.budget.charge(mem::size_of::<$ty>())?;
+                self.inner.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'de, V: de::Visitor<'de>> de::Visitor<'de> for Wrap<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool);
+        visit_i8(i8);
+        visit_i16(i16);
+        visit_i32(i32);
+        visit_i64(i64);
+        visit_i128(i128);
+        visit_u8(u8);
+        visit_u16(u16);
+        visit_u32(u32);
+        visit_u64(u64);
+        visit_u128(u128);
+        visit_f32(f32);
+        visit_f64(f64);
+        visit_char(char);
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<V::Value, E> {
+        self.budget.charge(v.len())?;
+        self.inner.visit_str(v)
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<V::Value, E> {
+        self.budget.charge(v.len())?;
+        self.inner.visit_borrowed_str(v)
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<V::Value, E> {
+        self.budget.charge(v.len())?;
+        self.inner.visit_string(v)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<V::Value, E> {
+        self.budget.charge(v.len())?;
+        self.inner.visit_bytes(v)
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<V::Value, E> {
+        self.budget.charge(v.len())?;
+        self.inner.visit_borrowed_bytes(v)
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<V::Value, E> {
+        self.budget.charge(v.len())?;
+        self.inner.visit_byte_buf(v)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<V::Value, E> {
+        self.budget.charge(CONTAINER_SIZE)?;
+        self.inner.visit_none()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        self.budget.charge(CONTAINER_SIZE)?;
+        self.inner.visit_some(Limited {
+            inner: deserializer,
+            budget: self.budget,
+        })
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<V::Value, E> {
+        // Charged so that a long sequence of units is still limited.
+        self.budget.charge(1)?;
+        self.inner.visit_unit()
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<V::Value, D::Error> {
+        self.inner.visit_newtype_struct(Limited {
+            inner: deserializer,
+            budget: self.budget,
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<V::Value, A::Error> {
+        self.budget.charge(CONTAINER_SIZE)?;
+        self.inner.visit_seq(Wrap::new(seq, self.budget))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<V::Value, A::Error> {
+        self.budget.charge(CONTAINER_SIZE)?;
+        self.inner.visit_map(Wrap::new(map, self.budget))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<V::Value, A::Error> {
+        self.budget.charge(CONTAINER_SIZE)?;
+        self.inner.visit_enum(Wrap::new(data, self.budget))
+    }
+}
+
+impl<'de, T: DeserializeSeed<'de>> DeserializeSeed<'de> for Wrap<'_, T> {
+    type Value = T::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<T::Value, D::Error> {
+        self.inner.deserialize(Limited {
+            inner: deserializer,
+            budget: self.budget,
+        })
+    }
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Wrap<'_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, A::Error> {
+        self.inner.next_element_seed(Wrap::new(seed, self.budget))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Wrap<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        self.inner.next_key_seed(Wrap::new(seed, self.budget))
+    }
+
+    fn next_value_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, A::Error> {
+        self.inner.next_value_seed(Wrap::new(seed, self.budget))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, 'de, A: EnumAccess<'de>> EnumAccess<'de> for Wrap<'a, A> {
+    type Error = A::Error;
+    type Variant = Wrap<'a, A::Variant>;
+
+    fn variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<(T::Value, Self::Variant), A::Error> {
+        let (value, variant) = self.inner.variant_seed(Wrap::new(seed, self.budget))?;
+        Ok((value, Wrap::new(variant, self.budget)))
+    }
+}
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for Wrap<'_, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+        self.inner
+            .newtype_variant_seed(Wrap::new(seed, self.budget))
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        self.budget.enter()?;
+        let value = self
+            .inner
+            .tuple_variant(len, Wrap::new(visitor, self.budget));
+        self.budget.exit();
+        value
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        self.budget.enter()?;
+        let value = self
+            .inner
+            .struct_variant(fields, Wrap::new(visitor, self.budget));
+        self.budget.exit();
+        value
+    }
+}
+
+// Deserialize a message with bincode, within the given limits if any.
+pub(crate) fn bincode_deserialize<'a, T, O>(
+    options: O,
+    bytes: &'a [u8],
+    limits: Option<&DecodeLimits>,
+) -> Result<T, bincode::Error>
+where
+    T: Deserialize<'a>,
+    O: bincode::Options,
+{
+    match limits {
+        Some(limits) => options.deserialize_seed(limits.seed(), bytes),
+        None => options.deserialize(bytes),
+    }
+}
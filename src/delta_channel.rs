@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{Sink, Stream};
+
+use crate::{
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{
+        DefaultIncoming, DefaultOutgoing, RecvError, SendError, UnreliableChannel,
+    },
+};
+
+/// The number of recent states each side remembers.  The sender only encodes deltas against
+/// acknowledged baselines less than this many states old, and sends a full state otherwise.
+pub const HISTORY_LEN: u16 = 32;
+
+const FULL: u8 = 0;
+const DELTA: u8 = 1;
+const ACK: u8 = 2;
+
+// Unchanged runs shorter than this are included in a changed span rather than starting a new
+// span, since every span has a 4 byte header.
+const MIN_SKIP: usize = 4;
+
+/// Replicates a stream of state snapshots, sending each state as a byte-level delta against the
+/// most recent state the receiver has acknowledged.
+///
+/// The first state, and any state for which no recent enough baseline has been acknowledged, is
+/// sent in full.  Every state received is acknowledged to the other side, and each side may send
+/// states independently.  Only the newest state matters: states which arrive after a newer state
+/// has already been received are dropped, as are deltas whose baseline is unknown.
+///
+/// Acknowledgements are sent on the next call to `flush`, and acknowledgements from the other side
+/// are processed by `recv`.  A side which only sends states must still keep calling `recv`, and a
+/// side which only receives states must still regularly call `flush`.
+pub struct DeltaChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableChannel<R, P, I, O>,
+    next_sequence: u16,
+    sent: VecDeque<(u16, Vec<u8>)>,
+    baseline: Option<(u16, Vec<u8>)>,
+    received: VecDeque<(u16, Vec<u8>)>,
+    pending_ack: Option<u16>,
+    buffer: Vec<u8>,
+}
+
+impl<R, P, I, O> DeltaChannel<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(channel: UnreliableChannel<R, P, I, O>) -> Self {
+        DeltaChannel {
+            channel,
+            next_sequence: 0,
+            sent: VecDeque::new(),
+            baseline: None,
+            received: VecDeque::new(),
+            pending_ack: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// The sequence number of the most recent sent state that the other side has acknowledged,
+    /// which is the baseline for the next delta.
+    pub fn baseline(&self) -> Option<u16> {
+        self.baseline.as_ref().map(|(sequence, _)| *sequence)
+    }
+
+    /// Write a new state to the channel, returning its sequence number.
+    ///
+    /// Just like the underlying channel, in order to guarantee that the state is actually sent you
+    /// must call `flush`.
+    ///
+    /// This method is cancel safe, though canceling it may or may not send the state.
+    pub async fn send(&mut self, state: &[u8]) -> Result<u16, SendError> {
+        let sequence = self.next_sequence;
+
+        self.buffer.clear();
+        match &self.baseline {
+            Some((base_sequence, base)) if sequence.wrapping_sub(*base_sequence) < HISTORY_LEN => {
+                self.buffer.push(DELTA);
+                write_u16(&mut self.buffer, sequence);
+                write_u16(&mut self.buffer, *base_sequence);
+                encode_delta(base, state, &mut self.buffer);
+            }
+            _ => {}
+        }
+        if self.buffer.is_empty() || self.buffer.len() >= state.len() + 3 {
+            self.buffer.clear();
+            self.buffer.push(FULL);
+            write_u16(&mut self.buffer, sequence);
+            self.buffer.extend_from_slice(state);
+        }
+        self.channel.send(&self.buffer).await?;
+
+        self.next_sequence = sequence.wrapping_add(1);
+        if self.sent.len() == HISTORY_LEN as usize {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((sequence, state.to_vec()));
+        Ok(sequence)
+    }
+
+    /// Send any pending acknowledgement and finish sending any unsent coalesced packets.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        if let Some(sequence) = self.pending_ack {
+            let mut ack = [ACK, 0, 0];
+            LittleEndian::write_u16(&mut ack[1..3], sequence);
+            self.channel.send(&ack).await?;
+            self.pending_ack = None;
+        }
+        self.channel.flush().await
+    }
+
+    /// Receive the next state, processing any acknowledgements that arrive in the meantime.
+    ///
+    /// This method is cancel safe, it will never drop a received state.
+    pub async fn recv(&mut self) -> Result<&[u8], RecvError> {
+        loop {
+            let msg = self.channel.recv().await?;
+            if msg.len() < 3 {
+                return Err(RecvError::BadFormat);
+            }
+            let sequence = LittleEndian::read_u16(&msg[1..3]);
+
+            let state = match msg[0] {
+                ACK => {
+                    if let Some(i) = self.sent.iter().position(|(s, _)| *s == sequence) {
+                        let newer = match &self.baseline {
+                            Some((base, _)) => is_newer(sequence, *base),
+                            None => true,
+                        };
+                        if newer {
+                            self.sent.drain(..i);
+                            self.baseline = self.sent.pop_front();
+                        }
+                    }
+                    continue;
+                }
+                FULL | DELTA if !is_newest(&self.received, sequence) => continue,
+                FULL => msg[3..].to_vec(),
+                DELTA => {
+                    if msg.len() < 7 {
+                        return Err(RecvError::BadFormat);
+                    }
+                    let base_sequence = LittleEndian::read_u16(&msg[3..5]);
+                    let base = match self.received.iter().find(|(s, _)| *s == base_sequence) {
+                        Some((_, base)) => base,
+                        None => continue,
+                    };
+                    apply_delta(base, &msg[5..]).ok_or(RecvError::BadFormat)?
+                }
+                _ => return Err(RecvError::BadFormat),
+            };
+
+            if self.received.len() == HISTORY_LEN as usize {
+                self.received.pop_front();
+            }
+            self.received.push_back((sequence, state));
+            self.pending_ack = Some(sequence);
+            return Ok(&self.received.back().unwrap().1);
+        }
+    }
+}
+
+fn is_newest(received: &VecDeque<(u16, Vec<u8>)>, sequence: u16) -> bool {
+    match received.back() {
+        Some((latest, _)) => is_newer(sequence, *latest),
+        None => true,
+    }
+}
+
+pub(crate) fn is_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+fn write_u16(buffer: &mut Vec<u8>, val: u16) {
+    let mut bytes = [0; 2];
+    LittleEndian::write_u16(&mut bytes, val);
+    buffer.extend_from_slice(&bytes);
+}
+
+// Deltas are the new length as a u16, followed by spans of changed bytes.  Each span is the number
+// of unchanged bytes to skip and the number of changed bytes as u16s, followed by the changed
+// bytes.  Bytes past the end of the baseline are compared against zero.
+pub(crate) fn encode_delta(base: &[u8], new: &[u8], out: &mut Vec<u8>) {
+    let base_byte = |i: usize| base.get(i).copied().unwrap_or(0);
+    write_u16(out, new.len() as u16);
+
+    let mut pos = 0;
+    while pos < new.len() {
+        let start = match (pos..new.len()).find(|&i| new[i] != base_byte(i)) {
+            Some(start) => start,
+            None => break,
+        };
+
+        let mut end = start;
+        let mut unchanged = 0;
+        for (i, &b) in new.iter().enumerate().skip(start) {
+            if b == base_byte(i) {
+                unchanged += 1;
+                if unchanged == MIN_SKIP {
+                    break;
+                }
+            } else {
+                unchanged = 0;
+                end = i + 1;
+            }
+        }
+
+        write_u16(out, (start - pos) as u16);
+        write_u16(out, (end - start) as u16);
+        out.extend_from_slice(&new[start..end]);
+        pos = end;
+    }
+}
+
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let len = LittleEndian::read_u16(delta.get(0..2)?) as usize;
+    let mut state = base.to_vec();
+    state.resize(len, 0);
+
+    let mut pos = 0;
+    let mut delta = &delta[2..];
+    while !delta.is_empty() {
+        let skip = LittleEndian::read_u16(delta.get(0..2)?) as usize;
+        let count = LittleEndian::read_u16(delta.get(2..4)?) as usize;
+        let bytes = delta.get(4..4 + count)?;
+        let start = pos + skip;
+        state.get_mut(start..start + count)?.copy_from_slice(bytes);
+        pos = start + count;
+        delta = &delta[4 + count..];
+    }
+    Some(state)
+}
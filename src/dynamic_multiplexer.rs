@@ -0,0 +1,665 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::{
+    channel::mpsc::{self, Receiver, Sender},
+    future::{self, poll_fn, Either},
+    pin_mut, Sink, Stream, StreamExt,
+};
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketMetadata, PacketPool},
+    packet_multiplexer::{DuplicateChannel, PacketChannel},
+    runtime::Timer,
+};
+
+/// The length of the channel and generation header of every `DynamicMultiplexer` packet.
+pub const HEADER_LEN: usize = 2;
+
+/// The channel number reserved for the control packets which open and close channels, which may
+/// not be opened itself.  Control packets are the control channel, a packet kind, and then the channel and
+/// generation they refer to.
+pub const CONTROL_CHANNEL: PacketChannel = 255;
+
+/// How often `DynamicMultiplexer::open` resends its open request while waiting for the peer.
+pub const OPEN_RESEND_INTERVAL: Duration = Duration::from_millis(200);
+
+const CONTROL_LEN: usize = 4;
+const CONTROL_OPEN: u8 = 0;
+const CONTROL_OPEN_ACK: u8 = 1;
+const CONTROL_CLOSE: u8 = 2;
+
+const OPEN: u8 = 0;
+const CLOSED: u8 = 1;
+const CLOSED_BY_PEER: u8 = 2;
+
+/// Counts how many times a channel number has been closed, wrapping around.
+pub type Generation = u8;
+
+/// A wrapper over a `Packet` that reserves the first two bytes for the channel and its generation.
+#[derive(Debug)]
+pub struct DynPacket<P>(P);
+
+impl<P: Packet> Packet for DynPacket<P> {
+    fn capacity(&self) -> usize {
+        self.0.capacity().saturating_sub(HEADER_LEN)
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.0.resize(len + HEADER_LEN, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
+    }
+}
+
+impl<P: Packet> Deref for DynPacket<P> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[HEADER_LEN..]
+    }
+}
+
+impl<P: Packet> DerefMut for DynPacket<P> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[HEADER_LEN..]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DynPacketPool<P>(P);
+
+impl<P> DynPacketPool<P> {
+    pub fn new(packet_pool: P) -> Self {
+        DynPacketPool(packet_pool)
+    }
+}
+
+impl<P: PacketPool> PacketPool for DynPacketPool<P> {
+    type Packet = DynPacket<P::Packet>;
+
+    fn acquire(&self) -> DynPacket<P::Packet> {
+        let mut packet = self.0.acquire();
+        packet.resize(HEADER_LEN, 0);
+        DynPacket(packet)
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum IncomingError {
+    #[error("packet is too short for the dynamic channel header")]
+    TooShort,
+    #[error("packet received for a channel which is not open")]
+    UnknownChannel,
+    /// The packet was sent on a previous incarnation of the channel, before it was closed and
+    /// reopened.
+    #[error("packet received for a previous generation of its channel")]
+    StaleGeneration,
+    #[error("channel receiver has been dropped")]
+    ChannelReceiverDropped,
+}
+
+#[derive(Error)]
+pub enum IncomingTrySendError<P> {
+    #[error("packet channel is full")]
+    IsFull(P),
+    #[error(transparent)]
+    Error(#[from] IncomingError),
+}
+
+impl<P> fmt::Debug for IncomingTrySendError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncomingTrySendError::IsFull(_) => write!(f, "IncomingTrySendError::IsFull"),
+            IncomingTrySendError::Error(err) => f
+                .debug_tuple("IncomingTrySendError::Error")
+                .field(err)
+                .finish(),
+        }
+    }
+}
+
+impl<P> IncomingTrySendError<P> {
+    pub fn is_full(&self) -> bool {
+        matches!(self, IncomingTrySendError::IsFull(_))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("dynamic channel has been closed or disconnected")]
+pub struct ChannelClosed;
+
+#[derive(Debug, Error)]
+pub enum RecvError {
+    /// The channel was closed with `DynamicMultiplexer::close_channel`.
+    #[error("dynamic channel has been closed")]
+    Closed,
+    /// The peer closed the channel with `DynamicMultiplexer::close_channel`.
+    #[error("dynamic channel has been closed by the peer")]
+    ClosedByPeer,
+    /// The multiplexer has been dropped.
+    #[error("dynamic multiplexer has been disconnected")]
+    Disconnected,
+}
+
+#[derive(Debug, Error)]
+pub enum OpenError {
+    #[error(transparent)]
+    Duplicate(#[from] DuplicateChannel),
+    /// The peer did not open the channel in time, and the channel has been closed again.
+    #[error("timed out waiting for the peer to open the channel")]
+    TimedOut,
+}
+
+/// Routes packets between a single packet stream and a set of channels which, unlike those of a
+/// `PacketMultiplexer`, may be opened and closed at any time, with their numbers reused.
+///
+/// Every packet is tagged with its channel and the channel's generation, the number of times that
+/// channel number has been closed.  Once a channel is closed and reopened, late packets sent on
+/// its previous incarnation are dropped instead of being delivered to the new channel.  Both peers
+/// must open and close each channel number the same number of times for their generations to
+/// agree.
+///
+/// Packets which arrive for a channel the receiving side has not opened yet are dropped, so the
+/// side which opens a channel first should use `DynamicMultiplexer::open`, which waits for the
+/// peer to open the channel too before returning it.
+///
+/// This is usually run over a single channel of a `PacketMultiplexer`, with a task forwarding
+/// packets between the two.  The `DynamicMultiplexer` is a cheap handle, and clones open and
+/// close channels on the same multiplexer.
+pub struct DynamicMultiplexer<P> {
+    shared: Arc<Mutex<Shared<P>>>,
+    outgoing: Sender<P>,
+}
+
+impl<P> Clone for DynamicMultiplexer<P> {
+    fn clone(&self) -> Self {
+        DynamicMultiplexer {
+            shared: Arc::clone(&self.shared),
+            outgoing: self.outgoing.clone(),
+        }
+    }
+}
+
+struct Shared<P> {
+    channels: HashMap<PacketChannel, Channel<P>>,
+    generations: [Generation; 256],
+    acquire: Box<dyn Fn() -> P + Send>,
+    control: Sender<P>,
+}
+
+struct Channel<P> {
+    generation: Generation,
+    sender: Sender<DynPacket<P>>,
+    state: Arc<AtomicU8>,
+    // Whether the peer is known to have opened the channel, and the task waiting for it to.
+    confirmed: bool,
+    confirmed_waker: Option<Waker>,
+}
+
+impl<P: Packet> Shared<P> {
+    // Send a control packet, dropping it if the outgoing buffer is full.  Every control packet is
+    // either resent or a response to one which is.
+    fn send_control(&mut self, kind: u8, channel: PacketChannel, generation: Generation) {
+        let mut packet = (self.acquire)();
+        packet.extend(&[CONTROL_CHANNEL, kind, channel, generation]);
+        if self.control.try_send(packet).is_err() {
+            debug_event!(channel, kind, "dropping dynamic channel control packet");
+        }
+    }
+
+    // Close the given channel if it is open, returning the generation it had.
+    fn close(&mut self, channel: PacketChannel, state: u8) -> Option<Generation> {
+        let open = self.channels.remove(&channel)?;
+        open.state.store(state, Ordering::Relaxed);
+        let generation = &mut self.generations[channel as usize];
+        *generation = generation.wrapping_add(1);
+        debug_event!(
+            channel,
+            by_peer = state == CLOSED_BY_PEER,
+            "closed dynamic channel"
+        );
+        Some(open.generation)
+    }
+
+    fn confirm(&mut self, channel: PacketChannel) {
+        if let Some(open) = self.channels.get_mut(&channel) {
+            if !open.confirmed {
+                debug_event!(
+                    channel,
+                    generation = open.generation,
+                    "peer opened dynamic channel"
+                );
+                open.confirmed = true;
+                if let Some(waker) = open.confirmed_waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+impl<P> DynamicMultiplexer<P>
+where
+    P: Packet + Unpin,
+{
+    /// Create a multiplexer with no open channels, returning the handle to open channels with and
+    /// the `Sink` and `Stream` of packets to connect to the underlying packet stream.
+    ///
+    /// Control packets are acquired from the given pool, which should be the pool wrapped by the
+    /// `DynPacketPool` used for the channels.  The `buffer_size` is the buffer size of the MPSC
+    /// channel that every channel's outgoing packets are sent into.
+    pub fn new<Pl>(
+        packet_pool: Pl,
+        buffer_size: usize,
+    ) -> (Self, DynamicIncoming<P>, DynamicOutgoing<P>)
+    where
+        Pl: PacketPool<Packet = P> + Send + 'static,
+    {
+        let (outgoing, outgoing_receiver) = mpsc::channel(buffer_size);
+        let shared = Arc::new(Mutex::new(Shared {
+            channels: HashMap::new(),
+            generations: [0; 256],
+            acquire: Box::new(move || packet_pool.acquire()),
+            control: outgoing.clone(),
+        }));
+        (
+            DynamicMultiplexer {
+                shared: Arc::clone(&shared),
+                outgoing,
+            },
+            DynamicIncoming { shared },
+            DynamicOutgoing(outgoing_receiver),
+        )
+    }
+
+    /// Open a channel, producing a sender for outgoing packets on this channel and a receiver for
+    /// incoming packets on this channel, which may be used to construct any channel type.
+    ///
+    /// The `buffer_size` parameter controls the buffer size of the MPSC channel for incoming
+    /// packets.
+    ///
+    /// The channel is open as soon as this returns, but packets sent on it are dropped by the peer
+    /// until the peer has opened it too.  A peer waiting in `DynamicMultiplexer::open` returns
+    /// once the channel is opened here.
+    ///
+    /// # Panics
+    /// Panics if `channel` is the `CONTROL_CHANNEL`.
+    pub fn open_channel(
+        &self,
+        channel: PacketChannel,
+        buffer_size: usize,
+    ) -> Result<(DynSender<P>, DynReceiver<P>), DuplicateChannel> {
+        assert!(
+            channel != CONTROL_CHANNEL,
+            "the dynamic control channel cannot be opened"
+        );
+        let mut shared = self.shared.lock().unwrap();
+        if shared.channels.contains_key(&channel) {
+            return Err(DuplicateChannel);
+        }
+        let generation = shared.generations[channel as usize];
+        let (incoming_sender, incoming_receiver) = mpsc::channel(buffer_size);
+        let state = Arc::new(AtomicU8::new(OPEN));
+        shared.channels.insert(
+            channel,
+            Channel {
+                generation,
+                sender: incoming_sender,
+                state: Arc::clone(&state),
+                confirmed: false,
+                confirmed_waker: None,
+            },
+        );
+        debug_event!(channel, generation, "opened dynamic channel");
+
+        Ok((
+            DynSender {
+                shared: Arc::clone(&self.shared),
+                sender: self.outgoing.clone(),
+                channel,
+                generation,
+            },
+            DynReceiver {
+                receiver: incoming_receiver,
+                state,
+            },
+        ))
+    }
+
+    /// Open a channel like `DynamicMultiplexer::open_channel`, but only return it once the peer has
+    /// opened it too, so that the first packets sent on it are not dropped.
+    ///
+    /// The open request is resent every `OPEN_RESEND_INTERVAL`.  If the peer has not opened the
+    /// channel within `timeout`, the channel is closed again without starting a new generation and
+    /// this returns `OpenError::TimedOut`.
+    ///
+    /// This method is not cancel safe, canceling it leaves the channel open with no handles.
+    ///
+    /// # Panics
+    /// Panics if `channel` is the `CONTROL_CHANNEL`.
+    pub async fn open<T: Timer>(
+        &self,
+        timer: &T,
+        channel: PacketChannel,
+        buffer_size: usize,
+        timeout: Duration,
+    ) -> Result<(DynSender<P>, DynReceiver<P>), OpenError> {
+        let handles = self.open_channel(channel, buffer_size)?;
+        let generation = handles.0.generation;
+        let start = timer.now();
+
+        loop {
+            let elapsed = timer.elapsed(start);
+            if elapsed >= timeout {
+                break;
+            }
+            self.shared
+                .lock()
+                .unwrap()
+                .send_control(CONTROL_OPEN, channel, generation);
+
+            let confirmed = self.confirmed(channel);
+            let sleep = timer.sleep(OPEN_RESEND_INTERVAL.min(timeout - elapsed));
+            pin_mut!(confirmed, sleep);
+            if let Either::Left(_) = future::select(confirmed, sleep).await {
+                return Ok(handles);
+            }
+        }
+
+        debug_event!(channel, "timed out opening dynamic channel");
+        if let Some(open) = self.shared.lock().unwrap().channels.remove(&channel) {
+            open.state.store(CLOSED, Ordering::Relaxed);
+        }
+        Err(OpenError::TimedOut)
+    }
+
+    /// Returns true if the given channel is open and the peer is known to have opened it too,
+    /// either because it has been confirmed by `DynamicMultiplexer::open` or because a packet has
+    /// been received on it.
+    pub fn is_confirmed(&self, channel: PacketChannel) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared
+            .channels
+            .get(&channel)
+            .is_some_and(|open| open.confirmed)
+    }
+
+    // Resolves once the given channel is confirmed.
+    async fn confirmed(&self, channel: PacketChannel) {
+        poll_fn(|cx| {
+            let mut shared = self.shared.lock().unwrap();
+            match shared.channels.get_mut(&channel) {
+                Some(open) if !open.confirmed => {
+                    open.confirmed_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+                _ => Poll::Ready(()),
+            }
+        })
+        .await
+    }
+
+    /// Close an open channel, returning false if it was not open.
+    ///
+    /// The channel's receiver returns `RecvError::Closed` once its buffered packets are read, and
+    /// its sender errors with `ChannelClosed`.  Reopening the channel number starts its next
+    /// generation.
+    ///
+    /// The peer is sent a close notice, which closes its side of the channel too, so that its
+    /// receiver returns `RecvError::ClosedByPeer`.  If the notice is lost, it is sent again in
+    /// response to the next packet the peer sends on the channel.
+    pub fn close_channel(&self, channel: PacketChannel) -> bool {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.close(channel, CLOSED) {
+            Some(generation) => {
+                shared.send_control(CONTROL_CLOSE, channel, generation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns every open channel, sorted.
+    pub fn channels(&self) -> Vec<PacketChannel> {
+        let mut channels: Vec<_> = self
+            .shared
+            .lock()
+            .unwrap()
+            .channels
+            .keys()
+            .copied()
+            .collect();
+        channels.sort_unstable();
+        channels
+    }
+
+    /// The generation of the given channel number, which is the generation it has while open, or
+    /// will have once it is next opened.
+    pub fn generation(&self, channel: PacketChannel) -> Generation {
+        self.shared.lock().unwrap().generations[channel as usize]
+    }
+}
+
+/// A handle to push incoming packets into a `DynamicMultiplexer`.
+pub struct DynamicIncoming<P> {
+    shared: Arc<Mutex<Shared<P>>>,
+}
+
+impl<P> DynamicIncoming<P>
+where
+    P: Packet + Unpin,
+{
+    /// Attempt to send the given packet to the appropriate channel without blocking.
+    pub fn try_send(&mut self, packet: P) -> Result<(), IncomingTrySendError<P>> {
+        if packet.len() < HEADER_LEN {
+            return Err(IncomingError::TooShort.into());
+        }
+        let (channel, generation) = (packet[0], packet[1]);
+
+        let mut shared = self.shared.lock().unwrap();
+        if channel == CONTROL_CHANNEL {
+            if packet.len() != CONTROL_LEN {
+                return Err(IncomingError::TooShort.into());
+            }
+            recv_control(&mut shared, packet[1], packet[2], packet[3]);
+            return Ok(());
+        }
+
+        let open = match shared.channels.get_mut(&channel) {
+            Some(open) => open,
+            None => {
+                // The peer may have missed the notice that this side closed the channel.
+                let closed = shared.generations[channel as usize].wrapping_sub(1);
+                if generation == closed {
+                    shared.send_control(CONTROL_CLOSE, channel, closed);
+                }
+                return Err(IncomingError::UnknownChannel.into());
+            }
+        };
+        if generation != open.generation {
+            debug_event!(
+                channel,
+                generation,
+                "dropping packet for stale channel generation"
+            );
+            return Err(IncomingError::StaleGeneration.into());
+        }
+        let res = open.sender.try_send(DynPacket(packet)).map_err(|e| {
+            if e.is_full() {
+                IncomingTrySendError::IsFull(e.into_inner().0)
+            } else {
+                IncomingError::ChannelReceiverDropped.into()
+            }
+        });
+        // Any packet for the current generation shows that the peer has the channel open.
+        shared.confirm(channel);
+        res
+    }
+
+    /// Send the given packet to the appropriate channel, waiting while the channel's buffer is
+    /// full.
+    ///
+    /// This method is not cancel safe, canceling it drops the packet.
+    pub async fn send(&mut self, packet: P) -> Result<(), IncomingError> {
+        let mut packet = Some(packet);
+        poll_fn(|cx| loop {
+            match self.try_send(packet.take().unwrap()) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(IncomingTrySendError::Error(err)) => return Poll::Ready(Err(err)),
+                Err(IncomingTrySendError::IsFull(p)) => packet = Some(p),
+            }
+
+            // Wait for the channel to have room, then try again.
+            let channel = packet.as_ref().unwrap()[0];
+            let mut shared = self.shared.lock().unwrap();
+            match shared.channels.get_mut(&channel) {
+                Some(open) => {
+                    if open.sender.poll_ready(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+                None => return Poll::Ready(Err(IncomingError::UnknownChannel)),
+            }
+        })
+        .await
+    }
+}
+
+fn recv_control<P: Packet>(
+    shared: &mut Shared<P>,
+    kind: u8,
+    channel: PacketChannel,
+    generation: Generation,
+) {
+    let open = match shared.channels.get(&channel) {
+        Some(open) if open.generation == generation => open,
+        // Requests for channels which are not open here are ignored, and resent by the peer.
+        _ => return,
+    };
+    match kind {
+        CONTROL_OPEN => {
+            let generation = open.generation;
+            shared.confirm(channel);
+            shared.send_control(CONTROL_OPEN_ACK, channel, generation);
+        }
+        CONTROL_OPEN_ACK => shared.confirm(channel),
+        CONTROL_CLOSE => {
+            shared.close(channel, CLOSED_BY_PEER);
+        }
+        _ => {
+            debug_event!(kind, "dropping unknown dynamic channel control packet");
+        }
+    }
+}
+
+/// A `Stream` of the outgoing packets of every channel of a `DynamicMultiplexer`.
+pub struct DynamicOutgoing<P>(Receiver<P>);
+
+impl<P> Stream for DynamicOutgoing<P> {
+    type Item = P;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<P>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+/// The incoming packet `Stream` of a single `DynamicMultiplexer` channel, which ends when the
+/// channel is closed.
+pub struct DynReceiver<P> {
+    receiver: Receiver<DynPacket<P>>,
+    state: Arc<AtomicU8>,
+}
+
+impl<P> DynReceiver<P> {
+    /// Receive the next packet, or once every buffered packet has been received, the reason the
+    /// channel was closed.
+    pub async fn recv(&mut self) -> Result<DynPacket<P>, RecvError> {
+        match self.receiver.next().await {
+            Some(packet) => Ok(packet),
+            None => Err(match self.state.load(Ordering::Relaxed) {
+                CLOSED => RecvError::Closed,
+                CLOSED_BY_PEER => RecvError::ClosedByPeer,
+                _ => RecvError::Disconnected,
+            }),
+        }
+    }
+}
+
+impl<P> Stream for DynReceiver<P> {
+    type Item = DynPacket<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<DynPacket<P>>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+/// The outgoing packet `Sink` of a single `DynamicMultiplexer` channel, which tags every packet
+/// with the channel and its generation.
+pub struct DynSender<P> {
+    shared: Arc<Mutex<Shared<P>>>,
+    sender: Sender<P>,
+    channel: PacketChannel,
+    generation: Generation,
+}
+
+impl<P> DynSender<P> {
+    fn is_open(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared
+            .channels
+            .get(&self.channel)
+            .is_some_and(|open| open.generation == self.generation)
+    }
+}
+
+impl<P: Packet> Sink<DynPacket<P>> for DynSender<P> {
+    type Error = ChannelClosed;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), ChannelClosed>> {
+        if !self.is_open() {
+            return Poll::Ready(Err(ChannelClosed));
+        }
+        self.sender.poll_ready(cx).map_err(|_| ChannelClosed)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: DynPacket<P>) -> Result<(), ChannelClosed> {
+        let mut packet = item.0;
+        packet[0] = self.channel;
+        packet[1] = self.generation;
+        self.sender.start_send(packet).map_err(|_| ChannelClosed)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), ChannelClosed>> {
+        Pin::new(&mut self.sender)
+            .poll_flush(cx)
+            .map_err(|_| ChannelClosed)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), ChannelClosed>> {
+        Pin::new(&mut self.sender)
+            .poll_close(cx)
+            .map_err(|_| ChannelClosed)
+    }
+}
@@ -0,0 +1,670 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Key, Nonce, Tag,
+};
+use futures::{ready, Sink, Stream};
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketMetadata, PacketPool},
+    packet_multiplexer::PacketChannel,
+    replay_window::ReplayWindow,
+};
+
+/// The size of the sequence number header at the start of every encrypted packet.
+pub const HEADER_LEN: usize = 8;
+/// The size of the authentication tag at the end of every encrypted packet.
+pub const TAG_LEN: usize = 16;
+/// The total number of bytes encryption adds to every packet.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+
+/// Pre-established 256-bit ChaCha20-Poly1305 keys for each direction of a connection.
+///
+/// The `send` key of one side must be the `recv` key of the other.  The two keys must be different,
+/// since nonces are derived from per-direction sequence numbers and would otherwise repeat.
+#[derive(Clone)]
+pub struct Keys {
+    pub send: [u8; 32],
+    pub recv: [u8; 32],
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Keys { .. }")
+    }
+}
+
+/// A wrapper over a `Packet` that reserves space for the encryption header and tag.
+#[derive(Debug)]
+pub struct EncryptedPacket<P>(P);
+
+impl<P> Packet for EncryptedPacket<P>
+where
+    P: Packet,
+{
+    fn capacity(&self) -> usize {
+        self.0.capacity().saturating_sub(OVERHEAD)
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.0.resize(len + HEADER_LEN, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
+    }
+}
+
+impl<P> Deref for EncryptedPacket<P>
+where
+    P: Packet,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[HEADER_LEN..]
+    }
+}
+
+impl<P> DerefMut for EncryptedPacket<P>
+where
+    P: Packet,
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[HEADER_LEN..]
+    }
+}
+
+/// A packet pool for plaintext packets, which produces `EncryptedPacket`s with room to be
+/// encrypted in place.
+#[derive(Debug, Clone)]
+pub struct EncryptedPacketPool<P>(P);
+
+impl<P> EncryptedPacketPool<P> {
+    pub fn new(packet_pool: P) -> Self {
+        EncryptedPacketPool(packet_pool)
+    }
+}
+
+impl<P> PacketPool for EncryptedPacketPool<P>
+where
+    P: PacketPool,
+{
+    type Packet = EncryptedPacket<P::Packet>;
+
+    fn acquire(&self) -> EncryptedPacket<P::Packet> {
+        let mut packet = self.0.acquire();
+        packet.resize(HEADER_LEN, 0);
+        EncryptedPacket(packet)
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
+}
+
+impl<P> From<P> for EncryptedPacketPool<P> {
+    fn from(pool: P) -> EncryptedPacketPool<P> {
+        EncryptedPacketPool(pool)
+    }
+}
+
+/// Error returned when decrypting a packet, decryption errors are not fatal.
+#[derive(Debug, Error)]
+pub enum DecryptError {
+    #[error("packet is too short to be encrypted")]
+    TooShort,
+    #[error("packet failed authentication")]
+    Unauthenticated,
+    #[error("packet was encrypted with a key that has since been replaced")]
+    ExpiredKey,
+    #[error("packet has already been received")]
+    Replayed,
+    #[error("plaintext packet is too long to be received")]
+    TooLong,
+}
+
+/// Encrypts outgoing packets, each with a nonce derived from an incrementing sequence number.
+pub struct Encryptor {
+    keys: EpochKeys,
+    sequence: u64,
+}
+
+impl Encryptor {
+    pub fn new(key: &[u8; 32]) -> Encryptor {
+        Encryptor {
+            keys: EpochKeys::new(key, None),
+            sequence: 0,
+        }
+    }
+
+    /// Create an `Encryptor` which derives a new key every `rekey_interval` packets, so that a
+    /// compromised key cannot be used to decrypt earlier packets.  The remote `Decryptor` must be
+    /// created with the same interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is zero.
+    pub fn with_rekey_interval(key: &[u8; 32], rekey_interval: u64) -> Encryptor {
+        assert!(rekey_interval > 0, "rekey interval must be non-zero");
+        Encryptor {
+            keys: EpochKeys::new(key, Some(rekey_interval)),
+            sequence: 0,
+        }
+    }
+
+    /// The sequence number that will be used for the next packet.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Encrypt a packet in place, returning the underlying packet ready to be sent.
+    pub fn encrypt<P: Packet>(&mut self, packet: EncryptedPacket<P>) -> P {
+        let mut packet = packet.0;
+        let (sequence, tag) = self.seal(&[], &mut packet[HEADER_LEN..]);
+        LittleEndian::write_u64(&mut packet[0..HEADER_LEN], sequence);
+        packet.extend(&tag);
+        packet
+    }
+
+    // Encrypt a buffer in place with the next sequence number, authenticating the associated data
+    // along with it.
+    fn seal(&mut self, associated_data: &[u8], buffer: &mut [u8]) -> (u64, Tag) {
+        let sequence = self.sequence;
+        // The nonce of the last sequence number is used to derive the next key in
+        // `EpochKeys::next`, so it must never also encrypt a packet.
+        assert!(sequence < u64::MAX, "sequence numbers exhausted");
+        self.sequence += 1;
+
+        while self.keys.epoch < self.keys.epoch_of(sequence) {
+            self.keys = self.keys.next();
+        }
+
+        let tag = self
+            .keys
+            .cipher
+            .encrypt_in_place_detached(&nonce(sequence), associated_data, buffer)
+            .expect("packet too large to encrypt");
+        (sequence, tag)
+    }
+}
+
+/// Decrypts and authenticates incoming packets.
+///
+/// Packets which have already been received are rejected with `DecryptError::Replayed`, so that
+/// recorded packets cannot be re-injected, see `ReplayWindow`.
+pub struct Decryptor {
+    keys: EpochKeys,
+    previous: Option<ChaCha20Poly1305>,
+    replay_window: ReplayWindow,
+}
+
+impl Decryptor {
+    pub fn new(key: &[u8; 32]) -> Decryptor {
+        Decryptor {
+            keys: EpochKeys::new(key, None),
+            previous: None,
+            replay_window: ReplayWindow::new(),
+        }
+    }
+
+    /// Create a `Decryptor` for packets from an `Encryptor` with the same rekey interval.
+    ///
+    /// Keys are derived as needed up to `MAX_EPOCH_SKIP` rekey intervals ahead, and packets from
+    /// the previous interval can still be decrypted, so reordering around a rekey is tolerated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interval is zero.
+    pub fn with_rekey_interval(key: &[u8; 32], rekey_interval: u64) -> Decryptor {
+        assert!(rekey_interval > 0, "rekey interval must be non-zero");
+        Decryptor {
+            keys: EpochKeys::new(key, Some(rekey_interval)),
+            previous: None,
+            replay_window: ReplayWindow::new(),
+        }
+    }
+
+    /// Decrypt a packet in place, returning the plaintext packet and its sequence number.
+    pub fn decrypt<P: Packet>(
+        &mut self,
+        mut packet: P,
+    ) -> Result<(u64, EncryptedPacket<P>), DecryptError> {
+        if packet.len() < OVERHEAD {
+            return Err(DecryptError::TooShort);
+        }
+        let sequence = LittleEndian::read_u64(&packet[0..HEADER_LEN]);
+        let tag_start = packet.len() - TAG_LEN;
+        let tag = Tag::clone_from_slice(&packet[tag_start..]);
+        self.open(sequence, &[], &mut packet[HEADER_LEN..tag_start], &tag)?;
+        packet.resize(tag_start, 0);
+        Ok((sequence, EncryptedPacket(packet)))
+    }
+
+    // Decrypt and authenticate a buffer in place, along with its associated data.
+    fn open(
+        &mut self,
+        sequence: u64,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &Tag,
+    ) -> Result<(), DecryptError> {
+        if !self.replay_window.accepts(sequence) {
+            return Err(DecryptError::Replayed);
+        }
+        let epoch = self.keys.epoch_of(sequence);
+
+        let mut next = None;
+        let cipher = if epoch == self.keys.epoch {
+            &self.keys.cipher
+        } else if epoch + 1 == self.keys.epoch {
+            self.previous.as_ref().ok_or(DecryptError::ExpiredKey)?
+        } else if epoch > self.keys.epoch && epoch - self.keys.epoch <= MAX_EPOCH_SKIP {
+            let mut previous = None;
+            let mut keys = self.keys.next();
+            while keys.epoch < epoch {
+                let following = keys.next();
+                previous = Some(keys.cipher);
+                keys = following;
+            }
+            &next.insert((keys, previous)).0.cipher
+        } else if epoch > self.keys.epoch {
+            return Err(DecryptError::Unauthenticated);
+        } else {
+            return Err(DecryptError::ExpiredKey);
+        };
+
+        cipher
+            .decrypt_in_place_detached(&nonce(sequence), associated_data, buffer, tag)
+            .map_err(|_| DecryptError::Unauthenticated)?;
+
+        // Only move to a new key once a packet has been authenticated with it.
+        if let Some((next, previous)) = next {
+            let current = std::mem::replace(&mut self.keys, next);
+            self.previous = Some(previous.unwrap_or(current.cipher));
+        }
+        self.replay_window.insert(sequence);
+        Ok(())
+    }
+}
+
+/// The maximum number of rekey intervals a `Decryptor` will skip ahead when receiving a packet.
+pub const MAX_EPOCH_SKIP: u64 = 16;
+
+struct EpochKeys {
+    cipher: ChaCha20Poly1305,
+    epoch: u64,
+    rekey_interval: Option<u64>,
+}
+
+impl EpochKeys {
+    fn new(key: &[u8; 32], rekey_interval: Option<u64>) -> EpochKeys {
+        EpochKeys {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            epoch: 0,
+            rekey_interval,
+        }
+    }
+
+    fn epoch_of(&self, sequence: u64) -> u64 {
+        match self.rekey_interval {
+            Some(interval) => sequence / interval,
+            None => 0,
+        }
+    }
+
+    // Derives the next key the same way as the Noise protocol `REKEY` function, by encrypting
+    // zeros with the maximum nonce.
+    fn next(&self) -> EpochKeys {
+        let mut key = [0; 32];
+        // The tag is discarded, only the first 32 bytes of ciphertext are the new key.
+        let _ = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(u64::MAX), &[], &mut key);
+        EpochKeys {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            epoch: self.epoch + 1,
+            rekey_interval: self.rekey_interval,
+        }
+    }
+}
+
+/// Wrap a raw packet stream and sink so that packets are encrypted with ChaCha20-Poly1305.
+///
+/// The returned stream and sink carry `EncryptedPacket`s, and are meant to be given to a
+/// `PacketMultiplexer` (or channel) using an `EncryptedPacketPool`.  Incoming packets that fail
+/// authentication are dropped.
+pub fn encrypted<I, O>(
+    keys: &Keys,
+    incoming: I,
+    outgoing: O,
+) -> (DecryptStream<I>, EncryptSink<O>) {
+    (
+        DecryptStream {
+            decryptor: Decryptor::new(&keys.recv),
+            incoming,
+        },
+        EncryptSink {
+            encryptor: Encryptor::new(&keys.send),
+            outgoing,
+        },
+    )
+}
+
+/// A `Stream` of decrypted packets, created by `encrypted`.
+pub struct DecryptStream<I> {
+    decryptor: Decryptor,
+    incoming: I,
+}
+
+impl<I> DecryptStream<I> {
+    pub fn new(decryptor: Decryptor, incoming: I) -> Self {
+        DecryptStream {
+            decryptor,
+            incoming,
+        }
+    }
+}
+
+impl<I, P> Stream for DecryptStream<I>
+where
+    I: Stream<Item = P> + Unpin,
+    P: Packet,
+{
+    type Item = EncryptedPacket<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.incoming).poll_next(cx)) {
+                Some(packet) => {
+                    if let Ok((_, packet)) = self.decryptor.decrypt(packet) {
+                        return Poll::Ready(Some(packet));
+                    }
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// A `Sink` which encrypts packets, created by `encrypted`.
+pub struct EncryptSink<O> {
+    encryptor: Encryptor,
+    outgoing: O,
+}
+
+impl<O> EncryptSink<O> {
+    pub fn new(encryptor: Encryptor, outgoing: O) -> Self {
+        EncryptSink {
+            encryptor,
+            outgoing,
+        }
+    }
+}
+
+impl<O, P> Sink<EncryptedPacket<P>> for EncryptSink<O>
+where
+    O: Sink<P> + Unpin,
+    P: Packet,
+{
+    type Error = O::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: EncryptedPacket<P>) -> Result<(), Self::Error> {
+        let packet = self.encryptor.encrypt(packet);
+        Pin::new(&mut self.outgoing).start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
+
+/// The size of the channel and sequence number header at the start of every packet encrypted by a
+/// `ChannelEncryptor`.
+pub const CHANNEL_HEADER_LEN: usize = HEADER_LEN + 1;
+
+/// Encrypts the outgoing packets of a multiplexer per channel, each encrypted channel with its own
+/// `Encryptor`, and sends every other channel as plaintext.
+///
+/// Encrypting only the channels which need confidentiality, such as voice, saves the cost of
+/// encryption on the channels which do not, such as telemetry.  The channel of every packet is
+/// left in plaintext so that the remote knows which key to use, and is authenticated along with the
+/// rest of an encrypted packet.  Plaintext channels are not authenticated either, so anyone on the
+/// path can read, forge or replay their packets.
+///
+/// An encrypted packet is its channel, its sequence number, the ciphertext and the tag.  A
+/// plaintext packet is the multiplexed packet unchanged.
+pub struct ChannelEncryptor {
+    encryptors: FxHashMap<PacketChannel, Encryptor>,
+}
+
+impl ChannelEncryptor {
+    pub fn new(encryptors: impl IntoIterator<Item = (PacketChannel, Encryptor)>) -> Self {
+        ChannelEncryptor {
+            encryptors: encryptors.into_iter().collect(),
+        }
+    }
+
+    /// Whether packets on the given channel are encrypted.
+    pub fn is_encrypted(&self, channel: PacketChannel) -> bool {
+        self.encryptors.contains_key(&channel)
+    }
+
+    /// Encrypt a multiplexed packet in place if its channel is encrypted, returning the underlying
+    /// packet ready to be sent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the packet is empty, since a multiplexed packet always starts with its channel.
+    pub fn encrypt<P: Packet>(&mut self, packet: EncryptedPacket<P>) -> P {
+        let mut packet = packet.0;
+        let channel = *packet.get(HEADER_LEN).expect("packet has no channel");
+        match self.encryptors.get_mut(&channel) {
+            Some(encryptor) => {
+                // The channel moves to the front, and the sequence number fills the rest of the
+                // header up to the start of the payload.
+                let (sequence, tag) = encryptor.seal(&[channel], &mut packet[CHANNEL_HEADER_LEN..]);
+                packet[0] = channel;
+                LittleEndian::write_u64(&mut packet[1..CHANNEL_HEADER_LEN], sequence);
+                packet.extend(&tag);
+            }
+            None => {
+                let len = packet.len();
+                packet.copy_within(HEADER_LEN..len, 0);
+                packet.resize(len - HEADER_LEN, 0);
+            }
+        }
+        packet
+    }
+}
+
+/// Decrypts the incoming packets of a multiplexer encrypted by a `ChannelEncryptor`, each
+/// encrypted channel with its own `Decryptor`.
+///
+/// Packets on channels without a `Decryptor` are received as plaintext, so both sides must agree on
+/// which channels are encrypted.
+pub struct ChannelDecryptor {
+    decryptors: FxHashMap<PacketChannel, Decryptor>,
+}
+
+impl ChannelDecryptor {
+    pub fn new(decryptors: impl IntoIterator<Item = (PacketChannel, Decryptor)>) -> Self {
+        ChannelDecryptor {
+            decryptors: decryptors.into_iter().collect(),
+        }
+    }
+
+    /// Whether packets on the given channel are expected to be encrypted.
+    pub fn is_encrypted(&self, channel: PacketChannel) -> bool {
+        self.decryptors.contains_key(&channel)
+    }
+
+    /// Decrypt a packet in place if its channel is encrypted, returning its channel and the
+    /// multiplexed packet.
+    pub fn decrypt<P: Packet>(
+        &mut self,
+        mut packet: P,
+    ) -> Result<(PacketChannel, EncryptedPacket<P>), DecryptError> {
+        let channel = *packet.first().ok_or(DecryptError::TooShort)?;
+        match self.decryptors.get_mut(&channel) {
+            Some(decryptor) => {
+                if packet.len() < CHANNEL_HEADER_LEN + TAG_LEN {
+                    return Err(DecryptError::TooShort);
+                }
+                let sequence = LittleEndian::read_u64(&packet[1..CHANNEL_HEADER_LEN]);
+                let tag_start = packet.len() - TAG_LEN;
+                let tag = Tag::clone_from_slice(&packet[tag_start..]);
+                decryptor.open(
+                    sequence,
+                    &[channel],
+                    &mut packet[CHANNEL_HEADER_LEN..tag_start],
+                    &tag,
+                )?;
+                packet.resize(tag_start, 0);
+                packet[HEADER_LEN] = channel;
+            }
+            None => {
+                let len = packet.len();
+                if len + HEADER_LEN > packet.capacity() {
+                    return Err(DecryptError::TooLong);
+                }
+                packet.resize(len + HEADER_LEN, 0);
+                packet.copy_within(0..len, HEADER_LEN);
+            }
+        }
+        Ok((channel, EncryptedPacket(packet)))
+    }
+}
+
+/// Wrap a raw packet stream and sink so that the packets of the given channels are encrypted, each
+/// with its own keys, and the packets of every other channel are sent as plaintext.
+///
+/// Like `encrypted`, the returned stream and sink carry `EncryptedPacket`s and are meant to be given
+/// to a `PacketMultiplexer` using an `EncryptedPacketPool`, see `ChannelEncryptor`.  Incoming
+/// packets on encrypted channels that fail authentication are dropped.
+pub fn channel_encrypted<I, O>(
+    keys: impl IntoIterator<Item = (PacketChannel, Keys)>,
+    incoming: I,
+    outgoing: O,
+) -> (ChannelDecryptStream<I>, ChannelEncryptSink<O>) {
+    let keys: Vec<_> = keys.into_iter().collect();
+    (
+        ChannelDecryptStream {
+            decryptor: ChannelDecryptor::new(
+                keys.iter()
+                    .map(|(channel, keys)| (*channel, Decryptor::new(&keys.recv))),
+            ),
+            incoming,
+        },
+        ChannelEncryptSink {
+            encryptor: ChannelEncryptor::new(
+                keys.iter()
+                    .map(|(channel, keys)| (*channel, Encryptor::new(&keys.send))),
+            ),
+            outgoing,
+        },
+    )
+}
+
+/// A `Stream` of packets decrypted per channel, created by `channel_encrypted`.
+pub struct ChannelDecryptStream<I> {
+    decryptor: ChannelDecryptor,
+    incoming: I,
+}
+
+impl<I> ChannelDecryptStream<I> {
+    pub fn new(decryptor: ChannelDecryptor, incoming: I) -> Self {
+        ChannelDecryptStream {
+            decryptor,
+            incoming,
+        }
+    }
+}
+
+impl<I, P> Stream for ChannelDecryptStream<I>
+where
+    I: Stream<Item = P> + Unpin,
+    P: Packet,
+{
+    type Item = EncryptedPacket<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.incoming).poll_next(cx)) {
+                Some(packet) => {
+                    if let Ok((_, packet)) = self.decryptor.decrypt(packet) {
+                        return Poll::Ready(Some(packet));
+                    }
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// A `Sink` which encrypts packets per channel, created by `channel_encrypted`.
+pub struct ChannelEncryptSink<O> {
+    encryptor: ChannelEncryptor,
+    outgoing: O,
+}
+
+impl<O> ChannelEncryptSink<O> {
+    pub fn new(encryptor: ChannelEncryptor, outgoing: O) -> Self {
+        ChannelEncryptSink {
+            encryptor,
+            outgoing,
+        }
+    }
+}
+
+impl<O, P> Sink<EncryptedPacket<P>> for ChannelEncryptSink<O>
+where
+    O: Sink<P> + Unpin,
+    P: Packet,
+{
+    type Error = O::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: EncryptedPacket<P>) -> Result<(), Self::Error> {
+        let packet = self.encryptor.encrypt(packet);
+        Pin::new(&mut self.outgoing).start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
+
+fn nonce(sequence: u64) -> Nonce {
+    let mut nonce = Nonce::default();
+    LittleEndian::write_u64(&mut nonce[4..], sequence);
+    nonce
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+
+use bincode::Options as _;
+use rustc_hash::FxHashMap;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::decode_limits::{self, DecodeLimits};
+
+type Decoder<T> =
+    Box<dyn Fn(&[u8], Option<&DecodeLimits>) -> Result<T, bincode::Error> + Send + Sync>;
+
+/// A schema versioned envelope for the messages of a typed channel, so that a message type can
+/// change while peers using older versions of it can still connect.
+///
+/// When a typed channel is created with an envelope, every message is sent prefixed with the
+/// envelope's current version, and received messages are decoded according to the version they
+/// were sent with.  Decoders for older versions are registered with `Envelope::upgrade_from`, and
+/// upgrade the old message to the current type.  A message with a version that has no decoder
+/// fails to deserialize, and is skipped just like any other message that fails to deserialize.
+///
+/// Only receiving is versioned: messages are always sent with the current version, so a peer
+/// which must also send to older peers should keep sending with the older version until they are
+/// all upgraded.
+pub struct Envelope<T> {
+    version: u16,
+    decoders: FxHashMap<u16, Decoder<T>>,
+}
+
+impl<T> fmt::Debug for Envelope<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut versions: Vec<_> = self.decoders.keys().copied().collect();
+        versions.sort_unstable();
+        f.debug_struct("Envelope")
+            .field("version", &self.version)
+            .field("versions", &versions)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned + 'static> Envelope<T> {
+    /// Create an envelope which sends messages of type `T` as the given schema version.
+    pub fn new(version: u16) -> Self {
+        let mut decoders = FxHashMap::default();
+        decoders.insert(version, decoder(|msg: T| msg));
+        Envelope { version, decoders }
+    }
+}
+
+impl<T: 'static> Envelope<T> {
+    /// Accept messages sent with an older schema version, which are deserialized as type `Old`
+    /// and then converted to the current message type with `upgrade`.
+    ///
+    /// # Panics
+    /// Panics if `version` already has a decoder, including if it is the current version.
+    pub fn upgrade_from<Old, F>(mut self, version: u16, upgrade: F) -> Self
+    where
+        Old: DeserializeOwned,
+        F: Fn(Old) -> T + Send + Sync + 'static,
+    {
+        assert!(
+            self.decoders.insert(version, decoder(upgrade)).is_none(),
+            "schema version {} already has a decoder",
+            version
+        );
+        self
+    }
+}
+
+impl<T> Envelope<T> {
+    /// The current schema version, which all messages are sent with.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Whether messages with the given schema version can be decoded.
+    pub fn supports(&self, version: u16) -> bool {
+        self.decoders.contains_key(&version)
+    }
+
+    pub(crate) fn encode(&self, msg: &T) -> Result<(u16, Vec<u8>), bincode::Error>
+    where
+        T: Serialize,
+    {
+        Ok((self.version, bincode::options().serialize(msg)?))
+    }
+
+    pub(crate) fn decode(
+        &self,
+        version: u16,
+        payload: &[u8],
+        limits: Option<&DecodeLimits>,
+    ) -> Result<T, bincode::Error> {
+        match self.decoders.get(&version) {
+            Some(decoder) => decoder(payload, limits),
+            None => Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "unsupported message schema version {}",
+                version
+            )))),
+        }
+    }
+}
+
+fn decoder<Old, T, F>(upgrade: F) -> Decoder<T>
+where
+    Old: DeserializeOwned,
+    F: Fn(Old) -> T + Send + Sync + 'static,
+{
+    Box::new(move |payload, limits| {
+        let msg = decode_limits::bincode_deserialize(bincode::options(), payload, limits)?;
+        Ok(upgrade(msg))
+    })
+}
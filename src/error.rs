@@ -0,0 +1,467 @@
+use std::{any, error::Error as StdError, fmt};
+
+use crate::{
+    acked_channel, blob_transfer, compressed_bincode_channel,
+    message_channels::{ChannelTaskError, MessageChannelsDisconnected, TryAsyncMessageError},
+    packet_multiplexer::PacketChannel,
+    reliable_bincode_channel, reliable_channel, replication,
+    runtime::TaskFailed,
+    serde_channel, unreliable_bincode_channel, unreliable_channel,
+};
+
+#[cfg(feature = "prost")]
+use crate::prost_channel;
+#[cfg(feature = "rkyv")]
+use crate::rkyv_channel;
+
+/// The general category of an `Error`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// A packet stream or channel has been disconnected or has shut down.
+    Disconnected,
+    /// The remote endpoint sent data which violates a protocol, or which is otherwise malformed.
+    Protocol,
+    /// A message or chunk is larger than the configured or maximum possible length.
+    TooLarge,
+    /// A message could not be serialized or deserialized.
+    Serialization,
+    /// A background task panicked or was dropped before it completed.
+    TaskFailed,
+    /// Any other error.
+    Other,
+}
+
+/// A single error type that the error types of every channel and module convert into, so that
+/// application code can handle them all in one place.
+///
+/// The original error is kept as the `source` of this error, and can be recovered by downcasting
+/// with `Error::downcast_ref`.  Errors can optionally carry the packet channel and message type
+/// they happened on.
+pub struct Error {
+    kind: ErrorKind,
+    fatal: bool,
+    channel: Option<PacketChannel>,
+    message_type: Option<&'static str>,
+    source: Box<dyn StdError + Send + Sync>,
+}
+
+impl Error {
+    /// Wrap an arbitrary error.
+    pub fn new<E>(kind: ErrorKind, fatal: bool, source: E) -> Self
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        Error {
+            kind,
+            fatal,
+            channel: None,
+            message_type: None,
+            source: source.into(),
+        }
+    }
+
+    /// Add the packet channel the error happened on.
+    pub fn with_channel(mut self, channel: PacketChannel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Add the type of the message being sent or received when the error happened.
+    pub fn with_message_type<M: ?Sized>(mut self) -> Self {
+        self.message_type = Some(any::type_name::<M>());
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Whether the channel that returned this error is unusable.
+    ///
+    /// Errors which are only fatal when receiving, such as a `BincodeError` from a
+    /// `CompressedBincodeChannel`, are always considered fatal.
+    pub fn is_fatal(&self) -> bool {
+        self.fatal
+    }
+
+    pub fn channel(&self) -> Option<PacketChannel> {
+        self.channel
+    }
+
+    pub fn message_type(&self) -> Option<&'static str> {
+        self.message_type
+    }
+
+    /// Returns a reference to the original error if it is of type `E`.
+    pub fn downcast_ref<E: StdError + 'static>(&self) -> Option<&E> {
+        self.source.downcast_ref()
+    }
+
+    /// Returns the original error.
+    pub fn into_source(self) -> Box<dyn StdError + Send + Sync> {
+        self.source
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("kind", &self.kind)
+            .field("fatal", &self.fatal)
+            .field("channel", &self.channel)
+            .field("message_type", &self.message_type)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.channel, self.message_type) {
+            (Some(channel), Some(message_type)) => {
+                write!(f, "channel {} ({}): ", channel, message_type)?
+            }
+            (Some(channel), None) => write!(f, "channel {}: ", channel)?,
+            (None, Some(message_type)) => write!(f, "{}: ", message_type)?,
+            (None, None) => {}
+        }
+        write!(f, "{}", self.source)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+impl From<reliable_channel::Error> for Error {
+    fn from(err: reliable_channel::Error) -> Self {
+        let (kind, _) = classify_reliable(&err);
+        Error::new(kind, true, err)
+    }
+}
+
+impl From<reliable_channel::UrgentError> for Error {
+    fn from(err: reliable_channel::UrgentError) -> Self {
+        let (kind, fatal) = match &err {
+            reliable_channel::UrgentError::ReliableChannelError(err) => classify_reliable(err),
+            reliable_channel::UrgentError::TooLarge => (ErrorKind::TooLarge, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<reliable_bincode_channel::Error> for Error {
+    fn from(err: reliable_bincode_channel::Error) -> Self {
+        let (kind, fatal) = classify_reliable_bincode(&err);
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<compressed_bincode_channel::Error> for Error {
+    fn from(err: compressed_bincode_channel::Error) -> Self {
+        let (kind, _) = classify_compressed(&err);
+        Error::new(kind, true, err)
+    }
+}
+
+impl From<unreliable_channel::SendError> for Error {
+    fn from(err: unreliable_channel::SendError) -> Self {
+        let (kind, fatal) = classify_unreliable_send(&err);
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<unreliable_channel::RecvError> for Error {
+    fn from(err: unreliable_channel::RecvError) -> Self {
+        let (kind, fatal) = classify_unreliable_recv(&err);
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<acked_channel::SendError> for Error {
+    fn from(err: acked_channel::SendError) -> Self {
+        let (kind, fatal) = match &err {
+            acked_channel::SendError::Disconnected => (ErrorKind::Disconnected, true),
+            acked_channel::SendError::TooBig => (ErrorKind::TooLarge, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<acked_channel::RecvError> for Error {
+    fn from(err: acked_channel::RecvError) -> Self {
+        let (kind, fatal) = classify_acked_recv(&err);
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<replication::RecvError> for Error {
+    fn from(err: replication::RecvError) -> Self {
+        let (kind, fatal) = match &err {
+            replication::RecvError::AckedChannelError(err) => classify_acked_recv(err),
+            replication::RecvError::BadFormat => (ErrorKind::Protocol, false),
+            replication::RecvError::BincodeError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<unreliable_bincode_channel::SendError> for Error {
+    fn from(err: unreliable_bincode_channel::SendError) -> Self {
+        let (kind, fatal) = classify_unreliable_bincode_send(&err);
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<unreliable_bincode_channel::RecvError> for Error {
+    fn from(err: unreliable_bincode_channel::RecvError) -> Self {
+        let (kind, fatal) = classify_unreliable_bincode_recv(&err);
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl<E: StdError + Send + Sync + 'static> From<serde_channel::SendError<E>> for Error {
+    fn from(err: serde_channel::SendError<E>) -> Self {
+        let (kind, fatal) = match &err {
+            serde_channel::SendError::UnreliableChannelError(err) => classify_unreliable_send(err),
+            serde_channel::SendError::SerializationError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl<E: StdError + Send + Sync + 'static> From<serde_channel::RecvError<E>> for Error {
+    fn from(err: serde_channel::RecvError<E>) -> Self {
+        let (kind, fatal) = match &err {
+            serde_channel::RecvError::UnreliableChannelError(err) => classify_unreliable_recv(err),
+            serde_channel::RecvError::SerializationError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl<E: StdError + Send + Sync + 'static> From<serde_channel::Error<E>> for Error {
+    fn from(err: serde_channel::Error<E>) -> Self {
+        let (kind, fatal) = match &err {
+            serde_channel::Error::ReliableChannelError(err) => classify_reliable(err),
+            serde_channel::Error::PrefixTooLarge => (ErrorKind::TooLarge, true),
+            serde_channel::Error::SerializationError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+#[cfg(feature = "prost")]
+impl From<prost_channel::SendError> for Error {
+    fn from(err: prost_channel::SendError) -> Self {
+        let (kind, fatal) = match &err {
+            prost_channel::SendError::UnreliableChannelError(err) => classify_unreliable_send(err),
+            prost_channel::SendError::EncodeError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+#[cfg(feature = "prost")]
+impl From<prost_channel::RecvError> for Error {
+    fn from(err: prost_channel::RecvError) -> Self {
+        let (kind, fatal) = match &err {
+            prost_channel::RecvError::UnreliableChannelError(err) => classify_unreliable_recv(err),
+            prost_channel::RecvError::DecodeError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+#[cfg(feature = "prost")]
+impl From<prost_channel::Error> for Error {
+    fn from(err: prost_channel::Error) -> Self {
+        let (kind, fatal) = match &err {
+            prost_channel::Error::ReliableChannelError(err) => classify_reliable(err),
+            prost_channel::Error::PrefixTooLarge => (ErrorKind::TooLarge, true),
+            prost_channel::Error::EncodeError(_) | prost_channel::Error::DecodeError(_) => {
+                (ErrorKind::Serialization, false)
+            }
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl From<rkyv_channel::SendError> for Error {
+    fn from(err: rkyv_channel::SendError) -> Self {
+        let (kind, fatal) = match &err {
+            rkyv_channel::SendError::UnreliableChannelError(err) => classify_unreliable_send(err),
+            rkyv_channel::SendError::TooLarge => (ErrorKind::TooLarge, false),
+            rkyv_channel::SendError::RkyvError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl From<rkyv_channel::RecvError> for Error {
+    fn from(err: rkyv_channel::RecvError) -> Self {
+        let (kind, fatal) = match &err {
+            rkyv_channel::RecvError::UnreliableChannelError(err) => classify_unreliable_recv(err),
+            rkyv_channel::RecvError::RkyvError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl From<rkyv_channel::Error> for Error {
+    fn from(err: rkyv_channel::Error) -> Self {
+        let (kind, fatal) = match &err {
+            rkyv_channel::Error::ReliableChannelError(err) => classify_reliable(err),
+            rkyv_channel::Error::PrefixTooLarge => (ErrorKind::TooLarge, true),
+            rkyv_channel::Error::TooLarge => (ErrorKind::TooLarge, false),
+            rkyv_channel::Error::RkyvError(_) => (ErrorKind::Serialization, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<blob_transfer::Error> for Error {
+    fn from(err: blob_transfer::Error) -> Self {
+        let (kind, fatal) = match &err {
+            blob_transfer::Error::ReliableChannelError(err) => classify_reliable(err),
+            blob_transfer::Error::ProtocolError => (ErrorKind::Protocol, true),
+            blob_transfer::Error::BlobTooLarge => (ErrorKind::TooLarge, false),
+            blob_transfer::Error::HashMismatch => (ErrorKind::Protocol, false),
+            blob_transfer::Error::Rejected => (ErrorKind::Other, false),
+        };
+        Error::new(kind, fatal, err)
+    }
+}
+
+impl From<TaskFailed> for Error {
+    fn from(err: TaskFailed) -> Self {
+        Error::new(ErrorKind::TaskFailed, true, err)
+    }
+}
+
+impl From<MessageChannelsDisconnected> for Error {
+    fn from(err: MessageChannelsDisconnected) -> Self {
+        Error::new(ErrorKind::Disconnected, true, err)
+    }
+}
+
+impl From<TryAsyncMessageError> for Error {
+    fn from(err: TryAsyncMessageError) -> Self {
+        match err {
+            TryAsyncMessageError::Unregistered(err) => Error::new(ErrorKind::Other, false, err),
+            TryAsyncMessageError::Disconnected(err) => err.into(),
+        }
+    }
+}
+
+/// Converts the error of a message type's channel task, with the message type name as context.
+///
+/// The task's error is classified if it is one of the channel error types, and is always fatal.
+impl From<ChannelTaskError> for Error {
+    fn from(err: ChannelTaskError) -> Self {
+        let source = err.error;
+        let kind = if let Some(err) = source.downcast_ref() {
+            classify_reliable(err).0
+        } else if let Some(err) = source.downcast_ref() {
+            classify_reliable_bincode(err).0
+        } else if let Some(err) = source.downcast_ref() {
+            classify_compressed(err).0
+        } else if let Some(err) = source.downcast_ref() {
+            classify_unreliable_send(err).0
+        } else if let Some(err) = source.downcast_ref() {
+            classify_unreliable_recv(err).0
+        } else if let Some(err) = source.downcast_ref() {
+            classify_unreliable_bincode_send(err).0
+        } else if let Some(err) = source.downcast_ref() {
+            classify_unreliable_bincode_recv(err).0
+        } else if source.is::<TaskFailed>() {
+            ErrorKind::TaskFailed
+        } else {
+            ErrorKind::Other
+        };
+        Error {
+            kind,
+            fatal: true,
+            channel: None,
+            message_type: Some(err.type_name),
+            source,
+        }
+    }
+}
+
+fn classify_reliable(err: &reliable_channel::Error) -> (ErrorKind, bool) {
+    let kind = match err {
+        reliable_channel::Error::Disconnected | reliable_channel::Error::Shutdown => {
+            ErrorKind::Disconnected
+        }
+        reliable_channel::Error::ProtocolError => ErrorKind::Protocol,
+        reliable_channel::Error::TaskFailed(_) => ErrorKind::TaskFailed,
+    };
+    (kind, true)
+}
+
+fn classify_reliable_bincode(err: &reliable_bincode_channel::Error) -> (ErrorKind, bool) {
+    match err {
+        reliable_bincode_channel::Error::ReliableChannelError(err) => classify_reliable(err),
+        reliable_bincode_channel::Error::PrefixTooLarge => (ErrorKind::TooLarge, true),
+        reliable_bincode_channel::Error::MessageTooLarge(_) => (ErrorKind::TooLarge, false),
+        reliable_bincode_channel::Error::BincodeError(_) => (ErrorKind::Serialization, false),
+    }
+}
+
+fn classify_compressed(err: &compressed_bincode_channel::Error) -> (ErrorKind, bool) {
+    match err {
+        compressed_bincode_channel::Error::ReliableChannelError(err) => classify_reliable(err),
+        compressed_bincode_channel::Error::ChunkTooLarge => (ErrorKind::TooLarge, true),
+        compressed_bincode_channel::Error::SnapError(_) => (ErrorKind::Protocol, true),
+        compressed_bincode_channel::Error::BincodeError(_) => (ErrorKind::Serialization, true),
+    }
+}
+
+fn classify_acked_recv(err: &acked_channel::RecvError) -> (ErrorKind, bool) {
+    match err {
+        acked_channel::RecvError::Disconnected => (ErrorKind::Disconnected, true),
+        acked_channel::RecvError::BadFormat => (ErrorKind::Protocol, false),
+    }
+}
+
+fn classify_unreliable_send(err: &unreliable_channel::SendError) -> (ErrorKind, bool) {
+    match err {
+        unreliable_channel::SendError::Disconnected => (ErrorKind::Disconnected, true),
+        unreliable_channel::SendError::TooBig => (ErrorKind::TooLarge, false),
+    }
+}
+
+fn classify_unreliable_recv(err: &unreliable_channel::RecvError) -> (ErrorKind, bool) {
+    match err {
+        unreliable_channel::RecvError::Disconnected => (ErrorKind::Disconnected, true),
+        unreliable_channel::RecvError::BadFormat => (ErrorKind::Protocol, false),
+    }
+}
+
+fn classify_unreliable_bincode_send(
+    err: &unreliable_bincode_channel::SendError,
+) -> (ErrorKind, bool) {
+    match err {
+        unreliable_bincode_channel::SendError::UnreliableChannelError(err) => {
+            classify_unreliable_send(err)
+        }
+        unreliable_bincode_channel::SendError::BincodeError(_) => (ErrorKind::Serialization, false),
+    }
+}
+
+fn classify_unreliable_bincode_recv(
+    err: &unreliable_bincode_channel::RecvError,
+) -> (ErrorKind, bool) {
+    match err {
+        unreliable_bincode_channel::RecvError::UnreliableChannelError(err) => {
+            classify_unreliable_recv(err)
+        }
+        unreliable_bincode_channel::RecvError::BincodeError(_) => (ErrorKind::Serialization, false),
+    }
+}
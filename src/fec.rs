@@ -0,0 +1,497 @@
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{ready, Sink, Stream};
+use thiserror::Error;
+
+use crate::packet::{Packet, PacketMetadata, PacketPool};
+
+/// The size of the FEC header at the start of every packet: the group number as a u16, the index
+/// of the packet within its group, and the group size.
+pub const HEADER_LEN: usize = 4;
+/// The total number of bytes FEC reserves in every data packet.  Parity packets also carry the
+/// XOR of the data packet lengths, so data packets must leave room for it.
+pub const OVERHEAD: usize = HEADER_LEN + 2;
+/// The largest supported group size.
+pub const MAX_GROUP_SIZE: u8 = 64;
+
+// The number of recent groups the decoder keeps state for, packets from older groups are still
+// delivered but can no longer take part in recovery.
+const TRACKED_GROUPS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The number of data packets in each group, after which a parity packet is sent.
+    ///
+    /// Any single lost packet in a group can be recovered, at the cost of `1 / group_size` extra
+    /// packets.  Smaller groups recover from more loss, larger groups use less bandwidth.  Lost
+    /// packets are only recovered once the rest of their group has arrived, so larger groups also
+    /// delay recovery for longer.
+    pub group_size: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { group_size: 4 }
+    }
+}
+
+/// A wrapper over a `Packet` that reserves space for the FEC header.
+#[derive(Debug)]
+pub struct FecPacket<P>(P);
+
+impl<P> Packet for FecPacket<P>
+where
+    P: Packet,
+{
+    fn capacity(&self) -> usize {
+        self.0.capacity().saturating_sub(OVERHEAD)
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.0.resize(len + HEADER_LEN, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
+    }
+}
+
+impl<P> Deref for FecPacket<P>
+where
+    P: Packet,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[HEADER_LEN..]
+    }
+}
+
+impl<P> DerefMut for FecPacket<P>
+where
+    P: Packet,
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[HEADER_LEN..]
+    }
+}
+
+/// A packet pool which produces `FecPacket`s with room for the FEC header.
+#[derive(Debug, Clone)]
+pub struct FecPacketPool<P>(P);
+
+impl<P> FecPacketPool<P> {
+    pub fn new(packet_pool: P) -> Self {
+        FecPacketPool(packet_pool)
+    }
+}
+
+impl<P> PacketPool for FecPacketPool<P>
+where
+    P: PacketPool,
+{
+    type Packet = FecPacket<P::Packet>;
+
+    fn acquire(&self) -> FecPacket<P::Packet> {
+        FecPacket(header_packet(&self.0))
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
+}
+
+impl<P> From<P> for FecPacketPool<P> {
+    fn from(pool: P) -> FecPacketPool<P> {
+        FecPacketPool(pool)
+    }
+}
+
+/// Error returned when decoding a packet, decoding errors are not fatal.
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("incoming packet has bad FEC format")]
+    BadFormat,
+}
+
+/// Sends a parity packet after every group of data packets, the XOR of every packet in the group.
+pub struct FecEncoder {
+    group_size: u8,
+    group: u16,
+    index: u8,
+    parity: Vec<u8>,
+}
+
+impl FecEncoder {
+    /// # Panics
+    ///
+    /// Panics if the group size is zero or larger than `MAX_GROUP_SIZE`.
+    pub fn new(settings: &Settings) -> FecEncoder {
+        assert!(
+            settings.group_size > 0 && settings.group_size <= MAX_GROUP_SIZE,
+            "invalid FEC group size"
+        );
+        FecEncoder {
+            group_size: settings.group_size,
+            group: 0,
+            index: 0,
+            parity: Vec::new(),
+        }
+    }
+
+    /// Write the FEC header of a data packet, returning the underlying packet ready to be sent,
+    /// along with a parity packet to send after it if the packet completes a group.
+    pub fn encode<P: PacketPool>(
+        &mut self,
+        pool: &P,
+        packet: FecPacket<P::Packet>,
+    ) -> (P::Packet, Option<P::Packet>) {
+        let mut packet = packet.0;
+        write_header(&mut packet, self.group, self.index, self.group_size);
+        xor_into(&mut self.parity, &packet[HEADER_LEN..]);
+
+        self.index += 1;
+        if self.index < self.group_size {
+            return (packet, None);
+        }
+
+        let mut parity = header_packet(pool);
+        write_header(&mut parity, self.group, self.group_size, self.group_size);
+        parity.extend(&self.parity);
+        self.parity.clear();
+        self.group = self.group.wrapping_add(1);
+        self.index = 0;
+        (packet, Some(parity))
+    }
+}
+
+/// The result of decoding a single incoming packet.
+#[derive(Debug)]
+pub struct Decoded<P> {
+    /// The received packet, or `None` if it was a parity packet or a duplicate of a packet that
+    /// was already received or recovered.
+    pub packet: Option<FecPacket<P>>,
+    /// A lost packet that could be recovered using the received packet.
+    pub recovered: Option<FecPacket<P>>,
+}
+
+/// Reconstructs lost packets from the parity packets sent by a `FecEncoder`.
+pub struct FecDecoder {
+    groups: VecDeque<Group>,
+    recovered: u64,
+}
+
+struct Group {
+    group: u16,
+    size: u8,
+    // Bit `i` is set once data packet `i` has been received or recovered.
+    received: u64,
+    parity: Option<Vec<u8>>,
+    // The XOR of every received data packet, with their lengths.
+    accumulated: Vec<u8>,
+}
+
+impl Default for FecDecoder {
+    fn default() -> Self {
+        FecDecoder::new()
+    }
+}
+
+impl FecDecoder {
+    pub fn new() -> FecDecoder {
+        FecDecoder {
+            groups: VecDeque::new(),
+            recovered: 0,
+        }
+    }
+
+    /// The total number of lost packets that have been recovered.
+    pub fn recovered(&self) -> u64 {
+        self.recovered
+    }
+
+    /// Decode an incoming packet, the pool is used to allocate recovered packets.
+    pub fn decode<P: PacketPool>(
+        &mut self,
+        pool: &P,
+        packet: P::Packet,
+    ) -> Result<Decoded<P::Packet>, DecodeError> {
+        if packet.len() < HEADER_LEN {
+            return Err(DecodeError::BadFormat);
+        }
+        let group = LittleEndian::read_u16(&packet[0..2]);
+        let index = packet[2];
+        let size = packet[3];
+        if size == 0 || size > MAX_GROUP_SIZE || index > size {
+            return Err(DecodeError::BadFormat);
+        }
+
+        let state = match self.groups.iter_mut().position(|g| g.group == group) {
+            Some(i) => &mut self.groups[i],
+            None => {
+                if self.groups.len() == TRACKED_GROUPS {
+                    self.groups.pop_front();
+                }
+                self.groups.push_back(Group {
+                    group,
+                    size,
+                    received: 0,
+                    parity: None,
+                    accumulated: Vec::new(),
+                });
+                self.groups.back_mut().unwrap()
+            }
+        };
+        if state.size != size {
+            return Err(DecodeError::BadFormat);
+        }
+
+        let packet = if index == size {
+            if state.parity.is_some() {
+                return Ok(Decoded {
+                    packet: None,
+                    recovered: None,
+                });
+            }
+            state.parity = Some(packet[HEADER_LEN..].to_vec());
+            None
+        } else {
+            let bit = 1 << index;
+            if state.received & bit != 0 {
+                return Ok(Decoded {
+                    packet: None,
+                    recovered: None,
+                });
+            }
+            state.received |= bit;
+            xor_into(&mut state.accumulated, &packet[HEADER_LEN..]);
+            Some(FecPacket(packet))
+        };
+
+        let recovered = state.recover(pool);
+        if recovered.is_some() {
+            self.recovered += 1;
+        }
+        Ok(Decoded { packet, recovered })
+    }
+}
+
+impl Group {
+    fn recover<P: PacketPool>(&mut self, pool: &P) -> Option<FecPacket<P::Packet>> {
+        let parity = self.parity.as_ref()?;
+        let missing = !self.received & (u64::MAX >> (64 - self.size as u32));
+        if missing.count_ones() != 1 {
+            return None;
+        }
+        self.received |= missing;
+
+        // What remains after removing every received packet from the parity is the missing
+        // packet.
+        xor_bytes(&mut self.accumulated, 0, parity);
+        if self.accumulated.len() < 2 {
+            return None;
+        }
+        let len = LittleEndian::read_u16(&self.accumulated[0..2]) as usize;
+        let data = self.accumulated.get(2..2 + len)?;
+        let mut packet = header_packet(pool);
+        if packet.capacity() < HEADER_LEN + len {
+            return None;
+        }
+        write_header(
+            &mut packet,
+            self.group,
+            missing.trailing_zeros() as u8,
+            self.size,
+        );
+        packet.extend(data);
+        Some(FecPacket(packet))
+    }
+}
+
+/// Wrap a packet stream and sink with forward error correction, so that lost packets can be
+/// reconstructed by the receiver without waiting for a retransmission.
+///
+/// This is intended for unreliable traffic, such as a stream of state snapshots on a lossy link,
+/// placed between an `UnreliableChannel` created with a `FecPacketPool` and the multiplexer
+/// channel it would otherwise use.  The pool is the pool of the underlying packets, and is used to
+/// allocate parity and recovered packets.  Recovered packets may arrive out of order.
+pub fn fec<P, I, O>(
+    settings: &Settings,
+    pool: P,
+    incoming: I,
+    outgoing: O,
+) -> (FecStream<P, I>, FecSink<P, O>)
+where
+    P: PacketPool + Clone,
+{
+    (
+        FecStream::new(FecDecoder::new(), pool.clone(), incoming),
+        FecSink::new(FecEncoder::new(settings), pool, outgoing),
+    )
+}
+
+/// A `Stream` of received and recovered packets, created by `fec`.
+pub struct FecStream<P: PacketPool, I> {
+    decoder: FecDecoder,
+    pool: P,
+    incoming: I,
+    recovered: Option<FecPacket<P::Packet>>,
+}
+
+impl<P: PacketPool, I> FecStream<P, I> {
+    pub fn new(decoder: FecDecoder, pool: P, incoming: I) -> Self {
+        FecStream {
+            decoder,
+            pool,
+            incoming,
+            recovered: None,
+        }
+    }
+
+    pub fn decoder(&self) -> &FecDecoder {
+        &self.decoder
+    }
+}
+
+impl<P, I> Stream for FecStream<P, I>
+where
+    P: PacketPool + Unpin,
+    P::Packet: Unpin,
+    I: Stream<Item = P::Packet> + Unpin,
+{
+    type Item = FecPacket<P::Packet>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if let Some(packet) = this.recovered.take() {
+            return Poll::Ready(Some(packet));
+        }
+
+        loop {
+            match ready!(Pin::new(&mut this.incoming).poll_next(cx)) {
+                Some(packet) => {
+                    if let Ok(decoded) = this.decoder.decode(&this.pool, packet) {
+                        match (decoded.packet, decoded.recovered) {
+                            (Some(packet), recovered) => {
+                                this.recovered = recovered;
+                                return Poll::Ready(Some(packet));
+                            }
+                            (None, Some(recovered)) => return Poll::Ready(Some(recovered)),
+                            (None, None) => {}
+                        }
+                    }
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// A `Sink` which adds FEC headers and parity packets, created by `fec`.
+pub struct FecSink<P: PacketPool, O> {
+    encoder: FecEncoder,
+    pool: P,
+    outgoing: O,
+    parity: Option<P::Packet>,
+}
+
+impl<P: PacketPool, O> FecSink<P, O> {
+    pub fn new(encoder: FecEncoder, pool: P, outgoing: O) -> Self {
+        FecSink {
+            encoder,
+            pool,
+            outgoing,
+            parity: None,
+        }
+    }
+}
+
+impl<P, O> FecSink<P, O>
+where
+    P: PacketPool,
+    O: Sink<P::Packet> + Unpin,
+{
+    fn poll_send_parity(&mut self, cx: &mut Context) -> Poll<Result<(), O::Error>> {
+        if self.parity.is_some() {
+            ready!(Pin::new(&mut self.outgoing).poll_ready(cx))?;
+            let parity = self.parity.take().unwrap();
+            Pin::new(&mut self.outgoing).start_send(parity)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<P, O> Sink<FecPacket<P::Packet>> for FecSink<P, O>
+where
+    P: PacketPool + Unpin,
+    P::Packet: Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    type Error = O::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_send_parity(cx))?;
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(
+        mut self: Pin<&mut Self>,
+        packet: FecPacket<P::Packet>,
+    ) -> Result<(), Self::Error> {
+        let this = &mut *self;
+        let (packet, parity) = this.encoder.encode(&this.pool, packet);
+        this.parity = parity;
+        Pin::new(&mut this.outgoing).start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_send_parity(cx))?;
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_send_parity(cx))?;
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
+
+fn header_packet<P: PacketPool>(pool: &P) -> P::Packet {
+    let mut packet = pool.acquire();
+    packet.resize(HEADER_LEN, 0);
+    packet
+}
+
+fn write_header(packet: &mut [u8], group: u16, index: u8, size: u8) {
+    LittleEndian::write_u16(&mut packet[0..2], group);
+    packet[2] = index;
+    packet[3] = size;
+}
+
+// XORs the length of `data` followed by `data` itself into the accumulator, so that the length of
+// a recovered packet can be recovered along with its contents.
+fn xor_into(accumulated: &mut Vec<u8>, data: &[u8]) {
+    let mut len = [0; 2];
+    LittleEndian::write_u16(&mut len, data.len() as u16);
+    xor_bytes(accumulated, 0, &len);
+    xor_bytes(accumulated, 2, data);
+}
+
+fn xor_bytes(accumulated: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    if accumulated.len() < offset + data.len() {
+        accumulated.resize(offset + data.len(), 0);
+    }
+    for (a, d) in accumulated[offset..].iter_mut().zip(data) {
+        *a ^= d;
+    }
+}
@@ -0,0 +1,233 @@
+//! A harness for fuzzing and property testing the paths which handle data from remote peers.
+//!
+//! Each function here takes arbitrary input bytes, splits them into packets and feeds them to one
+//! layer of the protocol stack as though they arrived from a malicious peer.  The functions return
+//! nothing, any error is the expected outcome for garbage input, and the only failures are panics,
+//! hangs, or unbounded memory use.  They are meant to be called from `cargo fuzz` targets or from
+//! randomized tests, for example:
+//!
+//! ```ignore
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| turbulence::fuzz::message_channels(data));
+//! ```
+//!
+//! Input bytes are split into packets by `packets`, so that fuzzers can discover multi-packet
+//! sequences.  Everything runs on a `SimulationRuntime`, so runs are deterministic.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use futures::{channel::mpsc, FutureExt, StreamExt};
+
+use crate::{
+    buffer::{BufferPacketPool, BufferPool},
+    message_channels::{MessageChannelMode, MessageChannelSettings, MessageChannelsBuilder},
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{PacketChannel, PacketMultiplexer},
+    reliable_bincode_channel::{self, ReliableBincodeChannel},
+    reliable_channel::{self, ReliableChannel},
+    runtime::{Runtime, SimulationRuntime},
+    unreliable_bincode_channel::{RecvError, UnreliableBincodeChannel},
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+/// The message type decoded by the unreliable targets, which exercises most of the bincode data
+/// model.
+pub type UnreliableMessage = (u8, i64, String, Option<Vec<u16>>);
+
+/// The message type decoded by the reliable targets.
+pub type ReliableMessage = (Vec<u8>, BTreeMap<u32, String>, bool, f64);
+
+/// The size of every packet buffer used by the harness.
+pub const PACKET_LEN: usize = 512;
+
+/// The packet channels `multiplexer` and `message_channels` open, packets on any other channel
+/// should be rejected.
+pub const CHANNELS: [PacketChannel; 2] = [0, 1];
+
+const RELIABLE_SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+    bandwidth: 16384,
+    burst_bandwidth: 4096,
+    recv_window_size: 1024,
+    send_window_size: 1024,
+    init_send: 512,
+    resend_time: Duration::from_millis(50),
+    initial_rtt: Duration::from_millis(100),
+    max_rtt: Duration::from_secs(2),
+    rtt_update_factor: 0.1,
+    rtt_resend_factor: 1.5,
+};
+
+const UNRELIABLE_SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+    bandwidth: 16384,
+    burst_bandwidth: 4096,
+};
+
+const MAX_MESSAGE_LEN: u16 = 256;
+
+// How much virtual time passes between each packet.
+const PACKET_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Split fuzzer input into packets.
+///
+/// Each packet is a length byte followed by that many bytes, and a final packet that is shorter
+/// than its length byte is kept with whatever bytes remain.  Packets longer than `PACKET_LEN` are
+/// truncated.
+pub fn packets(mut data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        let (&len, rest) = data.split_first()?;
+        let len = (len as usize).min(rest.len());
+        let (packet, rest) = rest.split_at(len);
+        data = rest;
+        Some(&packet[..packet.len().min(PACKET_LEN)])
+    })
+}
+
+/// Feed packets, including their channel header, to a `PacketMultiplexer` with the `CHANNELS`
+/// open.
+pub fn multiplexer(data: &[u8]) {
+    let pool = BufferPacketPool::new(FuzzBufferPool);
+    let mut multiplexer = PacketMultiplexer::new();
+    let mut receivers = Vec::new();
+    for channel in CHANNELS {
+        let (_, receiver, _) = multiplexer.open_channel(channel, 4).unwrap();
+        receivers.push(receiver);
+    }
+    let (mut incoming, _outgoing) = multiplexer.start();
+
+    for (i, packet) in packets(data).enumerate() {
+        let _ = incoming.try_send(to_packet(&pool, packet));
+        // Only drain sometimes, so that full channel buffers are exercised too.
+        if i % 4 == 3 {
+            for receiver in &mut receivers {
+                while let Some(Some(_)) = receiver.next().now_or_never() {}
+            }
+        }
+    }
+}
+
+/// Feed packets, without a channel header, to an `UnreliableBincodeChannel` receiving
+/// `UnreliableMessage`.
+pub fn unreliable_channel(data: &[u8]) {
+    let runtime = SimulationRuntime::new();
+    let pool = BufferPacketPool::new(FuzzBufferPool);
+    let (mut incoming, incoming_recv) = mpsc::channel(1);
+    let (outgoing_send, _outgoing) = mpsc::channel(1);
+    let mut channel = UnreliableBincodeChannel::new(
+        UnreliableChannel::new(
+            runtime.handle(),
+            pool,
+            UNRELIABLE_SETTINGS,
+            incoming_recv,
+            outgoing_send,
+        ),
+        MAX_MESSAGE_LEN,
+    );
+
+    for packet in packets(data) {
+        let _ = incoming.try_send(to_packet(&pool, packet));
+        // Every message in the packet is available without waiting, so stop once receiving would
+        // wait for the next packet.
+        while let Some(res) = channel.recv::<UnreliableMessage>().now_or_never() {
+            if let Err(RecvError::UnreliableChannelError(
+                unreliable_channel::RecvError::Disconnected,
+            )) = res
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Feed packets, without a channel header, to a `ReliableChannel` read by a
+/// `ReliableBincodeChannel` receiving `ReliableMessage`, with virtual time passing between
+/// packets.
+pub fn reliable_channel(data: &[u8]) {
+    let mut runtime = SimulationRuntime::new();
+    let pool = BufferPacketPool::new(FuzzBufferPool);
+    let (mut incoming, incoming_recv) = mpsc::channel(4);
+    let (outgoing_send, outgoing) = mpsc::channel(4);
+    // Acknowledgements are discarded.
+    runtime.handle().spawn(outgoing.for_each(|_| async {}));
+
+    let mut channel = ReliableBincodeChannel::new(
+        ReliableChannel::new(
+            runtime.handle(),
+            pool,
+            RELIABLE_SETTINGS,
+            incoming_recv,
+            outgoing_send,
+        ),
+        MAX_MESSAGE_LEN,
+    );
+    runtime.handle().spawn(async move {
+        // Fatal errors end the channel, non-fatal ones skip the message.
+        while let Ok(_) | Err(reliable_bincode_channel::Error::BincodeError(_)) =
+            channel.recv::<ReliableMessage>().await
+        {}
+    });
+
+    for packet in packets(data) {
+        let _ = incoming.try_send(to_packet(&pool, packet));
+        runtime.run_for(PACKET_INTERVAL);
+    }
+    runtime.run_for(RELIABLE_SETTINGS.max_rtt);
+}
+
+/// Feed packets, including their channel header, to a `MessageChannels` with an unreliable
+/// `UnreliableMessage` channel and a reliable `ReliableMessage` channel, on the `CHANNELS`.
+pub fn message_channels(data: &[u8]) {
+    let mut runtime = SimulationRuntime::new();
+    let pool = BufferPacketPool::new(FuzzBufferPool);
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let mut builder = MessageChannelsBuilder::new(runtime.handle(), pool);
+    builder
+        .register::<UnreliableMessage>(MessageChannelSettings {
+            channel: CHANNELS[0],
+            channel_mode: MessageChannelMode::Unreliable {
+                settings: UNRELIABLE_SETTINGS,
+                max_message_len: MAX_MESSAGE_LEN,
+            },
+            message_buffer_size: 4,
+            packet_buffer_size: 4,
+        })
+        .unwrap();
+    builder
+        .register::<ReliableMessage>(MessageChannelSettings {
+            channel: CHANNELS[1],
+            channel_mode: MessageChannelMode::Reliable {
+                settings: RELIABLE_SETTINGS,
+                max_message_len: MAX_MESSAGE_LEN,
+            },
+            message_buffer_size: 4,
+            packet_buffer_size: 4,
+        })
+        .unwrap();
+    let mut channels = builder.build(&mut multiplexer);
+    let (mut incoming, outgoing) = multiplexer.start();
+    runtime.handle().spawn(outgoing.for_each(|_| async {}));
+
+    for packet in packets(data) {
+        let _ = incoming.try_send(to_packet(&pool, packet));
+        runtime.run_for(PACKET_INTERVAL);
+        while channels.recv::<UnreliableMessage>().is_some() {}
+        while channels.recv::<ReliableMessage>().is_some() {}
+    }
+    runtime.run_for(RELIABLE_SETTINGS.max_rtt);
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FuzzBufferPool;
+
+impl BufferPool for FuzzBufferPool {
+    type Buffer = Box<[u8]>;
+
+    fn acquire(&self) -> Self::Buffer {
+        vec![0; PACKET_LEN].into_boxed_slice()
+    }
+}
+
+fn to_packet<P: PacketPool>(pool: &P, data: &[u8]) -> P::Packet {
+    let mut packet = pool.acquire();
+    packet.extend(data);
+    packet
+}
@@ -0,0 +1,121 @@
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+};
+
+use bincode::Options;
+use serde::Serialize;
+
+use crate::scratch::ScratchArena;
+
+/// A zeroed byte buffer which starts at an initial length and grows on demand, never past a
+/// maximum length.
+///
+/// Used for the serialization buffers of bincode channels, so that channels which only ever carry
+/// small messages never allocate room for the largest message.  With a `ScratchArena`, the
+/// storage is borrowed from the arena when the buffer is grown, and returned to it by
+/// `GrowableBuffer::release`, after which the buffer is empty until it is grown again.
+pub(crate) struct GrowableBuffer {
+    buffer: Vec<u8>,
+    initial_len: usize,
+    max_len: usize,
+    arena: Option<ScratchArena>,
+}
+
+impl GrowableBuffer {
+    pub(crate) fn new(initial_len: usize, max_len: usize) -> Self {
+        let initial_len = initial_len.min(max_len);
+        GrowableBuffer {
+            buffer: vec![0; initial_len],
+            initial_len,
+            max_len,
+            arena: None,
+        }
+    }
+
+    /// Borrow storage from the given arena from now on, or own it again if `None`.  Any storage
+    /// currently owned is released.
+    pub(crate) fn set_arena(&mut self, arena: Option<ScratchArena>) {
+        ScratchArena::release(&arena, &mut self.buffer);
+        self.arena = arena;
+    }
+
+    pub(crate) fn arena(&self) -> Option<&ScratchArena> {
+        self.arena.as_ref()
+    }
+
+    /// Return the storage of the buffer to its arena, if it has one.
+    pub(crate) fn release(&mut self) {
+        ScratchArena::release(&self.arena, &mut self.buffer);
+    }
+
+    /// Grow the buffer to at least `len` bytes, up to the maximum length.  The buffer at least
+    /// doubles whenever it grows, so that repeatedly growing to fit slightly larger messages is
+    /// cheap.
+    pub(crate) fn reserve(&mut self, len: usize) {
+        if self.buffer.is_empty() {
+            ScratchArena::acquire(&self.arena, &mut self.buffer);
+            self.buffer.resize(self.initial_len, 0);
+        }
+        let len = len.min(self.max_len);
+        if self.buffer.len() < len {
+            let len = len.max(self.buffer.len() * 2).min(self.max_len);
+            self.buffer.resize(len, 0);
+        }
+    }
+
+    /// Serialize the message into the buffer starting at `offset`, growing the buffer as needed,
+    /// and returns the end of the serialized message.
+    ///
+    /// Running out of room before the maximum length is reached grows the buffer and serializes
+    /// the message again, so the configuration should limit the size of messages to fit.
+    pub(crate) fn serialize<O, T>(
+        &mut self,
+        offset: usize,
+        options: O,
+        msg: &T,
+    ) -> Result<usize, bincode::Error>
+    where
+        O: Options + Copy,
+        T: Serialize + ?Sized,
+    {
+        self.reserve(offset);
+        loop {
+            let mut w = &mut self.buffer[offset..];
+            match options.serialize_into(&mut w, msg) {
+                Ok(()) => {
+                    let remaining = w.len();
+                    return Ok(self.buffer.len() - remaining);
+                }
+                Err(err) if is_write_zero(&err) && self.buffer.len() < self.max_len => {
+                    self.reserve(self.buffer.len() + 1);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Drop for GrowableBuffer {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+impl Deref for GrowableBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for GrowableBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+fn is_write_zero(err: &bincode::Error) -> bool {
+    matches!(&**err, bincode::ErrorKind::Io(err) if err.kind() == io::ErrorKind::WriteZero)
+}
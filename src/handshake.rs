@@ -0,0 +1,278 @@
+use std::time::Duration;
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{
+    future::{self, Either},
+    pin_mut, Sink, SinkExt, Stream, StreamExt,
+};
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketPool, WIRE_VERSION},
+    packet_multiplexer::PacketChannel,
+    runtime::Timer,
+};
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The packet channel reserved for handshake packets, no multiplexed channel may be opened on
+    /// this channel.
+    ///
+    /// Handshake packets that arrive late, after the handshake has completed, are then dropped by
+    /// the multiplexer as packets for an unopened channel.
+    pub channel: PacketChannel,
+    /// The application protocol version, both sides must use the same version.
+    pub protocol_version: u32,
+    /// How often to resend our hello packet until the handshake completes.
+    pub resend_interval: Duration,
+    /// Give up on the handshake if it has not completed after this long.
+    pub timeout: Duration,
+    /// An opaque token, such as a connect token from `connect_token::TokenIssuer`, sent on the
+    /// handshake channel before every hello packet.
+    ///
+    /// Servers which require tokens only accept a client once it has sent a valid one, and since
+    /// the token is resent along with the hello, the handshake still succeeds if it is lost.
+    /// The other side ignores token packets as invalid hello packets.
+    pub connect_token: Option<Vec<u8>>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            channel: 255,
+            protocol_version: 0,
+            resend_interval: Duration::from_millis(250),
+            timeout: Duration::from_secs(10),
+            connect_token: None,
+        }
+    }
+}
+
+/// The result of a successful handshake.
+#[derive(Debug)]
+pub struct Established<P> {
+    /// The protocol version both sides agreed on.
+    pub protocol_version: u32,
+    /// The packet channels both sides agreed on, sorted.
+    pub channels: Vec<PacketChannel>,
+    /// The other side may finish the handshake first and start sending multiplexed packets, if
+    /// such a packet was received before the handshake finished it is returned here and should be
+    /// given to the multiplexer.
+    pub first_packet: Option<P>,
+}
+
+/// Error returned by `handshake`, all errors are fatal.
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    /// The other side uses an incompatible version of the `turbulence` wire format, see
+    /// `WIRE_VERSION`.
+    #[error("wire format version mismatch, local version is {local} remote version is {remote}")]
+    WireVersionMismatch { local: u16, remote: u16 },
+    #[error("protocol version mismatch, local version is {local} remote version is {remote}")]
+    VersionMismatch { local: u32, remote: u32 },
+    #[error("channel table mismatch, local channels are {local:?} remote channels are {remote:?}")]
+    ChannelMismatch {
+        local: Vec<PacketChannel>,
+        remote: Vec<PacketChannel>,
+    },
+    #[error("handshake timed out")]
+    TimedOut,
+    #[error("packet stream disconnected during handshake")]
+    Disconnected,
+}
+
+/// Perform a connection handshake over a raw packet stream and sink, before they are given to a
+/// `PacketMultiplexer`.
+///
+/// Both sides repeatedly send a hello packet carrying their wire format version, their protocol
+/// version and their channel table (see `PacketMultiplexer::channels`), and the handshake completes
+/// once each side knows the other has received its hello.  The handshake fails with
+/// `HandshakeError::WireVersionMismatch`, `HandshakeError::VersionMismatch` or
+/// `HandshakeError::ChannelMismatch` if the two sides are incompatible, rather than the two sides
+/// exchanging packets neither can understand.
+///
+/// The handshake is symmetric, both sides of a connection call this function in the same way.
+/// Packets on the handshake channel which are not valid hello packets are ignored.
+pub async fn handshake<T, P, I, O>(
+    timer: T,
+    pool: P,
+    settings: &Settings,
+    channels: &[PacketChannel],
+    incoming: &mut I,
+    outgoing: &mut O,
+) -> Result<Established<P::Packet>, HandshakeError>
+where
+    T: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    let mut channels = channels.to_vec();
+    channels.sort_unstable();
+    channels.dedup();
+
+    let start = timer.now();
+    let mut remote_seen = false;
+
+    loop {
+        if let Some(token) = &settings.connect_token {
+            let mut packet = pool.acquire();
+            packet.extend(&[settings.channel]);
+            packet.extend(token);
+            outgoing
+                .send(packet)
+                .await
+                .map_err(|_| HandshakeError::Disconnected)?;
+        }
+
+        let hello = Hello {
+            wire_version: WIRE_VERSION,
+            seen: remote_seen,
+            protocol_version: settings.protocol_version,
+            channels: &channels,
+        };
+        outgoing
+            .send(hello.write(&pool, settings.channel))
+            .await
+            .map_err(|_| HandshakeError::Disconnected)?;
+
+        let resend = timer.sleep(settings.resend_interval);
+        pin_mut!(resend);
+        loop {
+            let packet = match future::select(incoming.next(), resend.as_mut()).await {
+                Either::Left((Some(packet), _)) => packet,
+                Either::Left((None, _)) => return Err(HandshakeError::Disconnected),
+                Either::Right(((), _)) => break,
+            };
+
+            if packet.first() != Some(&settings.channel) {
+                // The other side only sends multiplexed packets once it has finished, which
+                // it can only do after receiving our hello.
+                if remote_seen {
+                    return Ok(Established {
+                        protocol_version: settings.protocol_version,
+                        channels,
+                        first_packet: Some(packet),
+                    });
+                }
+                continue;
+            }
+
+            let remote = match Hello::read(&packet[1..]) {
+                Some(remote) => remote,
+                None => continue,
+            };
+            if remote.wire_version != WIRE_VERSION {
+                return Err(HandshakeError::WireVersionMismatch {
+                    local: WIRE_VERSION,
+                    remote: remote.wire_version,
+                });
+            }
+            if remote.protocol_version != settings.protocol_version {
+                return Err(HandshakeError::VersionMismatch {
+                    local: settings.protocol_version,
+                    remote: remote.protocol_version,
+                });
+            }
+            if remote.channels != &channels[..] {
+                return Err(HandshakeError::ChannelMismatch {
+                    local: channels,
+                    remote: remote.channels.to_vec(),
+                });
+            }
+
+            if remote.seen {
+                if !remote_seen {
+                    // Let the other side know that we have seen its hello as well, if this is
+                    // lost the other side will finish once our first multiplexed packet arrives.
+                    let hello = Hello {
+                        wire_version: WIRE_VERSION,
+                        seen: true,
+                        protocol_version: settings.protocol_version,
+                        channels: &channels,
+                    };
+                    outgoing
+                        .send(hello.write(&pool, settings.channel))
+                        .await
+                        .map_err(|_| HandshakeError::Disconnected)?;
+                }
+                return Ok(Established {
+                    protocol_version: settings.protocol_version,
+                    channels,
+                    first_packet: None,
+                });
+            } else if !remote_seen {
+                remote_seen = true;
+                // Reply immediately rather than waiting for the next resend.
+                break;
+            }
+        }
+
+        if timer.elapsed(start) >= settings.timeout {
+            return Err(HandshakeError::TimedOut);
+        }
+    }
+}
+
+const MAGIC: &[u8; 4] = b"TRBH";
+
+struct Hello<'a> {
+    wire_version: u16,
+    seen: bool,
+    protocol_version: u32,
+    channels: &'a [PacketChannel],
+}
+
+impl<'a> Hello<'a> {
+    // Hello packets are the channel byte, followed by 4 magic bytes, the wire version as a u16, a
+    // byte which is 1 if we have seen the remote hello, the protocol version as a u32, and the
+    // channel table as a length prefixed list of channels.
+    //
+    // The wire version immediately follows the magic bytes, and must stay there in every future
+    // wire format so that mismatched peers can always be detected.
+    fn write<P: PacketPool>(&self, pool: &P, channel: PacketChannel) -> P::Packet {
+        let mut packet = pool.acquire();
+        packet.extend(&[channel]);
+        packet.extend(MAGIC);
+        let mut wire_version = [0; 2];
+        LittleEndian::write_u16(&mut wire_version, self.wire_version);
+        packet.extend(&wire_version);
+        packet.extend(&[self.seen as u8]);
+        let mut version = [0; 4];
+        LittleEndian::write_u32(&mut version, self.protocol_version);
+        packet.extend(&version);
+        packet.extend(&[self.channels.len() as u8]);
+        packet.extend(self.channels);
+        packet
+    }
+
+    fn read(data: &'a [u8]) -> Option<Hello<'a>> {
+        if data.len() < 6 || &data[0..4] != MAGIC {
+            return None;
+        }
+        let wire_version = LittleEndian::read_u16(&data[4..6]);
+        if wire_version != WIRE_VERSION {
+            // The rest of the hello may have any layout, only the wire version is known.
+            return Some(Hello {
+                wire_version,
+                seen: false,
+                protocol_version: 0,
+                channels: &[],
+            });
+        }
+        if data.len() < 12 || data[6] > 1 {
+            return None;
+        }
+        let channels = &data[12..];
+        if channels.len() != data[11] as usize {
+            return None;
+        }
+        Some(Hello {
+            wire_version,
+            seen: data[6] == 1,
+            protocol_version: LittleEndian::read_u32(&data[7..11]),
+            channels,
+        })
+    }
+}
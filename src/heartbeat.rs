@@ -0,0 +1,248 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{
+    future::{self, Either},
+    pin_mut, Sink, Stream, StreamExt,
+};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// How often to send a ping to the other side, must be nonzero.
+    pub interval: Duration,
+    /// The number of recent pings the RTT minimum and loss estimates are taken over.
+    pub samples: usize,
+    /// Pings which have not been answered after this long count as lost.
+    pub timeout: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: Duration::from_millis(500),
+            samples: 32,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Error returned by `Heartbeat::run`, all errors are fatal.
+#[derive(Debug, Error)]
+pub enum HeartbeatError {
+    #[error("unreliable channel send error: {0}")]
+    SendError(#[from] unreliable_channel::SendError),
+    #[error("unreliable channel receive error: {0}")]
+    RecvError(#[from] unreliable_channel::RecvError),
+}
+
+/// The latest connection quality estimates of a `Heartbeat`.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct HeartbeatStats {
+    /// The smoothed round trip time, if any ping has been answered yet.
+    pub rtt: Option<Duration>,
+    /// The lowest round trip time over the recent pings, which approximates the network delay
+    /// without any queueing.
+    pub min_rtt: Option<Duration>,
+    /// The variation in one-way delay of the remote's pings, estimated as in RFC 3550.
+    pub jitter: Duration,
+    /// The fraction of the recent pings past the timeout which were never answered, lost in either
+    /// direction.
+    pub loss: f64,
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+}
+
+/// A handle to the estimates of a `Heartbeat`, which are updated while it runs.
+#[derive(Debug, Clone, Default)]
+pub struct HeartbeatMonitor(Arc<Mutex<HeartbeatStats>>);
+
+impl HeartbeatMonitor {
+    pub fn stats(&self) -> HeartbeatStats {
+        *self.0.lock().unwrap()
+    }
+}
+
+const PING: u8 = 0;
+const PONG: u8 = 1;
+const MESSAGE_LEN: usize = 13;
+
+// Gain of the smoothed round trip time, as in TCP.
+const RTT_GAIN: f64 = 1. / 8.;
+// Gain of the jitter estimate, as in RFC 3550.
+const JITTER_GAIN: f64 = 1. / 16.;
+
+/// Measures round trip time, jitter and packet loss by exchanging pings, even while a connection
+/// has no other traffic.
+///
+/// Both sides run a `Heartbeat` over an unreliable channel dedicated to it, each answering the
+/// other's pings.  Every ping carries its send time on the sender's timeline, so the receiver can
+/// estimate the jitter of the one-way delay without synchronized clocks.
+pub struct Heartbeat<T: Timer> {
+    timer: T,
+    settings: Settings,
+    monitor: HeartbeatMonitor,
+}
+
+struct Sent {
+    seq: u32,
+    time: Duration,
+    answered: bool,
+}
+
+impl<T: Timer> Heartbeat<T> {
+    pub fn new(timer: T, settings: Settings) -> Self {
+        Heartbeat {
+            timer,
+            settings,
+            monitor: HeartbeatMonitor::default(),
+        }
+    }
+
+    /// A handle to read the current estimates, which are updated while `run` is running.
+    pub fn monitor(&self) -> HeartbeatMonitor {
+        self.monitor.clone()
+    }
+
+    /// Send pings and answer the other side's pings over the given channel, until the channel is
+    /// disconnected.
+    pub async fn run<R, P, I, O>(
+        self,
+        mut channel: UnreliableChannel<R, P, I, O>,
+    ) -> Result<(), HeartbeatError>
+    where
+        R: Timer,
+        P: PacketPool,
+        I: Stream<Item = P::Packet> + Unpin,
+        O: Sink<P::Packet> + Unpin,
+    {
+        let start = self.timer.now();
+        let local_time = || self.timer.elapsed(start);
+        let mut interval = self.timer.interval(self.settings.interval);
+        let max_samples = self.settings.samples.max(1);
+
+        let mut sent = VecDeque::<Sent>::new();
+        let mut rtt_samples = VecDeque::<Duration>::new();
+        let mut next_seq = 0u32;
+        // The transit time of the last ping received from the remote, as the difference between
+        // the two timelines, and its sequence number.
+        let mut last_transit: Option<(u32, f64)> = None;
+        let mut stats = HeartbeatStats::default();
+
+        loop {
+            let mut msg = [0; MESSAGE_LEN];
+            let received = {
+                let recv = channel.recv();
+                pin_mut!(recv);
+                match future::select(recv, interval.next()).await {
+                    Either::Left((received, _)) => {
+                        let received = received?;
+                        if received.len() == MESSAGE_LEN {
+                            msg.copy_from_slice(received);
+                            true
+                        } else {
+                            // Unknown messages are ignored.
+                            continue;
+                        }
+                    }
+                    Either::Right(_) => false,
+                }
+            };
+
+            let now = local_time();
+            let seq = LittleEndian::read_u32(&msg[1..5]);
+            let time = read_time(&msg[5..13]);
+            match (received, msg[0]) {
+                (false, _) => {
+                    let mut ping = [0; MESSAGE_LEN];
+                    ping[0] = PING;
+                    LittleEndian::write_u32(&mut ping[1..5], next_seq);
+                    write_time(&mut ping[5..13], now);
+                    channel.send(&ping).await?;
+                    channel.flush().await?;
+
+                    sent.push_back(Sent {
+                        seq: next_seq,
+                        time: now,
+                        answered: false,
+                    });
+                    if sent.len() > max_samples {
+                        sent.pop_front();
+                    }
+                    next_seq = next_seq.wrapping_add(1);
+                    stats.pings_sent += 1;
+                }
+                (true, PING) => {
+                    let transit = now.as_secs_f64() - time.as_secs_f64();
+                    match last_transit {
+                        Some((last_seq, _)) if seq.wrapping_sub(last_seq) >= 1 << 31 => {}
+                        Some((_, last)) => {
+                            let jitter = stats.jitter.as_secs_f64();
+                            let jitter = jitter + ((transit - last).abs() - jitter) * JITTER_GAIN;
+                            stats.jitter = Duration::from_secs_f64(jitter);
+                            last_transit = Some((seq, transit));
+                        }
+                        None => last_transit = Some((seq, transit)),
+                    }
+
+                    let mut pong = msg;
+                    pong[0] = PONG;
+                    channel.send(&pong).await?;
+                    channel.flush().await?;
+                }
+                (true, PONG) => {
+                    let ping = match sent.iter_mut().find(|sent| sent.seq == seq) {
+                        Some(ping) if !ping.answered => ping,
+                        _ => continue,
+                    };
+                    ping.answered = true;
+                    stats.pongs_received += 1;
+
+                    let rtt = now.saturating_sub(ping.time);
+                    stats.rtt = Some(match stats.rtt {
+                        Some(srtt) => srtt.mul_f64(1. - RTT_GAIN) + rtt.mul_f64(RTT_GAIN),
+                        None => rtt,
+                    });
+                    rtt_samples.push_back(rtt);
+                    if rtt_samples.len() > max_samples {
+                        rtt_samples.pop_front();
+                    }
+                    stats.min_rtt = rtt_samples.iter().min().copied();
+                }
+                (true, _) => continue,
+            }
+
+            let timed_out = sent
+                .iter()
+                .filter(|sent| now.saturating_sub(sent.time) >= self.settings.timeout);
+            let (count, lost) = timed_out.fold((0, 0), |(count, lost), sent| {
+                (count + 1, lost + !sent.answered as usize)
+            });
+            stats.loss = if count == 0 {
+                0.
+            } else {
+                lost as f64 / count as f64
+            };
+            *self.monitor.0.lock().unwrap() = stats;
+        }
+    }
+}
+
+fn write_time(buf: &mut [u8], time: Duration) {
+    LittleEndian::write_u64(buf, time.as_micros() as u64);
+}
+
+fn read_time(buf: &[u8]) -> Duration {
+    Duration::from_micros(LittleEndian::read_u64(buf))
+}
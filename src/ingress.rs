@@ -0,0 +1,137 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::Sink;
+
+use crate::{
+    bandwidth_limiter::BandwidthLimiter,
+    packet::Packet,
+    packet_multiplexer::{DropReason, PacketObserver},
+    runtime::Timer,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The sustained number of incoming packets admitted per second.
+    pub packets_per_sec: u32,
+    /// The number of packets which may arrive at once above the sustained rate.
+    pub burst_packets: u32,
+    /// The sustained number of incoming bytes admitted per second.
+    pub bytes_per_sec: u32,
+    /// The number of bytes which may arrive at once above the sustained rate.
+    pub burst_bytes: u32,
+}
+
+/// Limits the rate of incoming packets of a single connection, in front of a `PacketMultiplexer`,
+/// so that a misbehaving or malicious peer flooding the connection cannot overwhelm the server.
+///
+/// Wraps the `Sink` of incoming packets, such as an `IncomingMultiplexedPackets`.  Packets which
+/// arrive while either the packet rate or the byte rate is exhausted are dropped without being
+/// forwarded, and reported to the observer, if any, with `DropReason::RateLimited`.  As with
+/// bandwidth limiting of outgoing packets, a packet is admitted whenever there is any byte credit
+/// left, so a burst may exceed the byte limit by at most one packet.
+pub struct IngressLimiter<S, R: Timer> {
+    incoming: S,
+    settings: Settings,
+    packets: BandwidthLimiter<R>,
+    bytes: BandwidthLimiter<R>,
+    observer: Option<Arc<dyn PacketObserver>>,
+    dropped: u64,
+    dropped_bytes: u64,
+}
+
+impl<S, R: Timer> IngressLimiter<S, R> {
+    pub fn new(runtime: R, settings: Settings, incoming: S) -> Self {
+        IngressLimiter {
+            packets: BandwidthLimiter::new(
+                runtime.clone(),
+                settings.packets_per_sec,
+                settings.burst_packets,
+            ),
+            bytes: BandwidthLimiter::new(runtime, settings.bytes_per_sec, settings.burst_bytes),
+            incoming,
+            settings,
+            observer: None,
+            dropped: 0,
+            dropped_bytes: 0,
+        }
+    }
+
+    /// Install an observer, which is called for every packet dropped by the limiter.  Replaces
+    /// any previously installed observer.
+    pub fn set_observer<O: PacketObserver + 'static>(&mut self, observer: O) {
+        self.observer = Some(Arc::new(observer));
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// The number of packets dropped for exceeding the rate limit.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The total length of the packets dropped for exceeding the rate limit.
+    pub fn dropped_bytes(&self) -> u64 {
+        self.dropped_bytes
+    }
+
+    pub fn into_inner(self) -> S {
+        self.incoming
+    }
+
+    // Take credit for a packet of the given length, returning false if it should be dropped.
+    fn admit(&mut self, len: usize) -> bool {
+        self.packets.update_available();
+        self.bytes.update_available();
+        if !self.packets.has_bytes(1) || !self.bytes.bytes_available() {
+            return false;
+        }
+        self.packets.take_bytes(1);
+        self.bytes.take_bytes(len as u32);
+        true
+    }
+}
+
+impl<S, R, P> Sink<P> for IngressLimiter<S, R>
+where
+    S: Sink<P> + Unpin,
+    R: Timer,
+    P: Packet,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.incoming).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: P) -> Result<(), Self::Error> {
+        if !self.admit(packet.len()) {
+            self.dropped += 1;
+            self.dropped_bytes += packet.len() as u64;
+            debug_event!(
+                channel = packet.first().copied(),
+                len = packet.len(),
+                "dropped incoming packet over the ingress rate limit"
+            );
+            if let (Some(observer), Some(&channel)) = (&self.observer, packet.first()) {
+                observer.on_packet_dropped(channel, packet.len() - 1, DropReason::RateLimited);
+            }
+            return Ok(());
+        }
+        Pin::new(&mut self.incoming).start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.incoming).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.incoming).poll_close(cx)
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::VecDeque, convert::TryInto};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{Sink, Stream};
+
+use crate::{
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{
+        DefaultIncoming, DefaultOutgoing, RecvError, SendError, UnreliableChannel,
+    },
+};
+
+/// Numbers the inputs sent on an `InputChannel`, counting up from zero.
+pub type Frame = u32;
+
+// The frame of the oldest input in the message and the number of inputs.
+const HEADER_LEN: usize = 5;
+
+/// Sends a stream of per-frame player inputs, usually from a client to the server, with the last
+/// few inputs repeated in every message so that an input is only lost if every message carrying it
+/// is.
+///
+/// The receiving side gets each input exactly once and in frame order, ready to be fed to a
+/// rollback or prediction loop.  Inputs which arrive after a newer input has already been received
+/// are dropped, as are duplicates, and inputs which were lost from every message are skipped and
+/// counted by `InputChannel::missed`.
+///
+/// Each side may send inputs independently.
+pub struct InputChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableChannel<R, P, I, O>,
+    redundancy: usize,
+    next_frame: Frame,
+    history: VecDeque<Vec<u8>>,
+    buffer: Vec<u8>,
+    next_recv: Frame,
+    received: VecDeque<(Frame, Vec<u8>)>,
+    current: Vec<u8>,
+    missed: u64,
+}
+
+impl<R, P, I, O> InputChannel<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    /// Create an input channel which sends each input in `redundancy` consecutive messages.
+    ///
+    /// # Panics
+    /// Panics if `redundancy` is zero.
+    pub fn new(channel: UnreliableChannel<R, P, I, O>, redundancy: u8) -> Self {
+        assert!(redundancy > 0, "input redundancy must be at least 1");
+        InputChannel {
+            channel,
+            redundancy: redundancy as usize,
+            next_frame: 0,
+            history: VecDeque::new(),
+            buffer: Vec::new(),
+            next_recv: 0,
+            received: VecDeque::new(),
+            current: Vec::new(),
+            missed: 0,
+        }
+    }
+
+    /// The total number of inputs from the other side which were lost from every message that
+    /// carried them.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// Write the input for the next frame to the channel, returning its frame.  The message sent
+    /// also carries the previous inputs, up to the channel's redundancy.
+    ///
+    /// Each input must fit in a message `redundancy` times over, along with two bytes of length
+    /// each and a five byte header, or this returns `SendError::TooBig` and the input is not sent.
+    /// Just like the underlying channel, in order to guarantee that the input is actually sent you
+    /// must call `flush`.
+    ///
+    /// This method is cancel safe, though canceling it may or may not send the input.
+    pub async fn send(&mut self, input: &[u8]) -> Result<Frame, SendError> {
+        let input_len: u16 = input.len().try_into().map_err(|_| SendError::TooBig)?;
+        let frame = self.next_frame;
+        let count = self.history.len().min(self.redundancy - 1) + 1;
+        let first = frame - (count as Frame - 1);
+
+        self.buffer.clear();
+        let mut header = [0; HEADER_LEN];
+        LittleEndian::write_u32(&mut header[0..4], first);
+        header[4] = count as u8;
+        self.buffer.extend_from_slice(&header);
+        let previous = self.history.iter().skip(self.history.len() + 1 - count);
+        for prev in previous {
+            write_input(&mut self.buffer, prev.len() as u16, prev);
+        }
+        write_input(&mut self.buffer, input_len, input);
+        self.channel.send(&self.buffer).await?;
+
+        self.next_frame = frame + 1;
+        if self.redundancy > 1 {
+            if self.history.len() == self.redundancy - 1 {
+                self.history.pop_front();
+            }
+            self.history.push_back(input.to_vec());
+        }
+        Ok(frame)
+    }
+
+    /// Finish sending any unsent coalesced packets.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        self.channel.flush().await
+    }
+
+    /// Receive the next input from the other side, along with its frame.
+    ///
+    /// This method is cancel safe, it will never drop a received input.
+    pub async fn recv(&mut self) -> Result<(Frame, &[u8]), RecvError> {
+        loop {
+            if let Some((frame, input)) = self.received.pop_front() {
+                self.current = input;
+                return Ok((frame, &self.current));
+            }
+
+            let msg = self.channel.recv().await?;
+            if msg.len() < HEADER_LEN {
+                return Err(RecvError::BadFormat);
+            }
+            let first = LittleEndian::read_u32(&msg[0..4]);
+            let count = msg[4] as usize;
+
+            let mut inputs = Vec::with_capacity(count);
+            let mut pos = HEADER_LEN;
+            for _ in 0..count {
+                let len = msg.get(pos..pos + 2).ok_or(RecvError::BadFormat)?;
+                let len = LittleEndian::read_u16(len) as usize;
+                inputs.push(
+                    msg.get(pos + 2..pos + 2 + len)
+                        .ok_or(RecvError::BadFormat)?,
+                );
+                pos += 2 + len;
+            }
+            if pos != msg.len() {
+                return Err(RecvError::BadFormat);
+            }
+
+            for (i, input) in inputs.into_iter().enumerate() {
+                let frame = match first.checked_add(i as Frame) {
+                    Some(frame) => frame,
+                    None => return Err(RecvError::BadFormat),
+                };
+                if frame < self.next_recv {
+                    continue;
+                }
+                if frame > self.next_recv {
+                    debug_event!(
+                        missed = frame - self.next_recv,
+                        "inputs lost from every message"
+                    );
+                    self.missed += (frame - self.next_recv) as u64;
+                }
+                self.received.push_back((frame, input.to_vec()));
+                self.next_recv = frame + 1;
+            }
+        }
+    }
+}
+
+fn write_input(buffer: &mut Vec<u8>, len: u16, input: &[u8]) {
+    let mut bytes = [0; 2];
+    LittleEndian::write_u16(&mut bytes, len);
+    buffer.extend_from_slice(&bytes);
+    buffer.extend_from_slice(input);
+}
@@ -0,0 +1,95 @@
+use std::any::{Any, TypeId};
+
+use rustc_hash::FxHashMap;
+
+use crate::message_channels::{ChannelMessage, MessageChannels};
+
+type Predicate<K> = Box<dyn Fn(&K, &dyn Any) -> bool + Send + Sync>;
+
+/// The outcome of `InterestFilter::broadcast`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Broadcast {
+    /// The number of peers the message was sent to.
+    pub sent: usize,
+    /// The number of peers the message was not relevant to.
+    pub filtered: usize,
+    /// The number of peers the message was relevant to, but whose outgoing message buffer was
+    /// full, so the message was dropped.
+    pub dropped: usize,
+}
+
+/// Per message type predicates that decide whether a message is relevant to a peer, for doing
+/// interest management (such as filtering by distance or team visibility) in the send path of a
+/// server which broadcasts to many connections.
+///
+/// Each peer is described by some per-peer state `K`, such as the position and team of the
+/// peer's player, which is passed to the predicate along with the message.  Message types without
+/// a predicate are relevant to every peer.
+pub struct InterestFilter<K> {
+    predicates: FxHashMap<TypeId, Predicate<K>>,
+}
+
+impl<K> Default for InterestFilter<K> {
+    fn default() -> Self {
+        InterestFilter {
+            predicates: FxHashMap::default(),
+        }
+    }
+}
+
+impl<K: 'static> InterestFilter<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the predicate for messages of type `M`, replacing any previous predicate.
+    pub fn set<M, F>(&mut self, predicate: F)
+    where
+        M: ChannelMessage,
+        F: Fn(&K, &M) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.insert(
+            TypeId::of::<M>(),
+            Box::new(move |peer, message| predicate(peer, message.downcast_ref().unwrap())),
+        );
+    }
+
+    /// Remove the predicate for messages of type `M`, making them relevant to every peer.
+    pub fn remove<M: ChannelMessage>(&mut self) {
+        self.predicates.remove(&TypeId::of::<M>());
+    }
+
+    /// Whether the given message is relevant to the given peer.
+    pub fn is_relevant<M: ChannelMessage>(&self, peer: &K, message: &M) -> bool {
+        match self.predicates.get(&TypeId::of::<M>()) {
+            Some(predicate) => predicate(peer, message),
+            None => true,
+        }
+    }
+
+    /// Send a copy of the given message to every peer it is relevant to.
+    ///
+    /// Just like `MessageChannels::send`, `MessageChannels::flush` must be called on each peer's
+    /// channels to ensure delivery.  If a peer's outgoing message buffer is full, the message is
+    /// dropped for that peer.
+    ///
+    /// # Panics
+    /// Panics if the message type is not registered on a peer it is relevant to.
+    pub fn broadcast<'a, M, I>(&self, peers: I, message: &M) -> Broadcast
+    where
+        M: ChannelMessage + Clone,
+        I: IntoIterator<Item = (&'a K, &'a mut MessageChannels)>,
+    {
+        let mut broadcast = Broadcast::default();
+        for (peer, channels) in peers {
+            if !self.is_relevant(peer, message) {
+                broadcast.filtered += 1;
+            } else if channels.send(message.clone()).is_some() {
+                broadcast.dropped += 1;
+            } else {
+                broadcast.sent += 1;
+            }
+        }
+        broadcast
+    }
+}
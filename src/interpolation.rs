@@ -0,0 +1,144 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// Two snapshots bracketing a render time, and how far between them the render time is.
+#[derive(Debug, PartialEq)]
+pub struct Sample<'a, T> {
+    pub from: &'a T,
+    pub from_time: Duration,
+    pub to: &'a T,
+    pub to_time: Duration,
+    /// The interpolation factor from `from` to `to`, from 0 to 1.
+    pub t: f32,
+    /// True if the render time was outside of the buffered snapshots, in which case `from` and
+    /// `to` are both the oldest or both the newest snapshot.
+    ///
+    /// Sampling the newest snapshot like this means snapshots are arriving later than the
+    /// interpolation delay allows for, and the delay should probably be increased.
+    pub clamped: bool,
+}
+
+/// A receive side buffer of timestamped snapshots, for rendering remote state smoothly by
+/// interpolating between the two snapshots around a render time.
+///
+/// Snapshots are typically received from an unreliable channel, timestamped by the sender (for
+/// example with its tick number multiplied by its tick duration).  They may be inserted in any
+/// order, so reordered snapshots are put in their place, and lost snapshots are interpolated
+/// across.  The render time should trail the newest received snapshot by an interpolation delay
+/// large enough to cover the sender's snapshot interval plus network jitter, so that there is
+/// usually a snapshot on either side of it.
+#[derive(Debug, Clone)]
+pub struct SnapshotBuffer<T> {
+    capacity: usize,
+    snapshots: VecDeque<(Duration, T)>,
+}
+
+impl<T> SnapshotBuffer<T> {
+    /// Create a buffer which holds up to `capacity` snapshots, dropping the oldest beyond that.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is less than 2.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity >= 2, "snapshot buffer capacity must be at least 2");
+        SnapshotBuffer {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// The timestamp of the oldest buffered snapshot.
+    pub fn oldest_time(&self) -> Option<Duration> {
+        self.snapshots.front().map(|(time, _)| *time)
+    }
+
+    /// The timestamp of the newest buffered snapshot.
+    pub fn newest_time(&self) -> Option<Duration> {
+        self.snapshots.back().map(|(time, _)| *time)
+    }
+
+    /// Insert a snapshot with the given timestamp.
+    ///
+    /// Returns false and drops the snapshot if a snapshot with the same timestamp has already been
+    /// inserted, or if the buffer is full and the snapshot is older than every buffered snapshot.
+    pub fn insert(&mut self, time: Duration, snapshot: T) -> bool {
+        // Snapshots usually arrive in order, so search from the back.
+        let pos = self
+            .snapshots
+            .iter()
+            .rposition(|(t, _)| *t <= time)
+            .map_or(0, |i| i + 1);
+        if pos > 0 && self.snapshots[pos - 1].0 == time {
+            return false;
+        }
+
+        if self.snapshots.len() == self.capacity {
+            if pos == 0 {
+                return false;
+            }
+            self.snapshots.pop_front();
+            self.snapshots.insert(pos - 1, (time, snapshot));
+        } else {
+            self.snapshots.insert(pos, (time, snapshot));
+        }
+        true
+    }
+
+    /// Find the snapshots on either side of the given render time.
+    ///
+    /// Returns `None` only if the buffer is empty.
+    pub fn sample(&self, render_time: Duration) -> Option<Sample<'_, T>> {
+        let (oldest_time, oldest) = self.snapshots.front()?;
+        let (newest_time, newest) = self.snapshots.back()?;
+
+        let clamped = |time, snapshot| Sample {
+            from: snapshot,
+            from_time: time,
+            to: snapshot,
+            to_time: time,
+            t: 0.0,
+            clamped: true,
+        };
+        if render_time < *oldest_time {
+            return Some(clamped(*oldest_time, oldest));
+        }
+        if render_time >= *newest_time {
+            let mut sample = clamped(*newest_time, newest);
+            // Rendering exactly the newest snapshot is not clamped.
+            sample.clamped = render_time > *newest_time;
+            return Some(sample);
+        }
+
+        let i = self
+            .snapshots
+            .iter()
+            .rposition(|(t, _)| *t <= render_time)
+            .unwrap();
+        let (from_time, from) = &self.snapshots[i];
+        let (to_time, to) = &self.snapshots[i + 1];
+        let t = (render_time - *from_time).as_secs_f64() / (*to_time - *from_time).as_secs_f64();
+        Some(Sample {
+            from,
+            from_time: *from_time,
+            to,
+            to_time: *to_time,
+            t: t as f32,
+            clamped: false,
+        })
+    }
+
+    /// Drop every snapshot that can no longer be sampled at or after the given render time,
+    /// keeping the snapshot immediately before it.
+    pub fn discard_before(&mut self, render_time: Duration) {
+        while self.snapshots.len() > 1 && self.snapshots[1].0 <= render_time {
+            self.snapshots.pop_front();
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    packet::PacketPool,
+    serde_channel::{
+        self, Format, ReliableSerdeChannel, ReliableSerdeTypedChannel, UnreliableSerdeChannel,
+        UnreliableSerdeTypedChannel,
+    },
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+/// The JSON data format.
+///
+/// Every message is a single compact JSON document encoded as UTF-8, so traffic can be read in
+/// plaintext during development and spoken by browser based tools without a bincode
+/// implementation.  JSON is much larger than bincode on the wire, so this is mostly useful for
+/// debugging and web interop.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Json;
+
+impl Format for Json {
+    type Error = serde_json::Error;
+
+    fn serialize<T: Serialize + ?Sized>(
+        buffer: &mut [u8],
+        msg: &T,
+    ) -> Result<usize, serde_json::Error> {
+        let len = buffer.len();
+        let mut w = buffer;
+        serde_json::to_writer(&mut w, msg)?;
+        Ok(len - w.len())
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(buffer: &'a [u8]) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(buffer)
+    }
+}
+
+pub type SendError = serde_channel::SendError<serde_json::Error>;
+pub type RecvError = serde_channel::RecvError<serde_json::Error>;
+pub type Error = serde_channel::Error<serde_json::Error>;
+
+/// An `UnreliableChannel` which sends messages serialized as JSON.
+pub type UnreliableJsonChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeChannel<Json, R, P, I, O>;
+
+pub type UnreliableJsonTypedChannel<
+    T,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeTypedChannel<T, Json, R, P, I, O>;
+
+/// A `ReliableChannel` which sends messages serialized as JSON, each prefixed with its length.
+pub type ReliableJsonChannel = ReliableSerdeChannel<Json>;
+
+pub type ReliableJsonTypedChannel<T> = ReliableSerdeTypedChannel<T, Json>;
@@ -0,0 +1,313 @@
+use std::time::Duration;
+
+use futures::{
+    future::{self, Either},
+    pin_mut, select, FutureExt, Sink, SinkExt, Stream, StreamExt,
+};
+use snow::{params::NoiseParams, Builder, HandshakeState};
+use thiserror::Error;
+
+use crate::{
+    encryption::{Decryptor, EncryptedPacket, Encryptor, Keys, HEADER_LEN},
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{IncomingMultiplexedPackets, OutgoingMultiplexedPackets},
+    runtime::Timer,
+};
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+// Handshake packets use the sequence number reserved by `Encryptor`, so they can never be
+// mistaken for encrypted packets.
+const HANDSHAKE_SEQUENCE: [u8; HEADER_LEN] = [0xff; HEADER_LEN];
+
+// The largest Noise XX handshake message, which is the second.
+const MAX_MESSAGE_LEN: usize = 96;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// How often to resend the last handshake message until the other side responds.
+    pub resend_interval: Duration,
+    /// Give up on the handshake if it has not completed after this long.
+    pub timeout: Duration,
+    /// If set, both directions derive a new key every this many packets, both sides must use the
+    /// same interval.  See `Encryptor::with_rekey_interval`.
+    pub rekey_interval: Option<u64>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            resend_interval: Duration::from_millis(250),
+            timeout: Duration::from_secs(10),
+            rekey_interval: Some(1 << 16),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+/// A long-lived X25519 keypair identifying one side of a connection.
+#[derive(Clone)]
+pub struct Keypair {
+    pub public: [u8; 32],
+    pub private: [u8; 32],
+}
+
+impl Keypair {
+    pub fn generate() -> Keypair {
+        let keypair = Builder::new(noise_params())
+            .generate_keypair()
+            .expect("could not generate keypair");
+        let mut public = [0; 32];
+        let mut private = [0; 32];
+        public.copy_from_slice(&keypair.public);
+        private.copy_from_slice(&keypair.private);
+        Keypair { public, private }
+    }
+}
+
+/// Error returned by `SecureSession::run_multiplexer`, all errors are fatal.
+#[derive(Debug, Error)]
+pub enum KeyExchangeError {
+    #[error("key exchange timed out")]
+    TimedOut,
+    #[error("packet stream disconnected")]
+    Disconnected,
+    #[error("remote static key is not the expected key")]
+    UnknownPeer,
+    #[error("noise handshake error: {0}")]
+    Noise(#[from] snow::Error),
+}
+
+/// Establishes session keys with a Noise XX handshake, then encrypts a multiplexer's packets with
+/// them.
+///
+/// The handshake messages are resent until answered, so the key exchange works over a lossy
+/// transport.  Once established, packets are encrypted as in `encryption::encrypted`, and if
+/// `Settings::rekey_interval` is set, the session keys are periodically replaced without any
+/// further handshake, so the connection is never interrupted.
+pub struct SecureSession<T, P> {
+    timer: T,
+    pool: P,
+    settings: Settings,
+    role: Role,
+    keypair: Keypair,
+    remote_key: Option<[u8; 32]>,
+}
+
+impl<T, P> SecureSession<T, P>
+where
+    T: Timer,
+    P: PacketPool,
+{
+    pub fn new(timer: T, pool: P, settings: Settings, role: Role, keypair: Keypair) -> Self {
+        SecureSession {
+            timer,
+            pool,
+            settings,
+            role,
+            keypair,
+            remote_key: None,
+        }
+    }
+
+    /// Fail the key exchange with `KeyExchangeError::UnknownPeer` unless the other side's static
+    /// public key is the given key.
+    pub fn expect_remote_key(mut self, remote_key: [u8; 32]) -> Self {
+        self.remote_key = Some(remote_key);
+        self
+    }
+
+    /// Perform the key exchange over the given raw packet stream and sink, then move packets
+    /// between them and the multiplexer, encrypting outgoing and decrypting incoming packets.
+    ///
+    /// The multiplexer must have been created with an `EncryptedPacketPool`.  Returns `Ok(())` if
+    /// either side of the multiplexer is dropped.
+    pub async fn run_multiplexer<I, O>(
+        self,
+        mut incoming: I,
+        mut outgoing: O,
+        mut mux_incoming: IncomingMultiplexedPackets<EncryptedPacket<P::Packet>>,
+        mut mux_outgoing: OutgoingMultiplexedPackets<EncryptedPacket<P::Packet>>,
+    ) -> Result<(), KeyExchangeError>
+    where
+        I: Stream<Item = P::Packet> + Unpin,
+        O: Sink<P::Packet> + Unpin,
+        P::Packet: Unpin,
+    {
+        let (keys, final_message) = self.handshake(&mut incoming, &mut outgoing).await?;
+
+        let (mut encryptor, mut decryptor) = match self.settings.rekey_interval {
+            Some(interval) => (
+                Encryptor::with_rekey_interval(&keys.send, interval),
+                Decryptor::with_rekey_interval(&keys.recv, interval),
+            ),
+            None => (Encryptor::new(&keys.send), Decryptor::new(&keys.recv)),
+        };
+
+        let mut incoming = incoming.fuse();
+        loop {
+            select! {
+                packet = incoming.next() => {
+                    let packet = packet.ok_or(KeyExchangeError::Disconnected)?;
+                    if packet.starts_with(&HANDSHAKE_SEQUENCE) {
+                        // The other side did not get our final handshake message, so it is
+                        // still resending its own.
+                        if let Some(message) = &final_message {
+                            send(&mut outgoing, self.handshake_packet(3, message)).await?;
+                        }
+                    } else if let Ok((_, packet)) = decryptor.decrypt(packet) {
+                        // Packets for full or unknown channels are dropped, the same as every
+                        // other transport adapter.
+                        let _ = mux_incoming.try_send(packet);
+                    }
+                }
+                packet = mux_outgoing.next().fuse() => {
+                    match packet {
+                        Some(packet) => send(&mut outgoing, encryptor.encrypt(packet)).await?,
+                        None => return Ok(()),
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns the session keys, and for the initiator the final handshake message in case it
+    // needs to be resent.
+    async fn handshake<I, O>(
+        &self,
+        incoming: &mut I,
+        outgoing: &mut O,
+    ) -> Result<(Keys, Option<Vec<u8>>), KeyExchangeError>
+    where
+        I: Stream<Item = P::Packet> + Unpin,
+        O: Sink<P::Packet> + Unpin,
+    {
+        let builder = Builder::new(noise_params()).local_private_key(&self.keypair.private);
+        let mut state = match self.role {
+            Role::Initiator => builder.build_initiator()?,
+            Role::Responder => builder.build_responder()?,
+        };
+
+        let start = self.timer.now();
+        let mut buffer = [0; MAX_MESSAGE_LEN];
+
+        // The last message we sent, along with its index, which is resent until the other side
+        // sends the next message.
+        let mut last_sent = None;
+        if self.role == Role::Initiator {
+            let len = state.write_message(&[], &mut buffer)?;
+            last_sent = Some((1, buffer[..len].to_vec()));
+        }
+
+        loop {
+            if let Some((index, message)) = &last_sent {
+                send(outgoing, self.handshake_packet(*index, message)).await?;
+            }
+
+            let resend = self.timer.sleep(self.settings.resend_interval);
+            pin_mut!(resend);
+            loop {
+                let packet = match future::select(incoming.next(), resend.as_mut()).await {
+                    Either::Left((Some(packet), _)) => packet,
+                    Either::Left((None, _)) => return Err(KeyExchangeError::Disconnected),
+                    Either::Right(((), _)) => break,
+                };
+
+                if packet.len() <= HEADER_LEN || !packet.starts_with(&HANDSHAKE_SEQUENCE) {
+                    continue;
+                }
+                let index = packet[HEADER_LEN];
+                let message = &packet[HEADER_LEN + 1..];
+                let expected = match &last_sent {
+                    Some((sent, _)) => sent + 1,
+                    None => 1,
+                };
+                if index != expected {
+                    // A duplicate of the message before our last, so ours was probably lost.
+                    if index + 2 == expected {
+                        break;
+                    }
+                    continue;
+                }
+
+                let mut payload = [0; MAX_MESSAGE_LEN];
+                state.read_message(message, &mut payload)?;
+
+                match index {
+                    1 => {
+                        let len = state.write_message(&[], &mut buffer)?;
+                        last_sent = Some((2, buffer[..len].to_vec()));
+                        break;
+                    }
+                    2 => {
+                        self.check_remote_key(&state)?;
+                        let len = state.write_message(&[], &mut buffer)?;
+                        let message = buffer[..len].to_vec();
+                        send(outgoing, self.handshake_packet(3, &message)).await?;
+                        return Ok((split(&mut state, self.role), Some(message)));
+                    }
+                    _ => {
+                        self.check_remote_key(&state)?;
+                        return Ok((split(&mut state, self.role), None));
+                    }
+                }
+            }
+
+            if self.timer.elapsed(start) >= self.settings.timeout {
+                return Err(KeyExchangeError::TimedOut);
+            }
+        }
+    }
+
+    fn check_remote_key(&self, state: &HandshakeState) -> Result<(), KeyExchangeError> {
+        match (self.remote_key, state.get_remote_static()) {
+            (None, _) => Ok(()),
+            (Some(expected), Some(remote)) if expected[..] == *remote => Ok(()),
+            _ => Err(KeyExchangeError::UnknownPeer),
+        }
+    }
+
+    // Handshake packets are the reserved sequence number, followed by the message index, followed
+    // by the Noise handshake message.
+    fn handshake_packet(&self, index: u8, message: &[u8]) -> P::Packet {
+        let mut packet = self.pool.acquire();
+        packet.extend(&HANDSHAKE_SEQUENCE);
+        packet.extend(&[index]);
+        packet.extend(message);
+        packet
+    }
+}
+
+fn noise_params() -> NoiseParams {
+    NOISE_PARAMS.parse().unwrap()
+}
+
+fn split(state: &mut HandshakeState, role: Role) -> Keys {
+    let (initiator, responder) = state.dangerously_get_raw_split();
+    match role {
+        Role::Initiator => Keys {
+            send: initiator,
+            recv: responder,
+        },
+        Role::Responder => Keys {
+            send: responder,
+            recv: initiator,
+        },
+    }
+}
+
+async fn send<O, P>(outgoing: &mut O, packet: P) -> Result<(), KeyExchangeError>
+where
+    O: Sink<P> + Unpin,
+{
+    outgoing
+        .send(packet)
+        .await
+        .map_err(|_| KeyExchangeError::Disconnected)
+}
@@ -0,0 +1,249 @@
+//! A reliable message channel made of independently ordered sub-streams.
+//!
+//! A `ReliableChannel` delivers everything in the order it was sent, so a single lost packet
+//! holds back every message sent after it until it is resent.  When messages only need to be
+//! ordered relative to some of the others, such as the chat of each player, this head-of-line
+//! blocking is wasted latency.  A `KeyedChannel` tags every message with a `StreamKey` and a
+//! sequence number within its key, resends the messages of lost packets on top of an
+//! `AckedChannel`, and delivers each key's messages in order as soon as they are complete,
+//! regardless of any other key, like the streams of QUIC or SCTP.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{Sink, Stream};
+use thiserror::Error;
+
+use crate::{
+    acked_channel::{self, AckEvent, AckedChannel, MessageId},
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+pub use crate::acked_channel::RecvError;
+
+/// Identifies an independently ordered sub-stream of a `KeyedChannel`.
+pub type StreamKey = u8;
+
+/// The length of the key and sequence number header at the start of every message.
+pub const HEADER_LEN: usize = 3;
+
+/// The maximum number of messages of a single key which may be sent before they are acknowledged.
+pub const MAX_UNACKED: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum SendError {
+    /// Fatal error due to channel disconnection.
+    #[error("outgoing packet stream has been disconnected")]
+    Disconnected,
+    /// Non-fatal error, message is unsent.
+    #[error("sent message is larger than the maximum packet size")]
+    TooBig,
+    /// Non-fatal error, message is unsent.  The key has `MAX_UNACKED` messages waiting to be
+    /// acknowledged, flush and receive until some are.
+    #[error("too many unacknowledged messages on stream key")]
+    WindowFull,
+}
+
+impl From<acked_channel::SendError> for SendError {
+    fn from(err: acked_channel::SendError) -> Self {
+        match err {
+            acked_channel::SendError::Disconnected => SendError::Disconnected,
+            acked_channel::SendError::TooBig => SendError::TooBig,
+        }
+    }
+}
+
+/// Turns an `AckedChannel` into a reliable channel whose messages are ordered only within their
+/// `StreamKey`.
+///
+/// Messages of lost packets are resent by `KeyedChannel::flush`, which like
+/// `AckedChannel::flush` must be called regularly on both sides, since acknowledgements ride on
+/// outgoing packets.  Acknowledgements are processed while receiving.  The maximum message length
+/// is `HEADER_LEN` less than that of the `AckedChannel`.
+pub struct KeyedChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: AckedChannel<R, P, I, O>,
+    send_streams: HashMap<StreamKey, SendStream>,
+    // Sent messages by the id the `AckedChannel` gave them, and messages of lost packets waiting
+    // to be resent.
+    unacked: HashMap<MessageId, Unacked>,
+    resend: VecDeque<Unacked>,
+    recv_streams: HashMap<StreamKey, RecvStream>,
+    ready: VecDeque<(StreamKey, Box<[u8]>)>,
+    // The message returned by the last `recv`.
+    current: Box<[u8]>,
+}
+
+#[derive(Default)]
+struct SendStream {
+    next_seq: u16,
+    unacked: usize,
+}
+
+#[derive(Default)]
+struct RecvStream {
+    next_seq: u16,
+    // Messages received ahead of the next in order.
+    pending: HashMap<u16, Box<[u8]>>,
+}
+
+struct Unacked {
+    key: StreamKey,
+    // The message with its header.
+    msg: Box<[u8]>,
+}
+
+impl<R, P, I, O> KeyedChannel<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(channel: AckedChannel<R, P, I, O>) -> Self {
+        KeyedChannel {
+            channel,
+            send_streams: HashMap::new(),
+            unacked: HashMap::new(),
+            resend: VecDeque::new(),
+            recv_streams: HashMap::new(),
+            ready: VecDeque::new(),
+            current: Box::new([]),
+        }
+    }
+
+    /// The number of sent messages, across all keys, which have not yet been acknowledged.
+    ///
+    /// Acknowledgements processed while receiving are only applied by the next `send`, `flush` or
+    /// received message.
+    pub fn unacked(&self) -> usize {
+        self.unacked.len() + self.resend.len()
+    }
+
+    pub fn into_inner(self) -> AckedChannel<R, P, I, O> {
+        self.channel
+    }
+
+    /// Write the given message to the sub-stream with the given key.
+    ///
+    /// Like `AckedChannel::send`, messages are coalesced into packets, so `flush` must be called to
+    /// guarantee that they are sent.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send(&mut self, key: StreamKey, msg: &[u8]) -> Result<(), SendError> {
+        self.process_events();
+        let stream = self.send_streams.entry(key).or_default();
+        if stream.unacked >= MAX_UNACKED {
+            return Err(SendError::WindowFull);
+        }
+
+        let mut framed = Vec::with_capacity(HEADER_LEN + msg.len());
+        framed.push(key);
+        framed.extend_from_slice(&stream.next_seq.to_le_bytes());
+        framed.extend_from_slice(msg);
+        let id = self.channel.send(&framed).await?;
+
+        let stream = self.send_streams.get_mut(&key).unwrap();
+        stream.next_seq = stream.next_seq.wrapping_add(1);
+        stream.unacked += 1;
+        self.unacked.insert(
+            id,
+            Unacked {
+                key,
+                msg: framed.into_boxed_slice(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Resend the messages of any lost packets, then flush the `AckedChannel`.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        self.process_events();
+        while let Some(unacked) = self.resend.front() {
+            let id = self.channel.send(&unacked.msg).await?;
+            let unacked = self.resend.pop_front().unwrap();
+            trace_event!(key = unacked.key, "resending keyed message");
+            self.unacked.insert(id, unacked);
+        }
+        Ok(self.channel.flush().await?)
+    }
+
+    /// Receive the next message of any key, in order within its key.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv(&mut self) -> Result<(StreamKey, &[u8]), RecvError> {
+        loop {
+            if let Some((key, msg)) = self.ready.pop_front() {
+                self.current = msg;
+                return Ok((key, &self.current));
+            }
+
+            let msg = self.channel.recv().await?;
+            if msg.len() < HEADER_LEN {
+                debug_event!(len = msg.len(), "dropping malformed keyed message");
+                return Err(RecvError::BadFormat);
+            }
+            let key = msg[0];
+            let seq = LittleEndian::read_u16(&msg[1..3]);
+            let stream = self.recv_streams.entry(key).or_default();
+
+            // Messages more than half the sequence space behind are resends of messages already
+            // delivered.
+            let ahead = seq.wrapping_sub(stream.next_seq);
+            if ahead >= 1 << 15 || stream.pending.contains_key(&seq) {
+                trace_event!(key, seq, "dropping duplicate keyed message");
+            } else if ahead == 0 {
+                self.ready.push_back((key, msg[HEADER_LEN..].into()));
+                stream.next_seq = stream.next_seq.wrapping_add(1);
+                while let Some(msg) = stream.pending.remove(&stream.next_seq) {
+                    self.ready.push_back((key, msg));
+                    stream.next_seq = stream.next_seq.wrapping_add(1);
+                }
+            } else {
+                stream.pending.insert(seq, msg[HEADER_LEN..].into());
+            }
+            self.process_events();
+        }
+    }
+
+    fn process_events(&mut self) {
+        while let Some(event) = self.channel.next_event() {
+            match event {
+                AckEvent::Acked { messages, .. } => self.acked(messages),
+                AckEvent::Lost { messages, .. } => {
+                    for id in messages {
+                        if let Some(unacked) = self.unacked.remove(&id) {
+                            self.resend.push_back(unacked);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn acked(&mut self, messages: Range<MessageId>) {
+        for id in messages {
+            if let Some(unacked) = self.unacked.remove(&id) {
+                if let Some(stream) = self.send_streams.get_mut(&unacked.key) {
+                    stream.unacked -= 1;
+                }
+            }
+        }
+    }
+}
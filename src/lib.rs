@@ -1,33 +1,192 @@
+//! With the default `std` feature disabled, `turbulence` is `no_std` (but requires `alloc`), and
+//! only packets, `UnreliableChannel` and the runtime traits are available.  The time source is
+//! then entirely user supplied by implementing `Timer`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// Must come first, so that the logging macros are available to every other module.
+#[macro_use]
+mod trace;
+
+#[cfg(feature = "std")]
+pub mod acked_channel;
+#[cfg(feature = "authentication")]
+pub mod authentication;
+#[cfg(feature = "std")]
+pub mod bandwidth_estimator;
 mod bandwidth_limiter;
+#[cfg(feature = "bevy")]
+pub mod bevy_plugin;
+#[cfg(feature = "bitcode")]
+pub mod bitcode_channel;
+#[cfg(feature = "std")]
+pub mod blob_transfer;
 pub mod buffer;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "minicbor-serde")]
+pub mod cbor_channel;
+#[cfg(feature = "std")]
 pub mod channel_builder;
+#[cfg(feature = "std")]
+pub mod clock_sync;
+#[cfg(feature = "std")]
 pub mod compressed_bincode_channel;
+#[cfg(feature = "std")]
+pub mod conformance;
+#[cfg(feature = "authentication")]
+pub mod connect_token;
+#[cfg(feature = "std")]
+pub mod constant_bitrate;
+#[cfg(feature = "std")]
+pub mod debug_header;
+#[cfg(feature = "std")]
+pub mod decode_limits;
+#[cfg(feature = "std")]
+pub mod delta_channel;
+#[cfg(feature = "std")]
+pub mod dynamic_multiplexer;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "std")]
+pub mod envelope;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
 mod event_watch;
+#[cfg(feature = "std")]
+pub mod fec;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(feature = "std")]
+mod growable_buffer;
+#[cfg(feature = "std")]
+pub mod handshake;
+#[cfg(feature = "std")]
+pub mod heartbeat;
+#[cfg(feature = "std")]
+pub mod ingress;
+#[cfg(feature = "std")]
+pub mod input_channel;
+#[cfg(feature = "std")]
+pub mod interest;
+#[cfg(feature = "std")]
+pub mod interpolation;
+#[cfg(feature = "serde_json")]
+pub mod json_channel;
+#[cfg(feature = "key-exchange")]
+pub mod key_exchange;
+#[cfg(feature = "std")]
+pub mod keyed_channel;
+#[cfg(feature = "std")]
+pub mod liveness;
+#[cfg(feature = "std")]
+pub mod loss_statistics;
+#[cfg(feature = "std")]
+pub mod media_channel;
+#[cfg(feature = "std")]
+pub mod memory_budget;
+#[cfg(feature = "std")]
 pub mod message_channels;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod mock_message_channels;
+#[cfg(feature = "rmp-serde")]
+pub mod msgpack_channel;
+pub mod pacing;
 pub mod packet;
+#[cfg(feature = "std")]
+pub mod packet_compression;
+pub mod packet_metadata;
+#[cfg(feature = "std")]
 pub mod packet_multiplexer;
+#[cfg(feature = "std")]
+pub mod piggyback;
+#[cfg(feature = "std")]
+pub mod playout;
+#[cfg(feature = "postcard")]
+pub mod postcard_channel;
+#[cfg(feature = "std")]
+pub mod prometheus;
+#[cfg(feature = "prost")]
+pub mod prost_channel;
+#[cfg(feature = "std")]
+pub mod pubsub;
+#[cfg(feature = "std")]
+pub mod quantize;
+#[cfg(feature = "quinn")]
+pub mod quic_transport;
+#[cfg(feature = "std")]
 pub mod reliable_bincode_channel;
+#[cfg(feature = "std")]
 pub mod reliable_channel;
+pub mod replay_window;
+#[cfg(feature = "std")]
+pub mod replication;
+#[cfg(feature = "rkyv")]
+pub mod rkyv_channel;
 pub mod runtime;
+#[cfg(feature = "std")]
+pub mod scratch;
+#[cfg(any(feature = "std", feature = "serde", feature = "postcard"))]
+pub mod serde_channel;
+#[cfg(feature = "std")]
+pub mod simulation;
+#[cfg(feature = "std")]
+pub mod telemetry;
+pub mod tick_budget;
+#[cfg(feature = "std")]
+pub mod timestamp_channel;
+#[cfg(feature = "udp")]
+pub mod udp_transport;
+#[cfg(feature = "std")]
 pub mod unreliable_bincode_channel;
 pub mod unreliable_channel;
+#[cfg(feature = "web-transport")]
+pub mod web_transport;
+#[cfg(all(feature = "web-rtc", target_arch = "wasm32"))]
+pub mod webrtc_transport;
+#[cfg(feature = "std")]
 mod windows;
+#[cfg(feature = "std")]
+pub mod work_budget;
 
 pub use self::{
     buffer::{BufferPacket, BufferPacketPool, BufferPool},
+    packet::{Packet, PacketPool, MAX_PACKET_LEN, WIRE_VERSION},
+    runtime::{LocalRuntime, Runtime, Timer},
+    unreliable_channel::UnreliableChannel,
+};
+
+#[cfg(feature = "std")]
+pub use self::{
     channel_builder::ChannelBuilder,
     compressed_bincode_channel::{CompressedBincodeChannel, CompressedTypedChannel},
+    error::{Error, ErrorKind},
     message_channels::{
         MessageChannelMode, MessageChannelSettings, MessageChannels, MessageChannelsBuilder,
+        TransportPreset,
     },
-    packet::{Packet, PacketPool, MAX_PACKET_LEN},
     packet_multiplexer::{
-        ChannelStatistics, ChannelTotals, IncomingMultiplexedPackets, MuxPacket, MuxPacketPool,
-        OutgoingMultiplexedPackets, PacketChannel, PacketMultiplexer,
+        ChannelBuffers, ChannelStatistics, ChannelTotals, IncomingMultiplexedPackets, MuxPacket,
+        MuxPacketPool, OutgoingMultiplexedPackets, PacketChannel, PacketMultiplexer,
     },
     reliable_bincode_channel::{ReliableBincodeChannel, ReliableTypedChannel},
     reliable_channel::ReliableChannel,
-    runtime::Runtime,
+    simulation::LinkSimulator,
     unreliable_bincode_channel::{UnreliableBincodeChannel, UnreliableTypedChannel},
-    unreliable_channel::UnreliableChannel,
 };
+
+#[cfg(feature = "bevy")]
+pub use self::bevy_plugin::{ConnectionEvent, ConnectionId, Connections, TurbulencePlugin};
+#[cfg(feature = "quinn")]
+pub use self::quic_transport::QuicDatagrams;
+#[cfg(feature = "udp")]
+pub use self::udp_transport::{UdpAcceptor, UdpPeer, UdpTransport};
+#[cfg(feature = "web-transport")]
+pub use self::web_transport::WebTransportPackets;
+#[cfg(all(feature = "web-rtc", target_arch = "wasm32"))]
+pub use self::webrtc_transport::RtcDataChannelPackets;
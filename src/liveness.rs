@@ -0,0 +1,154 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    packet_multiplexer::{DropReason, NetworkEvent, NetworkEvents, PacketChannel, PacketObserver},
+    runtime::Runtime,
+    telemetry::{EventKind, EventLog},
+};
+
+/// Which directions of a connection have gone without traffic for longer than a threshold.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Staleness {
+    /// Packets are being sent, but nothing has been received, as when the remote has gone away or
+    /// a NAT mapping has expired on the return path.
+    SendOnly,
+    /// Packets are being received, but nothing has been sent, as when the local send path has
+    /// stalled.
+    ReceiveOnly,
+    /// No traffic in either direction.
+    Silent,
+}
+
+/// Tracks when a connection last sent and last received any packet, across all of its channels,
+/// to detect half-open connections.
+///
+/// A reliable channel cannot tell a remote which is gone from one which is merely idle, and an
+/// unreliable channel cannot tell at all, but a connection which keeps sending without receiving
+/// anything, or the reverse, for long enough is almost certainly half-open.
+///
+/// A `Liveness` is a cheap handle, and clones track the same connection.  Install it on the
+/// connection's `PacketMultiplexer` with `PacketMultiplexer::set_observer`, or call
+/// `Liveness::record_sent` and `Liveness::record_received` from another observer.  Incoming
+/// packets which the multiplexer drops still count as received, since they show that the remote
+/// is sending.  Both directions count as active when the handle is created.
+pub struct Liveness<R: Runtime> {
+    runtime: R,
+    state: Arc<Mutex<State<R::Instant>>>,
+}
+
+impl<R: Runtime> Clone for Liveness<R> {
+    fn clone(&self) -> Self {
+        Liveness {
+            runtime: self.runtime.clone(),
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+struct State<I> {
+    last_sent: I,
+    last_received: I,
+    event_log: Option<EventLog>,
+    network_events: Option<NetworkEvents>,
+}
+
+impl<R: Runtime> Liveness<R> {
+    pub fn new(runtime: R) -> Self {
+        let now = runtime.now();
+        Liveness {
+            runtime,
+            state: Arc::new(Mutex::new(State {
+                last_sent: now,
+                last_received: now,
+                event_log: None,
+                network_events: None,
+            })),
+        }
+    }
+
+    /// Record an `EventKind::Stale` event in the given log whenever `Liveness::stale` resolves.
+    pub fn set_event_log(&self, event_log: EventLog) {
+        self.state.lock().unwrap().event_log = Some(event_log);
+    }
+
+    /// Publish a `NetworkEvent::KeepaliveTimeout` to the given events, usually those of the
+    /// connection's multiplexer, whenever `Liveness::stale` resolves.
+    pub fn set_network_events(&self, network_events: NetworkEvents) {
+        self.state.lock().unwrap().network_events = Some(network_events);
+    }
+
+    pub fn record_sent(&self) {
+        self.state.lock().unwrap().last_sent = self.runtime.now();
+    }
+
+    pub fn record_received(&self) {
+        self.state.lock().unwrap().last_received = self.runtime.now();
+    }
+
+    /// The time since any packet was last sent.
+    pub fn since_sent(&self) -> Duration {
+        self.runtime.elapsed(self.state.lock().unwrap().last_sent)
+    }
+
+    /// The time since any packet was last received.
+    pub fn since_received(&self) -> Duration {
+        self.runtime
+            .elapsed(self.state.lock().unwrap().last_received)
+    }
+
+    /// Which directions, if any, have gone without traffic for at least `threshold`.
+    pub fn staleness(&self, threshold: Duration) -> Option<Staleness> {
+        match (
+            self.since_sent() >= threshold,
+            self.since_received() >= threshold,
+        ) {
+            (false, false) => None,
+            (false, true) => Some(Staleness::SendOnly),
+            (true, false) => Some(Staleness::ReceiveOnly),
+            (true, true) => Some(Staleness::Silent),
+        }
+    }
+
+    /// Returns true if either direction has gone without traffic for at least `threshold`.
+    pub fn is_stale(&self, threshold: Duration) -> bool {
+        self.staleness(threshold).is_some()
+    }
+
+    /// Wait until either direction has gone without traffic for at least `threshold`.
+    ///
+    /// Traffic while waiting pushes the deadline back, so a connection which stays active never
+    /// resolves this.
+    pub async fn stale(&self, threshold: Duration) -> Staleness {
+        loop {
+            if let Some(staleness) = self.staleness(threshold) {
+                let state = self.state.lock().unwrap();
+                if let Some(event_log) = &state.event_log {
+                    event_log.record(EventKind::Stale(staleness));
+                }
+                if let Some(network_events) = &state.network_events {
+                    network_events.publish(NetworkEvent::KeepaliveTimeout { staleness });
+                }
+                return staleness;
+            }
+            let idle = self.since_sent().max(self.since_received());
+            self.runtime.sleep(threshold - idle).await;
+        }
+    }
+}
+
+impl<R: Runtime> PacketObserver for Liveness<R> {
+    fn on_packet_sent(&self, _channel: PacketChannel, _len: usize) {
+        self.record_sent();
+    }
+
+    fn on_packet_received(&self, _channel: PacketChannel, _len: usize) {
+        self.record_received();
+    }
+
+    fn on_packet_dropped(&self, _channel: PacketChannel, _len: usize, _reason: DropReason) {
+        self.record_received();
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// The number of buckets in each histogram of `LossStatistics`.
+///
+/// Bucket `i` counts values from `2^i` up to but not including `2^(i + 1)`, except for the last
+/// bucket, which counts every value from `2^(LOSS_BUCKETS - 1)` up.
+pub const LOSS_BUCKETS: usize = 8;
+
+/// A handle to the loss patterns seen by a `ReliableChannel`, from
+/// `ReliableChannel::loss_statistics`.
+///
+/// Every range of data a reliable channel sends is acknowledged on its own, so the sender knows
+/// exactly which of its packets arrived, and in what order.  Packets are numbered as they are
+/// sent, including resends, and each is counted as lost if it is resent before being
+/// acknowledged, and as delivered otherwise.
+///
+/// Random loss shows up as mostly single packet bursts of loss, while a queue overflowing on the
+/// path, such as an oversized router buffer tail dropping, loses long bursts of consecutive
+/// packets.  A packet acknowledged after a packet sent later than it was reordered on the path, by
+/// the distance in packets to the latest sent packet acknowledged so far.  Reordering is only
+/// measured for packets which were never resent, since a resent range does not tell which of its
+/// sends arrived.
+///
+/// The statistics are as seen through acknowledgements, so lost acknowledgements count as lost
+/// packets, and reordered acknowledgements as reordered packets.  The handle remains valid after
+/// the channel is wrapped in another channel type.
+#[derive(Debug, Clone, Default)]
+pub struct LossStatistics(Arc<LossStatisticsData>);
+
+#[derive(Debug, Default)]
+struct LossStatisticsData {
+    delivered: AtomicU64,
+    lost: AtomicU64,
+    burst_lengths: [AtomicU64; LOSS_BUCKETS],
+    reorder_distances: [AtomicU64; LOSS_BUCKETS],
+}
+
+impl LossStatistics {
+    /// The total number of sent packets which were acknowledged.
+    pub fn delivered(&self) -> u64 {
+        self.0.delivered.load(Ordering::Relaxed)
+    }
+
+    /// The total number of sent packets which were resent before being acknowledged.
+    pub fn lost(&self) -> u64 {
+        self.0.lost.load(Ordering::Relaxed)
+    }
+
+    /// A histogram of the lengths of bursts of consecutively sent packets which were lost, with
+    /// buckets as described by `LOSS_BUCKETS`.
+    ///
+    /// A burst is only counted once it has ended with a delivered packet, so a burst still in
+    /// progress is not yet included.
+    pub fn burst_loss_lengths(&self) -> [u64; LOSS_BUCKETS] {
+        load(&self.0.burst_lengths)
+    }
+
+    /// A histogram of the reorder distances of reordered packets, with buckets as described by
+    /// `LOSS_BUCKETS`.  Packets which were not reordered are not counted.
+    pub fn reorder_distances(&self) -> [u64; LOSS_BUCKETS] {
+        load(&self.0.reorder_distances)
+    }
+}
+
+// Numbers the packets sent by a reliable channel task, and records them to a `LossStatistics`
+// once they are delivered or lost.
+pub(crate) struct LossTracker {
+    statistics: LossStatistics,
+    next_seq: u64,
+    // Every packet before the cursor has been delivered or lost, and counted towards a burst.
+    cursor: u64,
+    // Whether each packet at or after the cursor which is known to be delivered or lost was
+    // delivered.
+    outcomes: BTreeMap<u64, bool>,
+    // The length of the burst of lost packets just before the cursor.
+    burst: u64,
+    highest_delivered: Option<u64>,
+}
+
+impl LossTracker {
+    pub(crate) fn new(statistics: LossStatistics) -> Self {
+        LossTracker {
+            statistics,
+            next_seq: 0,
+            cursor: 0,
+            outcomes: BTreeMap::new(),
+            burst: 0,
+            highest_delivered: None,
+        }
+    }
+
+    /// Number a newly sent packet.
+    pub(crate) fn sent(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    /// Record the acknowledgement of a sent packet, which only measures reordering if
+    /// `measure_reorder` is true, when it is known that the acknowledgement is for this packet.
+    pub(crate) fn delivered(&mut self, seq: u64, measure_reorder: bool) {
+        if !self.resolve(seq, true) {
+            return;
+        }
+        self.statistics.0.delivered.fetch_add(1, Ordering::Relaxed);
+        match self.highest_delivered {
+            Some(highest) if highest > seq => {
+                if measure_reorder {
+                    record(&self.statistics.0.reorder_distances, highest - seq);
+                }
+            }
+            _ => self.highest_delivered = Some(seq),
+        }
+    }
+
+    /// Record that a sent packet is being resent.
+    pub(crate) fn lost(&mut self, seq: u64) {
+        if self.resolve(seq, false) {
+            self.statistics.0.lost.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Record the outcome of a packet, returning false if it was already known.
+    fn resolve(&mut self, seq: u64, delivered: bool) -> bool {
+        if seq < self.cursor || self.outcomes.contains_key(&seq) {
+            return false;
+        }
+        self.outcomes.insert(seq, delivered);
+        while let Some(delivered) = self.outcomes.remove(&self.cursor) {
+            self.cursor += 1;
+            if !delivered {
+                self.burst += 1;
+            } else if self.burst > 0 {
+                record(&self.statistics.0.burst_lengths, self.burst);
+                self.burst = 0;
+            }
+        }
+        true
+    }
+}
+
+fn record(histogram: &[AtomicU64; LOSS_BUCKETS], value: u64) {
+    let bucket = (63 - value.max(1).leading_zeros() as usize).min(LOSS_BUCKETS - 1);
+    histogram[bucket].fetch_add(1, Ordering::Relaxed);
+}
+
+fn load(histogram: &[AtomicU64; LOSS_BUCKETS]) -> [u64; LOSS_BUCKETS] {
+    let mut counts = [0; LOSS_BUCKETS];
+    for (count, bucket) in counts.iter_mut().zip(histogram) {
+        *count = bucket.load(Ordering::Relaxed);
+    }
+    counts
+}
@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{Sink, Stream};
+
+use crate::{
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{
+        DefaultIncoming, DefaultOutgoing, RecvError, SendError, UnreliableChannel,
+    },
+};
+
+/// The length of the header before every frame: a 2 byte sequence number, a 4 byte timestamp and
+/// a flags byte.
+pub const HEADER_LEN: usize = 7;
+
+const MARKER: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The number of frames past a missing frame that are held back waiting for it to arrive.
+    ///
+    /// Once a frame arrives this many frames past the oldest missing one, the missing frames are
+    /// given up on and skipped.  A window of 1 disables reordering, frames are then delivered in
+    /// the order they arrive and only late frames are dropped.
+    pub reorder_window: u16,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { reorder_window: 4 }
+    }
+}
+
+/// A received audio or video frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaFrame {
+    pub sequence: u16,
+    /// The media timestamp the frame was sent with, in whatever units the sender uses (usually
+    /// the sample clock of the codec).
+    pub timestamp: u32,
+    /// Marks a significant frame, such as the first frame of a talk spurt or the last packet of
+    /// a video frame.
+    pub marker: bool,
+    pub data: Vec<u8>,
+}
+
+/// An unreliable channel for audio and video frames, so that voice chat can share a multiplexer
+/// with game traffic.
+///
+/// Every frame is sent with a compact RTP-like header of a sequence number, a media timestamp and
+/// a marker bit.  Received frames are put back into sequence order within a small window, frames
+/// which arrive after later frames have already been delivered are dropped, as are duplicates.
+///
+/// Frames held back waiting for a missing frame are only released by later frames arriving, so
+/// a sender should keep sending frames (or silence) for as long as timely delivery matters.
+pub struct MediaChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableChannel<R, P, I, O>,
+    settings: Settings,
+    next_send: u16,
+    buffer: Vec<u8>,
+    // The next sequence number to deliver, and the frames received after it, indexed by their
+    // offset from it.
+    next_recv: Option<u16>,
+    pending: VecDeque<Option<MediaFrame>>,
+    ready: VecDeque<MediaFrame>,
+    lost_frames: u64,
+    late_frames: u64,
+}
+
+impl<R, P, I, O> MediaChannel<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    /// # Panics
+    /// Panics if `settings.reorder_window` is zero.
+    pub fn new(channel: UnreliableChannel<R, P, I, O>, settings: Settings) -> Self {
+        assert!(settings.reorder_window != 0);
+        MediaChannel {
+            channel,
+            settings,
+            next_send: 0,
+            buffer: Vec::new(),
+            next_recv: None,
+            pending: VecDeque::new(),
+            ready: VecDeque::new(),
+            lost_frames: 0,
+            late_frames: 0,
+        }
+    }
+
+    /// Write a frame to the channel, returning its sequence number.
+    ///
+    /// Just like the underlying channel, in order to guarantee that the frame is actually sent
+    /// you must call `flush`.
+    ///
+    /// This method is cancel safe, though canceling it may or may not send the frame.
+    pub async fn send(
+        &mut self,
+        timestamp: u32,
+        marker: bool,
+        data: &[u8],
+    ) -> Result<u16, SendError> {
+        let sequence = self.next_send;
+
+        self.buffer.clear();
+        self.buffer.resize(HEADER_LEN, 0);
+        LittleEndian::write_u16(&mut self.buffer[0..2], sequence);
+        LittleEndian::write_u32(&mut self.buffer[2..6], timestamp);
+        self.buffer[6] = if marker { MARKER } else { 0 };
+        self.buffer.extend_from_slice(data);
+        self.channel.send(&self.buffer).await?;
+
+        self.next_send = sequence.wrapping_add(1);
+        Ok(sequence)
+    }
+
+    /// Finish sending any unsent coalesced packets.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        self.channel.flush().await
+    }
+
+    /// Receive the next frame in sequence order.
+    ///
+    /// This method is cancel safe, it will never drop received frames.
+    pub async fn recv(&mut self) -> Result<MediaFrame, RecvError> {
+        loop {
+            if let Some(frame) = self.ready.pop_front() {
+                return Ok(frame);
+            }
+
+            let msg = self.channel.recv().await?;
+            if msg.len() < HEADER_LEN {
+                return Err(RecvError::BadFormat);
+            }
+            let frame = MediaFrame {
+                sequence: LittleEndian::read_u16(&msg[0..2]),
+                timestamp: LittleEndian::read_u32(&msg[2..6]),
+                marker: msg[6] & MARKER != 0,
+                data: msg[HEADER_LEN..].to_vec(),
+            };
+            self.insert(frame);
+        }
+    }
+
+    /// The number of frames which were skipped because they did not arrive within the reorder
+    /// window.
+    pub fn lost_frames(&self) -> u64 {
+        self.lost_frames
+    }
+
+    /// The number of frames which were dropped because they arrived after a later frame had
+    /// already been delivered.
+    pub fn late_frames(&self) -> u64 {
+        self.late_frames
+    }
+
+    fn insert(&mut self, frame: MediaFrame) {
+        let next_recv = *self.next_recv.get_or_insert(frame.sequence);
+        let offset = frame.sequence.wrapping_sub(next_recv) as i16;
+        if offset < 0 {
+            self.late_frames += 1;
+            return;
+        }
+        let mut offset = offset as u16;
+
+        // Make room in the window for the new frame, skipping over any missing frames.
+        let window = self.settings.reorder_window;
+        if offset >= window {
+            let mut advance = offset - window + 1;
+            while advance > 0 {
+                match self.pending.pop_front() {
+                    Some(Some(frame)) => self.ready.push_back(frame),
+                    Some(None) => self.lost_frames += 1,
+                    None => {
+                        self.lost_frames += advance as u64;
+                        break;
+                    }
+                }
+                advance -= 1;
+            }
+            self.next_recv = Some(next_recv.wrapping_add(offset - window + 1));
+            offset = window - 1;
+        }
+
+        let offset = offset as usize;
+        if self.pending.len() <= offset {
+            self.pending.resize(offset + 1, None);
+        }
+        if self.pending[offset].is_some() {
+            return;
+        }
+        self.pending[offset] = Some(frame);
+
+        while let Some(Some(_)) = self.pending.front() {
+            self.ready
+                .push_back(self.pending.pop_front().unwrap().unwrap());
+            self.next_recv = self.next_recv.map(|n| n.wrapping_add(1));
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! A hard limit on the memory a single connection can hold on to.
+//!
+//! A `MemoryBudget` is shared by everything belonging to one connection: a `BudgetedPacketPool`
+//! charges every packet it hands out against it, a `BudgetedIncoming` admits incoming packets from
+//! the transport only while the budget allows, and a `MessageChannelsBuilder` given the budget
+//! reserves the reliable windows and message buffers of every registered channel.  Reservations
+//! are returned to the budget when the packet or `MessageChannels` holding them is dropped, so a
+//! peer which stops reading, or which floods the connection, runs into the limit instead of growing
+//! server memory without bound.
+//!
+//! What happens when incoming data would exceed the budget is configured by `OverBudget`.
+
+use std::{
+    collections::HashSet,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use futures::Sink;
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketMetadata, PacketPool},
+    packet_multiplexer::PacketChannel,
+};
+
+/// What a `BudgetedIncoming` does with incoming packets which would exceed the budget.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverBudget {
+    /// Fail with `IncomingBudgetError::OverBudget`, which the caller should treat as a reason to
+    /// drop the connection.
+    Error,
+    /// Drop packets on channels which are not marked reliable, reliable packets are still admitted.
+    ///
+    /// Reliable channels bound their own memory use by their receive windows, and dropping their
+    /// packets would only cause them to be resent.
+    DropUnreliable,
+    /// Stop accepting incoming packets until memory is returned to the budget, which pushes back on
+    /// the transport.
+    Backpressure,
+}
+
+/// Returned when a reservation does not fit in the remaining budget.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Error)]
+#[error("memory budget exceeded: {requested} bytes requested with {available} of {limit} bytes available")]
+pub struct BudgetExceeded {
+    pub requested: usize,
+    pub available: usize,
+    pub limit: usize,
+}
+
+/// A shared, cloneable limit on the number of bytes a connection may hold.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget(Arc<State>);
+
+impl MemoryBudget {
+    pub fn new(limit: usize, policy: OverBudget) -> Self {
+        MemoryBudget(Arc::new(State {
+            limit,
+            policy,
+            used: AtomicUsize::new(0),
+            waiting: Mutex::new(Vec::new()),
+        }))
+    }
+
+    pub fn limit(&self) -> usize {
+        self.0.limit
+    }
+
+    pub fn policy(&self) -> OverBudget {
+        self.0.policy
+    }
+
+    /// The number of bytes currently reserved, which may be more than the limit due to
+    /// `MemoryBudget::force_reserve`.
+    pub fn used(&self) -> usize {
+        self.0.used.load(Ordering::Acquire)
+    }
+
+    pub fn available(&self) -> usize {
+        self.limit().saturating_sub(self.used())
+    }
+
+    /// Reserve the given number of bytes if they fit in the remaining budget.
+    pub fn try_reserve(&self, bytes: usize) -> Result<Reservation, BudgetExceeded> {
+        self.0
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.0.limit)
+            })
+            .map_err(|used| BudgetExceeded {
+                requested: bytes,
+                available: self.0.limit.saturating_sub(used),
+                limit: self.0.limit,
+            })?;
+        Ok(self.reservation(bytes))
+    }
+
+    /// Reserve the given number of bytes even if that goes over the limit.
+    ///
+    /// Used for memory which must be allocated regardless, such as outgoing packets.  Going over
+    /// the limit still causes incoming packets to be refused until enough memory is returned.
+    pub fn force_reserve(&self, bytes: usize) -> Reservation {
+        self.0.used.fetch_add(bytes, Ordering::AcqRel);
+        self.reservation(bytes)
+    }
+
+    fn reservation(&self, bytes: usize) -> Reservation {
+        Reservation {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+
+    // Ready once some of the budget is available, registering the waker to be woken on release
+    // otherwise.
+    fn poll_available(&self, cx: &mut Context) -> Poll<()> {
+        if self.available() > 0 {
+            return Poll::Ready(());
+        }
+        let mut waiting = self.0.waiting.lock().unwrap();
+        // Check again while holding the lock, so that a release in between cannot be missed.
+        if self.available() > 0 {
+            return Poll::Ready(());
+        }
+        if !waiting.iter().any(|w| w.will_wake(cx.waker())) {
+            waiting.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    fn release(&self, bytes: usize) {
+        self.0.used.fetch_sub(bytes, Ordering::AcqRel);
+        let waiting = std::mem::take(&mut *self.0.waiting.lock().unwrap());
+        for waker in waiting {
+            waker.wake();
+        }
+    }
+}
+
+/// Bytes reserved from a `MemoryBudget`, which are returned to it on drop.
+#[derive(Debug)]
+pub struct Reservation {
+    budget: MemoryBudget,
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+/// A packet whose capacity is reserved from a `MemoryBudget` for as long as it is alive.
+#[derive(Debug)]
+pub struct BudgetedPacket<P> {
+    packet: P,
+    _reservation: Reservation,
+}
+
+impl<P> BudgetedPacket<P> {
+    pub fn into_inner(self) -> P {
+        self.packet
+    }
+}
+
+impl<P: Packet> Packet for BudgetedPacket<P> {
+    fn capacity(&self) -> usize {
+        self.packet.capacity()
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.packet.resize(len, val)
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.packet.metadata()
+    }
+}
+
+impl<P: Packet> Deref for BudgetedPacket<P> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.packet
+    }
+}
+
+impl<P: Packet> DerefMut for BudgetedPacket<P> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.packet
+    }
+}
+
+/// A `PacketPool` which charges the capacity of every packet it hands out to a `MemoryBudget`.
+///
+/// Packets acquired through `PacketPool::acquire` are always handed out, even over budget, since
+/// the channels acquiring them have no way to handle failure.  Incoming packets should instead go
+/// through a `BudgetedIncoming`, which applies the budget's `OverBudget` policy.
+#[derive(Debug, Clone)]
+pub struct BudgetedPacketPool<P> {
+    pool: P,
+    budget: MemoryBudget,
+}
+
+impl<P: PacketPool> BudgetedPacketPool<P> {
+    pub fn new(pool: P, budget: MemoryBudget) -> Self {
+        BudgetedPacketPool { pool, budget }
+    }
+
+    pub fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    /// The underlying pool, which transports should acquire unbudgeted incoming packets from.
+    pub fn inner(&self) -> &P {
+        &self.pool
+    }
+
+    /// Acquire a packet only if its capacity fits in the remaining budget.
+    pub fn try_acquire(&self) -> Result<BudgetedPacket<P::Packet>, BudgetExceeded> {
+        let packet = self.pool.acquire();
+        let reservation = self.budget.try_reserve(packet.capacity())?;
+        Ok(BudgetedPacket {
+            packet,
+            _reservation: reservation,
+        })
+    }
+}
+
+impl<P: PacketPool> PacketPool for BudgetedPacketPool<P> {
+    type Packet = BudgetedPacket<P::Packet>;
+
+    fn acquire(&self) -> Self::Packet {
+        let packet = self.pool.acquire();
+        let reservation = self.budget.force_reserve(packet.capacity());
+        BudgetedPacket {
+            packet,
+            _reservation: reservation,
+        }
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.pool.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.pool.shrink_to(count);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum IncomingBudgetError<E> {
+    /// Fatal, the connection has exceeded its memory budget with the `OverBudget::Error` policy.
+    #[error(transparent)]
+    OverBudget(BudgetExceeded),
+    /// Error from the wrapped sink.
+    #[error(transparent)]
+    Incoming(E),
+}
+
+/// Admits packets from a transport into a sink of `BudgetedPacket`s, such as the
+/// `IncomingMultiplexedPackets` of a multiplexer using a `BudgetedPacketPool`, according to the
+/// budget's `OverBudget` policy.
+///
+/// Packets are expected to start with their multiplexer channel header, which decides whether
+/// they are reliable for `OverBudget::DropUnreliable`.
+pub struct BudgetedIncoming<S> {
+    incoming: S,
+    budget: MemoryBudget,
+    reliable_channels: HashSet<PacketChannel>,
+    dropped: u64,
+}
+
+impl<S> BudgetedIncoming<S> {
+    pub fn new(incoming: S, budget: MemoryBudget) -> Self {
+        BudgetedIncoming {
+            incoming,
+            budget,
+            reliable_channels: HashSet::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Mark the given channels as reliable, so that `OverBudget::DropUnreliable` keeps admitting
+    /// their packets.
+    pub fn with_reliable_channels(
+        mut self,
+        channels: impl IntoIterator<Item = PacketChannel>,
+    ) -> Self {
+        self.reliable_channels.extend(channels);
+        self
+    }
+
+    /// The number of packets dropped for being over budget.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn into_inner(self) -> S {
+        self.incoming
+    }
+}
+
+impl<S, P> Sink<P> for BudgetedIncoming<S>
+where
+    S: Sink<BudgetedPacket<P>> + Unpin,
+    P: Packet,
+{
+    type Error = IncomingBudgetError<S::Error>;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if self.budget.policy() == OverBudget::Backpressure
+            && self.budget.poll_available(cx).is_pending()
+        {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.incoming)
+            .poll_ready(cx)
+            .map_err(IncomingBudgetError::Incoming)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: P) -> Result<(), Self::Error> {
+        let reservation = match self.budget.policy() {
+            // `poll_ready` has waited for room, so at most one packet goes over the limit.
+            OverBudget::Backpressure => self.budget.force_reserve(packet.capacity()),
+            OverBudget::Error => self
+                .budget
+                .try_reserve(packet.capacity())
+                .map_err(IncomingBudgetError::OverBudget)?,
+            OverBudget::DropUnreliable => match self.budget.try_reserve(packet.capacity()) {
+                Ok(reservation) => reservation,
+                Err(_)
+                    if packet
+                        .first()
+                        .is_some_and(|c| self.reliable_channels.contains(c)) =>
+                {
+                    self.budget.force_reserve(packet.capacity())
+                }
+                Err(_) => {
+                    self.dropped += 1;
+                    debug_event!(
+                        channel = packet.first().copied(),
+                        "dropped unreliable packet over memory budget"
+                    );
+                    return Ok(());
+                }
+            },
+        };
+        Pin::new(&mut self.incoming)
+            .start_send(BudgetedPacket {
+                packet,
+                _reservation: reservation,
+            })
+            .map_err(IncomingBudgetError::Incoming)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.incoming)
+            .poll_flush(cx)
+            .map_err(IncomingBudgetError::Incoming)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.incoming)
+            .poll_close(cx)
+            .map_err(IncomingBudgetError::Incoming)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    limit: usize,
+    policy: OverBudget,
+    used: AtomicUsize,
+    waiting: Mutex<Vec<Waker>>,
+}
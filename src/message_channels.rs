@@ -2,14 +2,17 @@ use std::{
     any::{type_name, Any, TypeId},
     collections::{hash_map, HashMap, HashSet},
     error::Error,
+    mem,
+    panic::AssertUnwindSafe,
+    time::Duration,
 };
 
 use futures::{
     channel::mpsc,
-    future::{self, BoxFuture, RemoteHandle},
+    future::{self, BoxFuture},
     select,
     stream::FuturesUnordered,
-    FutureExt, StreamExt, TryFutureExt,
+    FutureExt, StreamExt,
 };
 use rustc_hash::FxHashMap;
 use serde::{de::DeserializeOwned, Serialize};
@@ -18,17 +21,21 @@ use thiserror::Error;
 use crate::{
     channel_builder::ChannelBuilder,
     event_watch,
-    packet::PacketPool,
-    packet_multiplexer::{ChannelStatistics, PacketChannel, PacketMultiplexer},
+    heartbeat::{self, Heartbeat, HeartbeatMonitor},
+    memory_budget::{BudgetExceeded, MemoryBudget, Reservation},
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{ChannelStatistics, PacketChannel, PacketMultiplexer, HEADER_LEN},
     reliable_channel,
-    runtime::Runtime,
-    unreliable_channel,
+    runtime::{JoinHandle, Runtime, TaskFailed},
+    unreliable_channel::{self, OverflowPolicy, ShedPolicy, MAX_MESSAGE_LEN},
+    work_budget::WorkBudget,
 };
 
 // TODO: Message channels are currently always full-duplex, because the unreliable / reliable
 // channels backing them are always full-duplex.  We could add configuration to limit a channel to
 // send or receive only, and to error if the remote sends to a send-only channel.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MessageChannelSettings {
     pub channel: PacketChannel,
     pub channel_mode: MessageChannelMode,
@@ -40,7 +47,77 @@ pub struct MessageChannelSettings {
     pub packet_buffer_size: usize,
 }
 
+impl MessageChannelSettings {
+    /// Check that these settings are usable for a channel sending packets of up to `packet_len`
+    /// bytes, including the settings of the channel mode.
+    pub fn validate(&self, packet_len: usize) -> Result<(), SettingsError> {
+        if self.message_buffer_size == 0 || self.packet_buffer_size == 0 {
+            return Err(SettingsError::ZeroBufferSize);
+        }
+        match &self.channel_mode {
+            MessageChannelMode::Unreliable {
+                settings,
+                max_message_len,
+            } => {
+                settings.validate(packet_len)?;
+                // Every unreliable message has a 2 byte length prefix, and must fit in one packet.
+                let max_len = packet_len.saturating_sub(2).min(MAX_MESSAGE_LEN as usize);
+                if *max_message_len == 0 {
+                    return Err(SettingsError::ZeroMaxMessageLen);
+                } else if *max_message_len as usize > max_len {
+                    return Err(SettingsError::MaxMessageLenTooLarge {
+                        max_message_len: *max_message_len,
+                        max_len,
+                    });
+                }
+            }
+            MessageChannelMode::Reliable {
+                settings,
+                max_message_len: max_len,
+            }
+            | MessageChannelMode::Compressed {
+                settings,
+                max_chunk_len: max_len,
+            } => {
+                settings.validate(packet_len)?;
+                if *max_len == 0 {
+                    return Err(SettingsError::ZeroMaxMessageLen);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// An estimate of the bytes a channel with these settings holds on to for its buffers, when its
+    /// messages are `message_size` bytes in memory.
+    ///
+    /// This covers the mpsc message buffers, the serialization buffers and, for reliable channels,
+    /// the send and receive windows, but not packets, which are charged by a `BudgetedPacketPool`.
+    pub fn memory_usage(&self, message_size: usize) -> usize {
+        let message_buffers = 2 * self.message_buffer_size * message_size;
+        let channel_buffers = match &self.channel_mode {
+            MessageChannelMode::Unreliable {
+                max_message_len, ..
+            } => *max_message_len as usize,
+            MessageChannelMode::Reliable {
+                settings,
+                max_message_len: max_len,
+            }
+            | MessageChannelMode::Compressed {
+                settings,
+                max_chunk_len: max_len,
+            } => {
+                settings.recv_window_size as usize
+                    + settings.send_window_size as usize
+                    + 2 * (2 + *max_len as usize)
+            }
+        };
+        message_buffers + channel_buffers
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageChannelMode {
     Unreliable {
         settings: unreliable_channel::Settings,
@@ -56,6 +133,161 @@ pub enum MessageChannelMode {
     },
 }
 
+/// Channel defaults for a connection over a particular kind of transport, chosen for its MTU and
+/// typical round trip time, to be used as a starting point instead of tuning every setting.
+///
+/// Packet pools should produce packets of `packet_len` bytes, which fit in a single datagram of the
+/// transport with room to spare for encryption and other packet wrappers.  The settings of every
+/// channel are derived from the preset with `TransportPreset::unreliable`,
+/// `TransportPreset::reliable` and `TransportPreset::compressed`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransportPreset {
+    /// The packet length the packet pool should use.
+    pub packet_len: usize,
+    pub unreliable: unreliable_channel::Settings,
+    pub reliable: reliable_channel::Settings,
+    /// The maximum message length of reliable channels, and the maximum chunk length of compressed
+    /// channels.  Unreliable messages are limited to what fits in one packet.
+    pub max_message_len: u16,
+    pub message_buffer_size: usize,
+    pub packet_buffer_size: usize,
+}
+
+impl TransportPreset {
+    /// For UDP over the internet, with packets that fit the minimum IPv6 MTU, and round trip times
+    /// around 100ms.
+    pub const UDP_INTERNET: TransportPreset = TransportPreset {
+        packet_len: 1200,
+        unreliable: unreliable_channel::Settings {
+            bandwidth: 65536,
+            burst_bandwidth: 8192,
+        },
+        reliable: reliable_channel::Settings {
+            bandwidth: 65536,
+            burst_bandwidth: 8192,
+            recv_window_size: 65536,
+            send_window_size: 65536,
+            init_send: 4096,
+            resend_time: Duration::from_millis(50),
+            initial_rtt: Duration::from_millis(100),
+            max_rtt: Duration::from_millis(2000),
+            rtt_update_factor: 0.1,
+            rtt_resend_factor: 1.5,
+        },
+        max_message_len: 8192,
+        message_buffer_size: 64,
+        packet_buffer_size: 64,
+    };
+
+    /// For unordered, unreliable WebRTC data channels, whose SCTP and DTLS headers leave less room
+    /// in a datagram than plain UDP, and which are usually relayed or browser based with slower
+    /// round trips.
+    pub const WEBRTC: TransportPreset = TransportPreset {
+        packet_len: 1150,
+        unreliable: unreliable_channel::Settings {
+            bandwidth: 32768,
+            burst_bandwidth: 4096,
+        },
+        reliable: reliable_channel::Settings {
+            bandwidth: 32768,
+            burst_bandwidth: 4096,
+            recv_window_size: 32768,
+            send_window_size: 32768,
+            init_send: 2048,
+            resend_time: Duration::from_millis(75),
+            initial_rtt: Duration::from_millis(150),
+            max_rtt: Duration::from_millis(3000),
+            rtt_update_factor: 0.1,
+            rtt_resend_factor: 1.5,
+        },
+        max_message_len: 4096,
+        message_buffer_size: 32,
+        packet_buffer_size: 32,
+    };
+
+    /// For UDP on a local network, with packets that fit a 1500 byte Ethernet MTU, round trip
+    /// times of a few milliseconds and plentiful bandwidth.
+    pub const LAN: TransportPreset = TransportPreset {
+        packet_len: 1400,
+        unreliable: unreliable_channel::Settings {
+            bandwidth: 1 << 20,
+            burst_bandwidth: 65536,
+        },
+        reliable: reliable_channel::Settings {
+            bandwidth: 1 << 20,
+            burst_bandwidth: 65536,
+            recv_window_size: 262144,
+            send_window_size: 262144,
+            init_send: 16384,
+            resend_time: Duration::from_millis(10),
+            initial_rtt: Duration::from_millis(5),
+            max_rtt: Duration::from_millis(250),
+            rtt_update_factor: 0.1,
+            rtt_resend_factor: 1.5,
+        },
+        max_message_len: 16384,
+        message_buffer_size: 128,
+        packet_buffer_size: 128,
+    };
+
+    /// Settings for an unreliable channel, whose messages fit in a single packet.
+    pub fn unreliable(&self, channel: PacketChannel) -> MessageChannelSettings {
+        // The multiplexer header and the 2 byte length prefix of every message.
+        let max_message_len = self
+            .packet_len
+            .saturating_sub(HEADER_LEN + 2)
+            .min(MAX_MESSAGE_LEN as usize) as u16;
+        self.settings(
+            channel,
+            MessageChannelMode::Unreliable {
+                settings: self.unreliable.clone(),
+                max_message_len,
+            },
+        )
+    }
+
+    pub fn reliable(&self, channel: PacketChannel) -> MessageChannelSettings {
+        self.settings(
+            channel,
+            MessageChannelMode::Reliable {
+                settings: self.reliable.clone(),
+                max_message_len: self.max_message_len,
+            },
+        )
+    }
+
+    pub fn compressed(&self, channel: PacketChannel) -> MessageChannelSettings {
+        self.settings(
+            channel,
+            MessageChannelMode::Compressed {
+                settings: self.reliable.clone(),
+                max_chunk_len: self.max_message_len,
+            },
+        )
+    }
+
+    fn settings(
+        &self,
+        channel: PacketChannel,
+        channel_mode: MessageChannelMode,
+    ) -> MessageChannelSettings {
+        MessageChannelSettings {
+            channel,
+            channel_mode,
+            message_buffer_size: self.message_buffer_size,
+            packet_buffer_size: self.packet_buffer_size,
+        }
+    }
+}
+
+// The unreliable channel of a heartbeat, whose pings and pongs are tiny and infrequent.
+const HEARTBEAT_BUFFER_SIZE: usize = 8;
+const HEARTBEAT_CHANNEL_SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+    bandwidth: 4096,
+    burst_bandwidth: 1024,
+};
+
 pub trait ChannelMessage: Serialize + DeserializeOwned + Send + Sync + 'static {}
 
 impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> ChannelMessage for T {}
@@ -68,6 +300,34 @@ pub enum ChannelAlreadyRegistered {
     Channel,
 }
 
+/// Returned by `MessageChannelSettings::validate` when settings would keep a channel from working.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SettingsError {
+    #[error("message and packet buffer sizes must be nonzero")]
+    ZeroBufferSize,
+    #[error("max message length must be nonzero")]
+    ZeroMaxMessageLen,
+    #[error("max message length of {max_message_len} bytes exceeds the {max_len} bytes that fit in a packet")]
+    MaxMessageLenTooLarge {
+        max_message_len: u16,
+        max_len: usize,
+    },
+    #[error("invalid unreliable channel settings: {0}")]
+    Unreliable(#[from] unreliable_channel::SettingsError),
+    #[error("invalid reliable channel settings: {0}")]
+    Reliable(#[from] reliable_channel::SettingsError),
+}
+
+#[derive(Debug, Error)]
+pub enum RegisterError {
+    #[error(transparent)]
+    AlreadyRegistered(#[from] ChannelAlreadyRegistered),
+    #[error(transparent)]
+    InvalidSettings(#[from] SettingsError),
+    #[error(transparent)]
+    OverBudget(#[from] BudgetExceeded),
+}
+
 pub type TaskError = Box<dyn Error + Send + Sync>;
 
 #[derive(Debug, Error)]
@@ -86,6 +346,13 @@ where
     pool: P,
     channels: HashSet<PacketChannel>,
     register_fns: HashMap<TypeId, (&'static str, MessageChannelSettings, RegisterFn<R, P>)>,
+    memory_budget: Option<MemoryBudget>,
+    reservations: Vec<Reservation>,
+    shed_policies: HashMap<PacketChannel, ShedPolicy>,
+    overflow_policies: HashMap<PacketChannel, OverflowPolicy>,
+    work_budget: Option<WorkBudget>,
+    preset: Option<TransportPreset>,
+    heartbeat: Option<(PacketChannel, heartbeat::Settings)>,
 }
 
 impl<R, P> MessageChannelsBuilder<R, P>
@@ -99,7 +366,104 @@ where
             pool,
             channels: HashSet::new(),
             register_fns: HashMap::new(),
+            memory_budget: None,
+            reservations: Vec::new(),
+            shed_policies: HashMap::new(),
+            overflow_policies: HashMap::new(),
+            work_budget: None,
+            preset: None,
+            heartbeat: None,
+        }
+    }
+
+    /// Like `MessageChannelsBuilder::new`, for a connection over the given kind of transport.
+    ///
+    /// The preset is available from `MessageChannelsBuilder::preset` to derive the settings of
+    /// each registered message type, and the packet pool should produce packets of
+    /// `TransportPreset::packet_len` bytes.
+    pub fn with_preset(runtime: R, pool: P, preset: TransportPreset) -> Self {
+        let packet_len = pool.acquire().capacity();
+        if packet_len > preset.packet_len {
+            warn_event!(
+                packet_len,
+                preset_packet_len = preset.packet_len,
+                "packets are larger than the transport preset allows, and may be dropped"
+            );
+        }
+        MessageChannelsBuilder {
+            preset: Some(preset),
+            ..Self::new(runtime, pool)
+        }
+    }
+
+    /// With `TransportPreset::UDP_INTERNET`.
+    pub fn for_udp_internet(runtime: R, pool: P) -> Self {
+        Self::with_preset(runtime, pool, TransportPreset::UDP_INTERNET)
+    }
+
+    /// With `TransportPreset::WEBRTC`.
+    pub fn for_webrtc(runtime: R, pool: P) -> Self {
+        Self::with_preset(runtime, pool, TransportPreset::WEBRTC)
+    }
+
+    /// With `TransportPreset::LAN`.
+    pub fn for_lan(runtime: R, pool: P) -> Self {
+        Self::with_preset(runtime, pool, TransportPreset::LAN)
+    }
+
+    /// The transport preset the builder was created with, if any.
+    pub fn preset(&self) -> Option<&TransportPreset> {
+        self.preset.as_ref()
+    }
+
+    /// Set the `ShedPolicy` of the unreliable channel on the given packet channel, so that its
+    /// messages are shed rather than queued when its bandwidth limit is saturated.
+    ///
+    /// Has no effect on reliable channels, which can never drop messages.
+    pub fn set_shed_policy(&mut self, channel: PacketChannel, policy: ShedPolicy) {
+        self.shed_policies.insert(channel, policy);
+    }
+
+    /// Set the `OverflowPolicy` of the unreliable channel on the given packet channel, so that its
+    /// packets are dropped rather than waiting when its outgoing packet buffer is full.
+    ///
+    /// Has no effect on reliable channels.
+    pub fn set_overflow_policy(&mut self, channel: PacketChannel, policy: OverflowPolicy) {
+        self.overflow_policies.insert(channel, policy);
+    }
+
+    /// Reserve the buffers of every channel registered after this call from the given budget, as
+    /// estimated by `MessageChannelSettings::memory_usage`.
+    ///
+    /// The reservations are held by the built `MessageChannels` and returned when it is dropped.
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.memory_budget = Some(budget);
+    }
+
+    /// Run a `Heartbeat` on its own unreliable channel on the given packet channel, so that the
+    /// built `MessageChannels` measures round trip time, jitter and loss, from
+    /// `MessageChannels::heartbeat`, even while the connection is otherwise idle.
+    ///
+    /// The remote must also run a heartbeat on the same channel.  Errors if the packet channel is
+    /// already in use, or if a heartbeat is already set.
+    pub fn set_heartbeat(
+        &mut self,
+        channel: PacketChannel,
+        settings: heartbeat::Settings,
+    ) -> Result<(), ChannelAlreadyRegistered> {
+        if self.heartbeat.is_some() || !self.channels.insert(channel) {
+            return Err(ChannelAlreadyRegistered::Channel);
         }
+        self.heartbeat = Some((channel, settings));
+        Ok(())
+    }
+
+    /// Set the `WorkBudget` of every reliable channel, so that a burst of incoming packets on one
+    /// connection cannot starve other connections sharing the executor.
+    ///
+    /// Unreliable channels already yield whenever their buffer of received messages fills up.
+    pub fn set_work_budget(&mut self, budget: WorkBudget) {
+        self.work_budget = Some(budget);
     }
 }
 
@@ -117,6 +481,34 @@ where
     pub fn register<M: ChannelMessage>(
         &mut self,
         settings: MessageChannelSettings,
+    ) -> Result<(), ChannelAlreadyRegistered> {
+        // Reserved even over budget, use `try_register` to have exceeding it reported.
+        let reservation = self
+            .memory_budget
+            .as_ref()
+            .map(|budget| budget.force_reserve(settings.memory_usage(mem::size_of::<M>())));
+        self.insert::<M>(settings, reservation)
+    }
+
+    /// Like `MessageChannelsBuilder::register`, but first validates the settings against the
+    /// packet length of the packet pool, so that invalid settings are reported here rather than
+    /// causing a panic or a stalled channel once the `MessageChannels` is built.
+    pub fn try_register<M: ChannelMessage>(
+        &mut self,
+        settings: MessageChannelSettings,
+    ) -> Result<(), RegisterError> {
+        settings.validate(self.pool.acquire().capacity())?;
+        let reservation = match &self.memory_budget {
+            Some(budget) => Some(budget.try_reserve(settings.memory_usage(mem::size_of::<M>()))?),
+            None => None,
+        };
+        Ok(self.insert::<M>(settings, reservation)?)
+    }
+
+    fn insert<M: ChannelMessage>(
+        &mut self,
+        settings: MessageChannelSettings,
+        reservation: Option<Reservation>,
     ) -> Result<(), ChannelAlreadyRegistered> {
         if !self.channels.insert(settings.channel) {
             return Err(ChannelAlreadyRegistered::Channel);
@@ -126,6 +518,7 @@ where
             hash_map::Entry::Occupied(_) => Err(ChannelAlreadyRegistered::MessageType),
             hash_map::Entry::Vacant(vacant) => {
                 vacant.insert((type_name::<M>(), settings, register_message_type::<R, P, M>));
+                self.reservations.extend(reservation);
                 Ok(())
             }
         }
@@ -135,38 +528,112 @@ where
     /// types via channels on the given packet multiplexer.
     pub fn build(self, multiplexer: &mut PacketMultiplexer<P::Packet>) -> MessageChannels {
         let mut channel_builder = ChannelBuilder::new(self.runtime, self.pool);
+        channel_builder.work_budget = self.work_budget;
         let mut channels_map = ChannelsMap::default();
-        let mut tasks: FuturesUnordered<_> = self
+        let shed_policies = self.shed_policies;
+        let overflow_policies = self.overflow_policies;
+        let tasks: Vec<_> = self
             .register_fns
             .into_iter()
             .map(|(_, (type_name, settings, register_fn))| {
-                register_fn(
+                // Everything a message type's channel does, including any task spawned by the
+                // channel itself, happens in a span naming the channel and message type.
+                #[cfg(feature = "tracing")]
+                let span = tracing::debug_span!(
+                    "message_channel",
+                    channel = settings.channel,
+                    message = type_name
+                );
+                #[cfg(feature = "tracing")]
+                let _entered = span.enter();
+
+                channel_builder.shed_policy = shed_policies
+                    .get(&settings.channel)
+                    .copied()
+                    .unwrap_or_default();
+                channel_builder.overflow_policy = overflow_policies
+                    .get(&settings.channel)
+                    .copied()
+                    .unwrap_or_default();
+                let task = register_fn(
                     settings,
                     multiplexer,
                     &mut channel_builder,
                     &mut channels_map,
+                );
+                #[cfg(feature = "tracing")]
+                let task = tracing::Instrument::instrument(task, span.clone());
+                (type_name, task)
+            })
+            .collect();
+
+        let mut heartbeat_monitor = None;
+        let heartbeat = self.heartbeat.map(|(channel, settings)| {
+            #[cfg(feature = "tracing")]
+            let span = tracing::debug_span!("message_channel", channel, message = "heartbeat");
+            #[cfg(feature = "tracing")]
+            let _entered = span.enter();
+
+            channel_builder.shed_policy = ShedPolicy::default();
+            channel_builder.overflow_policy = OverflowPolicy::default();
+            let (unreliable, _) = channel_builder
+                .open_unreliable_channel(
+                    multiplexer,
+                    channel,
+                    HEARTBEAT_BUFFER_SIZE,
+                    HEARTBEAT_CHANNEL_SETTINGS,
                 )
-                .map_err(move |error| ChannelTaskError { type_name, error })
+                .expect("duplicate packet channel");
+            let heartbeat = Heartbeat::new(channel_builder.runtime.clone(), settings);
+            heartbeat_monitor = Some(heartbeat.monitor());
+            let task: ChannelTask = async move {
+                heartbeat
+                    .run(unreliable)
+                    .await
+                    .map_err(|err| Box::new(err) as TaskError)
+            }
+            .boxed();
+            #[cfg(feature = "tracing")]
+            let task = tracing::Instrument::instrument(task, span.clone());
+            ("heartbeat", task)
+        });
+
+        let mut tasks: FuturesUnordered<_> = tasks
+            .into_iter()
+            .chain(heartbeat)
+            .map(|(type_name, task)| {
+                // Catch panics per message type, so that the error can name the message type whose
+                // task panicked.
+                AssertUnwindSafe(task).catch_unwind().map(move |res| {
+                    let error = match res {
+                        Ok(Ok(())) => panic!("channel tasks only return errors"),
+                        Ok(Err(error)) => error,
+                        Err(panic) => Box::new(TaskFailed::from_panic(panic)),
+                    };
+                    ChannelTaskError { type_name, error }
+                })
             })
             .collect();
 
-        let (remote, remote_handle) = async move {
+        let task = channel_builder.runtime.spawn_with_handle(async move {
             match tasks.next().await {
                 None => ChannelTaskError {
                     type_name: "none",
                     error: "no channel tasks to run".to_owned().into(),
                 },
-                Some(Ok(())) => panic!("channel tasks only return errors"),
-                Some(Err(err)) => err,
+                Some(err) => {
+                    warn_event!(message = err.type_name, error = %err.error, "channel task failed");
+                    err
+                }
             }
-        }
-        .remote_handle();
-        channel_builder.runtime.spawn(remote);
+        });
 
         MessageChannels {
             disconnected: false,
-            task: remote_handle,
+            task,
             channels: channels_map,
+            heartbeat: heartbeat_monitor,
+            _reservations: self.reservations,
         }
     }
 }
@@ -200,8 +667,10 @@ pub enum TryAsyncMessageError {
 #[derive(Debug)]
 pub struct MessageChannels {
     disconnected: bool,
-    task: RemoteHandle<ChannelTaskError>,
+    task: JoinHandle<ChannelTaskError>,
     channels: ChannelsMap,
+    heartbeat: Option<HeartbeatMonitor>,
+    _reservations: Vec<Reservation>,
 }
 
 impl MessageChannels {
@@ -221,7 +690,10 @@ impl MessageChannels {
     /// return that error.
     pub async fn recv_err(self) -> ChannelTaskError {
         drop(self.channels);
-        self.task.await
+        self.task.await.unwrap_or_else(|failed| ChannelTaskError {
+            type_name: "none",
+            error: Box::new(failed),
+        })
     }
 
     /// Send the given message on the channel associated with its message type.
@@ -341,13 +813,14 @@ impl MessageChannels {
         Ok(if self.disconnected {
             None
         } else {
-            match channels.incoming_receiver.try_next() {
-                Ok(None) => {
-                    self.disconnected = true;
+            match channels.incoming_receiver.try_recv() {
+                Ok(msg) => Some(msg),
+                Err(err) => {
+                    if err.is_closed() {
+                        self.disconnected = true;
+                    }
                     None
                 }
-                Ok(Some(msg)) => Some(msg),
-                Err(_) => None,
             }
         })
     }
@@ -384,6 +857,12 @@ impl MessageChannels {
         }
     }
 
+    /// The round trip time, jitter and loss estimates of the heartbeat, if one was set with
+    /// `MessageChannelsBuilder::set_heartbeat`.
+    pub fn heartbeat(&self) -> Option<&HeartbeatMonitor> {
+        self.heartbeat.as_ref()
+    }
+
     pub fn statistics<M: ChannelMessage>(&self) -> &ChannelStatistics {
         self.try_statistics::<M>().unwrap()
     }
@@ -509,9 +988,12 @@ where
                             channel.send(&outgoing).await?;
                         }
                         Next::Flush => {
-                            while let Ok(outgoing) = outgoing_message_receiver.try_next() {
-                                let outgoing = outgoing.ok_or(ChannelDisconnected)?;
-                                channel.send(&outgoing).await?;
+                            loop {
+                                match outgoing_message_receiver.try_recv() {
+                                    Ok(outgoing) => channel.send(&outgoing).await?,
+                                    Err(err) if err.is_closed() => Err(ChannelDisconnected)?,
+                                    Err(_) => break,
+                                }
                             }
                             channel.flush().await?;
                         }
@@ -555,9 +1037,12 @@ where
                             channel.send(&outgoing).await?;
                         }
                         Next::Flush => {
-                            while let Ok(outgoing) = outgoing_message_receiver.try_next() {
-                                let outgoing = outgoing.ok_or(ChannelDisconnected)?;
-                                channel.send(&outgoing).await?;
+                            loop {
+                                match outgoing_message_receiver.try_recv() {
+                                    Ok(outgoing) => channel.send(&outgoing).await?,
+                                    Err(err) if err.is_closed() => Err(ChannelDisconnected)?,
+                                    Err(_) => break,
+                                }
                             }
                             channel.flush().await?;
                         }
@@ -601,9 +1086,12 @@ where
                             channel.send(&outgoing).await?;
                         }
                         Next::Flush => {
-                            while let Ok(outgoing) = outgoing_message_receiver.try_next() {
-                                let outgoing = outgoing.ok_or(ChannelDisconnected)?;
-                                channel.send(&outgoing).await?;
+                            loop {
+                                match outgoing_message_receiver.try_recv() {
+                                    Ok(outgoing) => channel.send(&outgoing).await?,
+                                    Err(err) if err.is_closed() => Err(ChannelDisconnected)?,
+                                    Err(_) => break,
+                                }
                             }
                             channel.flush().await?;
                         }
@@ -617,7 +1105,7 @@ where
 
     channels_map.insert(ChannelSet::<M> {
         outgoing_sender: outgoing_message_sender,
-        flush_sender: flush_sender,
+        flush_sender,
         incoming_receiver: incoming_message_receiver,
         statistics,
     });
@@ -0,0 +1,185 @@
+//! Exports internal counters through the `metrics` facade, to whatever recorder (such as a
+//! Prometheus exporter) the application installs.
+//!
+//! With the `metrics` feature enabled, every `PacketMultiplexer` channel and `ReliableChannel`
+//! records the metrics named below.  Metric handles are registered when a channel is opened, so
+//! the recorder must be installed before then.  Packet pool occupancy is only recorded for pools
+//! wrapped in a `MeteredPacketPool`.
+
+use core::ops::{Deref, DerefMut};
+
+use ::metrics::{counter, gauge, histogram, Counter, Gauge, Histogram};
+
+use crate::{
+    packet::{Packet, PacketMetadata, PacketPool},
+    packet_multiplexer::DropReason,
+};
+
+/// Counter of packets received on each multiplexer channel, labeled with `channel`.
+pub const INCOMING_PACKETS: &str = "turbulence_incoming_packets_total";
+/// Counter of bytes received on each multiplexer channel, labeled with `channel`.  Does not
+/// include the multiplexer's channel header.
+pub const INCOMING_BYTES: &str = "turbulence_incoming_bytes_total";
+/// Counter of packets sent on each multiplexer channel, labeled with `channel`.
+pub const OUTGOING_PACKETS: &str = "turbulence_outgoing_packets_total";
+/// Counter of bytes sent on each multiplexer channel, labeled with `channel`.  Does not include
+/// the multiplexer's channel header.
+pub const OUTGOING_BYTES: &str = "turbulence_outgoing_bytes_total";
+
+/// Counter of incoming packets dropped on each multiplexer channel, labeled with `channel` and the
+/// drop `reason`, as given by `DropReason::as_str`.
+pub const DROPPED_PACKETS: &str = "turbulence_dropped_packets_total";
+
+/// Histogram of round trip times measured by every `ReliableChannel`, in seconds.
+pub const RELIABLE_RTT: &str = "turbulence_reliable_rtt_seconds";
+/// Counter of packets retransmitted by every `ReliableChannel`.
+pub const RELIABLE_RETRANSMITS: &str = "turbulence_reliable_retransmits_total";
+/// Counter of data bytes retransmitted by every `ReliableChannel`.
+pub const RELIABLE_RETRANSMITTED_BYTES: &str = "turbulence_reliable_retransmitted_bytes_total";
+
+/// Gauge of the packets acquired from a `MeteredPacketPool` which have not yet been dropped,
+/// labeled with the pool's `pool` name.
+pub const POOL_PACKETS_IN_USE: &str = "turbulence_pool_packets_in_use";
+
+pub(crate) struct ChannelMetrics {
+    incoming_packets: Counter,
+    incoming_bytes: Counter,
+    outgoing_packets: Counter,
+    outgoing_bytes: Counter,
+    dropped: Vec<Counter>,
+}
+
+impl ChannelMetrics {
+    pub(crate) fn new(channel: u8) -> Self {
+        let channel = channel.to_string();
+        ChannelMetrics {
+            incoming_packets: counter!(INCOMING_PACKETS, "channel" => channel.clone()),
+            incoming_bytes: counter!(INCOMING_BYTES, "channel" => channel.clone()),
+            outgoing_packets: counter!(OUTGOING_PACKETS, "channel" => channel.clone()),
+            outgoing_bytes: counter!(OUTGOING_BYTES, "channel" => channel.clone()),
+            dropped: DropReason::ALL
+                .iter()
+                .map(|reason| {
+                    counter!(DROPPED_PACKETS, "channel" => channel.clone(), "reason" => reason.as_str())
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn mark_incoming_packet(&self, len: u64) {
+        self.incoming_packets.increment(1);
+        self.incoming_bytes.increment(len);
+    }
+
+    pub(crate) fn mark_outgoing_packet(&self, len: u64) {
+        self.outgoing_packets.increment(1);
+        self.outgoing_bytes.increment(len);
+    }
+
+    pub(crate) fn mark_dropped(&self, reason: DropReason) {
+        self.dropped[reason as usize].increment(1);
+    }
+}
+
+pub(crate) struct ReliableMetrics {
+    rtt: Histogram,
+    retransmits: Counter,
+    retransmitted_bytes: Counter,
+}
+
+impl ReliableMetrics {
+    pub(crate) fn new() -> Self {
+        ReliableMetrics {
+            rtt: histogram!(RELIABLE_RTT),
+            retransmits: counter!(RELIABLE_RETRANSMITS),
+            retransmitted_bytes: counter!(RELIABLE_RETRANSMITTED_BYTES),
+        }
+    }
+
+    pub(crate) fn mark_rtt(&self, rtt: f64) {
+        self.rtt.record(rtt);
+    }
+
+    pub(crate) fn mark_retransmit(&self, len: u32) {
+        self.retransmits.increment(1);
+        self.retransmitted_bytes.increment(len as u64);
+    }
+}
+
+/// Wraps a `PacketPool`, recording the number of its packets in use to the
+/// `POOL_PACKETS_IN_USE` gauge.
+#[derive(Debug, Clone)]
+pub struct MeteredPacketPool<P> {
+    pool: P,
+    in_use: Gauge,
+}
+
+impl<P> MeteredPacketPool<P> {
+    /// Wrap the given pool, labeling its gauge with the given name.
+    pub fn new(name: &'static str, pool: P) -> Self {
+        MeteredPacketPool {
+            pool,
+            in_use: gauge!(POOL_PACKETS_IN_USE, "pool" => name),
+        }
+    }
+}
+
+impl<P: PacketPool> PacketPool for MeteredPacketPool<P> {
+    type Packet = MeteredPacket<P::Packet>;
+
+    fn acquire(&self) -> Self::Packet {
+        self.in_use.increment(1.0);
+        MeteredPacket {
+            packet: self.pool.acquire(),
+            in_use: self.in_use.clone(),
+        }
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.pool.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.pool.shrink_to(count);
+    }
+}
+
+#[derive(Debug)]
+pub struct MeteredPacket<P> {
+    packet: P,
+    in_use: Gauge,
+}
+
+impl<P: Packet> Packet for MeteredPacket<P> {
+    fn capacity(&self) -> usize {
+        self.packet.capacity()
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.packet.resize(len, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.packet.metadata()
+    }
+}
+
+impl<P: Packet> Deref for MeteredPacket<P> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.packet
+    }
+}
+
+impl<P: Packet> DerefMut for MeteredPacket<P> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.packet
+    }
+}
+
+impl<P> Drop for MeteredPacket<P> {
+    fn drop(&mut self) {
+        self.in_use.decrement(1.0);
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::VecDeque,
+};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    message_channels::{
+        ChannelMessage, ChannelTaskError, MessageChannelsDisconnected, MessageTypeUnregistered,
+        TryAsyncMessageError,
+    },
+    packet_multiplexer::{ChannelBuffers, ChannelStatistics, PacketChannel},
+};
+
+/// A stand-in for `MessageChannels` with the same methods, which receives scripted messages and
+/// records sent messages instead of using a network connection, so that code using
+/// `MessageChannels` can be unit tested.
+///
+/// Message types are registered with `MockMessageChannels::register`, and messages to receive are
+/// queued with `MockMessageChannels::push_incoming`, to be returned by `recv` in order.  Sent
+/// messages are kept until taken with `MockMessageChannels::take_sent`.  A full outgoing buffer
+/// can be simulated with `MockMessageChannels::set_send_capacity`, and a lost connection with
+/// `MockMessageChannels::disconnect`.
+///
+/// The mock never waits.  `async_send` ignores the send capacity, and `async_recv` with no scripted
+/// message left behaves as though the connection was lost, rather than waiting forever.  Channel
+/// statistics always stay at zero.
+#[derive(Debug, Default)]
+pub struct MockMessageChannels {
+    disconnected: bool,
+    channels: FxHashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+#[derive(Debug)]
+struct MockChannel<M> {
+    incoming: VecDeque<M>,
+    sent: Vec<M>,
+    send_capacity: Option<usize>,
+    flushes: usize,
+    statistics: ChannelStatistics,
+}
+
+impl MockMessageChannels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a message type as though it was sent on the given channel.
+    ///
+    /// # Panics
+    /// Panics if this message type is already registered.
+    pub fn register<M: ChannelMessage>(&mut self, channel: PacketChannel) -> &mut Self {
+        let mock = MockChannel::<M> {
+            incoming: VecDeque::new(),
+            sent: Vec::new(),
+            send_capacity: None,
+            flushes: 0,
+            statistics: ChannelStatistics::detached(channel, ChannelBuffers::new(0)),
+        };
+        assert!(
+            self.channels
+                .insert(TypeId::of::<M>(), Box::new(mock))
+                .is_none(),
+            "message type {} registered twice",
+            type_name::<M>()
+        );
+        self
+    }
+
+    /// Queue a message to be received after any already queued.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn push_incoming<M: ChannelMessage>(&mut self, message: M) -> &mut Self {
+        self.mock_mut::<M>().unwrap().incoming.push_back(message);
+        self
+    }
+
+    /// Queue a sequence of messages to be received, in order, after any already queued.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn extend_incoming<M: ChannelMessage>(
+        &mut self,
+        messages: impl IntoIterator<Item = M>,
+    ) -> &mut Self {
+        self.mock_mut::<M>().unwrap().incoming.extend(messages);
+        self
+    }
+
+    /// The number of queued messages of this type not yet received.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn pending_incoming<M: ChannelMessage>(&self) -> usize {
+        self.mock::<M>().unwrap().incoming.len()
+    }
+
+    /// Take every message of this type sent since the last call, oldest first.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn take_sent<M: ChannelMessage>(&mut self) -> Vec<M> {
+        std::mem::take(&mut self.mock_mut::<M>().unwrap().sent)
+    }
+
+    /// The number of times messages of this type have been flushed.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn flushes<M: ChannelMessage>(&self) -> usize {
+        self.mock::<M>().unwrap().flushes
+    }
+
+    /// Make `send` return its message, as for a full outgoing buffer, once the given number of
+    /// sent messages of this type are waiting to be taken.  `None`, the default, never fills up.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn set_send_capacity<M: ChannelMessage>(&mut self, capacity: Option<usize>) -> &mut Self {
+        self.mock_mut::<M>().unwrap().send_capacity = capacity;
+        self
+    }
+
+    /// Put the mock permanently into the disconnected state, as though a network task failed.
+    pub fn disconnect(&mut self) {
+        self.disconnected = true;
+    }
+
+    /// Like `MessageChannels::is_connected`.
+    pub fn is_connected(&self) -> bool {
+        !self.disconnected
+    }
+
+    /// Like `MessageChannels::recv_err`, always returns an error for the simulated disconnection.
+    pub async fn recv_err(self) -> ChannelTaskError {
+        ChannelTaskError {
+            type_name: "none",
+            error: "mock message channels disconnected".to_owned().into(),
+        }
+    }
+
+    /// Like `MessageChannels::send`.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn send<M: ChannelMessage>(&mut self, message: M) -> Option<M> {
+        self.try_send(message).unwrap()
+    }
+
+    /// Like `MessageChannels::try_send`.
+    pub fn try_send<M: ChannelMessage>(
+        &mut self,
+        message: M,
+    ) -> Result<Option<M>, MessageTypeUnregistered> {
+        let disconnected = self.disconnected;
+        let mock = self.mock_mut::<M>()?;
+        Ok(
+            if disconnected
+                || mock
+                    .send_capacity
+                    .is_some_and(|capacity| mock.sent.len() >= capacity)
+            {
+                Some(message)
+            } else {
+                mock.sent.push(message);
+                None
+            },
+        )
+    }
+
+    /// Like `MessageChannels::async_send`, but ignores the send capacity.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub async fn async_send<M: ChannelMessage>(
+        &mut self,
+        message: M,
+    ) -> Result<(), MessageChannelsDisconnected> {
+        self.try_async_send(message).await.map_err(|e| match e {
+            TryAsyncMessageError::Unregistered(e) => panic!("{}", e),
+            TryAsyncMessageError::Disconnected(e) => e,
+        })
+    }
+
+    /// Like `MessageChannels::try_async_send`, but ignores the send capacity.
+    pub async fn try_async_send<M: ChannelMessage>(
+        &mut self,
+        message: M,
+    ) -> Result<(), TryAsyncMessageError> {
+        let disconnected = self.disconnected;
+        let mock = self.mock_mut::<M>()?;
+        if disconnected {
+            Err(MessageChannelsDisconnected.into())
+        } else {
+            mock.sent.push(message);
+            Ok(())
+        }
+    }
+
+    /// Like `MessageChannels::flush`.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn flush<M: ChannelMessage>(&mut self) {
+        self.try_flush::<M>().unwrap();
+    }
+
+    /// Like `MessageChannels::try_flush`.
+    pub fn try_flush<M: ChannelMessage>(&mut self) -> Result<(), MessageTypeUnregistered> {
+        self.mock_mut::<M>()?.flushes += 1;
+        Ok(())
+    }
+
+    /// Like `MessageChannels::recv`.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub fn recv<M: ChannelMessage>(&mut self) -> Option<M> {
+        self.try_recv().unwrap()
+    }
+
+    /// Like `MessageChannels::try_recv`.
+    pub fn try_recv<M: ChannelMessage>(&mut self) -> Result<Option<M>, MessageTypeUnregistered> {
+        let disconnected = self.disconnected;
+        let mock = self.mock_mut::<M>()?;
+        Ok(if disconnected {
+            None
+        } else {
+            mock.incoming.pop_front()
+        })
+    }
+
+    /// Like `MessageChannels::async_recv`, but disconnects once no scripted message is left.
+    ///
+    /// # Panics
+    /// Panics if this message type is not registered.
+    pub async fn async_recv<M: ChannelMessage>(
+        &mut self,
+    ) -> Result<M, MessageChannelsDisconnected> {
+        self.try_async_recv().await.map_err(|e| match e {
+            TryAsyncMessageError::Unregistered(e) => panic!("{}", e),
+            TryAsyncMessageError::Disconnected(e) => e,
+        })
+    }
+
+    /// Like `MessageChannels::try_async_recv`, but disconnects once no scripted message is left.
+    pub async fn try_async_recv<M: ChannelMessage>(&mut self) -> Result<M, TryAsyncMessageError> {
+        match self.try_recv()? {
+            Some(message) => Ok(message),
+            None => {
+                self.disconnected = true;
+                Err(MessageChannelsDisconnected.into())
+            }
+        }
+    }
+
+    /// Like `MessageChannels::statistics`, the statistics are always zero.
+    pub fn statistics<M: ChannelMessage>(&self) -> &ChannelStatistics {
+        self.try_statistics::<M>().unwrap()
+    }
+
+    /// Like `MessageChannels::try_statistics`.
+    pub fn try_statistics<M: ChannelMessage>(
+        &self,
+    ) -> Result<&ChannelStatistics, MessageTypeUnregistered> {
+        Ok(&self.mock::<M>()?.statistics)
+    }
+
+    fn mock<M: ChannelMessage>(&self) -> Result<&MockChannel<M>, MessageTypeUnregistered> {
+        Ok(self
+            .channels
+            .get(&TypeId::of::<M>())
+            .ok_or(MessageTypeUnregistered)?
+            .downcast_ref()
+            .unwrap())
+    }
+
+    fn mock_mut<M: ChannelMessage>(
+        &mut self,
+    ) -> Result<&mut MockChannel<M>, MessageTypeUnregistered> {
+        Ok(self
+            .channels
+            .get_mut(&TypeId::of::<M>())
+            .ok_or(MessageTypeUnregistered)?
+            .downcast_mut()
+            .unwrap())
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    serde_channel::{
+        self, Format, ReliableSerdeChannel, ReliableSerdeTypedChannel, UnreliableSerdeChannel,
+        UnreliableSerdeTypedChannel,
+    },
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+#[derive(Debug, Error)]
+pub enum MsgpackError {
+    #[error("MessagePack serialization error: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+    #[error("MessagePack deserialization error: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+}
+
+/// The MessagePack data format.
+///
+/// Structs are serialized as maps keyed by field name rather than as arrays, which is what most
+/// MessagePack implementations in other languages expect.  Either representation is accepted when
+/// deserializing.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Msgpack;
+
+impl Format for Msgpack {
+    type Error = MsgpackError;
+
+    fn serialize<T: Serialize + ?Sized>(buffer: &mut [u8], msg: &T) -> Result<usize, MsgpackError> {
+        let len = buffer.len();
+        let mut w = buffer;
+        rmp_serde::encode::write_named(&mut w, msg)?;
+        Ok(len - w.len())
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(buffer: &'a [u8]) -> Result<T, MsgpackError> {
+        Ok(rmp_serde::from_slice(buffer)?)
+    }
+}
+
+pub type SendError = serde_channel::SendError<MsgpackError>;
+pub type RecvError = serde_channel::RecvError<MsgpackError>;
+pub type Error = serde_channel::Error<MsgpackError>;
+
+/// An `UnreliableChannel` which sends messages serialized as MessagePack.
+pub type UnreliableMsgpackChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeChannel<Msgpack, R, P, I, O>;
+
+pub type UnreliableMsgpackTypedChannel<
+    T,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeTypedChannel<T, Msgpack, R, P, I, O>;
+
+/// A `ReliableChannel` which sends messages serialized as MessagePack, each prefixed with its
+/// length.
+pub type ReliableMsgpackChannel = ReliableSerdeChannel<Msgpack>;
+
+pub type ReliableMsgpackTypedChannel<T> = ReliableSerdeTypedChannel<T, Msgpack>;
@@ -0,0 +1,169 @@
+use alloc::{boxed::Box, collections::VecDeque};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::Stream;
+
+use crate::{packet::Packet, runtime::Timer};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The maximum time a packet is delayed by pacing.  Every burst of packets is spread out
+    /// evenly over this long, so it should usually be the game's tick interval.
+    pub interval: Duration,
+    /// The maximum number of packets held for pacing.  Once this many packets are queued, no more
+    /// are taken from the inner stream until some are sent.
+    pub max_queue: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: Duration::from_micros(16_667),
+            max_queue: 256,
+        }
+    }
+}
+
+/// Wrap a stream of outgoing packets, usually the `OutgoingMultiplexedPackets` of a multiplexer,
+/// so that bursts of packets are spread out over time rather than sent all at once.
+pub fn paced<T, S>(timer: T, settings: Settings, outgoing: S) -> Paced<T, S>
+where
+    T: Timer,
+    S: Stream + Unpin,
+    S::Item: Packet,
+{
+    Paced::new(timer, settings, outgoing)
+}
+
+/// A `Stream` which paces the packets of an inner stream, created by `paced`.
+///
+/// A tick's worth of packets written at once would otherwise be sent in a microburst, which can
+/// overflow the small queues of consumer routers and cause packet loss.  Instead, packets are
+/// queued as soon as they are available, and released from the queue at a rate which sends each
+/// queued packet no later than `Settings::interval` after it was queued.  A burst of packets is
+/// thus spaced evenly across the interval, while packets which arrive after the connection has
+/// been idle are sent immediately.
+pub struct Paced<T: Timer, S: Stream> {
+    timer: T,
+    settings: Settings,
+    inner: S,
+    inner_done: bool,
+    queue: VecDeque<(T::Instant, S::Item)>,
+    queued_bytes: usize,
+    // The time and length of the most recently released packet.
+    last_sent: Option<(T::Instant, usize)>,
+    sleep: Option<Pin<Box<T::Sleep>>>,
+}
+
+// No field is ever pinned, queued packets and the inner stream are only accessed by `&mut`.
+impl<T: Timer, S: Stream> Unpin for Paced<T, S> {}
+
+impl<T, S> Paced<T, S>
+where
+    T: Timer,
+    S: Stream + Unpin,
+    S::Item: Packet,
+{
+    pub fn new(timer: T, settings: Settings, outgoing: S) -> Self {
+        Paced {
+            timer,
+            settings,
+            inner: outgoing,
+            inner_done: false,
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+            last_sent: None,
+            sleep: None,
+        }
+    }
+
+    /// The number of packets currently held for pacing.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    // How much longer the packet at the front of the queue must wait to be released.
+    //
+    // The front packet is released once the time since the last release is at least the time the
+    // last packet would take to send at the rate which sends every queued byte in the time left
+    // before the front packet's deadline.
+    fn release_delay(&self, queued_at: T::Instant) -> Duration {
+        let (last_sent, last_len) = match self.last_sent {
+            Some(last_sent) => last_sent,
+            None => return Duration::ZERO,
+        };
+
+        let since_last = self.timer.elapsed(last_sent).as_secs_f64();
+        let remaining = self
+            .settings
+            .interval
+            .saturating_sub(self.timer.elapsed(queued_at))
+            .as_secs_f64();
+        let last_len = last_len as f64;
+        let queued = self.queued_bytes as f64;
+        if queued + last_len == 0.0 {
+            return Duration::ZERO;
+        }
+
+        // Solves `since_last + wait == last_len * (remaining - wait) / queued` for `wait`.
+        let wait = (last_len * remaining - since_last * queued) / (queued + last_len);
+        Duration::from_secs_f64(wait.max(0.0))
+    }
+}
+
+impl<T, S> Stream for Paced<T, S>
+where
+    T: Timer,
+    S: Stream + Unpin,
+    S::Item: Packet,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        let this = &mut *self;
+
+        while !this.inner_done && this.queue.len() < this.settings.max_queue {
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    this.queued_bytes += packet.len();
+                    this.queue.push_back((this.timer.now(), packet));
+                    // More queued bytes means a higher rate, so the release time must be
+                    // recalculated.
+                    this.sleep = None;
+                }
+                Poll::Ready(None) => this.inner_done = true,
+                Poll::Pending => break,
+            }
+        }
+
+        let queued_at = match this.queue.front() {
+            Some((queued_at, _)) => *queued_at,
+            None if this.inner_done => return Poll::Ready(None),
+            None => return Poll::Pending,
+        };
+
+        if this.sleep.is_none() {
+            let delay = this.release_delay(queued_at);
+            if delay > Duration::ZERO {
+                this.sleep = Some(Box::pin(this.timer.sleep(delay)));
+            }
+        }
+        if let Some(sleep) = &mut this.sleep {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.sleep = None;
+        }
+
+        let (_, packet) = this.queue.pop_front().unwrap();
+        this.queued_bytes -= packet.len();
+        this.last_sent = Some((this.timer.now(), packet.len()));
+        Poll::Ready(Some(packet))
+    }
+}
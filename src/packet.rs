@@ -1,4 +1,7 @@
-use std::ops::{Deref, DerefMut};
+use core::{
+    any::Any,
+    ops::{Deref, DerefMut},
+};
 
 /// The maximum usable packet size by `turbulence`.
 ///
@@ -6,6 +9,21 @@ use std::ops::{Deref, DerefMut};
 /// than this, `turbulence` may not be able to use the entire packet capacity otherwise.
 pub const MAX_PACKET_LEN: u16 = 32768;
 
+/// The version of the `turbulence` wire format.
+///
+/// This covers the framing of every built-in protocol: the multiplexer channel header, unreliable
+/// message coalescing, reliable data, acknowledgement, urgent and feedback packets, the length
+/// prefixes of the reliable message channels and the handshake hello.  Peers with the same wire
+/// version interoperate regardless of their crate version, and any change to these bytes
+/// increments it.
+/// The handshake checks it, failing with `HandshakeError::WireVersionMismatch`.
+///
+/// The exact bytes of each format are documented by the test vectors in `conformance`.
+pub const WIRE_VERSION: u16 = 3;
+
+/// Opaque metadata attached to a packet, see `Packet::metadata`.
+pub type PacketMetadata = dyn Any + Send + Sync;
+
 /// A trait for packet buffers used by `turbulence`.
 pub trait Packet: Deref<Target = [u8]> + DerefMut {
     /// Static capacity of this packet
@@ -37,6 +55,15 @@ pub trait Packet: Deref<Target = [u8]> + DerefMut {
     fn as_mut_slice(&mut self) -> &mut [u8] {
         self.deref_mut()
     }
+
+    /// Opaque metadata attached to an incoming packet by the transport, such as its source address,
+    /// receive time or ECN bits, see `packet_metadata::MetaPacket`.
+    ///
+    /// Packet wrappers forward this to the packet they wrap, so the metadata survives
+    /// demultiplexing and decryption.  Packets have no metadata by default.
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        None
+    }
 }
 
 /// Trait for packet allocation and pooling.
@@ -49,4 +76,15 @@ pub trait PacketPool {
     type Packet: Packet;
 
     fn acquire(&self) -> Self::Packet;
+
+    /// Make sure at least `count` packets are available to be acquired without allocating, for
+    /// pools which recycle packets.  Does nothing by default.
+    ///
+    /// Servers can call this at the start of a match, so that gameplay does not cause allocation
+    /// spikes.
+    fn prewarm(&self, _count: usize) {}
+
+    /// Release idle packets until at most `count` are kept for reuse, for pools which recycle
+    /// packets.  Does nothing by default.
+    fn shrink_to(&self, _count: usize) {}
 }
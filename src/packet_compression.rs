@@ -0,0 +1,294 @@
+use std::{
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{ready, Sink, Stream};
+use snap::raw::{decompress_len, max_compress_len, Decoder as SnapDecoder, Encoder as SnapEncoder};
+use thiserror::Error;
+
+use crate::packet::{Packet, PacketMetadata, PacketPool};
+
+/// The size of the format header at the start of every compressed packet.
+pub const HEADER_LEN: usize = 1;
+
+const UNCOMPRESSED: u8 = 0;
+const SNAPPY: u8 = 1;
+
+/// A wrapper over a `Packet` that reserves space for the compression header.
+#[derive(Debug)]
+pub struct CompressedPacket<P>(P);
+
+impl<P> Packet for CompressedPacket<P>
+where
+    P: Packet,
+{
+    fn capacity(&self) -> usize {
+        self.0.capacity().saturating_sub(HEADER_LEN)
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.0.resize(len + HEADER_LEN, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
+    }
+}
+
+impl<P> Deref for CompressedPacket<P>
+where
+    P: Packet,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0[HEADER_LEN..]
+    }
+}
+
+impl<P> DerefMut for CompressedPacket<P>
+where
+    P: Packet,
+{
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0[HEADER_LEN..]
+    }
+}
+
+/// A packet pool for uncompressed packets, which produces `CompressedPacket`s with room to be
+/// compressed in place.
+#[derive(Debug, Clone)]
+pub struct CompressedPacketPool<P>(P);
+
+impl<P> CompressedPacketPool<P> {
+    pub fn new(packet_pool: P) -> Self {
+        CompressedPacketPool(packet_pool)
+    }
+}
+
+impl<P> PacketPool for CompressedPacketPool<P>
+where
+    P: PacketPool,
+{
+    type Packet = CompressedPacket<P::Packet>;
+
+    fn acquire(&self) -> CompressedPacket<P::Packet> {
+        let mut packet = self.0.acquire();
+        packet.resize(HEADER_LEN, 0);
+        CompressedPacket(packet)
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
+    }
+}
+
+impl<P> From<P> for CompressedPacketPool<P> {
+    fn from(pool: P) -> CompressedPacketPool<P> {
+        CompressedPacketPool(pool)
+    }
+}
+
+/// Error returned when decompressing a packet, decompression errors are not fatal.
+#[derive(Debug, Error)]
+pub enum DecompressError {
+    #[error("packet is too short to have a compression header")]
+    TooShort,
+    #[error("packet has an unknown compression format")]
+    UnknownFormat,
+    #[error("decompressed packet would exceed the packet capacity")]
+    TooBig,
+    #[error("Snappy decompression error: {0}")]
+    SnapError(#[from] snap::Error),
+}
+
+/// Compresses whole outgoing packets with `snap`.
+///
+/// Packets which would not get any smaller are sent uncompressed, so compression never grows a
+/// packet by more than the header.
+pub struct Compressor {
+    encoder: SnapEncoder,
+    buffer: Vec<u8>,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compressor {
+    pub fn new() -> Compressor {
+        Compressor {
+            encoder: SnapEncoder::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Compress a packet in place, returning the underlying packet ready to be sent.
+    pub fn compress<P: Packet>(&mut self, packet: CompressedPacket<P>) -> P {
+        let mut packet = packet.0;
+        packet[0] = UNCOMPRESSED;
+
+        let data = &packet[HEADER_LEN..];
+        self.buffer.resize(max_compress_len(data.len()), 0);
+        if let Ok(len) = self.encoder.compress(data, &mut self.buffer) {
+            if len < data.len() {
+                packet.resize(HEADER_LEN, 0);
+                packet[0] = SNAPPY;
+                packet.extend(&self.buffer[..len]);
+            }
+        }
+        packet
+    }
+}
+
+/// Decompresses incoming packets compressed by a `Compressor`.
+pub struct Decompressor {
+    decoder: SnapDecoder,
+    buffer: Vec<u8>,
+}
+
+impl Default for Decompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decompressor {
+    pub fn new() -> Decompressor {
+        Decompressor {
+            decoder: SnapDecoder::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Decompress a packet in place.
+    ///
+    /// The packet must have the capacity to hold its decompressed contents, which is always the
+    /// case if the sender and receiver use packets of the same capacity.
+    pub fn decompress<P: Packet>(
+        &mut self,
+        mut packet: P,
+    ) -> Result<CompressedPacket<P>, DecompressError> {
+        match packet.first() {
+            None => Err(DecompressError::TooShort),
+            Some(&UNCOMPRESSED) => Ok(CompressedPacket(packet)),
+            Some(&SNAPPY) => {
+                let data = &packet[HEADER_LEN..];
+                let len = decompress_len(data)?;
+                if len > packet.capacity().saturating_sub(HEADER_LEN) {
+                    return Err(DecompressError::TooBig);
+                }
+                self.buffer.resize(len, 0);
+                self.decoder.decompress(data, &mut self.buffer)?;
+                packet.resize(HEADER_LEN, 0);
+                packet.extend(&self.buffer);
+                Ok(CompressedPacket(packet))
+            }
+            Some(_) => Err(DecompressError::UnknownFormat),
+        }
+    }
+}
+
+/// Wrap a raw packet stream and sink so that whole packets are compressed.
+///
+/// The returned stream and sink carry `CompressedPacket`s, and are meant to be given to a
+/// `PacketMultiplexer` using a `CompressedPacketPool`, so that the packets of every channel are
+/// compressed, after any coalescing of messages into packets.  Incoming packets that fail to
+/// decompress are dropped.
+///
+/// Compression works best on packets with redundant content, such as serialized game state.  It
+/// should be applied before encryption, since encrypted packets are incompressible.
+pub fn compressed<I, O>(incoming: I, outgoing: O) -> (DecompressStream<I>, CompressSink<O>) {
+    (
+        DecompressStream::new(Decompressor::new(), incoming),
+        CompressSink::new(Compressor::new(), outgoing),
+    )
+}
+
+/// A `Stream` of decompressed packets, created by `compressed`.
+pub struct DecompressStream<I> {
+    decompressor: Decompressor,
+    incoming: I,
+}
+
+impl<I> DecompressStream<I> {
+    pub fn new(decompressor: Decompressor, incoming: I) -> Self {
+        DecompressStream {
+            decompressor,
+            incoming,
+        }
+    }
+}
+
+impl<I, P> Stream for DecompressStream<I>
+where
+    I: Stream<Item = P> + Unpin,
+    P: Packet,
+{
+    type Item = CompressedPacket<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.incoming).poll_next(cx)) {
+                Some(packet) => {
+                    if let Ok(packet) = self.decompressor.decompress(packet) {
+                        return Poll::Ready(Some(packet));
+                    }
+                    debug_event!("dropping packet which failed to decompress");
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// A `Sink` which compresses packets, created by `compressed`.
+pub struct CompressSink<O> {
+    compressor: Compressor,
+    outgoing: O,
+}
+
+impl<O> CompressSink<O> {
+    pub fn new(compressor: Compressor, outgoing: O) -> Self {
+        CompressSink {
+            compressor,
+            outgoing,
+        }
+    }
+}
+
+impl<O, P> Sink<CompressedPacket<P>> for CompressSink<O>
+where
+    O: Sink<P> + Unpin,
+    P: Packet,
+{
+    type Error = O::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_ready(cx)
+    }
+
+    fn start_send(
+        mut self: Pin<&mut Self>,
+        packet: CompressedPacket<P>,
+    ) -> Result<(), Self::Error> {
+        let packet = self.compressor.compress(packet);
+        Pin::new(&mut self.outgoing).start_send(packet)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.outgoing).poll_close(cx)
+    }
+}
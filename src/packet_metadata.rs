@@ -0,0 +1,122 @@
+//! Attach metadata from the transport to incoming packets.
+//!
+//! A transport which knows more about each packet than its contents, such as the address it was
+//! received from, its receive timestamp or its ECN bits, can wrap its packets in a `MetaPacket`.
+//! The metadata then flows with the packet through any packet wrappers, such as a
+//! `PacketMultiplexer`, and can be read back with `Packet::metadata` by whatever finally receives
+//! it, for example with `UnreliableChannel::metadata`.  This lets upper layers apply per-source
+//! logic without keeping a parallel map from packets to their sources.
+
+use core::{
+    any::Any,
+    ops::{Deref, DerefMut},
+};
+
+use crate::packet::{Packet, PacketMetadata, PacketPool};
+
+/// A packet together with metadata of type `M`.
+#[derive(Debug)]
+pub struct MetaPacket<P, M> {
+    packet: P,
+    meta: M,
+}
+
+impl<P, M> MetaPacket<P, M> {
+    pub fn new(packet: P, meta: M) -> Self {
+        MetaPacket { packet, meta }
+    }
+
+    pub fn meta(&self) -> &M {
+        &self.meta
+    }
+
+    pub fn meta_mut(&mut self) -> &mut M {
+        &mut self.meta
+    }
+
+    pub fn into_parts(self) -> (P, M) {
+        (self.packet, self.meta)
+    }
+}
+
+impl<P, M> Packet for MetaPacket<P, M>
+where
+    P: Packet,
+    M: Any + Send + Sync,
+{
+    fn capacity(&self) -> usize {
+        self.packet.capacity()
+    }
+
+    fn resize(&mut self, len: usize, val: u8) {
+        self.packet.resize(len, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        Some(&self.meta)
+    }
+}
+
+impl<P: Packet, M> Deref for MetaPacket<P, M> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.packet
+    }
+}
+
+impl<P: Packet, M> DerefMut for MetaPacket<P, M> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.packet
+    }
+}
+
+/// A packet pool which produces `MetaPacket`s with default metadata, for packets which are not
+/// received from the transport, such as outgoing packets.
+#[derive(Debug, Clone)]
+pub struct MetaPacketPool<P, M> {
+    pool: P,
+    meta: M,
+}
+
+impl<P, M: Default> MetaPacketPool<P, M> {
+    pub fn new(pool: P) -> Self {
+        MetaPacketPool {
+            pool,
+            meta: M::default(),
+        }
+    }
+}
+
+impl<P, M> MetaPacketPool<P, M> {
+    /// Create a pool which produces packets with a clone of the given metadata.
+    pub fn with_meta(pool: P, meta: M) -> Self {
+        MetaPacketPool { pool, meta }
+    }
+
+    /// The underlying pool, which transports should acquire incoming packets from before
+    /// attaching their metadata.
+    pub fn inner(&self) -> &P {
+        &self.pool
+    }
+}
+
+impl<P, M> PacketPool for MetaPacketPool<P, M>
+where
+    P: PacketPool,
+    M: Any + Clone + Send + Sync,
+{
+    type Packet = MetaPacket<P::Packet, M>;
+
+    fn acquire(&self) -> Self::Packet {
+        MetaPacket::new(self.pool.acquire(), self.meta.clone())
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.pool.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.pool.shrink_to(count);
+    }
+}
@@ -1,42 +1,66 @@
 use std::{
-    collections::{hash_map, HashMap},
-    fmt,
+    collections::HashMap,
+    fmt, mem,
     ops::{Deref, DerefMut},
     pin::Pin,
     sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
     task::{Context, Poll},
-    u8,
 };
 
 use futures::{
     channel::mpsc::{self, Receiver, Sender},
+    future, ready,
     stream::SelectAll,
+    task::noop_waker_ref,
     Sink, Stream,
 };
 use rustc_hash::{FxHashMap, FxHashSet};
 use thiserror::Error;
 
-use crate::packet::{Packet, PacketPool};
+#[cfg(feature = "metrics")]
+use crate::metrics::ChannelMetrics;
+use crate::{
+    liveness::Staleness,
+    packet::{Packet, PacketMetadata, PacketPool},
+};
 
 pub type PacketChannel = u8;
 
+/// The size of the channel header at the start of every multiplexed packet.
+pub const HEADER_LEN: usize = 1;
+
+/// The smallest maximum datagram size a `DatagramLimit` can be set to, with room for the channel
+/// header, the 6 byte data header of a `ReliableChannel`, and a single byte of data.
+pub const MIN_DATAGRAM_SIZE: usize = HEADER_LEN + 7;
+
 /// A wrapper over a `Packet` that reserves the first byte for the channel.
+///
+/// The capacity is clamped to the current `DatagramLimit` of the pool the packet was acquired from,
+/// if it has one.
 #[derive(Debug)]
-pub struct MuxPacket<P>(P);
+pub struct MuxPacket<P>(P, Option<DatagramLimit>);
 
 impl<P> Packet for MuxPacket<P>
 where
     P: Packet,
 {
     fn capacity(&self) -> usize {
-        self.0.capacity() - 1
+        let capacity = match &self.1 {
+            Some(limit) => self.0.capacity().min(limit.max_len()),
+            None => self.0.capacity(),
+        };
+        capacity.saturating_sub(HEADER_LEN)
     }
 
     fn resize(&mut self, len: usize, val: u8) {
-        self.0.resize(len + 1, val);
+        self.0.resize(len + HEADER_LEN, val);
+    }
+
+    fn metadata(&self) -> Option<&PacketMetadata> {
+        self.0.metadata()
     }
 }
 
@@ -47,7 +71,7 @@ where
     type Target = [u8];
 
     fn deref(&self) -> &[u8] {
-        &self.0[1..]
+        &self.0[HEADER_LEN..]
     }
 }
 
@@ -56,16 +80,26 @@ where
     P: Packet,
 {
     fn deref_mut(&mut self) -> &mut [u8] {
-        &mut self.0[1..]
+        &mut self.0[HEADER_LEN..]
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct MuxPacketPool<P>(P);
+pub struct MuxPacketPool<P>(P, Option<DatagramLimit>);
 
 impl<P> MuxPacketPool<P> {
     pub fn new(packet_pool: P) -> Self {
-        MuxPacketPool(packet_pool)
+        MuxPacketPool(packet_pool, None)
+    }
+
+    /// Clamp the capacity of the packets acquired from this pool to the given limit, usually the
+    /// `PacketMultiplexer::datagram_limit` of the multiplexer the packets are sent through.
+    pub fn with_datagram_limit(self, limit: DatagramLimit) -> Self {
+        MuxPacketPool(self.0, Some(limit))
+    }
+
+    pub fn datagram_limit(&self) -> Option<&DatagramLimit> {
+        self.1.as_ref()
     }
 }
 
@@ -77,14 +111,71 @@ where
 
     fn acquire(&self) -> MuxPacket<P::Packet> {
         let mut packet = self.0.acquire();
-        packet.resize(1, 0);
-        MuxPacket(packet)
+        packet.resize(HEADER_LEN, 0);
+        MuxPacket(packet, self.1.clone())
+    }
+
+    fn prewarm(&self, count: usize) {
+        self.0.prewarm(count);
+    }
+
+    fn shrink_to(&self, count: usize) {
+        self.0.shrink_to(count);
     }
 }
 
 impl<P> From<P> for MuxPacketPool<P> {
     fn from(pool: P) -> MuxPacketPool<P> {
-        MuxPacketPool(pool)
+        MuxPacketPool::new(pool)
+    }
+}
+
+/// The largest datagram the transport under a `PacketMultiplexer` can currently carry, shared
+/// between the multiplexer, the transport adapter which sets it, and the packet pools of the
+/// multiplexer's channels.
+///
+/// A transport adapter which learns the path MTU, or that it has decreased, sets the new maximum
+/// here, and the capacity of every `MuxPacket` from a pool with this limit is clamped to fit.
+/// Channels size the packets they send by the capacity of the packets they acquire, so they follow
+/// the limit without being reconstructed.  A packet already filled past the new limit when it
+/// decreases is still sent as it is, and reliable channels resend data first sent before a
+/// decrease in pieces which fit.  Messages are never split by an unreliable channel, sending one
+/// which is larger than the new limit returns `SendError::TooBig`.
+///
+/// The maximum is for the whole multiplexed packet, including its channel header.  A transport
+/// with its own per-packet overhead below the multiplexer, such as encryption, should subtract
+/// that first.  A `DatagramLimit` is a cheap handle, and clones share the same limit.
+#[derive(Debug, Clone)]
+pub struct DatagramLimit(Arc<AtomicUsize>);
+
+impl DatagramLimit {
+    /// A limit with no maximum, so that packets are only limited by their own capacity.
+    pub fn new() -> Self {
+        DatagramLimit(Arc::new(AtomicUsize::new(usize::MAX)))
+    }
+
+    /// Set the maximum datagram size in bytes, or remove the maximum with `None`.  A maximum below
+    /// `MIN_DATAGRAM_SIZE` is raised to it, since no smaller packet could carry reliable data.
+    pub fn set_max_datagram_size(&self, max: Option<usize>) {
+        let max = max.map_or(usize::MAX, |max| max.max(MIN_DATAGRAM_SIZE));
+        self.0.store(max, Ordering::Relaxed);
+    }
+
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        match self.max_len() {
+            usize::MAX => None,
+            max => Some(max),
+        }
+    }
+
+    fn max_len(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for DatagramLimit {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -92,6 +183,40 @@ impl<P> From<P> for MuxPacketPool<P> {
 #[error("packet channel has already been opened")]
 pub struct DuplicateChannel;
 
+/// The buffer depths of a multiplexer channel, given when opening it.  A single `usize` converts
+/// into the same depth for both directions.
+///
+/// The incoming buffer holds up to `incoming + 1` packets received for the channel which its
+/// receiver has not yet taken.  When it is full, `IncomingMultiplexedPackets::try_send` returns the
+/// packet with `IncomingTrySendError::IsFull`, which counts it as dropped with
+/// `DropReason::ChannelFull`, and the `Sink` implementation instead waits for room, which stalls
+/// every other channel until then.  `IncomingMultiplexedPackets::ingest_batch` leaves the packet
+/// to the caller to retry, without counting it as dropped.  Every way, the overflow is counted by
+/// `ChannelStatistics::incoming_overflows`.
+///
+/// The outgoing buffer holds up to `outgoing` packets per sender which have not yet been taken
+/// from `OutgoingMultiplexedPackets`.  When it is full, the channel's sender waits for room.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChannelBuffers {
+    pub incoming: usize,
+    pub outgoing: usize,
+}
+
+impl ChannelBuffers {
+    pub fn new(buffer_size: usize) -> Self {
+        ChannelBuffers {
+            incoming: buffer_size,
+            outgoing: buffer_size,
+        }
+    }
+}
+
+impl From<usize> for ChannelBuffers {
+    fn from(buffer_size: usize) -> Self {
+        ChannelBuffers::new(buffer_size)
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct ChannelTotals {
     pub packets: u64,
@@ -102,6 +227,15 @@ pub struct ChannelTotals {
 pub struct ChannelStatistics(Arc<ChannelStatisticsData>);
 
 impl ChannelStatistics {
+    // Statistics for a channel outside of any multiplexer, which are never counted.
+    pub(crate) fn detached(channel: PacketChannel, buffers: ChannelBuffers) -> Self {
+        ChannelStatistics(Arc::new(ChannelStatisticsData::new(
+            channel,
+            buffers,
+            NetworkEvents::new(),
+        )))
+    }
+
     pub fn incoming_totals(&self) -> ChannelTotals {
         ChannelTotals {
             packets: self.0.incoming_packets.load(Ordering::Relaxed),
@@ -115,6 +249,207 @@ impl ChannelStatistics {
             bytes: self.0.outgoing_bytes.load(Ordering::Relaxed),
         }
     }
+
+    /// The buffer depths the channel was opened with.
+    pub fn buffers(&self) -> ChannelBuffers {
+        self.0.buffers
+    }
+
+    /// The number of times an incoming packet found the channel's incoming buffer full, whether
+    /// it was dropped, waited for room, or was left to retry by
+    /// `IncomingMultiplexedPackets::ingest_batch`, see `ChannelBuffers`.  A packet retried more
+    /// than once is counted for each attempt.
+    pub fn incoming_overflows(&self) -> u64 {
+        self.0.incoming_overflows.load(Ordering::Relaxed)
+    }
+
+    /// The number of incoming packets on this channel dropped for the given reason.
+    ///
+    /// Only packets which were actually thrown away are counted, packets left to retry by
+    /// `IncomingMultiplexedPackets::ingest_batch` are not.
+    ///
+    /// Packets for unopened channels are never counted here, they are counted by
+    /// `IncomingMultiplexedPackets::unknown_channel_drops`.
+    pub fn dropped(&self, reason: DropReason) -> u64 {
+        self.0.dropped[reason as usize].load(Ordering::Relaxed)
+    }
+
+    /// Count an incoming packet or message dropped by the channel reading this multiplexer
+    /// channel, such as a duplicate or a message which failed to decode.  Messages which failed to
+    /// decode also publish a `NetworkEvent::DecodeFailure`.
+    pub fn mark_dropped(&self, reason: DropReason) {
+        self.0.mark_dropped(reason);
+    }
+}
+
+/// The reason the multiplexer dropped an incoming packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropReason {
+    /// The packet was for a channel which has not been opened.
+    UnknownChannel,
+    /// The channel's incoming buffer was full.  Such packets are returned from
+    /// `IncomingMultiplexedPackets::try_send` rather than dropped, but every transport drops them.
+    ChannelFull,
+    /// The channel's receiver has been dropped.
+    ChannelClosed,
+    /// The packet or message was a duplicate of one already received.  The multiplexer never
+    /// drops packets for this reason, it is counted by the channel reading the packets with
+    /// `ChannelStatistics::mark_dropped`.
+    Duplicate,
+    /// The packet or message could not be decoded.  Like `DropReason::Duplicate`, this is only
+    /// counted by the channel reading the packets.
+    Malformed,
+    /// The packet arrived faster than the connection's ingress rate limit.  Such packets are
+    /// dropped by an `ingress::IngressLimiter` before reaching the multiplexer, so they are never
+    /// counted by a channel.
+    RateLimited,
+}
+
+impl DropReason {
+    /// Every drop reason.
+    pub const ALL: [DropReason; DROP_REASONS] = [
+        DropReason::UnknownChannel,
+        DropReason::ChannelFull,
+        DropReason::ChannelClosed,
+        DropReason::Duplicate,
+        DropReason::Malformed,
+        DropReason::RateLimited,
+    ];
+
+    /// The name of the reason in snake case, for use as a metric label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DropReason::UnknownChannel => "unknown_channel",
+            DropReason::ChannelFull => "channel_full",
+            DropReason::ChannelClosed => "channel_closed",
+            DropReason::Duplicate => "duplicate",
+            DropReason::Malformed => "malformed",
+            DropReason::RateLimited => "rate_limited",
+        }
+    }
+}
+
+const DROP_REASONS: usize = 6;
+
+/// A table translating between the channel numbers used by a remote peer and the local ones,
+/// installed with `PacketMultiplexer::set_channel_remap`, so that a server can keep serving older
+/// clients whose channels are numbered differently.
+///
+/// Every channel maps to itself unless remapped.
+#[derive(Debug, Clone)]
+pub struct ChannelRemap {
+    to_local: [PacketChannel; 256],
+    to_remote: [PacketChannel; 256],
+}
+
+impl Default for ChannelRemap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChannelRemap {
+    pub fn new() -> ChannelRemap {
+        let mut identity = [0; 256];
+        for (i, channel) in identity.iter_mut().enumerate() {
+            *channel = i as PacketChannel;
+        }
+        ChannelRemap {
+            to_local: identity,
+            to_remote: identity,
+        }
+    }
+
+    /// Deliver incoming packets on the `remote` channel to the `local` channel, and send outgoing
+    /// packets on the `local` channel as the `remote` channel.
+    ///
+    /// The table should stay one to one, so if two channels are swapped both directions must be
+    /// inserted.
+    pub fn insert(&mut self, remote: PacketChannel, local: PacketChannel) -> &mut Self {
+        self.to_local[remote as usize] = local;
+        self.to_remote[local as usize] = remote;
+        self
+    }
+
+    pub fn to_local(&self, remote: PacketChannel) -> PacketChannel {
+        self.to_local[remote as usize]
+    }
+
+    pub fn to_remote(&self, local: PacketChannel) -> PacketChannel {
+        self.to_remote[local as usize]
+    }
+}
+
+/// Callbacks for the packets passing through a `PacketMultiplexer`, installed with
+/// `PacketMultiplexer::set_observer`.
+///
+/// Every method has an empty default implementation, so observers only need to implement the
+/// events they are interested in.  The callbacks are called inline as packets are routed, so they
+/// should be cheap and must not block.  Packet lengths never include the channel header.
+pub trait PacketObserver: Send + Sync {
+    /// Called when an outgoing packet on the given channel is taken from the multiplexer to be
+    /// sent.
+    fn on_packet_sent(&self, _channel: PacketChannel, _len: usize) {}
+
+    /// Called when an incoming packet has been delivered to the given channel.
+    fn on_packet_received(&self, _channel: PacketChannel, _len: usize) {}
+
+    /// Called when an incoming packet for the given channel could not be delivered.
+    fn on_packet_dropped(&self, _channel: PacketChannel, _len: usize, _reason: DropReason) {}
+}
+
+/// A structured diagnostic event, delivered to the subscribers of a `NetworkEvents`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// The receiver of an opened channel has been dropped, so its incoming packets can no longer
+    /// be delivered.  Reported once per channel, when the first such packet arrives.
+    ChannelClosed { channel: PacketChannel },
+    /// An incoming packet arrived for a channel which has not been opened.
+    UnknownChannel { channel: PacketChannel },
+    /// An incoming packet arrived while the channel's incoming buffer was full.
+    Overflow { channel: PacketChannel },
+    /// A packet or message on the channel could not be decoded, reported when
+    /// `ChannelStatistics::mark_dropped` is called with `DropReason::Malformed`.
+    DecodeFailure { channel: PacketChannel },
+    /// A `liveness::Liveness` installed with `Liveness::set_network_events` detected that the
+    /// connection has gone without traffic in one or both directions.
+    KeepaliveTimeout { staleness: Staleness },
+}
+
+/// Delivers `NetworkEvent`s to any number of subscribers, so that applications can react to
+/// problems on a connection as they happen rather than polling counters.
+///
+/// Every `PacketMultiplexer` has one, available from `PacketMultiplexer::network_events`, and it
+/// is a cheap handle whose clones publish to the same subscribers.  Events are never allowed to
+/// slow the connection: a subscriber whose buffer is full misses events until it catches up, and
+/// dropped subscribers are forgotten.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkEvents(Arc<Mutex<Vec<Sender<NetworkEvent>>>>);
+
+impl NetworkEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to every event published after this call, buffering up to `buffer` events.
+    pub fn subscribe(&self, buffer: usize) -> Receiver<NetworkEvent> {
+        let (sender, receiver) = mpsc::channel(buffer);
+        self.0.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Publish an event to every subscriber.
+    pub fn publish(&self, event: NetworkEvent) {
+        let mut subscribers = self.0.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        trace_event!(?event, "network event");
+        subscribers.retain_mut(|sender| match sender.try_send(event) {
+            Ok(()) => true,
+            Err(err) => err.is_full(),
+        });
+    }
 }
 
 /// Routes packets marked with a channel header from a single `Sink` / `Stream` pair to a set of
@@ -125,6 +460,19 @@ impl ChannelStatistics {
 pub struct PacketMultiplexer<P> {
     incoming: HashMap<PacketChannel, ChannelSender<P>>,
     outgoing: SelectAll<ChannelReceiver<P>>,
+    observer: Option<Arc<dyn PacketObserver>>,
+    remap: Option<Arc<ChannelRemap>>,
+    events: NetworkEvents,
+    datagram_limit: DatagramLimit,
+}
+
+impl<P> Default for PacketMultiplexer<P>
+where
+    P: Packet + Unpin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<P> PacketMultiplexer<P>
@@ -135,18 +483,58 @@ where
         PacketMultiplexer {
             incoming: HashMap::new(),
             outgoing: SelectAll::new(),
+            observer: None,
+            remap: None,
+            events: NetworkEvents::new(),
+            datagram_limit: DatagramLimit::new(),
         }
     }
 
+    /// Install an observer, which is called for every packet the started multiplexer sends,
+    /// receives or drops.  Replaces any previously installed observer.
+    pub fn set_observer<O: PacketObserver + 'static>(&mut self, observer: O) {
+        self.observer = Some(Arc::new(observer));
+    }
+
+    /// The events of this multiplexer and its channels, which may be subscribed to at any time,
+    /// before or after the multiplexer is started.
+    pub fn network_events(&self) -> NetworkEvents {
+        self.events.clone()
+    }
+
+    /// The limit on the size of the packets of this multiplexer's channels, for the transport
+    /// adapter to set whenever it learns of a new maximum datagram size.
+    ///
+    /// Channels opened with a `ChannelBuilder` follow this limit, other channels only do if their
+    /// `MuxPacketPool` is given it with `MuxPacketPool::with_datagram_limit`.
+    pub fn datagram_limit(&self) -> DatagramLimit {
+        self.datagram_limit.clone()
+    }
+
+    /// Set the maximum datagram size of the transport, see `DatagramLimit::set_max_datagram_size`.
+    pub fn set_max_datagram_size(&self, max: Option<usize>) {
+        self.datagram_limit.set_max_datagram_size(max);
+    }
+
+    /// Install a table translating the channel numbers in the headers of incoming and outgoing
+    /// packets, replacing any previously installed table.
+    ///
+    /// Channels are always opened, reported and counted by their local number.  Raw channels are
+    /// routed by their remapped number as well, but their packets are never modified.
+    pub fn set_channel_remap(&mut self, remap: ChannelRemap) {
+        self.remap = Some(Arc::new(remap));
+    }
+
     /// Open a multiplexed packet channel, producing a sender for outgoing `MuxPacket`s on this
     /// channel, and a receiver for incoming `MuxPacket`s on this channel.
     ///
-    /// The `buffer_size` parameter controls the buffer size requested when creating the MPSC
-    /// futures channels for the returned `Sender` and `Receiver`.
+    /// The `buffers` parameter sets the depths of the channel's incoming and outgoing packet
+    /// buffers, and what happens when they overflow is described in `ChannelBuffers`.
+    #[allow(clippy::type_complexity)]
     pub fn open_channel(
         &mut self,
         channel: PacketChannel,
-        buffer_size: usize,
+        buffers: impl Into<ChannelBuffers>,
     ) -> Result<
         (
             Sender<MuxPacket<P>>,
@@ -155,43 +543,122 @@ where
         ),
         DuplicateChannel,
     > {
-        let statistics = Arc::new(ChannelStatisticsData::default());
-        match self.incoming.entry(channel) {
-            hash_map::Entry::Occupied(_) => Err(DuplicateChannel),
-            hash_map::Entry::Vacant(vacant) => {
-                let (incoming_sender, incoming_receiver) = mpsc::channel(buffer_size);
-                let (outgoing_sender, outgoing_receiver) = mpsc::channel(buffer_size);
-                vacant.insert(ChannelSender {
-                    sender: incoming_sender,
-                    statistics: Arc::clone(&statistics),
-                });
-                self.outgoing.push(ChannelReceiver {
-                    channel,
-                    receiver: outgoing_receiver,
-                    statistics: Arc::clone(&statistics),
-                });
-                Ok((
-                    outgoing_sender,
-                    incoming_receiver,
-                    ChannelStatistics(statistics),
-                ))
-            }
+        if self.incoming.contains_key(&channel) {
+            return Err(DuplicateChannel);
         }
+        let buffers = buffers.into();
+        let (incoming_sender, incoming_receiver) = mpsc::channel(buffers.incoming);
+        let (outgoing_sender, outgoing_receiver) = mpsc::channel(buffers.outgoing);
+        let statistics = self.insert(
+            channel,
+            buffers,
+            IncomingSender::Mux(incoming_sender),
+            OutgoingReceiver::Mux(outgoing_receiver),
+        );
+        debug_event!(
+            channel,
+            incoming = buffers.incoming,
+            outgoing = buffers.outgoing,
+            "opened packet channel"
+        );
+        Ok((outgoing_sender, incoming_receiver, statistics))
+    }
+
+    /// Open a raw packet channel, whose packets bypass the multiplexer header, so that the
+    /// multiplexer can share a socket with another protocol whose packets all start with the same
+    /// tag byte.
+    ///
+    /// Incoming packets whose first byte is the given channel are delivered unmodified, tag
+    /// included, and outgoing packets are sent unmodified, so they should start with the tag
+    /// themselves if the remote routes them with a multiplexer.  Statistics are counted as for any
+    /// other channel, with the tag byte as the channel header.  Empty outgoing packets are dropped.
+    #[allow(clippy::type_complexity)]
+    pub fn open_raw_channel(
+        &mut self,
+        channel: PacketChannel,
+        buffers: impl Into<ChannelBuffers>,
+    ) -> Result<(Sender<P>, Receiver<P>, ChannelStatistics), DuplicateChannel> {
+        if self.incoming.contains_key(&channel) {
+            return Err(DuplicateChannel);
+        }
+        let buffers = buffers.into();
+        let (incoming_sender, incoming_receiver) = mpsc::channel(buffers.incoming);
+        let (outgoing_sender, outgoing_receiver) = mpsc::channel(buffers.outgoing);
+        let statistics = self.insert(
+            channel,
+            buffers,
+            IncomingSender::Raw(incoming_sender),
+            OutgoingReceiver::Raw(outgoing_receiver),
+        );
+        debug_event!(
+            channel,
+            incoming = buffers.incoming,
+            outgoing = buffers.outgoing,
+            "opened raw packet channel"
+        );
+        Ok((outgoing_sender, incoming_receiver, statistics))
+    }
+
+    /// Returns every opened channel, sorted.
+    pub fn channels(&self) -> Vec<PacketChannel> {
+        let mut channels: Vec<_> = self.incoming.keys().copied().collect();
+        channels.sort_unstable();
+        channels
+    }
+
+    fn insert(
+        &mut self,
+        channel: PacketChannel,
+        buffers: ChannelBuffers,
+        sender: IncomingSender<P>,
+        receiver: OutgoingReceiver<P>,
+    ) -> ChannelStatistics {
+        let statistics = Arc::new(ChannelStatisticsData::new(
+            channel,
+            buffers,
+            self.events.clone(),
+        ));
+        self.incoming.insert(
+            channel,
+            ChannelSender {
+                sender,
+                statistics: Arc::clone(&statistics),
+            },
+        );
+        self.outgoing.push(ChannelReceiver {
+            channel,
+            tag: channel,
+            receiver,
+            statistics: Arc::clone(&statistics),
+        });
+        ChannelStatistics(statistics)
     }
 
     /// Start multiplexing packets to all opened channels.
     ///
     /// Returns an `IncomingMultiplexedPackets` which is a `Sink` for incoming packets, and an
     /// `OutgoingMultiplexedPackets` which is a `Stream` for outgoing packets.
-    pub fn start(self) -> (IncomingMultiplexedPackets<P>, OutgoingMultiplexedPackets<P>) {
+    pub fn start(mut self) -> (IncomingMultiplexedPackets<P>, OutgoingMultiplexedPackets<P>) {
+        if let Some(remap) = &self.remap {
+            for receiver in self.outgoing.iter_mut() {
+                receiver.tag = remap.to_remote(receiver.channel);
+            }
+        }
         (
             IncomingMultiplexedPackets {
                 incoming: self.incoming.into_iter().collect(),
                 to_send: None,
+                to_send_waiting: false,
                 to_flush: FxHashSet::default(),
+                observer: self.observer.clone(),
+                remap: self.remap,
+                events: self.events,
+                unknown_channel_drops: 0,
             },
             OutgoingMultiplexedPackets {
                 outgoing: self.outgoing,
+                observer: self.observer,
+                datagram_limit: self.datagram_limit,
             },
         )
     }
@@ -199,6 +666,8 @@ where
 
 #[derive(Debug, Error)]
 pub enum IncomingError {
+    #[error("packet is empty and has no channel header")]
+    EmptyPacket,
     #[error("packet received for unopened channel")]
     UnknownPacketChannel,
     #[error("channel receiver has been dropped")]
@@ -227,10 +696,7 @@ impl<P> fmt::Debug for IncomingTrySendError<P> {
 
 impl<P> IncomingTrySendError<P> {
     pub fn is_full(&self) -> bool {
-        match self {
-            IncomingTrySendError::IsFull(_) => true,
-            _ => false,
-        }
+        matches!(self, IncomingTrySendError::IsFull(_))
     }
 }
 
@@ -238,36 +704,121 @@ impl<P> IncomingTrySendError<P> {
 pub struct IncomingMultiplexedPackets<P> {
     incoming: FxHashMap<PacketChannel, ChannelSender<P>>,
     to_send: Option<P>,
+    // Whether `to_send` has already been counted as an overflow of its channel.
+    to_send_waiting: bool,
     to_flush: FxHashSet<PacketChannel>,
+    observer: Option<Arc<dyn PacketObserver>>,
+    remap: Option<Arc<ChannelRemap>>,
+    events: NetworkEvents,
+    unknown_channel_drops: u64,
 }
 
 impl<P> IncomingMultiplexedPackets<P>
 where
     P: Packet + Unpin,
 {
+    /// The number of incoming packets dropped because they were for a channel which has not been
+    /// opened.
+    pub fn unknown_channel_drops(&self) -> u64 {
+        self.unknown_channel_drops
+    }
+
     /// Attempt to send the given packet to the appropriate multiplexed channel without blocking.
     ///
     /// If a normal error occurs, returns `IncomingError::Error`, if the destination channel buffer
     /// is full, returns `IncomingTrySendError::IsFull`.
     pub fn try_send(&mut self, packet: P) -> Result<(), IncomingTrySendError<P>> {
-        let channel = packet[0];
-        let incoming = self
-            .incoming
-            .get_mut(&channel)
-            .ok_or(IncomingError::UnknownPacketChannel)?;
+        self.try_deliver(packet, true)
+    }
 
+    // Send a packet to its channel without blocking, a packet for a full channel only counts as
+    // dropped if `drop_full` is true, since otherwise the caller keeps it to retry.
+    fn try_deliver(&mut self, packet: P, drop_full: bool) -> Result<(), IncomingTrySendError<P>> {
+        let channel = self.channel_of(&packet)?;
         let mux_packet_len = (packet.len() - 1) as u64;
-        incoming.sender.try_send(MuxPacket(packet)).map_err(|e| {
-            if e.is_full() {
-                IncomingTrySendError::IsFull(e.into_inner().0)
-            } else {
-                IncomingError::ChannelReceiverDropped.into()
+        let observer = &self.observer;
+        let dropped = |reason| {
+            if let Some(observer) = observer {
+                observer.on_packet_dropped(channel, mux_packet_len as usize, reason);
+            }
+        };
+
+        let incoming = match self.incoming.get_mut(&channel) {
+            Some(incoming) => incoming,
+            None => {
+                debug_event!(channel, "incoming packet for unopened channel");
+                self.unknown_channel_drops += 1;
+                self.events
+                    .publish(NetworkEvent::UnknownChannel { channel });
+                dropped(DropReason::UnknownChannel);
+                return Err(IncomingError::UnknownPacketChannel.into());
             }
-        })?;
+        };
+
+        let statistics = &incoming.statistics;
+        incoming
+            .sender
+            .try_send(packet)
+            .map_err(|packet| match packet {
+                Some(packet) => {
+                    trace_event!(channel, "incoming channel buffer is full");
+                    statistics.mark_incoming_overflow();
+                    if drop_full {
+                        statistics.mark_dropped(DropReason::ChannelFull);
+                        dropped(DropReason::ChannelFull);
+                    }
+                    IncomingTrySendError::IsFull(packet)
+                }
+                None => {
+                    statistics.mark_dropped(DropReason::ChannelClosed);
+                    dropped(DropReason::ChannelClosed);
+                    IncomingError::ChannelReceiverDropped.into()
+                }
+            })?;
         incoming.statistics.mark_incoming_packet(mux_packet_len);
+        trace_event!(channel, len = mux_packet_len, "incoming packet");
+        if let Some(observer) = observer {
+            observer.on_packet_received(channel, mux_packet_len as usize);
+        }
 
         Ok(())
     }
+
+    /// Send a batch of incoming packets to their multiplexed channels without blocking, such as
+    /// the datagrams of a single `recvmmsg` call.
+    ///
+    /// Returns the number of packets delivered.  Packets whose destination channel buffer is full
+    /// are left in `packets` in their original order, so that they can be retried or dropped, and
+    /// every other packet is removed, including those dropped with any other error of `try_send`.
+    ///
+    /// Packets left in `packets` are counted by `ChannelStatistics::incoming_overflows` each time
+    /// they find their channel full, but not as dropped with `DropReason::ChannelFull`, since they
+    /// have not been thrown away.  A transport which gives up on them can pass them to `try_send`
+    /// once more, which counts them as dropped if their channel is still full.
+    pub fn ingest_batch(&mut self, packets: &mut Vec<P>) -> usize {
+        let mut batch = mem::take(packets);
+        let mut delivered = 0;
+        for packet in batch.drain(..) {
+            match self.try_deliver(packet, false) {
+                Ok(()) => delivered += 1,
+                Err(IncomingTrySendError::IsFull(packet)) => packets.push(packet),
+                Err(IncomingTrySendError::Error(_)) => {}
+            }
+        }
+        if packets.is_empty() {
+            *packets = batch;
+        }
+        delivered
+    }
+
+    // The local channel of an incoming packet.
+    fn channel_of(&self, packet: &P) -> Result<PacketChannel, IncomingError> {
+        let channel = *packet.first().ok_or(IncomingError::EmptyPacket)?;
+        Ok(match &self.remap {
+            Some(remap) => remap.to_local(channel),
+            None => channel,
+        })
+    }
 }
 
 impl<P> Sink<P> for IncomingMultiplexedPackets<P>
@@ -278,27 +829,57 @@ where
 
     fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
         if let Some(packet) = self.to_send.take() {
-            let channel = packet[0];
-            let incoming = &mut self
-                .incoming
-                .get_mut(&channel)
-                .ok_or(IncomingError::UnknownPacketChannel)?;
+            let this = &mut *self;
+            let channel = this.channel_of(&packet)?;
+            let mux_packet_len = (packet.len() - 1) as u64;
+            let observer = &this.observer;
+            let dropped = |reason| {
+                if let Some(observer) = observer {
+                    observer.on_packet_dropped(channel, mux_packet_len as usize, reason);
+                }
+            };
+
+            let incoming = match this.incoming.get_mut(&channel) {
+                Some(incoming) => incoming,
+                None => {
+                    debug_event!(channel, "incoming packet for unopened channel");
+                    this.unknown_channel_drops += 1;
+                    this.events
+                        .publish(NetworkEvent::UnknownChannel { channel });
+                    dropped(DropReason::UnknownChannel);
+                    return Poll::Ready(Err(IncomingError::UnknownPacketChannel));
+                }
+            };
+            let statistics = &incoming.statistics;
             match incoming.sender.poll_ready(cx) {
                 Poll::Pending => {
-                    self.to_send = Some(packet);
+                    if !this.to_send_waiting {
+                        trace_event!(channel, "waiting for incoming channel buffer");
+                        statistics.mark_incoming_overflow();
+                        this.to_send_waiting = true;
+                    }
+                    this.to_send = Some(packet);
                     Poll::Pending
                 }
                 Poll::Ready(Ok(())) => {
-                    let mux_packet_len = (packet.len() - 1) as u64;
-                    incoming
-                        .sender
-                        .start_send(MuxPacket(packet))
-                        .map_err(|_| IncomingError::ChannelReceiverDropped)?;
+                    incoming.sender.start_send(packet).map_err(|_| {
+                        statistics.mark_dropped(DropReason::ChannelClosed);
+                        dropped(DropReason::ChannelClosed);
+                        IncomingError::ChannelReceiverDropped
+                    })?;
                     incoming.statistics.mark_incoming_packet(mux_packet_len);
-                    self.to_flush.insert(channel);
+                    trace_event!(channel, len = mux_packet_len, "incoming packet");
+                    if let Some(observer) = observer {
+                        observer.on_packet_received(channel, mux_packet_len as usize);
+                    }
+                    this.to_flush.insert(channel);
                     Poll::Ready(Ok(()))
                 }
-                Poll::Ready(Err(_)) => Poll::Ready(Err(IncomingError::ChannelReceiverDropped)),
+                Poll::Ready(Err(_)) => {
+                    statistics.mark_dropped(DropReason::ChannelClosed);
+                    dropped(DropReason::ChannelClosed);
+                    Poll::Ready(Err(IncomingError::ChannelReceiverDropped))
+                }
             }
         } else {
             Poll::Ready(Ok(()))
@@ -308,6 +889,7 @@ where
     fn start_send(mut self: Pin<&mut Self>, item: P) -> Result<(), Self::Error> {
         assert!(self.to_send.is_none());
         self.to_send = Some(item);
+        self.to_send_waiting = false;
         Ok(())
     }
 
@@ -316,13 +898,11 @@ where
             return Poll::Pending;
         }
         while let Some(&channel) = self.to_flush.iter().next() {
-            let sender = Pin::new(
-                &mut self
-                    .incoming
-                    .get_mut(&channel)
-                    .ok_or(IncomingError::UnknownPacketChannel)?
-                    .sender,
-            );
+            let sender = &mut self
+                .incoming
+                .get_mut(&channel)
+                .ok_or(IncomingError::UnknownPacketChannel)?
+                .sender;
             if sender
                 .poll_flush(cx)
                 .map_err(|_| IncomingError::ChannelReceiverDropped)?
@@ -343,6 +923,67 @@ where
 /// A handle to receive outgoing packets from the multiplexer.
 pub struct OutgoingMultiplexedPackets<P> {
     outgoing: SelectAll<ChannelReceiver<P>>,
+    observer: Option<Arc<dyn PacketObserver>>,
+    datagram_limit: DatagramLimit,
+}
+
+impl<P> OutgoingMultiplexedPackets<P> {
+    /// The `PacketMultiplexer::datagram_limit` of the started multiplexer, for the transport
+    /// sending these packets to keep up to date.
+    pub fn datagram_limit(&self) -> &DatagramLimit {
+        &self.datagram_limit
+    }
+}
+
+impl<P> OutgoingMultiplexedPackets<P>
+where
+    P: Packet + Unpin,
+{
+    /// Move up to `max` outgoing packets which are ready right now into `packets` without
+    /// waiting, so that a transport can send them in one batch, such as with `sendmmsg`.
+    ///
+    /// Returns the number of packets added.  This does not register the current task for wakeup,
+    /// so once it returns fewer than `max` packets the stream should be polled to wait for more,
+    /// or `poll_drain_into` should be used instead.
+    pub fn drain_ready(&mut self, packets: &mut Vec<P>, max: usize) -> usize {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match self.poll_drain_into(&mut cx, packets, max) {
+            Poll::Ready(Some(added)) => added,
+            Poll::Ready(None) | Poll::Pending => 0,
+        }
+    }
+
+    /// Wait for at least one outgoing packet, then move up to `max` outgoing packets which are
+    /// ready into `packets`, so that a transport can gather a burst of packets and submit them in
+    /// one syscall, such as with `sendmmsg` or io_uring.
+    ///
+    /// Returns the number of packets added, or `None` once every channel has been closed and there
+    /// are no packets left.  If `max` is 0, returns `Some(0)` immediately.
+    pub fn poll_drain_into(
+        &mut self,
+        cx: &mut Context,
+        packets: &mut Vec<P>,
+        max: usize,
+    ) -> Poll<Option<usize>> {
+        let mut added = 0;
+        while added < max {
+            match Pin::new(&mut *self).poll_next(cx) {
+                Poll::Ready(Some(packet)) => {
+                    packets.push(packet);
+                    added += 1;
+                }
+                Poll::Ready(None) if added == 0 => return Poll::Ready(None),
+                Poll::Pending if added == 0 => return Poll::Pending,
+                Poll::Ready(None) | Poll::Pending => break,
+            }
+        }
+        Poll::Ready(Some(added))
+    }
+
+    /// The async form of `OutgoingMultiplexedPackets::poll_drain_into`.
+    pub async fn drain_into(&mut self, packets: &mut Vec<P>, max: usize) -> Option<usize> {
+        future::poll_fn(|cx| self.poll_drain_into(cx, packets, max)).await
+    }
 }
 
 impl<P> Stream for OutgoingMultiplexedPackets<P>
@@ -352,59 +993,192 @@ where
     type Item = P;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.outgoing).poll_next(cx)
+        let packet = ready!(Pin::new(&mut self.outgoing).poll_next(cx));
+        if let (Some(observer), Some((channel, packet))) = (&self.observer, &packet) {
+            observer.on_packet_sent(*channel, packet.len() - 1);
+        }
+        Poll::Ready(packet.map(|(_, packet)| packet))
     }
 }
 
 struct ChannelSender<P> {
-    sender: Sender<MuxPacket<P>>,
+    sender: IncomingSender<P>,
     statistics: Arc<ChannelStatisticsData>,
 }
 
+enum IncomingSender<P> {
+    Mux(Sender<MuxPacket<P>>),
+    Raw(Sender<P>),
+}
+
+impl<P> IncomingSender<P> {
+    // On failure, returns the packet if the channel is full, or `None` if the receiver has been
+    // dropped.
+    fn try_send(&mut self, packet: P) -> Result<(), Option<P>> {
+        match self {
+            IncomingSender::Mux(sender) => sender
+                .try_send(MuxPacket(packet, None))
+                .map_err(|e| e.is_full().then(|| e.into_inner().0)),
+            IncomingSender::Raw(sender) => sender
+                .try_send(packet)
+                .map_err(|e| e.is_full().then(|| e.into_inner())),
+        }
+    }
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), mpsc::SendError>> {
+        match self {
+            IncomingSender::Mux(sender) => sender.poll_ready(cx),
+            IncomingSender::Raw(sender) => sender.poll_ready(cx),
+        }
+    }
+
+    fn start_send(&mut self, packet: P) -> Result<(), mpsc::SendError> {
+        match self {
+            IncomingSender::Mux(sender) => sender.start_send(MuxPacket(packet, None)),
+            IncomingSender::Raw(sender) => sender.start_send(packet),
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), mpsc::SendError>> {
+        match self {
+            IncomingSender::Mux(sender) => Pin::new(sender).poll_flush(cx),
+            IncomingSender::Raw(sender) => Pin::new(sender).poll_flush(cx),
+        }
+    }
+}
+
 struct ChannelReceiver<P> {
     channel: PacketChannel,
-    receiver: Receiver<MuxPacket<P>>,
+    // The channel number written to the header of outgoing packets.
+    tag: PacketChannel,
+    receiver: OutgoingReceiver<P>,
     statistics: Arc<ChannelStatisticsData>,
 }
 
+enum OutgoingReceiver<P> {
+    Mux(Receiver<MuxPacket<P>>),
+    Raw(Receiver<P>),
+}
+
 impl<P> Stream for ChannelReceiver<P>
 where
     P: Packet + Unpin,
 {
-    type Item = P;
+    type Item = (PacketChannel, P);
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        match Pin::new(&mut self.receiver).poll_next(cx) {
-            Poll::Ready(Some(packet)) => {
-                let mut packet = packet.0;
-                packet[0] = self.channel;
-                self.statistics
-                    .mark_outgoing_packet((packet.len() - 1) as u64);
-                Poll::Ready(Some(packet))
+        let (channel, tag) = (self.channel, self.tag);
+        let packet = loop {
+            match &mut self.receiver {
+                OutgoingReceiver::Mux(receiver) => {
+                    let packet = ready!(Pin::new(receiver).poll_next(cx));
+                    break packet.map(|packet| {
+                        let mut packet = packet.0;
+                        packet[0] = tag;
+                        packet
+                    });
+                }
+                OutgoingReceiver::Raw(receiver) => match ready!(Pin::new(receiver).poll_next(cx)) {
+                    Some(packet) if packet.is_empty() => {
+                        debug_event!(channel, "dropping empty outgoing raw packet");
+                    }
+                    packet => break packet,
+                },
             }
-            Poll::Ready(None) => Poll::Ready(None),
-            Poll::Pending => Poll::Pending,
+        };
+        if let Some(packet) = &packet {
+            let len = (packet.len() - 1) as u64;
+            self.statistics.mark_outgoing_packet(len);
+            trace_event!(channel, len, "outgoing packet");
         }
+        Poll::Ready(packet.map(|packet| (channel, packet)))
     }
 }
 
-#[derive(Debug, Default)]
 struct ChannelStatisticsData {
     incoming_packets: AtomicU64,
     incoming_bytes: AtomicU64,
 
     outgoing_packets: AtomicU64,
     outgoing_bytes: AtomicU64,
+
+    dropped: [AtomicU64; DROP_REASONS],
+    buffers: ChannelBuffers,
+    incoming_overflows: AtomicU64,
+
+    channel: PacketChannel,
+    events: NetworkEvents,
+    // Whether `NetworkEvent::ChannelClosed` has been published.
+    closed: AtomicBool,
+
+    #[cfg(feature = "metrics")]
+    metrics: ChannelMetrics,
+}
+
+impl fmt::Debug for ChannelStatisticsData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelStatisticsData")
+            .field("incoming_packets", &self.incoming_packets)
+            .field("incoming_bytes", &self.incoming_bytes)
+            .field("outgoing_packets", &self.outgoing_packets)
+            .field("outgoing_bytes", &self.outgoing_bytes)
+            .field("dropped", &self.dropped)
+            .field("buffers", &self.buffers)
+            .field("incoming_overflows", &self.incoming_overflows)
+            .finish()
+    }
 }
 
 impl ChannelStatisticsData {
+    fn new(channel: PacketChannel, buffers: ChannelBuffers, events: NetworkEvents) -> Self {
+        ChannelStatisticsData {
+            incoming_packets: AtomicU64::new(0),
+            incoming_bytes: AtomicU64::new(0),
+            outgoing_packets: AtomicU64::new(0),
+            outgoing_bytes: AtomicU64::new(0),
+            dropped: Default::default(),
+            buffers,
+            incoming_overflows: AtomicU64::new(0),
+            channel,
+            events,
+            closed: AtomicBool::new(false),
+            #[cfg(feature = "metrics")]
+            metrics: ChannelMetrics::new(channel),
+        }
+    }
+
     fn mark_incoming_packet(&self, len: u64) {
         self.incoming_packets.fetch_add(1, Ordering::Relaxed);
         self.incoming_bytes.fetch_add(len, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.metrics.mark_incoming_packet(len);
     }
 
     fn mark_outgoing_packet(&self, len: u64) {
         self.outgoing_packets.fetch_add(1, Ordering::Relaxed);
         self.outgoing_bytes.fetch_add(len, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.metrics.mark_outgoing_packet(len);
+    }
+
+    fn mark_incoming_overflow(&self) {
+        self.incoming_overflows.fetch_add(1, Ordering::Relaxed);
+        self.events.publish(NetworkEvent::Overflow {
+            channel: self.channel,
+        });
+    }
+
+    fn mark_dropped(&self, reason: DropReason) {
+        self.dropped[reason as usize].fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.metrics.mark_dropped(reason);
+        let channel = self.channel;
+        match reason {
+            DropReason::ChannelClosed if !self.closed.swap(true, Ordering::Relaxed) => {
+                self.events.publish(NetworkEvent::ChannelClosed { channel });
+            }
+            DropReason::Malformed => self.events.publish(NetworkEvent::DecodeFailure { channel }),
+            _ => {}
+        }
     }
 }
@@ -0,0 +1,305 @@
+//! Piggybacking of reliable channel acknowledgements on other outgoing packets.
+//!
+//! Every `ReliableChannel` data packet is answered with a separate acknowledgement packet, so on a
+//! busy bidirectional link a large share of packets are tiny acknowledgements.  `piggyback_acks`
+//! wraps the outgoing packets of a multiplexer and, whenever acknowledgements are ready to send at
+//! the same time as other packets, bundles them together into one packet.  Acknowledgements may
+//! be held for up to an `ack_delay` waiting for a packet to ride along with, and with a zero delay
+//! only packets which are ready at the same time are bundled.
+//!
+//! Bundles are sent on a dedicated packet channel, which must not be opened on either multiplexer,
+//! and the remote must split them apart again by wrapping its incoming packets with `unbundle`.
+//! A bundle is the bundle channel followed by one or more entries, each of which is the channel of
+//! the bundled packet, the length of its payload as a u16, then the payload.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{ready, Sink, Stream};
+use rustc_hash::FxHashSet;
+
+use crate::{
+    packet::{Packet, PacketPool},
+    packet_multiplexer::PacketChannel,
+    reliable_channel,
+    runtime::Timer,
+};
+
+// The most acknowledgements held while looking for a packet to piggyback them on.
+const MAX_HELD_ACKS: usize = 64;
+
+const ENTRY_HEADER_LEN: usize = 3;
+
+/// Wrap a stream of outgoing packets, usually the `OutgoingMultiplexedPackets` of a multiplexer, so
+/// that acknowledgements on the given reliable channels are bundled with other ready packets on the
+/// `bundle_channel`.
+///
+/// Acknowledgements are held for at most `ack_delay` waiting for another packet.  The delay adds
+/// directly to the round trip times the remote measures, so it should be small compared to them,
+/// such as a fraction of a tick.
+pub fn piggyback_acks<T, S>(
+    timer: T,
+    ack_delay: Duration,
+    outgoing: S,
+    bundle_channel: PacketChannel,
+    reliable_channels: impl IntoIterator<Item = PacketChannel>,
+) -> PiggybackAcks<T, S>
+where
+    T: Timer,
+    S: Stream + Unpin,
+    S::Item: Packet,
+{
+    PiggybackAcks {
+        timer,
+        ack_delay,
+        inner: outgoing,
+        inner_done: false,
+        bundle_channel,
+        reliable_channels: reliable_channels.into_iter().collect(),
+        acks: VecDeque::new(),
+        held_since: None,
+        sleep: None,
+        piggybacked: 0,
+    }
+}
+
+/// A `Stream` which bundles acknowledgements with other packets, created by `piggyback_acks`.
+pub struct PiggybackAcks<T: Timer, S: Stream> {
+    timer: T,
+    ack_delay: Duration,
+    inner: S,
+    inner_done: bool,
+    bundle_channel: PacketChannel,
+    reliable_channels: FxHashSet<PacketChannel>,
+    acks: VecDeque<S::Item>,
+    // When the oldest held acknowledgement was held.
+    held_since: Option<T::Instant>,
+    sleep: Option<Pin<Box<T::Sleep>>>,
+    piggybacked: u64,
+}
+
+// No field is ever pinned, held packets and the inner stream are only accessed by `&mut`.
+impl<T: Timer, S: Stream> Unpin for PiggybackAcks<T, S> {}
+
+impl<T, S> PiggybackAcks<T, S>
+where
+    T: Timer,
+    S: Stream + Unpin,
+    S::Item: Packet,
+{
+    /// The total number of acknowledgements which were sent as part of another packet, which is
+    /// the number of packets saved.
+    pub fn piggybacked(&self) -> u64 {
+        self.piggybacked
+    }
+
+    fn is_ack(&self, packet: &[u8]) -> bool {
+        packet.len() > 1
+            && self.reliable_channels.contains(&packet[0])
+            && reliable_channel::is_ack(&packet[1..])
+    }
+
+    // Bundle as many held acknowledgements as fit into the given packet, or return it unchanged if
+    // none do.
+    fn attach(&mut self, packet: S::Item) -> S::Item {
+        let packet = self.bundle(packet);
+        if self.acks.is_empty() {
+            self.held_since = None;
+            self.sleep = None;
+        }
+        packet
+    }
+
+    fn bundle(&mut self, mut packet: S::Item) -> S::Item {
+        // Each bundled acknowledgement takes its length plus the two bytes of its entry length.
+        let capacity = packet.capacity();
+        let fits = |len: usize, ack: &S::Item| len + ack.len() + 2 <= capacity;
+        match self.acks.front() {
+            Some(ack) if fits(packet.len() + ENTRY_HEADER_LEN, ack) => {}
+            _ => return packet,
+        }
+
+        let (channel, len) = (packet[0], packet.len() - 1);
+        packet.resize(packet.len() + ENTRY_HEADER_LEN, 0);
+        packet.copy_within(1..1 + len, 1 + ENTRY_HEADER_LEN);
+        packet[0] = self.bundle_channel;
+        packet[1] = channel;
+        LittleEndian::write_u16(&mut packet[2..4], len as u16);
+
+        while let Some(ack) = self.acks.front() {
+            if !fits(packet.len(), ack) {
+                break;
+            }
+            let ack = self.acks.pop_front().unwrap();
+            let mut header = [ack[0], 0, 0];
+            LittleEndian::write_u16(&mut header[1..3], (ack.len() - 1) as u16);
+            packet.extend(&header);
+            packet.extend(&ack[1..]);
+            self.piggybacked += 1;
+        }
+        trace_event!(len = packet.len(), "sending bundled packet");
+        packet
+    }
+}
+
+impl<T, S> Stream for PiggybackAcks<T, S>
+where
+    T: Timer,
+    S: Stream + Unpin,
+    S::Item: Packet,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        let this = &mut *self;
+
+        while !this.inner_done && this.acks.len() < MAX_HELD_ACKS {
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(packet)) if this.is_ack(&packet) => {
+                    if this.acks.is_empty() {
+                        this.held_since = Some(this.timer.now());
+                    }
+                    this.acks.push_back(packet);
+                }
+                Poll::Ready(Some(packet)) => return Poll::Ready(Some(this.attach(packet))),
+                Poll::Ready(None) => this.inner_done = true,
+                Poll::Pending => break,
+            }
+        }
+
+        let held_since = match this.held_since {
+            Some(held_since) => held_since,
+            None if this.inner_done => return Poll::Ready(None),
+            None => return Poll::Pending,
+        };
+
+        // Wait for another packet until the oldest acknowledgement has been held for the delay,
+        // unless no more packets are coming or no more acknowledgements can be held.
+        if !this.inner_done && this.acks.len() < MAX_HELD_ACKS {
+            let remaining = this
+                .ack_delay
+                .saturating_sub(this.timer.elapsed(held_since));
+            if remaining > Duration::ZERO {
+                let timer = &this.timer;
+                let sleep = this
+                    .sleep
+                    .get_or_insert_with(|| Box::pin(timer.sleep(remaining)));
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+        }
+        this.sleep = None;
+
+        // Send the acknowledgements on their own, bundled together when there is more than one.
+        let ack = this.acks.pop_front().unwrap();
+        Poll::Ready(Some(this.attach(ack)))
+    }
+}
+
+/// Wrap a sink of incoming packets, usually the `IncomingMultiplexedPackets` of a multiplexer, so
+/// that bundles sent on the `bundle_channel` by a remote `PiggybackAcks` are split back into their
+/// packets, which are acquired from the given pool.
+///
+/// Malformed bundles are dropped from the first malformed entry onwards.
+pub fn unbundle<S, P>(incoming: S, pool: P, bundle_channel: PacketChannel) -> Unbundle<S, P>
+where
+    P: PacketPool,
+    S: Sink<P::Packet> + Unpin,
+{
+    Unbundle {
+        inner: incoming,
+        pool,
+        bundle_channel,
+        pending: VecDeque::new(),
+    }
+}
+
+/// A `Sink` which splits bundled packets, created by `unbundle`.
+pub struct Unbundle<S, P: PacketPool> {
+    inner: S,
+    pool: P,
+    bundle_channel: PacketChannel,
+    pending: VecDeque<P::Packet>,
+}
+
+impl<S, P> Unbundle<S, P>
+where
+    P: PacketPool,
+    P::Packet: Unpin,
+    S: Sink<P::Packet> + Unpin,
+{
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        while !self.pending.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx))?;
+            let packet = self.pending.pop_front().unwrap();
+            Pin::new(&mut self.inner).start_send(packet)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn split(&mut self, bundle: &[u8]) {
+        let mut pos = 1;
+        while pos < bundle.len() {
+            if pos + ENTRY_HEADER_LEN > bundle.len() {
+                debug_event!(len = bundle.len(), "dropping malformed bundle");
+                return;
+            }
+            let channel = bundle[pos];
+            let len = LittleEndian::read_u16(&bundle[pos + 1..pos + 3]) as usize;
+            pos += ENTRY_HEADER_LEN;
+
+            let mut packet = self.pool.acquire();
+            if channel == self.bundle_channel
+                || pos + len > bundle.len()
+                || len + 1 > packet.capacity()
+            {
+                debug_event!(len = bundle.len(), "dropping malformed bundle");
+                return;
+            }
+            packet.extend(&[channel]);
+            packet.extend(&bundle[pos..pos + len]);
+            self.pending.push_back(packet);
+            pos += len;
+        }
+    }
+}
+
+impl<S, P> Sink<P::Packet> for Unbundle<S, P>
+where
+    P: PacketPool + Unpin,
+    P::Packet: Unpin,
+    S: Sink<P::Packet> + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, packet: P::Packet) -> Result<(), Self::Error> {
+        if packet.first() == Some(&self.bundle_channel) {
+            self.split(&packet);
+            Ok(())
+        } else {
+            Pin::new(&mut self.inner).start_send(packet)
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,165 @@
+use std::{collections::VecDeque, time::Duration};
+
+// How much faster than real time the playout delay shrinks when the jitter drops.
+const SHRINK_RATE: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The smallest playout delay, added on top of the lowest recent transit time.
+    pub min_delay: Duration,
+    /// The largest playout delay, however bad the jitter gets.
+    pub max_delay: Duration,
+    /// The fraction of messages, from 0 to 1, whose jitter the delay should cover.  Messages with
+    /// more jitter than this arrive too late to be played on time.
+    pub percentile: f64,
+    /// The number of recent messages whose transit times are measured.
+    pub window: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(500),
+            percentile: 0.95,
+            window: 128,
+        }
+    }
+}
+
+/// A receive side jitter buffer, which holds back messages timestamped by their sender and releases
+/// them at the same steady cadence they were sent at, a fixed playout delay later.
+///
+/// Every message is timestamped with its send time on the sender's timeline and its receive time
+/// on the local timeline, such as the `remote` time of a `TimestampChannel` message and the local
+/// time of its arrival.  The difference between them is the message's transit time plus an
+/// unknown clock offset, so only differences between transit times matter and the clocks do not
+/// need to be synchronized.  The lowest transit time of the recent messages is the best case, and
+/// the playout delay is the chosen percentile of how much later than that recent messages arrived.
+///
+/// The delay grows as soon as the jitter does, which pauses playout, and shrinks gradually by
+/// playing slightly faster than real time, so that the cadence stays steady.  Messages which arrive
+/// after a later message has already been released are dropped.
+#[derive(Debug, Clone)]
+pub struct PlayoutBuffer<T> {
+    settings: Settings,
+    // Pending messages sorted by send time.
+    messages: VecDeque<(Duration, T)>,
+    // Recent transit times in arrival order, the receive time minus the send time in seconds.
+    transits: VecDeque<f64>,
+    delay: Duration,
+    target: Duration,
+    last_pop: Option<Duration>,
+    last_released: Option<Duration>,
+    late: u64,
+}
+
+impl<T> PlayoutBuffer<T> {
+    pub fn new(settings: Settings) -> Self {
+        PlayoutBuffer {
+            delay: settings.min_delay,
+            target: settings.min_delay,
+            settings,
+            messages: VecDeque::new(),
+            transits: VecDeque::new(),
+            last_pop: None,
+            last_released: None,
+            late: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// The current playout delay on top of the lowest recent transit time.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// The delay that the current delay is adapting towards, based on the measured jitter.
+    pub fn target_delay(&self) -> Duration {
+        self.target
+    }
+
+    /// The total number of messages dropped because they arrived after a later message had already
+    /// been released.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// The local time at which a message sent at the given time on the sender's timeline is
+    /// released, with the current delay.  Returns `None` if no messages have been received yet.
+    pub fn playout_time(&self, sent: Duration) -> Option<Duration> {
+        let min = self.transits.iter().copied().reduce(f64::min)?;
+        let base = (sent.as_secs_f64() + min).max(0.0);
+        Some(Duration::from_secs_f64(base) + self.delay)
+    }
+
+    /// Insert a message sent at `sent` on the sender's timeline, which was received at `received`
+    /// on the local timeline.
+    ///
+    /// Returns false and drops the message if a message with the same send time has already been
+    /// inserted, or if it is older than an already released message.
+    pub fn insert(&mut self, sent: Duration, received: Duration, message: T) -> bool {
+        if self.last_released.is_some_and(|last| sent <= last) {
+            self.late += 1;
+            return false;
+        }
+        let pos = self
+            .messages
+            .iter()
+            .rposition(|(t, _)| *t <= sent)
+            .map_or(0, |i| i + 1);
+        if pos > 0 && self.messages[pos - 1].0 == sent {
+            return false;
+        }
+        self.messages.insert(pos, (sent, message));
+
+        if self.transits.len() >= self.settings.window.max(1) {
+            self.transits.pop_front();
+        }
+        self.transits
+            .push_back(received.as_secs_f64() - sent.as_secs_f64());
+        self.update_target();
+        true
+    }
+
+    /// Release the next message whose playout time is at or before the given local time, along
+    /// with its send time.  Call this repeatedly, at least as often as messages are sent, until it
+    /// returns `None`.
+    pub fn pop(&mut self, now: Duration) -> Option<(Duration, T)> {
+        if let Some(last) = self.last_pop {
+            if self.delay < self.target {
+                self.delay = self.target;
+            } else if now > last {
+                let shrink = (now - last).mul_f64(SHRINK_RATE);
+                self.delay = self.delay.saturating_sub(shrink).max(self.target);
+            }
+        }
+        self.last_pop = Some(now);
+
+        let sent = self.messages.front()?.0;
+        if self.playout_time(sent)? > now {
+            return None;
+        }
+        self.last_released = Some(sent);
+        self.messages.pop_front()
+    }
+
+    fn update_target(&mut self) {
+        // The jitter of each message is how much later than the lowest transit time it arrived.
+        let min = self.transits.iter().copied().reduce(f64::min).unwrap();
+        let mut jitter: Vec<f64> = self.transits.iter().map(|t| t - min).collect();
+        jitter.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let percentile = self.settings.percentile.clamp(0.0, 1.0);
+        let i = ((jitter.len() - 1) as f64 * percentile).round() as usize;
+        self.target = (self.settings.min_delay + Duration::from_secs_f64(jitter[i]))
+            .min(self.settings.max_delay);
+    }
+}
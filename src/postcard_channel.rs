@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    packet::PacketPool,
+    serde_channel::{self, Format, UnreliableSerdeChannel, UnreliableSerdeTypedChannel},
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+#[cfg(feature = "std")]
+use crate::serde_channel::{ReliableSerdeChannel, ReliableSerdeTypedChannel};
+
+/// The postcard data format.
+///
+/// Postcard is very compact and works without `std`, so embedded peers (such as controllers or
+/// other small devices) can exchange typed messages with a desktop or server using the same
+/// message types.  Without `std`, only the unreliable channels are available.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Postcard;
+
+impl Format for Postcard {
+    type Error = postcard::Error;
+
+    fn serialize<T: Serialize + ?Sized>(
+        buffer: &mut [u8],
+        msg: &T,
+    ) -> Result<usize, postcard::Error> {
+        Ok(postcard::to_slice(msg, buffer)?.len())
+    }
+
+    fn deserialize<'a, T: Deserialize<'a>>(buffer: &'a [u8]) -> Result<T, postcard::Error> {
+        let (msg, rest) = postcard::take_from_bytes(buffer)?;
+        if !rest.is_empty() {
+            return Err(postcard::Error::DeserializeBadEncoding);
+        }
+        Ok(msg)
+    }
+}
+
+pub type SendError = serde_channel::SendError<postcard::Error>;
+pub type RecvError = serde_channel::RecvError<postcard::Error>;
+#[cfg(feature = "std")]
+pub type Error = serde_channel::Error<postcard::Error>;
+
+/// An `UnreliableChannel` which sends messages serialized with postcard.
+pub type UnreliablePostcardChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeChannel<Postcard, R, P, I, O>;
+
+pub type UnreliablePostcardTypedChannel<
+    T,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> = UnreliableSerdeTypedChannel<T, Postcard, R, P, I, O>;
+
+/// A `ReliableChannel` which sends messages serialized with postcard, each prefixed with its
+/// length.
+#[cfg(feature = "std")]
+pub type ReliablePostcardChannel = ReliableSerdeChannel<Postcard>;
+
+#[cfg(feature = "std")]
+pub type ReliablePostcardTypedChannel<T> = ReliableSerdeTypedChannel<T, Postcard>;
@@ -0,0 +1,231 @@
+//! Renders channel statistics in the Prometheus text exposition format, so that a server can
+//! serve them from a `/metrics` endpoint without depending on a metrics library.
+//!
+//! The counters use the same names as the `metrics` module, so dashboards work with either.
+
+use std::fmt::{self, Write};
+
+use crate::{
+    bandwidth_estimator::BandwidthEstimate,
+    heartbeat::HeartbeatStats,
+    packet_multiplexer::{ChannelStatistics, DropReason, PacketChannel},
+    reliable_channel::{Congestion, ReliableChannel},
+};
+
+/// Counter of packets received on each multiplexer channel.
+pub const INCOMING_PACKETS: &str = "turbulence_incoming_packets_total";
+/// Counter of bytes received on each multiplexer channel, not including the channel header.
+pub const INCOMING_BYTES: &str = "turbulence_incoming_bytes_total";
+/// Counter of packets sent on each multiplexer channel.
+pub const OUTGOING_PACKETS: &str = "turbulence_outgoing_packets_total";
+/// Counter of bytes sent on each multiplexer channel, not including the channel header.
+pub const OUTGOING_BYTES: &str = "turbulence_outgoing_bytes_total";
+/// Counter of incoming packets dropped on each multiplexer channel, also labeled with the drop
+/// `reason`.
+pub const DROPPED_PACKETS: &str = "turbulence_dropped_packets_total";
+/// Gauge of the current send rate of each reliable channel in bytes / sec, after congestion
+/// control.
+pub const RELIABLE_BANDWIDTH: &str = "turbulence_reliable_bandwidth_bytes_per_second";
+/// Gauge of the fraction of its receive window most recently reported as unread by the remote of
+/// each reliable channel.
+pub const RELIABLE_REMOTE_OCCUPANCY: &str = "turbulence_reliable_remote_occupancy_ratio";
+/// Gauge of the available throughput estimated by each reliable channel in bytes / sec.  Absent
+/// until the channel has sampled an estimate.
+pub const RELIABLE_BANDWIDTH_ESTIMATE: &str =
+    "turbulence_reliable_bandwidth_estimate_bytes_per_second";
+/// Gauge of the smoothed round trip time measured by a heartbeat in seconds.  Absent until the
+/// first pong.
+pub const HEARTBEAT_RTT: &str = "turbulence_heartbeat_rtt_seconds";
+/// Gauge of the one-way jitter of the remote's pings measured by a heartbeat in seconds.
+pub const HEARTBEAT_JITTER: &str = "turbulence_heartbeat_jitter_seconds";
+/// Gauge of the fraction of recent pings sent by a heartbeat which went unanswered.
+pub const HEARTBEAT_LOSS: &str = "turbulence_heartbeat_loss_ratio";
+
+const FAMILIES: [(&str, &str, &str); 11] = [
+    (
+        INCOMING_PACKETS,
+        "counter",
+        "Packets received on a channel.",
+    ),
+    (INCOMING_BYTES, "counter", "Bytes received on a channel."),
+    (OUTGOING_PACKETS, "counter", "Packets sent on a channel."),
+    (OUTGOING_BYTES, "counter", "Bytes sent on a channel."),
+    (
+        DROPPED_PACKETS,
+        "counter",
+        "Incoming packets dropped on a channel.",
+    ),
+    (
+        RELIABLE_BANDWIDTH,
+        "gauge",
+        "Send rate of a reliable channel after congestion control.",
+    ),
+    (
+        RELIABLE_REMOTE_OCCUPANCY,
+        "gauge",
+        "Fraction of the remote receive window of a reliable channel which is unread.",
+    ),
+    (
+        RELIABLE_BANDWIDTH_ESTIMATE,
+        "gauge",
+        "Available throughput estimated by a reliable channel.",
+    ),
+    (
+        HEARTBEAT_RTT,
+        "gauge",
+        "Smoothed round trip time measured by a heartbeat.",
+    ),
+    (
+        HEARTBEAT_JITTER,
+        "gauge",
+        "One-way jitter measured by a heartbeat.",
+    ),
+    (
+        HEARTBEAT_LOSS,
+        "gauge",
+        "Fraction of recent heartbeat pings which went unanswered.",
+    ),
+];
+
+/// Collects the statistics of any number of channels, and renders them with `Display` as a
+/// Prometheus text exposition.
+///
+/// Every sample is labeled with its `channel` number, after any extra labels given when adding it,
+/// such as a connection id.  Samples are grouped by metric as the format requires, so channels may
+/// be added in any order, but the same channel and labels should not be added twice.
+#[derive(Debug, Clone, Default)]
+pub struct Exposition {
+    // The rendered samples of each metric in `FAMILIES`.
+    samples: [String; FAMILIES.len()],
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the packet and byte totals and drop counts of a multiplexer channel.
+    pub fn channel(
+        &mut self,
+        labels: &[(&str, &str)],
+        channel: PacketChannel,
+        statistics: &ChannelStatistics,
+    ) -> &mut Self {
+        let incoming = statistics.incoming_totals();
+        let outgoing = statistics.outgoing_totals();
+        self.sample(0, labels, channel, None, incoming.packets);
+        self.sample(1, labels, channel, None, incoming.bytes);
+        self.sample(2, labels, channel, None, outgoing.packets);
+        self.sample(3, labels, channel, None, outgoing.bytes);
+        // Packets for unopened channels and rate limited packets are never counted by a channel.
+        for &reason in DropReason::ALL.iter().filter(|&&reason| {
+            reason != DropReason::UnknownChannel && reason != DropReason::RateLimited
+        }) {
+            let dropped = statistics.dropped(reason);
+            self.sample(4, labels, channel, Some(reason.as_str()), dropped);
+        }
+        self
+    }
+
+    /// Add the congestion control state and bandwidth estimate of a reliable channel.  This does
+    /// not include its totals, which are added with `Exposition::channel`.
+    pub fn reliable(
+        &mut self,
+        labels: &[(&str, &str)],
+        channel: PacketChannel,
+        reliable: &ReliableChannel,
+    ) -> &mut Self {
+        self.congestion(labels, channel, &reliable.congestion());
+        self.bandwidth_estimate(labels, channel, &reliable.bandwidth_estimate())
+    }
+
+    /// Add the congestion control state of a reliable channel, from a handle which outlives the
+    /// channel being moved into a task.
+    pub fn congestion(
+        &mut self,
+        labels: &[(&str, &str)],
+        channel: PacketChannel,
+        congestion: &Congestion,
+    ) -> &mut Self {
+        self.sample(5, labels, channel, None, congestion.bandwidth());
+        self.sample(6, labels, channel, None, congestion.remote_occupancy());
+        self
+    }
+
+    /// Add the bandwidth estimate of a reliable channel, if it has one yet.
+    pub fn bandwidth_estimate(
+        &mut self,
+        labels: &[(&str, &str)],
+        channel: PacketChannel,
+        estimate: &BandwidthEstimate,
+    ) -> &mut Self {
+        if let Some(bytes_per_sec) = estimate.bytes_per_sec() {
+            self.sample(7, labels, channel, None, bytes_per_sec);
+        }
+        self
+    }
+
+    /// Add the round trip time, jitter and loss estimates of a heartbeat, from
+    /// `HeartbeatMonitor::stats`.
+    pub fn heartbeat(
+        &mut self,
+        labels: &[(&str, &str)],
+        channel: PacketChannel,
+        stats: &HeartbeatStats,
+    ) -> &mut Self {
+        if let Some(rtt) = stats.rtt {
+            self.sample(8, labels, channel, None, rtt.as_secs_f64());
+        }
+        self.sample(9, labels, channel, None, stats.jitter.as_secs_f64());
+        self.sample(10, labels, channel, None, stats.loss);
+        self
+    }
+
+    fn sample(
+        &mut self,
+        family: usize,
+        labels: &[(&str, &str)],
+        channel: PacketChannel,
+        reason: Option<&str>,
+        value: impl fmt::Display,
+    ) {
+        let out = &mut self.samples[family];
+        out.push_str(FAMILIES[family].0);
+        out.push('{');
+        for (name, value) in labels {
+            out.push_str(name);
+            out.push_str("=\"");
+            escape(out, value);
+            out.push_str("\",");
+        }
+        write!(out, "channel=\"{}\"", channel).unwrap();
+        if let Some(reason) = reason {
+            write!(out, ",reason=\"{}\"", reason).unwrap();
+        }
+        writeln!(out, "}} {}", value).unwrap();
+    }
+}
+
+impl fmt::Display for Exposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ((name, kind, help), samples) in FAMILIES.iter().zip(&self.samples) {
+            if !samples.is_empty() {
+                writeln!(f, "# HELP {} {}", name, help)?;
+                writeln!(f, "# TYPE {} {}", name, kind)?;
+                f.write_str(samples)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn escape(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
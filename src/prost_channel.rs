@@ -0,0 +1,323 @@
+//! Channels which send protobuf messages encoded with `prost`.
+//!
+//! These mirror the bincode channels, but messages are any `prost::Message` rather than serde
+//! types, so that existing `.proto` schemas can be used directly.
+
+use std::marker::PhantomData;
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{Sink, Stream};
+use prost::{DecodeError, EncodeError, Message};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    reliable_channel::{self, ReliableChannel},
+    runtime::Timer,
+    unreliable_channel::{
+        self, DefaultIncoming, DefaultOutgoing, UnreliableChannel, MAX_MESSAGE_LEN,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum SendError {
+    #[error("unreliable channel error: {0}")]
+    UnreliableChannelError(#[from] unreliable_channel::SendError),
+    /// Non-fatal error, message is unsent.
+    #[error("encode error: {0}")]
+    EncodeError(#[from] EncodeError),
+}
+
+#[derive(Debug, Error)]
+pub enum RecvError {
+    #[error("unreliable channel error: {0}")]
+    UnreliableChannelError(#[from] unreliable_channel::RecvError),
+    /// Non-fatal error, message is skipped.
+    #[error("decode error: {0}")]
+    DecodeError(#[from] DecodeError),
+}
+
+/// Wraps an `UnreliableChannel` together with an internal buffer to allow easily sending protobuf
+/// messages.
+///
+/// Just like the underlying channel, messages are not guaranteed to arrive, nor are they guaranteed
+/// to arrive in order.
+pub struct UnreliableProstChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableChannel<R, P, I, O>,
+    buffer: Box<[u8]>,
+}
+
+impl<R, P, I, O> UnreliableProstChannel<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    /// Create a new `UnreliableProstChannel` with the given max message size.
+    ///
+    /// The maximum message size is always limited by the underlying `UnreliableChannel` maximum
+    /// message size regardless of the `max_message_len` setting, but this can be used to restrict
+    /// the intermediate buffer used to encode messages.
+    pub fn new(channel: UnreliableChannel<R, P, I, O>, max_message_len: u16) -> Self {
+        UnreliableProstChannel {
+            channel,
+            buffer: vec![0; max_message_len.min(MAX_MESSAGE_LEN) as usize].into_boxed_slice(),
+        }
+    }
+
+    /// Write the given protobuf message to the channel.
+    ///
+    /// Messages are coalesced into larger packets before being sent, so in order to guarantee that
+    /// the message is actually sent, you must call `flush`.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send<M: Message>(&mut self, msg: &M) -> Result<(), SendError> {
+        let written = encode(&mut self.buffer, msg)?;
+        Ok(self.channel.send(&self.buffer[0..written]).await?)
+    }
+
+    /// Finish sending any unsent coalesced packets.
+    ///
+    /// This *must* be called to guarantee that any sent messages are actually sent to the outgoing
+    /// packet stream.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        Ok(self.channel.flush().await?)
+    }
+
+    /// Receive a protobuf message as soon as the next message is available.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv<M: Message + Default>(&mut self) -> Result<M, RecvError> {
+        Ok(self.recv_timed().await?.0)
+    }
+
+    /// Like `UnreliableProstChannel::recv`, but also returns the time at which the packet
+    /// containing the message was received, as described in `UnreliableChannel::recv_timed`.
+    pub async fn recv_timed<M: Message + Default>(&mut self) -> Result<(M, R::Instant), RecvError> {
+        let (msg, received) = self.channel.recv_timed().await?;
+        Ok((M::decode(msg)?, received))
+    }
+}
+
+/// Wrapper over an `UnreliableProstChannel` that only allows a single message type.
+pub struct UnreliableProstTypedChannel<
+    M,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableProstChannel<R, P, I, O>,
+    _phantom: PhantomData<M>,
+}
+
+impl<M, R, P, I, O> UnreliableProstTypedChannel<M, R, P, I, O>
+where
+    M: Message + Default,
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(channel: UnreliableProstChannel<R, P, I, O>) -> Self {
+        UnreliableProstTypedChannel {
+            channel,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub async fn send(&mut self, msg: &M) -> Result<(), SendError> {
+        self.channel.send(msg).await
+    }
+
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        self.channel.flush().await
+    }
+
+    pub async fn recv(&mut self) -> Result<M, RecvError> {
+        self.channel.recv().await
+    }
+
+    pub async fn recv_timed(&mut self) -> Result<(M, R::Instant), RecvError> {
+        self.channel.recv_timed().await
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Fatal internal channel error.
+    #[error("reliable channel error: {0}")]
+    ReliableChannelError(#[from] reliable_channel::Error),
+    /// Fatal, reading the next message would exceed the maximum buffer length, no progress can be
+    /// made.
+    #[error("received message exceeds the configured max message length")]
+    PrefixTooLarge,
+    /// Non-fatal, no message is sent.
+    #[error("encode error: {0}")]
+    EncodeError(#[from] EncodeError),
+    /// Non-fatal, the message is *skipped*.
+    #[error("decode error: {0}")]
+    DecodeError(#[from] DecodeError),
+}
+
+/// Wraps a `ReliableChannel` together with an internal buffer to allow easily sending protobuf
+/// messages.
+///
+/// Messages are guaranteed to arrive, and are guaranteed to be in order.  Messages have a maximum
+/// length, but this maximum size can be larger than the size of an individual packet.  Just like
+/// `ReliableBincodeChannel`, every message is prefixed with its length as a 2 byte little endian
+/// integer.
+pub struct ReliableProstChannel {
+    channel: ReliableChannel,
+    max_message_len: u16,
+
+    write_buffer: Box<[u8]>,
+    write_pos: usize,
+    write_end: usize,
+
+    read_buffer: Box<[u8]>,
+    read_pos: usize,
+    read_end: usize,
+}
+
+impl ReliableProstChannel {
+    /// Create a new `ReliableProstChannel` with a maximum message size of `max_message_len`.
+    pub fn new(channel: ReliableChannel, max_message_len: u16) -> Self {
+        ReliableProstChannel {
+            channel,
+            max_message_len,
+            write_buffer: vec![0; 2 + max_message_len as usize].into_boxed_slice(),
+            write_pos: 0,
+            write_end: 0,
+            read_buffer: vec![0; 2 + max_message_len as usize].into_boxed_slice(),
+            read_pos: 0,
+            read_end: 0,
+        }
+    }
+
+    /// Write the given protobuf message to the reliable channel.
+    ///
+    /// In order to ensure that messages are sent in a timely manner, `flush` must be called after
+    /// calling this method.  Without calling `flush`, any pending writes will not be sent until the
+    /// next automatic sender task wakeup.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send<M: Message>(&mut self, msg: &M) -> Result<(), Error> {
+        self.finish_write().await?;
+
+        self.write_pos = 0;
+        self.write_end = 0;
+
+        let written = encode(&mut self.write_buffer[2..], msg)?;
+        self.write_end = written + 2;
+        LittleEndian::write_u16(&mut self.write_buffer[0..2], written as u16);
+        self.finish_write().await?;
+
+        Ok(())
+    }
+
+    /// Ensure that any previously sent messages are sent as soon as possible.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.finish_write().await?;
+        Ok(self.channel.flush().await?)
+    }
+
+    /// Read the next available incoming protobuf message.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv<M: Message + Default>(&mut self) -> Result<M, Error> {
+        if self.read_end < 2 {
+            self.read_end = 2;
+        }
+        self.finish_read().await?;
+
+        let message_len = LittleEndian::read_u16(&self.read_buffer[0..2]);
+        if message_len > self.max_message_len {
+            return Err(Error::PrefixTooLarge);
+        }
+        self.read_end = message_len as usize + 2;
+        self.finish_read().await?;
+
+        let res = M::decode(&self.read_buffer[2..self.read_end]);
+        self.read_pos = 0;
+        self.read_end = 0;
+        Ok(res?)
+    }
+
+    async fn finish_write(&mut self) -> Result<(), Error> {
+        while self.write_pos < self.write_end {
+            let len = self
+                .channel
+                .write(&self.write_buffer[self.write_pos..self.write_end])
+                .await?;
+            self.write_pos += len;
+        }
+        Ok(())
+    }
+
+    async fn finish_read(&mut self) -> Result<(), Error> {
+        while self.read_pos < self.read_end {
+            let len = self
+                .channel
+                .read(&mut self.read_buffer[self.read_pos..self.read_end])
+                .await?;
+            self.read_pos += len;
+        }
+        Ok(())
+    }
+}
+
+/// Wrapper over a `ReliableProstChannel` that only allows a single message type.
+pub struct ReliableProstTypedChannel<M> {
+    channel: ReliableProstChannel,
+    _phantom: PhantomData<M>,
+}
+
+impl<M: Message + Default> ReliableProstTypedChannel<M> {
+    pub fn new(channel: ReliableProstChannel) -> Self {
+        ReliableProstTypedChannel {
+            channel,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub async fn send(&mut self, msg: &M) -> Result<(), Error> {
+        self.channel.send(msg).await
+    }
+
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.channel.flush().await
+    }
+
+    pub async fn recv(&mut self) -> Result<M, Error> {
+        self.channel.recv().await
+    }
+}
+
+// Encode the message into the given buffer, returning the number of bytes written.
+fn encode<M: Message>(buffer: &mut [u8], msg: &M) -> Result<usize, EncodeError> {
+    let len = buffer.len();
+    let mut w = buffer;
+    msg.encode(&mut w)?;
+    Ok(len - w.len())
+}
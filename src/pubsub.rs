@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::message_channels::{ChannelMessage, MessageChannels};
+
+/// Identifies a topic that messages are published to.
+///
+/// Both sides of a connection must agree on topic ids.  Ids may be assigned by hand, or derived
+/// from a topic's name with `Topic::named`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Topic(pub u32);
+
+impl Topic {
+    /// The topic id for the given name, which is the 32-bit FNV-1a hash of the name.
+    pub fn named(name: &str) -> Topic {
+        let mut hash: u32 = 0x811c_9dc5;
+        for &b in name.as_bytes() {
+            hash ^= b as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+        Topic(hash)
+    }
+}
+
+impl Serialize for Topic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Topic {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u32::deserialize(deserializer).map(Topic)
+    }
+}
+
+/// A message of type `M` published to a topic.
+///
+/// This is the message type actually sent over `MessageChannels`, so in order to publish messages
+/// of type `M` with `PubSub`, `Published<M>` must be registered with the `MessageChannelsBuilder`.
+/// Every topic with messages of the same type shares that one message channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Published<M> {
+    pub topic: Topic,
+    pub message: M,
+}
+
+impl<M: Serialize> Serialize for Published<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.topic, &self.message).serialize(serializer)
+    }
+}
+
+impl<'de, M: Deserialize<'de>> Deserialize<'de> for Published<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (topic, message) = <(Topic, M)>::deserialize(deserializer)?;
+        Ok(Published { topic, message })
+    }
+}
+
+/// A change to the set of topics one side of a connection is subscribed to.
+///
+/// `SubscriptionUpdate` must be registered with the `MessageChannelsBuilder` in order to use
+/// `PubSub`, and since a lost update would leave the remote's view of subscriptions wrong, it
+/// should be registered on a reliable channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubscriptionUpdate {
+    Subscribe(Topic),
+    Unsubscribe(Topic),
+}
+
+impl Serialize for SubscriptionUpdate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            SubscriptionUpdate::Subscribe(topic) => (true, topic),
+            SubscriptionUpdate::Unsubscribe(topic) => (false, topic),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SubscriptionUpdate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (subscribe, topic) = <(bool, Topic)>::deserialize(deserializer)?;
+        Ok(if subscribe {
+            SubscriptionUpdate::Subscribe(topic)
+        } else {
+            SubscriptionUpdate::Unsubscribe(topic)
+        })
+    }
+}
+
+/// Topic based publish / subscribe on top of `MessageChannels`.
+///
+/// Each side subscribes to the topics it is interested in, and the set of subscribed topics is
+/// kept in sync with the other side by `SubscriptionUpdate` messages.  Messages published to a
+/// topic that the other side is not subscribed to are never sent, and since a message may still be
+/// in flight when its topic is unsubscribed from, messages for unsubscribed topics are also
+/// filtered out on receipt.
+///
+/// `PubSub::update` must be called regularly to send local subscription changes and to process
+/// the other side's.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    subscribed: FxHashSet<Topic>,
+    remote_subscribed: FxHashSet<Topic>,
+    pending_updates: VecDeque<SubscriptionUpdate>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to the given topic, returns false if it was already subscribed to.
+    ///
+    /// The other side is told of the subscription on the next call to `PubSub::update`.
+    pub fn subscribe(&mut self, topic: Topic) -> bool {
+        let added = self.subscribed.insert(topic);
+        if added {
+            self.pending_updates
+                .push_back(SubscriptionUpdate::Subscribe(topic));
+        }
+        added
+    }
+
+    /// Unsubscribe from the given topic, returns false if it was not subscribed to.
+    ///
+    /// Messages for this topic are no longer returned by `PubSub::recv`, even if they were sent
+    /// before the other side was told of the change.
+    pub fn unsubscribe(&mut self, topic: Topic) -> bool {
+        let removed = self.subscribed.remove(&topic);
+        if removed {
+            self.pending_updates
+                .push_back(SubscriptionUpdate::Unsubscribe(topic));
+        }
+        removed
+    }
+
+    pub fn is_subscribed(&self, topic: Topic) -> bool {
+        self.subscribed.contains(&topic)
+    }
+
+    /// Whether the other side is subscribed to the given topic, as of the last call to
+    /// `PubSub::update`.
+    pub fn is_remote_subscribed(&self, topic: Topic) -> bool {
+        self.remote_subscribed.contains(&topic)
+    }
+
+    /// Send any pending subscription changes, and process subscription changes from the other
+    /// side.
+    ///
+    /// Changes that do not fit in the outgoing message buffer are kept and retried on the next
+    /// call.
+    ///
+    /// # Panics
+    /// Panics if `SubscriptionUpdate` is not registered.
+    pub fn update(&mut self, channels: &mut MessageChannels) {
+        let mut sent = false;
+        while let Some(update) = self.pending_updates.pop_front() {
+            if let Some(update) = channels.send(update) {
+                self.pending_updates.push_front(update);
+                break;
+            }
+            sent = true;
+        }
+        if sent {
+            channels.flush::<SubscriptionUpdate>();
+        }
+
+        while let Some(update) = channels.recv::<SubscriptionUpdate>() {
+            match update {
+                SubscriptionUpdate::Subscribe(topic) => {
+                    self.remote_subscribed.insert(topic);
+                }
+                SubscriptionUpdate::Unsubscribe(topic) => {
+                    self.remote_subscribed.remove(&topic);
+                }
+            }
+        }
+    }
+
+    /// Publish a message to the given topic.
+    ///
+    /// If the other side is not subscribed to the topic the message is dropped without being
+    /// sent.  Otherwise this behaves like `MessageChannels::send`, returning the message back if
+    /// the outgoing message buffer is full, and `MessageChannels::flush` must be called for
+    /// `Published<M>` to ensure delivery.
+    ///
+    /// # Panics
+    /// Panics if `Published<M>` is not registered.
+    pub fn publish<M: ChannelMessage>(
+        &self,
+        channels: &mut MessageChannels,
+        topic: Topic,
+        message: M,
+    ) -> Option<M> {
+        if !self.is_remote_subscribed(topic) {
+            return None;
+        }
+        channels
+            .send(Published { topic, message })
+            .map(|published| published.message)
+    }
+
+    /// Receive the next incoming message of type `M` published to a subscribed topic, if one is
+    /// available.  Messages for topics that are not subscribed to are skipped.
+    ///
+    /// # Panics
+    /// Panics if `Published<M>` is not registered.
+    pub fn recv<M: ChannelMessage>(&self, channels: &mut MessageChannels) -> Option<(Topic, M)> {
+        while let Some(published) = channels.recv::<Published<M>>() {
+            if self.is_subscribed(published.topic) {
+                return Some((published.topic, published.message));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,219 @@
+//! Serialization helpers which trade precision for size, to make game state snapshots sent over
+//! the bincode channels much smaller.
+//!
+//! Each wrapper type serializes its value compactly with any serde serializer, and can be used
+//! directly as the type of a field in a message.  Quantized values take a fixed number of bytes,
+//! while `Fixed` values are serialized as integers, which the variable length integer encoding of
+//! the bincode channels makes small when the values are small.
+
+use std::fmt;
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// Quantize `value`, clamped to the range `[min, max]`, to an integer of `bits` bits.
+///
+/// # Panics
+/// Panics if `bits` is not between 1 and 32.
+pub fn quantize(value: f32, min: f32, max: f32, bits: u32) -> u32 {
+    let steps = max_quantized(bits);
+    let t = ((value - min) / (max - min)).clamp(0., 1.);
+    (t as f64 * steps as f64).round() as u32
+}
+
+/// The inverse of `quantize`, returns the value in `[min, max]` closest to the quantized value.
+///
+/// # Panics
+/// Panics if `bits` is not between 1 and 32.
+pub fn dequantize(quantized: u32, min: f32, max: f32, bits: u32) -> f32 {
+    let steps = max_quantized(bits);
+    let t = quantized.min(steps) as f64 / steps as f64;
+    (min as f64 + t * (max as f64 - min as f64)) as f32
+}
+
+/// A float in the range `[MIN, MAX]` quantized to `BITS` bits, serialized in `BITS / 8` bytes
+/// rounded up.
+///
+/// Values outside of the range are clamped to it.  For example, an angle could be a
+/// `Quantized<-180, 180, 10>`, which is accurate to about a third of a degree in two bytes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, PartialOrd)]
+pub struct Quantized<const MIN: i32, const MAX: i32, const BITS: u32>(pub f32);
+
+impl<const MIN: i32, const MAX: i32, const BITS: u32> Serialize for Quantized<MIN, MAX, BITS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let quantized = quantize(self.0, MIN as f32, MAX as f32, BITS);
+        serialize_bits(quantized as u64, BITS, serializer)
+    }
+}
+
+impl<'de, const MIN: i32, const MAX: i32, const BITS: u32> Deserialize<'de>
+    for Quantized<MIN, MAX, BITS>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let quantized = deserialize_bits(BITS, deserializer)? as u32;
+        Ok(Quantized(dequantize(
+            quantized, MIN as f32, MAX as f32, BITS,
+        )))
+    }
+}
+
+/// A float with a fixed precision of `1 / SCALE`, serialized as an `i32`.
+///
+/// With a variable length integer encoding, such as the one used by the bincode channels, values
+/// near zero take fewer bytes, which makes this a good fit for positions relative to some nearby
+/// origin.  For example, a `Fixed<100>` is accurate to a centimeter and serializes a value of
+/// less than a meter in a single byte.  Values too large for an `i32` are clamped.
+#[derive(Debug, Copy, Clone, Default, PartialEq, PartialOrd)]
+pub struct Fixed<const SCALE: u32>(pub f32);
+
+impl<const SCALE: u32> Serialize for Fixed<SCALE> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ((self.0 as f64 * SCALE as f64).round() as i32).serialize(serializer)
+    }
+}
+
+impl<'de, const SCALE: u32> Deserialize<'de> for Fixed<SCALE> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fixed = i32::deserialize(deserializer)?;
+        Ok(Fixed((fixed as f64 / SCALE as f64) as f32))
+    }
+}
+
+/// A unit vector packed into `2 * BITS` bits with an octahedral encoding, serialized in
+/// `BITS / 4` bytes rounded up.
+///
+/// With 11 bits, directions fit in three bytes and are accurate to within about a tenth of a
+/// degree.  Vectors are normalized before being encoded, and the zero vector is encoded as
+/// the +Z axis.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct UnitVector<const BITS: u32>(pub [f32; 3]);
+
+impl<const BITS: u32> Serialize for UnitVector<BITS> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        assert!(BITS <= 32);
+        let [x, y, z] = self.0;
+        let l1 = x.abs() + y.abs() + z.abs();
+        let (mut u, mut v) = if l1 > 0. { (x / l1, y / l1) } else { (0., 0.) };
+        if z < 0. {
+            let (fu, fv) = fold(u, v);
+            u = fu;
+            v = fv;
+        }
+        let u = quantize(u, -1., 1., BITS) as u64;
+        let v = quantize(v, -1., 1., BITS) as u64;
+        serialize_bits(u | (v << BITS), 2 * BITS, serializer)
+    }
+}
+
+impl<'de, const BITS: u32> Deserialize<'de> for UnitVector<BITS> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        assert!(BITS <= 32);
+        let packed = deserialize_bits(2 * BITS, deserializer)?;
+        let mask = (1 << BITS) - 1;
+        let u = dequantize((packed & mask) as u32, -1., 1., BITS);
+        let v = dequantize((packed >> BITS & mask) as u32, -1., 1., BITS);
+
+        let z = 1. - u.abs() - v.abs();
+        let (x, y) = if z < 0. { fold(u, v) } else { (u, v) };
+        let len = (x * x + y * y + z * z).sqrt();
+        Ok(UnitVector([x / len, y / len, z / len]))
+    }
+}
+
+/// `N` booleans packed into bits, serialized in `N / 8` bytes rounded up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PackedBools<const N: usize>(pub [bool; N]);
+
+impl<const N: usize> Default for PackedBools<N> {
+    fn default() -> Self {
+        PackedBools([false; N])
+    }
+}
+
+impl<const N: usize> Serialize for PackedBools<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(N.div_ceil(8))?;
+        for bools in self.0.chunks(8) {
+            let mut byte = 0u8;
+            for (i, &b) in bools.iter().enumerate() {
+                byte |= (b as u8) << i;
+            }
+            tuple.serialize_element(&byte)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for PackedBools<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_tuple(N.div_ceil(8), BytesVisitor(N.div_ceil(8)))?;
+        let mut bools = [false; N];
+        for (i, b) in bools.iter_mut().enumerate() {
+            *b = bytes[i / 8] & (1 << (i % 8)) != 0;
+        }
+        Ok(PackedBools(bools))
+    }
+}
+
+fn max_quantized(bits: u32) -> u32 {
+    assert!(
+        bits > 0 && bits <= 32,
+        "quantized bits must be between 1 and 32"
+    );
+    (((1u64) << bits) - 1) as u32
+}
+
+// Reflects a point of the lower half of the octahedron onto the outer triangles of the unit
+// square, and back.
+fn fold(u: f32, v: f32) -> (f32, f32) {
+    ((1. - v.abs()) * u.signum(), (1. - u.abs()) * v.signum())
+}
+
+fn serialize_bits<S: Serializer>(value: u64, bits: u32, serializer: S) -> Result<S::Ok, S::Error> {
+    let len = (bits as usize).div_ceil(8);
+    let mut tuple = serializer.serialize_tuple(len)?;
+    for i in 0..len {
+        tuple.serialize_element(&((value >> (i * 8)) as u8))?;
+    }
+    tuple.end()
+}
+
+fn deserialize_bits<'de, D: Deserializer<'de>>(
+    bits: u32,
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    let len = (bits as usize).div_ceil(8);
+    let bytes = deserializer.deserialize_tuple(len, BytesVisitor(len))?;
+    let value = bytes
+        .iter()
+        .enumerate()
+        .fold(0u64, |value, (i, &b)| value | (b as u64) << (i * 8));
+    if bits < 64 && value >> bits != 0 {
+        return Err(de::Error::custom("quantized value out of range"));
+    }
+    Ok(value)
+}
+
+struct BytesVisitor(usize);
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a tuple of {} bytes", self.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(self.0);
+        for i in 0..self.0 {
+            bytes.push(
+                seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(i, &self))?,
+            );
+        }
+        Ok(bytes)
+    }
+}
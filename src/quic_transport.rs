@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use futures::{future, pin_mut, StreamExt};
+use quinn::{Connection, ConnectionError, SendDatagramError};
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{IncomingMultiplexedPackets, OutgoingMultiplexedPackets},
+};
+
+#[derive(Debug, Error)]
+pub enum QuicError {
+    /// Datagrams are disabled locally or are not supported by the remote peer.
+    #[error("QUIC datagrams are unsupported on this connection")]
+    DatagramsUnsupported,
+    #[error("QUIC connection error: {0}")]
+    Connection(#[from] ConnectionError),
+}
+
+/// Sends and receives turbulence packets as QUIC DATAGRAM frames over a `quinn::Connection`.
+///
+/// QUIC provides encryption and connection establishment, and its datagrams are unreliable and
+/// unordered, so turbulence's channels can be layered on top of them exactly as with raw UDP.
+///
+/// The packet pool should produce packets no larger than `max_datagram_size`, packets that are
+/// too large for the connection to currently send are dropped, and received datagrams larger than
+/// the packet capacity are truncated.
+pub struct QuicDatagrams<P> {
+    connection: Connection,
+    packet_pool: P,
+}
+
+impl<P> QuicDatagrams<P>
+where
+    P: PacketPool,
+{
+    /// Returns `QuicError::DatagramsUnsupported` if the established connection cannot send
+    /// datagrams.
+    pub fn new(connection: Connection, packet_pool: P) -> Result<QuicDatagrams<P>, QuicError> {
+        if connection.max_datagram_size().is_none() {
+            return Err(QuicError::DatagramsUnsupported);
+        }
+        Ok(QuicDatagrams {
+            connection,
+            packet_pool,
+        })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The largest packet that may currently be sent, this can change over the lifetime of the
+    /// connection as the path MTU is discovered.
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size()
+    }
+
+    /// Send a single packet as a datagram.
+    ///
+    /// If the outgoing datagram buffer is full, the oldest buffered datagrams are dropped.  A
+    /// `SendDatagramError::TooLarge` error is not fatal.
+    pub fn send(&self, packet: &[u8]) -> Result<(), SendDatagramError> {
+        self.connection
+            .send_datagram(Bytes::copy_from_slice(packet))
+    }
+
+    /// Receive a single datagram as a packet.
+    pub async fn recv(&self) -> Result<P::Packet, ConnectionError> {
+        let datagram = self.connection.read_datagram().await?;
+        let mut packet = self.packet_pool.acquire();
+        let len = datagram.len().min(packet.capacity());
+        packet.extend(&datagram[..len]);
+        Ok(packet)
+    }
+
+    /// Forward packets between this connection and a started `PacketMultiplexer` until the
+    /// connection is closed or the multiplexer is dropped.
+    ///
+    /// The multiplexer's `DatagramLimit` is kept at the connection's `max_datagram_size` before
+    /// every send, so its channels shrink their packets as soon as the path MTU does.
+    ///
+    /// Incoming packets are delivered with `IncomingMultiplexedPackets::try_send`, so packets for
+    /// unknown channels or for channels whose buffers are full are dropped rather than blocking
+    /// other channels.
+    pub async fn run_multiplexer(
+        self,
+        mut incoming: IncomingMultiplexedPackets<P::Packet>,
+        mut outgoing: OutgoingMultiplexedPackets<P::Packet>,
+    ) -> Result<(), QuicError>
+    where
+        P::Packet: Unpin,
+    {
+        let incoming = async {
+            loop {
+                let packet = self.recv().await?;
+                let _ = incoming.try_send(packet);
+            }
+        };
+        let outgoing = async {
+            let datagram_limit = outgoing.datagram_limit().clone();
+            datagram_limit.set_max_datagram_size(self.max_datagram_size());
+            while let Some(packet) = outgoing.next().await {
+                let res = self.send(&packet);
+                datagram_limit.set_max_datagram_size(self.max_datagram_size());
+                match res {
+                    Ok(()) | Err(SendDatagramError::TooLarge) => {}
+                    Err(SendDatagramError::ConnectionLost(err)) => return Err(err.into()),
+                    Err(SendDatagramError::UnsupportedByPeer)
+                    | Err(SendDatagramError::Disabled) => {
+                        return Err(QuicError::DatagramsUnsupported)
+                    }
+                }
+            }
+            Ok(())
+        };
+        pin_mut!(incoming, outgoing);
+
+        future::select(incoming, outgoing).await.factor_first().0
+    }
+}
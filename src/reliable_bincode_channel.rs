@@ -1,11 +1,18 @@
-use std::{marker::PhantomData, u16};
+use std::marker::PhantomData;
 
 use bincode::Options as _;
 use byteorder::{ByteOrder, LittleEndian};
-use serde::{Deserialize, Serialize};
+use futures::FutureExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::reliable_channel::{self, ReliableChannel};
+use crate::{
+    decode_limits::{self, DecodeLimits},
+    envelope::Envelope,
+    growable_buffer::GrowableBuffer,
+    reliable_channel::{self, ReliableChannel},
+    scratch::ScratchArena,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -16,6 +23,10 @@ pub enum Error {
     /// made.
     #[error("received message exceeds the configured max message length")]
     PrefixTooLarge,
+    /// Non-fatal, the received message exceeded the maximum message length and is *skipped*.  Only
+    /// returned by channels set to skip oversized messages.
+    #[error("received message of {0} bytes exceeds the configured max message length")]
+    MessageTooLarge(u16),
     /// Non-fatal, on send, no message is sent, on receive the message is *skipped*.
     #[error("bincode serialization error: {0}")]
     BincodeError(#[from] bincode::Error),
@@ -30,30 +41,101 @@ pub struct ReliableBincodeChannel {
     channel: ReliableChannel,
     max_message_len: u16,
 
-    write_buffer: Box<[u8]>,
+    write_buffer: GrowableBuffer,
     write_pos: usize,
     write_end: usize,
 
-    read_buffer: Box<[u8]>,
+    // The length prefix of the message being read, the message itself is read into `read_buffer`.
+    // Positions are within the prefix followed by the message.
+    read_prefix: [u8; 2],
+    read_buffer: GrowableBuffer,
     read_pos: usize,
     read_end: usize,
+
+    skip_oversized: bool,
+    decode_limits: Option<DecodeLimits>,
+    // The number of bytes of a skipped message left to be discarded.
+    read_skip: usize,
 }
 
 impl ReliableBincodeChannel {
     /// Create a new `ReliableBincodeChannel` with a maximum message size of `max_message_len`.
     pub fn new(channel: ReliableChannel, max_message_len: u16) -> Self {
+        Self::with_initial_buffer_len(channel, max_message_len, max_message_len)
+    }
+
+    /// Create a new `ReliableBincodeChannel` whose send and receive buffers start with room for
+    /// messages of `initial_buffer_len`, and grow on demand up to `max_message_len`.
+    ///
+    /// Both buffers are normally allocated at the maximum message length, which adds up when a
+    /// connection has many channels which only carry small messages.  Buffers never shrink, so
+    /// their size is that of the largest message sent or received so far.
+    pub fn with_initial_buffer_len(
+        channel: ReliableChannel,
+        max_message_len: u16,
+        initial_buffer_len: u16,
+    ) -> Self {
+        let (initial_len, max_len) = (initial_buffer_len as usize, max_message_len as usize);
         ReliableBincodeChannel {
             channel,
             max_message_len,
-            write_buffer: vec![0; 2 + max_message_len as usize].into_boxed_slice(),
+            // The send buffer has room for the length prefix.
+            write_buffer: GrowableBuffer::new(2 + initial_len, 2 + max_len),
             write_pos: 0,
             write_end: 0,
-            read_buffer: vec![0; 2 + max_message_len as usize].into_boxed_slice(),
+            read_prefix: [0; 2],
+            read_buffer: GrowableBuffer::new(initial_len, max_len),
             read_pos: 0,
             read_end: 0,
+            skip_oversized: false,
+            decode_limits: None,
+            read_skip: 0,
         }
     }
 
+    /// Set limits on the decoded size and nesting depth of received messages, by default none.
+    ///
+    /// A message exceeding them fails to deserialize and is skipped, see `DecodeLimits`.
+    pub fn set_decode_limits(&mut self, limits: Option<DecodeLimits>) {
+        self.decode_limits = limits;
+    }
+
+    pub fn decode_limits(&self) -> Option<DecodeLimits> {
+        self.decode_limits
+    }
+
+    /// Set whether received messages longer than the maximum message length are skipped, by
+    /// default false.
+    ///
+    /// Normally such a message fails every receive with the fatal `Error::PrefixTooLarge`, since
+    /// the channel cannot read past it.  When skipping, the message is reported once with the
+    /// non-fatal `Error::MessageTooLarge`, and its bytes are discarded so that the channel resumes
+    /// from the length prefix of the next message.  Messages which fail to deserialize are always
+    /// skipped.
+    pub fn set_skip_oversized(&mut self, skip_oversized: bool) {
+        self.skip_oversized = skip_oversized;
+    }
+
+    pub fn skip_oversized(&self) -> bool {
+        self.skip_oversized
+    }
+
+    /// Borrow the send and receive buffers from the given arena, or own them again if `None`, see
+    /// `ScratchArena`.
+    pub fn set_scratch_arena(&mut self, arena: Option<ScratchArena>) {
+        self.write_buffer.set_arena(arena.clone());
+        self.read_buffer.set_arena(arena);
+    }
+
+    pub fn scratch_arena(&self) -> Option<&ScratchArena> {
+        self.write_buffer.arena()
+    }
+
+    /// The current combined length of the send and receive buffers.
+    pub fn buffer_len(&self) -> usize {
+        self.write_buffer.len() + self.read_buffer.len()
+    }
+
     /// Write the given message to the reliable channel.
     ///
     /// In order to ensure that messages are sent in a timely manner, `flush` must be called after
@@ -69,11 +151,7 @@ impl ReliableBincodeChannel {
         self.write_end = 0;
 
         let bincode_config = self.bincode_config();
-        let mut w = &mut self.write_buffer[2..];
-        bincode_config.serialize_into(&mut w, msg)?;
-
-        let remaining = w.len();
-        self.write_end = self.write_buffer.len() - remaining;
+        self.write_end = self.write_buffer.serialize(2, bincode_config, msg)?;
         LittleEndian::write_u16(&mut self.write_buffer[0..2], (self.write_end - 2) as u16);
         self.finish_write().await?;
 
@@ -93,25 +171,69 @@ impl ReliableBincodeChannel {
     /// This method is cancel safe, it will never partially read a message or drop received
     /// messages.
     pub async fn recv<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T, Error> {
+        self.finish_skip().await?;
         if self.read_end < 2 {
+            // No message has started to arrive, so the previously received message is no longer
+            // borrowed.
+            self.read_buffer.release();
             self.read_end = 2;
         }
         self.finish_read().await?;
 
-        let message_len = LittleEndian::read_u16(&self.read_buffer[0..2]);
+        let message_len = LittleEndian::read_u16(&self.read_prefix);
         if message_len > self.max_message_len {
-            return Err(Error::PrefixTooLarge);
+            if !self.skip_oversized {
+                return Err(Error::PrefixTooLarge);
+            }
+            debug_event!(len = message_len, "skipping oversized reliable message");
+            self.read_skip = message_len as usize;
+            self.read_pos = 0;
+            self.read_end = 0;
+            return Err(Error::MessageTooLarge(message_len));
         }
         self.read_end = message_len as usize + 2;
         self.finish_read().await?;
 
         let bincode_config = self.bincode_config();
-        let res = bincode_config.deserialize(&self.read_buffer[2..self.read_end]);
+        let res = decode_limits::bincode_deserialize(
+            bincode_config,
+            &self.read_buffer[..self.read_end - 2],
+            self.decode_limits.as_ref(),
+        );
         self.read_pos = 0;
         self.read_end = 0;
         Ok(res?)
     }
 
+    /// Wait for the next incoming message, then receive up to `max` messages in total into
+    /// `messages`, decoding any further messages which have already been received without
+    /// waiting.  Returns the number of messages received.
+    ///
+    /// If an error occurs after some messages have been received, those messages are left in
+    /// `messages`.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv_many<T: DeserializeOwned>(
+        &mut self,
+        messages: &mut Vec<T>,
+        max: usize,
+    ) -> Result<usize, Error> {
+        if max == 0 {
+            return Ok(0);
+        }
+        messages.push(self.recv().await?);
+        let mut count = 1;
+        while count < max {
+            match self.recv().now_or_never() {
+                Some(msg) => messages.push(msg?),
+                None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
     async fn finish_write(&mut self) -> Result<(), Error> {
         while self.write_pos < self.write_end {
             let len = self
@@ -120,20 +242,41 @@ impl ReliableBincodeChannel {
                 .await?;
             self.write_pos += len;
         }
+        self.write_buffer.release();
         Ok(())
     }
 
     async fn finish_read(&mut self) -> Result<(), Error> {
+        if self.read_end > 2 {
+            self.read_buffer.reserve(self.read_end - 2);
+        }
         while self.read_pos < self.read_end {
-            let len = self
-                .channel
-                .read(&mut self.read_buffer[self.read_pos..self.read_end])
-                .await?;
+            let len = if self.read_pos < 2 {
+                self.channel
+                    .read(&mut self.read_prefix[self.read_pos..])
+                    .await?
+            } else {
+                self.channel
+                    .read(&mut self.read_buffer[self.read_pos - 2..self.read_end - 2])
+                    .await?
+            };
             self.read_pos += len;
         }
         Ok(())
     }
 
+    async fn finish_skip(&mut self) -> Result<(), Error> {
+        if self.read_skip > 0 {
+            self.read_buffer.reserve(self.read_skip);
+        }
+        while self.read_skip > 0 {
+            let end = self.read_skip.min(self.read_buffer.len());
+            let len = self.channel.read(&mut self.read_buffer[..end]).await?;
+            self.read_skip -= len;
+        }
+        Ok(())
+    }
+
     fn bincode_config(&self) -> impl bincode::Options + Copy {
         bincode::options().with_limit(self.max_message_len as u64)
     }
@@ -142,6 +285,7 @@ impl ReliableBincodeChannel {
 /// Wrapper over an `ReliableBincodeChannel` that only allows a single message type.
 pub struct ReliableTypedChannel<T> {
     channel: ReliableBincodeChannel,
+    envelope: Option<Envelope<T>>,
     _phantom: PhantomData<T>,
 }
 
@@ -149,6 +293,17 @@ impl<T> ReliableTypedChannel<T> {
     pub fn new(channel: ReliableBincodeChannel) -> Self {
         ReliableTypedChannel {
             channel,
+            envelope: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Create a typed channel which sends and receives messages in the given versioned
+    /// `Envelope`.
+    pub fn with_envelope(channel: ReliableBincodeChannel, envelope: Envelope<T>) -> Self {
+        ReliableTypedChannel {
+            channel,
+            envelope: Some(envelope),
             _phantom: PhantomData,
         }
     }
@@ -160,12 +315,41 @@ impl<T> ReliableTypedChannel<T> {
 
 impl<T: Serialize> ReliableTypedChannel<T> {
     pub async fn send(&mut self, msg: &T) -> Result<(), Error> {
-        self.channel.send(msg).await
+        match &self.envelope {
+            Some(envelope) => self.channel.send(&envelope.encode(msg)?).await,
+            None => self.channel.send(msg).await,
+        }
     }
 }
 
 impl<'a, T: Deserialize<'a>> ReliableTypedChannel<T> {
     pub async fn recv(&'a mut self) -> Result<T, Error> {
-        self.channel.recv().await
+        match &self.envelope {
+            Some(envelope) => {
+                let limits = self.channel.decode_limits;
+                let (version, payload): (u16, &[u8]) = self.channel.recv().await?;
+                Ok(envelope.decode(version, payload, limits.as_ref())?)
+            }
+            None => self.channel.recv().await,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> ReliableTypedChannel<T> {
+    /// Receive up to `max` messages at once, see `ReliableBincodeChannel::recv_many`.
+    pub async fn recv_many(&mut self, messages: &mut Vec<T>, max: usize) -> Result<usize, Error> {
+        if max == 0 {
+            return Ok(0);
+        }
+        messages.push(self.recv().await?);
+        let mut count = 1;
+        while count < max {
+            match self.recv().now_or_never() {
+                Some(msg) => messages.push(msg?),
+                None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
     }
 }
@@ -1,31 +1,78 @@
 use std::{
+    collections::VecDeque,
     future::Future,
-    i16,
     num::Wrapping,
     pin::Pin,
-    sync::Arc,
-    task::{Poll, Waker},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
     time::Duration,
-    u32,
 };
 
 use byteorder::{ByteOrder, LittleEndian};
 use futures::{
-    channel::mpsc,
-    future::{self, Fuse, FusedFuture, RemoteHandle},
-    lock::{Mutex, MutexGuard},
-    pin_mut, select, FutureExt, StreamExt,
+    future::{self, Fuse, FusedFuture},
+    lock::{Mutex, MutexGuard, OwnedMutexGuard, OwnedMutexLockFuture},
+    pin_mut, ready, select, stream, FutureExt, Sink, SinkExt, Stream, StreamExt,
 };
 use rustc_hash::FxHashMap;
 use thiserror::Error;
 
 use crate::{
+    bandwidth_estimator::{BandwidthEstimate, BandwidthEstimator},
     bandwidth_limiter::BandwidthLimiter,
-    packet::{Packet, PacketPool},
-    runtime::Runtime,
+    loss_statistics::{LossStatistics, LossTracker},
+    packet::{Packet, PacketPool, MAX_PACKET_LEN},
+    replay_window::ReplayWindow,
+    runtime::{JoinHandle, LocalRuntime, Runtime, TaskFailed, Timer},
+    telemetry::{EventKind, EventLog},
+    tick_budget::{TickBudget, TickBudgetSlot, TickWindow},
     windows::{stream_gt, AckResult, RecvWindow, SendWindow, StreamPos},
+    work_budget::{Cooperative, WorkBudget, WorkBudgetSlot},
 };
 
+#[cfg(feature = "metrics")]
+use crate::metrics::ReliableMetrics;
+
+// How long delivery rate samples are kept for the bandwidth estimate.
+const BANDWIDTH_ESTIMATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// The maximum length of a message sent with `ReliableChannel::send_urgent`.
+pub const MAX_URGENT_LEN: usize = 256;
+
+/// The maximum number of urgent messages which may be queued or unacknowledged at once, in each
+/// direction.
+pub const URGENT_WINDOW: usize = 8;
+
+// Urgent packets are marked with a length that no data or acknowledgement packet can have, since
+// data packets are never longer than `i16::MAX`.
+const URGENT_MARKER: i16 = i16::MIN;
+const URGENT_DATA: u8 = 0;
+const URGENT_ACK: u8 = 1;
+const URGENT_HEADER_LEN: usize = 7;
+
+// Receivers report their delivery rate and buffer occupancy at most this often while data is
+// arriving, in feedback packets which are also marked with an impossible length.
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(100);
+const FEEDBACK_MARKER: i16 = i16::MIN + 1;
+const FEEDBACK_LEN: usize = 8;
+
+// A reported delivery rate below this fraction of the rate data was sent at over the same interval
+// means that a queue is building somewhere on the path.
+const CONGESTION_THRESHOLD: f64 = 0.9;
+// On congestion, the send rate is lowered to this fraction of the reported delivery rate, so that
+// the queue which has built up drains.
+const CONGESTION_DRAIN: f64 = 0.75;
+// Without congestion, the send rate recovers by this fraction of `Settings::bandwidth` per report.
+const CONGESTION_RECOVERY: f64 = 0.01;
+// The send rate is never lowered below this fraction of `Settings::bandwidth`.
+const MIN_CONGESTION_BANDWIDTH: f64 = 1. / 16.;
+// The send rate does not recover while the remote reports more of its receive window than this as
+// buffered but unread.
+const HIGH_OCCUPANCY: f64 = 0.5;
+
 /// All reliable channel errors are fatal.  Once any error is returned all further reliable channel
 /// method calls will return `Error::Shutdown` errors.
 #[derive(Debug, Error)]
@@ -36,9 +83,13 @@ pub enum Error {
     ProtocolError,
     #[error("an error has been encountered that has caused the channel to shutdown")]
     Shutdown,
+    /// The channel task panicked or was dropped by the runtime without completing.
+    #[error("reliable channel task failed: {0}")]
+    TaskFailed(#[from] TaskFailed),
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Settings {
     /// The target outgoing bandwidth, in bytes / sec.
     ///
@@ -74,6 +125,103 @@ pub struct Settings {
     pub rtt_resend_factor: f64,
 }
 
+#[derive(Debug, Error)]
+pub enum UrgentError {
+    /// Fatal internal channel error.
+    #[error(transparent)]
+    ReliableChannelError(#[from] Error),
+    /// Non-fatal, the message is longer than `MAX_URGENT_LEN`, or does not fit in a packet along
+    /// with the urgent packet header, and is not sent.
+    #[error("urgent message exceeds the maximum urgent message length")]
+    TooLarge,
+}
+
+/// Returns true if the packet, without any multiplexer channel header, is a `ReliableChannel`
+/// acknowledgement of stream data or of an urgent message.
+///
+/// Acknowledgements carry no data of their own, so they are safe to delay or reorder, for example
+/// to piggyback them on other packets.
+pub fn is_ack(packet: &[u8]) -> bool {
+    if packet.len() < 2 {
+        return false;
+    }
+    match LittleEndian::read_i16(&packet[0..2]) {
+        URGENT_MARKER => packet.len() == URGENT_HEADER_LEN && packet[2] == URGENT_ACK,
+        len => len < 0 && packet.len() == 10,
+    }
+}
+
+/// Returned by `Settings::validate` when settings would keep a channel from working, or would make
+/// it panic.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SettingsError {
+    #[error("bandwidth must be nonzero")]
+    ZeroBandwidth,
+    #[error("burst bandwidth of {burst_bandwidth} bytes is less than the packet length of {packet_len} bytes")]
+    BurstTooSmall {
+        burst_bandwidth: u32,
+        packet_len: u32,
+    },
+    #[error("send and receive window sizes must be nonzero")]
+    ZeroWindowSize,
+    #[error("initial send amount must be nonzero")]
+    ZeroInitSend,
+    #[error("resend time must be nonzero")]
+    ZeroResendTime,
+    #[error("initial RTT of {initial_rtt:?} exceeds the max RTT of {max_rtt:?}")]
+    InitialRttTooLarge {
+        initial_rtt: Duration,
+        max_rtt: Duration,
+    },
+    #[error("RTT update factor of {0} is not within (0, 1]")]
+    BadRttUpdateFactor(f64),
+    #[error("RTT resend factor of {0} is not positive")]
+    BadRttResendFactor(f64),
+}
+
+impl Settings {
+    /// Check that these settings are usable for a channel sending packets of up to `packet_len`
+    /// bytes.
+    ///
+    /// `ReliableChannel::new` panics on only some of these errors, the rest leave a channel which
+    /// still runs but stalls, busy loops, or never sends a full packet without going into bandwidth
+    /// debt.
+    pub fn validate(&self, packet_len: usize) -> Result<(), SettingsError> {
+        if self.bandwidth == 0 {
+            return Err(SettingsError::ZeroBandwidth);
+        }
+        let packet_len = packet_len.min(MAX_PACKET_LEN as usize) as u32;
+        if self.burst_bandwidth < packet_len {
+            return Err(SettingsError::BurstTooSmall {
+                burst_bandwidth: self.burst_bandwidth,
+                packet_len,
+            });
+        }
+        if self.recv_window_size == 0 || self.send_window_size == 0 {
+            return Err(SettingsError::ZeroWindowSize);
+        }
+        if self.init_send == 0 {
+            return Err(SettingsError::ZeroInitSend);
+        }
+        if self.resend_time == Duration::ZERO {
+            return Err(SettingsError::ZeroResendTime);
+        }
+        if self.initial_rtt > self.max_rtt {
+            return Err(SettingsError::InitialRttTooLarge {
+                initial_rtt: self.initial_rtt,
+                max_rtt: self.max_rtt,
+            });
+        }
+        if !(self.rtt_update_factor > 0. && self.rtt_update_factor <= 1.) {
+            return Err(SettingsError::BadRttUpdateFactor(self.rtt_update_factor));
+        }
+        if self.rtt_resend_factor.is_nan() || self.rtt_resend_factor <= 0. {
+            return Err(SettingsError::BadRttResendFactor(self.rtt_resend_factor));
+        }
+        Ok(())
+    }
+}
+
 /// Turns a stream of unreliable, unordered packets into a reliable in-order stream of data.
 ///
 /// All methods on `ReliableChannel` are always cancel safe, they return immediately once any amount
@@ -82,31 +230,129 @@ pub struct ReliableChannel {
     // TODO: It would be nicer to use `BiLock` once it is stable in `futures`, and would allow
     // `ReliableChannel` to implement `AsyncRead` and `AsyncWrite`.
     shared: Arc<Mutex<Shared>>,
-    task: Fuse<RemoteHandle<Error>>,
+    // The lock future of a poll method which is waiting for the shared state.
+    lock: Option<OwnedMutexLockFuture<Shared>>,
+    task: Fuse<JoinHandle<Error>>,
+    bandwidth_estimate: BandwidthEstimate,
+    congestion: Congestion,
+    event_log: EventLogSlot,
+    work_budget: WorkBudgetSlot,
+    tick_budget: TickBudgetSlot,
+    loss_statistics: LossStatistics,
 }
 
 impl ReliableChannel {
-    pub fn new<R, P>(
+    pub fn new<R, P, I, O>(
         runtime: R,
         packet_pool: P,
         settings: Settings,
-        incoming: mpsc::Receiver<P::Packet>,
-        outgoing: mpsc::Sender<P::Packet>,
+        incoming: I,
+        outgoing: O,
     ) -> Self
     where
         R: Runtime + 'static,
         P: PacketPool + Send + 'static,
         P::Packet: Send,
+        I: Stream<Item = P::Packet> + Send + Unpin + 'static,
+        O: Sink<P::Packet> + Send + Unpin + 'static,
+    {
+        let tick_budget = TickBudgetSlot::default();
+        let loss_statistics = LossStatistics::default();
+        let (shared, bandwidth_estimate, congestion, event_log, work_budget, task) =
+            ReliableChannel::with_task(
+                runtime.clone(),
+                packet_pool,
+                settings,
+                tick_budget.clone(),
+                loss_statistics.clone(),
+                incoming,
+                outgoing,
+            );
+        ReliableChannel {
+            shared,
+            lock: None,
+            task: runtime.spawn_with_handle(task).fuse(),
+            bandwidth_estimate,
+            congestion,
+            event_log,
+            work_budget,
+            tick_budget,
+            loss_statistics,
+        }
+    }
+
+    /// Like `ReliableChannel::new`, but spawns the channel task with `LocalRuntime::spawn_local`,
+    /// so neither the runtime nor the packet pool or its packets need to be `Send`.
+    pub fn new_local<R, P, I, O>(
+        runtime: R,
+        packet_pool: P,
+        settings: Settings,
+        incoming: I,
+        outgoing: O,
+    ) -> Self
+    where
+        R: LocalRuntime + 'static,
+        P: PacketPool + 'static,
+        I: Stream<Item = P::Packet> + Unpin + 'static,
+        O: Sink<P::Packet> + Unpin + 'static,
+    {
+        let tick_budget = TickBudgetSlot::default();
+        let loss_statistics = LossStatistics::default();
+        let (shared, bandwidth_estimate, congestion, event_log, work_budget, task) =
+            ReliableChannel::with_task(
+                runtime.clone(),
+                packet_pool,
+                settings,
+                tick_budget.clone(),
+                loss_statistics.clone(),
+                incoming,
+                outgoing,
+            );
+        ReliableChannel {
+            shared,
+            lock: None,
+            task: runtime.spawn_local_with_handle(task).fuse(),
+            bandwidth_estimate,
+            congestion,
+            event_log,
+            work_budget,
+            tick_budget,
+            loss_statistics,
+        }
+    }
+
+    fn with_task<R, P, I, O>(
+        runtime: R,
+        packet_pool: P,
+        settings: Settings,
+        tick_budget: TickBudgetSlot,
+        loss_statistics: LossStatistics,
+        incoming: I,
+        outgoing: O,
+    ) -> (
+        Arc<Mutex<Shared>>,
+        BandwidthEstimate,
+        Congestion,
+        EventLogSlot,
+        WorkBudgetSlot,
+        impl Future<Output = Error>,
+    )
+    where
+        R: Timer + 'static,
+        P: PacketPool + 'static,
+        I: Stream<Item = P::Packet> + Unpin + 'static,
+        O: Sink<P::Packet> + Unpin + 'static,
     {
         assert!(settings.bandwidth != 0);
         assert!(settings.recv_window_size != 0);
-        assert!(settings.recv_window_size != 0);
+        assert!(settings.send_window_size != 0);
         assert!(settings.burst_bandwidth != 0);
         assert!(settings.init_send != 0);
         assert!(settings.rtt_update_factor > 0.);
         assert!(settings.rtt_resend_factor > 0.);
 
         let resend_timer = Box::pin(runtime.sleep(settings.resend_time).fuse());
+        let urgent_max_len = urgent_max_len(&packet_pool.acquire());
 
         let shared = Arc::new(Mutex::new(Shared {
             send_window: SendWindow::new(settings.send_window_size, Wrapping(0)),
@@ -114,6 +360,14 @@ impl ReliableChannel {
             write_ready: None,
             recv_window: RecvWindow::new(settings.recv_window_size, Wrapping(0)),
             read_ready: None,
+
+            urgent_max_len,
+            urgent_out: VecDeque::new(),
+            urgent_in_flight: 0,
+            urgent_send_ready: None,
+            urgent_write_ready: None,
+            urgent_in: VecDeque::new(),
+            urgent_read_ready: None,
         }));
 
         let bandwidth_limiter = BandwidthLimiter::new(
@@ -123,31 +377,121 @@ impl ReliableChannel {
         );
         let remote_recv_available = settings.init_send;
         let rtt_estimate = settings.initial_rtt.as_secs_f64();
+        let bandwidth_estimator = BandwidthEstimator::new(BANDWIDTH_ESTIMATE_WINDOW);
+        let bandwidth_estimate = bandwidth_estimator.estimate();
+        let congestion = Congestion::new(settings.bandwidth);
+        let event_log = EventLogSlot::default();
+        let work_budget = WorkBudgetSlot::default();
+        let tick_window = TickWindow::new(runtime.clone());
+        let start = runtime.now();
 
         let task = Task {
             settings,
-            runtime: runtime.clone(),
+            runtime,
             packet_pool,
-            incoming,
+            incoming: Cooperative::with_slot(incoming, work_budget.clone()).fuse(),
             outgoing,
             resend_timer,
             remote_recv_available,
             unacked_ranges: FxHashMap::default(),
             rtt_estimate,
             bandwidth_limiter,
+            bandwidth_estimator,
+            tick_budget,
+            tick_window,
+            loss_tracker: LossTracker::new(loss_statistics),
+            start,
+            delivered: 0,
+            delivered_time: start,
+            first_sent_time: start,
+            urgent_seq: 0,
+            urgent_unacked: FxHashMap::default(),
+            urgent_received: ReplayWindow::new(),
+            congestion: congestion.clone(),
+            event_log: event_log.clone(),
+            feedback_sent: RateCounter::new(start),
+            feedback_received: RateCounter::new(start),
+            packets_too_small: false,
+            #[cfg(feature = "metrics")]
+            metrics: ReliableMetrics::new(),
         };
-        let (remote, remote_handle) = {
+        let task = {
             let shared = Arc::clone(&shared);
-            async move { task.main_loop(shared).await.unwrap_err() }
-        }
-        .remote_handle();
-
-        runtime.spawn(remote);
+            let event_log = event_log.clone();
+            async move {
+                let error = task.main_loop(shared).await.unwrap_err();
+                debug_event!(%error, "reliable channel task stopped");
+                if let Error::ProtocolError = error {
+                    event_log.record(EventKind::DecodeError);
+                }
+                error
+            }
+        };
+        // Run the task in the span it was created in, which is usually the span of the channel
+        // that owns it.
+        #[cfg(feature = "tracing")]
+        let task = tracing::Instrument::in_current_span(task);
 
-        ReliableChannel {
+        (
             shared,
-            task: remote_handle.fuse(),
-        }
+            bandwidth_estimate,
+            congestion,
+            event_log,
+            work_budget,
+            task,
+        )
+    }
+
+    /// A handle to the channel's estimate of the connection's available throughput, which is
+    /// sampled from the rate at which sent data is acknowledged.
+    ///
+    /// The estimate is the highest delivery rate sampled over the last two seconds, so it only
+    /// measures throughput the channel has actually used recently.  The handle remains valid
+    /// after the channel is wrapped in another channel type.
+    pub fn bandwidth_estimate(&self) -> BandwidthEstimate {
+        self.bandwidth_estimate.clone()
+    }
+
+    /// A handle to the channel's congestion control state, which lowers the send rate below
+    /// `Settings::bandwidth` while the remote reports that data is queueing up on the way to it.
+    pub fn congestion(&self) -> Congestion {
+        self.congestion.clone()
+    }
+
+    /// A handle to the loss and reordering patterns the channel has seen in the acknowledgements
+    /// of the data it sent.
+    pub fn loss_statistics(&self) -> LossStatistics {
+        self.loss_statistics.clone()
+    }
+
+    /// Record the channel's retransmits, dropped urgent messages and protocol errors to the given
+    /// log, replacing any previously set log.
+    pub fn set_event_log(&self, event_log: EventLog) {
+        *self.event_log.0.lock().unwrap() = Some(event_log);
+    }
+
+    /// Limit how many incoming packets the channel task handles before yielding to the executor,
+    /// or remove the limit with `None`.  There is no limit by default.
+    pub fn set_work_budget(&self, budget: Option<WorkBudget>) {
+        self.work_budget.set(budget);
+    }
+
+    pub fn work_budget(&self) -> Option<WorkBudget> {
+        self.work_budget.get()
+    }
+
+    /// Limit the bytes of new data the channel task sends per tick, or remove the limit with
+    /// `None`.  There is no limit by default.
+    ///
+    /// Data written over the budget is queued in the send window until a later tick, so once the
+    /// send window is full, writes wait too.  Resends, acknowledgements and urgent messages are
+    /// not counted against the budget.  A budget of zero bytes sends no new data at all.
+    pub fn set_tick_budget(&self, budget: Option<TickBudget>) {
+        self.tick_budget.set(budget);
+    }
+
+    pub fn tick_budget(&self) -> Option<TickBudget> {
+        self.tick_budget.get()
     }
 
     /// Write the given data to the reliable channel and return once any nonzero amount of data has
@@ -156,23 +500,121 @@ impl ReliableChannel {
     /// In order to ensure that data is written to the channel in a timely manner,
     /// `ReliableChannel::flush` must be called.
     pub async fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        future::poll_fn(|cx| self.poll_write(cx, data)).await
+    }
+
+    /// The poll form of `ReliableChannel::write`, for use in manual `Future` implementations.
+    ///
+    /// Data is only ever written when this returns `Poll::Ready(Ok(len))`, so the next call after
+    /// `Poll::Pending` may write different data.
+    pub fn poll_write(&mut self, cx: &mut Context, data: &[u8]) -> Poll<Result<usize, Error>> {
+        let mut shared = ready!(self.poll_shared(cx)?);
+        let len = shared.send_window.write(data);
+        if len > 0 {
+            return Poll::Ready(Ok(len));
+        }
+        shared.write_ready = Some(cx.waker().clone());
+        if let Some(send_ready) = shared.send_ready.take() {
+            send_ready.wake();
+        }
+        drop(shared);
+        self.poll_task(cx)
+    }
+
+    /// Ensure that any previously written data is sent in a timely manner.
+    ///
+    /// Returns once the sending task has been notified to wake up and will send the written data
+    /// promptly.  Does *not* actually wait for outgoing packets to be sent before returning.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        future::poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /// The poll form of `ReliableChannel::flush`.
+    pub fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let mut shared = ready!(self.poll_shared(cx)?);
+        if let Some(send_ready) = shared.send_ready.take() {
+            send_ready.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Read any available data.  Returns once at least one byte of data has been read.
+    pub async fn read(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+        future::poll_fn(|cx| self.poll_read(cx, data)).await
+    }
+
+    /// The poll form of `ReliableChannel::read`.  Data is only ever read into the buffer when this
+    /// returns `Poll::Ready(Ok(len))`.
+    pub fn poll_read(&mut self, cx: &mut Context, data: &mut [u8]) -> Poll<Result<usize, Error>> {
+        let mut shared = ready!(self.poll_shared(cx)?);
+        let len = shared.recv_window.read(data);
+        if len > 0 {
+            return Poll::Ready(Ok(len));
+        }
+        shared.read_ready = Some(cx.waker().clone());
+        drop(shared);
+        self.poll_task(cx)
+    }
+
+    // Lock the shared state, keeping the lock future across polls so that its place in the queue
+    // for the lock is kept, or return the task's error if it has stopped.
+    fn poll_shared(&mut self, cx: &mut Context) -> Poll<Result<OwnedMutexGuard<Shared>, Error>> {
         if self.task.is_terminated() {
-            return Err(Error::Shutdown);
+            return Poll::Ready(Err(Error::Shutdown));
+        }
+        let shared = &self.shared;
+        let lock = self
+            .lock
+            .get_or_insert_with(|| Arc::clone(shared).lock_owned());
+        match Pin::new(lock).poll(cx) {
+            Poll::Ready(guard) => {
+                self.lock = None;
+                Poll::Ready(Ok(guard))
+            }
+            Poll::Pending => self.poll_task(cx),
+        }
+    }
+
+    // Returns `Poll::Pending` unless the task has stopped, in which case returns its error.
+    fn poll_task<T>(&mut self, cx: &mut Context) -> Poll<Result<T, Error>> {
+        let res = ready!(Pin::new(&mut self.task).poll(cx));
+        Poll::Ready(Err(res.unwrap_or_else(Error::TaskFailed)))
+    }
+
+    /// Send a small message on the urgent lane, which bypasses the ordered stream.
+    ///
+    /// Urgent messages are sent reliably, but as separate packets which do not wait behind any
+    /// data written to the stream, nor for the remote's receive window or the channel's bandwidth
+    /// limit (though they are counted against it).  They are meant for tiny control messages like
+    /// pausing or kicking, which must arrive promptly even during a bulk transfer.  Urgent messages
+    /// are not ordered with respect to the stream or to each other.
+    ///
+    /// At most `URGENT_WINDOW` urgent messages may be queued or unacknowledged at once, sending
+    /// more waits until earlier messages are acknowledged.
+    ///
+    /// This method is cancel safe, it will never partially send a message.
+    pub async fn send_urgent(&mut self, msg: &[u8]) -> Result<(), UrgentError> {
+        if self.task.is_terminated() {
+            return Err(Error::Shutdown.into());
         }
 
         let shared = &self.shared;
         let mut shared_lock_future = shared.lock();
-        let mut write_done =
+        let mut send_done =
             future::poll_fn(|cx| match Pin::new(&mut shared_lock_future).poll(cx) {
                 Poll::Ready(mut shared_guard) => {
-                    let len = shared_guard.send_window.write(data);
-                    if len > 0 {
-                        Poll::Ready(len)
-                    } else {
-                        shared_guard.write_ready = Some(cx.waker().clone());
-                        if let Some(send_ready) = shared_guard.send_ready.take() {
-                            send_ready.wake();
+                    if msg.len() > shared_guard.urgent_max_len {
+                        Poll::Ready(Err(UrgentError::TooLarge))
+                    } else if shared_guard.urgent_out.len() + shared_guard.urgent_in_flight
+                        < URGENT_WINDOW
+                    {
+                        shared_guard.urgent_out.push_back(msg.into());
+                        if let Some(urgent_send_ready) = shared_guard.urgent_send_ready.take() {
+                            urgent_send_ready.wake();
                         }
+                        Poll::Ready(Ok(()))
+                    } else {
+                        shared_guard.urgent_write_ready = Some(cx.waker().clone());
                         shared_lock_future = shared.lock();
                         Poll::Pending
                     }
@@ -182,43 +624,29 @@ impl ReliableChannel {
             .fuse();
 
         select! {
-            len = write_done => Ok(len),
-            error = &mut self.task => Err(error),
+            res = send_done => res,
+            res = &mut self.task => Err(res.unwrap_or_else(Error::TaskFailed).into()),
         }
     }
 
-    /// Ensure that any previously written data is sent in a timely manner.
+    /// Receive the next message sent by the remote with `ReliableChannel::send_urgent`.
     ///
-    /// Returns once the sending task has been notified to wake up and will send the written data
-    /// promptly.  Does *not* actually wait for outgoing packets to be sent before returning.
-    pub async fn flush(&mut self) -> Result<(), Error> {
-        if self.task.is_terminated() {
-            return Err(Error::Shutdown);
-        }
-
-        let mut shared = self.shared.lock().await;
-        if let Some(send_ready) = shared.send_ready.take() {
-            send_ready.wake();
-        }
-        Ok(())
-    }
-
-    /// Read any available data.  Returns once at least one byte of data has been read.
-    pub async fn read(&mut self, data: &mut [u8]) -> Result<usize, Error> {
+    /// Only `URGENT_WINDOW` received urgent messages are buffered, further urgent messages are not
+    /// acknowledged and so are resent by the remote until there is room.
+    pub async fn recv_urgent(&mut self) -> Result<Box<[u8]>, Error> {
         if self.task.is_terminated() {
             return Err(Error::Shutdown);
         }
 
         let shared = &self.shared;
         let mut shared_lock_future = shared.lock();
-        let mut read_done =
+        let mut recv_done =
             future::poll_fn(|cx| match Pin::new(&mut shared_lock_future).poll(cx) {
                 Poll::Ready(mut shared_guard) => {
-                    let len = shared_guard.recv_window.read(data);
-                    if len > 0 {
-                        Poll::Ready(len)
+                    if let Some(msg) = shared_guard.urgent_in.pop_front() {
+                        Poll::Ready(msg)
                     } else {
-                        shared_guard.read_ready = Some(cx.waker().clone());
+                        shared_guard.urgent_read_ready = Some(cx.waker().clone());
                         shared_lock_future = shared.lock();
                         Poll::Pending
                     }
@@ -228,12 +656,76 @@ impl ReliableChannel {
             .fuse();
 
         select! {
-            len = read_done => Ok(len),
-            error = &mut self.task => Err(error),
+            msg = recv_done => Ok(msg),
+            res = &mut self.task => Err(res.unwrap_or_else(Error::TaskFailed)),
+        }
+    }
+}
+
+/// A handle to the state of a channel's congestion control.
+///
+/// Both ends of a reliable channel periodically report the rate they are receiving data at and how
+/// much of their receive window is buffered but unread.  When the remote reports receiving data
+/// more slowly than it is being sent, the excess is queueing up somewhere on the path, such as in
+/// the oversized buffer of a consumer router, so the channel lowers its send rate below the
+/// reported rate until the queue drains, rather than waiting for the queue to overflow and packets
+/// to be lost.  The send rate then slowly recovers back
+/// to `Settings::bandwidth` while reports show no congestion.
+#[derive(Debug, Clone)]
+pub struct Congestion(Arc<CongestionState>);
+
+impl Congestion {
+    fn new(bandwidth: u32) -> Self {
+        Congestion(Arc::new(CongestionState {
+            bandwidth: AtomicU32::new(bandwidth),
+            remote_delivery_rate: AtomicU32::new(NO_REPORT),
+            remote_occupancy: AtomicU32::new(0),
+        }))
+    }
+
+    /// The current send rate of the channel in bytes / sec, which is at most `Settings::bandwidth`.
+    pub fn bandwidth(&self) -> u32 {
+        self.0.bandwidth.load(Ordering::Relaxed)
+    }
+
+    /// The delivery rate most recently reported by the remote in bytes / sec, or `None` if there
+    /// has been no report yet.
+    pub fn remote_delivery_rate(&self) -> Option<u32> {
+        match self.0.remote_delivery_rate.load(Ordering::Relaxed) {
+            NO_REPORT => None,
+            rate => Some(rate),
+        }
+    }
+
+    /// The fraction of its receive window most recently reported by the remote as buffered but
+    /// unread, from 0 to 1.
+    pub fn remote_occupancy(&self) -> f64 {
+        self.0.remote_occupancy.load(Ordering::Relaxed) as f64 / u16::MAX as f64
+    }
+}
+
+const NO_REPORT: u32 = u32::MAX;
+
+// The event log shared between the channel and its task, which is only locked to set the log and
+// to record rare events.
+#[derive(Clone, Default)]
+struct EventLogSlot(Arc<std::sync::Mutex<Option<EventLog>>>);
+
+impl EventLogSlot {
+    fn record(&self, kind: EventKind) {
+        if let Some(event_log) = &*self.0.lock().unwrap() {
+            event_log.record(kind);
         }
     }
 }
 
+#[derive(Debug)]
+struct CongestionState {
+    bandwidth: AtomicU32,
+    remote_delivery_rate: AtomicU32,
+    remote_occupancy: AtomicU32,
+}
+
 struct Shared {
     send_window: SendWindow,
     send_ready: Option<Waker>,
@@ -241,6 +733,16 @@ struct Shared {
 
     recv_window: RecvWindow,
     read_ready: Option<Waker>,
+
+    // The longest urgent message that fits in a packet, urgent messages waiting to be sent, and
+    // the number sent but not yet acknowledged.
+    urgent_max_len: usize,
+    urgent_out: VecDeque<Box<[u8]>>,
+    urgent_in_flight: usize,
+    urgent_send_ready: Option<Waker>,
+    urgent_write_ready: Option<Waker>,
+    urgent_in: VecDeque<Box<[u8]>>,
+    urgent_read_ready: Option<Waker>,
 }
 
 struct UnackedRange<I> {
@@ -248,30 +750,61 @@ struct UnackedRange<I> {
     end: StreamPos,
     last_sent: Option<I>,
     retransmit: bool,
+    // The number of the packet this range was last sent in, for loss statistics, if it has been
+    // sent since it was last split.
+    loss_seq: Option<u64>,
+    // The total bytes acknowledged, the time of the latest acknowledgement, and the time the
+    // latest acknowledged range was sent, as of when this range was sent.
+    delivered: u64,
+    delivered_time: I,
+    first_sent_time: I,
 }
 
-struct Task<R, P>
+struct Task<R, P, I, O>
 where
-    R: Runtime,
+    R: Timer,
     P: PacketPool,
 {
     runtime: R,
     settings: Settings,
     packet_pool: P,
-    incoming: mpsc::Receiver<P::Packet>,
-    outgoing: mpsc::Sender<P::Packet>,
+    incoming: stream::Fuse<Cooperative<I>>,
+    outgoing: O,
 
     resend_timer: Pin<Box<Fuse<R::Sleep>>>,
     remote_recv_available: u32,
     unacked_ranges: FxHashMap<StreamPos, UnackedRange<R::Instant>>,
     rtt_estimate: f64,
     bandwidth_limiter: BandwidthLimiter<R>,
+    bandwidth_estimator: BandwidthEstimator,
+    tick_budget: TickBudgetSlot,
+    tick_window: TickWindow<R>,
+    loss_tracker: LossTracker,
+    start: R::Instant,
+    delivered: u64,
+    delivered_time: R::Instant,
+    first_sent_time: R::Instant,
+    urgent_seq: u32,
+    // Sent urgent messages by sequence number, with the time they were last sent.
+    urgent_unacked: FxHashMap<u32, (Box<[u8]>, R::Instant)>,
+    urgent_received: ReplayWindow,
+    congestion: Congestion,
+    event_log: EventLogSlot,
+    feedback_sent: RateCounter<R::Instant>,
+    feedback_received: RateCounter<R::Instant>,
+    // Set when a packet had no room for data, so that sending waits for the resend timer rather
+    // than retrying at once.
+    packets_too_small: bool,
+    #[cfg(feature = "metrics")]
+    metrics: ReliableMetrics,
 }
 
-impl<R, P> Task<R, P>
+impl<R, P, I, O> Task<R, P, I, O>
 where
-    R: Runtime,
+    R: Timer,
     P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
 {
     async fn main_loop(mut self, shared: Arc<Mutex<Shared>>) -> Result<(), Error> {
         loop {
@@ -279,11 +812,14 @@ where
                 ResendTimer,
                 IncomingPacket(P),
                 SendAvailable(MutexGuard<'a, Shared>),
+                UrgentAvailable(MutexGuard<'a, Shared>),
             }
 
             self.bandwidth_limiter.update_available();
+            let tick_delay = self.tick_delay();
 
             let wake_reason = {
+                let runtime = &self.runtime;
                 let bandwidth_limiter = &self.bandwidth_limiter;
                 let resend_timer = &mut self.resend_timer;
 
@@ -299,13 +835,20 @@ where
                 pin_mut!(resend_timer);
 
                 let remote_recv_available = self.remote_recv_available;
+                let packets_too_small = self.packets_too_small;
                 let send_available = async {
-                    if remote_recv_available == 0 {
+                    if remote_recv_available == 0 || packets_too_small {
                         // Don't wake up at all for sending new data if we couldn't send anything
-                        // anyway.
+                        // anyway, packets which were too small are tried again by the resend
+                        // timer.
                         future::pending::<()>().await;
                     }
 
+                    // Nor until the next tick, if this tick's budget is used up.
+                    if let Some(delay) = tick_delay {
+                        runtime.sleep(delay).await;
+                    }
+
                     // Don't wake up for sending new data until we have bandwidth available.
                     bandwidth_limiter.delay_until_available().await;
 
@@ -327,11 +870,32 @@ where
                 .fuse();
                 pin_mut!(send_available);
 
+                // Urgent messages wait for neither bandwidth nor the remote receive window.
+                let urgent_available = async {
+                    let mut shared_lock_future = shared.lock();
+                    future::poll_fn(|cx| match Pin::new(&mut shared_lock_future).poll(cx) {
+                        Poll::Ready(mut shared_guard) => {
+                            if !shared_guard.urgent_out.is_empty() {
+                                Poll::Ready(shared_guard)
+                            } else {
+                                shared_guard.urgent_send_ready = Some(cx.waker().clone());
+                                shared_lock_future = shared.lock();
+                                Poll::Pending
+                            }
+                        }
+                        Poll::Pending => Poll::Pending,
+                    })
+                    .await
+                }
+                .fuse();
+                pin_mut!(urgent_available);
+
                 select! {
                     _ = resend_timer => WakeReason::ResendTimer,
                     incoming_packet = self.incoming.next() => {
                         WakeReason::IncomingPacket(incoming_packet.ok_or(Error::Disconnected)?)
                     },
+                    shared = urgent_available => WakeReason::UrgentAvailable(shared),
                     shared = send_available => WakeReason::SendAvailable(shared),
                 }
             };
@@ -341,22 +905,26 @@ where
             match wake_reason {
                 WakeReason::ResendTimer => {
                     let mut shared = shared.lock().await;
-                    self.resend(&mut *shared).await?;
+                    self.resend(&mut shared).await?;
+                    self.packets_too_small = false;
                     self.resend_timer
                         .set(self.runtime.sleep(self.settings.resend_time).fuse());
                 }
                 WakeReason::IncomingPacket(packet) => {
                     let mut shared = shared.lock().await;
-                    self.recv_packet(&mut *shared, packet).await?;
+                    self.recv_packet(&mut shared, packet).await?;
                 }
                 WakeReason::SendAvailable(mut shared) => {
                     // We should use available bandwidth for resends before sending, to avoid
                     // starving resends
-                    self.resend(&mut *shared).await?;
+                    self.resend(&mut shared).await?;
                     self.resend_timer
                         .set(self.runtime.sleep(self.settings.resend_time).fuse());
 
-                    self.send(&mut *shared).await?;
+                    self.send(&mut shared).await?;
+                }
+                WakeReason::UrgentAvailable(mut shared) => {
+                    self.send_urgent(&mut shared).await?;
                 }
             }
 
@@ -377,8 +945,18 @@ where
             return Ok(());
         }
 
+        let tick_budget = self.tick_budget.get();
+        let tick_remaining = match &tick_budget {
+            Some(budget) => {
+                self.tick_window.update(budget);
+                self.tick_window.remaining(budget)
+            }
+            None => u32::MAX,
+        };
+
         let send_amt = (shared.send_window.send_available())
             .min(self.remote_recv_available)
+            .min(tick_remaining)
             .min(i16::MAX as u32);
 
         if send_amt == 0 {
@@ -386,7 +964,16 @@ where
         }
 
         let mut packet = self.packet_pool.acquire();
-        let send_amt = send_amt.min((packet.capacity() - 6) as u32);
+        let send_amt = match data_capacity(&packet) {
+            Some(capacity) => send_amt.min(capacity),
+            None => {
+                debug_event!("packets are too small to send reliable data");
+                self.packets_too_small = true;
+                return Ok(());
+            }
+        };
+        // Follow any change to the size of packets, such as a lower `DatagramLimit`.
+        shared.urgent_max_len = urgent_max_len(&packet);
 
         packet.resize(6 + send_amt as usize, 0);
 
@@ -396,31 +983,114 @@ where
         LittleEndian::write_i16(&mut packet[0..2], send_amt as i16);
         LittleEndian::write_u32(&mut packet[2..6], start.0);
 
+        let now = self.runtime.now();
+        if self.unacked_ranges.is_empty() {
+            // Nothing has been in flight, so the time since the last acknowledgement was spent
+            // idle and should not count against the delivery rate.
+            self.delivered_time = now;
+            self.first_sent_time = now;
+        }
         self.unacked_ranges.insert(
             start,
             UnackedRange {
                 start,
                 end,
-                last_sent: Some(self.runtime.now()),
+                last_sent: Some(now),
                 retransmit: false,
+                loss_seq: Some(self.loss_tracker.sent()),
+                delivered: self.delivered,
+                delivered_time: self.delivered_time,
+                first_sent_time: self.first_sent_time,
             },
         );
 
         self.bandwidth_limiter.take_bytes(packet.len() as u32);
-        future::poll_fn(|cx| self.outgoing.poll_ready(cx))
-            .await
-            .map_err(|_| Error::Disconnected)?;
-        self.outgoing
-            .start_send(packet)
-            .map_err(|_| Error::Disconnected)?;
+        if tick_budget.is_some() {
+            self.tick_window.take(send_amt);
+        }
+        self.feedback_sent.add(&self.runtime, send_amt as u64);
+        trace_event!(start = start.0, len = send_amt, "sending reliable data");
+        send_packet(&mut self.outgoing, packet).await?;
 
         self.remote_recv_available -= send_amt;
 
         Ok(())
     }
 
+    // How long until new data may be sent again, if the budget for the current tick is used up.
+    fn tick_delay(&mut self) -> Option<Duration> {
+        let budget = self.tick_budget.get()?;
+        self.tick_window.update(&budget);
+        if self.tick_window.remaining(&budget) == 0 {
+            Some(self.tick_window.delay(&budget))
+        } else {
+            None
+        }
+    }
+
+    // Send every queued urgent message, regardless of bandwidth.
+    async fn send_urgent(&mut self, shared: &mut Shared) -> Result<(), Error> {
+        while let Some(msg) = shared.urgent_out.pop_front() {
+            // Messages queued before packets shrank, such as with a lower `DatagramLimit`, may no
+            // longer fit, and are dropped.
+            let packet = self.packet_pool.acquire();
+            shared.urgent_max_len = urgent_max_len(&packet);
+            if msg.len() > shared.urgent_max_len {
+                debug_event!(
+                    len = msg.len(),
+                    "dropping urgent message too large for packets"
+                );
+                if let Some(urgent_write_ready) = shared.urgent_write_ready.take() {
+                    urgent_write_ready.wake();
+                }
+                continue;
+            }
+
+            let seq = self.urgent_seq;
+            self.urgent_seq = self.urgent_seq.wrapping_add(1);
+
+            let packet = urgent_packet(packet, URGENT_DATA, seq, &msg);
+            self.bandwidth_limiter.take_bytes(packet.len() as u32);
+            trace_event!(seq, len = msg.len(), "sending urgent message");
+            send_packet(&mut self.outgoing, packet).await?;
+
+            self.urgent_unacked.insert(seq, (msg, self.runtime.now()));
+            shared.urgent_in_flight += 1;
+        }
+        Ok(())
+    }
+
     // Resend any data whose retransmit time has been reached, if we have the bandwidth for it
     async fn resend(&mut self, shared: &mut Shared) -> Result<(), Error> {
+        // Unacknowledged urgent messages are resent first, and regardless of bandwidth.
+        let resend_after = self.rtt_estimate * self.settings.rtt_resend_factor;
+        // As when they are first sent, messages which no longer fit in a packet are dropped.
+        let urgent_max_len = urgent_max_len(&self.packet_pool.acquire());
+        let urgent_unacked = self.urgent_unacked.len();
+        self.urgent_unacked
+            .retain(|_, (msg, _)| msg.len() <= urgent_max_len);
+        let dropped = urgent_unacked - self.urgent_unacked.len();
+        if dropped > 0 {
+            debug_event!(
+                dropped,
+                "dropping unacknowledged urgent messages too large for packets"
+            );
+            shared.urgent_in_flight -= dropped;
+            if let Some(urgent_write_ready) = shared.urgent_write_ready.take() {
+                urgent_write_ready.wake();
+            }
+        }
+        for (&seq, (msg, last_sent)) in &mut self.urgent_unacked {
+            if self.runtime.elapsed(*last_sent).as_secs_f64() > resend_after {
+                *last_sent = self.runtime.now();
+                let packet = urgent_packet(self.packet_pool.acquire(), URGENT_DATA, seq, msg);
+                self.bandwidth_limiter.take_bytes(packet.len() as u32);
+                debug_event!(seq, "retransmitting urgent message");
+                self.event_log.record(EventKind::UrgentRetransmit { seq });
+                send_packet(&mut self.outgoing, packet).await?;
+            }
+        }
+
         for unacked in self.unacked_ranges.values_mut() {
             if !self.bandwidth_limiter.bytes_available() {
                 break;
@@ -434,12 +1104,21 @@ where
             };
 
             if resend {
+                // If packets have shrunk since the range was first sent, only the start of it is
+                // resent, and the rest is resent once the start is acknowledged.
+                let mut packet = self.packet_pool.acquire();
+                let len = match data_capacity(&packet) {
+                    Some(capacity) => (unacked.end - unacked.start).0.min(capacity),
+                    None => break,
+                };
+
                 unacked.last_sent = Some(self.runtime.now());
                 unacked.retransmit = true;
+                if let Some(seq) = unacked.loss_seq {
+                    self.loss_tracker.lost(seq);
+                }
+                unacked.loss_seq = Some(self.loss_tracker.sent());
 
-                let len = (unacked.end - unacked.start).0;
-
-                let mut packet = self.packet_pool.acquire();
                 packet.resize(6 + len as usize, 0);
                 LittleEndian::write_i16(&mut packet[0..2], len as i16);
                 LittleEndian::write_u32(&mut packet[2..6], unacked.start.0);
@@ -449,14 +1128,23 @@ where
                     .get_unacked(unacked.start, &mut packet[6..]);
 
                 self.bandwidth_limiter.take_bytes(packet.len() as u32);
-
-                let outgoing = &mut self.outgoing;
-                future::poll_fn(|cx| outgoing.poll_ready(cx))
-                    .await
-                    .map_err(|_| Error::Disconnected)?;
-                outgoing
-                    .start_send(packet)
-                    .map_err(|_| Error::Disconnected)?;
+                self.feedback_sent.add(&self.runtime, len as u64);
+                #[cfg(feature = "metrics")]
+                self.metrics.mark_retransmit(len);
+                debug_event!(
+                    start = unacked.start.0,
+                    len,
+                    rtt_estimate = self.rtt_estimate,
+                    "retransmitting reliable data"
+                );
+                self.event_log.record(EventKind::Retransmit {
+                    len,
+                    timeout: Duration::from_secs_f64(
+                        self.rtt_estimate * self.settings.rtt_resend_factor,
+                    ),
+                });
+
+                send_packet(&mut self.outgoing, packet).await?;
             }
         }
 
@@ -471,7 +1159,11 @@ where
         }
 
         let data_len = LittleEndian::read_i16(&packet[0..2]);
-        if data_len < 0 {
+        if data_len == URGENT_MARKER {
+            self.recv_urgent(shared, packet).await?;
+        } else if data_len == FEEDBACK_MARKER {
+            self.recv_feedback(packet)?;
+        } else if data_len < 0 {
             if packet.len() != 10 {
                 return Err(Error::ProtocolError);
             }
@@ -479,6 +1171,12 @@ where
             let start_pos = Wrapping(LittleEndian::read_u32(&packet[2..6]));
             let end_pos = start_pos + Wrapping(-data_len as u32);
             let recv_window_end = Wrapping(LittleEndian::read_u32(&packet[6..10]));
+            trace_event!(
+                start = start_pos.0,
+                len = -data_len,
+                recv_window_end = recv_window_end.0,
+                "received reliable ack"
+            );
 
             if stream_gt(&recv_window_end, &shared.send_window.send_pos()) {
                 let old_remote_recv_available = self.remote_recv_available;
@@ -514,6 +1212,10 @@ where
                             end: nacked_end,
                             last_sent: None,
                             retransmit: true,
+                            loss_seq: None,
+                            delivered: acked.delivered,
+                            delivered_time: acked.delivered_time,
+                            first_sent_time: acked.first_sent_time,
                         },
                     );
                     Some(acked)
@@ -521,6 +1223,31 @@ where
             };
 
             if let Some(acked_range) = acked_range {
+                if let Some(seq) = acked_range.loss_seq {
+                    self.loss_tracker.delivered(seq, !acked_range.retransmit);
+                }
+                let now = self.runtime.now();
+                self.delivered += (acked_range.end - acked_range.start).0 as u64;
+                // As with the RTT, retransmitted ranges give no reliable delivery rate sample.
+                if let (false, Some(last_sent)) = (acked_range.retransmit, acked_range.last_sent) {
+                    // Acknowledgements can arrive bunched together, so the rate is measured over
+                    // whichever is longer of the time the acknowledged data took to send and the
+                    // time it took to be acknowledged.
+                    let send_interval = self
+                        .runtime
+                        .duration_between(acked_range.first_sent_time, last_sent);
+                    let ack_interval = self
+                        .runtime
+                        .duration_between(acked_range.delivered_time, now);
+                    self.bandwidth_estimator.add_sample(
+                        self.runtime.duration_between(self.start, now),
+                        self.delivered - acked_range.delivered,
+                        send_interval.max(ack_interval),
+                    );
+                    self.first_sent_time = last_sent;
+                }
+                self.delivered_time = now;
+
                 // Only update the RTT estimation for acked ranges that did not need to be
                 // retransmitted, otherwise we do not know which packet is being acked and thus
                 // can't be sure of the actual RTT for this ack.
@@ -531,8 +1258,15 @@ where
                             .elapsed(last_sent)
                             .min(self.settings.max_rtt)
                             .as_secs_f64();
+                        #[cfg(feature = "metrics")]
+                        self.metrics.mark_rtt(rtt);
                         self.rtt_estimate +=
                             (rtt - self.rtt_estimate) * self.settings.rtt_update_factor;
+                        trace_event!(
+                            rtt,
+                            rtt_estimate = self.rtt_estimate,
+                            "updated rtt estimate"
+                        );
                     }
                 }
 
@@ -553,6 +1287,11 @@ where
             }
 
             if let Some(end_pos) = shared.recv_window.recv(start_pos, &packet[6..]) {
+                trace_event!(
+                    start = start_pos.0,
+                    len = (end_pos - start_pos).0,
+                    "received reliable data"
+                );
                 let mut ack_packet = self.packet_pool.acquire();
                 ack_packet.resize(10, 0);
                 let ack_len = (end_pos - start_pos).0 as i16;
@@ -562,21 +1301,244 @@ where
 
                 // We currently do not count acknowledgement packets against the outgoing bandwidth
                 // at all.
-                future::poll_fn(|cx| self.outgoing.poll_ready(cx))
-                    .await
-                    .map_err(|_| Error::Disconnected)?;
-                self.outgoing
-                    .start_send(ack_packet)
-                    .map_err(|_| Error::Disconnected)?;
+                send_packet(&mut self.outgoing, ack_packet).await?;
+                self.send_feedback(shared, data_len as u64).await?;
 
                 if shared.recv_window.read_available() > 0 {
                     if let Some(read_ready) = shared.read_ready.take() {
                         read_ready.wake();
                     }
                 }
+            } else {
+                debug_event!(
+                    start = start_pos.0,
+                    len = data_len,
+                    "dropping reliable data outside of the receive window"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    // Count received stream data, and report the delivery rate and receive window occupancy to the
+    // remote once the feedback interval has passed.
+    async fn send_feedback(&mut self, shared: &mut Shared, received: u64) -> Result<(), Error> {
+        self.feedback_received.add(&self.runtime, received);
+        if self.feedback_received.elapsed(&self.runtime) < FEEDBACK_INTERVAL {
+            return Ok(());
+        }
+        let delivery_rate = match self.feedback_received.take_rate(&self.runtime) {
+            Some(rate) => rate.min((NO_REPORT - 1) as f64) as u32,
+            None => return Ok(()),
+        };
+        let occupancy = (shared.recv_window.read_available() as f64
+            / self.settings.recv_window_size as f64
+            * u16::MAX as f64) as u16;
+
+        let mut packet = self.packet_pool.acquire();
+        packet.resize(FEEDBACK_LEN, 0);
+        LittleEndian::write_i16(&mut packet[0..2], FEEDBACK_MARKER);
+        LittleEndian::write_u32(&mut packet[2..6], delivery_rate);
+        LittleEndian::write_u16(&mut packet[6..8], occupancy);
+        trace_event!(delivery_rate, occupancy, "sending congestion feedback");
+        send_packet(&mut self.outgoing, packet).await
+    }
+
+    // Adjust the send rate from a feedback packet sent by the remote's `send_feedback`.
+    fn recv_feedback(&mut self, packet: P::Packet) -> Result<(), Error> {
+        if packet.len() != FEEDBACK_LEN {
+            return Err(Error::ProtocolError);
+        }
+        let delivery_rate = LittleEndian::read_u32(&packet[2..6]).min(NO_REPORT - 1);
+        let occupancy = LittleEndian::read_u16(&packet[6..8]);
+
+        // The reported interval ends roughly when the report was sent, so compare it against what
+        // was sent since the previous report arrived.
+        let send_rate = self.feedback_sent.take_rate(&self.runtime);
+
+        let max_bandwidth = self.settings.bandwidth as f64;
+        let mut bandwidth = self.bandwidth_limiter.bandwidth() as f64;
+        match send_rate {
+            Some(send_rate) if (delivery_rate as f64) < send_rate * CONGESTION_THRESHOLD => {
+                bandwidth = bandwidth.min(delivery_rate as f64 * CONGESTION_DRAIN);
+                debug_event!(
+                    delivery_rate,
+                    send_rate,
+                    "remote reports congestion, lowering send rate"
+                );
+            }
+            _ if (occupancy as f64 / u16::MAX as f64) < HIGH_OCCUPANCY => {
+                bandwidth += max_bandwidth * CONGESTION_RECOVERY;
+            }
+            _ => {}
+        }
+        let bandwidth = bandwidth
+            .max(max_bandwidth * MIN_CONGESTION_BANDWIDTH)
+            .min(max_bandwidth)
+            .max(1.) as u32;
+        self.bandwidth_limiter.set_bandwidth(bandwidth);
+
+        let state = &self.congestion.0;
+        state.bandwidth.store(bandwidth, Ordering::Relaxed);
+        state
+            .remote_delivery_rate
+            .store(delivery_rate, Ordering::Relaxed);
+        state
+            .remote_occupancy
+            .store(occupancy as u32, Ordering::Relaxed);
+        trace_event!(
+            delivery_rate,
+            occupancy,
+            bandwidth,
+            "received congestion feedback"
+        );
+        Ok(())
+    }
+
+    // Receive an urgent message or urgent acknowledgement packet.
+    async fn recv_urgent(&mut self, shared: &mut Shared, packet: P::Packet) -> Result<(), Error> {
+        if packet.len() < URGENT_HEADER_LEN {
+            return Err(Error::ProtocolError);
+        }
+        let seq = LittleEndian::read_u32(&packet[3..7]);
+
+        match packet[2] {
+            URGENT_DATA => {
+                let msg = &packet[URGENT_HEADER_LEN..];
+                if msg.len() > MAX_URGENT_LEN {
+                    return Err(Error::ProtocolError);
+                }
+
+                // Duplicates are acknowledged again, the previous acknowledgement may have been
+                // lost.
+                let extended_seq = extend_urgent_seq(self.urgent_received.highest(), seq);
+                if let Some(extended_seq) =
+                    extended_seq.filter(|&s| self.urgent_received.accepts(s))
+                {
+                    if shared.urgent_in.len() >= URGENT_WINDOW {
+                        debug_event!(seq, "dropping urgent message, urgent buffer is full");
+                        self.event_log.record(EventKind::Overflow);
+                        return Ok(());
+                    }
+                    self.urgent_received.insert(extended_seq);
+                    trace_event!(seq, len = msg.len(), "received urgent message");
+                    shared.urgent_in.push_back(msg.into());
+                    if let Some(urgent_read_ready) = shared.urgent_read_ready.take() {
+                        urgent_read_ready.wake();
+                    }
+                }
+
+                let ack_packet = urgent_packet(self.packet_pool.acquire(), URGENT_ACK, seq, &[]);
+                send_packet(&mut self.outgoing, ack_packet).await?;
             }
+            URGENT_ACK => {
+                if packet.len() != URGENT_HEADER_LEN {
+                    return Err(Error::ProtocolError);
+                }
+                if self.urgent_unacked.remove(&seq).is_some() {
+                    trace_event!(seq, "received urgent ack");
+                    shared.urgent_in_flight -= 1;
+                    if let Some(urgent_write_ready) = shared.urgent_write_ready.take() {
+                        urgent_write_ready.wake();
+                    }
+                }
+            }
+            _ => return Err(Error::ProtocolError),
         }
 
         Ok(())
     }
 }
+
+// Counts stream data bytes over a feedback interval, to measure the rate data is sent or received.
+struct RateCounter<I> {
+    bytes: u64,
+    since: I,
+    last: I,
+}
+
+impl<I: Copy> RateCounter<I> {
+    fn new(now: I) -> Self {
+        RateCounter {
+            bytes: 0,
+            since: now,
+            last: now,
+        }
+    }
+
+    fn add<T: Timer<Instant = I>>(&mut self, timer: &T, bytes: u64) {
+        let now = timer.now();
+        if timer.duration_between(self.last, now) >= FEEDBACK_INTERVAL {
+            // After a gap in the data, start a new interval at this packet rather than counting the
+            // idle time against the rate.  The packet itself marks the start of the interval, so it
+            // is not counted either.
+            self.bytes = 0;
+            self.since = now;
+        } else {
+            self.bytes += bytes;
+        }
+        self.last = now;
+    }
+
+    fn elapsed<T: Timer<Instant = I>>(&self, timer: &T) -> Duration {
+        timer.elapsed(self.since)
+    }
+
+    // Return the rate in bytes / sec since the start of the interval, if anything was counted, and
+    // start a new interval.
+    fn take_rate<T: Timer<Instant = I>>(&mut self, timer: &T) -> Option<f64> {
+        let interval = self.elapsed(timer).as_secs_f64();
+        let bytes = std::mem::take(&mut self.bytes);
+        self.since = timer.now();
+        (bytes > 0 && interval > 0.).then(|| bytes as f64 / interval)
+    }
+}
+
+// The room for data in the given packet after the data header, if there is any.
+fn data_capacity<P: Packet>(packet: &P) -> Option<u32> {
+    match packet.capacity().checked_sub(6) {
+        Some(0) | None => None,
+        Some(capacity) => Some(capacity.min(i16::MAX as usize) as u32),
+    }
+}
+
+async fn send_packet<O, P>(outgoing: &mut O, packet: P) -> Result<(), Error>
+where
+    O: Sink<P> + Unpin,
+{
+    outgoing.send(packet).await.map_err(|_| Error::Disconnected)
+}
+
+// Urgent packets are the urgent marker, the packet kind, the wrapping u32 sequence number of the
+// urgent message, then for urgent data the message itself.
+fn urgent_packet<P: Packet>(mut packet: P, kind: u8, seq: u32, msg: &[u8]) -> P {
+    packet.resize(URGENT_HEADER_LEN, 0);
+    LittleEndian::write_i16(&mut packet[0..2], URGENT_MARKER);
+    packet[2] = kind;
+    LittleEndian::write_u32(&mut packet[3..7], seq);
+    packet.extend(msg);
+    packet
+}
+
+// Urgent sequence numbers wrap around on the wire, so each received one is taken as the 64 bit
+// sequence number nearest to the highest received so far, in either direction.  Returns `None`
+// for a sequence number which would be before the very first.
+fn extend_urgent_seq(highest: Option<u64>, seq: u32) -> Option<u64> {
+    let highest = match highest {
+        Some(highest) => highest,
+        None => return Some(seq as u64),
+    };
+    match seq.wrapping_sub(highest as u32) as i32 {
+        delta if delta >= 0 => Some(highest + delta as u64),
+        delta => highest.checked_sub(delta.unsigned_abs() as u64),
+    }
+}
+
+// The longest urgent message which fits in the given packet.
+fn urgent_max_len<P: Packet>(packet: &P) -> usize {
+    packet
+        .capacity()
+        .saturating_sub(URGENT_HEADER_LEN)
+        .min(MAX_URGENT_LEN)
+}
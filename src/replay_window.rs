@@ -0,0 +1,97 @@
+/// The number of sequence numbers behind the highest received sequence number that a
+/// `ReplayWindow` keeps track of.
+pub const WINDOW_SIZE: u64 = 1024;
+
+const WORDS: usize = (WINDOW_SIZE / 64) as usize;
+
+/// A sliding window over received packet sequence numbers, used to reject packets that have been
+/// recorded and re-injected by an attacker.
+///
+/// Each sequence number is accepted at most once.  Packets may arrive out of order by up to
+/// `WINDOW_SIZE` sequence numbers, anything older than that is rejected since it can no longer be
+/// told apart from a replay.
+///
+/// The sequence number of a packet should only be inserted once the packet has been
+/// authenticated, otherwise forged sequence numbers could be used to move the window forward and
+/// cause real packets to be rejected.
+#[derive(Debug, Clone)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    // A ring of bits indexed by sequence number modulo `WINDOW_SIZE`, bits are set for every
+    // sequence number received in `(highest - WINDOW_SIZE, highest]`.
+    bits: [u64; WORDS],
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        ReplayWindow::new()
+    }
+}
+
+impl ReplayWindow {
+    pub fn new() -> ReplayWindow {
+        ReplayWindow {
+            highest: None,
+            bits: [0; WORDS],
+        }
+    }
+
+    /// The highest sequence number inserted so far.
+    pub fn highest(&self) -> Option<u64> {
+        self.highest
+    }
+
+    /// Returns true if a packet with this sequence number would be accepted, without recording it.
+    pub fn accepts(&self, sequence: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(highest) if sequence > highest => true,
+            Some(highest) if highest - sequence >= WINDOW_SIZE => false,
+            Some(_) => !self.bit(sequence),
+        }
+    }
+
+    /// Record a sequence number as received, returns false and does nothing if it would not be
+    /// accepted.
+    pub fn insert(&mut self, sequence: u64) -> bool {
+        if !self.accepts(sequence) {
+            return false;
+        }
+
+        match self.highest {
+            Some(highest) if sequence <= highest => {}
+            Some(highest) if sequence - highest < WINDOW_SIZE => {
+                for skipped in highest + 1..sequence {
+                    self.clear_bit(skipped);
+                }
+                self.highest = Some(sequence);
+            }
+            _ => {
+                self.bits = [0; WORDS];
+                self.highest = Some(sequence);
+            }
+        }
+        self.set_bit(sequence);
+        true
+    }
+
+    fn bit(&self, sequence: u64) -> bool {
+        let (word, bit) = index(sequence);
+        self.bits[word] & (1 << bit) != 0
+    }
+
+    fn set_bit(&mut self, sequence: u64) {
+        let (word, bit) = index(sequence);
+        self.bits[word] |= 1 << bit;
+    }
+
+    fn clear_bit(&mut self, sequence: u64) {
+        let (word, bit) = index(sequence);
+        self.bits[word] &= !(1 << bit);
+    }
+}
+
+fn index(sequence: u64) -> (usize, u64) {
+    let i = sequence % WINDOW_SIZE;
+    ((i / 64) as usize, i % 64)
+}
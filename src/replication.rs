@@ -0,0 +1,870 @@
+//! Replication of entity component state from a server to each of its peers.
+//!
+//! Component types are registered with a `ComponentId` on both sides, the server sets component
+//! values on entities with `Replicator::set`, which marks them dirty, and the `Replicator` decides
+//! what to send to each peer.  For every peer it remembers the last value of each component that
+//! the peer is known to have received, and sends changed components as byte-level deltas against
+//! that baseline, the same encoding as `DeltaChannel`.  Each peer has a budget of bytes sent per
+//! update, and when not everything fits, entities are sent in order of their accumulated
+//! priority, which grows every update that an entity is left waiting so that nothing starves.
+//!
+//! Updates are sent over an `AckedChannel` per peer, whose acknowledgement events drive the
+//! baselines.  Nothing is resent as-is: a change in a lost update is simply marked dirty again, and
+//! whatever the latest value is by then is sent instead.  Peers receive updates with a `Replica`,
+//! which must regularly `flush` to send its acknowledgements.
+
+use std::{
+    any::{Any, TypeId},
+    cmp::Ordering,
+    mem,
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{FutureExt, Sink, Stream};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use crate::{
+    acked_channel::{self, AckEvent, AckedChannel, MessageId},
+    delta_channel::{apply_delta, encode_delta, is_newer},
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing},
+};
+
+/// Identifies a replicated entity.
+pub type EntityId = u32;
+
+/// Identifies a registered component type, must be the same on both sides.
+pub type ComponentId = u16;
+
+/// The number of recent updates a `Replica` keeps component values from.  Deltas are only encoded
+/// against baselines from this many updates ago or fewer, older baselines get the full value.
+pub const HISTORY_LEN: u16 = 32;
+
+const FULL: u8 = 0;
+const DELTA: u8 = 1;
+const REMOVE: u8 = 2;
+
+// Every update message starts with its sequence number.
+const UPDATE_HEADER_LEN: usize = 2;
+
+// The entity, component and kind of an entry.
+const ENTRY_HEADER_LEN: usize = 7;
+
+type Key = (EntityId, ComponentId);
+
+#[derive(Debug, Error)]
+pub enum RegisterError {
+    #[error("component type already registered")]
+    TypeAlreadyRegistered,
+    #[error("component id {0} already registered")]
+    IdAlreadyRegistered(ComponentId),
+}
+
+#[derive(Debug, Error)]
+pub enum RecvError {
+    /// Fatal if the channel is disconnected, otherwise non-fatal.
+    #[error(transparent)]
+    AckedChannelError(#[from] acked_channel::RecvError),
+    /// Non-fatal error, the remainder of the update is dropped.
+    #[error("incoming update has bad format")]
+    BadFormat,
+    /// Non-fatal error, the remainder of the update is dropped.
+    #[error("bincode serialization error: {0}")]
+    BincodeError(#[from] bincode::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The most bytes of updates written to a peer by each `Replicator::send`, including
+    /// everything but the packet headers of the peer's channel.
+    pub update_budget: u32,
+    /// The maximum length of each update message, which must fit in the packets of each peer's
+    /// channel, see `AckedChannel::send`.
+    pub max_message_len: u16,
+}
+
+/// Identifies a peer added to a `Replicator`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(u64);
+
+/// What a call to `Replicator::send` wrote.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Update {
+    /// The number of changed or removed components sent.
+    pub sent: usize,
+    /// The number of changed or removed components left for a later update, because they did not
+    /// fit in the budget.
+    pub deferred: usize,
+    /// The total length of the update messages sent.
+    pub bytes: usize,
+}
+
+/// The server side of replication, which keeps the replicated state and sends it to each peer.
+pub struct Replicator<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    settings: Settings,
+    components: FxHashMap<TypeId, ComponentId>,
+    entities: FxHashMap<EntityId, Entity>,
+    next_version: u64,
+    peers: FxHashMap<PeerId, Peer<R, P, I, O>>,
+    next_peer: u64,
+    buffer: Vec<u8>,
+}
+
+struct Entity {
+    priority: f32,
+    components: FxHashMap<ComponentId, Slot>,
+}
+
+// A component value, or a removed component that some peer may still have.
+struct Slot {
+    version: u64,
+    value: Option<Vec<u8>>,
+}
+
+struct Peer<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: AckedChannel<R, P, I, O>,
+    next_seq: u16,
+    slots: FxHashMap<Key, PeerSlot>,
+    // Every component which may need sending to this peer.
+    pending: FxHashSet<Key>,
+    accumulators: FxHashMap<EntityId, f32>,
+    in_flight: FxHashMap<MessageId, (u16, Vec<SentEntry>)>,
+}
+
+// What a peer is known to have received of a component, and which version of it is in flight.
+#[derive(Default)]
+struct PeerSlot {
+    acked: Option<Baseline>,
+    sent: Option<u64>,
+}
+
+struct Baseline {
+    seq: u16,
+    version: u64,
+    value: Option<Vec<u8>>,
+}
+
+struct SentEntry {
+    key: Key,
+    version: u64,
+    value: Option<Vec<u8>>,
+}
+
+enum Status {
+    Done,
+    Waiting,
+    Send,
+}
+
+impl<R, P, I, O> Replicator<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(settings: Settings) -> Self {
+        Replicator {
+            settings,
+            components: FxHashMap::default(),
+            entities: FxHashMap::default(),
+            next_version: 0,
+            peers: FxHashMap::default(),
+            next_peer: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Register a component type to be replicated with the given id.
+    pub fn register<C>(&mut self, id: ComponentId) -> Result<(), RegisterError>
+    where
+        C: Serialize + 'static,
+    {
+        register(&mut self.components, TypeId::of::<C>(), id)
+    }
+
+    /// Add a peer, which will be sent the full replicated state over the given channel.
+    pub fn add_peer(&mut self, channel: AckedChannel<R, P, I, O>) -> PeerId {
+        let id = PeerId(self.next_peer);
+        self.next_peer += 1;
+        let pending = self
+            .entities
+            .iter()
+            .flat_map(|(&entity, e)| {
+                e.components
+                    .iter()
+                    .filter(|(_, slot)| slot.value.is_some())
+                    .map(move |(&component, _)| (entity, component))
+            })
+            .collect();
+        self.peers.insert(
+            id,
+            Peer {
+                channel,
+                next_seq: 0,
+                slots: FxHashMap::default(),
+                pending,
+                accumulators: FxHashMap::default(),
+                in_flight: FxHashMap::default(),
+            },
+        );
+        id
+    }
+
+    /// Remove a peer, returning its channel.
+    pub fn remove_peer(&mut self, peer: PeerId) -> Option<AckedChannel<R, P, I, O>> {
+        let removed = self.peers.remove(&peer)?;
+        for key in removed.slots.keys().chain(&removed.pending) {
+            self.collect_removed(*key);
+        }
+        Some(removed.channel)
+    }
+
+    /// The number of components which have changed since the given peer last acknowledged them.
+    ///
+    /// # Panics
+    /// Panics if the peer has been removed.
+    pub fn pending(&self, peer: PeerId) -> usize {
+        self.peers[&peer].pending.len()
+    }
+
+    /// Set the value of a component on an entity, spawning the entity if it does not exist.  If
+    /// the serialized value has changed, the component is marked dirty for every peer.
+    ///
+    /// # Panics
+    /// Panics if the component type is not registered.
+    pub fn set<C>(&mut self, entity: EntityId, value: &C) -> Result<(), bincode::Error>
+    where
+        C: Serialize + 'static,
+    {
+        let component = self.component_id::<C>();
+        let value = bincode::serialize(value)?;
+
+        let slot = self
+            .entities
+            .entry(entity)
+            .or_insert_with(|| Entity {
+                priority: 1.0,
+                components: FxHashMap::default(),
+            })
+            .components
+            .entry(component)
+            .or_insert(Slot {
+                version: 0,
+                value: None,
+            });
+        if slot.value.as_ref() != Some(&value) {
+            slot.version = self.next_version;
+            self.next_version += 1;
+            slot.value = Some(value);
+            for peer in self.peers.values_mut() {
+                peer.pending.insert((entity, component));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a component from an entity.
+    ///
+    /// # Panics
+    /// Panics if the component type is not registered.
+    pub fn remove<C: 'static>(&mut self, entity: EntityId) {
+        let component = self.component_id::<C>();
+        self.remove_component((entity, component));
+    }
+
+    /// Remove every component of an entity.
+    pub fn despawn(&mut self, entity: EntityId) {
+        let components: Vec<_> = match self.entities.get(&entity) {
+            Some(e) => e.components.keys().copied().collect(),
+            None => return,
+        };
+        for component in components {
+            self.remove_component((entity, component));
+        }
+    }
+
+    /// Set how quickly an entity's priority accumulates while it waits to be sent, by default 1.0.
+    /// An entity with a priority of 2.0 is sent twice as often as one of 1.0 when the budget does
+    /// not fit both.
+    ///
+    /// Does nothing if the entity has no components.
+    pub fn set_priority(&mut self, entity: EntityId, priority: f32) {
+        if let Some(e) = self.entities.get_mut(&entity) {
+            e.priority = priority;
+        }
+    }
+
+    /// Process the acknowledgements received from a peer, then send it the highest priority
+    /// changes that fit in the update budget and flush its channel.
+    ///
+    /// This should be called for each peer once per tick.
+    ///
+    /// # Panics
+    /// Panics if the peer has been removed.
+    pub async fn send(&mut self, peer: PeerId) -> Result<Update, acked_channel::SendError> {
+        let Replicator {
+            settings,
+            entities,
+            peers,
+            buffer,
+            ..
+        } = self;
+        let p = peers.get_mut(&peer).unwrap();
+
+        // The remote only sends acknowledgements, which are processed as a side effect of
+        // receiving.
+        while let Some(res) = p.channel.recv().now_or_never() {
+            match res {
+                Ok(_) => {
+                    debug_event!("dropping unexpected message from replica");
+                }
+                Err(acked_channel::RecvError::Disconnected) => {
+                    return Err(acked_channel::SendError::Disconnected)
+                }
+                Err(acked_channel::RecvError::BadFormat) => {}
+            }
+        }
+        p.recv_events();
+
+        let mut groups: FxHashMap<EntityId, Vec<ComponentId>> = FxHashMap::default();
+        let mut removed = Vec::new();
+        let peer_slots = &mut p.slots;
+        p.pending
+            .retain(|&key| match status(entities, peer_slots, key) {
+                Status::Done => {
+                    if slot(entities, key).is_none_or(|slot| slot.value.is_none()) {
+                        peer_slots.remove(&key);
+                        removed.push(key);
+                    }
+                    false
+                }
+                Status::Waiting => true,
+                Status::Send => {
+                    groups.entry(key.0).or_default().push(key.1);
+                    true
+                }
+            });
+
+        p.accumulators
+            .retain(|entity, _| groups.contains_key(entity));
+        let mut order: Vec<(f32, EntityId)> = groups
+            .keys()
+            .map(|&entity| {
+                let acc = p.accumulators.entry(entity).or_insert(0.0);
+                *acc += entities[&entity].priority;
+                (*acc, entity)
+            })
+            .collect();
+        order.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0)
+                .unwrap_or(Ordering::Equal)
+                .then(a.1.cmp(&b.1))
+        });
+
+        let max_message_len = settings.max_message_len as usize;
+        let mut budget = settings.update_budget as usize;
+        let mut update = Update::default();
+        let mut message = Vec::new();
+        let mut entries = Vec::new();
+        for (_, entity) in order {
+            let mut components = groups.remove(&entity).unwrap();
+            components.sort_unstable();
+            let mut all_sent = true;
+            for component in components {
+                let key = (entity, component);
+                let slot = slot(entities, key).unwrap();
+                buffer.clear();
+                p.encode(key, slot, buffer);
+
+                if UPDATE_HEADER_LEN + buffer.len() > max_message_len {
+                    warn_event!(
+                        entity,
+                        component,
+                        len = buffer.len(),
+                        "replicated component too big for an update message"
+                    );
+                    update.deferred += 1;
+                    all_sent = false;
+                    continue;
+                }
+
+                let new_message =
+                    message.is_empty() || message.len() + buffer.len() > max_message_len;
+                let len = buffer.len() + if new_message { UPDATE_HEADER_LEN } else { 0 };
+                if len > budget {
+                    update.deferred += 1;
+                    all_sent = false;
+                    continue;
+                }
+
+                if new_message {
+                    update.bytes += message.len();
+                    p.send_message(&mut message, &mut entries).await?;
+                    message.resize(UPDATE_HEADER_LEN, 0);
+                    LittleEndian::write_u16(&mut message, p.next_seq);
+                }
+                message.extend_from_slice(buffer);
+                entries.push(SentEntry {
+                    key,
+                    version: slot.version,
+                    value: slot.value.clone(),
+                });
+                budget -= len;
+                update.sent += 1;
+            }
+            if all_sent {
+                p.accumulators.remove(&entity);
+            }
+        }
+        update.bytes += message.len();
+        p.send_message(&mut message, &mut entries).await?;
+        p.channel.flush().await?;
+
+        for key in removed {
+            self.collect_removed(key);
+        }
+        Ok(update)
+    }
+
+    fn component_id<C: 'static>(&self) -> ComponentId {
+        *self
+            .components
+            .get(&TypeId::of::<C>())
+            .expect("component type is not registered")
+    }
+
+    fn remove_component(&mut self, key: Key) {
+        let slot = match self
+            .entities
+            .get_mut(&key.0)
+            .and_then(|e| e.components.get_mut(&key.1))
+        {
+            Some(slot) if slot.value.is_some() => slot,
+            _ => return,
+        };
+        slot.version = self.next_version;
+        self.next_version += 1;
+        slot.value = None;
+
+        // Only peers which may have been sent the component need to be told it is gone.
+        for peer in self.peers.values_mut() {
+            if peer.slots.contains_key(&key) {
+                peer.pending.insert(key);
+            }
+        }
+        self.collect_removed(key);
+    }
+
+    // Forget a removed component once no peer may still have it.
+    fn collect_removed(&mut self, key: Key) {
+        let entity = match self.entities.get_mut(&key.0) {
+            Some(entity) => entity,
+            None => return,
+        };
+        match entity.components.get(&key.1) {
+            Some(slot) if slot.value.is_none() => {}
+            _ => return,
+        }
+        if self
+            .peers
+            .values()
+            .any(|peer| peer.pending.contains(&key) || peer.slots.contains_key(&key))
+        {
+            return;
+        }
+        entity.components.remove(&key.1);
+        if entity.components.is_empty() {
+            self.entities.remove(&key.0);
+        }
+    }
+}
+
+impl<R, P, I, O> Peer<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    fn recv_events(&mut self) {
+        while let Some(event) = self.channel.next_event() {
+            match event {
+                AckEvent::Acked { messages, .. } => {
+                    for id in messages {
+                        let (seq, entries) = match self.in_flight.remove(&id) {
+                            Some(sent) => sent,
+                            None => continue,
+                        };
+                        for entry in entries {
+                            let slot = self.slots.entry(entry.key).or_default();
+                            if slot
+                                .acked
+                                .as_ref()
+                                .is_none_or(|b| b.version < entry.version)
+                            {
+                                slot.acked = Some(Baseline {
+                                    seq,
+                                    version: entry.version,
+                                    value: entry.value,
+                                });
+                            }
+                        }
+                    }
+                }
+                AckEvent::Lost { messages, .. } => {
+                    for id in messages {
+                        for entry in self.in_flight.remove(&id).into_iter().flat_map(|s| s.1) {
+                            if let Some(slot) = self.slots.get_mut(&entry.key) {
+                                if slot.sent == Some(entry.version) {
+                                    slot.sent = None;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Encode a component entry, as a delta against the acknowledged baseline if there is a recent
+    // enough one and the delta is smaller.
+    fn encode(&self, key: Key, slot: &Slot, out: &mut Vec<u8>) {
+        let mut header = [0; ENTRY_HEADER_LEN];
+        LittleEndian::write_u32(&mut header[0..4], key.0);
+        LittleEndian::write_u16(&mut header[4..6], key.1);
+
+        let value = match &slot.value {
+            Some(value) => value,
+            None => {
+                header[6] = REMOVE;
+                out.extend_from_slice(&header);
+                return;
+            }
+        };
+
+        // The entry may start the next message, so the window is one less than the history.
+        let baseline = self.slots.get(&key).and_then(|s| s.acked.as_ref());
+        if let Some(Baseline {
+            seq,
+            value: Some(base),
+            ..
+        }) = baseline
+        {
+            if self.next_seq.wrapping_sub(*seq) < HISTORY_LEN - 1 {
+                header[6] = DELTA;
+                out.extend_from_slice(&header);
+                write_u16(out, *seq);
+                write_u16(out, 0);
+                encode_delta(base, value, out);
+                let len = out.len() - ENTRY_HEADER_LEN - 4;
+                if len < value.len() {
+                    LittleEndian::write_u16(&mut out[ENTRY_HEADER_LEN + 2..], len as u16);
+                    return;
+                }
+                out.clear();
+            }
+        }
+
+        header[6] = FULL;
+        out.extend_from_slice(&header);
+        write_u16(out, value.len() as u16);
+        out.extend_from_slice(value);
+    }
+
+    async fn send_message(
+        &mut self,
+        message: &mut Vec<u8>,
+        entries: &mut Vec<SentEntry>,
+    ) -> Result<(), acked_channel::SendError> {
+        if message.is_empty() {
+            return Ok(());
+        }
+        // The sequence number is used even if sending is canceled, since the message may still
+        // have been buffered.
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let id = self.channel.send(message).await?;
+        message.clear();
+
+        for entry in entries.iter() {
+            self.slots.entry(entry.key).or_default().sent = Some(entry.version);
+        }
+        self.in_flight.insert(id, (seq, mem::take(entries)));
+        Ok(())
+    }
+}
+
+/// A change to a `Replica`, returned by `Replica::recv`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Change {
+    pub entity: EntityId,
+    pub component: ComponentId,
+    /// Whether the component was removed, rather than added or changed.
+    pub removed: bool,
+}
+
+type Decoder = fn(&[u8]) -> bincode::Result<Box<dyn Any + Send + Sync>>;
+
+/// The peer side of replication, which receives the state sent by a `Replicator`.
+///
+/// Updates may arrive out of order, but are only applied to components which have not already
+/// been changed by a newer update.
+pub struct Replica<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: AckedChannel<R, P, I, O>,
+    components: FxHashMap<TypeId, ComponentId>,
+    decoders: FxHashMap<ComponentId, Decoder>,
+    slots: FxHashMap<Key, ReplicaSlot>,
+    newest_seq: Option<u16>,
+    received: u16,
+    changes: Vec<Change>,
+}
+
+struct ReplicaSlot {
+    // Values from recent updates that the replicator may use as baselines, and always the newest.
+    history: Vec<(u16, Option<Vec<u8>>)>,
+    value: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl<R, P, I, O> Replica<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(channel: AckedChannel<R, P, I, O>) -> Self {
+        Replica {
+            channel,
+            components: FxHashMap::default(),
+            decoders: FxHashMap::default(),
+            slots: FxHashMap::default(),
+            newest_seq: None,
+            received: 0,
+            changes: Vec::new(),
+        }
+    }
+
+    /// Register a component type with the same id it has on the replicator.  Components which are
+    /// not registered are still tracked, but cannot be read.
+    pub fn register<C>(&mut self, id: ComponentId) -> Result<(), RegisterError>
+    where
+        C: DeserializeOwned + Send + Sync + 'static,
+    {
+        register(&mut self.components, TypeId::of::<C>(), id)?;
+        self.decoders.insert(id, |value| {
+            Ok(Box::new(bincode::deserialize::<C>(value)?) as Box<dyn Any + Send + Sync>)
+        });
+        Ok(())
+    }
+
+    /// The current value of a component on an entity, if it has one.
+    ///
+    /// # Panics
+    /// Panics if the component type is not registered.
+    pub fn get<C: 'static>(&self, entity: EntityId) -> Option<&C> {
+        let component = *self
+            .components
+            .get(&TypeId::of::<C>())
+            .expect("component type is not registered");
+        self.slots
+            .get(&(entity, component))?
+            .value
+            .as_ref()?
+            .downcast_ref()
+    }
+
+    /// Receive and apply the next update, returning the components it changed.
+    ///
+    /// This method is cancel safe, it will never partially apply an update.
+    pub async fn recv(&mut self) -> Result<&[Change], RecvError> {
+        let msg = self.channel.recv().await?;
+        self.changes.clear();
+        if msg.len() < UPDATE_HEADER_LEN {
+            return Err(RecvError::BadFormat);
+        }
+        let seq = LittleEndian::read_u16(&msg[0..2]);
+        match self.newest_seq {
+            Some(newest) if !is_newer(seq, newest) => {}
+            _ => self.newest_seq = Some(seq),
+        }
+        let newest = self.newest_seq.unwrap();
+
+        let mut entries = &msg[UPDATE_HEADER_LEN..];
+        while !entries.is_empty() {
+            let header = entries
+                .get(..ENTRY_HEADER_LEN)
+                .ok_or(RecvError::BadFormat)?;
+            let key = (
+                LittleEndian::read_u32(&header[0..4]),
+                LittleEndian::read_u16(&header[4..6]),
+            );
+            entries = &entries[ENTRY_HEADER_LEN..];
+
+            let slot = self.slots.entry(key).or_insert_with(|| ReplicaSlot {
+                history: Vec::new(),
+                value: None,
+            });
+            let value = match header[6] {
+                REMOVE => None,
+                FULL => {
+                    let len = read_u16(&mut entries)? as usize;
+                    let value = entries.get(..len).ok_or(RecvError::BadFormat)?.to_vec();
+                    entries = &entries[len..];
+                    Some(value)
+                }
+                DELTA => {
+                    let base_seq = read_u16(&mut entries)?;
+                    let len = read_u16(&mut entries)? as usize;
+                    let delta = entries.get(..len).ok_or(RecvError::BadFormat)?;
+                    entries = &entries[len..];
+                    let base = slot.history.iter().find(|(s, _)| *s == base_seq);
+                    match base {
+                        Some((_, Some(base))) => {
+                            Some(apply_delta(base, delta).ok_or(RecvError::BadFormat)?)
+                        }
+                        _ => {
+                            debug_event!(base_seq, "dropping delta with unknown baseline");
+                            continue;
+                        }
+                    }
+                }
+                _ => return Err(RecvError::BadFormat),
+            };
+
+            if slot.history.iter().any(|(s, _)| *s == seq) {
+                continue;
+            }
+            // The value must be kept as a possible baseline even if it fails to decode.
+            let is_current = slot.history.iter().all(|(s, _)| is_newer(seq, *s));
+            slot.history.push((seq, value));
+            prune(&mut slot.history, newest);
+            if is_current {
+                let value = slot.history.last().unwrap().1.as_deref();
+                self.changes.push(Change {
+                    entity: key.0,
+                    component: key.1,
+                    removed: value.is_none(),
+                });
+                slot.value = None;
+                if let (Some(value), Some(decode)) = (value, self.decoders.get(&key.1)) {
+                    slot.value = Some(decode(value)?);
+                }
+            }
+        }
+
+        // Every so often, also prune components which have not been updated recently, and forget
+        // removed components once they cannot be a baseline.
+        self.received = self.received.wrapping_add(1);
+        if self.received.is_multiple_of(HISTORY_LEN) {
+            self.slots.retain(|_, slot| {
+                prune(&mut slot.history, newest);
+                slot.history
+                    .iter()
+                    .any(|(s, v)| v.is_some() || newest.wrapping_sub(*s) < HISTORY_LEN)
+            });
+        }
+
+        Ok(&self.changes)
+    }
+
+    /// Send acknowledgements for the received updates, which should be done once per tick.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), acked_channel::SendError> {
+        self.channel.flush().await
+    }
+}
+
+fn register(
+    components: &mut FxHashMap<TypeId, ComponentId>,
+    type_id: TypeId,
+    id: ComponentId,
+) -> Result<(), RegisterError> {
+    if components.contains_key(&type_id) {
+        return Err(RegisterError::TypeAlreadyRegistered);
+    }
+    if components.values().any(|&c| c == id) {
+        return Err(RegisterError::IdAlreadyRegistered(id));
+    }
+    components.insert(type_id, id);
+    Ok(())
+}
+
+fn status(
+    entities: &FxHashMap<EntityId, Entity>,
+    peer_slots: &FxHashMap<Key, PeerSlot>,
+    key: Key,
+) -> Status {
+    let slot = match slot(entities, key) {
+        Some(slot) => slot,
+        None => return Status::Done,
+    };
+    let peer_slot = match peer_slots.get(&key) {
+        Some(peer_slot) => peer_slot,
+        None if slot.value.is_none() => return Status::Done,
+        None => return Status::Send,
+    };
+    let acked = peer_slot.acked.as_ref();
+    if acked.map(|b| b.version) == Some(slot.version) {
+        return Status::Done;
+    }
+    if peer_slot.sent == Some(slot.version) {
+        Status::Waiting
+    } else {
+        Status::Send
+    }
+}
+
+fn slot(entities: &FxHashMap<EntityId, Entity>, key: Key) -> Option<&Slot> {
+    entities.get(&key.0)?.components.get(&key.1)
+}
+
+// Drop all but the newest value older than the history window.
+fn prune(history: &mut Vec<(u16, Option<Vec<u8>>)>, newest: u16) {
+    let latest = match history
+        .iter()
+        .map(|(s, _)| *s)
+        .reduce(|a, b| if is_newer(b, a) { b } else { a })
+    {
+        Some(latest) => latest,
+        None => return,
+    };
+    history.retain(|(s, _)| *s == latest || newest.wrapping_sub(*s) < HISTORY_LEN);
+}
+
+fn read_u16(buf: &mut &[u8]) -> Result<u16, RecvError> {
+    let val = LittleEndian::read_u16(buf.get(..2).ok_or(RecvError::BadFormat)?);
+    *buf = &buf[2..];
+    Ok(val)
+}
+
+fn write_u16(buffer: &mut Vec<u8>, val: u16) {
+    let mut bytes = [0; 2];
+    LittleEndian::write_u16(&mut bytes, val);
+    buffer.extend_from_slice(&bytes);
+}
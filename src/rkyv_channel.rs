@@ -0,0 +1,309 @@
+//! Channels which send messages archived with `rkyv`.
+//!
+//! Received messages are validated and then accessed in place as their archived representation,
+//! without deserializing them.  This avoids any allocation for received messages, which matters
+//! most for large messages sent at a high rate, such as world snapshots.
+//!
+//! Archived data must be aligned, so incoming unreliable messages are copied into an aligned,
+//! reused buffer before being validated.  The reliable channel reads message data directly into its
+//! aligned buffer.
+
+use std::mem;
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{Sink, Stream};
+use rkyv::{
+    api::high::{HighSerializer, HighValidator},
+    bytecheck::CheckBytes,
+    rancor,
+    ser::allocator::ArenaHandle,
+    util::AlignedVec,
+    Archive, Serialize,
+};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    reliable_channel::{self, ReliableChannel},
+    runtime::Timer,
+    unreliable_channel::{
+        self, DefaultIncoming, DefaultOutgoing, UnreliableChannel, MAX_MESSAGE_LEN,
+    },
+};
+
+/// The serializer used to archive outgoing messages.
+pub type RkyvSerializer<'a> = HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>;
+
+/// The validator used to check incoming archived messages.
+pub type RkyvValidator<'a> = HighValidator<'a, rancor::Error>;
+
+#[derive(Debug, Error)]
+pub enum SendError {
+    #[error("unreliable channel error: {0}")]
+    UnreliableChannelError(#[from] unreliable_channel::SendError),
+    /// Non-fatal error, message is unsent.
+    #[error("archived message exceeds the configured max message length")]
+    TooLarge,
+    /// Non-fatal error, message is unsent.
+    #[error("rkyv error: {0}")]
+    RkyvError(rancor::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum RecvError {
+    #[error("unreliable channel error: {0}")]
+    UnreliableChannelError(#[from] unreliable_channel::RecvError),
+    /// Non-fatal error, message failed validation and is skipped.
+    #[error("rkyv error: {0}")]
+    RkyvError(rancor::Error),
+}
+
+/// Wraps an `UnreliableChannel` together with internal aligned buffers to allow sending messages
+/// archived with `rkyv` and accessing received messages without deserializing them.
+///
+/// Just like the underlying channel, messages are not guaranteed to arrive, nor are they guaranteed
+/// to arrive in order.
+pub struct UnreliableRkyvChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableChannel<R, P, I, O>,
+    max_message_len: u16,
+    write_buffer: AlignedVec,
+    read_buffer: AlignedVec,
+}
+
+impl<R, P, I, O> UnreliableRkyvChannel<R, P, I, O>
+where
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    /// Create a new `UnreliableRkyvChannel` with the given max message size.
+    ///
+    /// The maximum message size is always limited by the underlying `UnreliableChannel` maximum
+    /// message size regardless of the `max_message_len` setting.
+    pub fn new(channel: UnreliableChannel<R, P, I, O>, max_message_len: u16) -> Self {
+        let max_message_len = max_message_len.min(MAX_MESSAGE_LEN);
+        UnreliableRkyvChannel {
+            channel,
+            max_message_len,
+            write_buffer: AlignedVec::with_capacity(max_message_len as usize),
+            read_buffer: AlignedVec::with_capacity(max_message_len as usize),
+        }
+    }
+
+    /// Archive the given message and write it to the channel.
+    ///
+    /// Messages are coalesced into larger packets before being sent, so in order to guarantee that
+    /// the message is actually sent, you must call `flush`.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send<T>(&mut self, msg: &T) -> Result<(), SendError>
+    where
+        T: for<'a> Serialize<RkyvSerializer<'a>>,
+    {
+        let mut buffer = mem::take(&mut self.write_buffer);
+        buffer.clear();
+        self.write_buffer =
+            rkyv::api::high::to_bytes_in(msg, buffer).map_err(SendError::RkyvError)?;
+        if self.write_buffer.len() > self.max_message_len as usize {
+            return Err(SendError::TooLarge);
+        }
+        Ok(self.channel.send(&self.write_buffer).await?)
+    }
+
+    /// Finish sending any unsent coalesced packets.
+    ///
+    /// This *must* be called to guarantee that any sent messages are actually sent to the outgoing
+    /// packet stream.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        Ok(self.channel.flush().await?)
+    }
+
+    /// Receive the next message and access it as its archived type.
+    ///
+    /// The returned reference borrows the channel's internal buffer and is valid until the next
+    /// call to `recv`.
+    ///
+    /// This method is cancel safe, it will never partially receive a message and will never drop a
+    /// received message.
+    pub async fn recv<'a, T>(&'a mut self) -> Result<&'a T::Archived, RecvError>
+    where
+        T: Archive,
+        T::Archived: 'a + for<'v> CheckBytes<RkyvValidator<'v>>,
+    {
+        let msg = self.channel.recv().await?;
+        self.read_buffer.clear();
+        self.read_buffer.extend_from_slice(msg);
+        rkyv::access::<T::Archived, rancor::Error>(&self.read_buffer).map_err(RecvError::RkyvError)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Fatal internal channel error.
+    #[error("reliable channel error: {0}")]
+    ReliableChannelError(#[from] reliable_channel::Error),
+    /// Fatal, reading the next message would exceed the maximum buffer length, no progress can be
+    /// made.
+    #[error("received message exceeds the configured max message length")]
+    PrefixTooLarge,
+    /// Non-fatal, no message is sent.
+    #[error("archived message exceeds the configured max message length")]
+    TooLarge,
+    /// Non-fatal, on send, no message is sent, on receive the message failed validation and is
+    /// *skipped*.
+    #[error("rkyv error: {0}")]
+    RkyvError(rancor::Error),
+}
+
+/// Wraps a `ReliableChannel` together with internal aligned buffers to allow sending messages
+/// archived with `rkyv` and accessing received messages without deserializing them.
+///
+/// Messages are guaranteed to arrive, and are guaranteed to be in order.  Just like
+/// `ReliableBincodeChannel`, every message is prefixed with its length as a 2 byte little endian
+/// integer.
+pub struct ReliableRkyvChannel {
+    channel: ReliableChannel,
+    max_message_len: u16,
+
+    write_prefix: [u8; 2],
+    write_buffer: AlignedVec,
+    write_pos: usize,
+    write_end: usize,
+
+    read_prefix: [u8; 2],
+    read_buffer: AlignedVec,
+    read_pos: usize,
+    read_end: usize,
+}
+
+impl ReliableRkyvChannel {
+    /// Create a new `ReliableRkyvChannel` with a maximum message size of `max_message_len`.
+    pub fn new(channel: ReliableChannel, max_message_len: u16) -> Self {
+        ReliableRkyvChannel {
+            channel,
+            max_message_len,
+            write_prefix: [0; 2],
+            write_buffer: AlignedVec::with_capacity(max_message_len as usize),
+            write_pos: 0,
+            write_end: 0,
+            read_prefix: [0; 2],
+            read_buffer: AlignedVec::with_capacity(max_message_len as usize),
+            read_pos: 0,
+            read_end: 0,
+        }
+    }
+
+    /// Archive the given message and write it to the reliable channel.
+    ///
+    /// In order to ensure that messages are sent in a timely manner, `flush` must be called after
+    /// calling this method.  Without calling `flush`, any pending writes will not be sent until the
+    /// next automatic sender task wakeup.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send<T>(&mut self, msg: &T) -> Result<(), Error>
+    where
+        T: for<'a> Serialize<RkyvSerializer<'a>>,
+    {
+        self.finish_write().await?;
+
+        self.write_pos = 0;
+        self.write_end = 0;
+
+        let mut buffer = mem::take(&mut self.write_buffer);
+        buffer.clear();
+        self.write_buffer = rkyv::api::high::to_bytes_in(msg, buffer).map_err(Error::RkyvError)?;
+        if self.write_buffer.len() > self.max_message_len as usize {
+            return Err(Error::TooLarge);
+        }
+        LittleEndian::write_u16(&mut self.write_prefix, self.write_buffer.len() as u16);
+        self.write_end = self.write_buffer.len() + 2;
+        self.finish_write().await?;
+
+        Ok(())
+    }
+
+    /// Ensure that any previously sent messages are sent as soon as possible.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.finish_write().await?;
+        Ok(self.channel.flush().await?)
+    }
+
+    /// Read the next available incoming message and access it as its archived type.
+    ///
+    /// The returned reference borrows the channel's internal buffer and is valid until the next
+    /// call to `recv`.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv<'a, T>(&'a mut self) -> Result<&'a T::Archived, Error>
+    where
+        T: Archive,
+        T::Archived: 'a + for<'v> CheckBytes<RkyvValidator<'v>>,
+    {
+        if self.read_end < 2 {
+            self.read_end = 2;
+        }
+        self.finish_read().await?;
+
+        let message_len = LittleEndian::read_u16(&self.read_prefix);
+        if message_len > self.max_message_len {
+            return Err(Error::PrefixTooLarge);
+        }
+        if self.read_buffer.len() != message_len as usize {
+            self.read_buffer.resize(message_len as usize, 0);
+        }
+        self.read_end = message_len as usize + 2;
+        self.finish_read().await?;
+
+        self.read_pos = 0;
+        self.read_end = 0;
+        rkyv::access::<T::Archived, rancor::Error>(&self.read_buffer).map_err(Error::RkyvError)
+    }
+
+    async fn finish_write(&mut self) -> Result<(), Error> {
+        while self.write_pos < self.write_end {
+            let len = if self.write_pos < 2 {
+                self.channel
+                    .write(&self.write_prefix[self.write_pos..])
+                    .await?
+            } else {
+                self.channel
+                    .write(&self.write_buffer[self.write_pos - 2..self.write_end - 2])
+                    .await?
+            };
+            self.write_pos += len;
+        }
+        Ok(())
+    }
+
+    async fn finish_read(&mut self) -> Result<(), Error> {
+        while self.read_pos < self.read_end {
+            let len = if self.read_pos < 2 {
+                self.channel
+                    .read(&mut self.read_prefix[self.read_pos..])
+                    .await?
+            } else {
+                self.channel
+                    .read(&mut self.read_buffer[self.read_pos - 2..self.read_end - 2])
+                    .await?
+            };
+            self.read_pos += len;
+        }
+        Ok(())
+    }
+}
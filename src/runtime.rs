@@ -1,18 +1,47 @@
-use std::{future::Future, time::Duration};
+#[cfg(any(feature = "async-std", feature = "smol"))]
+mod async_io;
+#[cfg(feature = "async-std")]
+mod async_std;
+mod interval;
+#[cfg(feature = "std")]
+mod join_handle;
+#[cfg(feature = "std")]
+mod simulation;
+#[cfg(feature = "smol")]
+mod smol;
+#[cfg(feature = "tokio")]
+mod tokio;
+#[cfg(all(feature = "wasm-bindgen", target_arch = "wasm32"))]
+mod wasm;
 
-/// Trait for async runtime functionality needed by `turbulence`.
+use core::{future::Future, time::Duration};
+
+#[cfg(any(feature = "async-std", feature = "smol"))]
+pub use self::async_io::AsyncIoSleep;
+#[cfg(feature = "async-std")]
+pub use self::async_std::AsyncStdRuntime;
+pub use self::interval::{cancellable, CancelHandle, Cancellable, Cancelled, Interval};
+#[cfg(feature = "std")]
+pub use self::join_handle::{JoinHandle, TaskFailed};
+#[cfg(feature = "std")]
+pub use self::simulation::{SimulationHandle, SimulationRuntime, SimulationSleep};
+#[cfg(feature = "smol")]
+pub use self::smol::SmolRuntime;
+#[cfg(feature = "tokio")]
+pub use self::tokio::TokioRuntime;
+#[cfg(all(feature = "wasm-bindgen", target_arch = "wasm32"))]
+pub use self::wasm::{WasmRuntime, WasmSleep};
+
+/// Trait for the timing functionality needed by `turbulence`.
 ///
 /// This is designed so that it can be implemented on multiple platforms with multiple runtimes,
 /// including `wasm32-unknown-unknown`, where `std::time::Instant` is unavailable.
-pub trait Runtime: Clone + Send + Sync + Unpin {
-    type Instant: Copy + Send + Sync + Unpin;
-    type Sleep: Future<Output = ()> + Send;
-
-    /// This is similar to the `futures::task::Spawn` trait, but it is generic in the spawned
-    /// future, which is better for backends like tokio.
-    fn spawn<F>(&self, future: F)
-    where
-        F: Future<Output = ()> + Send + 'static;
+///
+/// This is the part of a runtime that is shared between `Runtime` and `LocalRuntime`, channels that
+/// never spawn tasks (like `UnreliableChannel`) only require a `Timer`.
+pub trait Timer: Clone + Unpin {
+    type Instant: Copy + Unpin;
+    type Sleep: Future<Output = ()>;
 
     /// Return the current instant.
     fn now(&self) -> Self::Instant;
@@ -25,19 +54,92 @@ pub trait Runtime: Clone + Send + Sync + Unpin {
 
     /// Create a future which resolves after the given time has passed.
     fn sleep(&self, duration: Duration) -> Self::Sleep;
-}
 
-impl<'a, R: Runtime> Runtime for &'a R {
-    type Instant = R::Instant;
-    type Sleep = R::Sleep;
+    /// Create a stream which yields once every `period`, starting one period from now.
+    ///
+    /// Wrap the returned `Interval` (or any sleep) with `runtime::cancellable` to be able to stop
+    /// it from elsewhere.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the period is zero.
+    fn interval(&self, period: Duration) -> Interval<Self>
+    where
+        Self: Sized,
+    {
+        Interval::new(self.clone(), period)
+    }
+}
 
+/// Trait for async runtime functionality needed by `turbulence`.
+///
+/// Implementations for tokio, async-std and smol are provided behind the `tokio`, `async-std` and
+/// `smol` features respectively, and an implementation for the browser on `wasm32-unknown-unknown`
+/// is provided behind the `wasm-bindgen` feature.
+pub trait Runtime: Timer<Instant: Send + Sync, Sleep: Send> + Send + Sync {
+    /// This is similar to the `futures::task::Spawn` trait, but it is generic in the spawned
+    /// future, which is better for backends like tokio.
     fn spawn<F>(&self, future: F)
     where
-        F: Future<Output = ()> + Send + 'static,
+        F: Future<Output = ()> + Send + 'static;
+
+    /// Spawn a task and return a `JoinHandle` for its output, which reports whether the task
+    /// panicked or was dropped by the runtime.  Dropping the `JoinHandle` cancels the task.
+    #[cfg(feature = "std")]
+    fn spawn_with_handle<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send,
     {
-        (**self).spawn(future);
+        let (task, handle) = join_handle::with_handle(future);
+        self.spawn(task);
+        handle
     }
 
+    /// Run a blocking or CPU-heavy closure (compression, encryption, large serialization) without
+    /// stalling the tasks driving the async reactor.
+    ///
+    /// Runtimes with a dedicated blocking thread pool (tokio, async-std, smol) run the closure
+    /// there.  The default implementation runs it inline as an ordinary spawned task, which is the
+    /// only option on single-threaded platforms and keeps `SimulationRuntime` deterministic.
+    ///
+    /// Dropping the returned `JoinHandle` does not interrupt a closure that has already started.
+    #[cfg(feature = "std")]
+    fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with_handle(async move { f() })
+    }
+}
+
+/// Trait for single-threaded async runtimes which can spawn `!Send` futures.
+///
+/// Types which spawn tasks provide separate `*_local` constructors that only require a
+/// `LocalRuntime`, and these do not require that packet pools, packets or messages be `Send`.
+pub trait LocalRuntime: Timer {
+    /// Like `Runtime::spawn`, but the spawned future need not be `Send`.
+    fn spawn_local<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + 'static;
+
+    /// Like `Runtime::spawn_with_handle`, but the spawned future need not be `Send`.
+    #[cfg(feature = "std")]
+    fn spawn_local_with_handle<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let (task, handle) = join_handle::with_handle(future);
+        self.spawn_local(task);
+        handle
+    }
+}
+
+impl<T: Timer> Timer for &T {
+    type Instant = T::Instant;
+    type Sleep = T::Sleep;
+
     fn now(&self) -> Self::Instant {
         (**self).now()
     }
@@ -54,3 +156,47 @@ impl<'a, R: Runtime> Runtime for &'a R {
         (**self).sleep(duration)
     }
 }
+
+impl<R: Runtime> Runtime for &R {
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        (**self).spawn(future);
+    }
+
+    #[cfg(feature = "std")]
+    fn spawn_with_handle<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send,
+    {
+        (**self).spawn_with_handle(future)
+    }
+
+    #[cfg(feature = "std")]
+    fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        (**self).spawn_blocking(f)
+    }
+}
+
+impl<R: LocalRuntime> LocalRuntime for &R {
+    fn spawn_local<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        (**self).spawn_local(future);
+    }
+
+    #[cfg(feature = "std")]
+    fn spawn_local_with_handle<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        (**self).spawn_local_with_handle(future)
+    }
+}
@@ -0,0 +1,27 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_io::Timer;
+
+/// A `Timer::Sleep` future built on an `async_io::Timer`, which is the timer used by both
+/// async-std and smol.
+#[derive(Debug)]
+pub struct AsyncIoSleep(Timer);
+
+impl AsyncIoSleep {
+    pub(crate) fn new(duration: Duration) -> Self {
+        AsyncIoSleep(Timer::after(duration))
+    }
+}
+
+impl Future for AsyncIoSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx).map(|_| ())
+    }
+}
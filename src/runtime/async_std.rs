@@ -0,0 +1,48 @@
+use std::{future::Future, time::Duration};
+
+use ::async_std::task;
+
+use crate::runtime::{async_io::AsyncIoSleep, JoinHandle, Runtime, Timer};
+
+/// A `Runtime` implementation that spawns tasks onto the global async-std executor.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AsyncStdRuntime;
+
+impl Timer for AsyncStdRuntime {
+    type Instant = std::time::Instant;
+    type Sleep = AsyncIoSleep;
+
+    fn now(&self) -> Self::Instant {
+        std::time::Instant::now()
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        instant.elapsed()
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        later.duration_since(earlier)
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        AsyncIoSleep::new(duration)
+    }
+}
+
+impl Runtime for AsyncStdRuntime {
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // Dropping an async-std `JoinHandle` detaches the task.
+        task::spawn(future);
+    }
+
+    fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with_handle(task::spawn_blocking(f))
+    }
+}
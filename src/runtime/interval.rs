@@ -0,0 +1,171 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{self, AtomicBool},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{stream::FusedStream, task::AtomicWaker, Stream};
+use thiserror::Error;
+
+use crate::runtime::Timer;
+
+/// A `Stream` which yields the current instant once every period, created with `Timer::interval`.
+///
+/// Ticks are scheduled relative to when the interval was created, so they do not drift.  If the
+/// task polling the interval falls behind by more than a period, the missed ticks are skipped
+/// rather than delivered in a burst.
+pub struct Interval<T: Timer> {
+    timer: T,
+    period: Duration,
+    start: T::Instant,
+    next_tick: u128,
+    sleep: Pin<Box<T::Sleep>>,
+}
+
+impl<T: Timer> Interval<T> {
+    pub(crate) fn new(timer: T, period: Duration) -> Interval<T> {
+        assert!(period > Duration::ZERO, "interval period must be non-zero");
+        let start = timer.now();
+        let sleep = Box::pin(timer.sleep(period));
+        Interval {
+            timer,
+            period,
+            start,
+            next_tick: 1,
+            sleep,
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+impl<T: Timer> Stream for Interval<T> {
+    type Item = T::Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            let period = self.period.as_nanos();
+            let elapsed = self.timer.elapsed(self.start).as_nanos();
+            if elapsed >= self.next_tick * period {
+                // Schedule the next tick strictly in the future, skipping any that were missed.
+                self.next_tick = elapsed / period + 1;
+                let until_next = nanos_duration(self.next_tick * period - elapsed);
+                let sleep = self.timer.sleep(until_next);
+                self.sleep.set(sleep);
+                return Poll::Ready(Some(self.timer.now()));
+            } else {
+                // The sleep resolved early, wait for the remainder.
+                let until_next = nanos_duration(self.next_tick * period - elapsed);
+                let sleep = self.timer.sleep(until_next);
+                self.sleep.set(sleep);
+            }
+        }
+    }
+}
+
+impl<T: Timer> FusedStream for Interval<T> {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
+
+fn nanos_duration(nanos: u128) -> Duration {
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+#[derive(Debug, Error)]
+#[error("cancelled")]
+pub struct Cancelled;
+
+/// Wrap a `Future` or `Stream` so that it can be cancelled from elsewhere with the returned
+/// `CancelHandle`.
+///
+/// This is most useful with `Timer::sleep` and `Timer::interval`, so that timers and periodic work
+/// can be torn down promptly by whoever owns the handle.  Cancelling wakes the task polling the
+/// wrapped value.  A cancelled future resolves to `Err(Cancelled)`, and a cancelled stream ends.
+pub fn cancellable<F>(inner: F) -> (Cancellable<F>, CancelHandle) {
+    let state = Arc::new(CancelState {
+        cancelled: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+    (
+        Cancellable {
+            inner: Box::pin(inner),
+            state: Arc::clone(&state),
+        },
+        CancelHandle(state),
+    )
+}
+
+pub struct Cancellable<F> {
+    inner: Pin<Box<F>>,
+    state: Arc<CancelState>,
+}
+
+impl<F> Cancellable<F> {
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(atomic::Ordering::Acquire)
+    }
+
+    fn poll_cancelled(&self, cx: &mut Context) -> bool {
+        if self.is_cancelled() {
+            return true;
+        }
+        self.state.waker.register(cx.waker());
+        self.is_cancelled()
+    }
+}
+
+impl<F: Future> Future for Cancellable<F> {
+    type Output = Result<F::Output, Cancelled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.poll_cancelled(cx) {
+            return Poll::Ready(Err(Cancelled));
+        }
+        self.inner.as_mut().poll(cx).map(Ok)
+    }
+}
+
+impl<F: Stream> Stream for Cancellable<F> {
+    type Item = F::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.poll_cancelled(cx) {
+            return Poll::Ready(None);
+        }
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// Cancels the `Cancellable` it was created with.  Dropping the handle does *not* cancel.
+#[derive(Clone)]
+pub struct CancelHandle(Arc<CancelState>);
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, atomic::Ordering::Release);
+        self.0.waker.wake();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(atomic::Ordering::Acquire)
+    }
+}
+
+struct CancelState {
+    cancelled: AtomicBool,
+    waker: AtomicWaker,
+}
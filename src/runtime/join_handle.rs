@@ -0,0 +1,88 @@
+use std::{
+    any::Any,
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+};
+
+use futures::{
+    channel::oneshot,
+    future::{AbortHandle, Abortable},
+    FutureExt,
+};
+use thiserror::Error;
+
+/// The reason a task spawned with a `JoinHandle` did not produce its output.
+#[derive(Debug, Error)]
+pub enum TaskFailed {
+    /// The task panicked, contains the panic message if it was a string.
+    #[error("task panicked: {0}")]
+    Panicked(String),
+    /// The task was dropped by the runtime before it completed, usually because the runtime has
+    /// shut down.
+    #[error("task was dropped before completing")]
+    Dropped,
+}
+
+impl TaskFailed {
+    /// Create a `TaskFailed::Panicked` from a caught panic payload.
+    pub fn from_panic(panic: Box<dyn Any + Send>) -> TaskFailed {
+        let msg = if let Some(msg) = panic.downcast_ref::<&str>() {
+            (*msg).to_owned()
+        } else if let Some(msg) = panic.downcast_ref::<String>() {
+            msg.clone()
+        } else {
+            "Box<dyn Any>".to_owned()
+        };
+        TaskFailed::Panicked(msg)
+    }
+}
+
+/// A handle to a task spawned with `Runtime::spawn_with_handle` or
+/// `LocalRuntime::spawn_local_with_handle`.
+///
+/// Resolves to the output of the task, or to `TaskFailed` if the task panicked or was dropped
+/// without completing.  Dropping a `JoinHandle` cancels the task.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    receiver: oneshot::Receiver<thread::Result<T>>,
+    abort: AbortHandle,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, TaskFailed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.receiver.poll_unpin(cx).map(|res| match res {
+            Ok(Ok(output)) => Ok(output),
+            Ok(Err(panic)) => Err(TaskFailed::from_panic(panic)),
+            Err(oneshot::Canceled) => Err(TaskFailed::Dropped),
+        })
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
+/// Wrap the given future so that its output (or panic) is delivered to the returned `JoinHandle`.
+/// The wrapped future must then be spawned.
+pub(crate) fn with_handle<F>(future: F) -> (impl Future<Output = ()>, JoinHandle<F::Output>)
+where
+    F: Future,
+{
+    let (sender, receiver) = oneshot::channel();
+    let (abort, registration) = AbortHandle::new_pair();
+    let task = Abortable::new(
+        async move {
+            let _ = sender.send(AssertUnwindSafe(future).catch_unwind().await);
+        },
+        registration,
+    )
+    .map(|_| ());
+    (task, JoinHandle { receiver, abort })
+}
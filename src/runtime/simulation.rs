@@ -0,0 +1,274 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::{
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+    task::{waker, ArcWake},
+};
+
+use crate::runtime::{Runtime, Timer};
+
+/// A single-threaded executor with a virtual clock, for deterministic tests and simulations.
+///
+/// Time never advances on its own, only when `advance_time`, `step` or `run_for` are called, so
+/// protocol timing (resends, RTT estimation, bandwidth limiting) can be exercised exactly and
+/// without actually waiting.  Tasks are spawned through a `SimulationHandle`, which implements
+/// `Runtime`, and are only polled from within the methods of `SimulationRuntime`.
+///
+/// Sleeps always wait for the clock to advance by at least one nanosecond, even for a zero
+/// duration, so that tasks that sleep in a loop cannot starve `run_until_stalled`.
+pub struct SimulationRuntime {
+    tasks: FuturesUnordered<BoxFuture<'static, ()>>,
+    woken: Arc<WakeFlag>,
+    handle: SimulationHandle,
+}
+
+// Set whenever `SimulationRuntime::tasks` is woken, which `FuturesUnordered` also does when it
+// yields with tasks still ready to poll.
+#[derive(Default)]
+struct WakeFlag(AtomicBool);
+
+impl ArcWake for WakeFlag {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A handle to a `SimulationRuntime` which can spawn tasks and read and sleep on the virtual
+/// clock.
+///
+/// Instants are the virtual time elapsed since the `SimulationRuntime` was created.
+#[derive(Clone)]
+pub struct SimulationHandle(Arc<Mutex<State>>);
+
+struct State {
+    time: Duration,
+    // Kept sorted by deadline, timers with equal deadlines are woken in the order they were
+    // registered.  Each timer has a unique id, so that a sleep polled again updates its own entry.
+    timers: Vec<(Duration, u64, Waker)>,
+    next_timer_id: u64,
+    spawned: Vec<BoxFuture<'static, ()>>,
+}
+
+impl SimulationRuntime {
+    pub fn new() -> SimulationRuntime {
+        SimulationRuntime {
+            tasks: FuturesUnordered::new(),
+            woken: Arc::new(WakeFlag::default()),
+            handle: SimulationHandle(Arc::new(Mutex::new(State {
+                time: Duration::ZERO,
+                timers: Vec::new(),
+                next_timer_id: 0,
+                spawned: Vec::new(),
+            }))),
+        }
+    }
+
+    pub fn handle(&self) -> SimulationHandle {
+        self.handle.clone()
+    }
+
+    /// The current virtual time.
+    pub fn now(&self) -> Duration {
+        self.handle.now()
+    }
+
+    /// Poll every task until no task can make progress without the clock advancing.
+    ///
+    /// Returns true if there are no tasks remaining.
+    pub fn run_until_stalled(&mut self) -> bool {
+        let waker = waker(Arc::clone(&self.woken));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            self.tasks
+                .extend(self.handle.0.lock().unwrap().spawned.drain(..));
+
+            self.woken.0.store(false, Ordering::SeqCst);
+            let next = self.tasks.poll_next_unpin(&mut cx);
+
+            if self.handle.0.lock().unwrap().spawned.is_empty() {
+                match next {
+                    // `FuturesUnordered` also returns pending after polling for a while, to yield
+                    // to its caller, but wakes itself first when tasks are still ready.
+                    Poll::Pending if self.woken.0.load(Ordering::SeqCst) => {}
+                    Poll::Pending => return false,
+                    Poll::Ready(None) => return true,
+                    Poll::Ready(Some(())) => {}
+                }
+            }
+        }
+    }
+
+    /// Advance the virtual clock by the given duration, waking any timers that have elapsed.
+    ///
+    /// Does not poll any tasks, call `run_until_stalled` to let woken tasks run.
+    pub fn advance_time(&mut self, duration: Duration) {
+        let mut state = self.handle.0.lock().unwrap();
+        let time = state.time + duration;
+        state.set_time(time);
+    }
+
+    /// The virtual time of the earliest pending timer, if there is one.
+    pub fn next_timer(&self) -> Option<Duration> {
+        self.handle
+            .0
+            .lock()
+            .unwrap()
+            .timers
+            .first()
+            .map(|(t, _, _)| *t)
+    }
+
+    /// Run all tasks until stalled, then jump the clock forward to the next pending timer and run
+    /// again.
+    ///
+    /// Returns false if no timer was pending, in which case every task is either finished or
+    /// waiting on something other than the clock.
+    pub fn step(&mut self) -> bool {
+        self.run_until_stalled();
+        match self.next_timer() {
+            Some(next) => {
+                self.handle.0.lock().unwrap().set_time(next);
+                self.run_until_stalled();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run tasks for the given amount of virtual time, jumping directly between timer deadlines.
+    ///
+    /// When this returns, the clock has advanced by exactly `duration` and all tasks are stalled.
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.now() + duration;
+        loop {
+            self.run_until_stalled();
+            match self.next_timer() {
+                Some(next) if next <= end => self.handle.0.lock().unwrap().set_time(next),
+                _ => break,
+            }
+        }
+        self.handle.0.lock().unwrap().set_time(end);
+        self.run_until_stalled();
+    }
+}
+
+impl Default for SimulationRuntime {
+    fn default() -> Self {
+        SimulationRuntime::new()
+    }
+}
+
+impl State {
+    fn set_time(&mut self, time: Duration) {
+        self.time = self.time.max(time);
+        let arrived = self
+            .timers
+            .iter()
+            .position(|(t, _, _)| *t > self.time)
+            .unwrap_or(self.timers.len());
+        for (_, _, waker) in self.timers.drain(..arrived) {
+            waker.wake();
+        }
+    }
+}
+
+impl Timer for SimulationHandle {
+    type Instant = Duration;
+    type Sleep = SimulationSleep;
+
+    fn now(&self) -> Self::Instant {
+        self.0.lock().unwrap().time
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        self.now() - instant
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        later - earlier
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        let deadline = self.now() + duration.max(Duration::from_nanos(1));
+        SimulationSleep {
+            state: Arc::clone(&self.0),
+            deadline,
+            timer: None,
+        }
+    }
+}
+
+impl Runtime for SimulationHandle {
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.0.lock().unwrap().spawned.push(Box::pin(future));
+    }
+}
+
+/// The `Timer::Sleep` future for `SimulationHandle`.
+pub struct SimulationSleep {
+    state: Arc<Mutex<State>>,
+    deadline: Duration,
+    // The id of this sleep's entry in `State::timers`, once it has registered one.
+    timer: Option<u64>,
+}
+
+impl Future for SimulationSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        let mut state = this.state.lock().unwrap();
+        if state.time >= this.deadline {
+            return Poll::Ready(());
+        }
+
+        let existing = this
+            .timer
+            .and_then(|id| state.timers.iter_mut().find(|(_, i, _)| *i == id));
+        match existing {
+            Some((_, _, waker)) => {
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let id = state.next_timer_id;
+                state.next_timer_id += 1;
+                let i = state
+                    .timers
+                    .partition_point(|(t, _, _)| *t <= this.deadline);
+                state
+                    .timers
+                    .insert(i, (this.deadline, id, cx.waker().clone()));
+                this.timer = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for SimulationSleep {
+    fn drop(&mut self) {
+        // A sleep dropped before its deadline no longer holds the clock back in `step` or
+        // `run_for`.
+        if let Some(id) = self.timer {
+            if let Ok(mut state) = self.state.lock() {
+                state.timers.retain(|(_, i, _)| *i != id);
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::{future::Future, time::Duration};
+
+use crate::runtime::{async_io::AsyncIoSleep, JoinHandle, Runtime, Timer};
+
+/// A `Runtime` implementation that spawns tasks onto the global smol executor.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SmolRuntime;
+
+impl Timer for SmolRuntime {
+    type Instant = std::time::Instant;
+    type Sleep = AsyncIoSleep;
+
+    fn now(&self) -> Self::Instant {
+        std::time::Instant::now()
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        instant.elapsed()
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        later.duration_since(earlier)
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        AsyncIoSleep::new(duration)
+    }
+}
+
+impl Runtime for SmolRuntime {
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        ::smol::spawn(future).detach();
+    }
+
+    fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with_handle(::smol::unblock(f))
+    }
+}
@@ -0,0 +1,90 @@
+use std::{future::Future, panic, time::Duration};
+
+use ::tokio::{
+    runtime::Handle,
+    time::{self, Instant, Sleep},
+};
+
+use crate::runtime::{JoinHandle, Runtime, Timer};
+
+/// A `Runtime` implementation backed by a tokio runtime `Handle`.
+///
+/// Tasks are spawned onto the runtime that the given handle refers to, and timers use
+/// `tokio::time`, so time can be paused and advanced with `tokio::time::pause` in tests.
+#[derive(Debug, Clone)]
+pub struct TokioRuntime(Handle);
+
+impl TokioRuntime {
+    pub fn new(handle: Handle) -> Self {
+        TokioRuntime(handle)
+    }
+
+    /// Create a `TokioRuntime` for the tokio runtime of the current context.
+    ///
+    /// # Panics
+    /// Panics if called outside of the context of a tokio runtime.
+    pub fn current() -> Self {
+        TokioRuntime(Handle::current())
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.0
+    }
+}
+
+impl From<Handle> for TokioRuntime {
+    fn from(handle: Handle) -> Self {
+        TokioRuntime(handle)
+    }
+}
+
+impl Timer for TokioRuntime {
+    type Instant = Instant;
+    type Sleep = Sleep;
+
+    fn now(&self) -> Self::Instant {
+        Instant::now()
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        instant.elapsed()
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        later.duration_since(earlier)
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        // Timers must be created inside the runtime context, and `sleep` may be called from
+        // outside of it (e.g. when constructing channels before the runtime is entered).
+        let _guard = self.0.enter();
+        time::sleep(duration)
+    }
+}
+
+impl Runtime for TokioRuntime {
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.0.spawn(future);
+    }
+
+    fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let blocking = self.0.spawn_blocking(f);
+        self.spawn_with_handle(async move {
+            match blocking.await {
+                Ok(output) => output,
+                // Re-raise the panic so that it is reported as `TaskFailed::Panicked`.
+                Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+                // Blocking tasks are only cancelled on runtime shutdown, which also drops this
+                // task and reports `TaskFailed::Dropped`.
+                Err(_) => futures::future::pending().await,
+            }
+        })
+    }
+}
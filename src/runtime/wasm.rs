@@ -0,0 +1,123 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::task::AtomicWaker;
+use js_sys::Reflect;
+use wasm_bindgen::{closure::Closure, prelude::*, JsCast};
+use web_sys::Performance;
+
+use crate::runtime::{LocalRuntime, Runtime, Timer};
+
+#[wasm_bindgen]
+extern "C" {
+    // Bound to the global `setTimeout` so that this works both in a window and in a worker.
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &JsValue, timeout: i32) -> JsValue;
+}
+
+/// A `Runtime` and `LocalRuntime` implementation for `wasm32-unknown-unknown` in a browser (or web
+/// worker).
+///
+/// Tasks are spawned with `wasm_bindgen_futures::spawn_local`, instants are the millisecond
+/// timestamps returned by `Performance.now()`, and sleeps are implemented with `setTimeout`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WasmRuntime;
+
+impl Timer for WasmRuntime {
+    /// Milliseconds as returned by `Performance.now()`.
+    type Instant = f64;
+    type Sleep = WasmSleep;
+
+    fn now(&self) -> Self::Instant {
+        performance_now()
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        self.duration_between(instant, performance_now())
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        // `Performance.now()` is monotonic, but we never want to panic on a negative duration due to
+        // float weirdness.
+        Duration::from_secs_f64((later - earlier).max(0.) / 1000.)
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        let state = Arc::new(SleepState {
+            waker: AtomicWaker::new(),
+            fired: AtomicBool::new(false),
+        });
+
+        // The closure frees itself once it is called; a dropped `WasmSleep` simply lets its timeout
+        // fire harmlessly rather than calling `clearTimeout`, which would leak the closure.
+        let handler = Closure::once_into_js({
+            let state = Arc::clone(&state);
+            move || {
+                state.fired.store(true, atomic::Ordering::SeqCst);
+                state.waker.wake();
+            }
+        });
+        let millis = duration.as_millis().min(i32::MAX as u128) as i32;
+        set_timeout(&handler, millis);
+
+        WasmSleep(state)
+    }
+}
+
+impl Runtime for WasmRuntime {
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+}
+
+impl LocalRuntime for WasmRuntime {
+    fn spawn_local<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+}
+
+/// The `Timer::Sleep` future for `WasmRuntime`.
+#[derive(Debug)]
+pub struct WasmSleep(Arc<SleepState>);
+
+impl Future for WasmSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        self.0.waker.register(cx.waker());
+        if self.0.fired.load(atomic::Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SleepState {
+    waker: AtomicWaker,
+    fired: AtomicBool,
+}
+
+fn performance_now() -> f64 {
+    // `performance` is available on the global object of both windows and workers, so look it up
+    // there rather than going through `Window`.
+    Reflect::get(&js_sys::global(), &JsValue::from_str("performance"))
+        .expect("no global `performance` object")
+        .unchecked_into::<Performance>()
+        .now()
+}
@@ -0,0 +1,79 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+/// A pool of scratch buffers shared by the channels of a connection, which they borrow for the
+/// temporaries of serialization and compression.
+///
+/// Without an arena, every bincode or compressed channel owns buffers large enough for its
+/// largest message for as long as it lives, which adds up on a server with many connections each
+/// with many rarely used channels.  Channels sharing an arena only hold a buffer while a message
+/// is being sent or received, and give it back once done, so the memory used is bounded by the
+/// number of messages in flight at once rather than by the number of channels.  A channel which
+/// is waiting on `recv` for a message which has not started to arrive holds no buffer.
+///
+/// A `ScratchArena` is a cheap handle, and clones share the same pool.  Buffers keep their
+/// capacity when returned, and at most `max_free` are kept, the rest are freed.
+#[derive(Debug, Clone)]
+pub struct ScratchArena {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_free: usize,
+}
+
+impl ScratchArena {
+    pub fn new(max_free: usize) -> Self {
+        ScratchArena {
+            free: Arc::new(Mutex::new(Vec::new())),
+            max_free,
+        }
+    }
+
+    /// The number of buffers currently in the pool, not borrowed by any channel.
+    pub fn free_buffers(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    /// The total capacity in bytes of the buffers currently in the pool.
+    pub fn free_bytes(&self) -> usize {
+        self.free
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|buffer| buffer.capacity())
+            .sum()
+    }
+
+    /// Take an empty buffer from the pool, or a new one if the pool is empty.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.free.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Return a buffer to the pool, or free it if the pool is full.
+    pub(crate) fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 {
+            return;
+        }
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.max_free {
+            buffer.clear();
+            free.push(buffer);
+        }
+    }
+
+    /// Return the given buffer to the pool if there is one, leaving it empty.
+    pub(crate) fn release(arena: &Option<ScratchArena>, buffer: &mut Vec<u8>) {
+        if let Some(arena) = arena {
+            arena.give(mem::take(buffer));
+        }
+    }
+
+    /// If the given buffer has been released, take a new one from the pool if there is one.
+    pub(crate) fn acquire(arena: &Option<ScratchArena>, buffer: &mut Vec<u8>) {
+        if let Some(arena) = arena {
+            if buffer.capacity() == 0 {
+                *buffer = arena.take();
+            }
+        }
+    }
+}
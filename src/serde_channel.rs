@@ -0,0 +1,383 @@
+//! Channels which send messages serialized with any serde data format, for interoperating with
+//! peers where `bincode` is not practical.
+//!
+//! The channels here mirror the bincode channels, but are generic over a `Format`.  Each data
+//! format feature provides a `Format` along with aliases for the channel types using it.
+
+use alloc::{boxed::Box, vec};
+use core::{fmt, marker::PhantomData};
+
+use futures::{Sink, Stream};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    packet::PacketPool,
+    runtime::Timer,
+    unreliable_channel::{
+        self, DefaultIncoming, DefaultOutgoing, UnreliableChannel, MAX_MESSAGE_LEN,
+    },
+};
+
+#[cfg(feature = "std")]
+pub use self::reliable::{Error, ReliableSerdeChannel, ReliableSerdeTypedChannel};
+
+/// A serde data format that messages are serialized with.
+pub trait Format {
+    type Error: fmt::Debug + fmt::Display;
+
+    /// Serialize a message into the given buffer, returning the number of bytes written.
+    ///
+    /// Must return an error if the message does not fit in the buffer.
+    fn serialize<T: Serialize + ?Sized>(buffer: &mut [u8], msg: &T) -> Result<usize, Self::Error>;
+
+    /// Deserialize a message which takes up the entire buffer.
+    fn deserialize<'a, T: Deserialize<'a>>(buffer: &'a [u8]) -> Result<T, Self::Error>;
+}
+
+#[derive(Debug, Error)]
+pub enum SendError<E> {
+    #[error("unreliable channel error: {0}")]
+    UnreliableChannelError(#[from] unreliable_channel::SendError),
+    /// Non-fatal error, message is unsent.
+    #[error("serialization error: {0}")]
+    SerializationError(E),
+}
+
+#[derive(Debug, Error)]
+pub enum RecvError<E> {
+    #[error("unreliable channel error: {0}")]
+    UnreliableChannelError(#[from] unreliable_channel::RecvError),
+    /// Non-fatal error, message is skipped.
+    #[error("serialization error: {0}")]
+    SerializationError(E),
+}
+
+/// Wraps an `UnreliableChannel` together with an internal buffer to allow easily sending message
+/// types serialized with the data format `F`.
+///
+/// Just like the underlying channel, messages are not guaranteed to arrive, nor are they guaranteed
+/// to arrive in order.
+pub struct UnreliableSerdeChannel<
+    F,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableChannel<R, P, I, O>,
+    buffer: Box<[u8]>,
+    _format: PhantomData<F>,
+}
+
+impl<F, R, P, I, O> UnreliableSerdeChannel<F, R, P, I, O>
+where
+    F: Format,
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    /// Create a new `UnreliableSerdeChannel` with the given max message size.
+    ///
+    /// The maximum message size is always limited by the underlying `UnreliableChannel` maximum
+    /// message size regardless of the `max_message_len` setting, but this can be used to restrict
+    /// the intermediate buffer used to serialize messages.
+    pub fn new(channel: UnreliableChannel<R, P, I, O>, max_message_len: u16) -> Self {
+        UnreliableSerdeChannel {
+            channel,
+            buffer: vec![0; max_message_len.min(MAX_MESSAGE_LEN) as usize].into_boxed_slice(),
+            _format: PhantomData,
+        }
+    }
+
+    /// Write the given serializable message type to the channel.
+    ///
+    /// Messages are coalesced into larger packets before being sent, so in order to guarantee that
+    /// the message is actually sent, you must call `flush`.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send<T: Serialize + ?Sized>(
+        &mut self,
+        msg: &T,
+    ) -> Result<(), SendError<F::Error>> {
+        let written = F::serialize(&mut self.buffer, msg).map_err(SendError::SerializationError)?;
+        Ok(self.channel.send(&self.buffer[0..written]).await?)
+    }
+
+    /// Finish sending any unsent coalesced packets.
+    ///
+    /// This *must* be called to guarantee that any sent messages are actually sent to the outgoing
+    /// packet stream.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError<F::Error>> {
+        Ok(self.channel.flush().await?)
+    }
+
+    /// Receive a deserializable message type as soon as the next message is available.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T, RecvError<F::Error>> {
+        Ok(self.recv_timed().await?.0)
+    }
+
+    /// Like `UnreliableSerdeChannel::recv`, but also returns the time at which the packet
+    /// containing the message was received, as described in `UnreliableChannel::recv_timed`.
+    pub async fn recv_timed<'a, T: Deserialize<'a>>(
+        &'a mut self,
+    ) -> Result<(T, R::Instant), RecvError<F::Error>> {
+        let (msg, received) = self.channel.recv_timed().await?;
+        let msg = F::deserialize(msg).map_err(RecvError::SerializationError)?;
+        Ok((msg, received))
+    }
+}
+
+/// Wrapper over an `UnreliableSerdeChannel` that only allows a single message type.
+pub struct UnreliableSerdeTypedChannel<
+    T,
+    F,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
+    P: PacketPool,
+{
+    channel: UnreliableSerdeChannel<F, R, P, I, O>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T, F, R, P, I, O> UnreliableSerdeTypedChannel<T, F, R, P, I, O>
+where
+    F: Format,
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(channel: UnreliableSerdeChannel<F, R, P, I, O>) -> Self {
+        UnreliableSerdeTypedChannel {
+            channel,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub async fn flush(&mut self) -> Result<(), SendError<F::Error>> {
+        self.channel.flush().await
+    }
+}
+
+impl<T, F, R, P, I, O> UnreliableSerdeTypedChannel<T, F, R, P, I, O>
+where
+    T: Serialize,
+    F: Format,
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub async fn send(&mut self, msg: &T) -> Result<(), SendError<F::Error>> {
+        self.channel.send(msg).await
+    }
+}
+
+impl<'a, T, F, R, P, I, O> UnreliableSerdeTypedChannel<T, F, R, P, I, O>
+where
+    T: Deserialize<'a>,
+    F: Format,
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub async fn recv(&'a mut self) -> Result<T, RecvError<F::Error>> {
+        self.channel.recv().await
+    }
+
+    pub async fn recv_timed(&'a mut self) -> Result<(T, R::Instant), RecvError<F::Error>> {
+        self.channel.recv_timed().await
+    }
+}
+
+#[cfg(feature = "std")]
+mod reliable {
+    use std::marker::PhantomData;
+
+    use byteorder::{ByteOrder, LittleEndian};
+    use serde::{Deserialize, Serialize};
+    use thiserror::Error;
+
+    use crate::reliable_channel::{self, ReliableChannel};
+
+    use super::Format;
+
+    #[derive(Debug, Error)]
+    pub enum Error<E> {
+        /// Fatal internal channel error.
+        #[error("reliable channel error: {0}")]
+        ReliableChannelError(#[from] reliable_channel::Error),
+        /// Fatal, reading the next message would exceed the maximum buffer length, no progress can
+        /// be made.
+        #[error("received message exceeds the configured max message length")]
+        PrefixTooLarge,
+        /// Non-fatal, on send, no message is sent, on receive the message is *skipped*.
+        #[error("serialization error: {0}")]
+        SerializationError(E),
+    }
+
+    /// Wraps a `ReliableChannel` together with an internal buffer to allow easily sending message
+    /// types serialized with the data format `F`.
+    ///
+    /// Messages are guaranteed to arrive, and are guaranteed to be in order.  Messages have a
+    /// maximum length, but this maximum size can be larger than the size of an individual packet.
+    /// Just like `ReliableBincodeChannel`, every message is prefixed with its length as a 2 byte
+    /// little endian integer.
+    pub struct ReliableSerdeChannel<F> {
+        channel: ReliableChannel,
+        max_message_len: u16,
+
+        write_buffer: Box<[u8]>,
+        write_pos: usize,
+        write_end: usize,
+
+        read_buffer: Box<[u8]>,
+        read_pos: usize,
+        read_end: usize,
+
+        _format: PhantomData<F>,
+    }
+
+    impl<F: Format> ReliableSerdeChannel<F> {
+        /// Create a new `ReliableSerdeChannel` with a maximum message size of `max_message_len`.
+        pub fn new(channel: ReliableChannel, max_message_len: u16) -> Self {
+            ReliableSerdeChannel {
+                channel,
+                max_message_len,
+                write_buffer: vec![0; 2 + max_message_len as usize].into_boxed_slice(),
+                write_pos: 0,
+                write_end: 0,
+                read_buffer: vec![0; 2 + max_message_len as usize].into_boxed_slice(),
+                read_pos: 0,
+                read_end: 0,
+                _format: PhantomData,
+            }
+        }
+
+        /// Write the given message to the reliable channel.
+        ///
+        /// In order to ensure that messages are sent in a timely manner, `flush` must be called
+        /// after calling this method.  Without calling `flush`, any pending writes will not be sent
+        /// until the next automatic sender task wakeup.
+        ///
+        /// This method is cancel safe, it will never partially send a message, though canceling it
+        /// may or may not buffer a message to be sent.
+        pub async fn send<T: Serialize + ?Sized>(
+            &mut self,
+            msg: &T,
+        ) -> Result<(), Error<F::Error>> {
+            self.finish_write().await?;
+
+            self.write_pos = 0;
+            self.write_end = 0;
+
+            let written = F::serialize(&mut self.write_buffer[2..], msg)
+                .map_err(Error::SerializationError)?;
+            self.write_end = written + 2;
+            LittleEndian::write_u16(&mut self.write_buffer[0..2], written as u16);
+            self.finish_write().await?;
+
+            Ok(())
+        }
+
+        /// Ensure that any previously sent messages are sent as soon as possible.
+        ///
+        /// This method is cancel safe.
+        pub async fn flush(&mut self) -> Result<(), Error<F::Error>> {
+            self.finish_write().await?;
+            Ok(self.channel.flush().await?)
+        }
+
+        /// Read the next available incoming message.
+        ///
+        /// This method is cancel safe, it will never partially read a message or drop received
+        /// messages.
+        pub async fn recv<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T, Error<F::Error>> {
+            if self.read_end < 2 {
+                self.read_end = 2;
+            }
+            self.finish_read().await?;
+
+            let message_len = LittleEndian::read_u16(&self.read_buffer[0..2]);
+            if message_len > self.max_message_len {
+                return Err(Error::PrefixTooLarge);
+            }
+            self.read_end = message_len as usize + 2;
+            self.finish_read().await?;
+
+            let res = F::deserialize(&self.read_buffer[2..self.read_end]);
+            self.read_pos = 0;
+            self.read_end = 0;
+            res.map_err(Error::SerializationError)
+        }
+
+        async fn finish_write(&mut self) -> Result<(), Error<F::Error>> {
+            while self.write_pos < self.write_end {
+                let len = self
+                    .channel
+                    .write(&self.write_buffer[self.write_pos..self.write_end])
+                    .await?;
+                self.write_pos += len;
+            }
+            Ok(())
+        }
+
+        async fn finish_read(&mut self) -> Result<(), Error<F::Error>> {
+            while self.read_pos < self.read_end {
+                let len = self
+                    .channel
+                    .read(&mut self.read_buffer[self.read_pos..self.read_end])
+                    .await?;
+                self.read_pos += len;
+            }
+            Ok(())
+        }
+    }
+
+    /// Wrapper over a `ReliableSerdeChannel` that only allows a single message type.
+    pub struct ReliableSerdeTypedChannel<T, F> {
+        channel: ReliableSerdeChannel<F>,
+        _phantom: PhantomData<T>,
+    }
+
+    impl<T, F: Format> ReliableSerdeTypedChannel<T, F> {
+        pub fn new(channel: ReliableSerdeChannel<F>) -> Self {
+            ReliableSerdeTypedChannel {
+                channel,
+                _phantom: PhantomData,
+            }
+        }
+
+        pub async fn flush(&mut self) -> Result<(), Error<F::Error>> {
+            self.channel.flush().await
+        }
+    }
+
+    impl<T: Serialize, F: Format> ReliableSerdeTypedChannel<T, F> {
+        pub async fn send(&mut self, msg: &T) -> Result<(), Error<F::Error>> {
+            self.channel.send(msg).await
+        }
+    }
+
+    impl<'a, T: Deserialize<'a>, F: Format> ReliableSerdeTypedChannel<T, F> {
+        pub async fn recv(&'a mut self) -> Result<T, Error<F::Error>> {
+            self.channel.recv().await
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{hash_map::RandomState, BinaryHeap},
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use futures::{
+    future::{self, Either},
+    pin_mut, Sink, SinkExt, Stream, StreamExt,
+};
+
+use crate::{
+    packet::{Packet, PacketPool},
+    runtime::Timer,
+};
+
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The base one-way delay applied to every packet.
+    pub latency: Duration,
+    /// Each packet is delayed by an additional random duration in `[0, jitter)`.  Jitter larger
+    /// than the time between two packets may reorder them.
+    pub jitter: Duration,
+    /// The probability, from 0 to 1, that a packet is dropped.
+    pub loss: f64,
+    /// The probability, from 0 to 1, that a packet which is not dropped is delivered twice.
+    pub duplicate: f64,
+    /// The probability, from 0 to 1, that a packet skips the latency and jitter, arriving ahead of
+    /// packets sent before it.
+    pub reorder: f64,
+    /// If set, the maximum number of bytes per second the link can carry.  Packets queue behind
+    /// each other when this is exceeded, which adds delay.
+    pub bandwidth: Option<u32>,
+}
+
+impl Settings {
+    /// A link that delivers every packet immediately, in order.
+    pub const PERFECT: Settings = Settings {
+        latency: Duration::ZERO,
+        jitter: Duration::ZERO,
+        loss: 0.0,
+        duplicate: 0.0,
+        reorder: 0.0,
+        bandwidth: None,
+    };
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings::PERFECT
+    }
+}
+
+/// Applies latency, jitter, loss, duplication, reordering and a bandwidth cap to packets
+/// travelling in one direction, use two for a bidirectional link.
+///
+/// A `LinkSimulator` is placed between two multiplexers (or any packet stream and sink).  Combined
+/// with `SimulationRuntime`, this makes it possible to test how a game behaves on a bad network
+/// without any external tooling.
+pub struct LinkSimulator<T, P> {
+    timer: T,
+    pool: P,
+    settings: Settings,
+    seed: u64,
+    rng: SimRng,
+}
+
+impl<T, P> LinkSimulator<T, P>
+where
+    T: Timer,
+    P: PacketPool,
+{
+    /// Create a `LinkSimulator` with a random seed.
+    ///
+    /// The pool is used to copy packets which are duplicated.
+    pub fn new(timer: T, pool: P, settings: Settings) -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Self::with_seed(timer, pool, settings, seed)
+    }
+
+    /// Create a `LinkSimulator` whose loss, duplication, reordering and jitter are decided by an
+    /// RNG with the given seed.
+    ///
+    /// With a deterministic runtime like `SimulationRuntime`, the same seed and the same packets
+    /// produce exactly the same packet schedule, so a pathological schedule found in a test or bug
+    /// report can be replayed exactly.
+    pub fn with_seed(timer: T, pool: P, settings: Settings, seed: u64) -> Self {
+        LinkSimulator {
+            timer,
+            pool,
+            settings,
+            seed,
+            rng: SimRng::new(seed),
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// The seed this simulator was created with, log this to be able to replay a run created with
+    /// `LinkSimulator::new`.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Forward packets from `incoming` to `outgoing` under the simulated conditions.
+    ///
+    /// Resolves once `incoming` has ended and every packet still in flight has been delivered, or
+    /// once `outgoing` errors.
+    pub async fn run<I, O>(mut self, mut incoming: I, mut outgoing: O)
+    where
+        I: Stream<Item = P::Packet> + Unpin,
+        O: Sink<P::Packet> + Unpin,
+    {
+        let start = self.timer.now();
+        let mut in_flight = BinaryHeap::new();
+        let mut next_seq = 0u64;
+        let mut link_free_at = Duration::ZERO;
+        let mut incoming_done = false;
+
+        loop {
+            let now = self.timer.elapsed(start);
+            while in_flight
+                .peek()
+                .is_some_and(|Reverse(p): &Reverse<InFlight<_>>| p.arrival <= now)
+            {
+                let Reverse(p) = in_flight.pop().unwrap();
+                if outgoing.feed(p.packet).await.is_err() {
+                    return;
+                }
+            }
+            if outgoing.flush().await.is_err() {
+                return;
+            }
+
+            let sleep = match in_flight.peek() {
+                Some(Reverse(p)) => Either::Left(self.timer.sleep(p.arrival - now)),
+                None if incoming_done => return,
+                None => Either::Right(future::pending()),
+            };
+            pin_mut!(sleep);
+
+            if incoming_done {
+                sleep.await;
+                continue;
+            }
+
+            let packet = match future::select(incoming.next(), sleep).await {
+                Either::Left((Some(packet), _)) => packet,
+                Either::Left((None, _)) => {
+                    incoming_done = true;
+                    continue;
+                }
+                Either::Right(((), _)) => continue,
+            };
+
+            if self.rng.chance(self.settings.loss) {
+                continue;
+            }
+
+            let now = self.timer.elapsed(start);
+            let departure = match self.settings.bandwidth {
+                Some(bandwidth) => {
+                    link_free_at = link_free_at.max(now)
+                        + Duration::from_secs_f64(packet.len() as f64 / bandwidth as f64);
+                    link_free_at
+                }
+                None => now,
+            };
+
+            let duplicate = if self.rng.chance(self.settings.duplicate) {
+                let mut dup = self.pool.acquire();
+                dup.extend(&packet);
+                Some(dup)
+            } else {
+                None
+            };
+
+            for packet in Some(packet).into_iter().chain(duplicate) {
+                let arrival = if self.rng.chance(self.settings.reorder) {
+                    departure
+                } else {
+                    departure + self.settings.latency + self.settings.jitter.mul_f64(self.rng.f64())
+                };
+                in_flight.push(Reverse(InFlight {
+                    arrival,
+                    seq: next_seq,
+                    packet,
+                }));
+                next_seq += 1;
+            }
+        }
+    }
+}
+
+struct InFlight<P> {
+    arrival: Duration,
+    // Breaks ties between packets arriving at the same time, so they are delivered in the order
+    // they were sent.
+    seq: u64,
+    packet: P,
+}
+
+impl<P> PartialEq for InFlight<P> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.arrival, self.seq) == (other.arrival, other.seq)
+    }
+}
+
+impl<P> Eq for InFlight<P> {}
+
+impl<P> PartialOrd for InFlight<P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P> Ord for InFlight<P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.arrival, self.seq).cmp(&(other.arrival, other.seq))
+    }
+}
+
+// A small splitmix64 generator, so that simulation does not require a dependency on `rand`.
+struct SimRng(u64);
+
+impl SimRng {
+    fn new(seed: u64) -> SimRng {
+        SimRng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // A uniformly distributed value in `[0, 1)`.
+    fn f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.f64() < probability
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    liveness::Staleness,
+    packet_multiplexer::{DropReason, PacketChannel, PacketObserver},
+    runtime::Runtime,
+};
+
+/// A notable network event recorded in an `EventLog`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EventKind {
+    /// A `ReliableChannel` resent `len` bytes of data which went unacknowledged for longer than
+    /// its current resend timeout.
+    Retransmit { len: u32, timeout: Duration },
+    /// A `ReliableChannel` resent an unacknowledged urgent message.
+    UrgentRetransmit { seq: u32 },
+    /// A `PacketMultiplexer` dropped an incoming packet of `len` bytes.
+    Dropped { len: usize, reason: DropReason },
+    /// A received message was dropped because the buffer it was to be received into was full.
+    Overflow,
+    /// A packet or message from the remote could not be decoded.
+    DecodeError,
+    /// A `Liveness` detected that the connection has gone without traffic in one or both
+    /// directions.
+    Stale(Staleness),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Event {
+    /// The time of the event, relative to when the log was created.
+    pub time: Duration,
+    /// The multiplexer channel the event happened on, if known.
+    pub channel: Option<PacketChannel>,
+    pub kind: EventKind,
+}
+
+/// A fixed size ring buffer of recent notable events on a connection, such as retransmits and
+/// dropped packets, kept so that the recent history of a connection can be dumped on demand, for
+/// example when a player reports a hiccup.
+///
+/// An `EventLog` is a cheap handle, and clones record into the same buffer.  Install it on a
+/// `PacketMultiplexer` with `PacketMultiplexer::set_observer` to record dropped packets, and on
+/// every `ReliableChannel` of the connection with `ReliableChannel::set_event_log`, each with
+/// `EventLog::channel` so that its events are tagged with their channel.  Events which the
+/// crate does not detect itself, such as failures to deserialize messages, can be recorded with
+/// `EventLog::record`.
+#[derive(Clone)]
+pub struct EventLog {
+    state: Arc<Mutex<State>>,
+    channel: Option<PacketChannel>,
+}
+
+struct State {
+    now: Box<dyn Fn() -> Duration + Send + Sync>,
+    capacity: usize,
+    events: VecDeque<Event>,
+}
+
+impl EventLog {
+    /// Create a log which keeps the most recent `capacity` events, timestamped with the given
+    /// runtime.
+    pub fn new<R: Runtime + 'static>(runtime: R, capacity: usize) -> Self {
+        let start = runtime.now();
+        EventLog {
+            state: Arc::new(Mutex::new(State {
+                now: Box::new(move || runtime.elapsed(start)),
+                capacity,
+                events: VecDeque::with_capacity(capacity),
+            })),
+            channel: None,
+        }
+    }
+
+    /// A handle to the same log, which tags the events recorded through it with the given
+    /// channel.
+    pub fn channel(&self, channel: PacketChannel) -> Self {
+        EventLog {
+            state: Arc::clone(&self.state),
+            channel: Some(channel),
+        }
+    }
+
+    /// Record an event at the current time, evicting the oldest event if the log is full.
+    pub fn record(&self, kind: EventKind) {
+        self.record_on(self.channel, kind);
+    }
+
+    /// The recorded events, oldest first.
+    pub fn events(&self) -> Vec<Event> {
+        self.state.lock().unwrap().events.iter().copied().collect()
+    }
+
+    /// The events recorded within the given time of now, oldest first.
+    pub fn recent(&self, within: Duration) -> Vec<Event> {
+        let state = self.state.lock().unwrap();
+        let since = (state.now)().saturating_sub(within);
+        state
+            .events
+            .iter()
+            .filter(|event| event.time >= since)
+            .copied()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().events.clear();
+    }
+
+    fn record_on(&self, channel: Option<PacketChannel>, kind: EventKind) {
+        let mut state = self.state.lock().unwrap();
+        if state.capacity == 0 {
+            return;
+        }
+        if state.events.len() == state.capacity {
+            state.events.pop_front();
+        }
+        let time = (state.now)();
+        state.events.push_back(Event {
+            time,
+            channel,
+            kind,
+        });
+    }
+}
+
+impl PacketObserver for EventLog {
+    fn on_packet_dropped(&self, channel: PacketChannel, len: usize, reason: DropReason) {
+        self.record_on(Some(channel), EventKind::Dropped { len, reason });
+    }
+}
@@ -0,0 +1,106 @@
+use core::time::Duration;
+
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+use crate::runtime::Timer;
+
+/// A fixed number of bytes a channel may send per flush interval, or tick, for deterministic
+/// bandwidth use from one tick to the next.
+///
+/// Ticks are consecutive windows of `tick` starting from when the channel was created, and the
+/// bytes used reset at the start of every window rather than accumulating like bandwidth credit.
+/// A bandwidth limit lets a channel which has been idle burst, and smooths its sends out over
+/// time, while a tick budget never lets a channel send more than `bytes_per_tick` in any one
+/// window, which is easier to reason about alongside a fixed simulation tick.
+///
+/// `UnreliableChannel` counts each message and its two byte length prefix against the budget,
+/// and messages over the budget are queued or shed by its `ShedPolicy`.  A single message larger
+/// than the whole budget is still sent, alone, in a window of its own.  `ReliableChannel` counts
+/// newly sent data, data written over the budget stays in the send window until a later tick.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TickBudget {
+    /// The maximum bytes sent per tick.
+    pub bytes_per_tick: u32,
+    /// The length of a tick, which should be the interval at which the channel is flushed.
+    pub tick: Duration,
+}
+
+// The bytes used so far in the current window of a `TickBudget`.
+pub(crate) struct TickWindow<T: Timer> {
+    timer: T,
+    start: T::Instant,
+    window: u64,
+    used: u32,
+}
+
+impl<T: Timer> TickWindow<T> {
+    pub(crate) fn new(timer: T) -> Self {
+        let start = timer.now();
+        TickWindow {
+            timer,
+            start,
+            window: 0,
+            used: 0,
+        }
+    }
+
+    pub(crate) fn timer(&self) -> &T {
+        &self.timer
+    }
+
+    /// Move on to the current window, if a new one has started since the last update.
+    pub(crate) fn update(&mut self, budget: &TickBudget) {
+        let window = (self.timer.elapsed(self.start).as_nanos() / tick_nanos(budget)) as u64;
+        if window != self.window {
+            self.window = window;
+            self.used = 0;
+        }
+    }
+
+    /// The bytes left in the current window.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn remaining(&self, budget: &TickBudget) -> u32 {
+        budget.bytes_per_tick.saturating_sub(self.used)
+    }
+
+    /// Returns true if `bytes` can be sent in the current window, which is always true for the
+    /// first send of a window so that sends larger than the budget are not stuck forever.
+    pub(crate) fn fits(&self, budget: &TickBudget, bytes: u32) -> bool {
+        self.used == 0 || self.used.saturating_add(bytes) <= budget.bytes_per_tick
+    }
+
+    /// Record that bytes were sent in the current window.
+    pub(crate) fn take(&mut self, bytes: u32) {
+        self.used = self.used.saturating_add(bytes);
+    }
+
+    /// How long until the next window starts.
+    pub(crate) fn delay(&self, budget: &TickBudget) -> Duration {
+        let tick = tick_nanos(budget);
+        let elapsed = self.timer.elapsed(self.start).as_nanos();
+        let next = (elapsed / tick + 1) * tick;
+        Duration::from_nanos((next - elapsed) as u64)
+    }
+}
+
+fn tick_nanos(budget: &TickBudget) -> u128 {
+    budget.tick.as_nanos().max(1)
+}
+
+// The budget shared between a `ReliableChannel` and its task, similar to `WorkBudgetSlot`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TickBudgetSlot(Arc<Mutex<Option<TickBudget>>>);
+
+#[cfg(feature = "std")]
+impl TickBudgetSlot {
+    pub(crate) fn set(&self, budget: Option<TickBudget>) {
+        *self.0.lock().unwrap() = budget;
+    }
+
+    pub(crate) fn get(&self) -> Option<TickBudget> {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,242 @@
+use std::{convert::TryInto, mem, pin::Pin, time::Duration};
+
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{future::poll_fn, Sink, SinkExt, Stream, StreamExt};
+
+use crate::{
+    bandwidth_limiter::BandwidthLimiter,
+    clock_sync::RemoteClock,
+    packet::{Packet, PacketPool, MAX_PACKET_LEN},
+    runtime::Timer,
+    unreliable_channel::{DefaultIncoming, DefaultOutgoing, RecvError, SendError, Settings},
+};
+
+/// The length of the base timestamp at the start of every packet.
+pub const HEADER_LEN: usize = 4;
+
+/// The maximum possible message length of a `TimestampChannel` message for the largest possible
+/// packet, based on the `MAX_PACKET_LEN`.
+pub const MAX_MESSAGE_LEN: u16 = MAX_PACKET_LEN - HEADER_LEN as u16 - 4;
+
+/// When a message received on a `TimestampChannel` was sent.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Timestamp {
+    /// The time the message was sent on the sender's timeline, to the millisecond.  Only the low 32
+    /// bits of milliseconds are sent, so this wraps around after about 49 days.
+    pub remote: Duration,
+    /// The same time on the local timeline, if the clock offset is known yet.
+    ///
+    /// This is the time to rewind to when checking what the sender saw as it sent the message,
+    /// for example for lag compensated hit detection.
+    pub local: Option<Duration>,
+}
+
+/// Turns a stream of unreliable, unordered packets into a stream of unreliable, unordered messages,
+/// each of which carries the time it was sent.
+///
+/// Times are on the timelines of a pair of `ClockSync`s: the sender stamps each message with the
+/// local time of its `RemoteClock`, and the receiver converts the stamp to its own timeline with
+/// the clock offset estimated by its `RemoteClock`.  Each packet carries the time of its first
+/// message as a u32 of milliseconds, and each message only the two byte difference from that.
+///
+/// Otherwise this behaves exactly like `UnreliableChannel`.
+pub struct TimestampChannel<
+    T,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    T: Timer,
+    R: Timer,
+    P: PacketPool,
+{
+    clock: RemoteClock<T>,
+    packet_pool: P,
+    bandwidth_limiter: BandwidthLimiter<R>,
+    incoming_packets: I,
+    outgoing_packets: O,
+    // The packet being coalesced, which starts with room for the header, and its base time once it
+    // has a message.
+    out_packet: P::Packet,
+    out_base: Option<u32>,
+    in_packet: Option<(P::Packet, usize)>,
+}
+
+impl<T, R, P, I, O> TimestampChannel<T, R, P, I, O>
+where
+    T: Timer,
+    R: Timer,
+    P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
+{
+    pub fn new(
+        clock: RemoteClock<T>,
+        runtime: R,
+        packet_pool: P,
+        settings: Settings,
+        incoming: I,
+        outgoing: O,
+    ) -> Self {
+        let out_packet = new_packet(&packet_pool);
+        TimestampChannel {
+            clock,
+            packet_pool,
+            bandwidth_limiter: BandwidthLimiter::new(
+                runtime,
+                settings.bandwidth,
+                settings.burst_bandwidth,
+            ),
+            incoming_packets: incoming,
+            outgoing_packets: outgoing,
+            out_packet,
+            out_base: None,
+            in_packet: None,
+        }
+    }
+
+    /// Write the given message to the channel, stamped with the current local time.
+    ///
+    /// Messages are coalesced into larger packets before being sent, so in order to guarantee that
+    /// the message is actually sent, you must call `flush`.  The maximum message length is
+    /// `HEADER_LEN + 4` less than the size of the packets returned by the pool.
+    ///
+    /// This method is cancel safe, it will never partially send a message, though canceling it may
+    /// or may not buffer a message to be sent.
+    pub async fn send(&mut self, msg: &[u8]) -> Result<(), SendError> {
+        let msg_len: u16 = msg.len().try_into().map_err(|_| SendError::TooBig)?;
+
+        // A message sent too long after the first message in the packet starts a new packet.
+        let mut now = self.now();
+        let in_range = self
+            .out_base
+            .is_none_or(|base| now.wrapping_sub(base) <= u16::MAX as u32);
+        // The capacity may have shrunk below the length with a lower `DatagramLimit`.
+        let available = self
+            .out_packet
+            .capacity()
+            .saturating_sub(self.out_packet.len());
+        if available < msg_len as usize + 4 || !in_range {
+            self.flush().await?;
+            let max_len = self.out_packet.capacity().checked_sub(HEADER_LEN + 4);
+            if max_len.is_none_or(|max_len| max_len < msg_len as usize) {
+                debug_event!(len = msg_len, "timestamped message too big");
+                return Err(SendError::TooBig);
+            }
+            now = self.now();
+        }
+        let base = *self.out_base.get_or_insert(now);
+        let delta = now.wrapping_sub(base) as u16;
+
+        let mut header = [0; 4];
+        LittleEndian::write_u16(&mut header[0..2], msg_len);
+        LittleEndian::write_u16(&mut header[2..4], delta);
+        self.out_packet.extend(&header);
+        self.out_packet.extend(msg);
+
+        Ok(())
+    }
+
+    /// Finish sending any unsent coalesced packets.
+    ///
+    /// This *must* be called to guarantee that any sent messages are actually sent to the outgoing
+    /// packet stream.
+    ///
+    /// This method is cancel safe.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        if let Some(base) = self.out_base {
+            self.bandwidth_limiter.update_available();
+            self.bandwidth_limiter.delay_until_available().await;
+
+            poll_fn(|cx| Pin::new(&mut self.outgoing_packets).poll_ready(cx))
+                .await
+                .map_err(|_| SendError::Disconnected)?;
+
+            LittleEndian::write_u32(&mut self.out_packet[0..HEADER_LEN], base);
+            self.out_base = None;
+            let out_packet = mem::replace(&mut self.out_packet, new_packet(&self.packet_pool));
+            self.bandwidth_limiter.take_bytes(out_packet.len() as u32);
+            Pin::new(&mut self.outgoing_packets)
+                .start_send(out_packet)
+                .map_err(|_| SendError::Disconnected)?;
+        }
+
+        self.outgoing_packets
+            .flush()
+            .await
+            .map_err(|_| SendError::Disconnected)?;
+
+        Ok(())
+    }
+
+    /// Receive a message, along with the time it was sent.
+    ///
+    /// This method is cancel safe, it will never partially read a message or drop received
+    /// messages.
+    pub async fn recv(&mut self) -> Result<(&[u8], Timestamp), RecvError> {
+        loop {
+            if let Some((packet, in_pos)) = &self.in_packet {
+                if *in_pos < packet.len() {
+                    break;
+                }
+            }
+            self.in_packet = None;
+
+            let packet = self
+                .incoming_packets
+                .next()
+                .await
+                .ok_or(RecvError::Disconnected)?;
+            if packet.len() < HEADER_LEN {
+                debug_event!(len = packet.len(), "dropping malformed timestamped packet");
+                return Err(RecvError::BadFormat);
+            }
+            self.in_packet = Some((packet, HEADER_LEN));
+        }
+        let (packet, in_pos) = self.in_packet.as_mut().unwrap();
+
+        if *in_pos + 4 > packet.len() {
+            debug_event!(
+                dropped = packet.len() - *in_pos,
+                "dropping malformed timestamped packet"
+            );
+            *in_pos = packet.len();
+            return Err(RecvError::BadFormat);
+        }
+        let length = LittleEndian::read_u16(&packet[*in_pos..*in_pos + 2]) as usize;
+        let delta = LittleEndian::read_u16(&packet[*in_pos + 2..*in_pos + 4]);
+        *in_pos += 4;
+
+        if *in_pos + length > packet.len() {
+            debug_event!(
+                dropped = packet.len() - *in_pos,
+                "dropping malformed timestamped packet"
+            );
+            *in_pos = packet.len();
+            return Err(RecvError::BadFormat);
+        }
+
+        let base = LittleEndian::read_u32(&packet[0..HEADER_LEN]);
+        let remote = Duration::from_millis(base.wrapping_add(delta as u32) as u64);
+        let timestamp = Timestamp {
+            remote,
+            local: self.clock.to_local(remote),
+        };
+        let msg = &packet[*in_pos..*in_pos + length];
+        *in_pos += length;
+
+        Ok((msg, timestamp))
+    }
+
+    // The current local time, as the low 32 bits of milliseconds.
+    fn now(&self) -> u32 {
+        self.clock.local_time().as_millis() as u32
+    }
+}
+
+fn new_packet<P: PacketPool>(pool: &P) -> P::Packet {
+    let mut packet = pool.acquire();
+    packet.resize(HEADER_LEN, 0);
+    packet
+}
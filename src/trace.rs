@@ -0,0 +1,33 @@
+// Internal logging macros, which forward to the `tracing` macros of the same level when the
+// `tracing` feature is enabled and otherwise expand to nothing.
+//
+// Arguments are not evaluated at all with the feature disabled, so they should not have side
+// effects.
+
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::trace!($($arg)*);
+        }
+    };
+}
+
+macro_rules! debug_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::debug!($($arg)*);
+        }
+    };
+}
+
+#[cfg_attr(not(feature = "std"), allow(unused_macros))]
+macro_rules! warn_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::warn!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,655 @@
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_io::Async;
+use futures::{
+    channel::mpsc,
+    future::{self, Fuse, FusedFuture},
+    pin_mut, select,
+    stream::SelectAll,
+    FutureExt, Stream, StreamExt,
+};
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+
+use crate::{
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{IncomingMultiplexedPackets, OutgoingMultiplexedPackets},
+    runtime::{JoinHandle, Runtime},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// The buffer size of the mpsc channels for each peer's incoming and outgoing packets.
+    ///
+    /// Incoming packets for a peer whose incoming buffer is full are dropped.
+    pub peer_buffer_size: usize,
+    /// The number of newly accepted peers that may be waiting to be received from the
+    /// `UdpAcceptor`.  Packets from new peers that arrive when this buffer is full are dropped.
+    pub accept_buffer_size: usize,
+    /// The maximum number of simultaneous peers, after which packets from unknown addresses are
+    /// dropped.
+    pub max_peers: usize,
+    /// The path MTU to peers, if known, less the IP and UDP headers.  This is set as the
+    /// `DatagramLimit` of every multiplexer run with `UdpPeer::run_multiplexer`, otherwise packets
+    /// are only limited by the capacity of the multiplexer's packet pool.
+    pub max_datagram_size: Option<usize>,
+}
+
+/// How long the new address of a migrating peer has to answer its path challenge.
+pub const PATH_VALIDATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The prefix of the path validation datagrams a `UdpTransport` exchanges with its peers.
+///
+/// Path validation datagrams are exactly `PATH_DATAGRAM_LEN` bytes long, and are this prefix, a
+/// kind byte of 0 for a challenge or 1 for a response, and a random token.  They are answered or
+/// acted on by the transport itself, so incoming datagrams of that length, prefix and kind are
+/// never delivered as packets.
+pub const PATH_PREFIX: [u8; 8] = *b"turbpath";
+/// The length of every path validation datagram, see `PATH_PREFIX`.
+pub const PATH_DATAGRAM_LEN: usize = PATH_PREFIX.len() + 1 + PATH_TOKEN_LEN;
+const PATH_CHALLENGE: u8 = 0;
+const PATH_RESPONSE: u8 = 1;
+const PATH_TOKEN_LEN: usize = 16;
+
+type MigrationFilter = Box<dyn FnMut(SocketAddr, &[u8]) -> Option<SocketAddr> + Send>;
+
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    #[error("a peer with this address already exists")]
+    DuplicatePeer,
+    #[error("the maximum number of peers has been reached")]
+    TooManyPeers,
+    #[error("transport task has shut down")]
+    Shutdown,
+}
+
+/// Binds a UDP socket and demultiplexes incoming datagrams into pooled packets for each remote
+/// peer address, and sends each peer's outgoing packets to its address.
+///
+/// Every datagram is a single packet, so the packet pool should return packets sized to the
+/// maximum datagram size that should be sent or received.  Datagrams larger than the packet
+/// capacity are truncated.
+///
+/// Clients will generally call `UdpTransport::connect` for the server address and drop the
+/// `UdpAcceptor`, which causes datagrams from unknown addresses to be ignored.  Servers receive
+/// a `UdpPeer` from the `UdpAcceptor` for every new address that sends a datagram.
+///
+/// The socket is driven by a task spawned on the provided runtime, which is shut down when the
+/// `UdpTransport` is dropped.
+///
+/// A server can let peers move to a new address mid-session, such as when a phone switches
+/// networks, with `UdpTransport::set_migration_filter`.  Every transport answers the path
+/// challenges this sends, so datagrams shaped like path validation datagrams are reserved, see
+/// `PATH_PREFIX`.  A multiplexed packet only has this shape if it is sent on channel 116 (the
+/// ASCII 't' of the prefix), so applications should avoid that channel number.
+pub struct UdpTransport<P>
+where
+    P: PacketPool,
+{
+    local_addr: SocketAddr,
+    registry: Arc<PeerRegistry<P::Packet>>,
+    task: Fuse<JoinHandle<io::Error>>,
+}
+
+impl<P> UdpTransport<P>
+where
+    P: PacketPool + Send + 'static,
+    P::Packet: Send,
+{
+    /// Bind a new UDP socket to the given address.
+    pub fn bind<R: Runtime>(
+        runtime: &R,
+        addr: impl Into<SocketAddr>,
+        packet_pool: P,
+        settings: Settings,
+    ) -> io::Result<(UdpTransport<P>, UdpAcceptor<P::Packet>)> {
+        UdpTransport::new(
+            runtime,
+            UdpSocket::bind(addr.into())?,
+            packet_pool,
+            settings,
+        )
+    }
+
+    /// Use an already bound UDP socket, the socket will be put into non-blocking mode.
+    pub fn new<R: Runtime>(
+        runtime: &R,
+        socket: UdpSocket,
+        packet_pool: P,
+        settings: Settings,
+    ) -> io::Result<(UdpTransport<P>, UdpAcceptor<P::Packet>)> {
+        UdpTransport::new_with_filter(runtime, socket, packet_pool, settings, |_, _| true)
+    }
+
+    /// Bind a new UDP socket to the given address, only accepting new peers whose first datagram
+    /// passes the given filter.
+    ///
+    /// The filter is called with the address and contents of every datagram from an unknown
+    /// address, and datagrams it rejects are dropped without allocating any state.  This can be
+    /// used to require a connect token from clients, see
+    /// `connect_token::TokenValidator::validate_request`.  The accepted datagram is delivered to
+    /// the new peer as its first packet.
+    pub fn bind_with_filter<R, F>(
+        runtime: &R,
+        addr: impl Into<SocketAddr>,
+        packet_pool: P,
+        settings: Settings,
+        filter: F,
+    ) -> io::Result<(UdpTransport<P>, UdpAcceptor<P::Packet>)>
+    where
+        R: Runtime,
+        F: FnMut(SocketAddr, &[u8]) -> bool + Send + 'static,
+    {
+        UdpTransport::new_with_filter(
+            runtime,
+            UdpSocket::bind(addr.into())?,
+            packet_pool,
+            settings,
+            filter,
+        )
+    }
+
+    /// Use an already bound UDP socket, only accepting new peers whose first datagram passes the
+    /// given filter, see `UdpTransport::bind_with_filter`.
+    pub fn new_with_filter<R, F>(
+        runtime: &R,
+        socket: UdpSocket,
+        packet_pool: P,
+        settings: Settings,
+        mut filter: F,
+    ) -> io::Result<(UdpTransport<P>, UdpAcceptor<P::Packet>)>
+    where
+        R: Runtime,
+        F: FnMut(SocketAddr, &[u8]) -> bool + Send + 'static,
+    {
+        let socket = Arc::new(Async::new(socket)?);
+        let local_addr = socket.get_ref().local_addr()?;
+
+        let (register_sender, register_receiver) = mpsc::unbounded();
+        let (accept_sender, accept_receiver) = mpsc::channel(settings.accept_buffer_size);
+        let registry = Arc::new(PeerRegistry {
+            settings,
+            state: Mutex::new(RegistryState {
+                next_id: 0,
+                peers: FxHashMap::default(),
+                migration_filter: None,
+                validations: FxHashMap::default(),
+            }),
+            register: register_sender,
+        });
+
+        let task = runtime.spawn_with_handle({
+            let registry = Arc::clone(&registry);
+            async move {
+                let recv =
+                    recv_loop(&socket, packet_pool, &registry, accept_sender, &mut filter).fuse();
+                let send = send_loop(&socket, &registry, register_receiver).fuse();
+                pin_mut!(recv, send);
+                select! {
+                    err = recv => err,
+                    err = send => err,
+                }
+            }
+        });
+
+        Ok((
+            UdpTransport {
+                local_addr,
+                registry,
+                task: task.fuse(),
+            },
+            UdpAcceptor(accept_receiver),
+        ))
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Start sending to and receiving from the given remote address.
+    ///
+    /// The peer is removed from the transport once the returned `UdpPeer` (or its outgoing
+    /// sender) is dropped.
+    pub fn connect(&mut self, addr: SocketAddr) -> Result<UdpPeer<P::Packet>, ConnectError> {
+        if self.task.is_terminated() {
+            return Err(ConnectError::Shutdown);
+        }
+
+        let mut state = self.registry.state.lock().unwrap();
+        if state.peers.contains_key(&addr) {
+            return Err(ConnectError::DuplicatePeer);
+        }
+        if state.peers.len() >= self.registry.settings.max_peers {
+            return Err(ConnectError::TooManyPeers);
+        }
+        Ok(self.registry.add_peer(&mut state, addr))
+    }
+
+    /// Let existing peers migrate to a new address, when a datagram from an unknown address passes
+    /// the given filter.
+    ///
+    /// The filter is called with the address and contents of every datagram from an unknown
+    /// address, before the filter for new peers, and returns the current address of the peer the
+    /// datagram belongs to, if any.  Only the application can tell this, such as by successfully
+    /// decrypting the datagram with that peer's keys, since the datagram's source address can be
+    /// forged.
+    ///
+    /// A datagram which belongs to a peer starts a path validation instead of being delivered: the
+    /// transport sends a challenge to the new address, and only once the remote answers it from
+    /// there is the peer moved to the new address.  Outgoing packets are sent to the old address
+    /// until then, and to the new one after, without disturbing the peer's packet streams or any
+    /// channel state.  The challenge is resent for every datagram that arrives from the new
+    /// address until it is answered, or for up to `PATH_VALIDATION_TIMEOUT`.
+    pub fn set_migration_filter<F>(&mut self, filter: F)
+    where
+        F: FnMut(SocketAddr, &[u8]) -> Option<SocketAddr> + Send + 'static,
+    {
+        self.registry.state.lock().unwrap().migration_filter = Some(Box::new(filter));
+    }
+
+    /// Wait for the transport task to shut down and return the IO error that caused it.
+    ///
+    /// If the transport task panicked or was dropped by the runtime, returns an error of kind
+    /// `io::ErrorKind::Other` wrapping the `TaskFailed` error.
+    pub async fn recv_err(mut self) -> io::Error {
+        (&mut self.task).await.unwrap_or_else(io::Error::other)
+    }
+}
+
+/// A `Stream` of new peers which have sent datagrams to a `UdpTransport`.
+pub struct UdpAcceptor<P>(mpsc::Receiver<UdpPeer<P>>);
+
+impl<P> Stream for UdpAcceptor<P> {
+    type Item = UdpPeer<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+/// The incoming and outgoing packet streams for a single remote address of a `UdpTransport`.
+///
+/// The `addr` is the address the peer started with, if it may have migrated since, its current
+/// address is available from `UdpPeer::path`.
+pub struct UdpPeer<P> {
+    pub addr: SocketAddr,
+    pub incoming: mpsc::Receiver<P>,
+    pub outgoing: mpsc::Sender<P>,
+    path: PeerPath,
+    max_datagram_size: Option<usize>,
+}
+
+/// A handle to the current address of a `UdpPeer`, which changes when the peer migrates.
+#[derive(Debug, Clone)]
+pub struct PeerPath(Arc<Mutex<SocketAddr>>);
+
+impl PeerPath {
+    pub fn addr(&self) -> SocketAddr {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl<P> UdpPeer<P> {
+    pub fn path(&self) -> PeerPath {
+        self.path.clone()
+    }
+}
+
+impl<P> UdpPeer<P>
+where
+    P: Packet + Unpin,
+{
+    /// Forward packets between this peer and a started `PacketMultiplexer` until either side is
+    /// disconnected.
+    ///
+    /// The multiplexer's `DatagramLimit` is set to the transport's `Settings::max_datagram_size`.
+    ///
+    /// Incoming packets are delivered with `IncomingMultiplexedPackets::try_send`, so packets
+    /// for unknown channels or for channels whose buffers are full are dropped rather than
+    /// blocking other channels.
+    pub async fn run_multiplexer(
+        self,
+        mut incoming: IncomingMultiplexedPackets<P>,
+        outgoing: OutgoingMultiplexedPackets<P>,
+    ) {
+        let UdpPeer {
+            incoming: mut peer_incoming,
+            outgoing: peer_outgoing,
+            max_datagram_size,
+            ..
+        } = self;
+        outgoing
+            .datagram_limit()
+            .set_max_datagram_size(max_datagram_size);
+
+        let incoming = async move {
+            while let Some(packet) = peer_incoming.next().await {
+                let _ = incoming.try_send(packet);
+            }
+        }
+        .fuse();
+        let outgoing = outgoing.map(Ok).forward(peer_outgoing).fuse();
+        pin_mut!(incoming, outgoing);
+
+        select! {
+            _ = incoming => {}
+            _ = outgoing => {}
+        }
+    }
+}
+
+struct PeerRegistry<P> {
+    settings: Settings,
+    state: Mutex<RegistryState<P>>,
+    register: mpsc::UnboundedSender<PeerOutgoing<P>>,
+}
+
+struct RegistryState<P> {
+    next_id: u64,
+    peers: FxHashMap<SocketAddr, Peer<P>>,
+    migration_filter: Option<MigrationFilter>,
+    // Path challenges sent to the new addresses of migrating peers.
+    validations: FxHashMap<SocketAddr, PathValidation>,
+}
+
+struct Peer<P> {
+    id: u64,
+    incoming: mpsc::Sender<P>,
+    path: PeerPath,
+}
+
+struct PathValidation {
+    // The address the peer is migrating from.
+    peer: SocketAddr,
+    token: [u8; PATH_TOKEN_LEN],
+    started: Instant,
+}
+
+impl<P> RegistryState<P> {
+    // Start or continue validating the new address of a peer, returning the challenge to send to
+    // it, if any.
+    fn validate_path(
+        &mut self,
+        peer: SocketAddr,
+        addr: SocketAddr,
+        max_validations: usize,
+    ) -> Option<[u8; PATH_DATAGRAM_LEN]> {
+        self.validations
+            .retain(|_, validation| validation.started.elapsed() < PATH_VALIDATION_TIMEOUT);
+        if let Some(validation) = self.validations.get(&addr) {
+            if validation.peer == peer {
+                return Some(path_datagram(PATH_CHALLENGE, &validation.token));
+            }
+        }
+        if !self.validations.contains_key(&addr) && self.validations.len() >= max_validations {
+            return None;
+        }
+
+        // The token comes from the operating system's CSPRNG, so that an off-path attacker
+        // cannot answer a challenge sent to an address they do not receive datagrams at.  If no
+        // randomness is available the migration is refused.
+        let mut token = [0; PATH_TOKEN_LEN];
+        getrandom::getrandom(&mut token).ok()?;
+        self.validations.insert(
+            addr,
+            PathValidation {
+                peer,
+                token,
+                started: Instant::now(),
+            },
+        );
+        Some(path_datagram(PATH_CHALLENGE, &token))
+    }
+
+    // Finish validating a new address with the token from its path response, moving the peer to
+    // it.
+    fn complete_path(&mut self, addr: SocketAddr, token: &[u8]) {
+        let validation = match self.validations.get(&addr) {
+            Some(validation)
+                if validation.token == token
+                    && validation.started.elapsed() < PATH_VALIDATION_TIMEOUT =>
+            {
+                self.validations.remove(&addr).unwrap()
+            }
+            _ => return,
+        };
+        if self.peers.contains_key(&addr) {
+            return;
+        }
+        if let Some(peer) = self.peers.remove(&validation.peer) {
+            debug_event!(from = %validation.peer, to = %addr, "udp peer migrated");
+            *peer.path.0.lock().unwrap() = addr;
+            self.peers.insert(addr, peer);
+        }
+    }
+}
+
+impl<P> PeerRegistry<P> {
+    fn add_peer(&self, state: &mut RegistryState<P>, addr: SocketAddr) -> UdpPeer<P> {
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let (incoming_sender, incoming_receiver) = mpsc::channel(self.settings.peer_buffer_size);
+        let (outgoing_sender, outgoing_receiver) = mpsc::channel(self.settings.peer_buffer_size);
+        let path = PeerPath(Arc::new(Mutex::new(addr)));
+        state.peers.insert(
+            addr,
+            Peer {
+                id,
+                incoming: incoming_sender,
+                path: path.clone(),
+            },
+        );
+        // The receiving end lives as long as the transport task, and if the task is gone the peer
+        // will simply never send anything.
+        let _ = self.register.unbounded_send(PeerOutgoing {
+            id,
+            path: path.clone(),
+            receiver: outgoing_receiver,
+            closed: false,
+        });
+
+        UdpPeer {
+            addr,
+            incoming: incoming_receiver,
+            outgoing: outgoing_sender,
+            path,
+            max_datagram_size: self.settings.max_datagram_size,
+        }
+    }
+
+    fn remove_peer(&self, id: u64, addr: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        if state.peers.get(&addr).map(|peer| peer.id) == Some(id) {
+            state.peers.remove(&addr);
+        }
+    }
+}
+
+enum Outgoing<P> {
+    Packet(SocketAddr, P),
+    Closed(u64, SocketAddr),
+}
+
+struct PeerOutgoing<P> {
+    id: u64,
+    path: PeerPath,
+    receiver: mpsc::Receiver<P>,
+    closed: bool,
+}
+
+impl<P> Stream for PeerOutgoing<P> {
+    type Item = Outgoing<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.closed {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut self.receiver).poll_next(cx) {
+            Poll::Ready(Some(packet)) => {
+                Poll::Ready(Some(Outgoing::Packet(self.path.addr(), packet)))
+            }
+            Poll::Ready(None) => {
+                self.closed = true;
+                Poll::Ready(Some(Outgoing::Closed(self.id, self.path.addr())))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// Errors that can be caused by a single misbehaving remote (e.g. ICMP port unreachable) and should
+// not shut down the whole socket.
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused
+    )
+}
+
+async fn recv_loop<P>(
+    socket: &Async<UdpSocket>,
+    packet_pool: P,
+    registry: &PeerRegistry<P::Packet>,
+    mut accept: mpsc::Sender<UdpPeer<P::Packet>>,
+    filter: &mut impl FnMut(SocketAddr, &[u8]) -> bool,
+) -> io::Error
+where
+    P: PacketPool,
+{
+    loop {
+        let mut packet = packet_pool.acquire();
+        packet.resize(packet.capacity(), 0);
+        let (len, addr) = match socket.recv_from(&mut packet).await {
+            Ok(r) => r,
+            Err(err) if is_transient(&err) => continue,
+            Err(err) => return err,
+        };
+        packet.truncate(len);
+
+        if packet.len() == PATH_DATAGRAM_LEN
+            && packet.starts_with(&PATH_PREFIX)
+            && matches!(packet[PATH_PREFIX.len()], PATH_CHALLENGE | PATH_RESPONSE)
+        {
+            let token = &packet[PATH_PREFIX.len() + 1..];
+            let response = {
+                let mut state = registry.state.lock().unwrap();
+                match packet[PATH_PREFIX.len()] {
+                    // Only known peers are answered, so that the transport cannot be used to
+                    // reflect datagrams at arbitrary addresses.
+                    PATH_CHALLENGE if state.peers.contains_key(&addr) => {
+                        Some(path_datagram(PATH_RESPONSE, token))
+                    }
+                    PATH_RESPONSE => {
+                        state.complete_path(addr, token);
+                        None
+                    }
+                    _ => None,
+                }
+            };
+            if let Some(response) = response {
+                if let Err(err) = socket.send_to(&response, addr).await {
+                    if !is_transient(&err) {
+                        return err;
+                    }
+                }
+            }
+            continue;
+        }
+
+        let challenge = {
+            let mut state = registry.state.lock().unwrap();
+            if let Some(peer) = migrating_peer(&mut state, addr, &packet) {
+                state.validate_path(peer, addr, registry.settings.max_peers)
+            } else {
+                if !state.peers.contains_key(&addr) {
+                    if accept.is_closed()
+                        || state.peers.len() >= registry.settings.max_peers
+                        || !filter(addr, &packet)
+                    {
+                        continue;
+                    }
+                    let peer = registry.add_peer(&mut state, addr);
+                    if accept.try_send(peer).is_err() {
+                        // Dropping the `UdpPeer` here will remove it from the registry once the
+                        // send task notices.
+                        state.peers.remove(&addr);
+                        continue;
+                    }
+                }
+
+                let sender = &mut state.peers.get_mut(&addr).unwrap().incoming;
+                if let Err(err) = sender.try_send(packet) {
+                    if err.is_disconnected() {
+                        state.peers.remove(&addr);
+                    }
+                }
+                continue;
+            }
+        };
+
+        if let Some(challenge) = challenge {
+            if let Err(err) = socket.send_to(&challenge, addr).await {
+                if !is_transient(&err) {
+                    return err;
+                }
+            }
+        }
+    }
+}
+
+// The existing peer that a datagram from an unknown address belongs to, according to the
+// migration filter.
+fn migrating_peer<P>(
+    state: &mut RegistryState<P>,
+    addr: SocketAddr,
+    packet: &[u8],
+) -> Option<SocketAddr> {
+    if state.peers.contains_key(&addr) {
+        return None;
+    }
+    let filter = state.migration_filter.as_mut()?;
+    filter(addr, packet).filter(|peer| state.peers.contains_key(peer))
+}
+
+fn path_datagram(kind: u8, token: &[u8]) -> [u8; PATH_DATAGRAM_LEN] {
+    let mut datagram = [0; PATH_DATAGRAM_LEN];
+    datagram[..PATH_PREFIX.len()].copy_from_slice(&PATH_PREFIX);
+    datagram[PATH_PREFIX.len()] = kind;
+    datagram[PATH_PREFIX.len() + 1..].copy_from_slice(token);
+    datagram
+}
+
+async fn send_loop<P>(
+    socket: &Async<UdpSocket>,
+    registry: &PeerRegistry<P>,
+    mut register: mpsc::UnboundedReceiver<PeerOutgoing<P>>,
+) -> io::Error
+where
+    P: Packet,
+{
+    let mut outgoing = SelectAll::new();
+    loop {
+        select! {
+            peer = register.select_next_some() => outgoing.push(peer),
+            next = outgoing.select_next_some() => match next {
+                Outgoing::Packet(addr, packet) => match socket.send_to(&packet, addr).await {
+                    Ok(_) => {}
+                    Err(err) if is_transient(&err) => {}
+                    Err(err) => return err,
+                },
+                Outgoing::Closed(id, addr) => registry.remove_peer(id, addr),
+            },
+            complete => return future::pending().await,
+        }
+    }
+}
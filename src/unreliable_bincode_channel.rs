@@ -1,13 +1,20 @@
 use std::marker::PhantomData;
 
 use bincode::Options as _;
+use futures::{Sink, Stream};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
-    packet::PacketPool,
-    runtime::Runtime,
-    unreliable_channel::{self, UnreliableChannel, MAX_MESSAGE_LEN},
+    decode_limits::{self, DecodeLimits},
+    envelope::Envelope,
+    growable_buffer::GrowableBuffer,
+    packet::{PacketMetadata, PacketPool},
+    runtime::Timer,
+    scratch::ScratchArena,
+    unreliable_channel::{
+        self, DefaultIncoming, DefaultOutgoing, RateLimit, UnreliableChannel, MAX_MESSAGE_LEN,
+    },
 };
 
 #[derive(Debug, Error)]
@@ -33,32 +40,81 @@ pub enum RecvError {
 ///
 /// Just like the underlying channel, messages are not guaranteed to arrive, nor are they guaranteed
 /// to arrive in order.
-pub struct UnreliableBincodeChannel<R, P>
-where
-    R: Runtime,
+pub struct UnreliableBincodeChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
     P: PacketPool,
 {
-    channel: UnreliableChannel<R, P>,
-    buffer: Box<[u8]>,
+    channel: UnreliableChannel<R, P, I, O>,
+    max_message_len: u16,
+    buffer: GrowableBuffer,
+    decode_limits: Option<DecodeLimits>,
 }
 
-impl<R, P> UnreliableBincodeChannel<R, P>
+impl<R, P, I, O> UnreliableBincodeChannel<R, P, I, O>
 where
-    R: Runtime,
+    R: Timer,
     P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
 {
     /// Create a new `UnreliableBincodeChannel` with the given max message size.
     ///
     /// The maximum message size is always limited by the underlying `UnreliableChannel` maximum
     /// message size regardless of the `max_message_len` setting, but this can be used to restrict
     /// the intermediate buffer used to serialize messages.
-    pub fn new(channel: UnreliableChannel<R, P>, max_message_len: u16) -> Self {
+    pub fn new(channel: UnreliableChannel<R, P, I, O>, max_message_len: u16) -> Self {
+        Self::with_initial_buffer_len(channel, max_message_len, max_message_len)
+    }
+
+    /// Create a new `UnreliableBincodeChannel` whose serialization buffer starts with room for
+    /// messages of `initial_buffer_len`, and grows on demand up to the max message size.
+    ///
+    /// The buffer never shrinks, so its size is that of the largest message sent so far.
+    pub fn with_initial_buffer_len(
+        channel: UnreliableChannel<R, P, I, O>,
+        max_message_len: u16,
+        initial_buffer_len: u16,
+    ) -> Self {
+        let max_message_len = max_message_len.min(MAX_MESSAGE_LEN);
         UnreliableBincodeChannel {
             channel,
-            buffer: vec![0; max_message_len.min(MAX_MESSAGE_LEN) as usize].into_boxed_slice(),
+            max_message_len,
+            buffer: GrowableBuffer::new(initial_buffer_len as usize, max_message_len as usize),
+            decode_limits: None,
         }
     }
 
+    /// Set limits on the decoded size and nesting depth of received messages, by default none.
+    ///
+    /// A message exceeding them fails to deserialize and is skipped, see `DecodeLimits`.
+    pub fn set_decode_limits(&mut self, limits: Option<DecodeLimits>) {
+        self.decode_limits = limits;
+    }
+
+    pub fn decode_limits(&self) -> Option<DecodeLimits> {
+        self.decode_limits
+    }
+
+    /// Borrow the serialization buffer from the given arena, or own it again if `None`, see
+    /// `ScratchArena`.
+    pub fn set_scratch_arena(&mut self, arena: Option<ScratchArena>) {
+        self.buffer.set_arena(arena);
+    }
+
+    pub fn scratch_arena(&self) -> Option<&ScratchArena> {
+        self.buffer.arena()
+    }
+
+    /// The current length of the serialization buffer.
+    pub fn buffer_len(&self) -> usize {
+        self.buffer.len()
+    }
+
     /// Write the given serializable message type to the channel.
     ///
     /// Messages are coalesced into larger packets before being sent, so in order to guarantee that
@@ -68,13 +124,16 @@ where
     /// or may not buffer a message to be sent.
     pub async fn send<T: Serialize>(&mut self, msg: &T) -> Result<(), SendError> {
         let bincode_config = self.bincode_config();
-        let mut w = &mut self.buffer[..];
-        bincode_config
-            .serialize_into(&mut w, msg)
-            .map_err(SendError::BincodeError)?;
-        let remaining = w.len();
-        let written = self.buffer.len() - remaining;
-        Ok(self.channel.send(&self.buffer[0..written]).await?)
+        let res = match self.buffer.serialize(0, bincode_config, msg) {
+            Ok(written) => self
+                .channel
+                .send(&self.buffer[0..written])
+                .await
+                .map_err(SendError::from),
+            Err(err) => Err(SendError::BincodeError(err)),
+        };
+        self.buffer.release();
+        res
     }
 
     /// Finish sending any unsent coalesced packets.
@@ -87,6 +146,17 @@ where
         Ok(self.channel.flush().await?)
     }
 
+    /// Ask the remote to send no faster than the given rate, see
+    /// `UnreliableChannel::request_rate`.
+    pub async fn request_rate(&mut self, rate: RateLimit) -> Result<(), SendError> {
+        Ok(self.channel.request_rate(rate).await?)
+    }
+
+    /// The rate most recently requested by the remote, see `UnreliableChannel::remote_rate`.
+    pub fn remote_rate(&self) -> RateLimit {
+        self.channel.remote_rate()
+    }
+
     /// Receive a deserializable message type as soon as the next message is available.
     ///
     /// This method is cancel safe, it will never partially read a message or drop received
@@ -94,34 +164,76 @@ where
     pub async fn recv<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T, RecvError> {
         let bincode_config = self.bincode_config();
         let msg = self.channel.recv().await?;
-        bincode_config
-            .deserialize(msg)
+        decode_limits::bincode_deserialize(bincode_config, msg, self.decode_limits.as_ref())
             .map_err(RecvError::BincodeError)
     }
 
+    /// Like `UnreliableBincodeChannel::recv`, but also returns the time at which the packet
+    /// containing the message was received, as described in `UnreliableChannel::recv_timed`.
+    ///
+    /// A message which fails to deserialize is still skipped.
+    pub async fn recv_timed<'a, T: Deserialize<'a>>(
+        &'a mut self,
+    ) -> Result<(T, R::Instant), RecvError> {
+        let bincode_config = self.bincode_config();
+        let (msg, received) = self.channel.recv_timed().await?;
+        let msg =
+            decode_limits::bincode_deserialize(bincode_config, msg, self.decode_limits.as_ref())
+                .map_err(RecvError::BincodeError)?;
+        Ok((msg, received))
+    }
+
+    /// The metadata of the packet containing the most recently received message, as described in
+    /// `UnreliableChannel::metadata`.
+    pub fn metadata(&self) -> Option<&PacketMetadata> {
+        self.channel.metadata()
+    }
+
     fn bincode_config(&self) -> impl bincode::Options + Copy {
-        bincode::options().with_limit(self.buffer.len() as u64)
+        bincode::options().with_limit(self.max_message_len as u64)
     }
 }
 
 /// Wrapper over an `UnreliableBincodeChannel` that only allows a single message type.
-pub struct UnreliableTypedChannel<T, R, P>
-where
-    R: Runtime,
+pub struct UnreliableTypedChannel<
+    T,
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
     P: PacketPool,
 {
-    channel: UnreliableBincodeChannel<R, P>,
+    channel: UnreliableBincodeChannel<R, P, I, O>,
+    envelope: Option<Envelope<T>>,
     _phantom: PhantomData<T>,
 }
 
-impl<T, R, P> UnreliableTypedChannel<T, R, P>
+impl<T, R, P, I, O> UnreliableTypedChannel<T, R, P, I, O>
 where
-    R: Runtime,
+    R: Timer,
     P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
 {
-    pub fn new(channel: UnreliableBincodeChannel<R, P>) -> Self {
+    pub fn new(channel: UnreliableBincodeChannel<R, P, I, O>) -> Self {
+        UnreliableTypedChannel {
+            channel,
+            envelope: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Create a typed channel which sends and receives messages in the given versioned
+    /// `Envelope`.
+    pub fn with_envelope(
+        channel: UnreliableBincodeChannel<R, P, I, O>,
+        envelope: Envelope<T>,
+    ) -> Self {
         UnreliableTypedChannel {
             channel,
+            envelope: Some(envelope),
             _phantom: PhantomData,
         }
     }
@@ -129,26 +241,68 @@ where
     pub async fn flush(&mut self) -> Result<(), SendError> {
         self.channel.flush().await
     }
+
+    /// Ask the remote to send no faster than the given rate, see
+    /// `UnreliableChannel::request_rate`.
+    pub async fn request_rate(&mut self, rate: RateLimit) -> Result<(), SendError> {
+        self.channel.request_rate(rate).await
+    }
+
+    /// The rate most recently requested by the remote, see `UnreliableChannel::remote_rate`.
+    pub fn remote_rate(&self) -> RateLimit {
+        self.channel.remote_rate()
+    }
+
+    /// The metadata of the packet containing the most recently received message, as described in
+    /// `UnreliableChannel::metadata`.
+    pub fn metadata(&self) -> Option<&PacketMetadata> {
+        self.channel.metadata()
+    }
 }
 
-impl<T, R, P> UnreliableTypedChannel<T, R, P>
+impl<T, R, P, I, O> UnreliableTypedChannel<T, R, P, I, O>
 where
     T: Serialize,
-    R: Runtime,
+    R: Timer,
     P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
 {
     pub async fn send(&mut self, msg: &T) -> Result<(), SendError> {
-        self.channel.send(msg).await
+        match &self.envelope {
+            Some(envelope) => {
+                let enveloped = envelope.encode(msg).map_err(SendError::BincodeError)?;
+                self.channel.send(&enveloped).await
+            }
+            None => self.channel.send(msg).await,
+        }
     }
 }
 
-impl<'a, T, R, P> UnreliableTypedChannel<T, R, P>
+impl<'a, T, R, P, I, O> UnreliableTypedChannel<T, R, P, I, O>
 where
     T: Deserialize<'a>,
-    R: Runtime,
+    R: Timer,
     P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
 {
     pub async fn recv(&'a mut self) -> Result<T, RecvError> {
-        self.channel.recv().await
+        Ok(self.recv_timed().await?.0)
+    }
+
+    pub async fn recv_timed(&'a mut self) -> Result<(T, R::Instant), RecvError> {
+        match &self.envelope {
+            Some(envelope) => {
+                let limits = self.channel.decode_limits;
+                let ((version, payload), received): ((u16, &[u8]), _) =
+                    self.channel.recv_timed().await?;
+                let msg = envelope
+                    .decode(version, payload, limits.as_ref())
+                    .map_err(RecvError::BincodeError)?;
+                Ok((msg, received))
+            }
+            None => self.channel.recv_timed().await,
+        }
     }
 }
@@ -1,23 +1,33 @@
-use std::{convert::TryInto, mem};
+use alloc::boxed::Box;
+use core::{
+    convert::TryInto,
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
 use byteorder::{ByteOrder, LittleEndian};
-use futures::{
-    channel::mpsc::{Receiver, Sender},
-    future::poll_fn,
-    StreamExt,
-};
+use futures::{future::poll_fn, ready, task::noop_waker_ref, Sink, Stream};
 use thiserror::Error;
 
 use crate::{
     bandwidth_limiter::BandwidthLimiter,
-    packet::{Packet, PacketPool, MAX_PACKET_LEN},
-    runtime::Runtime,
+    packet::{Packet, PacketMetadata, PacketPool, MAX_PACKET_LEN},
+    runtime::Timer,
+    tick_budget::{TickBudget, TickWindow},
 };
 
 /// The maximum possible message length of an `UnreliableChannel` message for the largest possible
 /// packet, based on the `MAX_PACKET_LEN`.
 pub const MAX_MESSAGE_LEN: u16 = MAX_PACKET_LEN - 2;
 
+// A rate request is a packet of its own, made of this length prefix, which is longer than any
+// possible message, followed by the requested bytes / sec and messages / sec as u32s, where 0 is
+// unlimited.
+const RATE_REQUEST: u16 = u16::MAX;
+const RATE_REQUEST_LEN: usize = 10;
+
 #[derive(Debug, Error)]
 pub enum SendError {
     /// Fatal error due to channel disconnection.
@@ -39,6 +49,7 @@ pub enum RecvError {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Settings {
     /// The target outgoing bandwidth, in bytes / sec.
     pub bandwidth: u32,
@@ -47,47 +58,279 @@ pub struct Settings {
     pub burst_bandwidth: u32,
 }
 
+/// Returned by `Settings::validate` when settings would keep a channel from working.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SettingsError {
+    #[error("bandwidth must be nonzero")]
+    ZeroBandwidth,
+    #[error("burst bandwidth of {burst_bandwidth} bytes is less than the packet length of {packet_len} bytes")]
+    BurstTooSmall {
+        burst_bandwidth: u32,
+        packet_len: u32,
+    },
+}
+
+impl Settings {
+    /// Check that these settings are usable for a channel sending packets of up to `packet_len`
+    /// bytes.
+    ///
+    /// A burst bandwidth smaller than a packet does not stop a channel from sending, but it does
+    /// mean that the channel can never send a full packet without going into bandwidth debt, so
+    /// it is rejected.
+    pub fn validate(&self, packet_len: usize) -> Result<(), SettingsError> {
+        if self.bandwidth == 0 {
+            return Err(SettingsError::ZeroBandwidth);
+        }
+        let packet_len = packet_len.min(MAX_PACKET_LEN as usize) as u32;
+        if self.burst_bandwidth < packet_len {
+            return Err(SettingsError::BurstTooSmall {
+                burst_bandwidth: self.burst_bandwidth,
+                packet_len,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A maximum rate which the receiving side of an `UnreliableChannel` asks the sending side to stay
+/// under, with `UnreliableChannel::request_rate`.
+///
+/// The sender enforces the lower of its own `Settings::bandwidth` and the requested bytes / sec.
+/// Messages over the requested messages / sec wait with `ShedPolicy::Queue`, and are dropped with
+/// either dropping shed policy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateLimit {
+    pub bytes_per_sec: Option<u32>,
+    pub messages_per_sec: Option<u32>,
+}
+
+impl RateLimit {
+    /// No limit beyond the sender's own settings.
+    pub const UNLIMITED: RateLimit = RateLimit {
+        bytes_per_sec: None,
+        messages_per_sec: None,
+    };
+}
+
+/// What an `UnreliableChannel` does with messages sent while its bandwidth limit is saturated.
+///
+/// Normally a message which does not fit in the current packet waits for bandwidth to send that
+/// packet, and every message behind it waits too.  Unreliable messages are usually state which is
+/// superseded by the next message, so under load it is better to drop some of them than to let
+/// the queue and its latency grow.  Messages are only ever shed when the limiter is in debt and the
+/// current packet is full, a channel which is keeping up sends everything.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ShedPolicy {
+    /// Never shed messages, wait for bandwidth instead.
+    #[default]
+    Queue,
+    /// Drop the message being sent, keeping the messages already in the current packet.
+    DropNewest,
+    /// Drop the stale messages in the current packet, and start a new packet with the message
+    /// being sent.
+    DropStale,
+}
+
+/// What an `UnreliableChannel` does with a packet when its outgoing packet sink is full, such as
+/// when the transport is not keeping up with the multiplexer.
+///
+/// Packets are only dropped by the channel's own handling, a packet which has been accepted by the
+/// sink is never taken back out of it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverflowPolicy {
+    /// Wait for the sink to have room.
+    #[default]
+    Wait,
+    /// Drop the packet being sent.
+    DropNewest,
+    /// Hold on to the packet being sent, to be handed to the sink by a later `flush` once it has
+    /// room.  Only one packet is held, so a newer packet replaces and drops it.
+    DropOldest,
+}
+
+// The default packet stream and sink types, `futures::channel::mpsc` requires std.
+#[cfg(feature = "std")]
+pub(crate) type DefaultIncoming<P> = futures::channel::mpsc::Receiver<P>;
+#[cfg(feature = "std")]
+pub(crate) type DefaultOutgoing<P> = futures::channel::mpsc::Sender<P>;
+#[cfg(not(feature = "std"))]
+pub(crate) type DefaultIncoming<P> = futures::stream::Pending<P>;
+#[cfg(not(feature = "std"))]
+pub(crate) type DefaultOutgoing<P> = futures::sink::Drain<P>;
+
 /// Turns a stream of unreliable, unordered packets into a stream of unreliable, unordered messages.
-pub struct UnreliableChannel<R, P>
-where
-    R: Runtime,
+///
+/// The incoming and outgoing packets may be any `Stream` and `Sink` of packets, by default these are
+/// the mpsc channels returned from `PacketMultiplexer::open_channel`.  The end of the incoming
+/// stream or any error from the outgoing sink is treated as a disconnection.
+pub struct UnreliableChannel<
+    R,
+    P,
+    I = DefaultIncoming<<P as PacketPool>::Packet>,
+    O = DefaultOutgoing<<P as PacketPool>::Packet>,
+> where
+    R: Timer,
     P: PacketPool,
 {
     packet_pool: P,
+    // The bandwidth from the channel `Settings`, before any rate requested by the remote.
+    bandwidth: u32,
     bandwidth_limiter: BandwidthLimiter<R>,
-    incoming_packets: Receiver<P::Packet>,
-    outgoing_packets: Sender<P::Packet>,
+    // The rate most recently requested by the remote, and a limiter of messages / sec if it
+    // requested one.
+    remote_rate: RateLimit,
+    message_limiter: Option<BandwidthLimiter<R>>,
+    message_sleep: Option<Pin<Box<R::Sleep>>>,
+    // The bytes / tick budget if one is set, the bytes sent in the current tick, and the sleep of
+    // a send waiting for the next tick.
+    tick_budget: Option<TickBudget>,
+    tick_window: TickWindow<R>,
+    tick_sleep: Option<Pin<Box<R::Sleep>>>,
+    incoming_packets: I,
+    outgoing_packets: O,
     out_packet: P::Packet,
-    in_packet: Option<(P::Packet, usize)>,
+    // The number of messages coalesced into `out_packet`.
+    out_messages: u64,
+    shed_policy: ShedPolicy,
+    shed: u64,
+    overflow_policy: OverflowPolicy,
+    overflowed: u64,
+    // The packet held back from a full sink by `OverflowPolicy::DropOldest`.
+    held_packet: Option<P::Packet>,
+    // The sleep of a flush waiting for bandwidth, kept across polls.
+    bandwidth_sleep: Option<Pin<Box<R::Sleep>>>,
+    // The packet currently being read, the position of the next message in it, and the time it
+    // was received.
+    in_packet: Option<(P::Packet, usize, R::Instant)>,
 }
 
-impl<R, P> UnreliableChannel<R, P>
+impl<R, P, I, O> UnreliableChannel<R, P, I, O>
 where
-    R: Runtime,
+    R: Timer,
     P: PacketPool,
+    I: Stream<Item = P::Packet> + Unpin,
+    O: Sink<P::Packet> + Unpin,
 {
-    pub fn new(
-        runtime: R,
-        packet_pool: P,
-        settings: Settings,
-        incoming: Receiver<P::Packet>,
-        outgoing: Sender<P::Packet>,
-    ) -> Self {
+    pub fn new(runtime: R, packet_pool: P, settings: Settings, incoming: I, outgoing: O) -> Self {
         let out_packet = packet_pool.acquire();
         UnreliableChannel {
             packet_pool,
+            bandwidth: settings.bandwidth,
+            tick_window: TickWindow::new(runtime.clone()),
             bandwidth_limiter: BandwidthLimiter::new(
                 runtime,
                 settings.bandwidth,
                 settings.burst_bandwidth,
             ),
+            remote_rate: RateLimit::UNLIMITED,
+            message_limiter: None,
+            message_sleep: None,
+            tick_budget: None,
+            tick_sleep: None,
             incoming_packets: incoming,
             outgoing_packets: outgoing,
             out_packet,
+            out_messages: 0,
+            shed_policy: ShedPolicy::Queue,
+            shed: 0,
+            overflow_policy: OverflowPolicy::Wait,
+            overflowed: 0,
+            held_packet: None,
+            bandwidth_sleep: None,
             in_packet: None,
         }
     }
 
+    /// Set what happens to messages sent while the channel's bandwidth is saturated, by default
+    /// `ShedPolicy::Queue`.
+    pub fn set_shed_policy(&mut self, policy: ShedPolicy) {
+        self.shed_policy = policy;
+    }
+
+    pub fn shed_policy(&self) -> ShedPolicy {
+        self.shed_policy
+    }
+
+    /// The total number of messages dropped by the shed policy.
+    pub fn shed(&self) -> u64 {
+        self.shed
+    }
+
+    /// Set what happens to packets sent while the outgoing packet sink is full, by default
+    /// `OverflowPolicy::Wait`.
+    ///
+    /// With either dropping policy, `flush` never waits for the sink, only for bandwidth.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    /// The total number of packets dropped by the overflow policy.
+    pub fn overflowed(&self) -> u64 {
+        self.overflowed
+    }
+
+    /// Limit the bytes of messages sent per tick, or remove the limit with `None`.  There is no
+    /// limit by default.
+    ///
+    /// Messages over the budget wait for the next tick with `ShedPolicy::Queue`, and are dropped
+    /// with either dropping shed policy.
+    pub fn set_tick_budget(&mut self, budget: Option<TickBudget>) {
+        self.tick_budget = budget;
+        self.tick_sleep = None;
+    }
+
+    pub fn tick_budget(&self) -> Option<TickBudget> {
+        self.tick_budget
+    }
+
+    /// Ask the remote to send to this channel no faster than the given rate, such as when this side
+    /// cannot keep up with the messages it is being sent.  `RateLimit::UNLIMITED` lifts an earlier
+    /// request.
+    ///
+    /// The request is sent immediately in a packet of its own, which may be lost like any other
+    /// unreliable packet, so it should be repeated periodically for as long as it applies.  The
+    /// remote only sees the request while it is receiving from its side of the channel.
+    pub async fn request_rate(&mut self, rate: RateLimit) -> Result<(), SendError> {
+        let mut packet = self.packet_pool.acquire();
+        packet.resize(RATE_REQUEST_LEN, 0);
+        LittleEndian::write_u16(&mut packet[0..2], RATE_REQUEST);
+        LittleEndian::write_u32(&mut packet[2..6], rate.bytes_per_sec.unwrap_or(0));
+        LittleEndian::write_u32(&mut packet[6..10], rate.messages_per_sec.unwrap_or(0));
+
+        poll_fn(|cx| Pin::new(&mut self.outgoing_packets).poll_ready(cx))
+            .await
+            .map_err(|_| SendError::Disconnected)?;
+        trace_event!(
+            bytes_per_sec = rate.bytes_per_sec,
+            messages_per_sec = rate.messages_per_sec,
+            "requesting unreliable rate"
+        );
+        self.start_send(packet)?;
+        poll_fn(|cx| Pin::new(&mut self.outgoing_packets).poll_flush(cx))
+            .await
+            .map_err(|_| SendError::Disconnected)
+    }
+
+    /// The rate most recently requested by the remote with `request_rate`, which this channel is
+    /// enforcing on the messages it sends.
+    pub fn remote_rate(&self) -> RateLimit {
+        self.remote_rate
+    }
+
+    /// Returns true if the bandwidth limit is saturated, so that sending a packet right now would
+    /// first wait for bandwidth to become available.
+    pub fn is_saturated(&mut self) -> bool {
+        self.bandwidth_limiter.update_available();
+        !self.bandwidth_limiter.bytes_available()
+    }
+
     /// Write the given message to the channel.
     ///
     /// Messages are coalesced into larger packets before being sent, so in order to guarantee that
@@ -100,47 +343,239 @@ where
     /// This method is cancel safe, it will never partially send a message, though canceling it may
     /// or may not buffer a message to be sent.
     pub async fn send(&mut self, msg: &[u8]) -> Result<(), SendError> {
-        let msg_len: u16 = msg.len().try_into().map_err(|_| SendError::TooBig)?;
+        poll_fn(|cx| self.poll_send(cx, msg)).await
+    }
+
+    /// The poll form of `UnreliableChannel::send`, for use in manual `Future` implementations.
+    ///
+    /// The message is only buffered once this returns `Poll::Ready(Ok(()))`, after
+    /// `Poll::Pending` the same message should be given to the next call.  Any progress made
+    /// flushing earlier messages is kept.
+    pub fn poll_send(&mut self, cx: &mut Context, msg: &[u8]) -> Poll<Result<(), SendError>> {
+        let msg_len: Result<u16, _> = msg.len().try_into().map_err(|_| {
+            debug_event!(len = msg.len(), "unreliable message too big");
+            SendError::TooBig
+        });
+        let msg_len = match msg_len {
+            Ok(msg_len) => msg_len,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+
+        if let Some(limiter) = &mut self.message_limiter {
+            limiter.update_available();
+            if !limiter.bytes_available() && self.shed_policy != ShedPolicy::Queue {
+                self.shed += 1;
+                debug_event!(
+                    len = msg_len,
+                    "shedding unreliable message over requested rate"
+                );
+                return Poll::Ready(Ok(()));
+            }
+            ready!(self.poll_message_rate(cx));
+        }
+
+        let cost = msg_len as u32 + 2;
+        if let Some(budget) = self.tick_budget {
+            self.tick_window.update(&budget);
+            while !self.tick_window.fits(&budget, cost) {
+                if self.shed_policy != ShedPolicy::Queue {
+                    self.shed += 1;
+                    debug_event!(
+                        len = msg_len,
+                        "shedding unreliable message over tick budget"
+                    );
+                    return Poll::Ready(Ok(()));
+                }
+                ready!(self.poll_next_tick(cx, &budget));
+            }
+        }
 
         let start = self.out_packet.len();
-        if self.out_packet.capacity() - start < msg_len as usize + 2 {
-            self.flush().await?;
+        if self.out_packet.capacity().saturating_sub(start) < msg_len as usize + 2 {
+            // Only messages which would otherwise wait for bandwidth are shed, messages too big for
+            // any packet still get `SendError::TooBig`.
+            let shed = self.shed_policy != ShedPolicy::Queue
+                && self.out_packet.capacity() >= msg_len as usize + 2
+                && self.is_saturated();
+            match self.shed_policy {
+                ShedPolicy::DropNewest if shed => {
+                    self.shed += 1;
+                    debug_event!(len = msg_len, "shedding unreliable message");
+                    return Poll::Ready(Ok(()));
+                }
+                ShedPolicy::DropStale if shed => {
+                    self.shed += self.out_messages;
+                    debug_event!(
+                        messages = self.out_messages,
+                        "shedding stale unreliable messages"
+                    );
+                    self.out_packet.clear();
+                    self.out_messages = 0;
+                }
+                _ => ready!(self.poll_flush(cx))?,
+            }
 
             if self.out_packet.capacity() < msg_len as usize + 2 {
-                return Err(SendError::TooBig);
+                debug_event!(len = msg_len, "unreliable message too big");
+                return Poll::Ready(Err(SendError::TooBig));
             }
         }
 
         let mut len = [0; 2];
         LittleEndian::write_u16(&mut len, msg_len);
         self.out_packet.extend(&len);
-        self.out_packet.extend(&msg);
+        self.out_packet.extend(msg);
+        self.out_messages += 1;
+        if let Some(limiter) = &mut self.message_limiter {
+            limiter.take_bytes(1);
+        }
+        if self.tick_budget.is_some() {
+            self.tick_window.take(cost);
+        }
 
-        Ok(())
+        Poll::Ready(Ok(()))
     }
 
     /// Finish sending any unsent coalesced packets.
     ///
     /// This *must* be called to guarantee that any sent messages are actually sent to the outgoing
-    /// packet stream.
+    /// packet stream, unless the overflow policy drops them.
     ///
     /// This method is cancel safe.
     pub async fn flush(&mut self) -> Result<(), SendError> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /// The poll form of `UnreliableChannel::flush`.  Any progress made is kept after
+    /// `Poll::Pending`, so a later call continues where this one left off.
+    pub fn poll_flush(&mut self, cx: &mut Context) -> Poll<Result<(), SendError>> {
+        if self.overflow_policy != OverflowPolicy::Wait {
+            return self.poll_flush_without_waiting(cx);
+        }
+
         if !self.out_packet.is_empty() {
             self.bandwidth_limiter.update_available();
-            self.bandwidth_limiter.delay_until_available().await;
+            ready!(self.poll_bandwidth(cx));
 
-            poll_fn(|cx| self.outgoing_packets.poll_ready(cx))
-                .await
+            ready!(Pin::new(&mut self.outgoing_packets).poll_ready(cx))
                 .map_err(|_| SendError::Disconnected)?;
             let out_packet = mem::replace(&mut self.out_packet, self.packet_pool.acquire());
-            self.bandwidth_limiter.take_bytes(out_packet.len() as u32);
-            self.outgoing_packets
-                .start_send(out_packet)
-                .map_err(|_| SendError::Disconnected)?;
+            self.out_messages = 0;
+            trace_event!(len = out_packet.len(), "sending unreliable packet");
+            self.start_send(out_packet)?;
         }
 
-        Ok(())
+        // Always flush the outgoing sink, even with no new packet, in case a previous flush was
+        // canceled after the packet was handed to the sink.
+        ready!(Pin::new(&mut self.outgoing_packets).poll_flush(cx))
+            .map_err(|_| SendError::Disconnected)?;
+
+        Poll::Ready(Ok(()))
+    }
+
+    // Flush with a dropping overflow policy, where the sink is only ever polled once.
+    fn poll_flush_without_waiting(&mut self, cx: &mut Context) -> Poll<Result<(), SendError>> {
+        if !self.out_packet.is_empty() {
+            self.bandwidth_limiter.update_available();
+            ready!(self.poll_bandwidth(cx));
+        }
+
+        if let Some(held_packet) = self.held_packet.take() {
+            if self.poll_outgoing_ready()? {
+                trace_event!(len = held_packet.len(), "sending held unreliable packet");
+                self.start_send(held_packet)?;
+            } else if self.out_packet.is_empty() {
+                self.held_packet = Some(held_packet);
+            } else {
+                debug_event!("outgoing packets are full, dropping held unreliable packet");
+                self.overflowed += 1;
+            }
+        }
+
+        if !self.out_packet.is_empty() {
+            let out_packet = mem::replace(&mut self.out_packet, self.packet_pool.acquire());
+            self.out_messages = 0;
+            if self.poll_outgoing_ready()? {
+                trace_event!(len = out_packet.len(), "sending unreliable packet");
+                self.start_send(out_packet)?;
+            } else if self.overflow_policy == OverflowPolicy::DropOldest {
+                self.held_packet = Some(out_packet);
+            } else {
+                debug_event!("outgoing packets are full, dropping unreliable packet");
+                self.overflowed += 1;
+            }
+        }
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        if let Poll::Ready(Err(_)) = Pin::new(&mut self.outgoing_packets).poll_flush(&mut cx) {
+            return Poll::Ready(Err(SendError::Disconnected));
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    // Wait until there is bandwidth available, keeping the same sleep across polls.
+    fn poll_bandwidth(&mut self, cx: &mut Context) -> Poll<()> {
+        let delay = match self.bandwidth_limiter.delay() {
+            Some(delay) => delay,
+            None => {
+                self.bandwidth_sleep = None;
+                return Poll::Ready(());
+            }
+        };
+        let timer = self.bandwidth_limiter.timer();
+        let sleep = self
+            .bandwidth_sleep
+            .get_or_insert_with(|| Box::pin(timer.sleep(delay)));
+        ready!(sleep.as_mut().poll(cx));
+        self.bandwidth_sleep = None;
+        Poll::Ready(())
+    }
+
+    // Wait until the messages / sec requested by the remote allow another message.
+    fn poll_message_rate(&mut self, cx: &mut Context) -> Poll<()> {
+        let delay = match self.message_limiter.as_ref().and_then(|l| l.delay()) {
+            Some(delay) => delay,
+            None => {
+                self.message_sleep = None;
+                return Poll::Ready(());
+            }
+        };
+        let timer = self.bandwidth_limiter.timer();
+        let sleep = self
+            .message_sleep
+            .get_or_insert_with(|| Box::pin(timer.sleep(delay)));
+        ready!(sleep.as_mut().poll(cx));
+        self.message_sleep = None;
+        Poll::Ready(())
+    }
+
+    // Wait for the next tick of the tick budget.
+    fn poll_next_tick(&mut self, cx: &mut Context, budget: &TickBudget) -> Poll<()> {
+        let window = &self.tick_window;
+        let sleep = self
+            .tick_sleep
+            .get_or_insert_with(|| Box::pin(window.timer().sleep(window.delay(budget))));
+        ready!(sleep.as_mut().poll(cx));
+        self.tick_sleep = None;
+        self.tick_window.update(budget);
+        Poll::Ready(())
+    }
+
+    fn poll_outgoing_ready(&mut self) -> Result<bool, SendError> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match Pin::new(&mut self.outgoing_packets).poll_ready(&mut cx) {
+            Poll::Ready(Ok(())) => Ok(true),
+            Poll::Ready(Err(_)) => Err(SendError::Disconnected),
+            Poll::Pending => Ok(false),
+        }
+    }
+
+    fn start_send(&mut self, packet: P::Packet) -> Result<(), SendError> {
+        self.bandwidth_limiter.take_bytes(packet.len() as u32);
+        Pin::new(&mut self.outgoing_packets)
+            .start_send(packet)
+            .map_err(|_| SendError::Disconnected)
     }
 
     /// Receive a message into the provide buffer.
@@ -151,23 +586,101 @@ where
     /// This method is cancel safe, it will never partially read a message or drop received
     /// messages.
     pub async fn recv(&mut self) -> Result<&[u8], RecvError> {
-        if let Some((packet, in_pos)) = &self.in_packet {
+        Ok(self.recv_timed().await?.0)
+    }
+
+    /// The poll form of `UnreliableChannel::recv`, which is cancel safe in the same way.
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Result<&[u8], RecvError>> {
+        ready!(self.poll_in_packet(cx))?;
+        Poll::Ready(self.read_message().map(|(msg, _)| msg))
+    }
+
+    /// Like `UnreliableChannel::recv`, but also returns the time at which the packet containing
+    /// the message was received.
+    ///
+    /// Packets are stamped with the time they are taken from the incoming packet stream, so this
+    /// is only the packet's arrival time when the channel is received from as soon as packets are
+    /// available, which is always true of channels driven by their own task (such as those of
+    /// `MessageChannels`).  Every message coalesced into the same packet has the same receive
+    /// time.
+    ///
+    /// This method is cancel safe in the same way as `UnreliableChannel::recv`.
+    pub async fn recv_timed(&mut self) -> Result<(&[u8], R::Instant), RecvError> {
+        poll_fn(|cx| self.poll_in_packet(cx)).await?;
+        self.read_message()
+    }
+
+    /// The metadata of the packet containing the most recently received message, see
+    /// `Packet::metadata`.
+    ///
+    /// Once every message in that packet has been received, this stays available until the next
+    /// call to receive.
+    pub fn metadata(&self) -> Option<&PacketMetadata> {
+        self.in_packet.as_ref()?.0.metadata()
+    }
+
+    // Make sure there is a packet being read with messages left in it.
+    fn poll_in_packet(&mut self, cx: &mut Context) -> Poll<Result<(), RecvError>> {
+        if let Some((packet, in_pos, _)) = &self.in_packet {
             if *in_pos == packet.len() {
                 self.in_packet = None;
             }
         }
 
-        if self.in_packet.is_none() {
-            let packet = self
-                .incoming_packets
-                .next()
-                .await
+        while self.in_packet.is_none() {
+            let packet = ready!(Pin::new(&mut self.incoming_packets).poll_next(cx))
                 .ok_or(RecvError::Disconnected)?;
-            self.in_packet = Some((packet, 0));
+            if packet.len() == RATE_REQUEST_LEN
+                && LittleEndian::read_u16(&packet[0..2]) == RATE_REQUEST
+            {
+                self.set_remote_rate(RateLimit {
+                    bytes_per_sec: Some(LittleEndian::read_u32(&packet[2..6])).filter(|&r| r != 0),
+                    messages_per_sec: Some(LittleEndian::read_u32(&packet[6..10]))
+                        .filter(|&r| r != 0),
+                });
+                continue;
+            }
+            let received = self.bandwidth_limiter.timer().now();
+            self.in_packet = Some((packet, 0, received));
         }
-        let (packet, in_pos) = self.in_packet.as_mut().unwrap();
+        Poll::Ready(Ok(()))
+    }
+
+    fn set_remote_rate(&mut self, rate: RateLimit) {
+        if rate == self.remote_rate {
+            return;
+        }
+        trace_event!(
+            bytes_per_sec = rate.bytes_per_sec,
+            messages_per_sec = rate.messages_per_sec,
+            "remote requested unreliable rate"
+        );
+        self.remote_rate = rate;
+
+        let bandwidth = match rate.bytes_per_sec {
+            Some(bytes_per_sec) => bytes_per_sec.min(self.bandwidth),
+            None => self.bandwidth,
+        };
+        self.bandwidth_limiter.set_bandwidth(bandwidth);
+
+        // A limiter lets a message through whenever its credit is not negative, so a burst of one
+        // less than the rate lets through a second's worth of messages at once.
+        self.message_limiter = rate.messages_per_sec.map(|messages_per_sec| {
+            let timer = self.bandwidth_limiter.timer().clone();
+            BandwidthLimiter::new(timer, messages_per_sec, messages_per_sec - 1)
+        });
+        self.message_sleep = None;
+    }
+
+    // Read the next message from the packet being read.
+    fn read_message(&mut self) -> Result<(&[u8], R::Instant), RecvError> {
+        let (packet, in_pos, received) = self.in_packet.as_mut().unwrap();
 
         if *in_pos + 2 > packet.len() {
+            debug_event!(
+                dropped = packet.len() - *in_pos,
+                "dropping malformed unreliable packet"
+            );
             *in_pos = packet.len();
             return Err(RecvError::BadFormat);
         }
@@ -175,6 +688,10 @@ where
         *in_pos += 2;
 
         if *in_pos + length > packet.len() {
+            debug_event!(
+                dropped = packet.len() - *in_pos,
+                "dropping malformed unreliable packet"
+            );
             *in_pos = packet.len();
             return Err(RecvError::BadFormat);
         }
@@ -182,6 +699,6 @@ where
         let msg = &packet[*in_pos..*in_pos + length];
         *in_pos += length;
 
-        Ok(msg)
+        Ok((msg, *received))
     }
 }
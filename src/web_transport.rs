@@ -0,0 +1,253 @@
+use byteorder::{ByteOrder, LittleEndian};
+use futures::{
+    channel::mpsc,
+    future::{self, Either},
+    pin_mut, select, FutureExt, SinkExt, StreamExt,
+};
+use rustc_hash::FxHashSet;
+use thiserror::Error;
+use wtransport::{
+    error::{ConnectionError, SendDatagramError, StreamReadExactError, StreamWriteError},
+    Connection, RecvStream, SendStream,
+};
+
+use crate::{
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{IncomingMultiplexedPackets, OutgoingMultiplexedPackets, PacketChannel},
+};
+
+#[derive(Debug, Error)]
+pub enum WebTransportError {
+    /// Datagrams are not supported by the remote peer.
+    #[error("WebTransport datagrams are unsupported on this connection")]
+    DatagramsUnsupported,
+    #[error("WebTransport connection error: {0}")]
+    Connection(#[from] ConnectionError),
+    #[error("WebTransport stream read error: {0}")]
+    StreamRead(#[from] StreamReadExactError),
+    #[error("WebTransport stream write error: {0}")]
+    StreamWrite(#[from] StreamWriteError),
+    /// The remote sent a packet on the reliable stream which is larger than the packet capacity.
+    #[error("stream packet exceeds packet capacity")]
+    StreamPacketTooLarge,
+}
+
+/// Sends and receives turbulence packets over a WebTransport session.
+///
+/// By default every packet is sent as a WebTransport datagram, which are unreliable and unordered,
+/// so all of turbulence's channel types work exactly as they do over raw UDP.  Optionally, the
+/// packets for a chosen set of multiplexer channels can instead be sent over a bidirectional
+/// WebTransport stream with `WebTransportPackets::with_stream`, which is reliable and ordered.
+///
+/// The packet pool should produce packets no larger than `max_datagram_size`, datagrams that are
+/// too large for the connection to currently send are dropped, and received datagrams larger than
+/// the packet capacity are truncated.
+pub struct WebTransportPackets<P> {
+    connection: Connection,
+    packet_pool: P,
+    stream: Option<PacketStream>,
+}
+
+struct PacketStream {
+    send: SendStream,
+    recv: RecvStream,
+    channels: FxHashSet<PacketChannel>,
+    buffer_size: usize,
+}
+
+impl<P> WebTransportPackets<P>
+where
+    P: PacketPool,
+{
+    /// Returns `WebTransportError::DatagramsUnsupported` if the established connection cannot
+    /// send datagrams.
+    pub fn new(
+        connection: Connection,
+        packet_pool: P,
+    ) -> Result<WebTransportPackets<P>, WebTransportError> {
+        if connection.max_datagram_size().is_none() {
+            return Err(WebTransportError::DatagramsUnsupported);
+        }
+        Ok(WebTransportPackets {
+            connection,
+            packet_pool,
+            stream: None,
+        })
+    }
+
+    /// Send the packets for the given multiplexer channels over the given bidirectional stream
+    /// rather than as datagrams.
+    ///
+    /// One side should open the stream with `Connection::open_bi` and the other should accept it
+    /// with `Connection::accept_bi`, and both sides must agree on the set of stream channels.
+    /// Every packet on the stream is prefixed with its length as a `u16`.
+    ///
+    /// Stream packets are buffered in up to `buffer_size` packets while they are waiting to be
+    /// written, once this buffer is full, sending any packet will wait for stream flow control.
+    ///
+    /// Since the stream is already reliable, this is most useful for channels which are themselves
+    /// reliable, like `ReliableChannel`, which then rarely need to resend anything.  Unreliable
+    /// channels should remain on datagrams, as the stream will stall on packet loss.
+    pub fn with_stream(
+        mut self,
+        stream: (SendStream, RecvStream),
+        channels: impl IntoIterator<Item = PacketChannel>,
+        buffer_size: usize,
+    ) -> Self {
+        self.stream = Some(PacketStream {
+            send: stream.0,
+            recv: stream.1,
+            channels: channels.into_iter().collect(),
+            buffer_size,
+        });
+        self
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The largest datagram that may currently be sent, this can change over the lifetime of the
+    /// connection as the path MTU is discovered.
+    pub fn max_datagram_size(&self) -> Option<usize> {
+        self.connection.max_datagram_size()
+    }
+
+    /// Forward packets between this session and a started `PacketMultiplexer` until the
+    /// connection is closed or the multiplexer is dropped.
+    ///
+    /// Incoming packets are delivered with `IncomingMultiplexedPackets::try_send`, so packets for
+    /// unknown channels or for channels whose buffers are full are dropped rather than blocking
+    /// other channels.
+    pub async fn run_multiplexer(
+        self,
+        incoming: IncomingMultiplexedPackets<P::Packet>,
+        mut outgoing: OutgoingMultiplexedPackets<P::Packet>,
+    ) -> Result<(), WebTransportError>
+    where
+        P::Packet: Unpin,
+    {
+        let WebTransportPackets {
+            connection,
+            packet_pool,
+            stream,
+        } = self;
+
+        let (incoming_sender, mut incoming_receiver) = mpsc::channel(1);
+        let (channels, stream_sender, stream_recv, stream_send) = match stream {
+            Some(stream) => {
+                let (stream_sender, stream_receiver) = mpsc::channel(stream.buffer_size);
+                (
+                    stream.channels,
+                    Some(stream_sender),
+                    Either::Left(recv_stream(
+                        stream.recv,
+                        &packet_pool,
+                        incoming_sender.clone(),
+                    )),
+                    Either::Left(send_stream(stream.send, stream_receiver)),
+                )
+            }
+            None => (
+                FxHashSet::default(),
+                None,
+                Either::Right(future::pending()),
+                Either::Right(future::pending()),
+            ),
+        };
+
+        let recv_datagrams = async {
+            let mut incoming_sender = incoming_sender;
+            loop {
+                let datagram = connection.receive_datagram().await?;
+                let mut packet = packet_pool.acquire();
+                let len = datagram.len().min(packet.capacity());
+                packet.extend(&datagram[..len]);
+                if incoming_sender.send(packet).await.is_err() {
+                    return Ok(());
+                }
+            }
+        };
+
+        let deliver = async {
+            let mut incoming = incoming;
+            while let Some(packet) = incoming_receiver.next().await {
+                let _ = incoming.try_send(packet);
+            }
+            Ok(())
+        };
+
+        let send = async {
+            let mut stream_sender = stream_sender;
+            while let Some(packet) = outgoing.next().await {
+                match stream_sender.as_mut() {
+                    Some(stream_sender) if channels.contains(&packet[0]) => {
+                        if stream_sender.send(packet).await.is_err() {
+                            break;
+                        }
+                    }
+                    _ => match connection.send_datagram(&packet[..]) {
+                        Ok(()) | Err(SendDatagramError::TooLarge) => {}
+                        Err(SendDatagramError::NotConnected) => break,
+                        Err(SendDatagramError::UnsupportedByPeer) => {
+                            return Err(WebTransportError::DatagramsUnsupported)
+                        }
+                    },
+                }
+            }
+            Ok(())
+        };
+
+        let recv_datagrams = recv_datagrams.fuse();
+        let deliver = deliver.fuse();
+        let send = send.fuse();
+        let stream_recv = stream_recv.fuse();
+        let stream_send = stream_send.fuse();
+        pin_mut!(recv_datagrams, deliver, send, stream_recv, stream_send);
+
+        select! {
+            res = recv_datagrams => res,
+            res = deliver => res,
+            res = send => res,
+            res = stream_recv => res,
+            res = stream_send => res,
+        }
+    }
+}
+
+async fn recv_stream<P: PacketPool>(
+    mut recv: RecvStream,
+    packet_pool: &P,
+    mut incoming: mpsc::Sender<P::Packet>,
+) -> Result<(), WebTransportError> {
+    let mut header = [0; 2];
+    loop {
+        recv.read_exact(&mut header).await?;
+        let len = LittleEndian::read_u16(&header) as usize;
+
+        let mut packet = packet_pool.acquire();
+        if len > packet.capacity() {
+            return Err(WebTransportError::StreamPacketTooLarge);
+        }
+        packet.resize(len, 0);
+        recv.read_exact(&mut packet).await?;
+
+        if incoming.send(packet).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn send_stream<P: Packet>(
+    mut send: SendStream,
+    mut outgoing: mpsc::Receiver<P>,
+) -> Result<(), WebTransportError> {
+    let mut header = [0; 2];
+    while let Some(packet) = outgoing.next().await {
+        LittleEndian::write_u16(&mut header, packet.len() as u16);
+        send.write_all(&header).await?;
+        send.write_all(&packet).await?;
+    }
+    send.finish().await?;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use futures::{channel::mpsc, future, pin_mut, StreamExt};
+use js_sys::{ArrayBuffer, Uint8Array};
+use thiserror::Error;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use web_sys::{Event, MessageEvent, RtcDataChannel, RtcDataChannelState, RtcDataChannelType};
+
+use crate::{
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{IncomingMultiplexedPackets, OutgoingMultiplexedPackets},
+};
+
+#[derive(Debug, Error)]
+pub enum RtcDataChannelError {
+    #[error("data channel is closed")]
+    Closed,
+    #[error("data channel send failed: {0:?}")]
+    Send(JsValue),
+}
+
+/// Sends and receives turbulence packets as binary messages on a browser `RTCDataChannel`.
+///
+/// The data channel should be created as unordered with no retransmits (`{ ordered: false,
+/// maxRetransmits: 0 }`), so that it behaves like UDP and turbulence's reliable channels do not
+/// stall behind the data channel's own retransmission.  Since every message is a single packet,
+/// the packet pool should produce packets no larger than the maximum message size of the
+/// connection, received messages larger than the packet capacity are truncated.
+///
+/// The native peer only needs to deliver each data channel message as a packet to its own
+/// `PacketMultiplexer`, so both sides can share the same turbulence channel definitions.
+///
+/// Received messages are buffered in the given number of packets, and messages that arrive while
+/// the buffer is full are dropped.  The message handlers installed on the data channel are removed
+/// when this is dropped.
+pub struct RtcDataChannelPackets<P: PacketPool> {
+    channel: RtcDataChannel,
+    incoming: mpsc::Receiver<P::Packet>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(Event)>,
+}
+
+impl<P> RtcDataChannelPackets<P>
+where
+    P: PacketPool + 'static,
+{
+    pub fn new(
+        channel: RtcDataChannel,
+        packet_pool: P,
+        buffer_size: usize,
+    ) -> RtcDataChannelPackets<P> {
+        channel.set_binary_type(RtcDataChannelType::Arraybuffer);
+
+        let (incoming_sender, incoming) = mpsc::channel(buffer_size);
+
+        let on_message = {
+            let mut incoming_sender = incoming_sender.clone();
+            Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                if let Ok(buffer) = event.data().dyn_into::<ArrayBuffer>() {
+                    let data = Uint8Array::new(&buffer);
+                    let mut packet = packet_pool.acquire();
+                    let len = (data.length() as usize).min(packet.capacity());
+                    packet.resize(len, 0);
+                    data.subarray(0, len as u32).copy_to(&mut packet);
+                    let _ = incoming_sender.try_send(packet);
+                }
+            })
+        };
+        channel.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let on_close = {
+            let mut incoming_sender = incoming_sender;
+            Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                incoming_sender.close_channel();
+            })
+        };
+        channel.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        RtcDataChannelPackets {
+            channel,
+            incoming,
+            _on_message: on_message,
+            _on_close: on_close,
+        }
+    }
+
+    pub fn channel(&self) -> &RtcDataChannel {
+        &self.channel
+    }
+
+    /// Send a single packet as a data channel message.
+    ///
+    /// Packets sent before the data channel is open are dropped.
+    pub fn send(&self, packet: &[u8]) -> Result<(), RtcDataChannelError> {
+        send_packet(&self.channel, packet)
+    }
+
+    /// Receive a single message as a packet, returns `None` once the data channel is closed.
+    pub async fn recv(&mut self) -> Option<P::Packet> {
+        self.incoming.next().await
+    }
+
+    /// Forward packets between this data channel and a started `PacketMultiplexer` until the data
+    /// channel is closed or the multiplexer is dropped.
+    ///
+    /// Incoming packets are delivered with `IncomingMultiplexedPackets::try_send`, so packets for
+    /// unknown channels or for channels whose buffers are full are dropped rather than blocking
+    /// other channels.
+    pub async fn run_multiplexer(
+        mut self,
+        mut incoming: IncomingMultiplexedPackets<P::Packet>,
+        mut outgoing: OutgoingMultiplexedPackets<P::Packet>,
+    ) -> Result<(), RtcDataChannelError>
+    where
+        P::Packet: Unpin,
+    {
+        let channel = &self.channel;
+        let channel_incoming = &mut self.incoming;
+
+        let incoming = async {
+            while let Some(packet) = channel_incoming.next().await {
+                let _ = incoming.try_send(packet);
+            }
+            Err(RtcDataChannelError::Closed)
+        };
+        let outgoing = async {
+            while let Some(packet) = outgoing.next().await {
+                send_packet(channel, &packet)?;
+            }
+            Ok(())
+        };
+        pin_mut!(incoming, outgoing);
+
+        future::select(incoming, outgoing).await.factor_first().0
+    }
+}
+
+impl<P: PacketPool> Drop for RtcDataChannelPackets<P> {
+    fn drop(&mut self) {
+        self.channel.set_onmessage(None);
+        self.channel.set_onclose(None);
+    }
+}
+
+fn send_packet(channel: &RtcDataChannel, packet: &[u8]) -> Result<(), RtcDataChannelError> {
+    match channel.ready_state() {
+        RtcDataChannelState::Connecting => Ok(()),
+        RtcDataChannelState::Open => channel
+            .send_with_u8_array(packet)
+            .map_err(RtcDataChannelError::Send),
+        _ => Err(RtcDataChannelError::Closed),
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cmp::Ordering, collections::VecDeque, num::Wrapping, u32};
+use std::{cmp::Ordering, collections::VecDeque, num::Wrapping};
 
 pub type StreamPos = Wrapping<u32>;
 
@@ -111,8 +111,8 @@ impl SendWindow {
         if send_amt == 0 {
             None
         } else {
-            for i in 0..send_amt as usize {
-                data[i] = self.buffer[i + self.sent as usize];
+            for (i, d) in data[0..send_amt as usize].iter_mut().enumerate() {
+                *d = self.buffer[i + self.sent as usize];
             }
             let start = self.send_pos;
             let end = start + Wrapping(send_amt);
@@ -137,8 +137,8 @@ impl SendWindow {
     pub fn get_unacked(&self, start: StreamPos, data: &mut [u8]) {
         let unacked_start = self.unacked_start();
         let buf_start = (start - unacked_start).0 as usize;
-        for i in 0..data.len() {
-            data[i] = self.buffer[buf_start + i];
+        for (i, d) in data.iter_mut().enumerate() {
+            *d = self.buffer[buf_start + i];
         }
     }
 
@@ -239,8 +239,8 @@ impl RecvWindow {
     /// read.
     pub fn read(&mut self, data: &mut [u8]) -> usize {
         let read_amt = data.len().min(self.ready as usize);
-        for i in 0..read_amt {
-            data[i] = self.buffer.pop_front().unwrap();
+        for d in &mut data[0..read_amt] {
+            *d = self.buffer.pop_front().unwrap();
         }
         self.ready -= read_amt as u32;
         read_amt
@@ -277,7 +277,7 @@ impl RecvWindow {
 
         // `recv_end_pos` is the stream position at the end of the maximum capacity of the receive
         // buffer.
-        let recv_end_pos = self.recv_pos + Wrapping(self.capacity as u32 - self.ready);
+        let recv_end_pos = self.recv_pos + Wrapping(self.capacity - self.ready);
 
         // `end_pos` is the stream position at the end of the input data
         let end_pos = start_pos + Wrapping(data.len() as u32);
@@ -403,11 +403,10 @@ impl RecvWindow {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_range_loop)]
 mod tests {
     use super::*;
 
-    use std::u32;
-
     #[test]
     fn test_send_window() {
         let stream_start = Wrapping(u32::MAX - 11);
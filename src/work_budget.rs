@@ -0,0 +1,127 @@
+use std::{
+    ops::Deref,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::Stream;
+
+/// Caps the work a task does on a burst of incoming packets before yielding to the executor.
+///
+/// A task which handles packets as fast as they arrive never returns `Poll::Pending` while packets
+/// are queued, so on a single threaded executor one connection receiving a burst can starve every
+/// other connection.  A budget makes the packet stream return `Poll::Pending` once the given
+/// number of packets or bytes have been yielded in a row, after immediately waking the task, so
+/// the executor can run other tasks before the task continues.
+///
+/// At least one packet is always handled between yields, however small the budget.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorkBudget {
+    /// The maximum packets handled between yields.
+    pub max_packets: u32,
+    /// The maximum packet bytes handled between yields.
+    pub max_bytes: u32,
+}
+
+// The budget shared between a `Cooperative` and a handle which may change it, such as the
+// `ReliableChannel` whose task owns the stream.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WorkBudgetSlot(Arc<Mutex<Option<WorkBudget>>>);
+
+impl WorkBudgetSlot {
+    pub(crate) fn set(&self, budget: Option<WorkBudget>) {
+        *self.0.lock().unwrap() = budget;
+    }
+
+    pub(crate) fn get(&self) -> Option<WorkBudget> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Wraps a `Stream` of packets so that it yields to the executor whenever it exhausts a
+/// `WorkBudget`.
+///
+/// The work counted resets whenever the inner stream has no packet ready, or the budget forces a
+/// yield.  Any packet stream can be wrapped, such as the receiver of a `PacketMultiplexer` channel,
+/// or the stream of packets from a socket.
+#[derive(Debug)]
+pub struct Cooperative<S> {
+    inner: S,
+    budget: WorkBudgetSlot,
+    packets: u32,
+    bytes: u32,
+}
+
+impl<S> Cooperative<S> {
+    pub fn new(inner: S, budget: Option<WorkBudget>) -> Self {
+        let slot = WorkBudgetSlot::default();
+        slot.set(budget);
+        Cooperative::with_slot(inner, slot)
+    }
+
+    pub(crate) fn with_slot(inner: S, budget: WorkBudgetSlot) -> Self {
+        Cooperative {
+            inner,
+            budget,
+            packets: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn budget(&self) -> Option<WorkBudget> {
+        self.budget.get()
+    }
+
+    /// Change the budget, or remove it with `None`.  Work already counted is kept.
+    pub fn set_budget(&mut self, budget: Option<WorkBudget>) {
+        self.budget.set(budget);
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Stream for Cooperative<S>
+where
+    S: Stream + Unpin,
+    S::Item: Deref<Target = [u8]>,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some(budget) = self.budget.get() {
+            if self.packets > 0
+                && (self.packets >= budget.max_packets || self.bytes >= budget.max_bytes)
+            {
+                trace_event!(
+                    packets = self.packets,
+                    bytes = self.bytes,
+                    "work budget exhausted, yielding"
+                );
+                self.packets = 0;
+                self.bytes = 0;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(Some(packet)) => {
+                self.packets = self.packets.saturating_add(1);
+                self.bytes = self
+                    .bytes
+                    .saturating_add(packet.len().min(u32::MAX as usize) as u32);
+                Poll::Ready(Some(packet))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                self.packets = 0;
+                self.bytes = 0;
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use futures::{channel::mpsc, executor::block_on, FutureExt};
+
+use turbulence::{
+    acked_channel::{AckEvent, AckedChannel, MessageId, Settings},
+    buffer::{BufferPacket, BufferPacketPool},
+    packet::{Packet, PacketPool},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime, SimpleRuntimeHandle};
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 4096,
+    loss_timeout: Duration::from_millis(500),
+};
+
+type TestPacket = BufferPacket<Box<[u8]>>;
+type TestChannel = AckedChannel<SimpleRuntimeHandle, BufferPacketPool<SimpleBufferPool>>;
+
+// One end of a link whose packets are only delivered by calling `deliver` or `drop_next`.
+struct End {
+    channel: TestChannel,
+    outgoing: mpsc::Receiver<TestPacket>,
+    incoming: mpsc::Sender<TestPacket>,
+}
+
+impl End {
+    fn send(&mut self, msgs: &[&[u8]]) -> Vec<MessageId> {
+        let ids = msgs
+            .iter()
+            .map(|msg| block_on(self.channel.send(msg)).unwrap())
+            .collect();
+        block_on(self.channel.flush()).unwrap();
+        ids
+    }
+
+    // Deliver every sent packet to the other end.
+    fn deliver(&mut self, to: &mut End) {
+        while let Ok(packet) = self.outgoing.try_recv() {
+            to.incoming.try_send(packet).unwrap();
+        }
+    }
+
+    fn drop_next(&mut self) {
+        self.outgoing.try_recv().unwrap();
+    }
+
+    fn recv_all(&mut self) -> Vec<Vec<u8>> {
+        let mut msgs = Vec::new();
+        while let Some(msg) = self.channel.recv().now_or_never() {
+            msgs.push(msg.unwrap().to_vec());
+        }
+        msgs
+    }
+
+    fn events(&mut self) -> Vec<AckEvent> {
+        std::iter::from_fn(|| self.channel.next_event()).collect()
+    }
+}
+
+fn link(runtime: &SimpleRuntime) -> (End, End) {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let (a_incoming, a_recv) = mpsc::channel(64);
+    let (a_send, a_outgoing) = mpsc::channel(64);
+    let (b_incoming, b_recv) = mpsc::channel(64);
+    let (b_send, b_outgoing) = mpsc::channel(64);
+    (
+        End {
+            channel: AckedChannel::new(runtime.handle(), pool, SETTINGS, a_recv, a_send),
+            outgoing: a_outgoing,
+            incoming: a_incoming,
+        },
+        End {
+            channel: AckedChannel::new(runtime.handle(), pool, SETTINGS, b_recv, b_send),
+            outgoing: b_outgoing,
+            incoming: b_incoming,
+        },
+    )
+}
+
+#[test]
+fn test_acked_events() {
+    let runtime = SimpleRuntime::new();
+    let (mut a, mut b) = link(&runtime);
+
+    assert_eq!(a.send(&[b"one", b"two"]), vec![0, 1]);
+    assert_eq!(a.send(&[b"three"]), vec![2]);
+    a.drop_next();
+    a.deliver(&mut b);
+    assert_eq!(b.recv_all(), vec![b"three".to_vec()]);
+    assert_eq!(a.channel.in_flight(), 2);
+
+    // Flushing with no messages still sends the acknowledgements.
+    b.send(&[]);
+    b.deliver(&mut a);
+    assert!(a.recv_all().is_empty());
+    assert_eq!(
+        a.events(),
+        vec![AckEvent::Acked {
+            packet: 1,
+            messages: 2..3
+        }]
+    );
+    assert_eq!(a.channel.in_flight(), 1);
+
+    // Acknowledgement-only packets are not themselves acknowledged.
+    b.send(&[]);
+    assert!(b.outgoing.try_recv().is_err());
+    assert!(b.events().is_empty());
+}
+
+#[test]
+fn test_acked_window_loss() {
+    let runtime = SimpleRuntime::new();
+    let (mut a, mut b) = link(&runtime);
+
+    a.send(&[b"lost"]);
+    a.drop_next();
+    for i in 1..=32u8 {
+        a.send(&[&[i]]);
+    }
+    a.deliver(&mut b);
+    assert_eq!(b.recv_all().len(), 32);
+    b.send(&[]);
+    b.deliver(&mut a);
+    a.recv_all();
+    // The lost packet is just inside the ack window, so it may still be acknowledged.
+    assert_eq!(a.events().len(), 32);
+    assert_eq!(a.channel.in_flight(), 1);
+
+    a.send(&[b"next"]);
+    a.deliver(&mut b);
+    b.recv_all();
+    b.send(&[]);
+    b.deliver(&mut a);
+    a.recv_all();
+    assert_eq!(
+        a.events(),
+        vec![
+            AckEvent::Lost {
+                packet: 0,
+                messages: 0..1
+            },
+            AckEvent::Acked {
+                packet: 33,
+                messages: 33..34
+            }
+        ]
+    );
+}
+
+#[test]
+fn test_acked_timeout_loss() {
+    let mut runtime = SimpleRuntime::new();
+    let (mut a, _b) = link(&runtime);
+
+    a.send(&[b"a", b"b"]);
+    runtime.advance_time(400);
+    assert!(a.events().is_empty());
+    runtime.advance_time(100);
+    assert_eq!(
+        a.events(),
+        vec![AckEvent::Lost {
+            packet: 0,
+            messages: 0..2
+        }]
+    );
+}
+
+#[test]
+fn test_acked_duplicates() {
+    let runtime = SimpleRuntime::new();
+    let (mut a, mut b) = link(&runtime);
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+
+    a.send(&[b"once"]);
+    let packet = a.outgoing.try_recv().unwrap();
+    for _ in 0..2 {
+        let mut copy = pool.acquire();
+        copy.extend(&packet);
+        b.incoming.try_send(copy).unwrap();
+    }
+    assert_eq!(b.recv_all(), vec![b"once".to_vec()]);
+}
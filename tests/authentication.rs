@@ -0,0 +1,222 @@
+#![cfg(feature = "authentication")]
+
+use futures::{channel::mpsc, executor::block_on, SinkExt, StreamExt};
+
+use turbulence::{
+    authentication::{
+        authenticated, AuthenticatedPacketPool, Secrets, Signer, Verifier, VerifyError,
+    },
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    runtime::Runtime,
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+const SECRET: &[u8] = b"a pre-shared secret of some length";
+const OTHER_SECRET: &[u8] = b"the pre-shared secret for the other direction";
+
+fn secrets(send: &[u8], recv: &[u8]) -> Secrets {
+    Secrets {
+        send: send.to_vec(),
+        recv: recv.to_vec(),
+    }
+}
+
+#[test]
+fn test_sign_verify() {
+    let pool = AuthenticatedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)));
+    let mut signer = Signer::new(SECRET);
+    let mut verifier = Verifier::new(SECRET);
+
+    let mut packet = pool.acquire();
+    assert_eq!(packet.capacity(), 40);
+    packet.extend(b"hello");
+    let signed = signer.sign(packet);
+    assert_eq!(signed.len(), 5 + 24);
+    // Authentication does not hide the contents.
+    assert_eq!(&signed[8..13], b"hello");
+
+    let (sequence, verified) = verifier.verify(signed).unwrap();
+    assert_eq!(sequence, 0);
+    assert_eq!(&verified[..], b"hello");
+
+    // Tampering with the sequence number, the contents or the tag, or verifying with the wrong
+    // secret, must fail.
+    for i in [1, 9, 15] {
+        let mut packet = pool.acquire();
+        packet.extend(b"hello");
+        let mut signed = signer.sign(packet);
+        signed[i] ^= 1;
+        assert!(matches!(
+            verifier.verify(signed),
+            Err(VerifyError::Unauthenticated)
+        ));
+    }
+
+    let mut packet = pool.acquire();
+    packet.extend(b"hello");
+    let signed = signer.sign(packet);
+    assert!(matches!(
+        Verifier::new(b"another secret").verify(signed),
+        Err(VerifyError::Unauthenticated)
+    ));
+
+    let mut short = BufferPacketPool::new(SimpleBufferPool(64)).acquire();
+    short.extend(&[0; 10]);
+    assert!(matches!(verifier.verify(short), Err(VerifyError::TooShort)));
+}
+
+#[test]
+fn test_replay() {
+    let buffer_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let pool = AuthenticatedPacketPool::new(buffer_pool);
+    let mut signer = Signer::new(SECRET);
+    let mut verifier = Verifier::new(SECRET);
+
+    let copy = |packet: &[u8]| {
+        let mut copy = buffer_pool.acquire();
+        copy.extend(packet);
+        copy
+    };
+
+    let signed: Vec<_> = (0..3u8)
+        .map(|i| {
+            let mut packet = pool.acquire();
+            packet.extend(&[i]);
+            signer.sign(packet)
+        })
+        .collect();
+
+    // Reordered packets are accepted, but each only once.
+    assert_eq!(verifier.verify(copy(&signed[1])).unwrap().0, 1);
+    assert_eq!(verifier.verify(copy(&signed[0])).unwrap().0, 0);
+    assert!(matches!(
+        verifier.verify(copy(&signed[1])),
+        Err(VerifyError::Replayed)
+    ));
+    assert!(matches!(
+        verifier.verify(copy(&signed[0])),
+        Err(VerifyError::Replayed)
+    ));
+
+    // A forged packet does not use up its sequence number.
+    let mut forged = copy(&signed[2]);
+    forged[9] ^= 1;
+    assert!(matches!(
+        verifier.verify(forged),
+        Err(VerifyError::Unauthenticated)
+    ));
+    assert_eq!(verifier.verify(copy(&signed[2])).unwrap().0, 2);
+}
+
+#[test]
+fn test_reflection() {
+    let pool = AuthenticatedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)));
+
+    // Every packet signed by one side is looped back into its own verifier.
+    let (reflect_send, reflect_recv) = mpsc::channel(8);
+    let (mut verified, mut signed) =
+        authenticated(&secrets(SECRET, OTHER_SECRET), reflect_recv, reflect_send);
+
+    block_on(async {
+        let mut packet = pool.acquire();
+        packet.extend(b"hello");
+        signed.send(packet).await.unwrap();
+        drop(signed);
+        assert!(verified.next().await.is_none());
+    });
+
+    // The same packet verifies on the remote, whose receive secret is the sender's send secret.
+    let mut packet = pool.acquire();
+    packet.extend(b"hello");
+    let signed = Signer::new(SECRET).sign(packet);
+    assert!(Verifier::new(&secrets(OTHER_SECRET, SECRET).recv)
+        .verify(signed)
+        .is_ok());
+}
+
+#[test]
+fn test_authenticated_multiplexer() {
+    let mut runtime = SimpleRuntime::new();
+    let buffer_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let pool = MuxPacketPool::new(AuthenticatedPacketPool::new(buffer_pool));
+
+    // Raw transport packets, connecting A and B.
+    let (a_send, b_recv) = mpsc::channel(8);
+    let (b_send, a_recv) = mpsc::channel(8);
+
+    let mut multiplexer_a = PacketMultiplexer::new();
+    let (a_out, a_in, _) = multiplexer_a.open_channel(0, 8).unwrap();
+    let mut multiplexer_b = PacketMultiplexer::new();
+    let (b_out, b_in, _) = multiplexer_b.open_channel(0, 8).unwrap();
+
+    // Spoofed packets injected into the transport must be dropped before reaching the
+    // multiplexer, even if they look like valid multiplexed packets.
+    let mut inject = a_send.clone();
+
+    for (multiplexer, secrets, incoming, outgoing) in [
+        (multiplexer_a, secrets(SECRET, OTHER_SECRET), a_recv, a_send),
+        (multiplexer_b, secrets(OTHER_SECRET, SECRET), b_recv, b_send),
+    ] {
+        let (mut verified, mut signed) = authenticated(&secrets, incoming, outgoing);
+        let (mut mux_incoming, mut mux_outgoing) = multiplexer.start();
+        runtime.spawn(async move {
+            while let Some(packet) = verified.next().await {
+                let _ = mux_incoming.try_send(packet);
+            }
+        });
+        runtime.spawn(async move {
+            while let Some(packet) = mux_outgoing.next().await {
+                signed.send(packet).await.unwrap();
+            }
+        });
+    }
+
+    let mut channel_a = UnreliableChannel::new(
+        runtime.handle(),
+        pool.clone(),
+        Settings {
+            bandwidth: 4096,
+            burst_bandwidth: 4096,
+        },
+        a_in,
+        a_out,
+    );
+    let mut channel_b = UnreliableChannel::new(
+        runtime.handle(),
+        pool,
+        Settings {
+            bandwidth: 4096,
+            burst_bandwidth: 4096,
+        },
+        b_in,
+        b_out,
+    );
+
+    let (done_send, mut done) = futures::channel::oneshot::channel();
+    runtime.spawn(async move {
+        let mut spoofed = buffer_pool.acquire();
+        spoofed.extend(&[0; 40]);
+        inject.send(spoofed).await.unwrap();
+
+        channel_a.send(b"genuine").await.unwrap();
+        channel_a.flush().await.unwrap();
+
+        assert_eq!(channel_b.recv().await.unwrap(), b"genuine");
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..100 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
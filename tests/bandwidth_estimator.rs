@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use futures::channel::{mpsc, oneshot};
+use rand::{rngs::SmallRng, SeedableRng};
+
+use turbulence::{
+    bandwidth_estimator::BandwidthEstimator,
+    buffer::BufferPacketPool,
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::Runtime,
+};
+
+mod util;
+
+use self::util::{condition_link, LinkCondition, SimpleBufferPool, SimpleRuntime};
+
+#[test]
+fn test_bandwidth_estimator() {
+    let mut estimator = BandwidthEstimator::new(Duration::from_secs(2));
+    let estimate = estimator.estimate();
+    assert_eq!(estimate.bytes_per_sec(), None);
+
+    estimator.add_sample(Duration::from_secs(1), 1000, Duration::from_millis(100));
+    assert_eq!(estimate.bytes_per_sec(), Some(10_000.));
+
+    // The estimate is the maximum recent sample.
+    estimator.add_sample(Duration::from_secs(2), 500, Duration::from_millis(100));
+    assert_eq!(estimate.bytes_per_sec(), Some(10_000.));
+    estimator.add_sample(Duration::from_secs(2), 2000, Duration::from_millis(100));
+    assert_eq!(estimate.bytes_per_sec(), Some(20_000.));
+
+    // Zero length intervals are ignored.
+    estimator.add_sample(Duration::from_secs(3), 2000, Duration::ZERO);
+    assert_eq!(estimate.bytes_per_sec(), Some(20_000.));
+
+    // Old samples expire, leaving the maximum of the samples still in the window.
+    estimator.add_sample(Duration::from_secs(3), 800, Duration::from_millis(100));
+    estimator.add_sample(Duration::from_secs(4), 300, Duration::from_millis(100));
+    assert_eq!(estimate.bytes_per_sec(), Some(20_000.));
+    estimator.add_sample(Duration::from_secs(5), 300, Duration::from_millis(100));
+    assert_eq!(estimate.bytes_per_sec(), Some(8_000.));
+    estimator.add_sample(Duration::from_secs(10), 100, Duration::from_millis(100));
+    assert_eq!(estimator.bytes_per_sec(), Some(1_000.));
+}
+
+#[test]
+fn test_reliable_bandwidth_estimate() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 16384,
+        burst_bandwidth: 2048,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    const CONDITION: LinkCondition = LinkCondition {
+        loss: 0.0,
+        duplicate: 0.0,
+        delay: Duration::from_millis(40),
+        jitter: Duration::ZERO,
+    };
+
+    let (asend, acondrecv) = mpsc::channel(8);
+    let (acondsend, arecv) = mpsc::channel(8);
+    condition_link(
+        CONDITION,
+        runtime.handle(),
+        packet_pool,
+        SmallRng::seed_from_u64(1),
+        acondrecv,
+        acondsend,
+    );
+    let (bsend, bcondrecv) = mpsc::channel(8);
+    let (bcondsend, brecv) = mpsc::channel(8);
+    condition_link(
+        CONDITION,
+        runtime.handle(),
+        packet_pool,
+        SmallRng::seed_from_u64(2),
+        bcondrecv,
+        bcondsend,
+    );
+    let mut stream1 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    let estimate = stream1.bandwidth_estimate();
+    assert_eq!(estimate.bytes_per_sec(), None);
+
+    runtime.spawn(async move {
+        let buffer = [0; 512];
+        // Stops once the receiving side is dropped.
+        while stream1.write(&buffer).await.is_ok() {
+            let _ = stream1.flush().await;
+        }
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        let mut buffer = [0; 512];
+        let mut received = 0;
+        while received < 65536 {
+            received += stream2.read(&mut buffer).await.unwrap();
+        }
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..1000 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            break;
+        }
+        runtime.advance_time(10);
+    }
+
+    // The sender is limited to its bandwidth setting, so the delivery rate should be close to it.
+    let bytes_per_sec = estimate.bytes_per_sec().unwrap();
+    assert!(
+        bytes_per_sec > 12000. && bytes_per_sec < 24000.,
+        "unexpected estimate {}",
+        bytes_per_sec
+    );
+}
@@ -0,0 +1,125 @@
+#![cfg(feature = "bevy")]
+
+use std::time::Duration;
+
+use bevy_app::App;
+use bevy_ecs::event::Events;
+use futures::channel::mpsc;
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    message_channels::{MessageChannelMode, MessageChannelSettings, MessageChannelsBuilder},
+    packet::PacketPool,
+    packet_multiplexer::PacketMultiplexer,
+    reliable_channel, ConnectionEvent, Connections, TurbulencePlugin,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+type Packet = <BufferPacketPool<SimpleBufferPool> as PacketPool>::Packet;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Message(i32);
+
+const SETTINGS: MessageChannelSettings = MessageChannelSettings {
+    channel: 0,
+    channel_mode: MessageChannelMode::Reliable {
+        settings: reliable_channel::Settings {
+            bandwidth: 4096,
+            burst_bandwidth: 1024,
+            recv_window_size: 1024,
+            send_window_size: 1024,
+            init_send: 512,
+            resend_time: Duration::from_millis(100),
+            initial_rtt: Duration::from_millis(200),
+            max_rtt: Duration::from_secs(2),
+            rtt_update_factor: 0.1,
+            rtt_resend_factor: 1.5,
+        },
+        max_message_len: 1024,
+    },
+    message_buffer_size: 8,
+    packet_buffer_size: 8,
+};
+
+#[test]
+fn test_bevy_plugin() {
+    let mut runtime = SimpleRuntime::new();
+    let pool = BufferPacketPool::new(SimpleBufferPool(32));
+
+    let mut app = App::new();
+    app.add_plugins(TurbulencePlugin::<Packet>::default());
+
+    // Connect two sets of channels within the same app through a pair of in-memory transports.
+    let (a_send, b_recv) = mpsc::channel(8);
+    let (b_send, a_recv) = mpsc::channel(8);
+    let mut connect = |incoming: mpsc::Receiver<Packet>, outgoing: mpsc::Sender<Packet>| {
+        let mut multiplexer = PacketMultiplexer::new();
+        let mut builder = MessageChannelsBuilder::new(runtime.handle(), pool);
+        builder.register::<Message>(SETTINGS).unwrap();
+        let channels = builder.build(&mut multiplexer);
+        app.world_mut().resource_mut::<Connections<Packet>>().add(
+            multiplexer,
+            channels,
+            incoming,
+            outgoing,
+        )
+    };
+    let a = connect(a_recv, a_send);
+    let b = connect(b_recv, b_send);
+
+    let mut events = app
+        .world()
+        .resource::<Events<ConnectionEvent>>()
+        .get_reader();
+    app.update();
+    assert_eq!(
+        events
+            .read(app.world().resource::<Events<ConnectionEvent>>())
+            .copied()
+            .collect::<Vec<_>>(),
+        vec![ConnectionEvent::Connected(a), ConnectionEvent::Connected(b)]
+    );
+
+    {
+        let mut connections = app.world_mut().resource_mut::<Connections<Packet>>();
+        let channels = connections.get_mut(a).unwrap();
+        assert!(channels.send(Message(42)).is_none());
+        channels.flush::<Message>();
+    }
+
+    let mut received = None;
+    for _ in 0..100 {
+        runtime.run_until_stalled();
+        app.update();
+        runtime.advance_time(50);
+        let mut connections = app.world_mut().resource_mut::<Connections<Packet>>();
+        if let Some(message) = connections.get_mut(b).unwrap().recv::<Message>() {
+            received = Some(message);
+            break;
+        }
+    }
+    assert_eq!(received, Some(Message(42)));
+
+    // Removing one side drops its transport, which disconnects the other side.
+    app.world_mut()
+        .resource_mut::<Connections<Packet>>()
+        .remove(b)
+        .unwrap();
+    runtime.run_until_stalled();
+    app.update();
+    assert!(app.world().resource::<Connections<Packet>>().is_empty());
+    assert_eq!(
+        events
+            .read(app.world().resource::<Events<ConnectionEvent>>())
+            .copied()
+            .collect::<Vec<_>>(),
+        vec![
+            ConnectionEvent::Disconnected(b),
+            ConnectionEvent::Disconnected(a)
+        ]
+    );
+}
@@ -0,0 +1,161 @@
+#![cfg(feature = "bitcode")]
+
+use std::time::Duration;
+
+use futures::{
+    channel::{mpsc, oneshot},
+    executor::block_on,
+    future,
+};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    bitcode_channel::{
+        ReliableBitcodeChannel, ReliableBitcodeTypedChannel, UnreliableBitcodeChannel,
+    },
+    buffer::BufferPacketPool,
+    reliable_channel::{self, ReliableChannel},
+    runtime::Runtime,
+    unreliable_channel::{self, UnreliableChannel},
+    UnreliableBincodeChannel,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+enum Stance {
+    Standing,
+    Crouching,
+    Prone,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Unit {
+    alive: bool,
+    visible: bool,
+    stance: Stance,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Squad {
+    units: Vec<Unit>,
+}
+
+fn squad(i: u32) -> Squad {
+    Squad {
+        units: (0..32)
+            .map(|j| Unit {
+                alive: (i >> (j % 32)) & 1 != 0,
+                visible: j % 3 == 0,
+                stance: [Stance::Standing, Stance::Crouching, Stance::Prone][(i + j) as usize % 3],
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn test_unreliable_bitcode_channel() {
+    const SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let (csend, crecv) = mpsc::channel(8);
+    let (dsend, drecv) = mpsc::channel(8);
+
+    let mut bitcode = UnreliableBitcodeChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        512,
+    );
+    let mut bitcode_raw =
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+    let mut bincode = UnreliableBincodeChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, crecv, dsend),
+        512,
+    );
+    let mut bincode_raw =
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, drecv, csend);
+
+    block_on(async {
+        // Booleans and enum tags are packed at the bit level rather than taking a byte each.
+        bitcode.send(&squad(0xa5a5)).await.unwrap();
+        bitcode.flush().await.unwrap();
+        let bitcode_len = bitcode_raw.recv().await.unwrap().len();
+
+        bincode.send(&squad(0xa5a5)).await.unwrap();
+        bincode.flush().await.unwrap();
+        let bincode_len = bincode_raw.recv().await.unwrap().len();
+        assert!(bitcode_len < bincode_len / 4);
+
+        bitcode_raw
+            .send(&::bitcode::serialize(&squad(7)).unwrap())
+            .await
+            .unwrap();
+        bitcode_raw.flush().await.unwrap();
+        assert_eq!(bitcode.recv::<Squad>().await.unwrap(), squad(7));
+    });
+}
+
+#[test]
+fn test_reliable_bitcode_channel() {
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 4096,
+        send_window_size: 4096,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut stream1 = ReliableBitcodeTypedChannel::<Squad>::new(ReliableBitcodeChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        1024,
+    ));
+    let mut stream2 = ReliableBitcodeTypedChannel::<Squad>::new(ReliableBitcodeChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+        1024,
+    ));
+
+    runtime.spawn(async move {
+        for i in 0..50 {
+            stream1.send(&squad(i)).await.unwrap();
+        }
+        stream1.flush().await.unwrap();
+        // Keep the channel alive until the other side has received everything.
+        future::pending::<()>().await;
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        for i in 0..50 {
+            assert_eq!(stream2.recv().await.unwrap(), squad(i));
+        }
+        done_send.send(()).unwrap();
+    });
+
+    for _ in 0..1000 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
@@ -0,0 +1,169 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    executor::block_on,
+    future, pin_mut,
+};
+
+use turbulence::{
+    blob_transfer::{BlobChannel, Error, PartialBlob, Settings as BlobSettings},
+    buffer::BufferPacketPool,
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::Runtime,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 4096,
+    recv_window_size: 16384,
+    send_window_size: 16384,
+    init_send: 512,
+    resend_time: Duration::from_millis(50),
+    initial_rtt: Duration::from_millis(100),
+    max_rtt: Duration::from_millis(2000),
+    rtt_update_factor: 0.1,
+    rtt_resend_factor: 1.5,
+};
+
+const BLOB_SETTINGS: BlobSettings = BlobSettings {
+    chunk_len: 700,
+    max_blob_len: 65536,
+};
+
+fn connect(runtime: &SimpleRuntime) -> (BlobChannel, BlobChannel) {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    (
+        BlobChannel::new(
+            ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+            BLOB_SETTINGS,
+        ),
+        BlobChannel::new(
+            ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+            BLOB_SETTINGS,
+        ),
+    )
+}
+
+fn run(runtime: &mut SimpleRuntime, done: &mut oneshot::Receiver<()>) {
+    for _ in 0..1000 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(10);
+    }
+    panic!("transfer did not finish");
+}
+
+#[test]
+fn test_blob_transfer_resume() {
+    let mut runtime = SimpleRuntime::new();
+    let blob: Arc<Vec<u8>> = Arc::new((0..20_000).map(|i| (i * 7 % 251) as u8).collect());
+
+    // Interrupt the first transfer once the receiver has part of the blob.
+    let (mut sender, mut receiver) = connect(&runtime);
+    runtime.spawn({
+        let blob = Arc::clone(&blob);
+        async move {
+            let _ = sender.send(3, &blob, |_, _| {}).await;
+        }
+    });
+    let (interrupt_send, interrupt) = oneshot::channel::<()>();
+    let (partial_send, partial_recv) = oneshot::channel();
+    runtime.spawn(async move {
+        let mut partial = PartialBlob::new();
+        let mut interrupt_send = Some(interrupt_send);
+        {
+            let recv = receiver.recv(&mut partial, |received, total| {
+                assert_eq!(total, 20_000);
+                if received >= 7000 {
+                    interrupt_send.take();
+                }
+            });
+            pin_mut!(recv);
+            let _ = future::select(recv, interrupt).await;
+        }
+        let _ = partial_send.send(partial);
+    });
+    runtime.run_until_stalled();
+    for _ in 0..100 {
+        runtime.advance_time(10);
+        runtime.run_until_stalled();
+    }
+
+    let mut partial = block_on(partial_recv).unwrap();
+    assert_eq!(partial.id(), Some(3));
+    assert_eq!(partial.total_len(), Some(20_000));
+    let resumed_from = partial.received();
+    assert!((7000..20_000).contains(&resumed_from));
+
+    // Resume on a new connection.
+    let (mut sender, mut receiver) = connect(&runtime);
+    let sent_progress = Arc::new(Mutex::new(Vec::new()));
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn({
+        let blob = Arc::clone(&blob);
+        let sent_progress = Arc::clone(&sent_progress);
+        async move {
+            sender
+                .send(3, &blob, |sent, _| sent_progress.lock().unwrap().push(sent))
+                .await
+                .unwrap();
+            let _ = done_send.send(());
+        }
+    });
+    runtime.spawn({
+        let blob = Arc::clone(&blob);
+        async move {
+            let received = receiver.recv(&mut partial, |_, _| {}).await.unwrap();
+            assert_eq!(received.id, 3);
+            assert_eq!(&received.data, &*blob);
+            assert_eq!(partial.received(), 0);
+            // Keep the channel open until the sender has seen the transfer complete.
+            future::pending::<()>().await;
+        }
+    });
+    run(&mut runtime, &mut done);
+
+    let sent_progress = sent_progress.lock().unwrap();
+    assert_eq!(sent_progress.first(), Some(&resumed_from));
+    assert_eq!(sent_progress.last(), Some(&20_000));
+}
+
+#[test]
+fn test_blob_transfer_rejected() {
+    let mut runtime = SimpleRuntime::new();
+
+    let (mut sender, mut receiver) = connect(&runtime);
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        let too_large = vec![0; 70_000];
+        assert!(matches!(
+            sender.send(1, &too_large, |_, _| {}).await,
+            Err(Error::Rejected)
+        ));
+        sender.send(2, &[1, 2, 3], |_, _| {}).await.unwrap();
+        let _ = done_send.send(());
+    });
+    runtime.spawn(async move {
+        let mut partial = PartialBlob::new();
+        assert!(matches!(
+            receiver.recv(&mut partial, |_, _| {}).await,
+            Err(Error::BlobTooLarge)
+        ));
+        let received = receiver.recv(&mut partial, |_, _| {}).await.unwrap();
+        assert_eq!(received.data, vec![1, 2, 3]);
+        future::pending::<()>().await;
+    });
+    run(&mut runtime, &mut done);
+}
@@ -0,0 +1,66 @@
+use turbulence::{
+    buffer::{AlignedBuffer, AlignedBufferPool, BufferPacketPool, RecyclingBufferPool},
+    packet::{Packet, PacketPool},
+    packet_compression::CompressedPacketPool,
+    packet_multiplexer::{self, MuxPacketPool},
+};
+
+#[test]
+fn test_recycling_pool() {
+    let buffers = RecyclingBufferPool::new(64);
+    let pool = MuxPacketPool::new(CompressedPacketPool::new(BufferPacketPool::new(
+        buffers.clone(),
+    )));
+
+    pool.prewarm(8);
+    assert_eq!(buffers.free(), 8);
+
+    let mut packets: Vec<_> = (0..10).map(|_| pool.acquire()).collect();
+    assert_eq!(buffers.free(), 0);
+    packets[0].extend(&[1, 2, 3]);
+    assert_eq!(&packets[0][..], &[1, 2, 3]);
+
+    drop(packets);
+    assert_eq!(buffers.free(), 10);
+
+    // Recycled buffers start out empty.
+    let packet = pool.acquire();
+    assert!(packet.is_empty());
+    assert_eq!(buffers.free(), 9);
+    drop(packet);
+
+    pool.shrink_to(4);
+    assert_eq!(buffers.free(), 4);
+    pool.prewarm(2);
+    assert_eq!(buffers.free(), 4);
+}
+
+#[test]
+fn test_aligned_buffers() {
+    for offset in 0..4 {
+        let buffer = AlignedBuffer::new(10, 64, offset);
+        assert_eq!(buffer.len(), 10);
+        assert!(buffer.iter().all(|&b| b == 0));
+        assert_eq!(buffer[offset..].as_ptr() as usize % 64, 0);
+    }
+
+    let pool = MuxPacketPool::new(BufferPacketPool::new(
+        AlignedBufferPool::new(64, 16).with_offset(packet_multiplexer::HEADER_LEN),
+    ));
+    for _ in 0..8 {
+        let mut packet = pool.acquire();
+        packet.extend(&[1, 2, 3, 4]);
+        assert_eq!(packet.as_ptr() as usize % 16, 0);
+    }
+
+    let buffers = RecyclingBufferPool::new(64).with_alignment(32, 2);
+    assert_eq!(buffers.align(), 32);
+    let pool = BufferPacketPool::new(buffers.clone());
+    pool.prewarm(4);
+    for _ in 0..8 {
+        let mut packet = pool.acquire();
+        packet.resize(64, 0);
+        assert_eq!(packet.capacity(), 64);
+        assert_eq!(packet[2..].as_ptr() as usize % 32, 0);
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    net::SocketAddrV4,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use futures::{channel::mpsc, SinkExt, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    capture::{
+        export_pcapng, replay, CaptureError, CaptureReader, Direction, PacketCapture, PcapngLink,
+        PcapngWriter, Record, Recorder,
+    },
+    packet::{Packet, PacketPool},
+    packet_multiplexer::PacketMultiplexer,
+    runtime::{Runtime, SimulationRuntime, Timer},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+#[test]
+fn test_capture_replay() {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut runtime = SimulationRuntime::new();
+
+    // Capture a connection's raw packets.
+    let capture = PacketCapture::new(runtime.handle(), Recorder::new(Vec::new()).unwrap());
+    let (mut remote_send, incoming) = mpsc::channel(8);
+    let (outgoing, mut remote_recv) = mpsc::channel(8);
+    let mut incoming = capture.tap_incoming(incoming);
+    let mut outgoing = capture.tap_outgoing(outgoing);
+
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        async move {
+            for i in 0..3u8 {
+                handle.sleep(Duration::from_millis(100)).await;
+                let mut packet = pool.acquire();
+                packet.extend(&[0, i]);
+                remote_send.send(packet).await.unwrap();
+            }
+        }
+    });
+    runtime.handle().spawn(async move {
+        while let Some(packet) = incoming.next().await {
+            let mut reply = pool.acquire();
+            reply.extend(&[0, packet[1] + 10]);
+            outgoing.send(reply).await.unwrap();
+        }
+    });
+    runtime.run_for(Duration::from_secs(1));
+    assert_eq!(&remote_recv.try_recv().unwrap()[..], &[0, 10]);
+
+    let log = capture.stop().unwrap().unwrap().into_inner();
+    let records = CaptureReader::new(&log[..])
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records.len(), 6);
+    assert_eq!(
+        records[2],
+        Record {
+            time: Duration::from_millis(200),
+            direction: Direction::Incoming,
+            data: vec![0, 1],
+        }
+    );
+    assert_eq!(records[3].direction, Direction::Outgoing);
+    assert_eq!(records[3].data, vec![0, 11]);
+
+    // Truncated logs are detected.
+    let mut reader = CaptureReader::new(&log[..log.len() - 1]).unwrap();
+    assert!(reader.by_ref().take(5).all(|r| r.is_ok()));
+    assert!(matches!(reader.next(), Some(Err(CaptureError::BadFormat))));
+    assert!(matches!(
+        CaptureReader::new(&b"garbage"[..]),
+        Err(CaptureError::BadFormat)
+    ));
+
+    // Replay the incoming packets into a new multiplexer, with their original timing.
+    let mut multiplexer = PacketMultiplexer::new();
+    let (_, mut receiver, _) = multiplexer.open_channel(0, 8).unwrap();
+    let (mut mux_incoming, _mux_outgoing) = multiplexer.start();
+
+    let mut runtime = SimulationRuntime::new();
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        async move {
+            replay(
+                handle,
+                pool,
+                CaptureReader::new(&log[..]).unwrap(),
+                &mut mux_incoming,
+            )
+            .await
+            .unwrap();
+        }
+    });
+
+    for i in 0..3u8 {
+        runtime.run_for(Duration::from_millis(99));
+        assert!(receiver.try_recv().is_err());
+        runtime.run_for(Duration::from_millis(1));
+        assert_eq!(&receiver.try_recv().unwrap()[..], &[i]);
+    }
+}
+
+#[test]
+fn test_pcapng_export() {
+    let mut recorder = Recorder::new(Vec::new()).unwrap();
+    recorder
+        .record(Duration::from_millis(5), Direction::Incoming, &[0, 1, 2])
+        .unwrap();
+    recorder
+        .record(Duration::from_secs(2), Direction::Outgoing, &[3; 8])
+        .unwrap();
+    let log = recorder.into_inner();
+
+    let u32_at =
+        |buf: &[u8], i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+
+    let start = UNIX_EPOCH + Duration::from_secs(1_000);
+    let writer = PcapngWriter::new(Vec::new(), PcapngLink::User, start).unwrap();
+    let pcap = export_pcapng(CaptureReader::new(&log[..]).unwrap(), writer).unwrap();
+
+    // The section header and interface description, then one padded block per packet.
+    assert_eq!(u32_at(&pcap, 0), 0x0a0d0d0a);
+    assert_eq!(u32_at(&pcap, 8), 0x1a2b3c4d);
+    assert_eq!(u32_at(&pcap, 28), 1);
+    assert_eq!(&pcap[36..38], &147u16.to_le_bytes());
+    let block = &pcap[48..];
+    assert_eq!(u32_at(block, 0), 6);
+    assert_eq!(u32_at(block, 4), 48);
+    let time = (u32_at(block, 12) as u64) << 32 | u32_at(block, 16) as u64;
+    assert_eq!(time, 1_000_005_000);
+    assert_eq!(u32_at(block, 20), 3);
+    assert_eq!(&block[28..32], &[0, 1, 2, 0]);
+    // Inbound flag.
+    assert_eq!(u32_at(block, 36), 1);
+    assert_eq!(u32_at(block, 44), 48);
+    let block = &block[48..];
+    assert_eq!(u32_at(block, 4), 52);
+    assert_eq!(&block[28..36], &[3; 8]);
+    assert_eq!(u32_at(block, 40), 2);
+    assert_eq!(pcap.len(), 48 + 48 + 52);
+
+    // Packets are wrapped in UDP datagrams between the two addresses.
+    let local: SocketAddrV4 = "10.0.0.1:5000".parse().unwrap();
+    let remote: SocketAddrV4 = "10.0.0.2:6000".parse().unwrap();
+    let writer = PcapngWriter::new(Vec::new(), PcapngLink::Udp { local, remote }, start).unwrap();
+    let pcap = export_pcapng(CaptureReader::new(&log[..]).unwrap(), writer).unwrap();
+    assert_eq!(&pcap[36..38], &228u16.to_le_bytes());
+    let block = &pcap[48..];
+    assert_eq!(u32_at(block, 20), 31);
+    let ip = &block[28..59];
+    assert_eq!(ip[0], 0x45);
+    assert_eq!(&ip[2..4], &31u16.to_be_bytes());
+    assert_eq!(ip[9], 17);
+    assert_eq!(&ip[12..16], &[10, 0, 0, 2]);
+    assert_eq!(&ip[16..20], &[10, 0, 0, 1]);
+    // The header checksum makes the ones' complement sum of the header zero.
+    let mut sum = ip[..20]
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+        .sum::<u32>();
+    sum = (sum & 0xffff) + (sum >> 16);
+    assert_eq!(sum, 0xffff);
+    assert_eq!(&ip[20..22], &6000u16.to_be_bytes());
+    assert_eq!(&ip[22..24], &5000u16.to_be_bytes());
+    assert_eq!(&ip[24..26], &11u16.to_be_bytes());
+    assert_eq!(&ip[28..], &[0, 1, 2]);
+}
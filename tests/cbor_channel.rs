@@ -0,0 +1,59 @@
+#![cfg(feature = "minicbor-serde")]
+
+use futures::{channel::mpsc, executor::block_on};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    cbor_channel::{CborError, UnreliableCborChannel, UnreliableCborTypedChannel},
+    serde_channel::RecvError,
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Sample {
+    id: u8,
+    value: i16,
+}
+
+#[test]
+fn test_cbor_channel() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut cbor = UnreliableCborTypedChannel::<Sample, _, _>::new(UnreliableCborChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        64,
+    ));
+    let mut raw = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    // `{"id": 1, "value": -2}`
+    const ENCODED: &[u8] = b"\xa2\x62id\x01\x65value\x21";
+
+    block_on(async {
+        cbor.send(&Sample { id: 1, value: -2 }).await.unwrap();
+        cbor.flush().await.unwrap();
+        assert_eq!(raw.recv().await.unwrap(), ENCODED);
+
+        raw.send(ENCODED).await.unwrap();
+        raw.send(&[ENCODED, &[0]].concat()).await.unwrap();
+        raw.flush().await.unwrap();
+        assert_eq!(cbor.recv().await.unwrap(), Sample { id: 1, value: -2 });
+        assert!(matches!(
+            cbor.recv().await,
+            Err(RecvError::SerializationError(CborError::TrailingBytes))
+        ));
+    });
+}
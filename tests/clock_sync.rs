@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use futures::channel::mpsc;
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    clock_sync::{ClockSync, Settings},
+    runtime::{Runtime, SimulationHandle, SimulationRuntime, Timer},
+    simulation::{self, LinkSimulator},
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// A timer which runs 1% faster than the simulation.
+#[derive(Clone)]
+struct FastTimer(SimulationHandle);
+
+const RATE: f64 = 1.01;
+
+impl Timer for FastTimer {
+    type Instant = <SimulationHandle as Timer>::Instant;
+    type Sleep = <SimulationHandle as Timer>::Sleep;
+
+    fn now(&self) -> Self::Instant {
+        self.0.now()
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        self.0.elapsed(instant).mul_f64(RATE)
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        self.0.duration_between(earlier, later).mul_f64(RATE)
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        self.0.sleep(duration.div_f64(RATE))
+    }
+}
+
+#[test]
+fn test_clock_sync() {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut runtime = SimulationRuntime::new();
+    let link = simulation::Settings {
+        latency: Duration::from_millis(50),
+        jitter: Duration::from_millis(20),
+        ..simulation::Settings::PERFECT
+    };
+    let channel_settings = unreliable_channel::Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 4096,
+    };
+
+    let (asend, alinkrecv) = mpsc::channel(8);
+    let (alinksend, arecv) = mpsc::channel(8);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, link, 1).run(alinkrecv, alinksend));
+    let (bsend, blinkrecv) = mpsc::channel(8);
+    let (blinksend, brecv) = mpsc::channel(8);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, link, 2).run(blinkrecv, blinksend));
+
+    let sync_a = ClockSync::new(runtime.handle(), Settings::default());
+    let clock_a = sync_a.remote_clock();
+    let channel_a = UnreliableChannel::new(
+        runtime.handle(),
+        pool,
+        channel_settings.clone(),
+        arecv,
+        bsend,
+    );
+    runtime.handle().spawn(async move {
+        let _ = sync_a.run(channel_a).await;
+    });
+
+    // B's timeline starts 5 seconds after A's, and runs 1% faster.
+    runtime.run_for(Duration::from_secs(5));
+    assert_eq!(clock_a.offset(), None);
+
+    let sync_b = ClockSync::new(FastTimer(runtime.handle()), Settings::default());
+    let clock_b = sync_b.remote_clock();
+    let channel_b = UnreliableChannel::new(runtime.handle(), pool, channel_settings, brecv, asend);
+    runtime.handle().spawn(async move {
+        let _ = sync_b.run(channel_b).await;
+    });
+
+    runtime.run_for(Duration::from_secs(30));
+
+    let local = clock_a.local_time().as_secs_f64();
+    let expected = (local - 5.0) * RATE - local;
+    assert!((clock_a.offset().unwrap() - expected).abs() < 0.01);
+    assert!((clock_a.drift().unwrap() - (RATE - 1.0)).abs() < 0.002);
+    let rtt = clock_a.rtt().unwrap();
+    assert!(rtt >= Duration::from_millis(99) && rtt < Duration::from_millis(140));
+
+    // Both sides agree on what time it is on the other's timeline.
+    let b_time = clock_b.local_time().as_secs_f64();
+    assert!((clock_a.remote_time().unwrap().as_secs_f64() - b_time).abs() < 0.01);
+    let a_time = clock_b.remote_time().unwrap().as_secs_f64();
+    assert!((a_time - local).abs() < 0.01);
+}
@@ -44,7 +44,7 @@ fn test_compressed_bincode_channel() {
     condition_link(
         CONDITION,
         runtime.handle(),
-        packet_pool.clone(),
+        packet_pool,
         SmallRng::from_rng(thread_rng()).unwrap(),
         acondrecv,
         acondsend,
@@ -55,7 +55,7 @@ fn test_compressed_bincode_channel() {
     condition_link(
         CONDITION,
         runtime.handle(),
-        packet_pool.clone(),
+        packet_pool,
         SmallRng::from_rng(thread_rng()).unwrap(),
         bcondrecv,
         bcondsend,
@@ -64,7 +64,7 @@ fn test_compressed_bincode_channel() {
     let mut stream1 = CompressedBincodeChannel::new(
         ReliableChannel::new(
             runtime.handle(),
-            packet_pool.clone(),
+            packet_pool,
             SETTINGS.clone(),
             arecv,
             bsend,
@@ -74,7 +74,7 @@ fn test_compressed_bincode_channel() {
     let mut stream2 = CompressedBincodeChannel::new(
         ReliableChannel::new(
             runtime.handle(),
-            packet_pool.clone(),
+            packet_pool,
             SETTINGS.clone(),
             brecv,
             asend,
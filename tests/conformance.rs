@@ -0,0 +1,504 @@
+use std::time::Duration;
+
+use futures::{
+    channel::mpsc,
+    executor::{block_on, LocalPool},
+    stream, FutureExt, SinkExt, Stream, StreamExt,
+};
+
+use turbulence::{
+    acked_channel::{self, AckedChannel},
+    buffer::BufferPacketPool,
+    clock_sync::{self, ClockSync},
+    conformance,
+    handshake::{self, handshake},
+    packet::{Packet, PacketPool, WIRE_VERSION},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    piggyback,
+    reliable_channel::{self, ReliableChannel},
+    runtime::Runtime,
+    timestamp_channel::TimestampChannel,
+    unreliable_channel::{self, UnreliableChannel},
+    ReliableBincodeChannel,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+const UNRELIABLE_SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+    bandwidth: 4096,
+    burst_bandwidth: 1024,
+};
+
+const ACKED_SETTINGS: acked_channel::Settings = acked_channel::Settings {
+    bandwidth: 4096,
+    burst_bandwidth: 1024,
+    loss_timeout: Duration::from_secs(1),
+};
+
+fn reliable_settings(recv_window_size: u32) -> reliable_channel::Settings {
+    reliable_channel::Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size,
+        send_window_size: 4096,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    }
+}
+
+// Run the runtime until the receiver has an item.  `run_until_stalled` may return before every task
+// is done when the task pool yields, so this retries a few times.
+fn next<T, S: Stream<Item = T> + Unpin>(runtime: &mut SimpleRuntime, recv: &mut S) -> T {
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        if let Some(item) = recv.next().now_or_never() {
+            return item.unwrap();
+        }
+    }
+    panic!("nothing received");
+}
+
+#[test]
+fn test_wire_version() {
+    // The vectors describe this wire version, changing either requires changing both.
+    assert_eq!(WIRE_VERSION, 3);
+}
+
+#[test]
+fn test_mux_vectors() {
+    let mut pool = LocalPool::new();
+    let packet_pool = MuxPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)));
+
+    for vector in conformance::MUX {
+        let mut multiplexer = PacketMultiplexer::new();
+        let (mut sender, mut receiver, _) = multiplexer.open_channel(vector.channel, 8).unwrap();
+        let (mut incoming, mut outgoing) = multiplexer.start();
+
+        let packet = pool.run_until(async {
+            let mut packet = packet_pool.acquire();
+            packet.extend(vector.payload);
+            sender.send(packet).await.unwrap();
+            outgoing.next().await.unwrap()
+        });
+        assert_eq!(&packet[..], vector.packet, "{}", vector.name);
+
+        let payload = pool.run_until(async {
+            incoming.send(packet).await.unwrap();
+            receiver.next().await.unwrap()
+        });
+        assert_eq!(&payload[..], vector.payload, "{}", vector.name);
+    }
+}
+
+#[test]
+fn test_bundle_vectors() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+    for vector in conformance::BUNDLE {
+        // The acknowledgement is ready first, so it is held and sent along with the data.
+        let packets = IntoIterator::into_iter([vector.ack, vector.data]).map(|data| {
+            let mut packet = packet_pool.acquire();
+            packet.extend(data);
+            packet
+        });
+        let mut bundles = block_on(
+            piggyback::piggyback_acks(
+                SimpleRuntime::new().handle(),
+                Duration::ZERO,
+                stream::iter(packets),
+                vector.bundle_channel,
+                [1],
+            )
+            .collect::<Vec<_>>(),
+        );
+        assert_eq!(bundles.len(), 1, "{}", vector.name);
+        assert_eq!(&bundles[0][..], vector.bundle, "{}", vector.name);
+
+        let (send, recv) = mpsc::unbounded();
+        let mut incoming = piggyback::unbundle(send, packet_pool, vector.bundle_channel);
+        block_on(incoming.send(bundles.remove(0))).unwrap();
+        drop(incoming);
+        let unbundled = block_on(recv.collect::<Vec<_>>());
+        assert_eq!(unbundled.len(), 2, "{}", vector.name);
+        assert_eq!(&unbundled[0][..], vector.data, "{}", vector.name);
+        assert_eq!(&unbundled[1][..], vector.ack, "{}", vector.name);
+    }
+}
+
+#[test]
+fn test_acked_vectors() {
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (mut asend, arecv) = mpsc::channel(8);
+    let (bsend, mut brecv) = mpsc::channel(8);
+    let mut channel =
+        AckedChannel::new(runtime.handle(), packet_pool, ACKED_SETTINGS, arecv, bsend);
+
+    for vector in conformance::ACKED {
+        for &seq in vector.received {
+            let mut packet = packet_pool.acquire();
+            packet.extend(&seq.to_le_bytes());
+            packet.extend(b"\xff\xff\x00\x00\x00\x00");
+            asend.try_send(packet).unwrap();
+        }
+        assert!(channel.recv().now_or_never().is_none());
+
+        block_on(async {
+            for msg in vector.messages {
+                channel.send(msg).await.unwrap();
+            }
+            channel.flush().await.unwrap();
+        });
+        assert_eq!(
+            &brecv.next().now_or_never().unwrap().unwrap()[..],
+            vector.packet,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn test_timestamp_vectors() {
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    for vector in conformance::TIMESTAMP {
+        let clock =
+            ClockSync::new(runtime.handle(), clock_sync::Settings::default()).remote_clock();
+        let (_asend, arecv) = mpsc::channel(8);
+        let (bsend, mut brecv) = mpsc::channel(8);
+        let mut channel = TimestampChannel::new(
+            clock,
+            runtime.handle(),
+            packet_pool,
+            UNRELIABLE_SETTINGS,
+            arecv,
+            bsend,
+        );
+
+        let mut time = 0;
+        for &(at, msg) in vector.messages {
+            runtime.advance_time((at - time) as u64);
+            time = at;
+            block_on(channel.send(msg)).unwrap();
+        }
+        block_on(channel.flush()).unwrap();
+        assert_eq!(
+            &brecv.next().now_or_never().unwrap().unwrap()[..],
+            vector.packet,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn test_unreliable_vectors() {
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    for vector in conformance::UNRELIABLE {
+        let (_asend, arecv) = mpsc::channel(8);
+        let (bsend, mut brecv) = mpsc::channel(8);
+        let mut sender = UnreliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            UNRELIABLE_SETTINGS,
+            arecv,
+            bsend,
+        );
+
+        let (mut csend, crecv) = mpsc::channel(8);
+        let (dsend, _drecv) = mpsc::channel(8);
+        let mut receiver = UnreliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            UNRELIABLE_SETTINGS,
+            crecv,
+            dsend,
+        );
+
+        block_on(async {
+            for msg in vector.messages {
+                sender.send(msg).await.unwrap();
+            }
+            sender.flush().await.unwrap();
+            assert_eq!(
+                &brecv.next().await.unwrap()[..],
+                vector.packet,
+                "{}",
+                vector.name
+            );
+
+            let mut packet = packet_pool.acquire();
+            packet.extend(vector.packet);
+            csend.send(packet).await.unwrap();
+            for &msg in vector.messages {
+                assert_eq!(receiver.recv().await.unwrap(), msg, "{}", vector.name);
+            }
+        });
+    }
+}
+
+#[test]
+fn test_reliable_vectors() {
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+    let settings = reliable_settings(conformance::RELIABLE[0].recv_window_size);
+
+    // A sender whose data packets are checked, which never receives acknowledgements.
+    let (_asend, arecv) = mpsc::channel(8);
+    let (bsend, mut brecv) = mpsc::channel(8);
+    let mut sender = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        settings.clone(),
+        arecv,
+        bsend,
+    );
+
+    // A receiver which is given the vector data packets and whose acknowledgements are checked.
+    // The receiver is never read from, so its receive window never moves.
+    let (mut csend, crecv) = mpsc::channel(8);
+    let (dsend, mut drecv) = mpsc::channel(8);
+    let _receiver = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        settings.clone(),
+        crecv,
+        dsend,
+    );
+
+    let (writes, mut write_recv) = mpsc::unbounded::<&'static [u8]>();
+    runtime.spawn(async move {
+        while let Some(data) = write_recv.next().await {
+            let mut written = 0;
+            while written < data.len() {
+                written += sender.write(&data[written..]).await.unwrap();
+            }
+            sender.flush().await.unwrap();
+        }
+    });
+
+    for vector in conformance::RELIABLE {
+        assert_eq!(vector.recv_window_size, settings.recv_window_size);
+        assert_eq!(
+            &vector.packet[2..6],
+            &vector.start.to_le_bytes(),
+            "{}",
+            vector.name
+        );
+
+        writes.unbounded_send(vector.data).unwrap();
+        let packet = next(&mut runtime, &mut brecv);
+        assert_eq!(&packet[..], vector.packet, "{}", vector.name);
+
+        let mut packet = packet_pool.acquire();
+        packet.extend(vector.packet);
+        csend.try_send(packet).unwrap();
+        let ack = next(&mut runtime, &mut drecv);
+        assert_eq!(&ack[..], vector.ack, "{}", vector.name);
+    }
+}
+
+#[test]
+fn test_urgent_vectors() {
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+    let settings = reliable_settings(4096);
+
+    let (_asend, arecv) = mpsc::channel(8);
+    let (bsend, mut brecv) = mpsc::channel(8);
+    let mut sender = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        settings.clone(),
+        arecv,
+        bsend,
+    );
+
+    let (mut csend, crecv) = mpsc::channel(8);
+    let (dsend, mut drecv) = mpsc::channel(8);
+    let mut receiver = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        settings.clone(),
+        crecv,
+        dsend,
+    );
+
+    for vector in conformance::URGENT {
+        block_on(sender.send_urgent(vector.message)).unwrap();
+        let packet = next(&mut runtime, &mut brecv);
+        assert_eq!(&packet[..], vector.packet, "{}", vector.name);
+        assert_eq!(&packet[3..7], &vector.seq.to_le_bytes(), "{}", vector.name);
+
+        let mut packet = packet_pool.acquire();
+        packet.extend(vector.packet);
+        csend.try_send(packet).unwrap();
+        let ack = next(&mut runtime, &mut drecv);
+        assert_eq!(&ack[..], vector.ack, "{}", vector.name);
+        assert_eq!(
+            &block_on(receiver.recv_urgent()).unwrap()[..],
+            vector.message,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn test_feedback_vectors() {
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    for vector in conformance::FEEDBACK {
+        assert_eq!(&vector.packet[2..6], &vector.delivery_rate.to_le_bytes());
+        assert_eq!(&vector.packet[6..8], &vector.occupancy.to_le_bytes());
+
+        let (mut asend, arecv) = mpsc::channel(8);
+        let (bsend, _brecv) = mpsc::channel(8);
+        let channel = ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            reliable_settings(4096),
+            arecv,
+            bsend,
+        );
+        let congestion = channel.congestion();
+
+        let mut packet = packet_pool.acquire();
+        packet.extend(vector.packet);
+        asend.try_send(packet).unwrap();
+        runtime.run_until_stalled();
+        assert_eq!(
+            congestion.remote_delivery_rate(),
+            Some(vector.delivery_rate),
+            "{}",
+            vector.name
+        );
+        assert_eq!(
+            congestion.remote_occupancy(),
+            vector.occupancy as f64 / u16::MAX as f64,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn test_reliable_bincode_vectors() {
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+    let settings = reliable_settings(4096);
+
+    let (_asend, arecv) = mpsc::channel(8);
+    let (bsend, mut brecv) = mpsc::channel(8);
+    let mut sender = ReliableBincodeChannel::new(
+        ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            settings.clone(),
+            arecv,
+            bsend,
+        ),
+        256,
+    );
+
+    let (mut csend, crecv) = mpsc::channel(8);
+    let (dsend, _drecv) = mpsc::channel(8);
+    let mut receiver = ReliableBincodeChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, settings, crecv, dsend),
+        256,
+    );
+
+    let (writes, mut write_recv) = mpsc::unbounded::<&'static str>();
+    runtime.spawn(async move {
+        while let Some(msg) = write_recv.next().await {
+            sender.send(&msg).await.unwrap();
+            sender.flush().await.unwrap();
+        }
+    });
+
+    let (received_send, mut received) = mpsc::unbounded();
+    runtime.spawn(async move {
+        loop {
+            let msg = receiver.recv::<String>().await.unwrap();
+            received_send.unbounded_send(msg).unwrap();
+        }
+    });
+
+    let mut pos = 0u32;
+    for vector in conformance::RELIABLE_BINCODE {
+        writes.unbounded_send(vector.message).unwrap();
+        let packet = next(&mut runtime, &mut brecv);
+        assert_eq!(&packet[6..], vector.stream, "{}", vector.name);
+
+        let mut packet = packet_pool.acquire();
+        packet.extend(&(vector.stream.len() as i16).to_le_bytes());
+        packet.extend(&pos.to_le_bytes());
+        packet.extend(vector.stream);
+        csend.try_send(packet).unwrap();
+        assert_eq!(
+            next(&mut runtime, &mut received),
+            vector.message,
+            "{}",
+            vector.name
+        );
+        pos += vector.stream.len() as u32;
+    }
+}
+
+#[test]
+fn test_hello_vectors() {
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+
+    let initial = &conformance::HELLO[0];
+    let reply = &conformance::HELLO[1];
+    assert!(!initial.seen && reply.seen);
+
+    let settings = handshake::Settings {
+        channel: initial.handshake_channel,
+        protocol_version: initial.protocol_version,
+        ..Default::default()
+    };
+
+    let (mut asend, mut arecv) = mpsc::channel(8);
+    let (mut bsend, mut brecv) = mpsc::channel(8);
+    runtime.spawn({
+        let handle = runtime.handle();
+        async move {
+            // Channels are sorted before being sent.
+            let mut channels = initial.channels.to_vec();
+            channels.reverse();
+            let _ = handshake(
+                handle,
+                packet_pool,
+                &settings,
+                &channels,
+                &mut arecv,
+                &mut bsend,
+            )
+            .await;
+        }
+    });
+
+    let packet = next(&mut runtime, &mut brecv);
+    assert_eq!(&packet[..], initial.packet, "{}", initial.name);
+
+    // Once the hello from the other side is received, the reply says it has been seen.
+    let mut packet = packet_pool.acquire();
+    packet.extend(initial.packet);
+    asend.try_send(packet).unwrap();
+    let packet = next(&mut runtime, &mut brecv);
+    assert_eq!(&packet[..], reply.packet, "{}", reply.name);
+    assert_eq!(reply.channels, initial.channels);
+}
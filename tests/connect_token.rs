@@ -0,0 +1,55 @@
+#![cfg(feature = "authentication")]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use turbulence::connect_token::{ConnectToken, TokenError, TokenIssuer, TokenValidator, TOKEN_LEN};
+
+const SECRET: &[u8] = b"shared between backend and servers";
+
+#[test]
+fn test_connect_token() {
+    let issuer = TokenIssuer::new(SECRET);
+    let validator = TokenValidator::new(SECRET);
+
+    let token = issuer.issue(17, Duration::from_secs(30));
+    assert_eq!(token.len(), TOKEN_LEN);
+    let validated = validator.validate(&token).unwrap();
+    assert_eq!(validated.client_id, 17);
+    assert!(validated.expires_at > SystemTime::now());
+
+    let expired = issuer.issue_token(&ConnectToken {
+        client_id: 17,
+        expires_at: UNIX_EPOCH + Duration::from_secs(1000),
+    });
+    assert!(matches!(
+        validator.validate(&expired),
+        Err(TokenError::Expired)
+    ));
+
+    // Changing the client id or expiry, or a token from a different issuer, must fail.
+    let mut tampered = token;
+    tampered[4] ^= 1;
+    assert!(matches!(
+        validator.validate(&tampered),
+        Err(TokenError::Unauthenticated)
+    ));
+    assert!(matches!(
+        TokenValidator::new(b"another secret").validate(&token),
+        Err(TokenError::Unauthenticated)
+    ));
+    assert!(matches!(
+        validator.validate(&token[1..]),
+        Err(TokenError::Malformed)
+    ));
+
+    let mut request = vec![255];
+    request.extend(&token);
+    assert_eq!(
+        validator.validate_request(255, &request).unwrap().client_id,
+        17
+    );
+    assert!(matches!(
+        validator.validate_request(254, &request),
+        Err(TokenError::Malformed)
+    ));
+}
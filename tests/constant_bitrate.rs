@@ -0,0 +1,75 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{channel::mpsc, stream, StreamExt};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    constant_bitrate::{constant_bitrate, unpadded, PaddedPacket, PaddedPacketPool, Settings},
+    packet::{Packet, PacketPool},
+    runtime::{Runtime, Timer},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[test]
+fn test_constant_bitrate() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let pool = PaddedPacketPool::new(raw_pool);
+    let mut runtime = SimpleRuntime::new();
+    let start = runtime.handle().now();
+
+    let (sender, receiver) = mpsc::unbounded::<PaddedPacket<BufferPacket<Box<[u8]>>>>();
+    let mut padded = constant_bitrate(
+        runtime.handle(),
+        Settings {
+            interval: Duration::from_millis(10),
+            max_queue: 16,
+        },
+        raw_pool,
+        receiver,
+    );
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    runtime.spawn({
+        let handle = runtime.handle();
+        let sent = Arc::clone(&sent);
+        async move {
+            while let Some(packet) = padded.next().await {
+                sent.lock()
+                    .unwrap()
+                    .push((handle.elapsed(start).as_millis() as u64, packet));
+            }
+        }
+    });
+
+    // A burst of packets is sent one per interval.
+    for i in 1..=3 {
+        let mut packet = pool.acquire();
+        packet.resize(i * 5, i as u8);
+        sender.unbounded_send(packet).unwrap();
+    }
+    for _ in 0..60 {
+        runtime.run_until_stalled();
+        runtime.advance_time(1);
+    }
+
+    let sent: Vec<_> = sent.lock().unwrap().drain(..).collect();
+    assert_eq!(
+        sent.iter().map(|(time, _)| *time).collect::<Vec<_>>(),
+        vec![10, 20, 30, 40, 50]
+    );
+    // Every packet is the same size, real or padding.
+    assert!(sent.iter().all(|(_, packet)| packet.len() == 64));
+
+    let received: Vec<_> = futures::executor::block_on(
+        unpadded(stream::iter(sent.into_iter().map(|(_, packet)| packet)))
+            .map(|packet| packet.to_vec())
+            .collect(),
+    );
+    assert_eq!(received, vec![vec![1; 5], vec![2; 10], vec![3; 15]]);
+}
@@ -0,0 +1,60 @@
+use futures::{executor::block_on, stream, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    debug_header::{debug_headers, strip_debug_headers, DebugPacketPool, HEADER_LEN},
+    packet::{Packet, PacketPool},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+#[test]
+fn test_debug_headers() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let pool = DebugPacketPool::new(raw_pool);
+
+    // Packets as the multiplexer writes them, a channel byte followed by the payload.
+    let packets: Vec<_> = [&[3, 1, 2][..], &[7, 9, 9, 9, 9]]
+        .iter()
+        .map(|data| {
+            let mut packet = pool.acquire();
+            packet.extend(data);
+            packet
+        })
+        .collect();
+    assert_eq!(packets[0].capacity(), 32 - HEADER_LEN + 1);
+
+    let sent: Vec<_> = block_on(debug_headers(stream::iter(packets)).collect());
+    assert_eq!(
+        &sent[0][..],
+        &[b'T', b'R', b'B', b'D', 1, 0, 0, 2, 0, 0, 0, 0, 3, 1, 2]
+    );
+    assert_eq!(
+        &sent[1][..HEADER_LEN],
+        &[b'T', b'R', b'B', b'D', 1, 0, 0, 4, 0, 0, 0, 1, 7]
+    );
+
+    // Packets without a valid header are dropped.
+    let incoming: Vec<_> = [
+        &[3, 1, 2][..],
+        &sent[0],
+        &sent[1][..sent[1].len() - 1],
+        &sent[1],
+    ]
+    .iter()
+    .map(|data| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    })
+    .collect();
+
+    let received: Vec<_> = block_on(
+        strip_debug_headers(stream::iter(incoming))
+            .map(|packet| packet.to_vec())
+            .collect(),
+    );
+    assert_eq!(received, vec![vec![3, 1, 2], vec![7, 9, 9, 9, 9]]);
+}
@@ -0,0 +1,96 @@
+use bincode::Options;
+use futures::{channel::mpsc, FutureExt};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    decode_limits::DecodeLimits,
+    unreliable_bincode_channel::{RecvError, UnreliableBincodeChannel},
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Tree {
+    Leaf(String),
+    Node(Box<Tree>, Option<Box<Tree>>),
+}
+
+fn nested(depth: usize) -> Tree {
+    let mut tree = Tree::Leaf("leaf".to_owned());
+    for _ in 0..depth {
+        tree = Tree::Node(Box::new(tree), None);
+    }
+    tree
+}
+
+#[test]
+fn test_decode_limits() {
+    let limits = DecodeLimits {
+        max_size: 4096,
+        max_depth: 32,
+    };
+    let decode = |bytes: &[u8]| bincode::options().deserialize_seed(limits.seed::<Tree>(), bytes);
+
+    let shallow = bincode::options().serialize(&nested(4)).unwrap();
+    assert_eq!(decode(&shallow).unwrap(), nested(4));
+
+    let deep = bincode::options().serialize(&nested(40)).unwrap();
+    assert!(bincode::options().deserialize::<Tree>(&deep).is_ok());
+    let err = decode(&deep).unwrap_err();
+    assert!(err.to_string().contains("depth limit"), "{}", err);
+
+    // A few bytes claiming a huge sequence of zero sized elements.
+    let huge = bincode::options().serialize(&(1u64 << 40)).unwrap();
+    let err = bincode::options()
+        .deserialize_seed(limits.seed::<Vec<()>>(), &huge)
+        .unwrap_err();
+    assert!(err.to_string().contains("size limit"), "{}", err);
+
+    let strings = bincode::options()
+        .serialize(&vec!["a string of some length"; 200])
+        .unwrap();
+    let err = bincode::options()
+        .deserialize_seed(limits.seed::<Vec<String>>(), &strings)
+        .unwrap_err();
+    assert!(err.to_string().contains("size limit"), "{}", err);
+}
+
+#[test]
+fn test_channel_decode_limits() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 4096,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut sender = UnreliableBincodeChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        1024,
+    );
+    let mut receiver = UnreliableBincodeChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+        1024,
+    );
+    receiver.set_decode_limits(Some(DecodeLimits {
+        max_size: 1024,
+        max_depth: 8,
+    }));
+
+    sender.send(&nested(2)).now_or_never().unwrap().unwrap();
+    sender.send(&nested(20)).now_or_never().unwrap().unwrap();
+    sender.send(&nested(3)).now_or_never().unwrap().unwrap();
+    sender.flush().now_or_never().unwrap().unwrap();
+
+    let mut recv = || receiver.recv::<Tree>().now_or_never().unwrap();
+    assert_eq!(recv().unwrap(), nested(2));
+    assert!(matches!(recv(), Err(RecvError::BincodeError(_))));
+    assert_eq!(recv().unwrap(), nested(3));
+}
@@ -0,0 +1,124 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{self, Either},
+    pin_mut, FutureExt, StreamExt,
+};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    delta_channel::DeltaChannel,
+    runtime::{Runtime, Timer},
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 65536,
+};
+
+#[test]
+fn test_delta_channel() {
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel::<BufferPacket<Box<[u8]>>>(8);
+    let (lossy_send, lossy_recv) = mpsc::channel(8);
+
+    // Drop every third packet from A to B, and record the sizes of those that get through.
+    let sizes = Arc::new(Mutex::new(Vec::new()));
+    runtime.spawn({
+        let sizes = Arc::clone(&sizes);
+        brecv
+            .enumerate()
+            .filter(|(i, _)| future::ready(i % 3 != 1))
+            .map(move |(_, packet)| {
+                sizes.lock().unwrap().push(packet.len());
+                Ok(packet)
+            })
+            .forward(lossy_send)
+            .map(|_| ())
+    });
+
+    let mut channel_a = DeltaChannel::new(UnreliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        arecv,
+        bsend,
+    ));
+    let mut channel_b = DeltaChannel::new(UnreliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        lossy_recv,
+        asend,
+    ));
+
+    let state = |tick: u8| {
+        let mut state = vec![7; 200];
+        state[10] = tick;
+        state[150] = tick.wrapping_mul(3);
+        state
+    };
+
+    runtime.spawn({
+        let handle = runtime.handle();
+        async move {
+            for tick in 0..20 {
+                channel_a.send(&state(tick)).await.unwrap();
+                channel_a.flush().await.unwrap();
+
+                // Process acknowledgements until the next tick.
+                let sleep = handle.sleep(std::time::Duration::from_millis(50));
+                pin_mut!(sleep);
+                loop {
+                    let recv = channel_a.recv();
+                    pin_mut!(recv);
+                    match future::select(recv, sleep.as_mut()).await {
+                        Either::Left((res, _)) => {
+                            res.unwrap();
+                        }
+                        Either::Right(_) => break,
+                    }
+                }
+            }
+            assert!(channel_a.baseline().is_some());
+        }
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        // Receive until A is finished and drops its channel.
+        let mut received = Vec::new();
+        while let Ok(state) = channel_b.recv().await {
+            received.push(state.to_vec());
+            let _ = channel_b.flush().await;
+        }
+        let _ = done_send.send(received);
+    });
+
+    for _ in 0..100 {
+        runtime.run_until_stalled();
+        if let Some(received) = done.try_recv().unwrap() {
+            // Every received state is reconstructed exactly, despite the lost packets.
+            for s in &received {
+                assert_eq!(s, &state(s[10]));
+            }
+            assert_eq!(received.len(), 13);
+
+            let sizes = sizes.lock().unwrap();
+            assert!(sizes[0] > 200);
+            assert!(*sizes.last().unwrap() < 30);
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
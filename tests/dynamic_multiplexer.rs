@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use futures::{channel::oneshot, executor::block_on, FutureExt, SinkExt, StreamExt};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    dynamic_multiplexer::{
+        DynPacketPool, DynamicIncoming, DynamicMultiplexer, DynamicOutgoing, IncomingError,
+        IncomingTrySendError, OpenError, RecvError,
+    },
+    packet::{Packet, PacketPool},
+    runtime::Runtime,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+type TestPacket = BufferPacket<Box<[u8]>>;
+
+#[test]
+fn test_dynamic_generations() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let packet_pool = DynPacketPool::new(raw_pool);
+    let (mux_a, _, mut outgoing_a) = DynamicMultiplexer::new(raw_pool, 8);
+    let (mux_b, mut incoming_b, _) = DynamicMultiplexer::new(raw_pool, 8);
+
+    let packet = |val| {
+        let mut packet = packet_pool.acquire();
+        packet.resize(1, val);
+        packet
+    };
+
+    let (mut sender_a, _) = mux_a.open_channel(3, 8).unwrap();
+    let (_, mut receiver_b) = mux_b.open_channel(3, 8).unwrap();
+    assert!(mux_a.open_channel(3, 8).is_err());
+
+    block_on(async {
+        sender_a.send(packet(1)).await.unwrap();
+        sender_a.send(packet(2)).await.unwrap();
+        let first = outgoing_a.next().await.unwrap();
+        assert_eq!(&first[..], &[3, 0, 1]);
+        incoming_b.send(first).await.unwrap();
+        assert_eq!(&receiver_b.next().await.unwrap()[..], &[1]);
+
+        // The second packet is delayed until after the channel is reopened on both sides.
+        let late = outgoing_a.next().await.unwrap();
+        assert!(mux_a.close_channel(3));
+        assert!(!mux_a.close_channel(3));
+        assert!(sender_a.send(packet(3)).await.is_err());
+
+        // The close notice closes the peer's side of the channel.
+        incoming_b
+            .send(outgoing_a.next().await.unwrap())
+            .await
+            .unwrap();
+        assert!(matches!(
+            receiver_b.recv().await,
+            Err(RecvError::ClosedByPeer)
+        ));
+        assert!(!mux_b.close_channel(3));
+
+        let (mut sender_a, _) = mux_a.open_channel(3, 8).unwrap();
+        let (_, mut receiver_b) = mux_b.open_channel(3, 8).unwrap();
+        assert_eq!(mux_b.generation(3), 1);
+
+        assert!(matches!(
+            incoming_b.try_send(late),
+            Err(IncomingTrySendError::Error(IncomingError::StaleGeneration))
+        ));
+        sender_a.send(packet(4)).await.unwrap();
+        incoming_b
+            .send(outgoing_a.next().await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(&receiver_b.next().await.unwrap()[..], &[4]);
+    });
+
+    assert_eq!(mux_a.channels(), vec![3]);
+}
+
+// Deliver every packet sent so far in both directions.
+fn forward(
+    a: &mut (DynamicIncoming<TestPacket>, DynamicOutgoing<TestPacket>),
+    b: &mut (DynamicIncoming<TestPacket>, DynamicOutgoing<TestPacket>),
+) {
+    while let Some(Some(packet)) = a.1.next().now_or_never() {
+        let _ = b.0.try_send(packet);
+    }
+    while let Some(Some(packet)) = b.1.next().now_or_never() {
+        let _ = a.0.try_send(packet);
+    }
+}
+
+#[test]
+fn test_dynamic_open() {
+    let mut runtime = SimpleRuntime::new();
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let (mux_a, incoming_a, outgoing_a) = DynamicMultiplexer::new(raw_pool, 8);
+    let (mux_b, incoming_b, outgoing_b) = DynamicMultiplexer::new(raw_pool, 8);
+    let mut a = (incoming_a, outgoing_a);
+    let mut b = (incoming_b, outgoing_b);
+
+    let (opened_send, mut opened) = oneshot::channel();
+    runtime.spawn({
+        let mux_a = mux_a.clone();
+        let handle = runtime.handle();
+        async move {
+            let res = mux_a.open(&handle, 5, 8, Duration::from_secs(1)).await;
+            let _ = opened_send.send(res.map(|_| ()));
+        }
+    });
+
+    // The peer only opens the channel after a few open requests have been ignored, and the open
+    // returns once the next request is acknowledged.
+    let mut opened_at = None;
+    for time in (0..1000).step_by(100) {
+        if time == 500 {
+            mux_b.open_channel(5, 8).unwrap();
+        }
+        runtime.run_until_stalled();
+        forward(&mut a, &mut b);
+        runtime.run_until_stalled();
+        if opened_at.is_none() {
+            if let Some(res) = opened.try_recv().unwrap() {
+                res.unwrap();
+                opened_at = Some(time);
+            }
+        }
+        runtime.advance_time(100);
+    }
+    assert_eq!(opened_at, Some(600));
+    assert!(mux_a.is_confirmed(5));
+    assert!(mux_b.is_confirmed(5));
+
+    // Nobody opens channel 6, so opening it times out.
+    let (opened_send, mut opened) = oneshot::channel();
+    runtime.spawn({
+        let mux_a = mux_a.clone();
+        let handle = runtime.handle();
+        async move {
+            let res = mux_a.open(&handle, 6, 8, Duration::from_millis(500)).await;
+            let _ = opened_send.send(res.map(|_| ()));
+        }
+    });
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        forward(&mut a, &mut b);
+        runtime.advance_time(100);
+    }
+    assert!(matches!(
+        opened.try_recv().unwrap(),
+        Some(Err(OpenError::TimedOut))
+    ));
+    assert_eq!(mux_a.channels(), vec![5]);
+    assert_eq!(mux_a.generation(6), 0);
+}
+
+#[test]
+fn test_dynamic_close() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let packet_pool = DynPacketPool::new(raw_pool);
+    let (mux_a, incoming_a, outgoing_a) = DynamicMultiplexer::new(raw_pool, 8);
+    let (mux_b, incoming_b, outgoing_b) = DynamicMultiplexer::new(raw_pool, 8);
+    let mut a = (incoming_a, outgoing_a);
+    let mut b = (incoming_b, outgoing_b);
+
+    let (_, mut receiver_a) = mux_a.open_channel(1, 8).unwrap();
+    let (mut sender_b, mut receiver_b) = mux_b.open_channel(1, 8).unwrap();
+
+    // The close notice is lost, so it is only resent once the peer sends on the closed channel.
+    mux_a.close_channel(1);
+    a.1.next().now_or_never().unwrap().unwrap();
+    assert!(matches!(
+        block_on(receiver_a.recv()),
+        Err(RecvError::Closed)
+    ));
+
+    let mut packet = packet_pool.acquire();
+    packet.resize(1, 7);
+    block_on(sender_b.send(packet)).unwrap();
+    forward(&mut a, &mut b);
+    forward(&mut a, &mut b);
+    assert!(matches!(
+        block_on(receiver_b.recv()),
+        Err(RecvError::ClosedByPeer)
+    ));
+    assert_eq!(mux_a.generation(1), mux_b.generation(1));
+}
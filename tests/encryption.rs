@@ -0,0 +1,279 @@
+#![cfg(feature = "encryption")]
+
+use futures::{channel::mpsc, SinkExt, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    encryption::{
+        encrypted, ChannelDecryptor, ChannelEncryptor, DecryptError, Decryptor,
+        EncryptedPacketPool, Encryptor, Keys,
+    },
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    runtime::Runtime,
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+const KEY_A: [u8; 32] = [1; 32];
+const KEY_B: [u8; 32] = [2; 32];
+
+#[test]
+fn test_encrypt_decrypt() {
+    let pool = EncryptedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)));
+    let mut encryptor = Encryptor::new(&KEY_A);
+    let mut decryptor = Decryptor::new(&KEY_A);
+
+    // Packets too small for the overhead have no room rather than underflowing.
+    let tiny_pool = EncryptedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(16)));
+    assert_eq!(tiny_pool.acquire().capacity(), 0);
+
+    let mut packet = pool.acquire();
+    assert_eq!(packet.capacity(), 40);
+    packet.extend(b"hello");
+    let encrypted = encryptor.encrypt(packet);
+    assert_eq!(encrypted.len(), 5 + 24);
+    assert_ne!(&encrypted[8..13], b"hello");
+
+    let mut replayed = BufferPacketPool::new(SimpleBufferPool(64)).acquire();
+    replayed.extend(&encrypted);
+
+    let (sequence, decrypted) = decryptor.decrypt(encrypted).unwrap();
+    assert_eq!(sequence, 0);
+    assert_eq!(&decrypted[..], b"hello");
+
+    // A recorded packet cannot be decrypted a second time.
+    assert!(matches!(
+        decryptor.decrypt(replayed),
+        Err(DecryptError::Replayed)
+    ));
+
+    // Every packet gets a new sequence number, and so a new nonce.
+    let mut packet = pool.acquire();
+    packet.extend(b"hello");
+    let mut encrypted = encryptor.encrypt(packet);
+    assert_eq!(encryptor.sequence(), 2);
+
+    // Tampering with the ciphertext, or decrypting with the wrong key, must fail.
+    encrypted[9] ^= 1;
+    assert!(matches!(
+        decryptor.decrypt(encrypted),
+        Err(DecryptError::Unauthenticated)
+    ));
+
+    let mut packet = pool.acquire();
+    packet.extend(b"hello");
+    let encrypted = encryptor.encrypt(packet);
+    assert!(matches!(
+        Decryptor::new(&KEY_B).decrypt(encrypted),
+        Err(DecryptError::Unauthenticated)
+    ));
+
+    let mut short = BufferPacketPool::new(SimpleBufferPool(64)).acquire();
+    short.extend(&[0; 10]);
+    assert!(matches!(
+        decryptor.decrypt(short),
+        Err(DecryptError::TooShort)
+    ));
+}
+
+#[test]
+fn test_encrypted_multiplexer() {
+    let mut runtime = SimpleRuntime::new();
+    let buffer_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let pool = MuxPacketPool::new(EncryptedPacketPool::new(buffer_pool));
+
+    let keys_a = Keys {
+        send: KEY_A,
+        recv: KEY_B,
+    };
+    let keys_b = Keys {
+        send: KEY_B,
+        recv: KEY_A,
+    };
+
+    // Raw transport packets, connecting A and B.
+    let (a_send, b_recv) = mpsc::channel(8);
+    let (b_send, a_recv) = mpsc::channel(8);
+
+    let mut multiplexer_a = PacketMultiplexer::new();
+    let (a_out, a_in, _) = multiplexer_a.open_channel(0, 8).unwrap();
+    let mut multiplexer_b = PacketMultiplexer::new();
+    let (b_out, b_in, _) = multiplexer_b.open_channel(0, 8).unwrap();
+
+    // Tampered packets injected into the transport must be dropped.
+    let mut inject = a_send.clone();
+
+    for (multiplexer, keys, incoming, outgoing) in [
+        (multiplexer_a, keys_a, a_recv, a_send),
+        (multiplexer_b, keys_b, b_recv, b_send),
+    ] {
+        let (mut decrypted, mut encrypted) = encrypted(&keys, incoming, outgoing);
+        let (mut mux_incoming, mut mux_outgoing) = multiplexer.start();
+        runtime.spawn(async move {
+            while let Some(packet) = decrypted.next().await {
+                let _ = mux_incoming.try_send(packet);
+            }
+        });
+        runtime.spawn(async move {
+            while let Some(packet) = mux_outgoing.next().await {
+                encrypted.send(packet).await.unwrap();
+            }
+        });
+    }
+
+    let mut channel_a = UnreliableChannel::new(
+        runtime.handle(),
+        pool.clone(),
+        Settings {
+            bandwidth: 4096,
+            burst_bandwidth: 4096,
+        },
+        a_in,
+        a_out,
+    );
+    let mut channel_b = UnreliableChannel::new(
+        runtime.handle(),
+        pool,
+        Settings {
+            bandwidth: 4096,
+            burst_bandwidth: 4096,
+        },
+        b_in,
+        b_out,
+    );
+
+    let (done_send, mut done) = futures::channel::oneshot::channel();
+    runtime.spawn(async move {
+        let mut garbage = buffer_pool.acquire();
+        garbage.extend(&[7; 40]);
+        inject.send(garbage).await.unwrap();
+
+        channel_a.send(b"secret").await.unwrap();
+        channel_a.flush().await.unwrap();
+
+        assert_eq!(channel_b.recv().await.unwrap(), b"secret");
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..100 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
+
+#[test]
+fn test_rekeying() {
+    let pool = EncryptedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)));
+    let mut encryptor = Encryptor::with_rekey_interval(&KEY_A, 4);
+    let mut decryptor = Decryptor::with_rekey_interval(&KEY_A, 4);
+
+    let mut encrypted: Vec<_> = (0..20u8)
+        .map(|i| {
+            let mut packet = pool.acquire();
+            packet.extend(&[i]);
+            Some(encryptor.encrypt(packet))
+        })
+        .collect();
+    let mut take = |sequence: usize| encrypted[sequence].take().unwrap();
+
+    // Packets after the first rekey must not be decryptable with the original key.
+    assert!(matches!(
+        Decryptor::new(&KEY_A).decrypt(take(5)),
+        Err(DecryptError::Unauthenticated)
+    ));
+
+    assert_eq!(decryptor.decrypt(take(0)).unwrap().0, 0);
+    // Skipping ahead several epochs derives the keys in between.
+    assert_eq!(decryptor.decrypt(take(9)).unwrap().0, 9);
+    // Packets from the previous epoch are still accepted, but not from earlier.
+    assert_eq!(decryptor.decrypt(take(4)).unwrap().0, 4);
+    assert_eq!(&decryptor.decrypt(take(8)).unwrap().1[..], &[8]);
+    assert!(matches!(
+        decryptor.decrypt(take(1)),
+        Err(DecryptError::ExpiredKey)
+    ));
+    assert_eq!(decryptor.decrypt(take(12)).unwrap().0, 12);
+    assert_eq!(decryptor.decrypt(take(11)).unwrap().0, 11);
+    assert!(matches!(
+        decryptor.decrypt(take(6)),
+        Err(DecryptError::ExpiredKey)
+    ));
+}
+
+#[test]
+fn test_channel_encryption() {
+    let pool = EncryptedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)));
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(64));
+
+    // Channels 1 and 2 are encrypted with independent keys, channel 3 is plaintext.
+    let mut encryptor = ChannelEncryptor::new(vec![
+        (1, Encryptor::new(&KEY_A)),
+        (2, Encryptor::new(&KEY_B)),
+    ]);
+    let mut decryptor = ChannelDecryptor::new(vec![
+        (1, Decryptor::new(&KEY_A)),
+        (2, Decryptor::new(&KEY_B)),
+    ]);
+    assert!(encryptor.is_encrypted(2));
+    assert!(!decryptor.is_encrypted(3));
+
+    let mut packet = pool.acquire();
+    packet.extend(b"\x01voice");
+    let voice = encryptor.encrypt(packet);
+    assert_eq!(voice.len(), 1 + 8 + 5 + 16);
+    assert_eq!(voice[0], 1);
+    assert_ne!(&voice[9..14], b"voice");
+
+    let mut packet = pool.acquire();
+    packet.extend(b"\x03telemetry");
+    let telemetry = encryptor.encrypt(packet);
+    assert_eq!(&telemetry[..], b"\x03telemetry");
+
+    let mut replayed = raw_pool.acquire();
+    replayed.extend(&voice);
+
+    let (channel, decrypted) = decryptor.decrypt(voice).unwrap();
+    assert_eq!(channel, 1);
+    assert_eq!(&decrypted[..], b"\x01voice");
+    let (channel, decrypted) = decryptor.decrypt(telemetry).unwrap();
+    assert_eq!(channel, 3);
+    assert_eq!(&decrypted[..], b"\x03telemetry");
+    assert!(matches!(
+        decryptor.decrypt(replayed),
+        Err(DecryptError::Replayed)
+    ));
+
+    // Each channel has its own sequence numbers, and the channel is authenticated, so a packet
+    // cannot be moved to another encrypted channel.
+    for _ in 0..2 {
+        let mut packet = pool.acquire();
+        packet.extend(b"\x02voice");
+        let encrypted = encryptor.encrypt(packet);
+        assert_eq!(decryptor.decrypt(encrypted).unwrap().0, 2);
+    }
+    let mut packet = pool.acquire();
+    packet.extend(b"\x02voice");
+    let mut moved = encryptor.encrypt(packet);
+    assert_eq!(&moved[1..9], &[2, 0, 0, 0, 0, 0, 0, 0]);
+    moved[0] = 1;
+    assert!(matches!(
+        decryptor.decrypt(moved),
+        Err(DecryptError::Unauthenticated)
+    ));
+
+    // Plaintext on an encrypted channel is rejected.
+    let mut forged = raw_pool.acquire();
+    forged.extend(&[2; 30]);
+    assert!(matches!(
+        decryptor.decrypt(forged),
+        Err(DecryptError::Unauthenticated)
+    ));
+}
@@ -0,0 +1,146 @@
+use std::sync::{Arc, Mutex};
+
+use bincode::Options;
+use futures::{channel::mpsc, executor::block_on};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    envelope::Envelope,
+    runtime::Runtime,
+    unreliable_bincode_channel::{RecvError, UnreliableBincodeChannel, UnreliableTypedChannel},
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PlayerV1 {
+    name: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PlayerV2 {
+    name: String,
+    score: u32,
+}
+
+#[test]
+fn test_envelope_upgrade() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let old_client = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let server = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    let envelope = Envelope::new(2).upgrade_from(1, |old: PlayerV1| PlayerV2 {
+        name: old.name,
+        score: 0,
+    });
+    assert_eq!(envelope.version(), 2);
+    assert!(envelope.supports(1));
+    assert!(!envelope.supports(3));
+
+    let mut old_client = UnreliableTypedChannel::with_envelope(
+        UnreliableBincodeChannel::new(old_client, 512),
+        Envelope::new(1),
+    );
+    let mut server =
+        UnreliableTypedChannel::with_envelope(UnreliableBincodeChannel::new(server, 512), envelope);
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    runtime.spawn({
+        let received = Arc::clone(&received);
+        async move {
+            loop {
+                let msg = match server.recv().await {
+                    Ok(msg) => Some(msg),
+                    Err(RecvError::BincodeError(_)) => None,
+                    Err(err) => panic!("{:?}", err),
+                };
+                received.lock().unwrap().push(msg);
+            }
+        }
+    });
+    runtime.run_until_stalled();
+
+    block_on(async {
+        old_client
+            .send(&PlayerV1 {
+                name: "old".to_owned(),
+            })
+            .await
+            .unwrap();
+        old_client.flush().await.unwrap();
+    });
+    runtime.run_until_stalled();
+
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![Some(PlayerV2 {
+            name: "old".to_owned(),
+            score: 0,
+        })]
+    );
+}
+
+#[test]
+fn test_envelope_unsupported_version() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let client = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let server = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    let mut client = UnreliableBincodeChannel::new(client, 512);
+    let mut server = UnreliableTypedChannel::with_envelope(
+        UnreliableBincodeChannel::new(server, 512),
+        Envelope::<u32>::new(2),
+    );
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    runtime.spawn({
+        let received = Arc::clone(&received);
+        async move {
+            loop {
+                let msg = match server.recv().await {
+                    Ok(msg) => Some(msg),
+                    Err(RecvError::BincodeError(_)) => None,
+                    Err(err) => panic!("{:?}", err),
+                };
+                received.lock().unwrap().push(msg);
+            }
+        }
+    });
+    runtime.run_until_stalled();
+
+    // A message from a newer peer with an unknown version is skipped, and does not affect the
+    // messages after it.
+    block_on(async {
+        let payload = bincode::options().serialize(&7u32).unwrap();
+        client.send(&(3u16, &payload[..])).await.unwrap();
+        client.send(&(2u16, &payload[..])).await.unwrap();
+        client.flush().await.unwrap();
+    });
+    runtime.run_until_stalled();
+
+    assert_eq!(*received.lock().unwrap(), vec![None, Some(7)]);
+}
@@ -0,0 +1,71 @@
+use std::error::Error as _;
+
+use turbulence::{
+    message_channels::ChannelTaskError, reliable_bincode_channel, reliable_channel,
+    unreliable_bincode_channel, unreliable_channel, Error, ErrorKind,
+};
+
+fn send(too_big: bool) -> Result<(), Error> {
+    if too_big {
+        Err(
+            unreliable_bincode_channel::SendError::UnreliableChannelError(
+                unreliable_channel::SendError::TooBig,
+            ),
+        )?;
+    }
+    Err(reliable_bincode_channel::Error::ReliableChannelError(
+        reliable_channel::Error::Disconnected,
+    ))?;
+    Ok(())
+}
+
+#[test]
+fn test_error_conversion() {
+    let err = send(true).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TooLarge);
+    assert!(!err.is_fatal());
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(
+            unreliable_bincode_channel::SendError::UnreliableChannelError(
+                unreliable_channel::SendError::TooBig
+            )
+        )
+    ));
+
+    let err = send(false).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Disconnected);
+    assert!(err.is_fatal());
+
+    // The whole chain of sources is preserved.
+    let source = err.source().unwrap();
+    assert!(source.is::<reliable_bincode_channel::Error>());
+    assert!(source.source().unwrap().is::<reliable_channel::Error>());
+}
+
+#[test]
+fn test_error_context() {
+    let err = Error::from(unreliable_channel::RecvError::BadFormat)
+        .with_channel(3)
+        .with_message_type::<u32>();
+    assert_eq!(err.kind(), ErrorKind::Protocol);
+    assert_eq!(err.channel(), Some(3));
+    assert_eq!(err.message_type(), Some("u32"));
+    assert_eq!(
+        err.to_string(),
+        "channel 3 (u32): incoming packet has bad message format"
+    );
+
+    let err = Error::from(ChannelTaskError {
+        type_name: "Position",
+        error: Box::new(reliable_bincode_channel::Error::PrefixTooLarge),
+    });
+    assert_eq!(err.kind(), ErrorKind::TooLarge);
+    assert!(err.is_fatal());
+    assert_eq!(err.message_type(), Some("Position"));
+    assert!(err.into_source().is::<reliable_bincode_channel::Error>());
+
+    let err = Error::from(reliable_bincode_channel::Error::MessageTooLarge(100));
+    assert_eq!(err.kind(), ErrorKind::TooLarge);
+    assert!(!err.is_fatal());
+}
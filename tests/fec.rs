@@ -0,0 +1,112 @@
+use futures::{channel::mpsc, FutureExt, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    fec::{FecDecoder, FecEncoder, FecPacketPool, FecSink, FecStream, Settings},
+    packet::{Packet, PacketPool},
+    runtime::Runtime,
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[test]
+fn test_fec_recovery() {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let fec_pool = FecPacketPool::new(pool);
+    let mut encoder = FecEncoder::new(&Settings { group_size: 3 });
+    let mut decoder = FecDecoder::new();
+
+    let mut sent = Vec::new();
+    for data in [&[1, 2, 3][..], &[4], &[5, 6], &[7, 8, 9, 10], &[11], &[12]] {
+        let mut packet = fec_pool.acquire();
+        assert_eq!(packet.capacity(), 58);
+        packet.extend(data);
+        let (packet, parity) = encoder.encode(&pool, packet);
+        sent.push(packet);
+        sent.extend(parity);
+    }
+    // Two groups of three data packets, each followed by a parity packet.
+    assert_eq!(sent.len(), 8);
+
+    let mut received = Vec::new();
+    for (i, packet) in sent.into_iter().enumerate() {
+        // Lose one packet from the first group, and two from the second.
+        if i == 1 || i == 4 || i == 6 {
+            continue;
+        }
+        let decoded = decoder.decode(&pool, packet).unwrap();
+        received.extend(decoded.packet.map(|p| p.to_vec()));
+        received.extend(decoded.recovered.map(|p| p.to_vec()));
+    }
+
+    assert_eq!(received, vec![vec![1, 2, 3], vec![5, 6], vec![4], vec![11]]);
+    assert_eq!(decoder.recovered(), 1);
+}
+
+#[test]
+fn test_fec_unreliable_channel() {
+    let mut runtime = SimpleRuntime::new();
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let settings = Settings { group_size: 2 };
+
+    let (send, recv) = mpsc::channel(8);
+    let (lossy_send, lossy_recv) = mpsc::channel(8);
+    let fec_outgoing = FecSink::new(FecEncoder::new(&settings), pool, send);
+    let fec_incoming = FecStream::new(FecDecoder::new(), pool, lossy_recv);
+
+    // Drop every third packet, which is the first data packet of each group.
+    runtime.spawn(
+        recv.enumerate()
+            .filter(|(i, _)| futures::future::ready(i % 3 != 0))
+            .map(|(_, packet)| Ok(packet))
+            .forward(lossy_send)
+            .map(|_| ()),
+    );
+
+    let channel_settings = unreliable_channel::Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 4096,
+    };
+    let mut channel_a = UnreliableChannel::new(
+        runtime.handle(),
+        FecPacketPool::new(pool),
+        channel_settings.clone(),
+        futures::stream::pending(),
+        fec_outgoing,
+    );
+    let mut channel_b = UnreliableChannel::new(
+        runtime.handle(),
+        FecPacketPool::new(pool),
+        channel_settings,
+        fec_incoming,
+        futures::sink::drain(),
+    );
+
+    let (done_send, mut done) = futures::channel::oneshot::channel();
+    runtime.spawn(async move {
+        for i in 0..4u8 {
+            channel_a.send(&[i; 10]).await.unwrap();
+            channel_a.flush().await.unwrap();
+        }
+
+        // The lost packets arrive after the rest of their group, once they are recovered.
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            received.push(channel_b.recv().await.unwrap()[0]);
+        }
+        assert_eq!(received, vec![1, 0, 3, 2]);
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..100 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
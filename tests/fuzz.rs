@@ -0,0 +1,142 @@
+#![cfg(feature = "fuzz")]
+
+use std::collections::BTreeMap;
+
+use bincode::Options;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use turbulence::{
+    conformance,
+    fuzz::{self, ReliableMessage, UnreliableMessage, CHANNELS},
+};
+
+const ITERATIONS: usize = 500;
+
+// Encode packets as fuzzer input, the inverse of `fuzz::packets`.
+fn input(packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for packet in packets {
+        input.push(packet.len() as u8);
+        input.extend(packet);
+    }
+    input
+}
+
+fn unreliable_packet(msg: &UnreliableMessage) -> Vec<u8> {
+    let msg = bincode::options().serialize(msg).unwrap();
+    let mut packet = (msg.len() as u16).to_le_bytes().to_vec();
+    packet.extend(msg);
+    packet
+}
+
+fn reliable_packet(start: u32, msg: &ReliableMessage) -> Vec<u8> {
+    let msg = bincode::options().serialize(msg).unwrap();
+    let mut data = (msg.len() as u16).to_le_bytes().to_vec();
+    data.extend(msg);
+    let mut packet = (data.len() as i16).to_le_bytes().to_vec();
+    packet.extend(start.to_le_bytes());
+    packet.extend(data);
+    packet
+}
+
+fn with_channel(channel: u8, packet: &[u8]) -> Vec<u8> {
+    let mut muxed = vec![channel];
+    muxed.extend(packet);
+    muxed
+}
+
+// Randomly corrupt a valid input, so that inputs get past the first layers of parsing.
+fn mutate(rng: &mut SmallRng, input: &[u8]) -> Vec<u8> {
+    let mut input = input.to_vec();
+    for _ in 0..rng.gen_range(1..4) {
+        let pos = rng.gen_range(0..=input.len());
+        match rng.gen_range(0..5) {
+            0 if pos < input.len() => input[pos] ^= 1 << rng.gen_range(0..8),
+            1 if pos < input.len() => input[pos] = rng.gen(),
+            2 => input.insert(pos, rng.gen()),
+            3 if pos < input.len() => {
+                input.remove(pos);
+            }
+            _ => input.truncate(pos),
+        }
+    }
+    input
+}
+
+fn random(rng: &mut SmallRng) -> Vec<u8> {
+    let len = rng.gen_range(0..1024);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+fn run(seed: u64, corpus: &[Vec<u8>], target: fn(&[u8])) {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    for input in corpus {
+        target(input);
+    }
+    for _ in 0..ITERATIONS {
+        let input = if rng.gen_bool(0.5) {
+            random(&mut rng)
+        } else {
+            let i = rng.gen_range(0..corpus.len());
+            mutate(&mut rng, &corpus[i])
+        };
+        target(&input);
+    }
+}
+
+fn unreliable_message() -> UnreliableMessage {
+    (7, -300, "hello".to_owned(), Some(vec![1, 2, 3]))
+}
+
+fn reliable_message() -> ReliableMessage {
+    let mut map = BTreeMap::new();
+    map.insert(1, "one".to_owned());
+    (vec![4, 5, 6], map, true, 1.5)
+}
+
+#[test]
+fn test_packets() {
+    let packets: Vec<_> = fuzz::packets(b"\x02ab\x00\x05cd").collect();
+    assert_eq!(packets, vec![&b"ab"[..], b"", b"cd"]);
+}
+
+#[test]
+fn test_fuzz_multiplexer() {
+    let corpus: Vec<_> = conformance::MUX
+        .iter()
+        .map(|vector| input(&[vector.packet.to_vec(), Vec::new(), vec![CHANNELS[1]; 9]]))
+        .collect();
+    run(1, &corpus, fuzz::multiplexer);
+}
+
+#[test]
+fn test_fuzz_unreliable_channel() {
+    let mut corpus: Vec<_> = conformance::UNRELIABLE
+        .iter()
+        .map(|vector| input(&[vector.packet.to_vec()]))
+        .collect();
+    corpus.push(input(&[unreliable_packet(&unreliable_message())]));
+    run(2, &corpus, fuzz::unreliable_channel);
+}
+
+#[test]
+fn test_fuzz_reliable_channel() {
+    let mut corpus: Vec<_> = conformance::RELIABLE
+        .iter()
+        .map(|vector| input(&[vector.packet.to_vec(), vector.ack.to_vec()]))
+        .collect();
+    let first = reliable_packet(0, &reliable_message());
+    let next = reliable_packet(first.len() as u32 - 6, &reliable_message());
+    corpus.push(input(&[first.clone(), next, first]));
+    run(3, &corpus, fuzz::reliable_channel);
+}
+
+#[test]
+fn test_fuzz_message_channels() {
+    let corpus = vec![input(&[
+        with_channel(CHANNELS[0], &unreliable_packet(&unreliable_message())),
+        with_channel(CHANNELS[1], &reliable_packet(0, &reliable_message())),
+        with_channel(CHANNELS[1], conformance::RELIABLE[0].ack),
+    ])];
+    run(4, &corpus, fuzz::message_channels);
+}
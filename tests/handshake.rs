@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use futures::{
+    channel::{mpsc, oneshot},
+    SinkExt,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    handshake::{handshake, Established, HandshakeError, Settings},
+    packet::{Packet, PacketPool, WIRE_VERSION},
+    runtime::{Runtime, SimulationRuntime},
+    simulation::{self, LinkSimulator},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+type PacketType = <BufferPacketPool<SimpleBufferPool> as PacketPool>::Packet;
+type HandshakeResult = Result<Established<PacketType>, HandshakeError>;
+
+// Run a handshake between two sides over a simulated link, returning the result of each side.
+fn run_handshake(
+    link: simulation::Settings,
+    a: (Settings, Vec<u8>),
+    b: (Settings, Vec<u8>),
+    b_sends_first: bool,
+) -> (HandshakeResult, HandshakeResult) {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut runtime = SimulationRuntime::new();
+
+    let (asend, alinkrecv) = mpsc::channel(8);
+    let (alinksend, mut arecv) = mpsc::channel(8);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, link, 1).run(alinkrecv, alinksend));
+    let (bsend, blinkrecv) = mpsc::channel(8);
+    let (blinksend, mut brecv) = mpsc::channel(8);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, link, 2).run(blinkrecv, blinksend));
+
+    let (a_done, mut a_result) = oneshot::channel();
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        let mut bsend = bsend;
+        async move {
+            let res = handshake(handle, pool, &a.0, &a.1, &mut arecv, &mut bsend).await;
+            if res.is_ok() {
+                // Start sending multiplexed packets, as a real connection would.
+                let mut packet = pool.acquire();
+                packet.extend(&[1, 42]);
+                let _ = bsend.send(packet).await;
+            }
+            let _ = a_done.send(res);
+            futures::future::pending::<()>().await;
+            drop((arecv, bsend));
+        }
+    });
+
+    let (b_done, mut b_result) = oneshot::channel();
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        let mut asend = asend;
+        async move {
+            if b_sends_first {
+                let _ = asend.send(pool.acquire()).await;
+            }
+            let res = handshake(handle, pool, &b.0, &b.1, &mut brecv, &mut asend).await;
+            let _ = b_done.send(res);
+            futures::future::pending::<()>().await;
+            drop((brecv, asend));
+        }
+    });
+
+    let (mut a, mut b) = (None, None);
+    for _ in 0..1000 {
+        runtime.run_for(Duration::from_millis(50));
+        a = a.or_else(|| a_result.try_recv().unwrap());
+        b = b.or_else(|| b_result.try_recv().unwrap());
+        if let (Some(_), Some(_)) = (&a, &b) {
+            return (a.unwrap(), b.unwrap());
+        }
+    }
+    panic!("handshake didn't finish in time");
+}
+
+const LINK: simulation::Settings = simulation::Settings {
+    latency: Duration::from_millis(20),
+    ..simulation::Settings::PERFECT
+};
+
+#[test]
+fn test_handshake() {
+    let settings = Settings {
+        protocol_version: 3,
+        ..Settings::default()
+    };
+    let (a, b) = run_handshake(
+        LINK,
+        (settings.clone(), vec![2, 0, 1]),
+        (settings.clone(), vec![0, 1, 2]),
+        false,
+    );
+    let a = a.unwrap();
+    let b = b.unwrap();
+    assert_eq!(a.protocol_version, 3);
+    assert_eq!(a.channels, vec![0, 1, 2]);
+    assert_eq!(b.channels, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_handshake_lossy() {
+    let lossy = simulation::Settings {
+        loss: 0.5,
+        duplicate: 0.2,
+        jitter: Duration::from_millis(50),
+        ..LINK
+    };
+    let settings = Settings::default();
+    let (a, b) = run_handshake(
+        lossy,
+        (settings.clone(), vec![0, 1]),
+        (settings.clone(), vec![0, 1]),
+        true,
+    );
+    a.unwrap();
+    let b = b.unwrap();
+    // If `b` finished after `a` had started sending, it must hand over the first packet.
+    if let Some(packet) = b.first_packet {
+        assert_eq!(&packet[..], &[1, 42]);
+    }
+}
+
+#[test]
+fn test_handshake_connect_token() {
+    let settings = Settings {
+        connect_token: Some(b"token".to_vec()),
+        ..Settings::default()
+    };
+    let (a, b) = run_handshake(
+        LINK,
+        (settings.clone(), vec![0]),
+        (Settings::default(), vec![0]),
+        false,
+    );
+    a.unwrap();
+    b.unwrap();
+}
+
+#[test]
+fn test_handshake_mismatch() {
+    let v1 = Settings {
+        protocol_version: 1,
+        ..Settings::default()
+    };
+    let v2 = Settings {
+        protocol_version: 2,
+        ..Settings::default()
+    };
+    match run_handshake(LINK, (v1.clone(), vec![0]), (v2, vec![0]), false) {
+        (
+            Err(HandshakeError::VersionMismatch {
+                local: 1,
+                remote: 2,
+            }),
+            Err(HandshakeError::VersionMismatch {
+                local: 2,
+                remote: 1,
+            }),
+        ) => {}
+        res => panic!("unexpected handshake result {:?}", res),
+    }
+
+    match run_handshake(LINK, (v1.clone(), vec![0, 1]), (v1, vec![0]), false) {
+        (
+            Err(HandshakeError::ChannelMismatch { local, remote }),
+            Err(HandshakeError::ChannelMismatch { .. }),
+        ) => {
+            assert_eq!(local, vec![0, 1]);
+            assert_eq!(remote, vec![0]);
+        }
+        res => panic!("unexpected handshake result {:?}", res),
+    }
+}
+
+#[test]
+fn test_handshake_wire_version_mismatch() {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut runtime = SimulationRuntime::new();
+    let (mut send, _recv) = mpsc::channel(64);
+    let (mut remote_send, mut recv) = mpsc::channel::<PacketType>(8);
+
+    let (done, mut result) = oneshot::channel();
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        async move {
+            let settings = Settings::default();
+            let _ = done.send(handshake(handle, pool, &settings, &[0], &mut recv, &mut send).await);
+        }
+    });
+
+    // A hello from a peer with a newer wire format, of which only the magic bytes and the wire
+    // version are known.
+    let mut packet = pool.acquire();
+    packet.extend(&[255]);
+    packet.extend(b"TRBH");
+    packet.extend(&(WIRE_VERSION + 1).to_le_bytes());
+    packet.extend(&[0xaa; 3]);
+    remote_send.try_send(packet).unwrap();
+
+    runtime.run_for(Duration::from_millis(50));
+    match result.try_recv().unwrap() {
+        Some(Err(HandshakeError::WireVersionMismatch { local, remote })) => {
+            assert_eq!(local, WIRE_VERSION);
+            assert_eq!(remote, WIRE_VERSION + 1);
+        }
+        res => panic!("unexpected handshake result {:?}", res),
+    }
+}
+
+#[test]
+fn test_handshake_timeout() {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut runtime = SimulationRuntime::new();
+    let (mut send, _recv) = mpsc::channel(64);
+    let (_never_send, mut recv) = mpsc::channel::<PacketType>(1);
+
+    let (done, mut result) = oneshot::channel();
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        async move {
+            let settings = Settings {
+                timeout: Duration::from_secs(1),
+                ..Settings::default()
+            };
+            let _ = done.send(handshake(handle, pool, &settings, &[0], &mut recv, &mut send).await);
+            drop(_never_send);
+        }
+    });
+
+    runtime.run_for(Duration::from_secs(2));
+    match result.try_recv().unwrap() {
+        Some(Err(HandshakeError::TimedOut)) => {}
+        res => panic!("unexpected handshake result {:?}", res),
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use futures::{
+    channel::mpsc,
+    future::{self, Either},
+    SinkExt, StreamExt,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    heartbeat::{self, Heartbeat},
+    message_channels::{ChannelAlreadyRegistered, MessageChannelsBuilder},
+    packet_multiplexer::PacketMultiplexer,
+    runtime::{Runtime, SimulationRuntime},
+    simulation::{self, LinkSimulator},
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[test]
+fn test_heartbeat() {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut runtime = SimulationRuntime::new();
+    let link = simulation::Settings {
+        latency: Duration::from_millis(40),
+        jitter: Duration::from_millis(20),
+        loss: 0.2,
+        ..simulation::Settings::PERFECT
+    };
+    let channel_settings = unreliable_channel::Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 4096,
+    };
+    let settings = heartbeat::Settings {
+        interval: Duration::from_millis(100),
+        samples: 64,
+        timeout: Duration::from_millis(500),
+    };
+
+    let (asend, alinkrecv) = mpsc::channel(8);
+    let (alinksend, arecv) = mpsc::channel(8);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, link, 1).run(alinkrecv, alinksend));
+    let (bsend, blinkrecv) = mpsc::channel(8);
+    let (blinksend, brecv) = mpsc::channel(8);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, link, 2).run(blinkrecv, blinksend));
+
+    let heartbeat_a = Heartbeat::new(runtime.handle(), settings.clone());
+    let monitor_a = heartbeat_a.monitor();
+    let channel_a = UnreliableChannel::new(
+        runtime.handle(),
+        pool,
+        channel_settings.clone(),
+        arecv,
+        bsend,
+    );
+    runtime.handle().spawn(async move {
+        let _ = heartbeat_a.run(channel_a).await;
+    });
+
+    let heartbeat_b = Heartbeat::new(runtime.handle(), settings);
+    let monitor_b = heartbeat_b.monitor();
+    let channel_b = UnreliableChannel::new(runtime.handle(), pool, channel_settings, brecv, asend);
+    runtime.handle().spawn(async move {
+        let _ = heartbeat_b.run(channel_b).await;
+    });
+
+    assert_eq!(monitor_a.stats().rtt, None);
+    runtime.run_for(Duration::from_secs(30));
+
+    for monitor in &[monitor_a, monitor_b] {
+        let stats = monitor.stats();
+        assert!(stats.pings_sent >= 290);
+        assert!(stats.pongs_received < stats.pings_sent);
+
+        // Both directions add 40ms to 60ms of latency.
+        let rtt = stats.rtt.unwrap();
+        assert!(rtt >= Duration::from_millis(80) && rtt <= Duration::from_millis(120));
+        let min_rtt = stats.min_rtt.unwrap();
+        assert!(min_rtt >= Duration::from_millis(80) && min_rtt <= rtt);
+        assert!(stats.jitter > Duration::ZERO && stats.jitter < Duration::from_millis(20));
+
+        // A ping is lost if either it or its pong is dropped, 36% of the time.
+        assert!(stats.loss > 0.1 && stats.loss < 0.7);
+    }
+}
+
+#[test]
+fn test_message_channels_heartbeat() {
+    let mut runtime = SimpleRuntime::new();
+    let pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let settings = heartbeat::Settings {
+        interval: Duration::from_millis(100),
+        ..heartbeat::Settings::default()
+    };
+
+    let mut multiplexer_a = PacketMultiplexer::new();
+    let mut builder_a = MessageChannelsBuilder::new(runtime.handle(), pool);
+    builder_a.set_heartbeat(7, settings.clone()).unwrap();
+    assert!(matches!(
+        builder_a.set_heartbeat(8, settings.clone()),
+        Err(ChannelAlreadyRegistered::Channel)
+    ));
+    let channels_a = builder_a.build(&mut multiplexer_a);
+
+    let mut multiplexer_b = PacketMultiplexer::new();
+    let mut builder_b = MessageChannelsBuilder::new(runtime.handle(), pool);
+    builder_b.set_heartbeat(7, settings).unwrap();
+    let channels_b = builder_b.build(&mut multiplexer_b);
+
+    runtime.spawn(async move {
+        let (mut a_incoming, mut a_outgoing) = multiplexer_a.start();
+        let (mut b_incoming, mut b_outgoing) = multiplexer_b.start();
+        loop {
+            match future::select(a_outgoing.next(), b_outgoing.next()).await {
+                Either::Left((Some(packet), _)) => {
+                    b_incoming.send(packet).await.unwrap();
+                }
+                Either::Right((Some(packet), _)) => {
+                    a_incoming.send(packet).await.unwrap();
+                }
+                Either::Left((None, _)) | Either::Right((None, _)) => break,
+            }
+        }
+    });
+
+    for _ in 0..250 {
+        runtime.run_until_stalled();
+        runtime.advance_time(10);
+    }
+
+    for channels in &[channels_a, channels_b] {
+        let stats = channels.heartbeat().unwrap().stats();
+        assert!(stats.pings_sent >= 20);
+        assert!(stats.pongs_received + 1 >= stats.pings_sent);
+        // Delivery is instant, but each hop takes a turn of the test runtime.
+        assert!(stats.rtt.unwrap() < Duration::from_millis(50));
+        assert_eq!(stats.loss, 0.);
+    }
+}
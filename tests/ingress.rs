@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{executor::block_on, SinkExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    ingress::{IngressLimiter, Settings},
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{DropReason, PacketChannel, PacketMultiplexer, PacketObserver},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Clone, Default)]
+struct DropObserver(Arc<Mutex<Vec<(PacketChannel, usize, DropReason)>>>);
+
+impl PacketObserver for DropObserver {
+    fn on_packet_dropped(&self, channel: PacketChannel, len: usize, reason: DropReason) {
+        self.0.lock().unwrap().push((channel, len, reason));
+    }
+}
+
+#[test]
+fn test_ingress_limiter() {
+    let mut runtime = SimpleRuntime::new();
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let raw_packet = |len: usize| {
+        let mut packet = raw_pool.acquire();
+        packet.resize(len + 1, 7);
+        packet
+    };
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (_sender, mut receiver, _) = multiplexer.open_channel(7, 16).unwrap();
+    let (incoming, _outgoing) = multiplexer.start();
+
+    let settings = Settings {
+        packets_per_sec: 10,
+        burst_packets: 2,
+        bytes_per_sec: 1000,
+        burst_bytes: 30,
+    };
+    let mut limiter = IngressLimiter::new(runtime.handle(), settings, incoming);
+    let observer = DropObserver::default();
+    limiter.set_observer(observer.clone());
+
+    // Only the packet burst is admitted.
+    for _ in 0..3 {
+        block_on(limiter.send(raw_packet(4))).unwrap();
+    }
+    assert!(receiver.try_recv().is_ok());
+    assert!(receiver.try_recv().is_ok());
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(limiter.dropped(), 1);
+    assert_eq!(limiter.dropped_bytes(), 5);
+    assert_eq!(
+        &*observer.0.lock().unwrap(),
+        &[(7, 4, DropReason::RateLimited)]
+    );
+
+    // Credit for one packet per 100ms, and a large packet exhausts the byte credit.
+    runtime.advance_time(200);
+    block_on(limiter.send(raw_packet(40))).unwrap();
+    block_on(limiter.send(raw_packet(4))).unwrap();
+    assert_eq!(receiver.try_recv().unwrap().len(), 40);
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(limiter.dropped(), 2);
+
+    runtime.advance_time(100);
+    block_on(limiter.send(raw_packet(4))).unwrap();
+    assert_eq!(receiver.try_recv().unwrap().len(), 4);
+}
@@ -0,0 +1,114 @@
+use futures::{channel::mpsc, executor::block_on, FutureExt};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    input_channel::{Frame, InputChannel},
+    packet::{Packet, PacketPool},
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime, SimpleRuntimeHandle};
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 65536,
+};
+
+type TestPacket = BufferPacket<Box<[u8]>>;
+type TestChannel = InputChannel<SimpleRuntimeHandle, BufferPacketPool<SimpleBufferPool>>;
+
+// A client sending inputs to a server, whose packets are only delivered by calling `deliver`.
+struct Link {
+    client: TestChannel,
+    server: TestChannel,
+    outgoing: mpsc::Receiver<TestPacket>,
+    incoming: mpsc::Sender<TestPacket>,
+    _unused: (mpsc::Sender<TestPacket>, mpsc::Receiver<TestPacket>),
+}
+
+impl Link {
+    fn new(runtime: &SimpleRuntime, redundancy: u8) -> Link {
+        let pool = BufferPacketPool::new(SimpleBufferPool(1200));
+        let (client_incoming, client_recv) = mpsc::channel(64);
+        let (client_send, outgoing) = mpsc::channel(64);
+        let (incoming, server_recv) = mpsc::channel(64);
+        let (server_send, server_outgoing) = mpsc::channel(64);
+        Link {
+            client: InputChannel::new(
+                UnreliableChannel::new(runtime.handle(), pool, SETTINGS, client_recv, client_send),
+                redundancy,
+            ),
+            server: InputChannel::new(
+                UnreliableChannel::new(runtime.handle(), pool, SETTINGS, server_recv, server_send),
+                redundancy,
+            ),
+            outgoing,
+            incoming,
+            _unused: (client_incoming, server_outgoing),
+        }
+    }
+
+    // Send an input and return the packet it was sent in.
+    fn send(&mut self, input: &[u8]) -> (Frame, TestPacket) {
+        let frame = block_on(self.client.send(input)).unwrap();
+        block_on(self.client.flush()).unwrap();
+        (frame, self.outgoing.try_recv().unwrap())
+    }
+
+    fn deliver(&mut self, packet: &TestPacket) {
+        let mut copy = BufferPacketPool::new(SimpleBufferPool(1200)).acquire();
+        copy.extend(packet);
+        self.incoming.try_send(copy).unwrap();
+    }
+
+    fn recv_all(&mut self) -> Vec<(Frame, Vec<u8>)> {
+        let mut inputs = Vec::new();
+        while let Some(res) = self.server.recv().now_or_never() {
+            let (frame, input) = res.unwrap();
+            inputs.push((frame, input.to_vec()));
+        }
+        inputs
+    }
+}
+
+#[test]
+fn test_input_redundancy() {
+    let runtime = SimpleRuntime::new();
+    let mut link = Link::new(&runtime, 3);
+
+    // Two of every three packets are lost, but every input still arrives once, in order.
+    for i in 0..30u8 {
+        let (frame, packet) = link.send(&[i]);
+        assert_eq!(frame, i as Frame);
+        if i % 3 == 2 || i == 29 {
+            link.deliver(&packet);
+        }
+    }
+    assert_eq!(
+        link.recv_all(),
+        (0..30u8).map(|i| (i as Frame, vec![i])).collect::<Vec<_>>()
+    );
+    assert_eq!(link.server.missed(), 0);
+}
+
+#[test]
+fn test_input_missed() {
+    let runtime = SimpleRuntime::new();
+    let mut link = Link::new(&runtime, 2);
+
+    let packets: Vec<_> = (0..8u8).map(|i| link.send(&[i; 3]).1).collect();
+
+    // Deliver packets out of order and duplicated, losing frames 2 to 5 entirely.
+    for i in [1, 7, 6, 1, 7] {
+        link.deliver(&packets[i]);
+    }
+    let frames: Vec<_> = link
+        .recv_all()
+        .into_iter()
+        .map(|(frame, _)| frame)
+        .collect();
+    assert_eq!(frames, vec![0, 1, 6, 7]);
+    assert_eq!(link.server.missed(), 4);
+}
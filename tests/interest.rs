@@ -0,0 +1,143 @@
+use futures::{
+    future::{self, Either},
+    SinkExt, StreamExt,
+};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    interest::{Broadcast, InterestFilter},
+    message_channels::{
+        MessageChannelMode, MessageChannelSettings, MessageChannels, MessageChannelsBuilder,
+    },
+    packet_multiplexer::PacketMultiplexer,
+    runtime::Runtime,
+    unreliable_channel,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Position(i32);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Chat(String);
+
+fn unreliable_settings(channel: u8) -> MessageChannelSettings {
+    MessageChannelSettings {
+        channel,
+        channel_mode: MessageChannelMode::Unreliable {
+            settings: unreliable_channel::Settings {
+                bandwidth: 4096,
+                burst_bandwidth: 1024,
+            },
+            max_message_len: 64,
+        },
+        message_buffer_size: 8,
+        packet_buffer_size: 8,
+    }
+}
+
+fn connect(runtime: &SimpleRuntime) -> (MessageChannels, MessageChannels) {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let build = || {
+        let mut multiplexer = PacketMultiplexer::new();
+        let mut builder = MessageChannelsBuilder::new(runtime.handle(), pool);
+        builder
+            .register::<Position>(unreliable_settings(0))
+            .unwrap();
+        builder.register::<Chat>(unreliable_settings(1)).unwrap();
+        let channels = builder.build(&mut multiplexer);
+        (multiplexer, channels)
+    };
+    let (multiplexer_a, channels_a) = build();
+    let (multiplexer_b, channels_b) = build();
+
+    runtime.spawn(async move {
+        let (mut a_incoming, mut a_outgoing) = multiplexer_a.start();
+        let (mut b_incoming, mut b_outgoing) = multiplexer_b.start();
+        loop {
+            match future::select(a_outgoing.next(), b_outgoing.next()).await {
+                Either::Left((Some(packet), _)) => {
+                    b_incoming.send(packet).await.unwrap();
+                }
+                Either::Right((Some(packet), _)) => {
+                    a_incoming.send(packet).await.unwrap();
+                }
+                Either::Left((None, _)) | Either::Right((None, _)) => break,
+            }
+        }
+    });
+
+    (channels_a, channels_b)
+}
+
+#[test]
+fn test_interest_filter() {
+    let mut runtime = SimpleRuntime::new();
+
+    // Each peer is described by the position of its player.
+    let mut server = Vec::new();
+    let mut clients = Vec::new();
+    for position in [0, 5, 100] {
+        let (server_channels, client_channels) = connect(&runtime);
+        server.push((position, server_channels));
+        clients.push(client_channels);
+    }
+
+    let mut filter = InterestFilter::<i32>::new();
+    filter.set::<Position, _>(|peer, position| (peer - position.0).abs() <= 10);
+
+    assert_eq!(
+        filter.broadcast(
+            server.iter_mut().map(|(peer, channels)| (&*peer, channels)),
+            &Position(3)
+        ),
+        Broadcast {
+            sent: 2,
+            filtered: 1,
+            dropped: 0,
+        }
+    );
+    assert_eq!(
+        filter
+            .broadcast(
+                server.iter_mut().map(|(peer, channels)| (&*peer, channels)),
+                &Chat("hi".to_owned())
+            )
+            .sent,
+        3
+    );
+    for (_, channels) in &mut server {
+        channels.flush::<Position>();
+        channels.flush::<Chat>();
+    }
+
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+
+    let received = clients
+        .iter_mut()
+        .map(|channels| {
+            (
+                channels.recv::<Position>(),
+                channels.recv::<Chat>().is_some(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        received,
+        vec![
+            (Some(Position(3)), true),
+            (Some(Position(3)), true),
+            (None, true),
+        ]
+    );
+
+    filter.remove::<Position>();
+    assert!(filter.is_relevant(&100, &Position(3)));
+}
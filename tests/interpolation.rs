@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use turbulence::interpolation::SnapshotBuffer;
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn test_snapshot_buffer() {
+    let mut buffer = SnapshotBuffer::new(4);
+    assert!(buffer.sample(ms(0)).is_none());
+
+    // Snapshots arriving out of order are sorted, and duplicates are dropped.
+    assert!(buffer.insert(ms(100), 1.0));
+    assert!(buffer.insert(ms(300), 3.0));
+    assert!(buffer.insert(ms(200), 2.0));
+    assert!(!buffer.insert(ms(200), 2.0));
+    assert_eq!(buffer.len(), 3);
+
+    let sample = buffer.sample(ms(150)).unwrap();
+    assert_eq!((*sample.from, *sample.to), (1.0, 2.0));
+    assert!((sample.t - 0.5).abs() < 1e-6);
+    assert!(!sample.clamped);
+
+    let sample = buffer.sample(ms(300)).unwrap();
+    assert_eq!(
+        (*sample.from, *sample.to, sample.clamped),
+        (3.0, 3.0, false)
+    );
+
+    // Outside of the buffered range the sample is clamped to the nearest snapshot.
+    let sample = buffer.sample(ms(50)).unwrap();
+    assert_eq!((*sample.from, *sample.to, sample.clamped), (1.0, 1.0, true));
+    let sample = buffer.sample(ms(350)).unwrap();
+    assert_eq!((*sample.from, *sample.to, sample.clamped), (3.0, 3.0, true));
+
+    // A lost snapshot is interpolated across.
+    assert!(buffer.insert(ms(500), 5.0));
+    let sample = buffer.sample(ms(450)).unwrap();
+    assert_eq!((*sample.from, *sample.to), (3.0, 5.0));
+    assert!((sample.t - 0.75).abs() < 1e-6);
+
+    // Once full the oldest snapshot is dropped, and snapshots older than every buffered snapshot
+    // are rejected.
+    assert!(buffer.insert(ms(400), 4.0));
+    assert_eq!(buffer.oldest_time(), Some(ms(200)));
+    assert!(!buffer.insert(ms(150), 1.5));
+    assert_eq!(buffer.newest_time(), Some(ms(500)));
+
+    buffer.discard_before(ms(450));
+    assert_eq!(buffer.len(), 2);
+    let sample = buffer.sample(ms(450)).unwrap();
+    assert_eq!((*sample.from, *sample.to), (4.0, 5.0));
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "serde_json")]
+
+use futures::{channel::mpsc, executor::block_on};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    json_channel::{UnreliableJsonChannel, UnreliableJsonTypedChannel},
+    serde_channel::{RecvError, SendError},
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Chat {
+    from: String,
+    text: String,
+}
+
+#[test]
+fn test_json_channel() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut json = UnreliableJsonTypedChannel::<Chat, _, _>::new(UnreliableJsonChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        64,
+    ));
+    let mut raw = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    block_on(async {
+        json.send(&Chat {
+            from: "alice".to_owned(),
+            text: "hi".to_owned(),
+        })
+        .await
+        .unwrap();
+        json.flush().await.unwrap();
+        assert_eq!(
+            raw.recv().await.unwrap(),
+            br#"{"from":"alice","text":"hi"}"#
+        );
+
+        // Messages which do not fit in the buffer are not sent.
+        assert!(matches!(
+            json.send(&Chat {
+                from: "bob".to_owned(),
+                text: "a".repeat(64),
+            })
+            .await,
+            Err(SendError::SerializationError(_))
+        ));
+
+        raw.send(br#"{"from":"bob","text":"hello"}"#).await.unwrap();
+        raw.send(b"not json").await.unwrap();
+        raw.flush().await.unwrap();
+        assert_eq!(
+            json.recv().await.unwrap(),
+            Chat {
+                from: "bob".to_owned(),
+                text: "hello".to_owned(),
+            }
+        );
+        assert!(matches!(
+            json.recv().await,
+            Err(RecvError::SerializationError(_))
+        ));
+    });
+}
@@ -0,0 +1,181 @@
+#![cfg(feature = "key-exchange")]
+
+use std::time::Duration;
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{self, Either},
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    encryption::EncryptedPacketPool,
+    key_exchange::{KeyExchangeError, Keypair, Role, SecureSession, Settings},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    runtime::{Runtime, SimulationHandle, SimulationRuntime, Timer},
+    simulation::{self, LinkSimulator},
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+const LINK: simulation::Settings = simulation::Settings {
+    latency: Duration::from_millis(20),
+    jitter: Duration::from_millis(20),
+    loss: 0.4,
+    duplicate: 0.1,
+    reorder: 0.0,
+    bandwidth: None,
+};
+
+const CHANNEL: unreliable_channel::Settings = unreliable_channel::Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 65536,
+};
+
+type Session = SecureSession<SimulationHandle, BufferPacketPool<SimpleBufferPool>>;
+type SessionResult = Option<Result<(), KeyExchangeError>>;
+
+// Connect an initiator and a responder over a lossy link, returning the result of each side's
+// session and whether the unreliable channel message got through.
+fn run_session(
+    initiator: Session,
+    responder: Session,
+    runtime: &mut SimulationRuntime,
+) -> (SessionResult, SessionResult, bool) {
+    let buffer_pool = BufferPacketPool::new(SimpleBufferPool(256));
+    let pool = MuxPacketPool::new(EncryptedPacketPool::new(buffer_pool));
+
+    let mut results = Vec::new();
+    let mut channels = Vec::new();
+    let (a_send, a_link_recv) = mpsc::channel(8);
+    let (a_link_send, b_recv) = mpsc::channel(8);
+    let (b_send, b_link_recv) = mpsc::channel(8);
+    let (b_link_send, a_recv) = mpsc::channel(8);
+    for (seed, link_incoming, link_outgoing) in
+        [(1, a_link_recv, a_link_send), (2, b_link_recv, b_link_send)]
+    {
+        runtime.handle().spawn(
+            LinkSimulator::with_seed(runtime.handle(), buffer_pool, LINK, seed)
+                .run(link_incoming, link_outgoing),
+        );
+    }
+
+    for (session, incoming, outgoing) in [(initiator, a_recv, a_send), (responder, b_recv, b_send)]
+    {
+        let mut multiplexer = PacketMultiplexer::new();
+        let (channel_out, channel_in, _) = multiplexer.open_channel(0, 8).unwrap();
+        channels.push(UnreliableChannel::new(
+            runtime.handle(),
+            pool.clone(),
+            CHANNEL,
+            channel_in,
+            channel_out,
+        ));
+        let (mux_incoming, mux_outgoing) = multiplexer.start();
+        let (done, result) = oneshot::channel();
+        results.push(result);
+        runtime.handle().spawn(async move {
+            let _ = done.send(
+                session
+                    .run_multiplexer(incoming, outgoing, mux_incoming, mux_outgoing)
+                    .await,
+            );
+        });
+    }
+
+    let mut channel_b = channels.pop().unwrap();
+    let mut channel_a = channels.pop().unwrap();
+    let (received_send, mut received) = oneshot::channel();
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        async move {
+            // The link is lossy, so keep sending until the message gets through.
+            loop {
+                if channel_a.send(b"hello").await.is_err() || channel_a.flush().await.is_err() {
+                    return;
+                }
+                let sleep = handle.sleep(Duration::from_millis(100));
+                let recv = channel_b.recv();
+                futures::pin_mut!(sleep, recv);
+                if let Either::Left((Ok(msg), _)) = future::select(recv, sleep).await {
+                    assert_eq!(msg, b"hello");
+                    break;
+                }
+            }
+            let _ = received_send.send(());
+            future::pending::<()>().await;
+        }
+    });
+
+    runtime.run_for(Duration::from_secs(20));
+    let mut results = results.into_iter().map(|mut r| r.try_recv().unwrap());
+    (
+        results.next().unwrap(),
+        results.next().unwrap(),
+        matches!(received.try_recv(), Ok(Some(()))),
+    )
+}
+
+#[test]
+fn test_key_exchange() {
+    let mut runtime = SimulationRuntime::new();
+    let buffer_pool = BufferPacketPool::new(SimpleBufferPool(256));
+    let a = Keypair::generate();
+    let b = Keypair::generate();
+    // Rekey on every packet, so that the session keys change many times during the test.
+    let settings = Settings {
+        rekey_interval: Some(1),
+        ..Settings::default()
+    };
+
+    let initiator = SecureSession::new(
+        runtime.handle(),
+        buffer_pool,
+        settings.clone(),
+        Role::Initiator,
+        a.clone(),
+    )
+    .expect_remote_key(b.public);
+    let responder = SecureSession::new(
+        runtime.handle(),
+        buffer_pool,
+        settings.clone(),
+        Role::Responder,
+        b,
+    )
+    .expect_remote_key(a.public);
+
+    let (a, b, received) = run_session(initiator, responder, &mut runtime);
+    assert!(a.is_none());
+    assert!(b.is_none());
+    assert!(received);
+}
+
+#[test]
+fn test_key_exchange_unknown_peer() {
+    let mut runtime = SimulationRuntime::new();
+    let buffer_pool = BufferPacketPool::new(SimpleBufferPool(256));
+
+    let initiator = SecureSession::new(
+        runtime.handle(),
+        buffer_pool,
+        Settings::default(),
+        Role::Initiator,
+        Keypair::generate(),
+    )
+    .expect_remote_key(Keypair::generate().public);
+    let responder = SecureSession::new(
+        runtime.handle(),
+        buffer_pool,
+        Settings::default(),
+        Role::Responder,
+        Keypair::generate(),
+    );
+
+    let (a, _, received) = run_session(initiator, responder, &mut runtime);
+    assert!(matches!(a, Some(Err(KeyExchangeError::UnknownPeer))));
+    assert!(!received);
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use futures::{channel::mpsc, executor::block_on, FutureExt};
+
+use turbulence::{
+    acked_channel::{AckedChannel, Settings},
+    buffer::{BufferPacket, BufferPacketPool},
+    keyed_channel::{KeyedChannel, StreamKey},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime, SimpleRuntimeHandle};
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 4096,
+    loss_timeout: Duration::from_millis(500),
+};
+
+type TestPacket = BufferPacket<Box<[u8]>>;
+type TestChannel = KeyedChannel<SimpleRuntimeHandle, BufferPacketPool<SimpleBufferPool>>;
+
+// One end of a link whose packets are only delivered by calling `deliver` or `drop_next`.
+struct End {
+    channel: TestChannel,
+    outgoing: mpsc::Receiver<TestPacket>,
+    incoming: mpsc::Sender<TestPacket>,
+}
+
+impl End {
+    // Send each message in its own packet.
+    fn send(&mut self, key: StreamKey, msg: &[u8]) {
+        block_on(self.channel.send(key, msg)).unwrap();
+        block_on(self.channel.flush()).unwrap();
+    }
+
+    fn deliver(&mut self, to: &mut End) {
+        while let Ok(packet) = self.outgoing.try_recv() {
+            to.incoming.try_send(packet).unwrap();
+        }
+    }
+
+    fn drop_next(&mut self) {
+        self.outgoing.try_recv().unwrap();
+    }
+
+    fn recv_all(&mut self) -> Vec<(StreamKey, Vec<u8>)> {
+        let mut msgs = Vec::new();
+        while let Some(msg) = self.channel.recv().now_or_never() {
+            let (key, msg) = msg.unwrap();
+            msgs.push((key, msg.to_vec()));
+        }
+        msgs
+    }
+}
+
+fn link(runtime: &SimpleRuntime) -> (End, End) {
+    let pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let (a_incoming, a_recv) = mpsc::channel(64);
+    let (a_send, a_outgoing) = mpsc::channel(64);
+    let (b_incoming, b_recv) = mpsc::channel(64);
+    let (b_send, b_outgoing) = mpsc::channel(64);
+    let end = |recv, send, outgoing, incoming| End {
+        channel: KeyedChannel::new(AckedChannel::new(
+            runtime.handle(),
+            pool,
+            SETTINGS,
+            recv,
+            send,
+        )),
+        outgoing,
+        incoming,
+    };
+    (
+        end(a_recv, a_send, a_outgoing, a_incoming),
+        end(b_recv, b_send, b_outgoing, b_incoming),
+    )
+}
+
+#[test]
+fn test_keyed_channel() {
+    let mut runtime = SimpleRuntime::new();
+    let (mut a, mut b) = link(&runtime);
+
+    a.send(1, b"a1");
+    a.send(1, b"a2");
+    a.send(2, b"b1");
+
+    // The first message of key 1 is lost, which holds back only the rest of key 1.
+    a.drop_next();
+    a.deliver(&mut b);
+    assert_eq!(b.recv_all(), vec![(2, b"b1".to_vec())]);
+
+    // Acknowledge what arrived.
+    block_on(b.channel.flush()).unwrap();
+    b.deliver(&mut a);
+    assert!(a.recv_all().is_empty());
+    block_on(a.channel.flush()).unwrap();
+    assert_eq!(a.channel.unacked(), 1);
+
+    // The lost message is resent once its packet passes the loss timeout.
+    runtime.advance_time(500);
+    block_on(a.channel.flush()).unwrap();
+    a.deliver(&mut b);
+    assert_eq!(b.recv_all(), vec![(1, b"a1".to_vec()), (1, b"a2".to_vec())]);
+
+    block_on(b.channel.flush()).unwrap();
+    b.deliver(&mut a);
+    a.recv_all();
+    block_on(a.channel.flush()).unwrap();
+    assert_eq!(a.channel.unacked(), 0);
+}
+
+#[test]
+fn test_keyed_channel_duplicates() {
+    let mut runtime = SimpleRuntime::new();
+    let (mut a, mut b) = link(&runtime);
+
+    // The message arrives, but its acknowledgement is lost, so it is resent.
+    a.send(3, b"once");
+    a.deliver(&mut b);
+    assert_eq!(b.recv_all(), vec![(3, b"once".to_vec())]);
+    block_on(b.channel.flush()).unwrap();
+    b.drop_next();
+
+    runtime.advance_time(500);
+    block_on(a.channel.flush()).unwrap();
+    a.send(3, b"twice");
+    a.deliver(&mut b);
+    assert_eq!(b.recv_all(), vec![(3, b"twice".to_vec())]);
+}
@@ -0,0 +1,310 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    SinkExt, StreamExt,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    reliable_channel::{self, ReliableChannel},
+    runtime::{Runtime, SimulationRuntime, Timer},
+    simulation::{LinkSimulator, Settings},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// Send the given number of packets of the given length through a link at time zero, and return
+// the arrival time of each delivered packet along with its first byte.
+fn deliver(settings: Settings, count: u8, len: usize) -> Vec<(Duration, u8)> {
+    deliver_seeded(settings, None, count, len)
+}
+
+fn deliver_seeded(
+    settings: Settings,
+    seed: Option<u64>,
+    count: u8,
+    len: usize,
+) -> Vec<(Duration, u8)> {
+    let pool = BufferPacketPool::new(SimpleBufferPool(1024));
+    let mut runtime = SimulationRuntime::new();
+    let handle = runtime.handle();
+
+    let (mut send, link_recv) = mpsc::channel(256);
+    let (link_send, mut recv) = mpsc::channel(256);
+    let link = match seed {
+        Some(seed) => LinkSimulator::with_seed(handle.clone(), pool, settings, seed),
+        None => LinkSimulator::new(handle.clone(), pool, settings),
+    };
+    runtime.handle().spawn(link.run(link_recv, link_send));
+
+    let arrivals = Arc::new(Mutex::new(Vec::new()));
+    runtime.handle().spawn({
+        let arrivals = Arc::clone(&arrivals);
+        async move {
+            while let Some(packet) = recv.next().await {
+                arrivals.lock().unwrap().push((handle.now(), packet[0]));
+            }
+        }
+    });
+
+    runtime.handle().spawn(async move {
+        for i in 0..count {
+            let mut packet = pool.acquire();
+            packet.resize(len, i);
+            send.send(packet).await.unwrap();
+        }
+    });
+
+    runtime.run_for(Duration::from_secs(60));
+    let arrivals = arrivals.lock().unwrap().clone();
+    arrivals
+}
+
+#[test]
+fn test_link_latency_and_bandwidth() {
+    assert_eq!(
+        deliver(Settings::PERFECT, 3, 16),
+        vec![
+            (Duration::ZERO, 0),
+            (Duration::ZERO, 1),
+            (Duration::ZERO, 2)
+        ]
+    );
+
+    let settings = Settings {
+        latency: Duration::from_millis(100),
+        bandwidth: Some(1024),
+        ..Settings::PERFECT
+    };
+    // Each 128 byte packet takes 125ms to send at 1024 bytes per second.
+    assert_eq!(
+        deliver(settings, 3, 128),
+        vec![
+            (Duration::from_millis(225), 0),
+            (Duration::from_millis(350), 1),
+            (Duration::from_millis(475), 2),
+        ]
+    );
+}
+
+#[test]
+fn test_link_loss_duplication_reordering() {
+    let lossy = Settings {
+        loss: 1.0,
+        ..Settings::PERFECT
+    };
+    assert!(deliver(lossy, 10, 16).is_empty());
+
+    let duplicating = Settings {
+        duplicate: 1.0,
+        ..Settings::PERFECT
+    };
+    let arrivals = deliver(duplicating, 10, 16);
+    assert_eq!(arrivals.len(), 20);
+    for i in 0..10 {
+        assert_eq!(arrivals.iter().filter(|(_, b)| *b == i).count(), 2);
+    }
+
+    let jittery = Settings {
+        latency: Duration::from_millis(50),
+        jitter: Duration::from_millis(200),
+        reorder: 0.2,
+        ..Settings::PERFECT
+    };
+    let arrivals = deliver(jittery, 100, 16);
+    assert_eq!(arrivals.len(), 100);
+    assert!(arrivals.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert!(arrivals.windows(2).any(|w| w[0].1 > w[1].1));
+    assert!(arrivals
+        .iter()
+        .all(|&(t, _)| t == Duration::ZERO || t >= Duration::from_millis(50)));
+}
+
+#[test]
+fn test_link_seeded() {
+    let settings = Settings {
+        latency: Duration::from_millis(50),
+        jitter: Duration::from_millis(200),
+        loss: 0.3,
+        duplicate: 0.1,
+        reorder: 0.1,
+        bandwidth: Some(16384),
+    };
+
+    let arrivals = deliver_seeded(settings, Some(17), 100, 64);
+    assert_eq!(arrivals, deliver_seeded(settings, Some(17), 100, 64));
+    assert_ne!(arrivals, deliver_seeded(settings, Some(18), 100, 64));
+}
+
+#[test]
+fn test_link_reliable_transfer() {
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    const LINK: Settings = Settings {
+        latency: Duration::from_millis(30),
+        jitter: Duration::from_millis(20),
+        loss: 0.2,
+        duplicate: 0.05,
+        reorder: 0.05,
+        bandwidth: Some(65536),
+    };
+
+    const LEN: usize = 20_000;
+
+    let pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimulationRuntime::new();
+
+    let (asend, alinkrecv) = mpsc::channel(2);
+    let (alinksend, arecv) = mpsc::channel(2);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::new(runtime.handle(), pool, LINK).run(alinkrecv, alinksend));
+
+    let (bsend, blinkrecv) = mpsc::channel(2);
+    let (blinksend, brecv) = mpsc::channel(2);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::new(runtime.handle(), pool, LINK).run(blinkrecv, blinksend));
+
+    let mut stream1 = ReliableChannel::new(runtime.handle(), pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), pool, SETTINGS, brecv, asend);
+
+    runtime.handle().spawn(async move {
+        let data = [17; LEN];
+        let mut written = 0;
+        while written < LEN {
+            written += stream1.write(&data[written..]).await.unwrap();
+            stream1.flush().await.unwrap();
+        }
+        // Keep the channel alive so that resends continue.
+        futures::future::pending::<()>().await;
+        drop(stream1);
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.handle().spawn(async move {
+        let mut buffer = [0; LEN];
+        let mut read = 0;
+        while read < LEN {
+            read += stream2.read(&mut buffer[read..]).await.unwrap();
+        }
+        assert!(buffer.iter().all(|&b| b == 17));
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..100_000 {
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.run_for(Duration::from_millis(50));
+    }
+
+    panic!("didn't finish in time");
+}
+
+#[test]
+fn test_link_congestion_feedback() {
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 65536,
+        burst_bandwidth: 4096,
+        recv_window_size: 65536,
+        send_window_size: 65536,
+        init_send: 4096,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    // The link carries a quarter of the channel's bandwidth, and queues the rest without limit.
+    const LINK: Settings = Settings {
+        latency: Duration::from_millis(20),
+        jitter: Duration::ZERO,
+        loss: 0.0,
+        duplicate: 0.0,
+        reorder: 0.0,
+        bandwidth: Some(16384),
+    };
+
+    const LEN: usize = 100_000;
+
+    let pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimulationRuntime::new();
+
+    let (asend, alinkrecv) = mpsc::channel(2);
+    let (alinksend, arecv) = mpsc::channel(2);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, LINK, 0).run(alinkrecv, alinksend));
+
+    let (bsend, blinkrecv) = mpsc::channel(2);
+    let (blinksend, brecv) = mpsc::channel(2);
+    runtime
+        .handle()
+        .spawn(LinkSimulator::with_seed(runtime.handle(), pool, LINK, 1).run(blinkrecv, blinksend));
+
+    let mut stream1 = ReliableChannel::new(runtime.handle(), pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), pool, SETTINGS, brecv, asend);
+    let congestion = stream1.congestion();
+    assert_eq!(congestion.bandwidth(), SETTINGS.bandwidth);
+    assert_eq!(congestion.remote_delivery_rate(), None);
+
+    runtime.handle().spawn(async move {
+        let data = vec![17; LEN];
+        let mut written = 0;
+        while written < LEN {
+            written += stream1.write(&data[written..]).await.unwrap();
+            stream1.flush().await.unwrap();
+        }
+        // Keep the channel alive so that resends continue.
+        futures::future::pending::<()>().await;
+        drop(stream1);
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.handle().spawn(async move {
+        let mut buffer = vec![0; LEN];
+        let mut read = 0;
+        while read < LEN {
+            read += stream2.read(&mut buffer[read..]).await.unwrap();
+        }
+        assert!(buffer.iter().all(|&b| b == 17));
+        let _ = done_send.send(());
+    });
+
+    // Without congestion feedback, the queue grows until retransmissions of data still sitting in
+    // it swamp the link and the transfer never finishes.
+    let mut min_bandwidth = SETTINGS.bandwidth;
+    for _ in 0..100_000 {
+        if done.try_recv().unwrap().is_some() {
+            assert!(min_bandwidth <= LINK.bandwidth.unwrap());
+            let delivery_rate = congestion.remote_delivery_rate().unwrap();
+            assert!(delivery_rate > LINK.bandwidth.unwrap() * 9 / 10);
+            assert!(delivery_rate <= LINK.bandwidth.unwrap());
+            return;
+        }
+        runtime.run_for(Duration::from_millis(50));
+        min_bandwidth = min_bandwidth.min(congestion.bandwidth());
+    }
+
+    panic!("didn't finish in time");
+}
@@ -0,0 +1,133 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::StreamExt;
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    liveness::{Liveness, Staleness},
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, NetworkEvent, PacketMultiplexer},
+    runtime::Runtime,
+    telemetry::{EventKind, EventLog},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[test]
+fn test_liveness() {
+    let mut runtime = SimpleRuntime::new();
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let packet_pool = MuxPacketPool::new(BufferPacketPool::new(SimpleBufferPool(32)));
+    let threshold = Duration::from_millis(1000);
+
+    let liveness = Liveness::new(runtime.handle());
+    let mut multiplexer = PacketMultiplexer::new();
+    multiplexer.set_observer(liveness.clone());
+    let (mut sender, mut receiver, _) = multiplexer.open_channel(3, 8).unwrap();
+    let (mut incoming, mut outgoing) = multiplexer.start();
+
+    runtime.advance_time(600);
+    assert_eq!(liveness.staleness(threshold), None);
+
+    // Only sending, the remote never answers.
+    let mut packet = packet_pool.acquire();
+    packet.resize(1, 0);
+    sender.try_send(packet).unwrap();
+    runtime.spawn(async move {
+        outgoing.next().await;
+    });
+    runtime.run_until_stalled();
+    assert_eq!(liveness.since_sent(), Duration::ZERO);
+
+    runtime.advance_time(400);
+    assert!(liveness.is_stale(threshold));
+    assert_eq!(liveness.staleness(threshold), Some(Staleness::SendOnly));
+
+    let mut packet = raw_pool.acquire();
+    packet.extend(&[3, 1]);
+    incoming.try_send(packet).unwrap();
+    assert!(receiver.try_recv().is_ok());
+    assert_eq!(liveness.staleness(threshold), None);
+
+    runtime.advance_time(1000);
+    assert_eq!(liveness.staleness(threshold), Some(Staleness::Silent));
+}
+
+#[test]
+fn test_liveness_stale_event() {
+    let mut runtime = SimpleRuntime::new();
+    let threshold = Duration::from_millis(1000);
+    let liveness = Liveness::new(runtime.handle());
+    let log = EventLog::new(runtime.handle(), 4);
+    liveness.set_event_log(log.clone());
+
+    let stale = Arc::new(AtomicBool::new(false));
+    runtime.spawn({
+        let liveness = liveness.clone();
+        let stale = Arc::clone(&stale);
+        async move {
+            assert_eq!(liveness.stale(threshold).await, Staleness::ReceiveOnly);
+            stale.store(true, Ordering::SeqCst);
+        }
+    });
+
+    // Traffic pushes back the deadline.
+    for _ in 0..3 {
+        runtime.run_until_stalled();
+        runtime.advance_time(500);
+        liveness.record_sent();
+        liveness.record_received();
+    }
+
+    // Then the connection stops sending.
+    runtime.run_until_stalled();
+    runtime.advance_time(500);
+    liveness.record_received();
+    runtime.run_until_stalled();
+    assert!(!stale.load(Ordering::SeqCst));
+
+    runtime.advance_time(500);
+    runtime.run_until_stalled();
+    assert!(stale.load(Ordering::SeqCst));
+
+    let events = log.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].time, Duration::from_millis(2500));
+    assert_eq!(events[0].kind, EventKind::Stale(Staleness::ReceiveOnly));
+}
+
+#[test]
+fn test_liveness_network_events() {
+    let mut runtime = SimpleRuntime::new();
+    let threshold = Duration::from_millis(1000);
+    let liveness = Liveness::new(runtime.handle());
+    let multiplexer = PacketMultiplexer::<BufferPacket<Box<[u8]>>>::new();
+    let mut events = multiplexer.network_events().subscribe(4);
+    liveness.set_network_events(multiplexer.network_events());
+
+    runtime.spawn({
+        let liveness = liveness.clone();
+        async move {
+            liveness.stale(threshold).await;
+        }
+    });
+    runtime.run_until_stalled();
+    assert!(events.try_recv().is_err());
+
+    runtime.advance_time(1000);
+    runtime.run_until_stalled();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        NetworkEvent::KeepaliveTimeout {
+            staleness: Staleness::Silent
+        }
+    );
+}
@@ -0,0 +1,160 @@
+use std::{cell::Cell, future::Future, rc::Rc, time::Duration};
+
+use futures::{
+    channel::oneshot,
+    executor::{LocalPool, LocalSpawner},
+    future::{self, Either},
+    task::LocalSpawnExt,
+    SinkExt, StreamExt,
+};
+
+use turbulence::{
+    buffer::{BufferPacketPool, BufferPool},
+    channel_builder::ChannelBuilder,
+    packet_multiplexer::PacketMultiplexer,
+    reliable_bincode_channel::ReliableBincodeChannel,
+    reliable_channel::Settings,
+    runtime::{LocalRuntime, Timer},
+};
+
+mod util;
+
+use self::util::{SimpleRuntime, SimpleRuntimeHandle};
+
+// A `LocalRuntime` that spawns onto a `LocalPool` and uses the manually advanced time from
+// `SimpleRuntime`.
+#[derive(Clone)]
+struct LocalRuntimeHandle {
+    time: SimpleRuntimeHandle,
+    spawner: LocalSpawner,
+}
+
+impl Timer for LocalRuntimeHandle {
+    type Instant = <SimpleRuntimeHandle as Timer>::Instant;
+    type Sleep = <SimpleRuntimeHandle as Timer>::Sleep;
+
+    fn now(&self) -> Self::Instant {
+        self.time.now()
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        self.time.elapsed(instant)
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        self.time.duration_between(earlier, later)
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        self.time.sleep(duration)
+    }
+}
+
+impl LocalRuntime for LocalRuntimeHandle {
+    fn spawn_local<F: Future<Output = ()> + 'static>(&self, future: F) {
+        self.spawner.spawn_local(future).unwrap();
+    }
+}
+
+// A buffer pool which is deliberately `!Send`.
+#[derive(Clone)]
+struct RcBufferPool {
+    size: usize,
+    acquired: Rc<Cell<usize>>,
+}
+
+impl BufferPool for RcBufferPool {
+    type Buffer = Box<[u8]>;
+
+    fn acquire(&self) -> Self::Buffer {
+        self.acquired.set(self.acquired.get() + 1);
+        vec![0; self.size].into_boxed_slice()
+    }
+}
+
+#[test]
+fn test_local_reliable_channel() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+        recv_window_size: 1024,
+        send_window_size: 1024,
+        init_send: 512,
+        resend_time: Duration::from_millis(100),
+        initial_rtt: Duration::from_millis(200),
+        max_rtt: Duration::from_secs(2),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let mut simple_runtime = SimpleRuntime::new();
+    let mut local_pool = LocalPool::new();
+    let runtime = LocalRuntimeHandle {
+        time: simple_runtime.handle(),
+        spawner: local_pool.spawner(),
+    };
+    let acquired = Rc::new(Cell::new(0));
+    let packet_pool = BufferPacketPool::new(RcBufferPool {
+        size: 64,
+        acquired: Rc::clone(&acquired),
+    });
+
+    let mut builder = ChannelBuilder::new(runtime.clone(), packet_pool);
+
+    let mut multiplexer_a = PacketMultiplexer::new();
+    let (channel_a, _) = builder
+        .open_reliable_channel_local(&mut multiplexer_a, 0, 8, SETTINGS)
+        .unwrap();
+    let mut channel_a = ReliableBincodeChannel::new(channel_a, 256);
+
+    let mut multiplexer_b = PacketMultiplexer::new();
+    let (channel_b, _) = builder
+        .open_reliable_channel_local(&mut multiplexer_b, 0, 8, SETTINGS)
+        .unwrap();
+    let mut channel_b = ReliableBincodeChannel::new(channel_b, 256);
+
+    runtime.spawn_local(async move {
+        let (mut a_incoming, mut a_outgoing) = multiplexer_a.start();
+        let (mut b_incoming, mut b_outgoing) = multiplexer_b.start();
+        loop {
+            match future::select(a_outgoing.next(), b_outgoing.next()).await {
+                Either::Left((Some(packet), _)) => {
+                    b_incoming.send(packet).await.unwrap();
+                }
+                Either::Right((Some(packet), _)) => {
+                    a_incoming.send(packet).await.unwrap();
+                }
+                Either::Left((None, _)) | Either::Right((None, _)) => break,
+            }
+        }
+    });
+
+    let (done_send, mut done_recv) = oneshot::channel();
+    runtime.spawn_local(async move {
+        for i in 0..32u32 {
+            channel_a.send(&vec![i; i as usize]).await.unwrap();
+        }
+        channel_a.flush().await.unwrap();
+
+        for i in 0..32u32 {
+            assert_eq!(
+                channel_b.recv::<Vec<u32>>().await.unwrap(),
+                vec![i; i as usize]
+            );
+        }
+
+        done_send.send((channel_a, channel_b)).ok().unwrap();
+    });
+
+    for _ in 0..100_000 {
+        if done_recv.try_recv().unwrap().is_some() {
+            assert!(acquired.get() > 0);
+            return;
+        }
+
+        local_pool.run_until_stalled();
+        simple_runtime.advance_time(50);
+    }
+
+    panic!("didn't finish in time");
+}
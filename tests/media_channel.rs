@@ -0,0 +1,87 @@
+use futures::{channel::mpsc, executor::block_on};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    media_channel::{MediaChannel, Settings},
+    packet::{Packet, PacketPool},
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+const SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 65536,
+};
+
+#[test]
+fn test_media_channel() {
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, _arecv) = mpsc::channel(16);
+    let (mut bsend, mut brecv) = mpsc::channel::<BufferPacket<Box<[u8]>>>(16);
+    let (_unused_send, unused_recv) = mpsc::channel(1);
+    let (mut incoming_send, incoming_recv) = mpsc::channel(16);
+
+    let mut sender = MediaChannel::new(
+        UnreliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS,
+            unused_recv,
+            bsend.clone(),
+        ),
+        Settings::default(),
+    );
+    let mut receiver = MediaChannel::new(
+        UnreliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS,
+            incoming_recv,
+            asend,
+        ),
+        Settings { reorder_window: 3 },
+    );
+
+    // Send ten frames, each in its own packet.
+    block_on(async {
+        for i in 0..12u8 {
+            let sequence = sender.send(i as u32 * 160, i == 0, &[i]).await.unwrap();
+            assert_eq!(sequence, i as u16);
+            sender.flush().await.unwrap();
+        }
+    });
+    bsend.close_channel();
+    let mut packets = Vec::new();
+    while let Ok(packet) = brecv.try_recv() {
+        packets.push(packet);
+    }
+    assert_eq!(packets.len(), 12);
+
+    // Frame 2 arrives after 3, frame 4 is lost, frame 8 is duplicated and frame 7 arrives too far
+    // behind to be waited for.
+    for i in [0, 1, 3, 2, 5, 6, 8, 8, 9, 10, 7, 11] {
+        let mut packet = packet_pool.acquire();
+        packet.extend(&packets[i]);
+        incoming_send.try_send(packet).unwrap();
+    }
+    drop(incoming_send);
+
+    let mut received = Vec::new();
+    block_on(async {
+        while let Ok(frame) = receiver.recv().await {
+            assert_eq!(frame.timestamp, frame.sequence as u32 * 160);
+            assert_eq!(frame.marker, frame.sequence == 0);
+            assert_eq!(frame.data, vec![frame.sequence as u8]);
+            received.push(frame.sequence);
+        }
+    });
+
+    assert_eq!(received, vec![0, 1, 2, 3, 5, 6, 8, 9, 10, 11]);
+    assert_eq!(receiver.lost_frames(), 2);
+    assert_eq!(receiver.late_frames(), 1);
+}
@@ -0,0 +1,184 @@
+use std::{pin::Pin, task::Context, time::Duration};
+
+use futures::{executor::block_on, task::noop_waker, Sink, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    memory_budget::{
+        BudgetedIncoming, BudgetedPacket, BudgetedPacketPool, IncomingBudgetError, MemoryBudget,
+        OverBudget,
+    },
+    message_channels::{
+        MessageChannelMode, MessageChannelSettings, MessageChannelsBuilder, RegisterError,
+    },
+    packet::{Packet, PacketPool},
+    packet_multiplexer::PacketMultiplexer,
+    reliable_channel,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+type TestPacket = BudgetedPacket<BufferPacket<Box<[u8]>>>;
+
+const PACKET_LEN: usize = 100;
+
+fn packet(channel: u8) -> BufferPacket<Box<[u8]>> {
+    let mut packet = BufferPacketPool::new(SimpleBufferPool(PACKET_LEN)).acquire();
+    packet.extend(&[channel, 1, 2, 3]);
+    packet
+}
+
+#[test]
+fn test_reservations() {
+    let budget = MemoryBudget::new(1000, OverBudget::Error);
+
+    let first = budget.try_reserve(600).unwrap();
+    assert_eq!(budget.used(), 600);
+    let err = budget.try_reserve(600).unwrap_err();
+    assert_eq!(err.requested, 600);
+    assert_eq!(err.available, 400);
+
+    let forced = budget.force_reserve(600);
+    assert_eq!(budget.used(), 1200);
+    assert_eq!(budget.available(), 0);
+
+    drop(first);
+    drop(forced);
+    assert_eq!(budget.used(), 0);
+
+    // Packets from a budgeted pool hold their capacity until they are dropped.
+    let pool = BudgetedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(400)), budget);
+    let packets: Vec<_> = (0..2).map(|_| pool.try_acquire().unwrap()).collect();
+    assert!(pool.try_acquire().is_err());
+    assert_eq!(pool.acquire().capacity(), 400);
+    drop(packets);
+    assert_eq!(pool.budget().used(), 0);
+}
+
+#[test]
+fn test_incoming_error() {
+    let budget = MemoryBudget::new(3 * PACKET_LEN, OverBudget::Error);
+    let mut multiplexer = PacketMultiplexer::<TestPacket>::new();
+    let (_, mut receiver, _) = multiplexer.open_channel(0, 8).unwrap();
+    let (incoming, _outgoing) = multiplexer.start();
+    let mut incoming = BudgetedIncoming::new(incoming, budget.clone());
+
+    block_on(async {
+        for _ in 0..3 {
+            incoming.send(packet(0)).await.unwrap();
+        }
+        assert_eq!(budget.used(), 3 * PACKET_LEN);
+        assert!(matches!(
+            incoming.send(packet(0)).await,
+            Err(IncomingBudgetError::OverBudget(_))
+        ));
+
+        // Reading packets returns their memory to the budget.
+        receiver.next().await.unwrap();
+        incoming.send(packet(0)).await.unwrap();
+    });
+}
+
+#[test]
+fn test_incoming_drop_unreliable() {
+    let budget = MemoryBudget::new(2 * PACKET_LEN, OverBudget::DropUnreliable);
+    let mut multiplexer = PacketMultiplexer::<TestPacket>::new();
+    let (_, mut unreliable, _) = multiplexer.open_channel(0, 8).unwrap();
+    let (_, mut reliable, _) = multiplexer.open_channel(1, 8).unwrap();
+    let (incoming, _outgoing) = multiplexer.start();
+    let mut incoming = BudgetedIncoming::new(incoming, budget.clone()).with_reliable_channels([1]);
+
+    block_on(async {
+        for _ in 0..4 {
+            incoming.send(packet(0)).await.unwrap();
+        }
+        for _ in 0..2 {
+            incoming.send(packet(1)).await.unwrap();
+        }
+        assert_eq!(incoming.dropped(), 2);
+        assert_eq!(budget.used(), 4 * PACKET_LEN);
+
+        for _ in 0..2 {
+            assert_eq!(unreliable.next().await.unwrap()[..], [1, 2, 3]);
+            assert_eq!(reliable.next().await.unwrap()[..], [1, 2, 3]);
+        }
+        assert_eq!(budget.used(), 0);
+    });
+}
+
+#[test]
+fn test_incoming_backpressure() {
+    let budget = MemoryBudget::new(2 * PACKET_LEN, OverBudget::Backpressure);
+    let mut multiplexer = PacketMultiplexer::<TestPacket>::new();
+    let (_, mut receiver, _) = multiplexer.open_channel(0, 8).unwrap();
+    let (incoming, _outgoing) = multiplexer.start();
+    let mut incoming = BudgetedIncoming::new(incoming, budget.clone());
+
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    block_on(async {
+        for _ in 0..2 {
+            incoming.send(packet(0)).await.unwrap();
+        }
+    });
+    assert!(Pin::new(&mut incoming).poll_ready(&mut cx).is_pending());
+
+    block_on(receiver.next()).unwrap();
+    assert!(Pin::new(&mut incoming).poll_ready(&mut cx).is_ready());
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message(i32);
+
+const SETTINGS: MessageChannelSettings = MessageChannelSettings {
+    channel: 0,
+    channel_mode: MessageChannelMode::Reliable {
+        settings: reliable_channel::Settings {
+            bandwidth: 4096,
+            burst_bandwidth: 1024,
+            recv_window_size: 1024,
+            send_window_size: 1024,
+            init_send: 512,
+            resend_time: Duration::from_millis(100),
+            initial_rtt: Duration::from_millis(200),
+            max_rtt: Duration::from_secs(2),
+            rtt_update_factor: 0.1,
+            rtt_resend_factor: 1.5,
+        },
+        max_message_len: 1024,
+    },
+    message_buffer_size: 8,
+    packet_buffer_size: 8,
+};
+
+#[test]
+fn test_message_channels_budget() {
+    let usage = SETTINGS.memory_usage(std::mem::size_of::<Message>());
+    assert_eq!(usage, 2 * 8 * 4 + 1024 + 1024 + 2 * 1026);
+
+    let runtime = SimpleRuntime::new();
+    let budget = MemoryBudget::new(usage + 1000, OverBudget::Error);
+    let pool = BudgetedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(32)), budget.clone());
+
+    let mut builder = MessageChannelsBuilder::new(runtime.handle(), pool.clone());
+    builder.set_memory_budget(budget.clone());
+    builder.try_register::<Message>(SETTINGS).unwrap();
+    assert_eq!(budget.used(), usage);
+
+    let mut settings = SETTINGS;
+    settings.channel = 1;
+    assert!(matches!(
+        builder.try_register::<(Message, Message)>(settings),
+        Err(RegisterError::OverBudget(_))
+    ));
+    assert_eq!(budget.used(), usage);
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let channels = builder.build(&mut multiplexer);
+    drop(channels);
+    drop(multiplexer);
+    assert_eq!(budget.used(), 0);
+}
@@ -9,7 +9,10 @@ use serde::{Deserialize, Serialize};
 
 use turbulence::{
     buffer::BufferPacketPool,
-    message_channels::{MessageChannelMode, MessageChannelSettings, MessageChannelsBuilder},
+    message_channels::{
+        MessageChannelMode, MessageChannelSettings, MessageChannelsBuilder, RegisterError,
+        SettingsError, TransportPreset,
+    },
     packet_multiplexer::PacketMultiplexer,
     reliable_channel,
     runtime::Runtime,
@@ -194,3 +197,89 @@ fn test_message_channels() {
 
     panic!("didn't finish in time");
 }
+
+#[test]
+fn test_message_channels_settings_validation() {
+    let runtime = SimpleRuntime::new();
+
+    // An unreliable message of up to 64 bytes cannot fit in a 32 byte packet.
+    let mut builder = MessageChannelsBuilder::new(
+        runtime.handle(),
+        BufferPacketPool::new(SimpleBufferPool(32)),
+    );
+    builder.try_register::<Message1>(MESSAGE1_SETTINGS).unwrap();
+    assert!(matches!(
+        builder.try_register::<Message2>(MESSAGE2_SETTINGS),
+        Err(RegisterError::InvalidSettings(
+            SettingsError::MaxMessageLenTooLarge {
+                max_message_len: 64,
+                max_len: 30,
+            }
+        ))
+    ));
+
+    let mut builder = MessageChannelsBuilder::new(
+        runtime.handle(),
+        BufferPacketPool::new(SimpleBufferPool(1024)),
+    );
+    builder.try_register::<Message2>(MESSAGE2_SETTINGS).unwrap();
+
+    let mut settings = MESSAGE1_SETTINGS;
+    if let MessageChannelMode::Reliable { settings, .. } = &mut settings.channel_mode {
+        settings.bandwidth = 0;
+    }
+    assert_eq!(
+        settings.validate(1200),
+        Err(SettingsError::Reliable(
+            reliable_channel::SettingsError::ZeroBandwidth
+        ))
+    );
+    assert!(matches!(
+        builder.try_register::<Message1>(settings),
+        Err(RegisterError::InvalidSettings(_))
+    ));
+
+    // A full packet could never be sent without exceeding the burst bandwidth.
+    assert_eq!(
+        MESSAGE1_SETTINGS.validate(1200),
+        Err(SettingsError::Reliable(
+            reliable_channel::SettingsError::BurstTooSmall {
+                burst_bandwidth: 1024,
+                packet_len: 1200,
+            }
+        ))
+    );
+}
+
+#[test]
+fn test_transport_presets() {
+    for preset in &[
+        TransportPreset::UDP_INTERNET,
+        TransportPreset::WEBRTC,
+        TransportPreset::LAN,
+    ] {
+        for settings in &[
+            preset.unreliable(0),
+            preset.reliable(1),
+            preset.compressed(2),
+        ] {
+            assert_eq!(settings.validate(preset.packet_len), Ok(()));
+        }
+    }
+
+    let runtime = SimpleRuntime::new();
+    let pool = BufferPacketPool::new(SimpleBufferPool(TransportPreset::WEBRTC.packet_len));
+    let mut builder = MessageChannelsBuilder::for_webrtc(runtime.handle(), pool);
+    let preset = builder.preset().unwrap().clone();
+    assert_eq!(preset, TransportPreset::WEBRTC);
+    builder
+        .try_register::<Message1>(preset.reliable(0))
+        .unwrap();
+    builder
+        .try_register::<Message2>(preset.unreliable(1))
+        .unwrap();
+
+    assert!(MessageChannelsBuilder::new(runtime.handle(), pool)
+        .preset()
+        .is_none());
+}
@@ -0,0 +1,211 @@
+#![cfg(feature = "metrics")]
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::{channel::mpsc, executor::block_on, SinkExt, StreamExt};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    metrics::{
+        MeteredPacketPool, INCOMING_BYTES, INCOMING_PACKETS, OUTGOING_BYTES, OUTGOING_PACKETS,
+        POOL_PACKETS_IN_USE, RELIABLE_RETRANSMITS,
+    },
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::Runtime,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Default)]
+struct Value(AtomicU64);
+
+impl CounterFn for Value {
+    fn increment(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+impl GaugeFn for Value {
+    fn increment(&self, value: f64) {
+        self.set(self.get() + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.set(self.get() - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl HistogramFn for Value {
+    fn record(&self, _: f64) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Value {
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+// Records every metric by its name and labels, formatted as `name{label=value}`.
+#[derive(Default)]
+struct TestRecorder(Mutex<HashMap<String, Arc<Value>>>);
+
+impl TestRecorder {
+    fn value(&self, key: &str) -> Arc<Value> {
+        Arc::clone(self.0.lock().unwrap().entry(key.to_owned()).or_default())
+    }
+
+    fn counter(&self, key: &str) -> u64 {
+        self.value(key).0.load(Ordering::Relaxed)
+    }
+
+    fn gauge(&self, key: &str) -> f64 {
+        self.value(key).get()
+    }
+
+    fn key_value(&self, key: &Key) -> Arc<Value> {
+        let mut name = key.name().to_owned();
+        for label in key.labels() {
+            name += &format!("{{{}={}}}", label.key(), label.value());
+        }
+        self.value(&name)
+    }
+}
+
+impl Recorder for TestRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.key_value(key))
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(self.key_value(key))
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(self.key_value(key))
+    }
+}
+
+#[test]
+fn test_multiplexer_metrics() {
+    let recorder = TestRecorder::default();
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (mux_pool, (mut sender, mut receiver, _)) = metrics::with_local_recorder(&recorder, || {
+        let pool = MeteredPacketPool::new("test", BufferPacketPool::new(SimpleBufferPool(32)));
+        (
+            MuxPacketPool::new(pool),
+            multiplexer.open_channel(7, 8).unwrap(),
+        )
+    });
+
+    block_on(async {
+        let (mut incoming, mut outgoing) = multiplexer.start();
+
+        let mut packet = mux_pool.acquire();
+        packet.extend(&[1, 2, 3]);
+        sender.send(packet).await.unwrap();
+        let packet = outgoing.next().await.unwrap();
+        assert_eq!(
+            recorder.gauge(&format!("{}{{pool=test}}", POOL_PACKETS_IN_USE)),
+            1.0
+        );
+
+        incoming.send(packet).await.unwrap();
+        let received = receiver.next().await.unwrap();
+        assert_eq!(&received[..], &[1, 2, 3]);
+        drop(received);
+        assert_eq!(
+            recorder.gauge(&format!("{}{{pool=test}}", POOL_PACKETS_IN_USE)),
+            0.0
+        );
+    });
+
+    assert_eq!(
+        recorder.counter(&format!("{}{{channel=7}}", OUTGOING_PACKETS)),
+        1
+    );
+    assert_eq!(
+        recorder.counter(&format!("{}{{channel=7}}", OUTGOING_BYTES)),
+        3
+    );
+    assert_eq!(
+        recorder.counter(&format!("{}{{channel=7}}", INCOMING_PACKETS)),
+        1
+    );
+    assert_eq!(
+        recorder.counter(&format!("{}{{channel=7}}", INCOMING_BYTES)),
+        3
+    );
+}
+
+#[test]
+fn test_reliable_metrics() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let recorder = TestRecorder::default();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    // Nothing is ever acknowledged, so everything sent is retransmitted.
+    let (_incoming_send, incoming) = mpsc::channel(2);
+    let (outgoing, mut outgoing_recv) = mpsc::channel(2);
+    let mut channel = metrics::with_local_recorder(&recorder, || {
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, incoming, outgoing)
+    });
+
+    runtime.spawn(async move {
+        channel.write(&[1, 2, 3, 4]).await.unwrap();
+        channel.flush().await.unwrap();
+        futures::future::pending::<()>().await;
+    });
+
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        while outgoing_recv.try_recv().is_ok() {}
+        runtime.advance_time(100);
+    }
+
+    assert!(recorder.counter(RELIABLE_RETRANSMITS) >= 2);
+}
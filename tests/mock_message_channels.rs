@@ -0,0 +1,88 @@
+use futures::executor::block_on;
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    message_channels::{MessageTypeUnregistered, TryAsyncMessageError},
+    mock_message_channels::MockMessageChannels,
+};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Move(i32, i32);
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Chat(String);
+
+// A game system under test, which echoes every move back as a chat message.
+fn echo_moves(channels: &mut MockMessageChannels) {
+    while let Some(Move(x, y)) = channels.recv::<Move>() {
+        channels.send(Chat(format!("{} {}", x, y)));
+    }
+    channels.flush::<Chat>();
+}
+
+#[test]
+fn test_mock_message_channels() {
+    let mut channels = MockMessageChannels::new();
+    channels.register::<Move>(0).register::<Chat>(1);
+    channels
+        .push_incoming(Move(1, 2))
+        .extend_incoming(vec![Move(3, 4), Move(5, 6)]);
+    assert_eq!(channels.pending_incoming::<Move>(), 3);
+
+    echo_moves(&mut channels);
+    assert_eq!(
+        channels.take_sent::<Chat>(),
+        vec![
+            Chat("1 2".to_owned()),
+            Chat("3 4".to_owned()),
+            Chat("5 6".to_owned())
+        ]
+    );
+    assert!(channels.take_sent::<Chat>().is_empty());
+    assert_eq!(channels.flushes::<Chat>(), 1);
+    assert_eq!(channels.statistics::<Chat>().outgoing_totals().packets, 0);
+
+    // A full outgoing buffer hands messages back until they are taken.
+    channels.set_send_capacity::<Chat>(Some(1));
+    assert_eq!(channels.send(Chat("a".to_owned())), None);
+    assert_eq!(
+        channels.send(Chat("b".to_owned())),
+        Some(Chat("b".to_owned()))
+    );
+    assert_eq!(channels.take_sent::<Chat>().len(), 1);
+    assert_eq!(channels.send(Chat("b".to_owned())), None);
+
+    assert!(matches!(
+        channels.try_send(1u8),
+        Err(MessageTypeUnregistered)
+    ));
+    assert!(channels.try_recv::<u8>().is_err());
+
+    channels.disconnect();
+    assert!(!channels.is_connected());
+    assert_eq!(
+        channels.send(Chat("c".to_owned())),
+        Some(Chat("c".to_owned()))
+    );
+    block_on(channels.recv_err());
+}
+
+#[test]
+fn test_mock_message_channels_async() {
+    let mut channels = MockMessageChannels::new();
+    channels.register::<Move>(0);
+    channels.push_incoming(Move(7, 8));
+
+    block_on(async {
+        channels.async_send(Move(0, 0)).await.unwrap();
+        assert_eq!(channels.async_recv::<Move>().await.unwrap(), Move(7, 8));
+        // The end of the script disconnects rather than waiting forever.
+        assert!(channels.async_recv::<Move>().await.is_err());
+        assert!(matches!(
+            channels.try_async_send(Move(0, 0)).await,
+            Err(TryAsyncMessageError::Disconnected(_))
+        ));
+    });
+    assert!(!channels.is_connected());
+    assert_eq!(channels.take_sent::<Move>(), vec![Move(0, 0)]);
+}
@@ -0,0 +1,146 @@
+#![cfg(feature = "rmp-serde")]
+
+use std::time::Duration;
+
+use futures::{
+    channel::{mpsc, oneshot},
+    executor::block_on,
+    future,
+};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    msgpack_channel::{
+        ReliableMsgpackChannel, ReliableMsgpackTypedChannel, UnreliableMsgpackChannel,
+    },
+    reliable_channel::{self, ReliableChannel},
+    runtime::Runtime,
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Score {
+    player: String,
+    points: u32,
+}
+
+#[test]
+fn test_unreliable_msgpack_channel() {
+    const SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut msgpack = UnreliableMsgpackChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        512,
+    );
+    let mut raw = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    let score = Score {
+        player: "alice".to_owned(),
+        points: 42,
+    };
+
+    block_on(async {
+        // Messages are plain MessagePack, with structs as maps keyed by field name.
+        msgpack.send(&score).await.unwrap();
+        msgpack.flush().await.unwrap();
+        assert_eq!(
+            raw.recv().await.unwrap(),
+            rmp_serde::to_vec_named(&score).unwrap().as_slice()
+        );
+
+        // Structs serialized as arrays by other peers are accepted too.
+        raw.send(&rmp_serde::to_vec(&score).unwrap()).await.unwrap();
+        raw.flush().await.unwrap();
+        assert_eq!(msgpack.recv::<Score>().await.unwrap(), score);
+
+        // Borrowed data is deserialized directly from the packet.
+        raw.send(&rmp_serde::to_vec("borrowed").unwrap())
+            .await
+            .unwrap();
+        raw.flush().await.unwrap();
+        assert_eq!(msgpack.recv::<&str>().await.unwrap(), "borrowed");
+    });
+}
+
+#[test]
+fn test_reliable_msgpack_channel() {
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 4096,
+        send_window_size: 4096,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut stream1 = ReliableMsgpackTypedChannel::<Score>::new(ReliableMsgpackChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        1024,
+    ));
+    let mut stream2 = ReliableMsgpackTypedChannel::<Score>::new(ReliableMsgpackChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+        1024,
+    ));
+
+    runtime.spawn(async move {
+        for i in 0..50 {
+            stream1
+                .send(&Score {
+                    player: format!("player{}", i),
+                    points: i,
+                })
+                .await
+                .unwrap();
+        }
+        stream1.flush().await.unwrap();
+        // Keep the channel alive until the other side has received everything.
+        future::pending::<()>().await;
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        for i in 0..50 {
+            assert_eq!(
+                stream2.recv().await.unwrap(),
+                Score {
+                    player: format!("player{}", i),
+                    points: i,
+                }
+            );
+        }
+        done_send.send(()).unwrap();
+    });
+
+    for _ in 0..1000 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
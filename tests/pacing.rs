@@ -0,0 +1,81 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{channel::mpsc, StreamExt};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    pacing::{paced, Settings},
+    packet::{Packet, PacketPool},
+    runtime::{Runtime, Timer},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[test]
+fn test_pacing() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+    let start = runtime.handle().now();
+
+    let (sender, receiver) = mpsc::unbounded::<BufferPacket<Box<[u8]>>>();
+    let mut paced = paced(
+        runtime.handle(),
+        Settings {
+            interval: Duration::from_millis(80),
+            max_queue: 16,
+        },
+        receiver,
+    );
+
+    let sent_times = Arc::new(Mutex::new(Vec::new()));
+    runtime.spawn({
+        let handle = runtime.handle();
+        let sent_times = Arc::clone(&sent_times);
+        async move {
+            while let Some(packet) = paced.next().await {
+                sent_times
+                    .lock()
+                    .unwrap()
+                    .push((handle.elapsed(start).as_millis() as u64, packet[0]));
+            }
+        }
+    });
+
+    let send = |i| {
+        let mut packet = packet_pool.acquire();
+        packet.resize(100, i);
+        sender.unbounded_send(packet).unwrap();
+    };
+
+    // A burst is spread evenly across the interval.
+    for i in 0..8 {
+        send(i);
+    }
+    for _ in 0..200 {
+        runtime.run_until_stalled();
+        runtime.advance_time(1);
+    }
+    {
+        let sent_times = sent_times.lock().unwrap();
+        assert_eq!(
+            sent_times.iter().map(|&(_, i)| i).collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+        assert_eq!(sent_times[0].0, 0);
+        for pair in sent_times.windows(2) {
+            let gap = pair[1].0 - pair[0].0;
+            assert!((9..=11).contains(&gap), "uneven gap {}", gap);
+        }
+        assert!(sent_times[7].0 <= 80);
+    }
+
+    // After being idle, a packet is sent right away.
+    send(8);
+    runtime.run_until_stalled();
+    assert_eq!(sent_times.lock().unwrap()[8], (200, 8));
+}
@@ -0,0 +1,85 @@
+use futures::{channel::mpsc, executor::block_on, SinkExt, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    packet_compression::{
+        compressed, CompressedPacketPool, Compressor, DecompressError, Decompressor,
+    },
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+#[test]
+fn test_compress_decompress() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let pool = CompressedPacketPool::new(raw_pool);
+    let mut compressor = Compressor::new();
+    let mut decompressor = Decompressor::new();
+
+    let mut packet = pool.acquire();
+    assert_eq!(packet.capacity(), 63);
+    packet.resize(60, 7);
+    let sent = compressor.compress(packet);
+    assert!(sent.len() < 20);
+    assert_eq!(&decompressor.decompress(sent).unwrap()[..], &[7; 60][..]);
+
+    // Packets which do not compress are sent as is.
+    let mut packet = pool.acquire();
+    packet.extend(&[1, 2, 3]);
+    let sent = compressor.compress(packet);
+    assert_eq!(&sent[..], &[0, 1, 2, 3]);
+    assert_eq!(&decompressor.decompress(sent).unwrap()[..], &[1, 2, 3]);
+
+    let mut packet = raw_pool.acquire();
+    packet.extend(&[9, 1]);
+    assert!(matches!(
+        decompressor.decompress(packet),
+        Err(DecompressError::UnknownFormat)
+    ));
+
+    // A packet which decompresses to more than the packet capacity.
+    let large_pool = CompressedPacketPool::new(BufferPacketPool::new(SimpleBufferPool(128)));
+    let mut packet = large_pool.acquire();
+    packet.resize(100, 7);
+    let mut small = raw_pool.acquire();
+    small.extend(&compressor.compress(packet));
+    assert!(matches!(
+        decompressor.decompress(small),
+        Err(DecompressError::TooBig)
+    ));
+}
+
+#[test]
+fn test_compressed_multiplexer() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let packet_pool = MuxPacketPool::new(CompressedPacketPool::new(raw_pool));
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (mut sender, mut receiver, statistics) = multiplexer.open_channel(3, 8).unwrap();
+    let (mut mux_incoming, mux_outgoing) = multiplexer.start();
+
+    let (raw_sender, raw_receiver) = mpsc::channel(8);
+    let (mut incoming, mut outgoing) = compressed(raw_receiver, raw_sender);
+
+    block_on(async {
+        let mut packet = packet_pool.acquire();
+        packet.resize(40, 5);
+        sender.send(packet).await.unwrap();
+
+        let mut mux_outgoing = mux_outgoing;
+        outgoing
+            .send(mux_outgoing.next().await.unwrap())
+            .await
+            .unwrap();
+        mux_incoming
+            .send(incoming.next().await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(&receiver.next().await.unwrap()[..], &[5; 40][..]);
+    });
+    assert_eq!(statistics.outgoing_totals().bytes, 40);
+}
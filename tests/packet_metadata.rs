@@ -0,0 +1,83 @@
+use std::net::SocketAddr;
+
+use futures::{channel::mpsc, executor::block_on, FutureExt, SinkExt, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    packet_metadata::{MetaPacket, MetaPacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+type Meta = Option<SocketAddr>;
+
+#[test]
+fn test_multiplexer_metadata() {
+    let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    let packet_pool = MetaPacketPool::<_, Meta>::new(BufferPacketPool::new(SimpleBufferPool(32)));
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (_sender, mut receiver, _) = multiplexer.open_channel(3, 8).unwrap();
+    let (mut incoming, _outgoing) = multiplexer.start();
+
+    let mut packet = packet_pool.inner().acquire();
+    packet.extend(&[3, 1, 2]);
+    block_on(incoming.send(MetaPacket::new(packet, Some(addr)))).unwrap();
+
+    let packet = block_on(receiver.next()).unwrap();
+    assert_eq!(&packet[..], &[1, 2]);
+    let meta = packet.metadata().unwrap().downcast_ref::<Meta>().unwrap();
+    assert_eq!(*meta, Some(addr));
+
+    // Packets from the pool carry the pool's metadata.
+    let packet = MuxPacketPool::new(packet_pool).acquire();
+    let meta = packet.metadata().unwrap().downcast_ref::<Meta>().unwrap();
+    assert_eq!(*meta, None);
+}
+
+#[test]
+fn test_unreliable_metadata() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 4096,
+    };
+
+    let addr: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+    let runtime = SimpleRuntime::new();
+    let packet_pool = MetaPacketPool::<_, Meta>::new(BufferPacketPool::new(SimpleBufferPool(64)));
+
+    let (asend, mut arecv) = mpsc::channel(8);
+    let (mut bsend, brecv) = mpsc::channel(8);
+    let (_, unused_recv) = mpsc::channel(8);
+    let (unused_send, _) = mpsc::channel(8);
+
+    let mut sender = UnreliableChannel::new(
+        runtime.handle(),
+        packet_pool.clone(),
+        SETTINGS,
+        unused_recv,
+        asend,
+    );
+    let mut receiver =
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, unused_send);
+
+    sender.send(&[1, 2, 3]).now_or_never().unwrap().unwrap();
+    sender.send(&[4, 5]).now_or_never().unwrap().unwrap();
+    sender.flush().now_or_never().unwrap().unwrap();
+
+    // Stand in for a transport, which attaches the source address to each received packet.
+    let (packet, _) = arecv.try_recv().unwrap().into_parts();
+    bsend.try_send(MetaPacket::new(packet, Some(addr))).unwrap();
+
+    assert!(receiver.metadata().is_none());
+    for msg in [&[1, 2, 3][..], &[4, 5][..]] {
+        assert_eq!(receiver.recv().now_or_never().unwrap().unwrap(), msg);
+        let meta = receiver.metadata().unwrap().downcast_ref::<Meta>().unwrap();
+        assert_eq!(*meta, Some(addr));
+    }
+}
@@ -1,19 +1,30 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
 use futures::{
-    executor::LocalPool,
+    executor::{block_on, LocalPool},
     future::{self, Either},
-    task::SpawnExt,
-    SinkExt, StreamExt,
+    task::{noop_waker_ref, SpawnExt},
+    FutureExt, Sink, SinkExt, StreamExt,
 };
 
 use turbulence::{
     buffer::BufferPacketPool,
+    channel_builder::ChannelBuilder,
     packet::{Packet, PacketPool},
-    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    packet_multiplexer::{
+        ChannelBuffers, ChannelRemap, DropReason, MuxPacketPool, NetworkEvent, PacketChannel,
+        PacketMultiplexer, PacketObserver,
+    },
+    unreliable_channel::{self, SendError, UnreliableChannel},
 };
 
 mod util;
 
-use self::util::SimpleBufferPool;
+use self::util::{SimpleBufferPool, SimpleRuntime};
 
 #[test]
 fn test_multiplexer() {
@@ -81,3 +92,411 @@ fn test_multiplexer() {
 
     pool.run();
 }
+
+#[derive(Clone, Default)]
+struct RecordingObserver(Arc<Mutex<Vec<String>>>);
+
+impl PacketObserver for RecordingObserver {
+    fn on_packet_sent(&self, channel: PacketChannel, len: usize) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("sent {} {}", channel, len));
+    }
+
+    fn on_packet_received(&self, channel: PacketChannel, len: usize) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("received {} {}", channel, len));
+    }
+
+    fn on_packet_dropped(&self, channel: PacketChannel, len: usize, reason: DropReason) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("dropped {} {} {:?}", channel, len, reason));
+    }
+}
+
+#[test]
+fn test_multiplexer_observer() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let packet_pool = MuxPacketPool::new(raw_pool);
+    let raw_packet = |data: &[u8]| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    };
+
+    let observer = RecordingObserver::default();
+    let mut multiplexer = PacketMultiplexer::new();
+    multiplexer.set_observer(observer.clone());
+    let (mut sender, _receiver, _) = multiplexer.open_channel(4, 0).unwrap();
+    let (_, closed_receiver, _) = multiplexer.open_channel(5, 0).unwrap();
+    drop(closed_receiver);
+    let (mut incoming, mut outgoing) = multiplexer.start();
+
+    let mut packet = packet_pool.acquire();
+    packet.resize(2, 17);
+    sender.try_send(packet).unwrap();
+    let sent = block_on(outgoing.next()).unwrap();
+    assert_eq!(&sent[..], &[4, 17, 17]);
+
+    incoming.try_send(sent).unwrap();
+    assert!(incoming
+        .try_send(raw_packet(&[4, 1, 2, 3]))
+        .unwrap_err()
+        .is_full());
+    assert!(incoming.try_send(raw_packet(&[5, 1])).is_err());
+    assert!(incoming.try_send(raw_packet(&[6])).is_err());
+
+    assert_eq!(
+        *observer.0.lock().unwrap(),
+        vec![
+            "sent 4 2",
+            "received 4 2",
+            "dropped 4 3 ChannelFull",
+            "dropped 5 1 ChannelClosed",
+            "dropped 6 0 UnknownChannel",
+        ]
+    );
+}
+
+#[test]
+fn test_multiplexer_drop_counts() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let raw_packet = |data: &[u8]| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    };
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (_sender, _receiver, full_stats) = multiplexer.open_channel(4, 0).unwrap();
+    let (_, closed_receiver, closed_stats) = multiplexer.open_channel(5, 0).unwrap();
+    drop(closed_receiver);
+    let (mut incoming, _outgoing) = multiplexer.start();
+
+    // A zero sized buffer still has room for one packet per sender.
+    incoming.try_send(raw_packet(&[4, 1])).unwrap();
+    for _ in 0..3 {
+        assert!(incoming.try_send(raw_packet(&[4, 1])).is_err());
+    }
+    assert!(incoming.try_send(raw_packet(&[5, 1])).is_err());
+    assert!(incoming.try_send(raw_packet(&[6, 1])).is_err());
+    full_stats.mark_dropped(DropReason::Malformed);
+
+    assert_eq!(full_stats.dropped(DropReason::ChannelFull), 3);
+    assert_eq!(full_stats.dropped(DropReason::Malformed), 1);
+    assert_eq!(full_stats.dropped(DropReason::ChannelClosed), 0);
+    assert_eq!(closed_stats.dropped(DropReason::ChannelClosed), 1);
+    assert_eq!(incoming.unknown_channel_drops(), 1);
+    assert_eq!(full_stats.incoming_totals().packets, 1);
+}
+
+#[test]
+fn test_multiplexer_network_events() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let raw_packet = |data: &[u8]| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    };
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let network_events = multiplexer.network_events();
+    let mut events = network_events.subscribe(8);
+    let (_sender, _receiver, full_stats) = multiplexer.open_channel(4, 0).unwrap();
+    let (_, closed_receiver, _) = multiplexer.open_channel(5, 0).unwrap();
+    drop(closed_receiver);
+    let (mut incoming, _outgoing) = multiplexer.start();
+
+    incoming.try_send(raw_packet(&[4, 1])).unwrap();
+    assert!(incoming.try_send(raw_packet(&[4, 1])).is_err());
+    // Channel closure is only reported once.
+    assert!(incoming.try_send(raw_packet(&[5, 1])).is_err());
+    assert!(incoming.try_send(raw_packet(&[5, 1])).is_err());
+    assert!(incoming.try_send(raw_packet(&[6, 1])).is_err());
+    full_stats.mark_dropped(DropReason::Malformed);
+    full_stats.mark_dropped(DropReason::Duplicate);
+
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    assert_eq!(
+        received,
+        vec![
+            NetworkEvent::Overflow { channel: 4 },
+            NetworkEvent::ChannelClosed { channel: 5 },
+            NetworkEvent::UnknownChannel { channel: 6 },
+            NetworkEvent::DecodeFailure { channel: 4 },
+        ]
+    );
+
+    // Subscribers may subscribe after the multiplexer is started, and miss events while their
+    // buffer is full.  A buffer of one has room for two events, one for the only sender.
+    drop(events);
+    let mut events = network_events.subscribe(1);
+    for _ in 0..4 {
+        full_stats.mark_dropped(DropReason::Malformed);
+    }
+    let mut received = 0;
+    while events.try_recv().is_ok() {
+        received += 1;
+    }
+    assert_eq!(received, 2);
+}
+
+#[test]
+fn test_multiplexer_raw_channel() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let packet_pool = MuxPacketPool::new(raw_pool);
+    let raw_packet = |data: &[u8]| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    };
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (mut sender, mut receiver, _) = multiplexer.open_channel(1, 8).unwrap();
+    let (mut raw_sender, mut raw_receiver, raw_stats) =
+        multiplexer.open_raw_channel(0xff, 8).unwrap();
+    assert!(multiplexer.open_raw_channel(1, 8).is_err());
+    let (mut incoming, mut outgoing) = multiplexer.start();
+
+    block_on(async {
+        incoming.send(raw_packet(&[0xff, 1, 2])).await.unwrap();
+        incoming.send(raw_packet(&[1, 3])).await.unwrap();
+        assert_eq!(&raw_receiver.next().await.unwrap()[..], &[0xff, 1, 2]);
+        assert_eq!(&receiver.next().await.unwrap()[..], &[3]);
+
+        // Outgoing raw packets are sent as is, even if they do not start with the tag.
+        raw_sender.send(raw_packet(&[])).await.unwrap();
+        raw_sender.send(raw_packet(&[7, 8])).await.unwrap();
+        assert_eq!(&outgoing.next().await.unwrap()[..], &[7, 8]);
+
+        let mut packet = packet_pool.acquire();
+        packet.resize(1, 9);
+        sender.send(packet).await.unwrap();
+        assert_eq!(&outgoing.next().await.unwrap()[..], &[1, 9]);
+    });
+
+    assert_eq!(raw_stats.incoming_totals().bytes, 2);
+    assert_eq!(raw_stats.outgoing_totals().packets, 1);
+}
+
+#[test]
+fn test_multiplexer_channel_remap() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let packet_pool = MuxPacketPool::new(raw_pool);
+    let raw_packet = |data: &[u8]| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    };
+
+    // The old client numbers channels 4 and 5 as 5 and 4.
+    let mut remap = ChannelRemap::new();
+    remap.insert(5, 4).insert(4, 5);
+    assert_eq!(remap.to_local(6), 6);
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (mut sender4, mut receiver4, stats4) = multiplexer.open_channel(4, 8).unwrap();
+    let (_sender5, mut receiver5, _) = multiplexer.open_channel(5, 8).unwrap();
+    multiplexer.set_channel_remap(remap);
+    let (mut incoming, mut outgoing) = multiplexer.start();
+
+    block_on(async {
+        incoming.send(raw_packet(&[5, 1])).await.unwrap();
+        incoming.send(raw_packet(&[4, 2])).await.unwrap();
+        assert_eq!(&receiver4.next().await.unwrap()[..], &[1]);
+        assert_eq!(&receiver5.next().await.unwrap()[..], &[2]);
+
+        let mut packet = packet_pool.acquire();
+        packet.resize(1, 3);
+        sender4.send(packet).await.unwrap();
+        assert_eq!(&outgoing.next().await.unwrap()[..], &[5, 3]);
+    });
+
+    assert_eq!(stats4.incoming_totals().packets, 1);
+    assert_eq!(stats4.outgoing_totals().packets, 1);
+}
+
+#[test]
+fn test_multiplexer_batches() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let packet_pool = MuxPacketPool::new(raw_pool);
+    let raw_packet = |data: &[u8]| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    };
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let (mut sender, mut receiver, stats) = multiplexer.open_channel(4, 1).unwrap();
+    let (mut incoming, mut outgoing) = multiplexer.start();
+
+    let mut batch = vec![
+        raw_packet(&[4, 1]),
+        raw_packet(&[9, 2]),
+        raw_packet(&[]),
+        raw_packet(&[4, 3]),
+        raw_packet(&[4, 4]),
+    ];
+    assert_eq!(incoming.ingest_batch(&mut batch), 2);
+    assert_eq!(batch.len(), 1);
+    assert_eq!(&batch[0][..], &[4, 4]);
+    assert_eq!(incoming.unknown_channel_drops(), 1);
+    // The packet left to retry overflowed its channel, but has not been dropped.
+    assert_eq!(stats.incoming_overflows(), 1);
+    assert_eq!(stats.dropped(DropReason::ChannelFull), 0);
+
+    block_on(async {
+        assert_eq!(&receiver.next().await.unwrap()[..], &[1]);
+        assert_eq!(&receiver.next().await.unwrap()[..], &[3]);
+    });
+    assert_eq!(incoming.ingest_batch(&mut batch), 1);
+    assert!(batch.is_empty());
+    assert_eq!(stats.dropped(DropReason::ChannelFull), 0);
+
+    let mut packets = Vec::new();
+    assert_eq!(outgoing.drain_ready(&mut packets, 8), 0);
+    for i in 0..2 {
+        let mut packet = packet_pool.acquire();
+        packet.resize(1, i);
+        sender.try_send(packet).unwrap();
+    }
+    assert_eq!(outgoing.drain_ready(&mut packets, 1), 1);
+    assert_eq!(outgoing.drain_ready(&mut packets, 8), 1);
+    let packets: Vec<&[u8]> = packets.iter().map(|packet| &packet[..]).collect();
+    assert_eq!(packets, [&[4, 0], &[4, 1]]);
+}
+
+#[test]
+fn test_multiplexer_poll_drain() {
+    let packet_pool = MuxPacketPool::new(BufferPacketPool::new(SimpleBufferPool(32)));
+    let mut multiplexer = PacketMultiplexer::new();
+    let (mut sender3, _receiver3, _) = multiplexer.open_channel(3, 8).unwrap();
+    let (mut sender4, _receiver4, _) = multiplexer.open_channel(4, 8).unwrap();
+    let (_incoming, mut outgoing) = multiplexer.start();
+
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut packets = Vec::new();
+    assert!(outgoing
+        .poll_drain_into(&mut cx, &mut packets, 8)
+        .is_pending());
+
+    for i in 0..3 {
+        let mut packet = packet_pool.acquire();
+        packet.resize(1, i);
+        sender3.try_send(packet).unwrap();
+    }
+    let mut packet = packet_pool.acquire();
+    packet.resize(1, 9);
+    sender4.try_send(packet).unwrap();
+
+    assert_eq!(
+        outgoing.poll_drain_into(&mut cx, &mut packets, 3),
+        Poll::Ready(Some(3))
+    );
+    drop((sender3, sender4));
+    assert_eq!(block_on(outgoing.drain_into(&mut packets, 8)), Some(1));
+    assert_eq!(block_on(outgoing.drain_into(&mut packets, 8)), None);
+
+    let mut packets: Vec<Vec<u8>> = packets.iter().map(|packet| packet.to_vec()).collect();
+    packets.sort();
+    assert_eq!(packets, [[3, 0], [3, 1], [3, 2], [4, 9]]);
+}
+
+#[test]
+fn test_multiplexer_channel_buffers() {
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let raw_packet = |data: &[u8]| {
+        let mut packet = raw_pool.acquire();
+        packet.extend(data);
+        packet
+    };
+
+    let mut multiplexer = PacketMultiplexer::new();
+    let buffers = ChannelBuffers {
+        incoming: 1,
+        outgoing: 4,
+    };
+    let (_sender, mut receiver, stats) = multiplexer.open_channel(4, buffers).unwrap();
+    let (mut incoming, _outgoing) = multiplexer.start();
+    assert_eq!(stats.buffers(), buffers);
+
+    // The incoming buffer holds one packet more than its depth.
+    incoming.try_send(raw_packet(&[4, 0])).unwrap();
+    incoming.try_send(raw_packet(&[4, 1])).unwrap();
+    assert!(incoming
+        .try_send(raw_packet(&[4, 2]))
+        .unwrap_err()
+        .is_full());
+    assert_eq!(stats.incoming_overflows(), 1);
+    assert_eq!(stats.dropped(DropReason::ChannelFull), 1);
+
+    // The sink waits for room instead, counting the overflow once however often it is polled.
+    let mut cx = Context::from_waker(noop_waker_ref());
+    let mut incoming = Pin::new(&mut incoming);
+    incoming.as_mut().start_send(raw_packet(&[4, 3])).unwrap();
+    assert!(incoming.as_mut().poll_flush(&mut cx).is_pending());
+    assert!(incoming.as_mut().poll_flush(&mut cx).is_pending());
+    assert_eq!(stats.incoming_overflows(), 2);
+    assert_eq!(stats.dropped(DropReason::ChannelFull), 1);
+
+    assert_eq!(&receiver.try_recv().unwrap()[..], &[0]);
+    assert!(incoming.as_mut().poll_ready(&mut cx).is_ready());
+    assert_eq!(&receiver.try_recv().unwrap()[..], &[1]);
+    assert_eq!(&receiver.try_recv().unwrap()[..], &[3]);
+    assert_eq!(stats.incoming_overflows(), 2);
+}
+
+#[test]
+fn test_multiplexer_datagram_limit() {
+    const SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+        bandwidth: 65536,
+        burst_bandwidth: 65536,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let mut multiplexer = PacketMultiplexer::new();
+    let mut builder = ChannelBuilder::new(
+        runtime.handle(),
+        BufferPacketPool::new(SimpleBufferPool(64)),
+    );
+    let (mut channel, _) = builder
+        .open_unreliable_channel(&mut multiplexer, 3, 8, SETTINGS)
+        .unwrap();
+    let limit = multiplexer.datagram_limit();
+    assert_eq!(limit.max_datagram_size(), None);
+    let (_incoming, mut outgoing) = multiplexer.start();
+
+    let mut send = |channel: &mut UnreliableChannel<_, _>| {
+        for i in 0..4 {
+            channel.send(&[i; 10]).now_or_never().unwrap().unwrap();
+        }
+        channel.flush().now_or_never().unwrap().unwrap();
+        let mut sent = Vec::new();
+        while let Some(Some(packet)) = outgoing.next().now_or_never() {
+            sent.push(packet.len());
+        }
+        sent
+    };
+
+    // Every message and its length prefix fits in a single packet.
+    assert_eq!(send(&mut channel), vec![49]);
+
+    // The transport learns of a smaller maximum, and the same channel sends smaller packets.
+    limit.set_max_datagram_size(Some(27));
+    assert_eq!(send(&mut channel), vec![25, 25]);
+    assert!(matches!(
+        channel.send(&[0; 30]).now_or_never().unwrap(),
+        Err(SendError::TooBig)
+    ));
+
+    limit.set_max_datagram_size(None);
+    assert_eq!(send(&mut channel), vec![49]);
+}
@@ -0,0 +1,127 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{channel::oneshot, future, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    message_channels::{MessageChannelMode, MessageChannelSettings, MessageChannelsBuilder},
+    packet_multiplexer::{PacketChannel, PacketMultiplexer},
+    piggyback, reliable_channel,
+    runtime::Runtime,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Serialize, Deserialize)]
+struct Message(i32);
+
+const SETTINGS: MessageChannelSettings = MessageChannelSettings {
+    channel: 0,
+    channel_mode: MessageChannelMode::Reliable {
+        settings: reliable_channel::Settings {
+            bandwidth: 4096,
+            burst_bandwidth: 1024,
+            recv_window_size: 1024,
+            send_window_size: 1024,
+            init_send: 512,
+            resend_time: Duration::from_millis(100),
+            initial_rtt: Duration::from_millis(200),
+            max_rtt: Duration::from_secs(2),
+            rtt_update_factor: 0.1,
+            rtt_resend_factor: 1.5,
+        },
+        max_message_len: 1024,
+    },
+    message_buffer_size: 8,
+    packet_buffer_size: 8,
+};
+
+const BUNDLE_CHANNEL: PacketChannel = 255;
+
+const ACK_DELAY: Duration = Duration::from_millis(20);
+
+const MESSAGES: i32 = 50;
+
+#[test]
+fn test_piggyback_bidirectional() {
+    let mut runtime = SimpleRuntime::new();
+    let pool = BufferPacketPool::new(SimpleBufferPool(256));
+
+    let mut multiplexer_a = PacketMultiplexer::new();
+    let mut builder_a = MessageChannelsBuilder::new(runtime.handle(), pool);
+    builder_a.register::<Message>(SETTINGS).unwrap();
+    let mut channels_a = builder_a.build(&mut multiplexer_a);
+
+    let mut multiplexer_b = PacketMultiplexer::new();
+    let mut builder_b = MessageChannelsBuilder::new(runtime.handle(), pool);
+    builder_b.register::<Message>(SETTINGS).unwrap();
+    let mut channels_b = builder_b.build(&mut multiplexer_b);
+
+    let (a_incoming, a_outgoing) = multiplexer_a.start();
+    let (b_incoming, b_outgoing) = multiplexer_b.start();
+
+    let bundles = Arc::new(AtomicUsize::new(0));
+    for (outgoing, incoming) in [(a_outgoing, b_incoming), (b_outgoing, a_incoming)] {
+        let bundles = bundles.clone();
+        let outgoing = piggyback::piggyback_acks(
+            runtime.handle(),
+            ACK_DELAY,
+            outgoing,
+            BUNDLE_CHANNEL,
+            [SETTINGS.channel],
+        );
+        runtime.spawn(async move {
+            outgoing
+                .inspect(move |packet| {
+                    if packet[0] == BUNDLE_CHANNEL {
+                        bundles.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+                .map(Ok)
+                .forward(piggyback::unbundle(incoming, pool, BUNDLE_CHANNEL))
+                .await
+                .unwrap();
+        });
+    }
+
+    let (is_done_send, mut is_done_recv) = oneshot::channel();
+    runtime.spawn(async move {
+        // Both sides send at once, so held acknowledgements ride along with the next data packets.
+        for i in 0..MESSAGES {
+            channels_a.async_send(Message(i)).await.unwrap();
+            channels_b.async_send(Message(-i)).await.unwrap();
+            channels_a.flush::<Message>();
+            channels_b.flush::<Message>();
+
+            let (a, b) = future::join(
+                channels_a.async_recv::<Message>(),
+                channels_b.async_recv::<Message>(),
+            )
+            .await;
+            assert_eq!(a.unwrap().0, -i);
+            assert_eq!(b.unwrap().0, i);
+        }
+        is_done_send.send(()).unwrap();
+    });
+
+    for _ in 0..100_000 {
+        if is_done_recv.try_recv().unwrap().is_some() {
+            assert!(bundles.load(Ordering::Relaxed) > 0);
+            return;
+        }
+
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+
+    panic!("didn't finish in time");
+}
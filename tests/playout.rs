@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use turbulence::playout::{PlayoutBuffer, Settings};
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+const INTERVAL: u64 = 20;
+
+// Send a message every interval with up to `jitter` ms of random extra delay on a 1000 ms clock
+// offset, popping every millisecond, and return the times messages were released at.
+fn play(
+    buffer: &mut PlayoutBuffer<u64>,
+    rng: &mut SmallRng,
+    start: u64,
+    count: u64,
+    jitter: u64,
+) -> Vec<(u64, u64)> {
+    let mut in_flight = Vec::new();
+    let mut released = Vec::new();
+    let end = start + count * INTERVAL;
+    for now in start..end + 500 {
+        if now < end && now % INTERVAL == 0 {
+            in_flight.push((now, now + 1000 + 30 + rng.gen_range(0..=jitter)));
+        }
+        in_flight.retain(|&(sent, arrival)| {
+            if arrival == now + 1000 {
+                buffer.insert(ms(sent), ms(arrival), sent);
+                false
+            } else {
+                true
+            }
+        });
+        while let Some((sent, msg)) = buffer.pop(ms(now + 1000)) {
+            assert_eq!(sent, ms(msg));
+            released.push((msg, now));
+        }
+    }
+    released
+}
+
+#[test]
+fn test_playout_cadence() {
+    let mut buffer = PlayoutBuffer::new(Settings::default());
+    let mut rng = SmallRng::seed_from_u64(1);
+
+    let released = play(&mut buffer, &mut rng, 0, 200, 40);
+    // Once the delay has grown to cover the jitter, messages are released at the cadence they were
+    // sent at, and few are late.
+    let delay = buffer.delay();
+    assert!((ms(40)..=ms(50)).contains(&delay), "{:?}", delay);
+    let steady = &released[released.len() - 100..];
+    // Releases are only checked every millisecond, so they may be up to a millisecond out.
+    for pair in steady.windows(2) {
+        let gap = (pair[1].1 - pair[0].1) as i64 - (pair[1].0 - pair[0].0) as i64;
+        assert!(gap.abs() <= 1, "{}", gap);
+    }
+    assert!(buffer.late() < 10, "{}", buffer.late());
+    assert!(released.len() as u64 + buffer.late() == 200);
+
+    // When the jitter drops, the delay shrinks again.
+    let released = play(&mut buffer, &mut rng, 10_000, 400, 2);
+    assert!(buffer.target_delay() <= ms(12));
+    assert!(buffer.delay() <= ms(12));
+    assert_eq!(released.len(), 400);
+    for pair in released.windows(2) {
+        let gap = pair[1].1 - pair[0].1;
+        assert!((18..=22).contains(&gap), "{}", gap);
+    }
+}
+
+#[test]
+fn test_playout_order() {
+    let mut buffer = PlayoutBuffer::new(Settings {
+        min_delay: ms(10),
+        max_delay: ms(100),
+        percentile: 1.0,
+        window: 16,
+    });
+    assert_eq!(buffer.pop(ms(0)), None);
+
+    // Reordered messages are released in send order, duplicates and late messages are dropped.
+    assert!(buffer.insert(ms(20), ms(1050), 'b'));
+    assert!(buffer.insert(ms(0), ms(1040), 'a'));
+    assert!(!buffer.insert(ms(0), ms(1041), 'a'));
+    assert_eq!(buffer.playout_time(ms(0)), Some(ms(1030) + buffer.delay()));
+
+    let mut released = Vec::new();
+    for now in 1040..1200 {
+        while let Some((_, msg)) = buffer.pop(ms(now)) {
+            released.push(msg);
+        }
+    }
+    assert_eq!(released, ['a', 'b']);
+    assert!(!buffer.insert(ms(10), ms(1200), 'x'));
+    assert_eq!(buffer.late(), 1);
+}
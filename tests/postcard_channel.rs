@@ -0,0 +1,70 @@
+#![cfg(feature = "postcard")]
+
+use futures::{channel::mpsc, executor::block_on};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    postcard_channel::{UnreliablePostcardChannel, UnreliablePostcardTypedChannel},
+    serde_channel::RecvError,
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Input {
+    buttons: u8,
+    stick: (i8, i8),
+}
+
+#[test]
+fn test_postcard_channel() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut device =
+        UnreliablePostcardTypedChannel::<Input, _, _>::new(UnreliablePostcardChannel::new(
+            UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+            64,
+        ));
+    let mut raw = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    block_on(async {
+        device
+            .send(&Input {
+                buttons: 0b101,
+                stick: (-1, 127),
+            })
+            .await
+            .unwrap();
+        device.flush().await.unwrap();
+        assert_eq!(raw.recv().await.unwrap(), &[0b101, 0xff, 0x7f]);
+
+        raw.send(&[0b10, 0, 1]).await.unwrap();
+        // Trailing bytes after a message are rejected.
+        raw.send(&[0b10, 0, 1, 0]).await.unwrap();
+        raw.flush().await.unwrap();
+        assert_eq!(
+            device.recv().await.unwrap(),
+            Input {
+                buttons: 0b10,
+                stick: (0, 1),
+            }
+        );
+        assert!(matches!(
+            device.recv().await,
+            Err(RecvError::SerializationError(_))
+        ));
+    });
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use futures::{executor::block_on, SinkExt, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    heartbeat::HeartbeatStats,
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    prometheus::Exposition,
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+#[test]
+fn test_prometheus_exposition() {
+    let packet_pool = MuxPacketPool::new(BufferPacketPool::new(SimpleBufferPool(32)));
+    let mut multiplexer = PacketMultiplexer::new();
+    let (mut sender, _receiver, statistics) = multiplexer.open_channel(3, 8).unwrap();
+    let (_, _, idle) = multiplexer.open_channel(7, 8).unwrap();
+    let (_incoming, mut outgoing) = multiplexer.start();
+
+    block_on(async {
+        for _ in 0..2 {
+            let mut packet = packet_pool.acquire();
+            packet.resize(10, 0);
+            sender.send(packet).await.unwrap();
+            outgoing.next().await.unwrap();
+        }
+    });
+
+    let mut exposition = Exposition::new();
+    exposition
+        .channel(&[("peer", "a\"b")], 3, &statistics)
+        .channel(&[("peer", "a\"b")], 7, &idle);
+    let text = exposition.to_string();
+
+    let expected = concat!(
+        "# HELP turbulence_outgoing_packets_total Packets sent on a channel.\n",
+        "# TYPE turbulence_outgoing_packets_total counter\n",
+        "turbulence_outgoing_packets_total{peer=\"a\\\"b\",channel=\"3\"} 2\n",
+        "turbulence_outgoing_packets_total{peer=\"a\\\"b\",channel=\"7\"} 0\n",
+        "# HELP turbulence_outgoing_bytes_total Bytes sent on a channel.\n",
+    );
+    assert!(text.contains(expected), "{}", text);
+    assert!(text.contains("turbulence_outgoing_bytes_total{peer=\"a\\\"b\",channel=\"3\"} 20\n"));
+    assert!(text.contains(
+        "turbulence_dropped_packets_total{peer=\"a\\\"b\",channel=\"3\",reason=\"channel_full\"} 0\n"
+    ));
+    assert_eq!(text.matches("# TYPE").count(), 5);
+    assert!(!text.contains("reliable"));
+}
+
+#[test]
+fn test_prometheus_heartbeat() {
+    let mut exposition = Exposition::new();
+    exposition
+        .heartbeat(&[], 2, &HeartbeatStats::default())
+        .heartbeat(
+            &[],
+            3,
+            &HeartbeatStats {
+                rtt: Some(Duration::from_millis(120)),
+                loss: 0.25,
+                ..HeartbeatStats::default()
+            },
+        );
+    let text = exposition.to_string();
+
+    assert!(!text.contains("turbulence_heartbeat_rtt_seconds{channel=\"2\"}"));
+    assert!(text.contains("turbulence_heartbeat_rtt_seconds{channel=\"3\"} 0.12\n"));
+    assert!(text.contains("turbulence_heartbeat_jitter_seconds{channel=\"2\"} 0\n"));
+    assert!(text.contains("turbulence_heartbeat_loss_ratio{channel=\"3\"} 0.25\n"));
+    assert_eq!(text.matches("# TYPE").count(), 3);
+}
@@ -0,0 +1,160 @@
+#![cfg(feature = "prost")]
+
+use std::time::Duration;
+
+use futures::{
+    channel::{mpsc, oneshot},
+    executor::block_on,
+    future,
+};
+use prost::Message;
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    prost_channel::{
+        RecvError, ReliableProstChannel, ReliableProstTypedChannel, SendError,
+        UnreliableProstChannel, UnreliableProstTypedChannel,
+    },
+    reliable_channel::{self, ReliableChannel},
+    runtime::Runtime,
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Clone, PartialEq, Message)]
+struct MatchRequest {
+    #[prost(string, tag = "1")]
+    player: String,
+    #[prost(uint32, tag = "2")]
+    rating: u32,
+    #[prost(string, repeated, tag = "3")]
+    regions: Vec<String>,
+}
+
+#[test]
+fn test_unreliable_prost_channel() {
+    const SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut prost =
+        UnreliableProstTypedChannel::<MatchRequest, _, _>::new(UnreliableProstChannel::new(
+            UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+            64,
+        ));
+    let mut raw = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    let request = MatchRequest {
+        player: "alice".to_owned(),
+        rating: 1500,
+        regions: vec!["eu".to_owned(), "us".to_owned()],
+    };
+
+    block_on(async {
+        // Messages are plain protobuf encoded.
+        prost.send(&request).await.unwrap();
+        prost.flush().await.unwrap();
+        assert_eq!(
+            raw.recv().await.unwrap(),
+            request.encode_to_vec().as_slice()
+        );
+
+        // Messages which do not fit in the buffer are not sent.
+        assert!(matches!(
+            prost
+                .send(&MatchRequest {
+                    player: "a".repeat(64),
+                    ..request.clone()
+                })
+                .await,
+            Err(SendError::EncodeError(_))
+        ));
+
+        raw.send(&request.encode_to_vec()).await.unwrap();
+        raw.send(&[0xff, 0xff, 0xff]).await.unwrap();
+        raw.flush().await.unwrap();
+        assert_eq!(prost.recv().await.unwrap(), request);
+        assert!(matches!(prost.recv().await, Err(RecvError::DecodeError(_))));
+    });
+}
+
+#[test]
+fn test_reliable_prost_channel() {
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 4096,
+        send_window_size: 4096,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut stream1 = ReliableProstTypedChannel::<MatchRequest>::new(ReliableProstChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        1024,
+    ));
+    let mut stream2 = ReliableProstTypedChannel::<MatchRequest>::new(ReliableProstChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+        1024,
+    ));
+
+    runtime.spawn(async move {
+        for i in 0..50 {
+            stream1
+                .send(&MatchRequest {
+                    player: format!("player{}", i),
+                    rating: i,
+                    regions: vec!["eu".to_owned(); i as usize % 4],
+                })
+                .await
+                .unwrap();
+        }
+        stream1.flush().await.unwrap();
+        // Keep the channel alive until the other side has received everything.
+        future::pending::<()>().await;
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        for i in 0..50 {
+            assert_eq!(
+                stream2.recv().await.unwrap(),
+                MatchRequest {
+                    player: format!("player{}", i),
+                    rating: i,
+                    regions: vec!["eu".to_owned(); i as usize % 4],
+                }
+            );
+        }
+        done_send.send(()).unwrap();
+    });
+
+    for _ in 0..1000 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
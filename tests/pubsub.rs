@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use futures::{
+    future::{self, Either},
+    SinkExt, StreamExt,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    message_channels::{
+        MessageChannelMode, MessageChannelSettings, MessageChannels, MessageChannelsBuilder,
+    },
+    packet_multiplexer::PacketMultiplexer,
+    pubsub::{PubSub, Published, SubscriptionUpdate, Topic},
+    reliable_channel,
+    runtime::Runtime,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+fn reliable_settings(channel: u8) -> MessageChannelSettings {
+    MessageChannelSettings {
+        channel,
+        channel_mode: MessageChannelMode::Reliable {
+            settings: reliable_channel::Settings {
+                bandwidth: 4096,
+                burst_bandwidth: 1024,
+                recv_window_size: 1024,
+                send_window_size: 1024,
+                init_send: 512,
+                resend_time: Duration::from_millis(100),
+                initial_rtt: Duration::from_millis(200),
+                max_rtt: Duration::from_secs(2),
+                rtt_update_factor: 0.1,
+                rtt_resend_factor: 1.5,
+            },
+            max_message_len: 1024,
+        },
+        message_buffer_size: 8,
+        packet_buffer_size: 8,
+    }
+}
+
+#[test]
+fn test_pubsub() {
+    let mut runtime = SimpleRuntime::new();
+    let pool = BufferPacketPool::new(SimpleBufferPool(32));
+
+    let mut multiplexer_a = PacketMultiplexer::new();
+    let mut builder_a = MessageChannelsBuilder::new(runtime.handle(), pool);
+    builder_a
+        .register::<SubscriptionUpdate>(reliable_settings(0))
+        .unwrap();
+    builder_a
+        .register::<Published<String>>(reliable_settings(1))
+        .unwrap();
+    let mut channels_a = builder_a.build(&mut multiplexer_a);
+
+    let mut multiplexer_b = PacketMultiplexer::new();
+    let mut builder_b = MessageChannelsBuilder::new(runtime.handle(), pool);
+    builder_b
+        .register::<SubscriptionUpdate>(reliable_settings(0))
+        .unwrap();
+    builder_b
+        .register::<Published<String>>(reliable_settings(1))
+        .unwrap();
+    let mut channels_b = builder_b.build(&mut multiplexer_b);
+
+    runtime.spawn(async move {
+        let (mut a_incoming, mut a_outgoing) = multiplexer_a.start();
+        let (mut b_incoming, mut b_outgoing) = multiplexer_b.start();
+        loop {
+            match future::select(a_outgoing.next(), b_outgoing.next()).await {
+                Either::Left((Some(packet), _)) => {
+                    b_incoming.send(packet).await.unwrap();
+                }
+                Either::Right((Some(packet), _)) => {
+                    a_incoming.send(packet).await.unwrap();
+                }
+                Either::Left((None, _)) | Either::Right((None, _)) => break,
+            }
+        }
+    });
+
+    let run = |runtime: &mut SimpleRuntime,
+               (pubsub_a, channels_a): (&mut PubSub, &mut MessageChannels),
+               (pubsub_b, channels_b): (&mut PubSub, &mut MessageChannels)| {
+        for _ in 0..20 {
+            pubsub_a.update(channels_a);
+            pubsub_b.update(channels_b);
+            runtime.run_until_stalled();
+            runtime.advance_time(50);
+        }
+    };
+
+    let lobby = Topic::named("lobby");
+    let chat = Topic::named("chat");
+    assert_ne!(lobby, chat);
+
+    let mut pubsub_a = PubSub::new();
+    let mut pubsub_b = PubSub::new();
+    assert!(pubsub_b.subscribe(lobby));
+    assert!(!pubsub_b.subscribe(lobby));
+    run(
+        &mut runtime,
+        (&mut pubsub_a, &mut channels_a),
+        (&mut pubsub_b, &mut channels_b),
+    );
+    assert!(pubsub_a.is_remote_subscribed(lobby));
+    assert!(!pubsub_a.is_remote_subscribed(chat));
+
+    // Only subscribed topics are sent.
+    assert!(pubsub_a
+        .publish(&mut channels_a, lobby, "hello".to_owned())
+        .is_none());
+    assert!(pubsub_a
+        .publish(&mut channels_a, chat, "ignored".to_owned())
+        .is_none());
+    channels_a.flush::<Published<String>>();
+    run(
+        &mut runtime,
+        (&mut pubsub_a, &mut channels_a),
+        (&mut pubsub_b, &mut channels_b),
+    );
+    assert_eq!(
+        pubsub_b.recv::<String>(&mut channels_b),
+        Some((lobby, "hello".to_owned()))
+    );
+    assert_eq!(pubsub_b.recv::<String>(&mut channels_b), None);
+
+    // A message already sent when its topic is unsubscribed from is filtered on receipt.
+    assert!(pubsub_a
+        .publish(&mut channels_a, lobby, "late".to_owned())
+        .is_none());
+    channels_a.flush::<Published<String>>();
+    assert!(pubsub_b.unsubscribe(lobby));
+    run(
+        &mut runtime,
+        (&mut pubsub_a, &mut channels_a),
+        (&mut pubsub_b, &mut channels_b),
+    );
+    assert!(!pubsub_a.is_remote_subscribed(lobby));
+    assert_eq!(pubsub_b.recv::<String>(&mut channels_b), None);
+}
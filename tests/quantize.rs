@@ -0,0 +1,91 @@
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use turbulence::quantize::{dequantize, quantize, Fixed, PackedBools, Quantized, UnitVector};
+
+// The same configuration used by the bincode channels.
+fn bincode_config() -> impl Options {
+    bincode::options()
+}
+
+fn roundtrip<T: Serialize + for<'de> Deserialize<'de>>(value: &T) -> (T, usize) {
+    let bytes = bincode_config().serialize(value).unwrap();
+    (bincode_config().deserialize(&bytes).unwrap(), bytes.len())
+}
+
+#[test]
+fn test_quantize() {
+    assert_eq!(quantize(-1., -1., 1., 8), 0);
+    assert_eq!(quantize(1., -1., 1., 8), 255);
+    assert_eq!(quantize(5., -1., 1., 8), 255);
+    assert_eq!(dequantize(255, -1., 1., 8), 1.);
+    assert!((dequantize(quantize(0.3, -1., 1., 16), -1., 1., 16) - 0.3).abs() < 1e-4);
+
+    let (angle, len) = roundtrip(&Quantized::<-180, 180, 10>(93.4));
+    assert_eq!(len, 2);
+    assert!((angle.0 - 93.4).abs() < 0.2);
+
+    let (position, len) = roundtrip(&Fixed::<100>(0.42));
+    assert_eq!(len, 1);
+    assert!((position.0 - 0.42).abs() < 0.005);
+    let (position, _) = roundtrip(&Fixed::<100>(-1234.567));
+    assert!((position.0 + 1234.567).abs() < 0.005);
+
+    for &v in &[
+        [1., 0., 0.],
+        [0., -1., 0.],
+        [0., 0., -1.],
+        [0.48, -0.6, -0.64],
+        [-0.2, 0.3, 0.9327379],
+    ] {
+        let (unit, len) = roundtrip(&UnitVector::<11>(v));
+        assert_eq!(len, 3);
+        let dot: f32 = unit.0.iter().zip(&v).map(|(a, b)| a * b).sum();
+        assert!(dot > 0.99999, "{:?} decoded as {:?}", v, unit.0);
+    }
+    // The zero vector decodes as (close to) the +Z axis.
+    assert!(roundtrip(&UnitVector::<11>([0., 0., 0.])).0 .0[2] > 0.99999);
+
+    let bools = PackedBools([
+        true, false, true, true, false, false, false, false, true, true,
+    ]);
+    assert_eq!(roundtrip(&bools), (bools, 2));
+}
+
+#[test]
+fn test_quantized_snapshot() {
+    #[derive(Serialize, Deserialize)]
+    struct Full {
+        position: [f32; 3],
+        facing: [f32; 3],
+        yaw: f32,
+        flags: [bool; 8],
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Compact {
+        position: [Fixed<100>; 3],
+        facing: UnitVector<11>,
+        yaw: Quantized<-180, 180, 10>,
+        flags: PackedBools<8>,
+    }
+
+    let full = bincode_config()
+        .serialize(&Full {
+            position: [1.5, -0.25, 0.8],
+            facing: [0., 1., 0.],
+            yaw: 45.,
+            flags: [true; 8],
+        })
+        .unwrap();
+    let compact = bincode_config()
+        .serialize(&Compact {
+            position: [Fixed(1.5), Fixed(-0.25), Fixed(0.8)],
+            facing: UnitVector([0., 1., 0.]),
+            yaw: Quantized(45.),
+            flags: PackedBools([true; 8]),
+        })
+        .unwrap();
+    assert_eq!(full.len(), 36);
+    assert_eq!(compact.len(), 11);
+}
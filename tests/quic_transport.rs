@@ -0,0 +1,101 @@
+#![cfg(all(feature = "quinn", feature = "tokio"))]
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use futures::{SinkExt, StreamExt};
+use quinn::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+        RootCertStore,
+    },
+    ClientConfig, Endpoint, ServerConfig,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    quic_transport::QuicDatagrams,
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// A self-signed certificate for "localhost", only for use in tests.
+const CERT: &[u8] = include_bytes!("quic/cert.der");
+const KEY: &[u8] = include_bytes!("quic/key.der");
+
+fn endpoints() -> (Endpoint, Endpoint) {
+    let localhost: SocketAddr = (Ipv4Addr::LOCALHOST, 0).into();
+    let cert = CertificateDer::from(CERT);
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(KEY));
+
+    let server_config = ServerConfig::with_single_cert(vec![cert.clone()], key).unwrap();
+    let server = Endpoint::server(server_config, localhost).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let mut client = Endpoint::client(localhost).unwrap();
+    client
+        .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+
+    (server, client)
+}
+
+#[test]
+fn test_quic_datagrams() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let packet_pool = BufferPacketPool::new(SimpleBufferPool(512));
+        let mux_packet_pool = MuxPacketPool::new(packet_pool);
+
+        let (server, client) = endpoints();
+        let server_addr = server.local_addr().unwrap();
+
+        let (server_connection, client_connection) = futures::join!(
+            async { server.accept().await.unwrap().await.unwrap() },
+            async {
+                client
+                    .connect(server_addr, "localhost")
+                    .unwrap()
+                    .await
+                    .unwrap()
+            },
+        );
+
+        let mut server_multiplexer = PacketMultiplexer::new();
+        let (mut server_sender, mut server_receiver, _) =
+            server_multiplexer.open_channel(7, 8).unwrap();
+        let (server_incoming, server_outgoing) = server_multiplexer.start();
+        let server_datagrams = QuicDatagrams::new(server_connection, packet_pool).unwrap();
+        tokio::spawn(server_datagrams.run_multiplexer(server_incoming, server_outgoing));
+
+        let mut client_multiplexer = PacketMultiplexer::new();
+        let (mut client_sender, mut client_receiver, _) =
+            client_multiplexer.open_channel(7, 8).unwrap();
+        let (client_incoming, client_outgoing) = client_multiplexer.start();
+        let client_datagrams = QuicDatagrams::new(client_connection, packet_pool).unwrap();
+        assert!(client_datagrams.max_datagram_size().unwrap() >= 512);
+        tokio::spawn(client_datagrams.run_multiplexer(client_incoming, client_outgoing));
+
+        let mut packet = mux_packet_pool.acquire();
+        packet.extend(&[1, 2, 3]);
+        client_sender.send(packet).await.unwrap();
+        let packet = server_receiver.next().await.unwrap();
+        assert_eq!(&packet[..], &[1, 2, 3]);
+
+        let mut packet = mux_packet_pool.acquire();
+        packet.extend(&[4, 5]);
+        server_sender.send(packet).await.unwrap();
+        let packet = client_receiver.next().await.unwrap();
+        assert_eq!(&packet[..], &[4, 5]);
+    });
+}
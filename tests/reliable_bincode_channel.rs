@@ -1,11 +1,14 @@
 use std::time::Duration;
 
-use futures::channel::{mpsc, oneshot};
+use futures::{
+    channel::{mpsc, oneshot},
+    future,
+};
 use rand::{rngs::SmallRng, thread_rng, SeedableRng};
 
 use turbulence::{
     buffer::BufferPacketPool,
-    reliable_bincode_channel::ReliableBincodeChannel,
+    reliable_bincode_channel::{Error, ReliableBincodeChannel},
     reliable_channel::{ReliableChannel, Settings},
     runtime::Runtime,
 };
@@ -14,21 +17,21 @@ mod util;
 
 use self::util::{condition_link, LinkCondition, SimpleBufferPool, SimpleRuntime};
 
+const SETTINGS: Settings = Settings {
+    bandwidth: 2048,
+    burst_bandwidth: 512,
+    recv_window_size: 512,
+    send_window_size: 512,
+    init_send: 256,
+    resend_time: Duration::from_millis(50),
+    initial_rtt: Duration::from_millis(100),
+    max_rtt: Duration::from_millis(2000),
+    rtt_update_factor: 0.1,
+    rtt_resend_factor: 1.5,
+};
+
 #[test]
 fn test_reliable_bincode_channel() {
-    const SETTINGS: Settings = Settings {
-        bandwidth: 2048,
-        burst_bandwidth: 512,
-        recv_window_size: 512,
-        send_window_size: 512,
-        init_send: 256,
-        resend_time: Duration::from_millis(50),
-        initial_rtt: Duration::from_millis(100),
-        max_rtt: Duration::from_millis(2000),
-        rtt_update_factor: 0.1,
-        rtt_resend_factor: 1.5,
-    };
-
     const CONDITION: LinkCondition = LinkCondition {
         loss: 0.2,
         duplicate: 0.05,
@@ -44,7 +47,7 @@ fn test_reliable_bincode_channel() {
     condition_link(
         CONDITION,
         runtime.handle(),
-        packet_pool.clone(),
+        packet_pool,
         SmallRng::from_rng(thread_rng()).unwrap(),
         acondrecv,
         acondsend,
@@ -55,7 +58,7 @@ fn test_reliable_bincode_channel() {
     condition_link(
         CONDITION,
         runtime.handle(),
-        packet_pool.clone(),
+        packet_pool,
         SmallRng::from_rng(thread_rng()).unwrap(),
         bcondrecv,
         bcondsend,
@@ -64,7 +67,7 @@ fn test_reliable_bincode_channel() {
     let mut stream1 = ReliableBincodeChannel::new(
         ReliableChannel::new(
             runtime.handle(),
-            packet_pool.clone(),
+            packet_pool,
             SETTINGS.clone(),
             arecv,
             bsend,
@@ -74,7 +77,7 @@ fn test_reliable_bincode_channel() {
     let mut stream2 = ReliableBincodeChannel::new(
         ReliableChannel::new(
             runtime.handle(),
-            packet_pool.clone(),
+            packet_pool,
             SETTINGS.clone(),
             brecv,
             asend,
@@ -134,3 +137,185 @@ fn test_reliable_bincode_channel() {
 
     panic!("didn't finish in time");
 }
+
+#[test]
+fn test_reliable_bincode_recv_many() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut sender = ReliableBincodeChannel::new(
+        ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS.clone(),
+            arecv,
+            bsend,
+        ),
+        64,
+    );
+    let mut receiver = ReliableBincodeChannel::new(
+        ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS.clone(),
+            brecv,
+            asend,
+        ),
+        64,
+    );
+
+    runtime.spawn(async move {
+        for i in 0..10u32 {
+            sender.send(&i).await.unwrap();
+        }
+        sender.flush().await.unwrap();
+        // Keep the channel open.
+        future::pending::<()>().await;
+    });
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        let mut messages = Vec::<u32>::new();
+        assert_eq!(receiver.recv_many(&mut messages, 4).await.unwrap(), 4);
+        assert_eq!(messages, (0..4).collect::<Vec<_>>());
+        // Only the messages already received are returned.
+        assert_eq!(receiver.recv_many(&mut messages, 100).await.unwrap(), 6);
+        assert_eq!(messages, (0..10).collect::<Vec<_>>());
+        let _ = done_send.send(());
+    });
+    runtime.run_until_stalled();
+    assert!(done.try_recv().unwrap().is_some());
+}
+
+#[test]
+fn test_reliable_bincode_skip_oversized() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut sender = ReliableBincodeChannel::new(
+        ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS.clone(),
+            arecv,
+            bsend,
+        ),
+        256,
+    );
+    let mut receiver = ReliableBincodeChannel::new(
+        ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS.clone(),
+            brecv,
+            asend,
+        ),
+        16,
+    );
+    receiver.set_skip_oversized(true);
+
+    runtime.spawn(async move {
+        sender.send(&1u32).await.unwrap();
+        sender.send(&vec![7u8; 100]).await.unwrap();
+        // A varint marker for a `u16` which is missing, so it fails to decode as a `u32`.
+        sender.send(&251u8).await.unwrap();
+        sender.send(&2u32).await.unwrap();
+        sender.flush().await.unwrap();
+        // Keep the channel open.
+        future::pending::<()>().await;
+    });
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        assert_eq!(receiver.recv::<u32>().await.unwrap(), 1);
+        assert!(matches!(
+            receiver.recv::<u32>().await,
+            Err(Error::MessageTooLarge(101))
+        ));
+        assert!(matches!(
+            receiver.recv::<u32>().await,
+            Err(Error::BincodeError(_))
+        ));
+        assert_eq!(receiver.recv::<u32>().await.unwrap(), 2);
+        let _ = done_send.send(());
+    });
+    runtime.run_until_stalled();
+    assert!(done.try_recv().unwrap().is_some());
+}
+
+#[test]
+fn test_reliable_bincode_buffer_growth() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut sender = ReliableBincodeChannel::with_initial_buffer_len(
+        ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS.clone(),
+            arecv,
+            bsend,
+        ),
+        256,
+        4,
+    );
+    let mut receiver = ReliableBincodeChannel::with_initial_buffer_len(
+        ReliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS.clone(),
+            brecv,
+            asend,
+        ),
+        256,
+        4,
+    );
+    // Both buffers have room for the initial length, and the send buffer for the length prefix.
+    assert_eq!(sender.buffer_len(), 10);
+
+    runtime.spawn(async move {
+        sender.send(&1u32).await.unwrap();
+        assert_eq!(sender.buffer_len(), 10);
+        sender.send(&vec![7u8; 100]).await.unwrap();
+        // The send buffer doubles until the message fits.
+        assert_eq!(sender.buffer_len(), 192 + 4);
+        // Messages longer than the maximum message length are rejected without growing the
+        // buffer.
+        assert!(matches!(
+            sender.send(&vec![7u8; 300]).await,
+            Err(Error::BincodeError(_))
+        ));
+        assert_eq!(sender.buffer_len(), 192 + 4);
+        sender.flush().await.unwrap();
+        // Keep the channel open.
+        future::pending::<()>().await;
+    });
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        assert_eq!(receiver.recv::<u32>().await.unwrap(), 1);
+        assert_eq!(receiver.recv::<Vec<u8>>().await.unwrap(), vec![7u8; 100]);
+        assert_eq!(receiver.buffer_len(), 6 + 101);
+        let _ = done_send.send(());
+    });
+    runtime.run_until_stalled();
+    assert!(done.try_recv().unwrap().is_some());
+}
@@ -1,12 +1,23 @@
-use std::time::Duration;
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
 
-use futures::channel::{mpsc, oneshot};
+use futures::{
+    channel::{mpsc, oneshot},
+    future, stream,
+    task::noop_waker_ref,
+};
 use rand::{rngs::SmallRng, thread_rng, SeedableRng};
 
 use turbulence::{
     buffer::BufferPacketPool,
-    reliable_channel::{ReliableChannel, Settings},
-    runtime::Runtime,
+    loss_statistics::LOSS_BUCKETS,
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{DatagramLimit, MuxPacketPool, MIN_DATAGRAM_SIZE},
+    reliable_channel::{Error, ReliableChannel, Settings, UrgentError, MAX_URGENT_LEN},
+    runtime::{Runtime, TaskFailed, Timer},
+    tick_budget::TickBudget,
 };
 
 mod util;
@@ -43,7 +54,7 @@ fn test_reliable_stream() {
     condition_link(
         CONDITION,
         runtime.handle(),
-        packet_pool.clone(),
+        packet_pool,
         SmallRng::from_rng(thread_rng()).unwrap(),
         acondrecv,
         acondsend,
@@ -54,26 +65,14 @@ fn test_reliable_stream() {
     condition_link(
         CONDITION,
         runtime.handle(),
-        packet_pool.clone(),
+        packet_pool,
         SmallRng::from_rng(thread_rng()).unwrap(),
         bcondrecv,
         bcondsend,
     );
 
-    let mut stream1 = ReliableChannel::new(
-        runtime.handle(),
-        packet_pool.clone(),
-        SETTINGS,
-        arecv,
-        bsend,
-    );
-    let mut stream2 = ReliableChannel::new(
-        runtime.handle(),
-        packet_pool.clone(),
-        SETTINGS,
-        brecv,
-        asend,
-    );
+    let mut stream1 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
 
     const END_POS: usize = 86_753;
     const FLUSH_EVERY: usize = 2000;
@@ -88,8 +87,8 @@ fn test_reliable_stream() {
             let mut c = 0;
 
             loop {
-                for i in 0..send_buffer.len() {
-                    send_buffer[i] = (c + i) as u8;
+                for (i, b) in send_buffer.iter_mut().enumerate() {
+                    *b = (c + i) as u8;
                 }
                 let len = stream1
                     .write(&send_buffer[0..send_buffer.len().min(END_POS - c)])
@@ -125,8 +124,8 @@ fn test_reliable_stream() {
 
             loop {
                 let len = stream2.read(&mut recv_buffer).await.unwrap();
-                for i in 0..len {
-                    if recv_buffer[i] != (c + i) as u8 {
+                for (i, &b) in recv_buffer[0..len].iter().enumerate() {
+                    if b != (c + i) as u8 {
                         panic!();
                     }
                 }
@@ -162,3 +161,552 @@ fn test_reliable_stream() {
 
     panic!("didn't finish in time");
 }
+
+#[test]
+fn test_reliable_task_failure() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    // An incoming packet stream that panics the channel task as soon as it is polled.
+    let incoming = stream::poll_fn(
+        |_| -> Poll<Option<<BufferPacketPool<SimpleBufferPool> as PacketPool>::Packet>> {
+            panic!("incoming packet stream failure")
+        },
+    );
+    let (outgoing, _outgoing_recv) = mpsc::channel(2);
+    let mut stream =
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, incoming, outgoing);
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        let mut buffer = [0; 16];
+        match stream.read(&mut buffer).await {
+            Err(Error::TaskFailed(TaskFailed::Panicked(msg))) => {
+                assert_eq!(msg, "incoming packet stream failure");
+            }
+            res => panic!("unexpected read result {:?}", res),
+        }
+        assert!(matches!(stream.write(&buffer).await, Err(Error::Shutdown)));
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..100_000 {
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+
+    panic!("didn't finish in time");
+}
+
+#[test]
+fn test_reliable_urgent() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 8192,
+        burst_bandwidth: 2048,
+        recv_window_size: 4096,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    const CONDITION: LinkCondition = LinkCondition {
+        loss: 0.3,
+        duplicate: 0.1,
+        delay: Duration::from_millis(30),
+        jitter: Duration::from_millis(20),
+    };
+
+    const MESSAGES: u8 = 20;
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    let (asend, acondrecv) = mpsc::channel(2);
+    let (acondsend, arecv) = mpsc::channel(2);
+    condition_link(
+        CONDITION,
+        runtime.handle(),
+        packet_pool,
+        SmallRng::seed_from_u64(1),
+        acondrecv,
+        acondsend,
+    );
+
+    let (bsend, bcondrecv) = mpsc::channel(2);
+    let (bcondsend, brecv) = mpsc::channel(2);
+    condition_link(
+        CONDITION,
+        runtime.handle(),
+        packet_pool,
+        SmallRng::seed_from_u64(2),
+        bcondrecv,
+        bcondsend,
+    );
+
+    let mut stream1 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    runtime.spawn(async move {
+        // Bulk data which is never read, so the stream stalls on the remote receive window.
+        let mut written = 0;
+        while written < SETTINGS.send_window_size as usize {
+            written += stream1.write(&[0; 1024]).await.unwrap();
+        }
+        stream1.flush().await.unwrap();
+
+        assert!(matches!(
+            stream1.send_urgent(&[0; MAX_URGENT_LEN + 1]).await,
+            Err(UrgentError::TooLarge)
+        ));
+        for i in 0..MESSAGES {
+            stream1.send_urgent(&[i; 16]).await.unwrap();
+        }
+        future::pending::<()>().await;
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        // Urgent messages arrive exactly once, though not necessarily in order.
+        let mut received = Vec::new();
+        for _ in 0..MESSAGES {
+            let msg = stream2.recv_urgent().await.unwrap();
+            assert_eq!(msg.len(), 16);
+            received.push(msg[0]);
+        }
+        received.sort_unstable();
+        assert_eq!(received, (0..MESSAGES).collect::<Vec<_>>());
+        done_send.send(stream2).ok().unwrap();
+    });
+
+    for _ in 0..10_000 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
+
+#[test]
+fn test_reliable_poll() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    let (asend, arecv) = mpsc::channel(2);
+    let (bsend, brecv) = mpsc::channel(2);
+    let mut stream1 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    let mut buffer = [0; 16];
+    assert!(stream2.poll_read(&mut cx, &mut buffer).is_pending());
+    assert!(matches!(
+        stream1.poll_write(&mut cx, b"hello"),
+        Poll::Ready(Ok(5))
+    ));
+    assert!(stream1.poll_flush(&mut cx).is_ready());
+
+    let mut read = Poll::Pending;
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        read = stream2.poll_read(&mut cx, &mut buffer);
+        if read.is_ready() {
+            break;
+        }
+        runtime.advance_time(50);
+    }
+    assert!(matches!(read, Poll::Ready(Ok(5))));
+    assert_eq!(&buffer[..5], b"hello");
+}
+
+#[test]
+fn test_reliable_tick_budget() {
+    // Nothing is acknowledged, so resends are pushed past the end of the test.
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 4096,
+        resend_time: Duration::from_secs(10),
+        initial_rtt: Duration::from_secs(10),
+        max_rtt: Duration::from_secs(20),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+    let start = runtime.handle().now();
+
+    let (_incoming, incoming_recv) = mpsc::channel(2);
+    let (outgoing_send, mut outgoing) = mpsc::channel(64);
+    let mut stream = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        incoming_recv,
+        outgoing_send,
+    );
+    stream.set_tick_budget(Some(TickBudget {
+        bytes_per_tick: 100,
+        tick: Duration::from_millis(10),
+    }));
+
+    runtime.spawn(async move {
+        let data = [7; 350];
+        let mut written = 0;
+        while written < data.len() {
+            written += stream.write(&data[written..]).await.unwrap();
+        }
+        stream.flush().await.unwrap();
+        future::pending::<()>().await;
+    });
+
+    let mut sent = Vec::new();
+    for _ in 0..50 {
+        runtime.run_until_stalled();
+        while let Ok(packet) = outgoing.try_recv() {
+            sent.push((
+                runtime.handle().elapsed(start).as_millis() as u64,
+                packet.len() - 6,
+            ));
+        }
+        runtime.advance_time(1);
+    }
+    // The task first sends a millisecond in, the rest waits for the start of each later tick.
+    assert_eq!(sent, vec![(1, 100), (10, 100), (20, 100), (30, 50)]);
+}
+
+#[test]
+fn test_reliable_loss_statistics() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 4096,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    // Every packet carries 20 bytes of data after its 6 byte header.
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(26));
+    let mut runtime = SimpleRuntime::new();
+
+    let (mut acks, incoming_recv) = mpsc::channel(8);
+    let (outgoing_send, mut outgoing) = mpsc::channel(64);
+    let mut stream = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        incoming_recv,
+        outgoing_send,
+    );
+    let statistics = stream.loss_statistics();
+
+    runtime.spawn(async move {
+        let data = [7; 100];
+        let mut written = 0;
+        while written < data.len() {
+            written += stream.write(&data[written..]).await.unwrap();
+        }
+        stream.flush().await.unwrap();
+        future::pending::<()>().await;
+    });
+
+    // The start of each data packet sent, ignoring congestion feedback.
+    let mut sent_starts = |runtime: &mut SimpleRuntime| {
+        runtime.run_until_stalled();
+        let mut starts = Vec::new();
+        while let Ok(packet) = outgoing.try_recv() {
+            if i16::from_le_bytes([packet[0], packet[1]]) > 0 {
+                starts.push(u32::from_le_bytes([
+                    packet[2], packet[3], packet[4], packet[5],
+                ]));
+            }
+        }
+        starts
+    };
+    let mut ack = |runtime: &mut SimpleRuntime, start: u32| {
+        let mut packet = packet_pool.acquire();
+        packet.extend(&(-20i16).to_le_bytes());
+        packet.extend(&start.to_le_bytes());
+        packet.extend(&4096u32.to_le_bytes());
+        acks.try_send(packet).unwrap();
+        runtime.run_until_stalled();
+    };
+
+    runtime.run_until_stalled();
+    runtime.advance_time(1);
+    assert_eq!(sent_starts(&mut runtime), vec![0, 20, 40, 60, 80]);
+
+    // The second and third packets are lost, and the fourth arrives after the fifth.
+    ack(&mut runtime, 0);
+    ack(&mut runtime, 80);
+    ack(&mut runtime, 60);
+
+    let mut resent = Vec::new();
+    for _ in 0..1000 {
+        resent.extend(sent_starts(&mut runtime));
+        if !resent.is_empty() {
+            break;
+        }
+        runtime.advance_time(1);
+    }
+    resent.sort_unstable();
+    assert_eq!(resent, vec![20, 40]);
+    ack(&mut runtime, 20);
+    ack(&mut runtime, 40);
+
+    assert_eq!(statistics.delivered(), 5);
+    assert_eq!(statistics.lost(), 2);
+    let mut bursts = [0; LOSS_BUCKETS];
+    bursts[1] = 1;
+    assert_eq!(statistics.burst_loss_lengths(), bursts);
+    let mut reordered = [0; LOSS_BUCKETS];
+    reordered[0] = 1;
+    assert_eq!(statistics.reorder_distances(), reordered);
+}
+
+#[test]
+fn test_reliable_datagram_limit() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 4096,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let limit = DatagramLimit::new();
+    let packet_pool = MuxPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)))
+        .with_datagram_limit(limit.clone());
+    let mut runtime = SimpleRuntime::new();
+
+    let (mut acks, incoming_recv) = mpsc::channel(8);
+    let (outgoing_send, mut outgoing) = mpsc::channel(64);
+    let mut stream = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool.clone(),
+        SETTINGS,
+        incoming_recv,
+        outgoing_send,
+    );
+
+    runtime.spawn(async move {
+        let data = [7; 100];
+        let mut written = 0;
+        while written < data.len() {
+            written += stream.write(&data[written..]).await.unwrap();
+        }
+        stream.flush().await.unwrap();
+        future::pending::<()>().await;
+    });
+
+    // The start and length of each data packet sent, ignoring congestion feedback, once some are.
+    let mut sent = |runtime: &mut SimpleRuntime| {
+        let mut sent = Vec::new();
+        for _ in 0..1000 {
+            runtime.run_until_stalled();
+            while let Ok(packet) = outgoing.try_recv() {
+                assert!(packet.len() <= packet.capacity());
+                let len = i16::from_le_bytes([packet[0], packet[1]]);
+                if len > 0 {
+                    let start = u32::from_le_bytes([packet[2], packet[3], packet[4], packet[5]]);
+                    sent.push((start, len));
+                }
+            }
+            if !sent.is_empty() {
+                break;
+            }
+            runtime.advance_time(1);
+        }
+        sent.sort_unstable();
+        sent
+    };
+
+    // Packets carry up to 57 bytes of data after the multiplexer and data headers.
+    assert_eq!(sent(&mut runtime), vec![(0, 57), (57, 43)]);
+
+    // Once the limit decreases, only what fits of each range is resent.
+    limit.set_max_datagram_size(Some(27));
+    assert_eq!(sent(&mut runtime), vec![(0, 20), (57, 20)]);
+
+    // The rest of a range is resent once its start is acknowledged.
+    let mut packet = packet_pool.acquire();
+    packet.extend(&(-20i16).to_le_bytes());
+    packet.extend(&0u32.to_le_bytes());
+    packet.extend(&4096u32.to_le_bytes());
+    acks.try_send(packet).unwrap();
+    assert_eq!(sent(&mut runtime), vec![(20, 20)]);
+
+    // Limits too small for any data are raised to the smallest workable datagram.
+    limit.set_max_datagram_size(Some(1));
+    assert_eq!(limit.max_datagram_size(), Some(MIN_DATAGRAM_SIZE));
+    let resent = sent(&mut runtime);
+    assert!(!resent.is_empty());
+    assert!(resent.iter().all(|&(_, len)| len == 1));
+}
+
+#[test]
+fn test_reliable_urgent_datagram_limit() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 4096,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let limit = DatagramLimit::new();
+    let packet_pool = MuxPacketPool::new(BufferPacketPool::new(SimpleBufferPool(64)))
+        .with_datagram_limit(limit.clone());
+    let mut runtime = SimpleRuntime::new();
+
+    let (_acks, incoming_recv) = mpsc::channel(8);
+    let (outgoing_send, mut outgoing) = mpsc::channel(64);
+    let mut stream = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        incoming_recv,
+        outgoing_send,
+    );
+
+    runtime.spawn(async move {
+        // The first message is queued while it still fits, but the limit shrinks before it is
+        // sent.
+        stream.send_urgent(&[1; 40]).await.unwrap();
+        limit.set_max_datagram_size(Some(20));
+        stream.send_urgent(&[2; 4]).await.unwrap();
+        future::pending::<()>().await;
+    });
+
+    // The urgent messages sent, once some are.
+    let mut sent = Vec::new();
+    for _ in 0..1000 {
+        runtime.run_until_stalled();
+        while let Ok(packet) = outgoing.try_recv() {
+            assert!(packet.len() <= packet.capacity());
+            if i16::from_le_bytes([packet[0], packet[1]]) == i16::MIN {
+                sent.push(packet[7..].to_vec());
+            }
+        }
+        if !sent.is_empty() {
+            break;
+        }
+        runtime.advance_time(1);
+    }
+    assert_eq!(sent, vec![vec![2; 4]]);
+}
+
+#[test]
+fn test_reliable_urgent_seq_wrap() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 4096,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut runtime = SimpleRuntime::new();
+
+    let (mut incoming, incoming_recv) = mpsc::channel(8);
+    let (outgoing_send, mut outgoing) = mpsc::channel(64);
+    let mut stream = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        incoming_recv,
+        outgoing_send,
+    );
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            received.push(stream.recv_urgent().await.unwrap()[0]);
+        }
+        done_send.send(received).unwrap();
+        future::pending::<()>().await;
+    });
+
+    // Urgent data packets from a remote whose sequence numbers are about to wrap, with a replay
+    // of the last sequence number before the wrap.
+    for &seq in &[u32::MAX - 1, u32::MAX, 0, u32::MAX, 1] {
+        let mut packet = packet_pool.acquire();
+        packet.extend(&i16::MIN.to_le_bytes());
+        packet.extend(&[0]);
+        packet.extend(&seq.to_le_bytes());
+        packet.extend(&[seq as u8]);
+        incoming.try_send(packet).unwrap();
+        runtime.run_until_stalled();
+    }
+
+    // Messages after the wrap are delivered rather than taken for replays, and every packet is
+    // acknowledged, including the duplicate.
+    assert_eq!(done.try_recv().unwrap(), Some(vec![254, 255, 0, 1]));
+    let mut acked = 0;
+    while let Ok(packet) = outgoing.try_recv() {
+        if i16::from_le_bytes([packet[0], packet[1]]) == i16::MIN {
+            acked += 1;
+        }
+    }
+    assert_eq!(acked, 5);
+}
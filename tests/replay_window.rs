@@ -0,0 +1,29 @@
+use turbulence::replay_window::{ReplayWindow, WINDOW_SIZE};
+
+#[test]
+fn test_replay_window() {
+    let mut window = ReplayWindow::new();
+    assert_eq!(window.highest(), None);
+
+    assert!(window.insert(5));
+    assert!(!window.insert(5));
+    assert!(window.insert(3));
+    assert!(!window.accepts(3));
+    assert!(window.accepts(4));
+    assert_eq!(window.highest(), Some(5));
+
+    // Moving the window forward forgets sequence numbers that fall behind it.
+    assert!(window.insert(WINDOW_SIZE + 4));
+    assert!(!window.accepts(4));
+    assert!(window.accepts(6));
+    assert!(window.insert(WINDOW_SIZE));
+    assert!(!window.insert(WINDOW_SIZE));
+    assert!(!window.accepts(WINDOW_SIZE + 4));
+
+    // Slots reused from before the window moved must not be treated as already received.
+    assert!(window.insert(WINDOW_SIZE + 5));
+    assert!(window.insert(3 * WINDOW_SIZE));
+    assert!(window.accepts(3 * WINDOW_SIZE - 1));
+    assert!(!window.accepts(2 * WINDOW_SIZE));
+    assert!(window.accepts(2 * WINDOW_SIZE + 5));
+}
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use futures::{channel::mpsc, executor::block_on, FutureExt};
+use serde::{Deserialize, Serialize};
+
+use turbulence::{
+    acked_channel::{self, AckedChannel},
+    buffer::{BufferPacket, BufferPacketPool},
+    replication::{Change, PeerId, Replica, Replicator, Settings},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime, SimpleRuntimeHandle};
+
+const CHANNEL_SETTINGS: acked_channel::Settings = acked_channel::Settings {
+    bandwidth: 65536,
+    burst_bandwidth: 65536,
+    loss_timeout: Duration::from_millis(500),
+};
+
+const SETTINGS: Settings = Settings {
+    update_budget: 4096,
+    max_message_len: 1000,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Position(i32, i32);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Inventory(Vec<u8>);
+
+type TestPacket = BufferPacket<Box<[u8]>>;
+type TestPool = BufferPacketPool<SimpleBufferPool>;
+
+// A replicator with a single peer, connected by links whose packets are only delivered by `tick`.
+struct Link {
+    replicator: Replicator<SimpleRuntimeHandle, TestPool>,
+    peer: PeerId,
+    replica: Replica<SimpleRuntimeHandle, TestPool>,
+    to_replica: (mpsc::Receiver<TestPacket>, mpsc::Sender<TestPacket>),
+    to_replicator: (mpsc::Receiver<TestPacket>, mpsc::Sender<TestPacket>),
+}
+
+impl Link {
+    fn new(runtime: &SimpleRuntime, settings: Settings) -> Link {
+        let pool = BufferPacketPool::new(SimpleBufferPool(1200));
+        let (server_incoming, server_recv) = mpsc::channel(64);
+        let (server_send, server_outgoing) = mpsc::channel(64);
+        let (client_incoming, client_recv) = mpsc::channel(64);
+        let (client_send, client_outgoing) = mpsc::channel(64);
+
+        let mut replicator = Replicator::new(settings);
+        replicator.register::<Position>(0).unwrap();
+        replicator.register::<Inventory>(1).unwrap();
+        let peer = replicator.add_peer(AckedChannel::new(
+            runtime.handle(),
+            pool,
+            CHANNEL_SETTINGS,
+            server_recv,
+            server_send,
+        ));
+
+        let mut replica = Replica::new(AckedChannel::new(
+            runtime.handle(),
+            pool,
+            CHANNEL_SETTINGS,
+            client_recv,
+            client_send,
+        ));
+        replica.register::<Position>(0).unwrap();
+        replica.register::<Inventory>(1).unwrap();
+
+        Link {
+            replicator,
+            peer,
+            replica,
+            to_replica: (server_outgoing, client_incoming),
+            to_replicator: (client_outgoing, server_incoming),
+        }
+    }
+
+    // Send an update, and deliver it to the replica unless it is dropped, returning the changes
+    // applied and the bytes sent.
+    fn tick(&mut self, drop: bool) -> (Vec<Change>, usize) {
+        let update = block_on(self.replicator.send(self.peer)).unwrap();
+        while let Ok(packet) = self.to_replica.0.try_recv() {
+            if !drop {
+                self.to_replica.1.try_send(packet).unwrap();
+            }
+        }
+
+        let mut changes = Vec::new();
+        while let Some(res) = self.replica.recv().now_or_never() {
+            changes.extend_from_slice(res.unwrap());
+        }
+        block_on(self.replica.flush()).unwrap();
+        while let Ok(packet) = self.to_replicator.0.try_recv() {
+            self.to_replicator.1.try_send(packet).unwrap();
+        }
+        (changes, update.bytes)
+    }
+}
+
+#[test]
+fn test_replication() {
+    let mut runtime = SimpleRuntime::new();
+    let mut link = Link::new(&runtime, SETTINGS);
+
+    for entity in 0..3 {
+        link.replicator
+            .set(entity, &Position(entity as i32, 0))
+            .unwrap();
+    }
+    link.replicator.set(1, &Inventory(vec![1, 2])).unwrap();
+
+    // The first update is lost, so once it is presumed lost everything is sent again.
+    link.tick(true);
+    assert_eq!(link.tick(false), (vec![], 0));
+    runtime.advance_time(500);
+    assert_eq!(link.replicator.pending(link.peer), 4);
+    let (changes, _) = link.tick(false);
+    assert_eq!(changes.len(), 4);
+    link.tick(false);
+    assert_eq!(link.replicator.pending(link.peer), 0);
+    for entity in 0..3 {
+        assert_eq!(
+            link.replica.get::<Position>(entity),
+            Some(&Position(entity as i32, 0))
+        );
+    }
+    assert_eq!(
+        link.replica.get::<Inventory>(1),
+        Some(&Inventory(vec![1, 2]))
+    );
+    assert_eq!(link.replica.get::<Inventory>(0), None);
+
+    // Setting an unchanged value sends nothing.
+    link.replicator.set(0, &Position(0, 0)).unwrap();
+    assert_eq!(link.tick(false), (vec![], 0));
+
+    link.replicator.set(2, &Position(2, 5)).unwrap();
+    link.replicator.despawn(1);
+    let (changes, _) = link.tick(false);
+    assert_eq!(changes.len(), 3);
+    assert_eq!(link.replica.get::<Position>(2), Some(&Position(2, 5)));
+    assert_eq!(link.replica.get::<Position>(1), None);
+    assert_eq!(link.replica.get::<Inventory>(1), None);
+    link.tick(false);
+    assert_eq!(link.replicator.pending(link.peer), 0);
+}
+
+#[test]
+fn test_replication_deltas() {
+    let runtime = SimpleRuntime::new();
+    let mut link = Link::new(&runtime, SETTINGS);
+
+    let mut inventory = vec![7; 200];
+    link.replicator
+        .set(0, &Inventory(inventory.clone()))
+        .unwrap();
+    let (_, full) = link.tick(false);
+    assert!(full > 200);
+
+    for i in 0..10 {
+        inventory[100] = i;
+        link.replicator
+            .set(0, &Inventory(inventory.clone()))
+            .unwrap();
+        // Every other update is lost, so some deltas are against older baselines.
+        let (changes, bytes) = link.tick(i % 2 == 0);
+        assert!(bytes < 30);
+        if i % 2 == 1 {
+            assert_eq!(changes.len(), 1);
+            assert_eq!(link.replica.get::<Inventory>(0).unwrap().0, inventory);
+        }
+    }
+}
+
+#[test]
+fn test_replication_priority() {
+    let runtime = SimpleRuntime::new();
+    // Only room for two positions per update.
+    let mut link = Link::new(
+        &runtime,
+        Settings {
+            update_budget: 40,
+            max_message_len: 1000,
+        },
+    );
+
+    for entity in 0..4 {
+        link.replicator.set(entity, &Position(0, 0)).unwrap();
+    }
+    link.replicator.set_priority(3, 4.0);
+
+    let mut sent = Vec::new();
+    for tick in 1..=20 {
+        for entity in 0..4 {
+            link.replicator.set(entity, &Position(tick, 0)).unwrap();
+        }
+        let (changes, _) = link.tick(false);
+        assert_eq!(changes.len(), 2);
+        sent.extend(changes.iter().map(|change| change.entity));
+    }
+
+    // The high priority entity is sent every update, and the rest are not starved.
+    assert_eq!(sent.iter().filter(|&&entity| entity == 3).count(), 20);
+    for entity in 0..3 {
+        assert!(sent.iter().filter(|&&e| e == entity).count() >= 5);
+    }
+}
@@ -0,0 +1,157 @@
+#![cfg(feature = "rkyv")]
+
+use std::time::Duration;
+
+use futures::{
+    channel::{mpsc, oneshot},
+    executor::block_on,
+    future,
+};
+use rkyv::{Archive, Serialize};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    reliable_channel::{self, ReliableChannel},
+    rkyv_channel::{RecvError, ReliableRkyvChannel, SendError, UnreliableRkyvChannel},
+    runtime::Runtime,
+    unreliable_channel::{self, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Archive, Serialize)]
+struct Snapshot {
+    tick: u32,
+    positions: Vec<(f32, f32)>,
+}
+
+#[test]
+fn test_unreliable_rkyv_channel() {
+    const SETTINGS: unreliable_channel::Settings = unreliable_channel::Settings {
+        bandwidth: 16384,
+        burst_bandwidth: 4096,
+    };
+
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut stream1 = UnreliableRkyvChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        512,
+    );
+    let mut stream2 = UnreliableRkyvChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+        512,
+    );
+
+    block_on(async {
+        stream1
+            .send(&Snapshot {
+                tick: 7,
+                positions: vec![(1.0, 2.0), (3.0, 4.0)],
+            })
+            .await
+            .unwrap();
+
+        // Messages whose archived form is larger than the max message length are not sent.
+        assert!(matches!(
+            stream1
+                .send(&Snapshot {
+                    tick: 8,
+                    positions: vec![(0.0, 0.0); 100],
+                })
+                .await,
+            Err(SendError::TooLarge)
+        ));
+        stream1.flush().await.unwrap();
+
+        let snapshot = stream2.recv::<Snapshot>().await.unwrap();
+        assert_eq!(snapshot.tick, 7);
+        assert_eq!(snapshot.positions.len(), 2);
+        assert_eq!(snapshot.positions[1].0, 3.0);
+        assert_eq!(snapshot.positions[1].1, 4.0);
+
+        // Messages which fail validation are skipped.
+        stream1.send(&7u8).await.unwrap();
+        stream1.flush().await.unwrap();
+        assert!(matches!(
+            stream2.recv::<Snapshot>().await,
+            Err(RecvError::RkyvError(_))
+        ));
+    });
+}
+
+#[test]
+fn test_reliable_rkyv_channel() {
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 4096,
+        send_window_size: 4096,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut stream1 = ReliableRkyvChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        4096,
+    );
+    let mut stream2 = ReliableRkyvChannel::new(
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+        4096,
+    );
+
+    runtime.spawn(async move {
+        for i in 0..20 {
+            // Snapshots larger than a single packet are split across packets.
+            stream1
+                .send(&Snapshot {
+                    tick: i,
+                    positions: vec![(i as f32, -(i as f32)); 200],
+                })
+                .await
+                .unwrap();
+        }
+        stream1.flush().await.unwrap();
+        // Keep the channel alive until the other side has received everything.
+        future::pending::<()>().await;
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        for i in 0..20 {
+            let snapshot = stream2.recv::<Snapshot>().await.unwrap();
+            assert_eq!(snapshot.tick, i);
+            assert_eq!(snapshot.positions.len(), 200);
+            assert!(snapshot
+                .positions
+                .iter()
+                .all(|p| p.0 == i as f32 && p.1 == -(i as f32)));
+        }
+        done_send.send(()).unwrap();
+    });
+
+    for _ in 0..1000 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("didn't finish in time");
+}
@@ -0,0 +1,129 @@
+#![allow(unused)]
+
+use std::time::Duration;
+
+use futures::channel::{mpsc, oneshot};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    reliable_channel::{self, ReliableChannel},
+    runtime::{Runtime, TaskFailed},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// Conformance checks that every provided `Runtime` implementation must pass.
+async fn check_runtime<R: Runtime + 'static>(runtime: R) {
+    // Spawned tasks must actually run.
+    let (send, recv) = oneshot::channel();
+    runtime.spawn(async move {
+        send.send(17).unwrap();
+    });
+    assert_eq!(recv.await.unwrap(), 17);
+
+    // Task handles must report the task output, or that the task panicked.
+    assert_eq!(runtime.spawn_with_handle(async { 23 }).await.unwrap(), 23);
+    match runtime
+        .spawn_with_handle(async { panic!("check panic") })
+        .await
+    {
+        Err(TaskFailed::Panicked(msg)) => assert_eq!(msg, "check panic"),
+        res => panic!("unexpected task result {:?}", res),
+    }
+
+    // Blocking closures must also report their output, or that they panicked.
+    assert_eq!(runtime.spawn_blocking(|| 29).await.unwrap(), 29);
+    match runtime
+        .spawn_blocking(|| panic!("check blocking panic"))
+        .await
+    {
+        Err(TaskFailed::Panicked(msg)) => assert_eq!(msg, "check blocking panic"),
+        res => panic!("unexpected blocking result {:?}", res),
+    }
+
+    // Sleeps must wait for at least the given duration, and the clock must agree with them.
+    let start = runtime.now();
+    runtime.sleep(Duration::from_millis(20)).await;
+    let end = runtime.now();
+    assert!(runtime.elapsed(start) >= Duration::from_millis(20));
+    assert!(runtime.duration_between(start, end) >= Duration::from_millis(20));
+    assert!(runtime.elapsed(start) >= runtime.duration_between(start, end));
+
+    // Sleeps created outside of a task must also work, and a zero sleep must resolve.
+    let sleep = runtime.sleep(Duration::from_millis(0));
+    sleep.await;
+
+    // The runtime must be able to drive a pair of reliable channels.
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 65536,
+        burst_bandwidth: 4096,
+        recv_window_size: 4096,
+        send_window_size: 4096,
+        init_send: 512,
+        resend_time: Duration::from_millis(20),
+        initial_rtt: Duration::from_millis(10),
+        max_rtt: Duration::from_millis(200),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+    const LEN: usize = 8192;
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(512));
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut channel_a = ReliableChannel::new(runtime.clone(), packet_pool, SETTINGS, arecv, bsend);
+    let mut channel_b = ReliableChannel::new(runtime.clone(), packet_pool, SETTINGS, brecv, asend);
+
+    let (done_send, done_recv) = oneshot::channel();
+    runtime.spawn(async move {
+        let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+        let mut pos = 0;
+        while pos < LEN {
+            pos += channel_a.write(&data[pos..]).await.unwrap();
+            channel_a.flush().await.unwrap();
+        }
+        let _ = done_send.send(channel_a);
+    });
+
+    let mut buffer = [0; 256];
+    let mut pos = 0;
+    while pos < LEN {
+        let len = channel_b.read(&mut buffer).await.unwrap();
+        for (i, &b) in buffer[0..len].iter().enumerate() {
+            assert_eq!(b, (pos + i) as u8);
+        }
+        pos += len;
+    }
+    let _channel_a = done_recv.await.unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_tokio_runtime() {
+    use turbulence::runtime::TokioRuntime;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let turbulence_runtime = TokioRuntime::new(runtime.handle().clone());
+    runtime.block_on(check_runtime(turbulence_runtime));
+}
+
+#[cfg(feature = "async-std")]
+#[test]
+fn test_async_std_runtime() {
+    use turbulence::runtime::AsyncStdRuntime;
+
+    async_std::task::block_on(check_runtime(AsyncStdRuntime));
+}
+
+#[cfg(feature = "smol")]
+#[test]
+fn test_smol_runtime() {
+    use turbulence::runtime::SmolRuntime;
+
+    smol::block_on(check_runtime(SmolRuntime));
+}
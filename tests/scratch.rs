@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    compressed_bincode_channel::CompressedBincodeChannel,
+    reliable_bincode_channel::ReliableBincodeChannel,
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::Runtime,
+    scratch::ScratchArena,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 4096,
+    burst_bandwidth: 1024,
+    recv_window_size: 1024,
+    send_window_size: 1024,
+    init_send: 512,
+    resend_time: Duration::from_millis(50),
+    initial_rtt: Duration::from_millis(100),
+    max_rtt: Duration::from_millis(2000),
+    rtt_update_factor: 0.1,
+    rtt_resend_factor: 1.5,
+};
+
+#[test]
+fn test_scratch_arena() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+    let arena = ScratchArena::new(8);
+
+    let reliable_pair = || {
+        let (asend, arecv) = mpsc::channel(8);
+        let (bsend, brecv) = mpsc::channel(8);
+        (
+            ReliableChannel::new(
+                runtime.handle(),
+                packet_pool,
+                SETTINGS.clone(),
+                arecv,
+                bsend,
+            ),
+            ReliableChannel::new(
+                runtime.handle(),
+                packet_pool,
+                SETTINGS.clone(),
+                brecv,
+                asend,
+            ),
+        )
+    };
+
+    let mut bincode_pairs = Vec::new();
+    for _ in 0..2 {
+        let (a, b) = reliable_pair();
+        let mut a = ReliableBincodeChannel::new(a, 512);
+        let mut b = ReliableBincodeChannel::new(b, 512);
+        a.set_scratch_arena(Some(arena.clone()));
+        b.set_scratch_arena(Some(arena.clone()));
+        bincode_pairs.push((a, b));
+    }
+    // Buffers owned before the arena was set are given to it.
+    assert_eq!(arena.free_buffers(), 8);
+    assert!(bincode_pairs[0].0.scratch_arena().is_some());
+
+    let (a, b) = reliable_pair();
+    let mut compressed_sender = CompressedBincodeChannel::new(a, 512);
+    let mut compressed_receiver = CompressedBincodeChannel::new(b, 512);
+    compressed_sender.set_scratch_arena(Some(arena.clone()));
+    compressed_receiver.set_scratch_arena(Some(arena.clone()));
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        for (sender, receiver) in &mut bincode_pairs {
+            sender.send(&vec![1u8; 300]).await.unwrap();
+            sender.flush().await.unwrap();
+            assert_eq!(sender.buffer_len(), 0);
+            assert_eq!(receiver.recv::<Vec<u8>>().await.unwrap(), vec![1u8; 300]);
+        }
+
+        for i in 0..20u32 {
+            compressed_sender.send(&i).await.unwrap();
+        }
+        compressed_sender.flush().await.unwrap();
+        for i in 0..20u32 {
+            assert_eq!(compressed_receiver.recv::<u32>().await.unwrap(), i);
+        }
+
+        let _ = done_send.send((bincode_pairs, compressed_sender, compressed_receiver));
+        future::pending::<()>().await;
+    });
+    for _ in 0..20 {
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+    let (mut bincode_pairs, _compressed_sender, compressed_receiver) =
+        done.try_recv().unwrap().unwrap();
+
+    // The receivers keep the buffers holding the last message they received until they next
+    // receive, since the message may borrow from them.  Everything else has been returned.
+    assert_eq!(bincode_pairs[0].1.buffer_len(), 512);
+    assert_eq!(bincode_pairs[1].1.buffer_len(), 512);
+    assert_eq!(bincode_pairs[0].0.buffer_len(), 0);
+    assert_eq!(arena.free_buffers(), 5);
+    assert!(arena.free_bytes() > 0);
+
+    // Dropped channels return their buffers, and channels which stop using the arena keep them.
+    bincode_pairs[1].1.set_scratch_arena(None);
+    drop(bincode_pairs);
+    drop(compressed_receiver);
+    assert_eq!(arena.free_buffers(), 7);
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use turbulence::{
+    message_channels::{MessageChannelMode, MessageChannelSettings},
+    reliable_channel, unreliable_channel,
+};
+
+#[test]
+fn test_settings_serde() {
+    let settings = vec![
+        MessageChannelSettings {
+            channel: 0,
+            channel_mode: MessageChannelMode::Reliable {
+                settings: reliable_channel::Settings {
+                    bandwidth: 4096,
+                    burst_bandwidth: 1024,
+                    recv_window_size: 1024,
+                    send_window_size: 1024,
+                    init_send: 512,
+                    resend_time: Duration::from_millis(100),
+                    initial_rtt: Duration::from_millis(200),
+                    max_rtt: Duration::from_secs(2),
+                    rtt_update_factor: 0.1,
+                    rtt_resend_factor: 1.5,
+                },
+                max_message_len: 1024,
+            },
+            message_buffer_size: 8,
+            packet_buffer_size: 8,
+        },
+        MessageChannelSettings {
+            channel: 1,
+            channel_mode: MessageChannelMode::Unreliable {
+                settings: unreliable_channel::Settings {
+                    bandwidth: 4096,
+                    burst_bandwidth: 1024,
+                },
+                max_message_len: 64,
+            },
+            message_buffer_size: 8,
+            packet_buffer_size: 8,
+        },
+    ];
+
+    let serialized = bincode::serialize(&settings).unwrap();
+    let deserialized: Vec<MessageChannelSettings> = bincode::deserialize(&serialized).unwrap();
+    assert_eq!(deserialized, settings);
+}
@@ -0,0 +1,254 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future, StreamExt,
+};
+use rand::{rngs::SmallRng, SeedableRng};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::{cancellable, Runtime, SimulationRuntime, Timer},
+};
+
+mod util;
+
+use self::util::{condition_link, LinkCondition, SimpleBufferPool};
+
+#[test]
+fn test_simulation_timers() {
+    let mut runtime = SimulationRuntime::new();
+    let handle = runtime.handle();
+    let woken = Arc::new(Mutex::new(Vec::new()));
+
+    for &millis in &[250, 100, 100, 0] {
+        let handle = handle.clone();
+        let woken = Arc::clone(&woken);
+        runtime.handle().spawn(async move {
+            handle.sleep(Duration::from_millis(millis)).await;
+            woken.lock().unwrap().push((millis, handle.now()));
+        });
+    }
+
+    // Nothing happens until the clock is advanced, not even zero duration sleeps.
+    assert!(!runtime.run_until_stalled());
+    assert!(woken.lock().unwrap().is_empty());
+    assert_eq!(runtime.next_timer(), Some(Duration::from_nanos(1)));
+
+    assert!(runtime.step());
+    assert_eq!(runtime.now(), Duration::from_nanos(1));
+    assert_eq!(runtime.next_timer(), Some(Duration::from_millis(100)));
+
+    runtime.run_for(Duration::from_secs(1));
+    assert_eq!(
+        runtime.now(),
+        Duration::from_secs(1) + Duration::from_nanos(1)
+    );
+    assert_eq!(
+        *woken.lock().unwrap(),
+        vec![
+            (0, Duration::from_nanos(1)),
+            (100, Duration::from_millis(100)),
+            (100, Duration::from_millis(100)),
+            (250, Duration::from_millis(250)),
+        ]
+    );
+    assert!(runtime.run_until_stalled());
+    assert!(!runtime.step());
+}
+
+#[test]
+fn test_simulation_yielding() {
+    let mut runtime = SimulationRuntime::new();
+    let handle = runtime.handle();
+    let (send, mut recv) = oneshot::channel();
+
+    runtime.handle().spawn(async move {
+        // Polling the same sleep repeatedly registers a single timer.
+        let mut sleep = handle.sleep(Duration::from_millis(100));
+        for _ in 0..10 {
+            let mut yielded = false;
+            future::poll_fn(|cx| {
+                assert!(Pin::new(&mut sleep).poll(cx).is_pending());
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+        drop(sleep);
+        let _ = send.send(handle.now());
+    });
+
+    // A task which yields many times still finishes within a single call, without the clock
+    // advancing.
+    assert!(runtime.run_until_stalled());
+    assert_eq!(recv.try_recv().unwrap(), Some(Duration::ZERO));
+
+    // The dropped sleep leaves no timer behind.
+    assert_eq!(runtime.next_timer(), None);
+    assert!(!runtime.step());
+}
+
+// Transfer data over a lossy link, and return the virtual time at which the transfer completed.
+fn lossy_transfer(seed: u64) -> Duration {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    const CONDITION: LinkCondition = LinkCondition {
+        loss: 0.3,
+        duplicate: 0.1,
+        delay: Duration::from_millis(30),
+        jitter: Duration::from_millis(20),
+    };
+
+    const LEN: usize = 20_000;
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimulationRuntime::new();
+    let mut rng = SmallRng::seed_from_u64(seed);
+
+    let (asend, acondrecv) = mpsc::channel(2);
+    let (acondsend, arecv) = mpsc::channel(2);
+    condition_link(
+        CONDITION,
+        runtime.handle(),
+        packet_pool,
+        SmallRng::from_rng(&mut rng).unwrap(),
+        acondrecv,
+        acondsend,
+    );
+
+    let (bsend, bcondrecv) = mpsc::channel(2);
+    let (bcondsend, brecv) = mpsc::channel(2);
+    condition_link(
+        CONDITION,
+        runtime.handle(),
+        packet_pool,
+        SmallRng::from_rng(&mut rng).unwrap(),
+        bcondrecv,
+        bcondsend,
+    );
+
+    let mut stream1 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    runtime.handle().spawn(async move {
+        let data = [17; LEN];
+        let mut written = 0;
+        while written < LEN {
+            written += stream1.write(&data[written..]).await.unwrap();
+            stream1.flush().await.unwrap();
+        }
+        // Keep the channel alive so that resends continue.
+        futures::future::pending::<()>().await;
+        drop(stream1);
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.handle().spawn({
+        let handle = runtime.handle();
+        async move {
+            let mut buffer = [0; LEN];
+            let mut read = 0;
+            while read < LEN {
+                read += stream2.read(&mut buffer[read..]).await.unwrap();
+            }
+            assert!(buffer.iter().all(|&b| b == 17));
+            let _ = done_send.send(handle.now());
+        }
+    });
+
+    while runtime.step() {
+        if let Some(time) = done.try_recv().unwrap() {
+            return time;
+        }
+    }
+
+    panic!("transfer stalled");
+}
+
+#[test]
+fn test_simulation_deterministic() {
+    let time = lossy_transfer(7);
+    assert!(time > Duration::from_secs(0));
+    assert_eq!(lossy_transfer(7), time);
+}
+
+#[test]
+fn test_simulation_interval() {
+    let mut runtime = SimulationRuntime::new();
+    let handle = runtime.handle();
+    let ticks = Arc::new(Mutex::new(Vec::new()));
+
+    let (interval, cancel) = cancellable(handle.interval(Duration::from_millis(100)));
+    runtime.handle().spawn({
+        let handle = handle.clone();
+        let ticks = Arc::clone(&ticks);
+        async move {
+            let mut interval = interval;
+            while interval.next().await.is_some() {
+                ticks.lock().unwrap().push(handle.now());
+            }
+            ticks.lock().unwrap().push(Duration::MAX);
+        }
+    });
+
+    runtime.run_for(Duration::from_millis(250));
+    assert_eq!(
+        *ticks.lock().unwrap(),
+        vec![Duration::from_millis(100), Duration::from_millis(200)]
+    );
+
+    // Falling behind skips missed ticks rather than delivering them in a burst, and ticks stay
+    // aligned to the original schedule.
+    runtime.advance_time(Duration::from_millis(400));
+    runtime.run_until_stalled();
+    runtime.run_for(Duration::from_millis(100));
+    assert_eq!(
+        *ticks.lock().unwrap(),
+        vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(650),
+            Duration::from_millis(700),
+        ]
+    );
+
+    // Cancelling ends the interval promptly, without waiting for the next tick.
+    cancel.cancel();
+    runtime.run_until_stalled();
+    assert_eq!(ticks.lock().unwrap().last(), Some(&Duration::MAX));
+
+    let (sleep, cancel) = cancellable(handle.sleep(Duration::from_secs(10)));
+    let (send, mut recv) = oneshot::channel();
+    runtime.handle().spawn(async move {
+        let _ = send.send(sleep.await);
+    });
+    runtime.run_for(Duration::from_secs(1));
+    assert!(recv.try_recv().unwrap().is_none());
+    cancel.cancel();
+    runtime.run_until_stalled();
+    assert!(recv.try_recv().unwrap().unwrap().is_err());
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use futures::{channel::mpsc, future, stream};
+
+use turbulence::{
+    buffer::{BufferPacket, BufferPacketPool},
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{DropReason, PacketMultiplexer},
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::Runtime,
+    telemetry::{EventKind, EventLog},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[test]
+fn test_event_log_drops() {
+    let mut runtime = SimpleRuntime::new();
+    let raw_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let log = EventLog::new(runtime.handle(), 2);
+
+    let mut multiplexer = PacketMultiplexer::<BufferPacket<Box<[u8]>>>::new();
+    multiplexer.set_observer(log.clone());
+    let (mut incoming, _outgoing) = multiplexer.start();
+
+    for channel in 1..=3 {
+        runtime.advance_time(100);
+        let mut packet = raw_pool.acquire();
+        packet.extend(&[channel, 0]);
+        assert!(incoming.try_send(packet).is_err());
+    }
+
+    // Only the most recent events are kept.
+    let events = log.events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].time, Duration::from_millis(200));
+    assert_eq!(events[0].channel, Some(2));
+    assert_eq!(
+        events[1].kind,
+        EventKind::Dropped {
+            len: 1,
+            reason: DropReason::UnknownChannel
+        }
+    );
+
+    runtime.advance_time(100);
+    log.channel(9).record(EventKind::DecodeError);
+    let recent = log.recent(Duration::from_millis(150));
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[1].channel, Some(9));
+    assert_eq!(recent[1].kind, EventKind::DecodeError);
+
+    log.clear();
+    assert!(log.events().is_empty());
+}
+
+#[test]
+fn test_event_log_retransmits() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let log = EventLog::new(runtime.handle(), 16);
+
+    // Every packet sent is lost.
+    let (outgoing, mut lost) = mpsc::channel(16);
+    let mut channel = ReliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        stream::pending(),
+        outgoing,
+    );
+    channel.set_event_log(log.channel(4));
+
+    runtime.spawn(async move {
+        channel.write(&[1; 100]).await.unwrap();
+        channel.flush().await.unwrap();
+        future::pending::<()>().await;
+    });
+
+    for _ in 0..20 {
+        runtime.run_until_stalled();
+        while lost.try_recv().is_ok() {}
+        runtime.advance_time(50);
+    }
+
+    let events = log.events();
+    assert!(!events.is_empty());
+    for event in events {
+        assert_eq!(event.channel, Some(4));
+        assert_eq!(
+            event.kind,
+            EventKind::Retransmit {
+                len: 100,
+                timeout: Duration::from_millis(150),
+            }
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::channel::mpsc;
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    clock_sync::{self, ClockSync},
+    runtime::{Runtime, SimulationRuntime, Timer},
+    simulation::{self, LinkSimulator},
+    timestamp_channel::TimestampChannel,
+    unreliable_channel::{Settings, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 4096,
+    burst_bandwidth: 4096,
+};
+
+const LATENCY: Duration = Duration::from_millis(50);
+
+#[test]
+fn test_timestamp_channel() {
+    let pool = BufferPacketPool::new(SimpleBufferPool(256));
+    let mut runtime = SimulationRuntime::new();
+    let link = simulation::Settings {
+        latency: LATENCY,
+        ..simulation::Settings::PERFECT
+    };
+
+    // Connect a pair of channels through simulated links, each seeded differently.
+    let mut seed = 0;
+    let mut connect = |runtime: &SimulationRuntime| {
+        let (asend, alinkrecv) = mpsc::channel(8);
+        let (alinksend, arecv) = mpsc::channel(8);
+        let (bsend, blinkrecv) = mpsc::channel(8);
+        let (blinksend, brecv) = mpsc::channel(8);
+        for (recv, send) in [(alinkrecv, alinksend), (blinkrecv, blinksend)] {
+            seed += 1;
+            runtime.handle().spawn(
+                LinkSimulator::with_seed(runtime.handle(), pool, link, seed).run(recv, send),
+            );
+        }
+        ((arecv, bsend), (brecv, asend))
+    };
+
+    let ((a_sync_recv, a_sync_send), (b_sync_recv, b_sync_send)) = connect(&runtime);
+    let ((a_recv, a_send), (b_recv, b_send)) = connect(&runtime);
+
+    let sync_a = ClockSync::new(runtime.handle(), clock_sync::Settings::default());
+    let clock_a = sync_a.remote_clock();
+    let channel =
+        UnreliableChannel::new(runtime.handle(), pool, SETTINGS, a_sync_recv, a_sync_send);
+    runtime.handle().spawn(async move {
+        let _ = sync_a.run(channel).await;
+    });
+
+    // B's timeline starts 5 seconds after A's.
+    runtime.run_for(Duration::from_secs(5));
+    let sync_b = ClockSync::new(runtime.handle(), clock_sync::Settings::default());
+    let clock_b = sync_b.remote_clock();
+    let channel =
+        UnreliableChannel::new(runtime.handle(), pool, SETTINGS, b_sync_recv, b_sync_send);
+    runtime.handle().spawn(async move {
+        let _ = sync_b.run(channel).await;
+    });
+    runtime.run_for(Duration::from_secs(5));
+
+    let mut sender = TimestampChannel::new(
+        clock_a.clone(),
+        runtime.handle(),
+        pool,
+        SETTINGS,
+        a_recv,
+        a_send,
+    );
+    let mut receiver = TimestampChannel::new(
+        clock_b.clone(),
+        runtime.handle(),
+        pool,
+        SETTINGS,
+        b_recv,
+        b_send,
+    );
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    runtime.handle().spawn({
+        let sent = Arc::clone(&sent);
+        let handle = runtime.handle();
+        async move {
+            for i in 0..3u8 {
+                if i > 0 {
+                    handle.sleep(Duration::from_millis(20)).await;
+                }
+                sender.send(&[i]).await.unwrap();
+                sent.lock().unwrap().push(clock_a.local_time());
+            }
+            sender.flush().await.unwrap();
+        }
+    });
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    runtime.handle().spawn({
+        let received = Arc::clone(&received);
+        async move {
+            // Runs until the sender is done and dropped.
+            while let Ok((msg, timestamp)) = receiver.recv().await {
+                received
+                    .lock()
+                    .unwrap()
+                    .push((msg[0], timestamp, clock_b.local_time()));
+            }
+        }
+    });
+    runtime.run_for(Duration::from_secs(1));
+
+    let sent = sent.lock().unwrap();
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    for (i, &(msg, timestamp, received_at)) in received.iter().enumerate() {
+        assert_eq!(msg, i as u8);
+        // All three messages are in one packet, so they arrive together.
+        let millis = |d: Duration| d.as_millis() as i64;
+        assert!((millis(timestamp.remote) - millis(sent[i])).abs() <= 1);
+        let local = timestamp.local.unwrap();
+        if i == 2 {
+            assert!((millis(received_at - local) - millis(LATENCY)).abs() <= 2);
+        }
+        assert!((millis(local) - (millis(sent[i]) - 5000)).abs() <= 2);
+    }
+}
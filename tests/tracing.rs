@@ -0,0 +1,205 @@
+#![cfg(feature = "tracing")]
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{channel::mpsc, SinkExt};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::Runtime,
+    unreliable_channel::{self, RecvError, UnreliableChannel},
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+#[derive(Default)]
+struct Recorded {
+    spans: Vec<&'static Metadata<'static>>,
+    entered: Vec<u64>,
+    // Every event message, along with the name of the innermost entered span.
+    events: Vec<(String, Option<&'static str>)>,
+}
+
+#[derive(Clone, Default)]
+struct RecordingSubscriber(Arc<Mutex<Recorded>>);
+
+impl RecordingSubscriber {
+    fn events(&self) -> Vec<(String, Option<&'static str>)> {
+        self.0.lock().unwrap().events.clone()
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+impl Subscriber for RecordingSubscriber {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        let mut recorded = self.0.lock().unwrap();
+        recorded.spans.push(span.metadata());
+        span::Id::from_u64(recorded.spans.len() as u64)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        let mut recorded = self.0.lock().unwrap();
+        let span = recorded
+            .entered
+            .last()
+            .map(|&id| recorded.spans[id as usize - 1].name());
+        recorded.events.push((visitor.0, span));
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.0.lock().unwrap().entered.push(span.into_u64());
+    }
+
+    fn current_span(&self) -> tracing_core::span::Current {
+        let recorded = self.0.lock().unwrap();
+        match recorded.entered.last() {
+            Some(&id) => tracing_core::span::Current::new(
+                span::Id::from_u64(id),
+                recorded.spans[id as usize - 1],
+            ),
+            None => tracing_core::span::Current::none(),
+        }
+    }
+
+    fn exit(&self, span: &span::Id) {
+        let mut recorded = self.0.lock().unwrap();
+        if let Some(i) = recorded
+            .entered
+            .iter()
+            .rposition(|&id| id == span.into_u64())
+        {
+            recorded.entered.remove(i);
+        }
+    }
+}
+
+#[test]
+fn test_reliable_retransmit_events() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 32768,
+        burst_bandwidth: 4096,
+        recv_window_size: 16384,
+        send_window_size: 16384,
+        init_send: 512,
+        resend_time: Duration::from_millis(50),
+        initial_rtt: Duration::from_millis(100),
+        max_rtt: Duration::from_millis(2000),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+
+    let subscriber = RecordingSubscriber::default();
+    let _default = tracing::subscriber::set_default(subscriber.clone());
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    // Nothing is ever acknowledged, so everything sent is retransmitted.
+    let (_incoming_send, incoming) = mpsc::channel(2);
+    let (outgoing, mut outgoing_recv) = mpsc::channel(2);
+    let mut channel = {
+        let _entered = tracing::debug_span!("test_channel").entered();
+        ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, incoming, outgoing)
+    };
+
+    runtime.spawn(async move {
+        channel.write(&[1, 2, 3, 4]).await.unwrap();
+        channel.flush().await.unwrap();
+        futures::future::pending::<()>().await;
+    });
+
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        while outgoing_recv.try_recv().is_ok() {}
+        runtime.advance_time(100);
+    }
+
+    let events = subscriber.events();
+    assert!(events.contains(&("sending reliable data".to_owned(), Some("test_channel"))));
+    let retransmits = events
+        .iter()
+        .filter(|(msg, _)| msg == "retransmitting reliable data")
+        .collect::<Vec<_>>();
+    assert!(retransmits.len() >= 2);
+    assert!(retransmits
+        .iter()
+        .all(|(_, span)| *span == Some("test_channel")));
+}
+
+#[test]
+fn test_unreliable_drop_events() {
+    let subscriber = RecordingSubscriber::default();
+    let _default = tracing::subscriber::set_default(subscriber.clone());
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+
+    let (mut incoming_send, incoming) = mpsc::channel(2);
+    let (outgoing, _outgoing_recv) = mpsc::channel(2);
+    let mut channel = UnreliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        unreliable_channel::Settings {
+            bandwidth: 32768,
+            burst_bandwidth: 4096,
+        },
+        incoming,
+        outgoing,
+    );
+
+    let (done_send, mut done) = futures::channel::oneshot::channel();
+    runtime.spawn(async move {
+        // A message length which is longer than the packet.
+        let mut packet = packet_pool.acquire();
+        packet.extend(&[10, 0, 1]);
+        incoming_send.send(packet).await.unwrap();
+        assert!(matches!(channel.recv().await, Err(RecvError::BadFormat)));
+
+        let message = vec![0; 2000];
+        assert!(channel.send(&message).await.is_err());
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..10 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            break;
+        }
+        runtime.advance_time(100);
+    }
+
+    let events = subscriber.events();
+    assert!(events.contains(&("dropping malformed unreliable packet".to_owned(), None)));
+    assert!(events.contains(&("unreliable message too big".to_owned(), None)));
+}
@@ -0,0 +1,285 @@
+#![cfg(feature = "udp")]
+
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    thread,
+    time::{Duration, Instant},
+};
+
+use futures::{SinkExt, StreamExt};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    runtime::{Runtime, Timer},
+    udp_transport::{self, UdpTransport},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// Runs every spawned task on its own thread, the UDP transport requires a runtime that actually
+// waits on IO, which `SimpleRuntime` does not.
+#[derive(Copy, Clone)]
+struct ThreadRuntime;
+
+impl Timer for ThreadRuntime {
+    type Instant = Instant;
+    type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    fn now(&self) -> Self::Instant {
+        Instant::now()
+    }
+
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        instant.elapsed()
+    }
+
+    fn duration_between(&self, earlier: Self::Instant, later: Self::Instant) -> Duration {
+        later.duration_since(earlier)
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        })
+    }
+}
+
+impl Runtime for ThreadRuntime {
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        thread::spawn(move || async_io::block_on(future));
+    }
+}
+
+const SETTINGS: udp_transport::Settings = udp_transport::Settings {
+    peer_buffer_size: 8,
+    accept_buffer_size: 8,
+    max_peers: 4,
+    max_datagram_size: None,
+};
+
+fn localhost() -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, 0).into()
+}
+
+#[test]
+fn test_udp_transport() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+
+    let (server, mut acceptor) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+    let (mut client, _) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+
+    async_io::block_on(async move {
+        let mut client_peer = client.connect(server.local_addr()).unwrap();
+        assert!(client.connect(server.local_addr()).is_err());
+
+        let mut packet = packet_pool.acquire();
+        packet.extend(&[1, 2, 3, 4]);
+        client_peer.outgoing.send(packet).await.unwrap();
+
+        let mut server_peer = acceptor.next().await.unwrap();
+        assert_eq!(server_peer.addr, client.local_addr());
+        let packet = server_peer.incoming.next().await.unwrap();
+        assert_eq!(&packet[..], &[1, 2, 3, 4]);
+
+        let mut packet = packet_pool.acquire();
+        packet.extend(&[5, 6, 7]);
+        server_peer.outgoing.send(packet).await.unwrap();
+
+        let packet = client_peer.incoming.next().await.unwrap();
+        assert_eq!(&packet[..], &[5, 6, 7]);
+    });
+}
+
+#[test]
+fn test_udp_reserved_datagrams() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+
+    let (server, mut acceptor) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+    let (mut client, _) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+
+    let path_datagram = |kind: u8| {
+        let mut packet = packet_pool.acquire();
+        packet.extend(&udp_transport::PATH_PREFIX);
+        packet.extend(&[kind]);
+        packet.resize(udp_transport::PATH_DATAGRAM_LEN, 0);
+        packet
+    };
+
+    async_io::block_on(async move {
+        let mut client_peer = client.connect(server.local_addr()).unwrap();
+
+        // Only path challenges and responses are reserved, other kinds are ordinary packets.
+        client_peer.outgoing.send(path_datagram(2)).await.unwrap();
+        let mut server_peer = acceptor.next().await.unwrap();
+        let packet = server_peer.incoming.next().await.unwrap();
+        assert_eq!(&packet[..], &path_datagram(2)[..]);
+
+        client_peer.outgoing.send(path_datagram(0)).await.unwrap();
+        let mut packet = packet_pool.acquire();
+        packet.extend(&[1]);
+        client_peer.outgoing.send(packet).await.unwrap();
+        let packet = server_peer.incoming.next().await.unwrap();
+        assert_eq!(&packet[..], &[1]);
+    });
+}
+
+#[test]
+fn test_udp_multiplexer() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mux_packet_pool = MuxPacketPool::new(packet_pool);
+
+    let (server, mut acceptor) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+    let client_settings = udp_transport::Settings {
+        max_datagram_size: Some(48),
+        ..SETTINGS
+    };
+    let (mut client, _) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, client_settings).unwrap();
+
+    let mut client_multiplexer = PacketMultiplexer::new();
+    let client_limit = client_multiplexer.datagram_limit();
+    let (mut client_sender, mut client_receiver, _) =
+        client_multiplexer.open_channel(3, 8).unwrap();
+    let (client_incoming, client_outgoing) = client_multiplexer.start();
+    let client_peer = client.connect(server.local_addr()).unwrap();
+    ThreadRuntime.spawn(client_peer.run_multiplexer(client_incoming, client_outgoing));
+
+    async_io::block_on(async move {
+        let mut packet = mux_packet_pool.acquire();
+        packet.extend(&[9, 8, 7]);
+        client_sender.send(packet).await.unwrap();
+
+        let server_peer = acceptor.next().await.unwrap();
+        let mut server_multiplexer = PacketMultiplexer::new();
+        let (mut server_sender, mut server_receiver, _) =
+            server_multiplexer.open_channel(3, 8).unwrap();
+        let (server_incoming, server_outgoing) = server_multiplexer.start();
+        ThreadRuntime.spawn(server_peer.run_multiplexer(server_incoming, server_outgoing));
+
+        // The first packet arrived before the server multiplexer was started, but it should still
+        // be buffered in the peer.
+        let packet = server_receiver.next().await.unwrap();
+        assert_eq!(&packet[..], &[9, 8, 7]);
+
+        let mut packet = mux_packet_pool.acquire();
+        packet.extend(&[6, 5]);
+        server_sender.send(packet).await.unwrap();
+
+        let packet = client_receiver.next().await.unwrap();
+        assert_eq!(&packet[..], &[6, 5]);
+    });
+
+    // The configured maximum datagram size is applied to the client's multiplexer.
+    assert_eq!(client_limit.max_datagram_size(), Some(48));
+}
+
+#[test]
+fn test_udp_accept_filter() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+
+    let (server, mut acceptor) = UdpTransport::bind_with_filter(
+        &ThreadRuntime,
+        localhost(),
+        packet_pool,
+        SETTINGS,
+        |_, packet| packet.first() == Some(&7),
+    )
+    .unwrap();
+    let (mut client, _) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+
+    async_io::block_on(async move {
+        let mut client_peer = client.connect(server.local_addr()).unwrap();
+
+        // Datagrams rejected by the filter do not create a peer, so the server peer's first
+        // packet is the first accepted datagram.
+        for data in [&[1, 2][..], &[7, 3], &[4]] {
+            let mut packet = packet_pool.acquire();
+            packet.extend(data);
+            client_peer.outgoing.send(packet).await.unwrap();
+        }
+
+        let mut server_peer = acceptor.next().await.unwrap();
+        assert_eq!(&server_peer.incoming.next().await.unwrap()[..], &[7, 3]);
+        // Once accepted, packets are no longer filtered.
+        assert_eq!(&server_peer.incoming.next().await.unwrap()[..], &[4]);
+    });
+}
+
+#[test]
+fn test_udp_migration() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+
+    let (mut server, mut acceptor) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+    let (mut client, _) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+    // The same client after moving to a new address.
+    let (mut moved_client, _) =
+        UdpTransport::bind(&ThreadRuntime, localhost(), packet_pool, SETTINGS).unwrap();
+
+    // Datagrams that start with 42 identify themselves as the first client's.
+    let client_addr = client.local_addr();
+    server.set_migration_filter(move |_, packet| {
+        if packet.first() == Some(&42) {
+            Some(client_addr)
+        } else {
+            None
+        }
+    });
+
+    async_io::block_on(async move {
+        let send = |peer_outgoing: &mut futures::channel::mpsc::Sender<_>, data: &[u8]| {
+            let mut packet = packet_pool.acquire();
+            packet.extend(data);
+            peer_outgoing.try_send(packet).unwrap();
+        };
+
+        let mut client_peer = client.connect(server.local_addr()).unwrap();
+        send(&mut client_peer.outgoing, &[1]);
+        let mut server_peer = acceptor.next().await.unwrap();
+        assert_eq!(&server_peer.incoming.next().await.unwrap()[..], &[1]);
+        let path = server_peer.path();
+        assert_eq!(path.addr(), client_addr);
+
+        // Datagrams from the new address are not delivered until it answers the path challenge.
+        let mut moved_peer = moved_client.connect(server.local_addr()).unwrap();
+        let start = Instant::now();
+        while path.addr() != moved_client.local_addr() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "peer did not migrate"
+            );
+            send(&mut moved_peer.outgoing, &[42, 2]);
+            async_io::Timer::after(Duration::from_millis(10)).await;
+        }
+
+        // The peer keeps its streams, which now use the new address.  Datagrams sent while the
+        // challenge was in flight may still arrive after the migration.
+        send(&mut moved_peer.outgoing, &[3]);
+        let packet = loop {
+            let packet = server_peer.incoming.next().await.unwrap();
+            if packet[0] != 42 {
+                break packet;
+            }
+        };
+        assert_eq!(&packet[..], &[3]);
+        send(&mut server_peer.outgoing, &[4]);
+        assert_eq!(&moved_peer.incoming.next().await.unwrap()[..], &[4]);
+        assert_eq!(server_peer.addr, client_addr);
+    });
+}
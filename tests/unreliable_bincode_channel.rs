@@ -1,9 +1,14 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use futures::channel::{mpsc, oneshot};
 use serde::{Deserialize, Serialize};
 
 use turbulence::{
     buffer::BufferPacketPool,
-    runtime::Runtime,
+    runtime::{Runtime, Timer},
     unreliable_bincode_channel::{UnreliableBincodeChannel, UnreliableTypedChannel},
     unreliable_channel::{Settings, UnreliableChannel},
 };
@@ -26,23 +31,11 @@ fn test_unreliable_bincode_channel() {
     let (bsend, brecv) = mpsc::channel(8);
 
     let mut stream1 = UnreliableTypedChannel::new(UnreliableBincodeChannel::new(
-        UnreliableChannel::new(
-            runtime.handle(),
-            packet_pool.clone(),
-            SETTINGS,
-            arecv,
-            bsend,
-        ),
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
         512,
     ));
     let mut stream2 = UnreliableTypedChannel::new(UnreliableBincodeChannel::new(
-        UnreliableChannel::new(
-            runtime.handle(),
-            packet_pool.clone(),
-            SETTINGS,
-            brecv,
-            asend,
-        ),
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
         512,
     ));
 
@@ -154,3 +147,68 @@ fn test_unreliable_bincode_channel() {
 
     panic!("didn't finish in time");
 }
+
+#[test]
+fn test_unreliable_recv_timed() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 4096,
+        burst_bandwidth: 1024,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+    let start = runtime.handle().now();
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+
+    let mut stream1 = UnreliableTypedChannel::<u32, _, _>::new(UnreliableBincodeChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend),
+        512,
+    ));
+    let mut stream2 = UnreliableTypedChannel::<u32, _, _>::new(UnreliableBincodeChannel::new(
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend),
+        512,
+    ));
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    runtime.spawn({
+        let handle = runtime.handle();
+        let received = Arc::clone(&received);
+        async move {
+            loop {
+                let (msg, time) = stream2.recv_timed().await.unwrap();
+                received
+                    .lock()
+                    .unwrap()
+                    .push((msg, handle.duration_between(start, time)));
+            }
+        }
+    });
+    runtime.run_until_stalled();
+
+    // Both messages sent at once are coalesced into one packet and share a receive time.
+    runtime.advance_time(20);
+    futures::executor::block_on(async {
+        stream1.send(&1).await.unwrap();
+        stream1.send(&2).await.unwrap();
+        stream1.flush().await.unwrap();
+    });
+    runtime.run_until_stalled();
+
+    runtime.advance_time(30);
+    futures::executor::block_on(async {
+        stream1.send(&3).await.unwrap();
+        stream1.flush().await.unwrap();
+    });
+    runtime.run_until_stalled();
+
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![
+            (1, Duration::from_millis(20)),
+            (2, Duration::from_millis(20)),
+            (3, Duration::from_millis(50)),
+        ]
+    );
+}
@@ -1,9 +1,19 @@
-use futures::channel::{mpsc, oneshot};
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    task::noop_waker_ref,
+    FutureExt,
+};
 
 use turbulence::{
-    buffer::BufferPacketPool,
+    buffer::{BufferPacket, BufferPacketPool},
     runtime::Runtime,
-    unreliable_channel::{Settings, UnreliableChannel},
+    tick_budget::TickBudget,
+    unreliable_channel::{OverflowPolicy, RateLimit, Settings, ShedPolicy, UnreliableChannel},
 };
 
 mod util;
@@ -23,20 +33,8 @@ fn test_unreliable_channel() {
     let (asend, arecv) = mpsc::channel(8);
     let (bsend, brecv) = mpsc::channel(8);
 
-    let mut stream1 = UnreliableChannel::new(
-        runtime.handle(),
-        packet_pool.clone(),
-        SETTINGS,
-        arecv,
-        bsend,
-    );
-    let mut stream2 = UnreliableChannel::new(
-        runtime.handle(),
-        packet_pool.clone(),
-        SETTINGS,
-        brecv,
-        asend,
-    );
+    let mut stream1 = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
 
     async fn send(
         stream: &mut UnreliableChannel<SimpleRuntimeHandle, BufferPacketPool<SimpleBufferPool>>,
@@ -114,3 +112,319 @@ fn test_unreliable_channel() {
 
     panic!("didn't finish in time");
 }
+
+#[test]
+fn test_unreliable_channel_generic_transport() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 512,
+        burst_bandwidth: 256,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+
+    // Any `Stream` and `Sink` of packets can be used directly, not just bounded mpsc channels.
+    let (asend, arecv) = mpsc::unbounded();
+    let (bsend, brecv) = mpsc::unbounded();
+
+    let mut stream1 = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        stream1.send(&[1, 2, 3]).await.unwrap();
+        stream1.send(&[4, 5]).await.unwrap();
+        stream1.flush().await.unwrap();
+        assert_eq!(stream2.recv().await.unwrap(), &[1, 2, 3]);
+        assert_eq!(stream2.recv().await.unwrap(), &[4, 5]);
+
+        stream2.send(&[6; 700]).await.unwrap();
+        stream2.flush().await.unwrap();
+        assert_eq!(stream1.recv().await.unwrap(), &[6; 700][..]);
+
+        let _ = done_send.send(());
+    });
+
+    for _ in 0..100_000 {
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+
+        runtime.run_until_stalled();
+        runtime.advance_time(50);
+    }
+
+    panic!("didn't finish in time");
+}
+
+#[test]
+fn test_unreliable_shedding() {
+    // Two 10 byte messages fit in a packet, and the burst allows sending a little over two
+    // packets before the channel is saturated.
+    const SETTINGS: Settings = Settings {
+        bandwidth: 1,
+        burst_bandwidth: 64,
+    };
+
+    fn messages(packets: &mut mpsc::Receiver<BufferPacket<Box<[u8]>>>) -> Vec<u8> {
+        let mut messages = Vec::new();
+        while let Ok(packet) = packets.try_recv() {
+            messages.extend(packet.chunks(12).map(|m| m[2]));
+        }
+        messages
+    }
+
+    for (policy, expected) in [
+        (ShedPolicy::DropNewest, vec![0, 1, 2, 3, 4, 5, 6, 7]),
+        (ShedPolicy::DropStale, vec![0, 1, 2, 3, 4, 5, 8, 9]),
+    ] {
+        let mut runtime = SimpleRuntime::new();
+        let packet_pool = BufferPacketPool::new(SimpleBufferPool(32));
+        let (_incoming, incoming_recv) = mpsc::channel(8);
+        let (outgoing_send, mut outgoing) = mpsc::channel(8);
+        let mut channel = UnreliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS,
+            incoming_recv,
+            outgoing_send,
+        );
+        channel.set_shed_policy(policy);
+
+        for i in 0..10 {
+            // Saturation never makes a shedding channel wait.
+            channel.send(&[i; 10]).now_or_never().unwrap().unwrap();
+        }
+        assert!(channel.is_saturated());
+        assert_eq!(channel.shed(), 2);
+
+        // The last packet is sent once bandwidth is available again.
+        runtime.advance_time(8000);
+        channel.flush().now_or_never().unwrap().unwrap();
+        assert_eq!(messages(&mut outgoing), expected);
+    }
+
+    // Without shedding, sending waits for bandwidth.
+    let runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let (_incoming, incoming_recv) = mpsc::channel(8);
+    let (outgoing_send, _outgoing) = mpsc::channel(8);
+    let mut channel = UnreliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        incoming_recv,
+        outgoing_send,
+    );
+    for i in 0..8 {
+        channel.send(&[i; 10]).now_or_never().unwrap().unwrap();
+    }
+    assert!(channel.send(&[8; 10]).now_or_never().is_none());
+}
+
+#[test]
+fn test_unreliable_overflow() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 65536,
+        burst_bandwidth: 65536,
+    };
+
+    fn messages(packets: &mut mpsc::Receiver<BufferPacket<Box<[u8]>>>) -> Vec<u8> {
+        let mut messages = Vec::new();
+        while let Ok(packet) = packets.try_recv() {
+            messages.extend(packet.chunks(12).map(|m| m[2]));
+        }
+        messages
+    }
+
+    // A zero sized mpsc channel has room for a single packet.
+    for (policy, overflowed, sent, held) in [
+        (OverflowPolicy::DropNewest, 3, vec![4], vec![]),
+        (OverflowPolicy::DropOldest, 2, vec![3], vec![4]),
+    ] {
+        let runtime = SimpleRuntime::new();
+        let packet_pool = BufferPacketPool::new(SimpleBufferPool(32));
+        let (_incoming, incoming_recv) = mpsc::channel(8);
+        let (outgoing_send, mut outgoing) = mpsc::channel(0);
+        let mut channel = UnreliableChannel::new(
+            runtime.handle(),
+            packet_pool,
+            SETTINGS,
+            incoming_recv,
+            outgoing_send,
+        );
+        channel.set_overflow_policy(policy);
+
+        for i in 0..5 {
+            if i == 4 {
+                assert_eq!(messages(&mut outgoing), vec![0]);
+            }
+            channel.send(&[i; 10]).now_or_never().unwrap().unwrap();
+            // A full sink never makes the channel wait.
+            channel.flush().now_or_never().unwrap().unwrap();
+        }
+        assert_eq!(channel.overflowed(), overflowed);
+        assert_eq!(messages(&mut outgoing), sent);
+
+        // A held packet is sent by the next flush once there is room.
+        channel.flush().now_or_never().unwrap().unwrap();
+        assert_eq!(messages(&mut outgoing), held);
+    }
+}
+
+#[test]
+fn test_unreliable_poll() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 100,
+        burst_bandwidth: 100,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1200));
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut sender = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut receiver =
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    assert!(receiver.poll_recv(&mut cx).is_pending());
+    assert!(sender.poll_send(&mut cx, &[1; 200]).is_ready());
+    assert!(sender.poll_flush(&mut cx).is_ready());
+
+    // The first packet used up the bandwidth, so the next flush waits, and keeps waiting for the
+    // same delay however often it is polled.
+    assert!(sender.poll_send(&mut cx, &[2; 10]).is_ready());
+    assert!(sender.poll_flush(&mut cx).is_pending());
+    runtime.advance_time(500);
+    assert!(sender.poll_flush(&mut cx).is_pending());
+    assert!(sender.poll_flush(&mut cx).is_pending());
+    runtime.advance_time(600);
+    assert!(matches!(sender.poll_flush(&mut cx), Poll::Ready(Ok(()))));
+
+    match receiver.poll_recv(&mut cx) {
+        Poll::Ready(Ok(msg)) => assert_eq!(msg, &[1; 200][..]),
+        _ => panic!("no message received"),
+    }
+    match receiver.poll_recv(&mut cx) {
+        Poll::Ready(Ok(msg)) => assert_eq!(msg, &[2; 10][..]),
+        _ => panic!("no message received"),
+    }
+    assert!(receiver.poll_recv(&mut cx).is_pending());
+}
+
+#[test]
+fn test_unreliable_rate_request() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 65536,
+        burst_bandwidth: 65536,
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(32));
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut sender = UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut receiver =
+        UnreliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+    let mut cx = Context::from_waker(noop_waker_ref());
+
+    let rate = RateLimit {
+        bytes_per_sec: Some(1000),
+        messages_per_sec: Some(2),
+    };
+    receiver.request_rate(rate).now_or_never().unwrap().unwrap();
+    assert_eq!(sender.remote_rate(), RateLimit::UNLIMITED);
+
+    // The request is taken in by receiving, and is never seen as a message.
+    assert!(sender.poll_recv(&mut cx).is_pending());
+    assert_eq!(sender.remote_rate(), rate);
+
+    // Messages over the requested rate wait.
+    for i in 0..2 {
+        sender.send(&[i; 4]).now_or_never().unwrap().unwrap();
+    }
+    assert!(sender.poll_send(&mut cx, &[2; 4]).is_pending());
+    runtime.advance_time(500);
+    assert!(sender.poll_send(&mut cx, &[2; 4]).is_ready());
+
+    // Or are shed with a dropping shed policy.
+    sender.set_shed_policy(ShedPolicy::DropNewest);
+    sender.send(&[3; 4]).now_or_never().unwrap().unwrap();
+    assert_eq!(sender.shed(), 1);
+
+    sender.flush().now_or_never().unwrap().unwrap();
+    for i in 0..3 {
+        assert_eq!(
+            receiver.recv().now_or_never().unwrap().unwrap(),
+            &[i; 4][..]
+        );
+    }
+    assert!(receiver.poll_recv(&mut cx).is_pending());
+
+    // Lifting the request restores the sender's own settings.
+    receiver
+        .request_rate(RateLimit::UNLIMITED)
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    assert!(sender.poll_recv(&mut cx).is_pending());
+    assert_eq!(sender.remote_rate(), RateLimit::UNLIMITED);
+    for i in 0..8 {
+        sender.send(&[i; 4]).now_or_never().unwrap().unwrap();
+    }
+    assert_eq!(sender.shed(), 1);
+}
+
+#[test]
+fn test_unreliable_tick_budget() {
+    const SETTINGS: Settings = Settings {
+        bandwidth: 65536,
+        burst_bandwidth: 65536,
+    };
+    // Two 10 byte messages and their length prefixes per tick.
+    const BUDGET: TickBudget = TickBudget {
+        bytes_per_tick: 24,
+        tick: Duration::from_millis(10),
+    };
+
+    let mut runtime = SimpleRuntime::new();
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let (_incoming, incoming_recv) = mpsc::channel(8);
+    let (outgoing_send, mut outgoing) = mpsc::channel(8);
+    let mut channel = UnreliableChannel::new(
+        runtime.handle(),
+        packet_pool,
+        SETTINGS,
+        incoming_recv,
+        outgoing_send,
+    );
+    channel.set_tick_budget(Some(BUDGET));
+
+    channel.send(&[0; 10]).now_or_never().unwrap().unwrap();
+    channel.send(&[1; 10]).now_or_never().unwrap().unwrap();
+    // The third message waits for the next tick.
+    assert!(channel.send(&[2; 10]).now_or_never().is_none());
+    runtime.advance_time(5);
+    assert!(channel.send(&[2; 10]).now_or_never().is_none());
+    runtime.advance_time(5);
+    channel.send(&[2; 10]).now_or_never().unwrap().unwrap();
+
+    // A message larger than the whole budget is sent alone at the start of a tick.
+    assert!(channel.send(&[3; 30]).now_or_never().is_none());
+    runtime.advance_time(10);
+    channel.send(&[3; 30]).now_or_never().unwrap().unwrap();
+    assert!(channel.send(&[4; 1]).now_or_never().is_none());
+
+    // Dropping shed policies drop messages over the budget instead of waiting.
+    channel.set_shed_policy(ShedPolicy::DropNewest);
+    channel.send(&[4; 1]).now_or_never().unwrap().unwrap();
+    assert_eq!(channel.shed(), 1);
+
+    channel.flush().now_or_never().unwrap().unwrap();
+    let mut sent = Vec::new();
+    while let Ok(packet) = outgoing.try_recv() {
+        sent.push(packet.len());
+    }
+    assert_eq!(sent, vec![36, 32]);
+}
@@ -22,7 +22,7 @@ use turbulence::{
     buffer::BufferPool,
     packet::{Packet, PacketPool},
     packet_multiplexer::{MuxPacket, MuxPacketPool},
-    runtime::Runtime,
+    runtime::{Runtime, Timer},
 };
 
 #[derive(Debug, Copy, Clone)]
@@ -145,14 +145,10 @@ async fn do_delay(state: Arc<HandleInner>, duration: Duration) -> u64 {
     .await
 }
 
-impl Runtime for SimpleRuntimeHandle {
+impl Timer for SimpleRuntimeHandle {
     type Instant = u64;
     type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
 
-    fn spawn<F: Future<Output = ()> + Send + 'static>(&self, f: F) {
-        self.0.incoming_tasks.lock().unwrap().push(Box::pin(f))
-    }
-
     fn now(&self) -> Self::Instant {
         self.0.time_state.lock().unwrap().time
     }
@@ -173,6 +169,12 @@ impl Runtime for SimpleRuntimeHandle {
     }
 }
 
+impl Runtime for SimpleRuntimeHandle {
+    fn spawn<F: Future<Output = ()> + Send + 'static>(&self, f: F) {
+        self.0.incoming_tasks.lock().unwrap().push(Box::pin(f))
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct LinkCondition {
     pub loss: f64,
@@ -183,7 +185,7 @@ pub struct LinkCondition {
 
 pub fn condition_link<P>(
     condition: LinkCondition,
-    runtime: impl Runtime + Clone + Send + 'static,
+    runtime: impl Runtime + 'static,
     pool: P,
     mut rng: impl Rng + Send + 'static,
     mut incoming: mpsc::Receiver<P::Packet>,
@@ -195,42 +197,37 @@ pub fn condition_link<P>(
     runtime.spawn({
         let runtime = runtime.clone();
         async move {
-            loop {
-                match incoming.next().await {
-                    Some(packet) => {
-                        if rng.gen::<f64>() > condition.loss {
-                            if rng.gen::<f64>() <= condition.duplicate {
-                                runtime.spawn({
-                                    let runtime = runtime.clone();
-                                    let mut outgoing = outgoing.clone();
-                                    let delay = Duration::from_secs_f64(
-                                        condition.delay.as_secs_f64()
-                                            + rng.gen::<f64>() * condition.jitter.as_secs_f64(),
-                                    );
-                                    let mut dup_packet = pool.acquire();
-                                    dup_packet.extend(&packet[..]);
-                                    async move {
-                                        runtime.sleep(delay).await;
-                                        let _ = outgoing.send(dup_packet).await;
-                                    }
-                                });
+            while let Some(packet) = incoming.next().await {
+                if rng.gen::<f64>() > condition.loss {
+                    if rng.gen::<f64>() <= condition.duplicate {
+                        runtime.spawn({
+                            let runtime = runtime.clone();
+                            let mut outgoing = outgoing.clone();
+                            let delay = Duration::from_secs_f64(
+                                condition.delay.as_secs_f64()
+                                    + rng.gen::<f64>() * condition.jitter.as_secs_f64(),
+                            );
+                            let mut dup_packet = pool.acquire();
+                            dup_packet.extend(&packet[..]);
+                            async move {
+                                runtime.sleep(delay).await;
+                                let _ = outgoing.send(dup_packet).await;
                             }
+                        });
+                    }
 
-                            runtime.spawn({
-                                let runtime = runtime.clone();
-                                let mut outgoing = outgoing.clone();
-                                let delay = Duration::from_secs_f64(
-                                    condition.delay.as_secs_f64()
-                                        + rng.gen::<f64>() * condition.jitter.as_secs_f64(),
-                                );
-                                async move {
-                                    runtime.sleep(delay).await;
-                                    let _ = outgoing.send(packet).await;
-                                }
-                            });
+                    runtime.spawn({
+                        let runtime = runtime.clone();
+                        let mut outgoing = outgoing.clone();
+                        let delay = Duration::from_secs_f64(
+                            condition.delay.as_secs_f64()
+                                + rng.gen::<f64>() * condition.jitter.as_secs_f64(),
+                        );
+                        async move {
+                            runtime.sleep(delay).await;
+                            let _ = outgoing.send(packet).await;
                         }
-                    }
-                    None => break,
+                    });
                 }
             }
         }
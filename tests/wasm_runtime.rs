@@ -0,0 +1,90 @@
+#![cfg(all(feature = "wasm-bindgen", target_arch = "wasm32"))]
+
+use std::{rc::Rc, time::Duration};
+
+use futures::channel::{mpsc, oneshot};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    reliable_channel::{self, ReliableChannel},
+    runtime::{LocalRuntime, Runtime, Timer, WasmRuntime},
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// Runs under node by default, or in a browser with `wasm-bindgen-test-runner` configured for one.
+#[wasm_bindgen_test]
+async fn test_wasm_runtime() {
+    let runtime = WasmRuntime;
+
+    // Spawned tasks must actually run, including tasks which are not `Send`.
+    let (send, recv) = oneshot::channel();
+    runtime.spawn(async move {
+        send.send(17).unwrap();
+    });
+    assert_eq!(recv.await.unwrap(), 17);
+
+    let (send, recv) = oneshot::channel();
+    let local = Rc::new(19);
+    runtime.spawn_local(async move {
+        send.send(*local).unwrap();
+    });
+    assert_eq!(recv.await.unwrap(), 19);
+
+    // Sleeps must wait for about the given duration, allowing for `setTimeout` firing up to a
+    // millisecond early, and the clock must agree with them.
+    let start = runtime.now();
+    runtime.sleep(Duration::from_millis(20)).await;
+    let end = runtime.now();
+    assert!(runtime.elapsed(start) >= Duration::from_millis(19));
+    assert!(runtime.duration_between(start, end) >= Duration::from_millis(19));
+    assert!(runtime.elapsed(start) >= runtime.duration_between(start, end));
+    assert_eq!(runtime.duration_between(end, start), Duration::ZERO);
+    runtime.sleep(Duration::ZERO).await;
+
+    // The runtime must be able to drive a pair of reliable channels.
+    const SETTINGS: reliable_channel::Settings = reliable_channel::Settings {
+        bandwidth: 65536,
+        burst_bandwidth: 4096,
+        recv_window_size: 4096,
+        send_window_size: 4096,
+        init_send: 512,
+        resend_time: Duration::from_millis(20),
+        initial_rtt: Duration::from_millis(10),
+        max_rtt: Duration::from_millis(200),
+        rtt_update_factor: 0.1,
+        rtt_resend_factor: 1.5,
+    };
+    const LEN: usize = 8192;
+
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(512));
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut channel_a = ReliableChannel::new(runtime, packet_pool, SETTINGS, arecv, bsend);
+    let mut channel_b = ReliableChannel::new(runtime, packet_pool, SETTINGS, brecv, asend);
+
+    let (done_send, done_recv) = oneshot::channel();
+    runtime.spawn(async move {
+        let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+        let mut pos = 0;
+        while pos < LEN {
+            pos += channel_a.write(&data[pos..]).await.unwrap();
+            channel_a.flush().await.unwrap();
+        }
+        let _ = done_send.send(channel_a);
+    });
+
+    let mut buffer = [0; 256];
+    let mut pos = 0;
+    while pos < LEN {
+        let len = channel_b.read(&mut buffer).await.unwrap();
+        for (i, &b) in buffer[0..len].iter().enumerate() {
+            assert_eq!(b, (pos + i) as u8);
+        }
+        pos += len;
+    }
+    let _channel_a = done_recv.await.unwrap();
+}
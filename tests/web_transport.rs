@@ -0,0 +1,124 @@
+#![cfg(all(feature = "web-transport", feature = "tokio"))]
+
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use wtransport::{
+    config::IpBindConfig,
+    tls::{
+        client::build_default_tls_config,
+        rustls::{pki_types::CertificateDer, RootCertStore},
+        Certificate, CertificateChain, PrivateKey,
+    },
+    ClientConfig, Endpoint, Identity, ServerConfig,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet::{Packet, PacketPool},
+    packet_multiplexer::{MuxPacketPool, PacketMultiplexer},
+    web_transport::WebTransportPackets,
+};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// A self-signed certificate for "localhost", only for use in tests.
+const CERT: &[u8] = include_bytes!("quic/cert.der");
+const KEY: &[u8] = include_bytes!("quic/key.der");
+
+#[test]
+fn test_web_transport() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let packet_pool = BufferPacketPool::new(SimpleBufferPool(512));
+        let mux_packet_pool = MuxPacketPool::new(packet_pool);
+
+        let identity = Identity::new(
+            CertificateChain::single(Certificate::from_der(CERT.to_vec()).unwrap()),
+            PrivateKey::from_der_pkcs8(KEY.to_vec()),
+        );
+        let server = Endpoint::server(
+            ServerConfig::builder()
+                .with_bind_config(IpBindConfig::LocalV4, 0)
+                .with_identity(identity)
+                .build(),
+        )
+        .unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(CERT)).unwrap();
+        let client = Endpoint::client(
+            ClientConfig::builder()
+                .with_bind_default()
+                .with_custom_tls(build_default_tls_config(Arc::new(roots), None))
+                .build(),
+        )
+        .unwrap();
+
+        let (server_connection, client_connection) = futures::join!(
+            async {
+                let request = server.accept().await.await.unwrap();
+                let connection = request.accept().await.unwrap();
+                let stream = connection.accept_bi().await.unwrap();
+                (connection, stream)
+            },
+            async {
+                let connection = client
+                    .connect(format!("https://localhost:{}", port))
+                    .await
+                    .unwrap();
+                let mut stream = connection.open_bi().await.unwrap().await.unwrap();
+                // Streams are not visible to the peer until something is written on them.
+                stream.0.write_all(&[]).await.unwrap();
+                (connection, stream)
+            },
+        );
+
+        // Channel 1 is sent as datagrams, channel 2 is sent over the stream.
+        let mut server_multiplexer = PacketMultiplexer::new();
+        let (mut server_sender1, mut server_receiver1, _) =
+            server_multiplexer.open_channel(1, 8).unwrap();
+        let (mut server_sender2, mut server_receiver2, _) =
+            server_multiplexer.open_channel(2, 8).unwrap();
+        let (server_incoming, server_outgoing) = server_multiplexer.start();
+        let server_packets = WebTransportPackets::new(server_connection.0, packet_pool)
+            .unwrap()
+            .with_stream(server_connection.1, [2], 8);
+        tokio::spawn(server_packets.run_multiplexer(server_incoming, server_outgoing));
+
+        let mut client_multiplexer = PacketMultiplexer::new();
+        let (mut client_sender1, mut client_receiver1, _) =
+            client_multiplexer.open_channel(1, 8).unwrap();
+        let (mut client_sender2, mut client_receiver2, _) =
+            client_multiplexer.open_channel(2, 8).unwrap();
+        let (client_incoming, client_outgoing) = client_multiplexer.start();
+        let client_packets = WebTransportPackets::new(client_connection.0, packet_pool)
+            .unwrap()
+            .with_stream(client_connection.1, [2], 8);
+        tokio::spawn(client_packets.run_multiplexer(client_incoming, client_outgoing));
+
+        for (sender, receiver, data) in [
+            (&mut client_sender1, &mut server_receiver1, &[1, 2, 3][..]),
+            (&mut client_sender2, &mut server_receiver2, &[4, 5][..]),
+            (&mut server_sender1, &mut client_receiver1, &[6][..]),
+            (
+                &mut server_sender2,
+                &mut client_receiver2,
+                &[7, 8, 9, 10][..],
+            ),
+        ] {
+            let mut packet = mux_packet_pool.acquire();
+            packet.extend(data);
+            sender.send(packet).await.unwrap();
+            let packet = receiver.next().await.unwrap();
+            assert_eq!(&packet[..], data);
+        }
+    });
+}
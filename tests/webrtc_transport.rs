@@ -0,0 +1,123 @@
+#![cfg(all(feature = "web-rtc", target_arch = "wasm32"))]
+
+use futures::channel::oneshot;
+use wasm_bindgen::{closure::Closure, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+use web_sys::{
+    Event, RtcDataChannel, RtcDataChannelInit, RtcDataChannelState, RtcPeerConnection,
+    RtcPeerConnectionIceEvent, RtcSessionDescriptionInit,
+};
+
+use turbulence::{buffer::BufferPacketPool, RtcDataChannelPackets};
+
+mod util;
+
+use self::util::SimpleBufferPool;
+
+// `RTCPeerConnection` is only available in browsers.
+wasm_bindgen_test_configure!(run_in_browser);
+
+// A data channel negotiated out of band with the same id on both peers, unordered and without
+// retransmits as `RtcDataChannelPackets` expects.
+fn data_channel(peer: &RtcPeerConnection) -> RtcDataChannel {
+    let init = RtcDataChannelInit::new();
+    init.set_negotiated(true);
+    init.set_id(0);
+    init.set_ordered(false);
+    init.set_max_retransmits(0);
+    peer.create_data_channel_with_data_channel_dict("turbulence", &init)
+}
+
+// Forward every ICE candidate gathered by one peer to the other, the returned handler must be kept
+// alive until the peers are connected.
+fn forward_candidates(
+    from: &RtcPeerConnection,
+    to: &RtcPeerConnection,
+) -> Closure<dyn FnMut(RtcPeerConnectionIceEvent)> {
+    let to = to.clone();
+    let on_ice_candidate = Closure::<dyn FnMut(RtcPeerConnectionIceEvent)>::new(
+        move |event: RtcPeerConnectionIceEvent| {
+            if let Some(candidate) = event.candidate() {
+                let _ = to.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&candidate));
+            }
+        },
+    );
+    from.set_onicecandidate(Some(on_ice_candidate.as_ref().unchecked_ref()));
+    on_ice_candidate
+}
+
+async fn connect(offerer: &RtcPeerConnection, answerer: &RtcPeerConnection) {
+    let offer: RtcSessionDescriptionInit = JsFuture::from(offerer.create_offer())
+        .await
+        .unwrap()
+        .unchecked_into();
+    JsFuture::from(offerer.set_local_description(&offer))
+        .await
+        .unwrap();
+    JsFuture::from(answerer.set_remote_description(&offer))
+        .await
+        .unwrap();
+
+    let answer: RtcSessionDescriptionInit = JsFuture::from(answerer.create_answer())
+        .await
+        .unwrap()
+        .unchecked_into();
+    JsFuture::from(answerer.set_local_description(&answer))
+        .await
+        .unwrap();
+    JsFuture::from(offerer.set_remote_description(&answer))
+        .await
+        .unwrap();
+}
+
+async fn opened(channel: &RtcDataChannel) {
+    if channel.ready_state() == RtcDataChannelState::Open {
+        return;
+    }
+    let (send, recv) = oneshot::channel();
+    let on_open: Closure<dyn FnMut(Event)> = Closure::once(move |_: Event| {
+        let _ = send.send(());
+    });
+    channel.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    recv.await.unwrap();
+    channel.set_onopen(None);
+}
+
+#[wasm_bindgen_test]
+async fn test_rtc_data_channel_loopback() {
+    let a = RtcPeerConnection::new().unwrap();
+    let b = RtcPeerConnection::new().unwrap();
+    let _a_candidates = forward_candidates(&a, &b);
+    let _b_candidates = forward_candidates(&b, &a);
+
+    let a_channel = data_channel(&a);
+    let b_channel = data_channel(&b);
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(64));
+    let mut a_packets = RtcDataChannelPackets::new(a_channel.clone(), packet_pool, 8);
+    let mut b_packets = RtcDataChannelPackets::new(b_channel.clone(), packet_pool, 8);
+
+    // Packets sent before the data channel is open are dropped rather than failing.
+    a_packets.send(&[0]).unwrap();
+
+    connect(&a, &b).await;
+    opened(&a_channel).await;
+    opened(&b_channel).await;
+
+    a_packets.send(&[1, 2, 3]).unwrap();
+    assert_eq!(&b_packets.recv().await.unwrap()[..], &[1, 2, 3]);
+    b_packets.send(&[4, 5]).unwrap();
+    assert_eq!(&a_packets.recv().await.unwrap()[..], &[4, 5]);
+
+    // Messages larger than the packet capacity are truncated.
+    a_packets.send(&[7; 100]).unwrap();
+    assert_eq!(&b_packets.recv().await.unwrap()[..], &[7; 64][..]);
+
+    // Closing the data channel ends the incoming packets and fails further sends.
+    a_channel.close();
+    assert!(a_packets.recv().await.is_none());
+    assert!(a_packets.send(&[6]).is_err());
+
+    a.close();
+    b.close();
+}
@@ -0,0 +1,164 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    stream,
+    task::noop_waker_ref,
+    Stream,
+};
+
+use turbulence::{
+    buffer::BufferPacketPool,
+    packet_multiplexer::PacketMultiplexer,
+    reliable_channel::{ReliableChannel, Settings},
+    runtime::Runtime,
+    work_budget::{Cooperative, WorkBudget},
+    ChannelBuilder,
+};
+
+mod util;
+
+use self::util::{SimpleBufferPool, SimpleRuntime};
+
+fn poll_lens<S>(stream: &mut S, count: usize) -> Vec<Option<usize>>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+{
+    let mut cx = Context::from_waker(noop_waker_ref());
+    (0..count)
+        .map(|_| match Pin::new(&mut *stream).poll_next(&mut cx) {
+            Poll::Ready(Some(packet)) => Some(packet.len()),
+            Poll::Ready(None) => panic!("stream ended"),
+            Poll::Pending => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_cooperative() {
+    let packets = vec![
+        vec![0; 10],
+        vec![0; 10],
+        vec![0; 10],
+        vec![0; 30],
+        vec![0; 10],
+    ];
+    let budget = WorkBudget {
+        max_packets: 3,
+        max_bytes: 25,
+    };
+    let mut cooperative = Cooperative::new(stream::iter(packets), Some(budget));
+
+    // The byte budget runs out first, then a single large packet exhausts it again.
+    assert_eq!(
+        poll_lens(&mut cooperative, 7),
+        vec![Some(10), Some(10), Some(10), None, Some(30), None, Some(10)]
+    );
+
+    // The packet budget, with the count reset whenever the stream has nothing ready.
+    let (sender, receiver) = mpsc::unbounded();
+    let mut cooperative = Cooperative::new(receiver, Some(budget));
+    for _ in 0..2 {
+        sender.unbounded_send(vec![0; 1]).unwrap();
+    }
+    assert_eq!(poll_lens(&mut cooperative, 3), vec![Some(1), Some(1), None]);
+    for _ in 0..4 {
+        sender.unbounded_send(vec![0; 1]).unwrap();
+    }
+    assert_eq!(
+        poll_lens(&mut cooperative, 5),
+        vec![Some(1), Some(1), Some(1), None, Some(1)]
+    );
+
+    cooperative.set_budget(None);
+    assert_eq!(cooperative.budget(), None);
+}
+
+const SETTINGS: Settings = Settings {
+    bandwidth: 32768,
+    burst_bandwidth: 4096,
+    recv_window_size: 16384,
+    send_window_size: 16384,
+    init_send: 512,
+    resend_time: Duration::from_millis(50),
+    initial_rtt: Duration::from_millis(100),
+    max_rtt: Duration::from_millis(2000),
+    rtt_update_factor: 0.1,
+    rtt_resend_factor: 1.5,
+};
+
+#[test]
+fn test_reliable_work_budget() {
+    let packet_pool = BufferPacketPool::new(SimpleBufferPool(1000));
+    let mut runtime = SimpleRuntime::new();
+    let budget = WorkBudget {
+        max_packets: 1,
+        max_bytes: 1,
+    };
+
+    let (asend, arecv) = mpsc::channel(8);
+    let (bsend, brecv) = mpsc::channel(8);
+    let mut stream1 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, arecv, bsend);
+    let mut stream2 = ReliableChannel::new(runtime.handle(), packet_pool, SETTINGS, brecv, asend);
+    stream1.set_work_budget(Some(budget));
+    stream2.set_work_budget(Some(budget));
+
+    const LEN: usize = 20_000;
+    runtime.spawn(async move {
+        let data = [7; LEN];
+        let mut written = 0;
+        while written < LEN {
+            written += stream1.write(&data[written..]).await.unwrap();
+            stream1.flush().await.unwrap();
+        }
+        futures::future::pending::<()>().await;
+    });
+
+    let (done_send, mut done) = oneshot::channel();
+    runtime.spawn(async move {
+        let mut buffer = [0; 1024];
+        let mut read = 0;
+        while read < LEN {
+            let len = stream2.read(&mut buffer).await.unwrap();
+            assert!(buffer[..len].iter().all(|&b| b == 7));
+            read += len;
+        }
+        done_send.send(()).unwrap();
+        futures::future::pending::<()>().await;
+    });
+
+    for _ in 0..100 {
+        runtime.run_until_stalled();
+        if done.try_recv().unwrap().is_some() {
+            return;
+        }
+        runtime.advance_time(50);
+    }
+    panic!("reliable channels stalled with a work budget");
+}
+
+#[test]
+fn test_channel_builder_work_budget() {
+    let runtime = SimpleRuntime::new();
+    let mut multiplexer = PacketMultiplexer::new();
+    let mut builder = ChannelBuilder::new(
+        runtime.handle(),
+        BufferPacketPool::new(SimpleBufferPool(1200)),
+    );
+    let budget = WorkBudget {
+        max_packets: 16,
+        max_bytes: 16384,
+    };
+    builder.work_budget = Some(budget);
+
+    let (channel, _) = builder
+        .open_reliable_channel(&mut multiplexer, 0, 8, SETTINGS)
+        .unwrap();
+    assert_eq!(channel.work_budget(), Some(budget));
+    channel.set_work_budget(None);
+    assert_eq!(channel.work_budget(), None);
+}